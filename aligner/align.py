@@ -0,0 +1,245 @@
+"""Aligns the words of a transcript with the audio of an alignment job, see
+trakktor/src/aws_batch/align.rs for the output format.
+
+The alignment is done like in WhisperX: the characters of the words are
+aligned with the emissions of a wav2vec2 CTC model, the multilingual MMS
+model of torchaudio, segment by segment when the transcript has timestamps.
+"""
+
+import json
+import os
+import re
+import subprocess
+import sys
+
+import boto3
+import torch
+import torchaudio
+import uroman
+
+# The encryption helper of the Whisper image, see the Dockerfile.
+sys.path.insert(0, "/")
+import trk_crypt as crypt  # noqa: E402
+
+BUCKET = os.environ["S3_STORAGE_BUCKET"]
+JOB_PREFIX = os.environ["TRK_JOB_PREFIX"]
+INPUT_FILE = os.environ["TRK_INPUT_FILE"]
+TRANSCRIPT_FILE = os.environ["TRK_TRANSCRIPT_FILE"]
+# The transcription job of the pipeline the job is part of, if any.
+SOURCE_PREFIX = os.environ.get("TRK_SOURCE_PREFIX")
+ENCRYPTION_KEY_PARAMETER = os.environ.get("TRK_ENCRYPTION_KEY_PARAMETER")
+DONE_FLAG = "done.🚜-flag"
+# The emissions are computed in windows of this length, to bound the memory.
+WINDOW_SECONDS = 30
+# A transcript without timestamps is aligned as a whole, which only works for
+# short audio.
+MAX_UNTIMED_SECONDS = 600
+
+s3 = boto3.client("s3")
+
+
+def download(key, dest):
+    """Downloads the object, decrypting it if it was encrypted on the client
+    side."""
+    s3.download_file(BUCKET, key, dest)
+    enc = s3.head_object(Bucket=BUCKET, Key=key)["Metadata"].get("trk-enc")
+    if enc:
+        if not ENCRYPTION_KEY_PARAMETER:
+            sys.exit(f"Error: {key} is encrypted, but no encryption key is set")
+        crypt.decrypt(dest, enc)
+
+
+def upload(src, key):
+    """Uploads the file, encrypting it if the encryption key is set."""
+    if ENCRYPTION_KEY_PARAMETER:
+        enc = crypt.encrypt(src)
+        s3.upload_file(src, BUCKET, key, ExtraArgs={
+            "ContentType": "application/octet-stream",
+            "Metadata": {"trk-enc": enc},
+        })
+    else:
+        s3.upload_file(src, BUCKET, key)
+
+
+CUE_TIMES = re.compile(
+    r"((?:\d+:)?\d+:\d+[.,]\d+)\s*-->\s*((?:\d+:)?\d+:\d+[.,]\d+)")
+TAG = re.compile(r"<[^>]*>")
+
+
+def parse_time(value):
+    seconds = 0.0
+    for part in value.replace(",", ".").split(":"):
+        seconds = seconds * 60 + float(part)
+    return seconds
+
+
+def read_segments(path):
+    """Reads the transcript into segments with their text and, if known,
+    their time range."""
+    ext = os.path.splitext(path)[1].lower()
+    with open(path, encoding="utf-8", errors="replace") as f:
+        contents = f.read()
+    if ext == ".json":
+        return [{"text": s["text"], "start": s["start"], "end": s["end"]}
+                for s in json.loads(contents)["segments"]]
+    if ext in (".srt", ".vtt"):
+        segments = []
+        for block in re.split(r"\n\s*\n", contents.replace("\r\n", "\n")):
+            lines = block.strip().split("\n")
+            for i, line in enumerate(lines):
+                times = CUE_TIMES.search(line)
+                if times:
+                    segments.append({
+                        "text": TAG.sub("", " ".join(lines[i + 1:])),
+                        "start": parse_time(times.group(1)),
+                        "end": parse_time(times.group(2)),
+                    })
+                    break
+        return segments
+    return [{"text": contents, "start": None, "end": None}]
+
+
+def load_audio(path, sample_rate):
+    """Decodes the audio into mono samples with ffmpeg."""
+    wav = "/task/audio.wav"
+    subprocess.run(["ffmpeg", "-nostdin", "-loglevel", "error", "-i", path,
+                    "-ac", "1", "-ar", str(sample_rate), "-y", wav],
+                   check=True)
+    waveform, _ = torchaudio.load(wav)
+    return waveform
+
+
+def compute_emission(model, waveform, sample_rate, device):
+    """Computes the emissions of the model in windows, concatenated."""
+    window = WINDOW_SECONDS * sample_rate
+    emissions = []
+    with torch.inference_mode():
+        for start in range(0, waveform.size(1), window):
+            chunk = waveform[:, start:start + window].to(device)
+            emission, _ = model(chunk)
+            emissions.append(emission[0].cpu())
+    return torch.cat(emissions)
+
+
+def normalize_word(word, romanizer, dictionary):
+    """The characters of the word the model knows, after romanization."""
+    romanized = romanizer.romanize_string(word).lower()
+    return "".join(c for c in romanized if c in dictionary)
+
+
+def align_segment(segment, emission, frame_seconds, bundle, romanizer):
+    """Aligns the words of the segment with its part of the emissions. The
+    words the model can't align get the times of the previous word."""
+    dictionary = bundle.get_dict(star=None)
+    words = segment["text"].split()
+    normalized = [normalize_word(w, romanizer, dictionary) for w in words]
+    alignable = [n for n in normalized if n]
+
+    first = int((segment["start"] or 0) / frame_seconds)
+    last = emission.size(0) if segment["end"] is None else min(
+        emission.size(0), int(segment["end"] / frame_seconds) + 1)
+    spans = []
+    if alignable and last > first:
+        try:
+            spans = bundle.get_aligner()(
+                emission[first:last], bundle.get_tokenizer()(alignable))
+        except RuntimeError as err:
+            # The segment is too short for its text.
+            print(f"Warning: Failed to align \"{segment['text']}\": {err}")
+
+    aligned = []
+    span_iter = iter(spans)
+    previous_end = segment["start"] or 0.0
+    for word, norm in zip(words, normalized):
+        span = next(span_iter, None) if norm else None
+        if span:
+            start = (first + span[0].start) * frame_seconds
+            end = (first + span[-1].end) * frame_seconds
+            score = sum(s.score * len(s) for s in span) / sum(
+                len(s) for s in span)
+            aligned.append({"word": word, "start": round(start, 3),
+                            "end": round(end, 3), "score": round(score, 3)})
+            previous_end = end
+        else:
+            aligned.append({"word": word, "start": round(previous_end, 3),
+                            "end": round(previous_end, 3), "score": None})
+    return aligned
+
+
+def format_vtt_time(seconds):
+    millis = int(round(seconds * 1000))
+    hours, millis = divmod(millis, 3_600_000)
+    minutes, millis = divmod(millis, 60_000)
+    seconds, millis = divmod(millis, 1000)
+    return f"{hours:02}:{minutes:02}:{seconds:02}.{millis:03}"
+
+
+def karaoke_vtt(segments):
+    """WebVTT subtitles of the segments, with the start time of every word
+    but the first before it."""
+    lines = ["WEBVTT", ""]
+    for words in segments:
+        if not words:
+            continue
+        text = words[0]["word"] + "".join(
+            f" <{format_vtt_time(w['start'])}>{w['word']}" for w in words[1:])
+        lines += [f"{format_vtt_time(words[0]['start'])} --> "
+                  f"{format_vtt_time(words[-1]['end'])}", text, ""]
+    return "\n".join(lines)
+
+
+def main():
+    print(f"TRK_JOB_PREFIX: {JOB_PREFIX}")
+    print(f"TRK_INPUT_FILE: {INPUT_FILE}")
+    print(f"TRK_TRANSCRIPT_FILE: {TRANSCRIPT_FILE}")
+    print(f"TRK_SOURCE_PREFIX: {SOURCE_PREFIX or ''}")
+    print(f"TRK_ENCRYPTION: {'on' if ENCRYPTION_KEY_PARAMETER else 'off'}")
+
+    os.makedirs("/task/in")
+    os.makedirs("/task/out")
+    audio_path = os.path.join("/task/in", INPUT_FILE)
+    transcript_path = os.path.join("/task/in", TRANSCRIPT_FILE)
+    if SOURCE_PREFIX:
+        download(f"{SOURCE_PREFIX}in/{INPUT_FILE}", audio_path)
+        download(f"{SOURCE_PREFIX}out/{TRANSCRIPT_FILE}", transcript_path)
+    else:
+        download(f"{JOB_PREFIX}in/{INPUT_FILE}", audio_path)
+        download(f"{JOB_PREFIX}in/{TRANSCRIPT_FILE}", transcript_path)
+
+    segments = read_segments(transcript_path)
+    if not any(s["text"].strip() for s in segments):
+        sys.exit("Error: The transcript is empty")
+
+    bundle = torchaudio.pipelines.MMS_FA
+    device = "cuda" if torch.cuda.is_available() else "cpu"
+    model = bundle.get_model(with_star=False).to(device)
+    waveform = load_audio(audio_path, bundle.sample_rate)
+    duration = waveform.size(1) / bundle.sample_rate
+    if segments[0]["start"] is None and duration > MAX_UNTIMED_SECONDS:
+        sys.exit("Error: A transcript without timestamps can only be aligned "
+                 f"with up to {MAX_UNTIMED_SECONDS} seconds of audio, use "
+                 "subtitles or the Whisper JSON output instead")
+
+    emission = compute_emission(model, waveform, bundle.sample_rate, device)
+    frame_seconds = duration / emission.size(0)
+    romanizer = uroman.Uroman()
+    aligned = [align_segment(s, emission, frame_seconds, bundle, romanizer)
+               for s in segments]
+    print(f"Aligned {sum(len(words) for words in aligned)} words")
+
+    base = os.path.splitext(INPUT_FILE)[0]
+    with open(f"/task/out/{base}.words.json", "w", encoding="utf-8") as f:
+        json.dump({"words": [w for words in aligned for w in words]}, f,
+                  ensure_ascii=False, indent=2)
+    with open(f"/task/out/{base}.vtt", "w", encoding="utf-8") as f:
+        f.write(karaoke_vtt(aligned))
+
+    for name in os.listdir("/task/out"):
+        upload(os.path.join("/task/out", name), f"{JOB_PREFIX}out/{name}")
+
+    open(f"/task/{DONE_FLAG}", "w").close()
+    s3.upload_file(f"/task/{DONE_FLAG}", BUCKET, JOB_PREFIX + DONE_FLAG)
+
+
+if __name__ == "__main__":
+    main()
@@ -1,6 +1,13 @@
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
 use clap::{Parser, Subcommand};
 use cmd_lib::*;
-use trakktor::aws_batch::whisper;
+use serde::Serialize;
+use strum::IntoEnumIterator;
+use trakktor::aws_batch::{aligner, indexer, preprocessor, whisper};
 
 #[derive(Debug)]
 struct TasksRunner {
@@ -12,8 +19,9 @@ struct TasksRunner {
 struct Cli {
     #[arg(long, default_value = "lymar")]
     ghcr_login: Box<str>,
+    /// Required to push the images.
     #[arg(long, env = "GHCR_TOKEN")]
-    ghcr_token: Box<str>,
+    ghcr_token: Option<Box<str>>,
     #[arg(long)]
     release: bool,
     #[clap(subcommand)]
@@ -21,9 +29,105 @@ struct Cli {
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::enum_variant_names)]
 enum Commands {
     /// Build and push the Docker images.
-    DockerBuild,
+    DockerBuild(DockerBuildArgs),
+    /// Build and push the Docker image of the indexer.
+    DockerBuildIndexer,
+    /// Build and push the Docker image of the aligner.
+    DockerBuildAligner,
+    /// Build and push the Docker image of the preprocessor.
+    DockerBuildPreprocessor,
+}
+
+#[derive(clap::Args, Debug)]
+struct DockerBuildArgs {
+    /// The Whisper model to build the image for. Can be repeated.
+    #[arg(long = "model", value_enum, default_value = "large-v3")]
+    models: Vec<whisper::Model>,
+    /// Build the images of all the models.
+    #[arg(long, conflicts_with = "models")]
+    all_models: bool,
+    /// The platform to build the images for. Can be repeated, the images of
+    /// several platforms are tagged per platform and pushed as a manifest
+    /// list under the common tag.
+    #[arg(long = "platform", value_enum, default_value = "amd64")]
+    platforms: Vec<Platform>,
+    /// How many images are built and pushed at the same time.
+    #[arg(long, default_value_t = 1)]
+    jobs: usize,
+    /// Only build the images, to push them later with `--push-only`.
+    #[arg(long, conflicts_with = "push_only")]
+    build_only: bool,
+    /// Only push the images built before with `--build-only`.
+    #[arg(long)]
+    push_only: bool,
+    /// Write the JSON list of the produced tags to the file.
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize)]
+enum Platform {
+    #[serde(rename = "linux/amd64")]
+    Amd64,
+    /// For the Graviton GPU instances, like g5g.
+    #[serde(rename = "linux/arm64")]
+    Arm64,
+}
+
+impl Platform {
+    fn get_name(&self) -> &'static str {
+        match self {
+            Platform::Amd64 => "linux/amd64",
+            Platform::Arm64 => "linux/arm64",
+        }
+    }
+
+    fn get_arch(&self) -> &'static str {
+        match self {
+            Platform::Amd64 => "amd64",
+            Platform::Arm64 => "arm64",
+        }
+    }
+}
+
+/// An image of a Whisper model, as listed in the manifest.
+#[derive(Debug, Serialize)]
+struct WhisperImage {
+    model: whisper::Model,
+    tag: String,
+    platforms: Vec<Platform>,
+    /// The tags of the images of every platform, the common tag itself for
+    /// a single platform.
+    platform_tags: Vec<String>,
+    pushed: bool,
+}
+
+impl WhisperImage {
+    fn new(
+        model: whisper::Model,
+        platforms: &[Platform],
+        is_dev: bool,
+        pushed: bool,
+    ) -> Self {
+        let tag = whisper::make_image_name(model, is_dev);
+        let platform_tags = match platforms {
+            [_] => vec![tag.clone()],
+            _ => platforms
+                .iter()
+                .map(|platform| format!("{tag}-{}", platform.get_arch()))
+                .collect(),
+        };
+        WhisperImage {
+            model,
+            tag,
+            platforms: platforms.to_vec(),
+            platform_tags,
+            pushed,
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
@@ -37,26 +141,155 @@ fn main() -> anyhow::Result<()> {
 
 impl TasksRunner {
     fn run(&self) -> anyhow::Result<()> {
-        match self.cli.command {
-            Commands::DockerBuild => self.docker_build()?,
+        match &self.cli.command {
+            Commands::DockerBuild(args) => self.docker_build(args)?,
+            Commands::DockerBuildIndexer => self.docker_build_indexer()?,
+            Commands::DockerBuildAligner => self.docker_build_aligner()?,
+            Commands::DockerBuildPreprocessor => {
+                self.docker_build_preprocessor()?
+            },
+        }
+
+        Ok(())
+    }
+
+    fn docker_build(&self, args: &DockerBuildArgs) -> anyhow::Result<()> {
+        let mut models = if args.all_models {
+            whisper::Model::iter().collect()
+        } else {
+            args.models.clone()
+        };
+        dedup(&mut models);
+        let mut platforms = args.platforms.clone();
+        dedup(&mut platforms);
+
+        let images = models
+            .into_iter()
+            .map(|model| {
+                WhisperImage::new(
+                    model,
+                    &platforms,
+                    !self.cli.release,
+                    !args.build_only,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        if !args.push_only {
+            let builds = images
+                .iter()
+                .flat_map(|image| {
+                    image.platforms.iter().zip(&image.platform_tags).map(
+                        |(&platform, tag)| {
+                            (image.model, platform, tag.as_str())
+                        },
+                    )
+                })
+                .collect::<Vec<_>>();
+            run_parallel(args.jobs, &builds, |&(model, platform, tag)| {
+                build_whisper_image(model, platform, tag)
+            })?;
+        }
+        if !args.build_only {
+            self.ghcr_login()?;
+            run_parallel(args.jobs, &images, |image| {
+                self.push_whisper_image(image)
+            })?;
+        }
+
+        for image in &images {
+            println!("Produced Docker image: {}", image.tag);
+        }
+        if let Some(path) = &args.manifest {
+            std::fs::write(path, serde_json::to_string_pretty(&images)?)?;
+            println!("Manifest written to {}", path.display());
         }
 
         Ok(())
     }
 
-    fn docker_build(&self) -> anyhow::Result<()> {
+    fn push_whisper_image(&self, image: &WhisperImage) -> anyhow::Result<()> {
+        if let [tag] = image.platform_tags.as_slice() {
+            return self.push_image(tag);
+        }
+
+        self.check_release_exists(&image.tag)?;
+        for tag in &image.platform_tags {
+            println!("Pushing Docker image: {}", tag);
+            run_cmd! {
+                docker push ${tag}
+            }?;
+        }
+        let (tag, platform_tags) = (&image.tag, &image.platform_tags);
+        println!("Pushing Docker manifest list: {}", tag);
+        run_cmd! {
+            docker manifest create --amend ${tag} $[platform_tags];
+            docker manifest push ${tag}
+        }?;
+
+        Ok(())
+    }
+
+    fn docker_build_indexer(&self) -> anyhow::Result<()> {
+        self.ghcr_login()?;
+
+        let model_name = indexer::EMBEDDINGS_MODEL;
+        let full_image_name = indexer::make_image_name(!self.cli.release);
+
+        // The indexer shares the encryption helper with the Whisper image.
+        println!("Building Docker image: {}", full_image_name);
+        run_cmd! {
+            docker build --platform linux/amd64 --build-arg EMBEDDINGS_MODEL=${model_name} --build-context whisper=./whisper -t ${full_image_name} -f ./indexer/Dockerfile ./indexer
+        }?;
+
+        self.push_image(&full_image_name)
+    }
+
+    fn docker_build_aligner(&self) -> anyhow::Result<()> {
         self.ghcr_login()?;
 
-        let model = whisper::Model::Large;
-        let model_name = model.get_name();
-        let full_image_name =
-            whisper::make_image_name(model, !self.cli.release);
+        let full_image_name = aligner::make_image_name(!self.cli.release);
 
+        // The aligner shares the encryption helper with the Whisper image.
         println!("Building Docker image: {}", full_image_name);
         run_cmd! {
-            docker build --platform linux/amd64 --build-arg WHISPER_MODEL=${model_name} -t ${full_image_name} -f ./whisper/Dockerfile ./whisper
+            docker build --platform linux/amd64 --build-context whisper=./whisper -t ${full_image_name} -f ./aligner/Dockerfile ./aligner
+        }?;
+
+        self.push_image(&full_image_name)
+    }
+
+    fn docker_build_preprocessor(&self) -> anyhow::Result<()> {
+        self.ghcr_login()?;
+
+        let full_image_name = preprocessor::make_image_name(!self.cli.release);
+
+        // The preprocessor shares the encryption helper with the Whisper
+        // image.
+        println!("Building Docker image: {}", full_image_name);
+        run_cmd! {
+            docker build --platform linux/amd64 --build-context whisper=./whisper -t ${full_image_name} -f ./preprocessor/Dockerfile ./preprocessor
+        }?;
+
+        self.push_image(&full_image_name)
+    }
+
+    fn push_image(&self, full_image_name: &str) -> anyhow::Result<()> {
+        self.check_release_exists(full_image_name)?;
+
+        println!("Pushing Docker image: {}", full_image_name);
+        run_cmd! {
+            docker push ${full_image_name}
         }?;
 
+        Ok(())
+    }
+
+    /// Fails if the release version of the image has been pushed already.
+    fn check_release_exists(
+        &self,
+        full_image_name: &str,
+    ) -> anyhow::Result<()> {
         if self.cli.release {
             let inspect_res = run_fun! {
                 docker manifest inspect ${full_image_name}
@@ -69,17 +302,15 @@ impl TasksRunner {
             }
         }
 
-        println!("Pushing Docker image: {}", full_image_name);
-        run_cmd! {
-            docker push ${full_image_name}
-        }?;
-
         Ok(())
     }
 
     fn ghcr_login(&self) -> anyhow::Result<()> {
         println!("Logging in to GitHub Container Registry...");
-        let (login, token) = (&self.cli.ghcr_login, &self.cli.ghcr_token);
+        let login = &self.cli.ghcr_login;
+        let Some(token) = &self.cli.ghcr_token else {
+            anyhow::bail!("--ghcr-token or GHCR_TOKEN is required to push");
+        };
 
         run_cmd! {
             echo "${token}" | docker login ghcr.io -u ${login} --password-stdin
@@ -88,3 +319,67 @@ impl TasksRunner {
         Ok(())
     }
 }
+
+fn build_whisper_image(
+    model: whisper::Model,
+    platform: Platform,
+    full_image_name: &str,
+) -> anyhow::Result<()> {
+    let model_name = model.get_name();
+    let platform_name = platform.get_name();
+
+    // Without the provenance attestation the image of a platform is a plain
+    // manifest, which a manifest list can be created from.
+    println!("Building Docker image: {}", full_image_name);
+    run_cmd! {
+        docker build --platform ${platform_name} --provenance=false --build-arg WHISPER_MODEL=${model_name} --build-context workspace=. -t ${full_image_name} -f ./whisper/Dockerfile ./whisper
+    }?;
+
+    Ok(())
+}
+
+/// Runs the task on every item with up to `jobs` threads, reporting all the
+/// failures.
+fn run_parallel<T: Sync>(
+    jobs: usize,
+    items: &[T],
+    task: impl Fn(&T) -> anyhow::Result<()> + Sync,
+) -> anyhow::Result<()> {
+    let next = AtomicUsize::new(0);
+    let failed = std::thread::scope(|scope| {
+        let workers = (0..jobs.clamp(1, items.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut failed = 0;
+                    while let Some(item) =
+                        items.get(next.fetch_add(1, Ordering::Relaxed))
+                    {
+                        if let Err(err) = task(item) {
+                            eprintln!("{err:#}");
+                            failed += 1;
+                        }
+                    }
+                    failed
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("Worker thread panicked"))
+            .sum::<usize>()
+    });
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} tasks failed", items.len());
+    }
+    Ok(())
+}
+
+/// Removes the repeated values, keeping the order.
+fn dedup<T: PartialEq + Copy>(values: &mut Vec<T>) {
+    let mut seen = vec![];
+    values.retain(|value| {
+        let new = !seen.contains(value);
+        seen.push(*value);
+        new
+    });
+}
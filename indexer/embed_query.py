@@ -0,0 +1,24 @@
+"""Prints the embedding of the search query read from the standard input as a
+JSON array, for `trakktor search`. The embedding is computed with the model
+of the indexes, like the embeddings of the chunks.
+"""
+
+import json
+import os
+import sys
+
+from sentence_transformers import SentenceTransformer
+
+
+def main():
+    query = sys.stdin.read().strip()
+    if not query:
+        sys.exit("Error: No query given")
+    model = SentenceTransformer(os.environ["EMBEDDINGS_MODEL"])
+    embedding = model.encode([query], normalize_embeddings=True,
+                             show_progress_bar=False)[0]
+    json.dump([float(x) for x in embedding], sys.stdout)
+
+
+if __name__ == "__main__":
+    main()
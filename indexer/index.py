@@ -0,0 +1,332 @@
+"""Computes the embeddings of the documents of an indexing job, see
+trakktor/src/aws_batch/index.rs for the output format.
+"""
+
+import html.parser
+import json
+import os
+import re
+import subprocess
+import sys
+import tempfile
+import zipfile
+
+import boto3
+import numpy as np
+from sentence_transformers import SentenceTransformer
+
+# The encryption helper of the Whisper image, see the Dockerfile.
+sys.path.insert(0, "/")
+import trk_crypt as crypt  # noqa: E402
+
+BUCKET = os.environ["S3_STORAGE_BUCKET"]
+JOB_PREFIX = os.environ["TRK_JOB_PREFIX"]
+INPUT_LIST = os.environ["TRK_INPUT_LIST"]
+DOCUMENT_LIST = os.environ.get("TRK_DOCUMENT_LIST")
+CHUNK_WORDS = int(os.environ.get("TRK_CHUNK_WORDS", "200"))
+CHUNK_OVERLAP = int(os.environ.get("TRK_CHUNK_OVERLAP", "0"))
+METADATA = set(filter(None, os.environ.get("TRK_METADATA", "").split(",")))
+MODEL = os.environ["EMBEDDINGS_MODEL"]
+ENCRYPTION_KEY_PARAMETER = os.environ.get("TRK_ENCRYPTION_KEY_PARAMETER")
+OCR = bool(os.environ.get("TRK_OCR"))
+OCR_LANGUAGE = os.environ.get("TRK_OCR_LANGUAGE", "eng")
+IMAGE_EXTENSIONS = (".png", ".jpg", ".jpeg", ".tif", ".tiff", ".bmp", ".gif",
+                    ".webp")
+# The resolution the pages of scanned PDFs are rendered at for OCR.
+OCR_PAGE_DPI = "300"
+DONE_FLAG = "done.🚜-flag"
+BATCH_SIZE = 64
+
+s3 = boto3.client("s3")
+
+
+def download(key, dest):
+    """Downloads the object, decrypting it if it was encrypted on the client
+    side."""
+    s3.download_file(BUCKET, key, dest)
+    enc = s3.head_object(Bucket=BUCKET, Key=key)["Metadata"].get("trk-enc")
+    if enc:
+        if not ENCRYPTION_KEY_PARAMETER:
+            sys.exit(f"Error: {key} is encrypted, but no encryption key is set")
+        crypt.decrypt(dest, enc)
+
+
+def upload(src, key):
+    """Uploads the file, encrypting it if the encryption key is set."""
+    if ENCRYPTION_KEY_PARAMETER:
+        enc = crypt.encrypt(src)
+        s3.upload_file(src, BUCKET, key, ExtraArgs={
+            "ContentType": "application/octet-stream",
+            "Metadata": {"trk-enc": enc},
+        })
+    else:
+        s3.upload_file(src, BUCKET, key)
+
+
+class TextExtractor(html.parser.HTMLParser):
+    SKIP_TAGS = {"script", "style", "noscript", "template"}
+    BLOCK_TAGS = {"p", "div", "br", "li", "tr", "pre", "blockquote", "section",
+                  "article", "w:p"}
+    HEADING_TAGS = {"h1", "h2", "h3", "h4", "h5", "h6"}
+
+    def __init__(self):
+        super().__init__()
+        self.parts = []
+        self.skip = 0
+
+    def handle_starttag(self, tag, attrs):
+        if tag in self.SKIP_TAGS:
+            self.skip += 1
+        elif tag in self.HEADING_TAGS:
+            # Marked as a Markdown heading to track the sections.
+            self.parts.append("\n\n# ")
+        elif tag in self.BLOCK_TAGS:
+            self.parts.append("\n\n")
+
+    def handle_endtag(self, tag):
+        if tag in self.SKIP_TAGS:
+            self.skip = max(0, self.skip - 1)
+        elif tag in self.BLOCK_TAGS or tag in self.HEADING_TAGS:
+            self.parts.append("\n\n")
+
+    def handle_data(self, data):
+        if not self.skip:
+            self.parts.append(" ".join(data.splitlines()))
+
+
+def markup_text(markup):
+    extractor = TextExtractor()
+    extractor.feed(markup)
+    extractor.close()
+    return "".join(extractor.parts)
+
+
+def ocr_image(path):
+    """Recognizes the text of the scanned image. Tesseract separates the
+    paragraphs with blank lines, as the text units are split."""
+    return subprocess.run(["tesseract", path, "stdout", "-l", OCR_LANGUAGE],
+                          check=True, capture_output=True).stdout.decode(
+                              "utf-8", errors="replace")
+
+
+def ocr_pdf(path):
+    """Renders the pages of the PDF to images and recognizes them one by
+    one."""
+    with tempfile.TemporaryDirectory() as pages_dir:
+        subprocess.run(["pdftoppm", "-r", OCR_PAGE_DPI, "-png", path,
+                        os.path.join(pages_dir, "page")], check=True,
+                       capture_output=True)
+        # The page numbers are zero-padded to the same width.
+        pages = sorted(os.listdir(pages_dir))
+        return "\n\n".join(
+            ocr_image(os.path.join(pages_dir, page)) for page in pages)
+
+
+def read_text(path):
+    """Extracts the text of the document, or returns None if the format is
+    not supported."""
+    ext = os.path.splitext(path)[1].lower()
+    if ext in (".txt", ".md", ".markdown", ".text", ".rst"):
+        with open(path, encoding="utf-8", errors="replace") as f:
+            return f.read()
+    if ext in (".html", ".htm", ".xhtml"):
+        with open(path, encoding="utf-8", errors="replace") as f:
+            return markup_text(f.read())
+    if ext == ".pdf":
+        if not OCR:
+            text = subprocess.run(["pdftotext", path, "-"], check=True,
+                                  capture_output=True).stdout.decode(
+                                      "utf-8", errors="replace")
+            if text.strip():
+                return text
+            print(f"{path} has no text, recognizing it with OCR")
+        return ocr_pdf(path)
+    if ext in IMAGE_EXTENSIONS:
+        return ocr_image(path)
+    if ext == ".docx":
+        with zipfile.ZipFile(path) as docx:
+            return markup_text(docx.read("word/document.xml").decode())
+    return None
+
+
+HEADING = re.compile(r"^\s*#{1,6}\s+(.+?)\s*#*\s*$")
+
+
+def text_units(text):
+    """Splits the text into paragraphs, with the heading of the Markdown
+    section they are in."""
+    units = []
+    section = None
+    for paragraph in re.split(r"\n\s*\n", text):
+        heading = HEADING.match(paragraph.strip().split("\n")[0])
+        if heading:
+            section = heading.group(1)
+        units.append({"words": paragraph.split(), "section": section})
+    return units
+
+
+def transcript_units(path):
+    """Reads the segments of a Whisper JSON transcript, with their speakers
+    and time ranges, or returns None if the file is not a transcript."""
+    with open(path, encoding="utf-8") as f:
+        data = json.load(f)
+    segments = data.get("segments") if isinstance(data, dict) else None
+    if not isinstance(segments, list):
+        return None
+    return [{
+        "words": str(segment.get("text", "")).split(),
+        "speaker": segment.get("speaker"),
+        "start": segment.get("start"),
+        "end": segment.get("end"),
+    } for segment in segments if isinstance(segment, dict)]
+
+
+def read_units(path):
+    """Reads the document into units packed into chunks: the paragraphs of
+    a text, or the segments of a transcript. Returns None if the format is
+    not supported."""
+    if os.path.splitext(path)[1].lower() == ".json":
+        return transcript_units(path)
+    text = read_text(path)
+    return None if text is None else text_units(text)
+
+
+def chunk_units(units, chunk_words, overlap):
+    """Packs the units into chunks of at most chunk_words words, splitting
+    longer units and starting a new chunk at every section. A chunk repeats
+    the last overlap words of the previous one in the same section."""
+    words = []  # The words with the index of their unit.
+    spans = []  # The chunks, as ranges of the words.
+    start = 0
+    for i, unit in enumerate(units):
+        if start < len(words) and (
+                units[words[start][1]].get("section") != unit.get("section")
+                or len(words) - start + len(unit["words"]) > chunk_words):
+            spans.append((start, len(words)))
+            start = len(words)
+        words.extend((word, i) for word in unit["words"])
+        while len(words) - start > chunk_words:
+            spans.append((start, start + chunk_words))
+            start += chunk_words
+    if start < len(words):
+        spans.append((start, len(words)))
+
+    chunks = []
+    for n, (start, end) in enumerate(spans):
+        unit = units[words[start][1]]
+        if n and overlap and units[words[spans[n - 1][0]][1]].get(
+                "section") == unit.get("section"):
+            start = max(spans[n - 1][0], start - overlap)
+        covered = [units[i] for i in sorted({i for _, i in words[start:end]})]
+        speakers = dict.fromkeys(u["speaker"] for u in covered
+                                 if u.get("speaker"))
+        chunks.append({
+            "text": " ".join(word for word, _ in words[start:end]),
+            "section": unit.get("section"),
+            "speaker": ", ".join(speakers) or None,
+            "start": covered[0].get("start"),
+            "end": covered[-1].get("end"),
+        })
+    return chunks
+
+
+def chunk_metadata(chunk):
+    """The metadata fields of the chunk selected for the job."""
+    fields = {}
+    if "section" in METADATA and chunk["section"]:
+        fields["section"] = chunk["section"]
+    if "speaker" in METADATA and chunk["speaker"]:
+        fields["speaker"] = chunk["speaker"]
+    if "timestamps" in METADATA and chunk["start"] is not None:
+        fields["start"] = chunk["start"]
+        fields["end"] = chunk["end"]
+    return fields
+
+
+def main():
+    print(f"EMBEDDINGS_MODEL: {MODEL}")
+    print(f"TRK_JOB_PREFIX: {JOB_PREFIX}")
+    print(f"TRK_CHUNK_WORDS: {CHUNK_WORDS}")
+    print(f"TRK_CHUNK_OVERLAP: {CHUNK_OVERLAP}")
+    print(f"TRK_METADATA: {','.join(sorted(METADATA))}")
+    print(f"TRK_ENCRYPTION: {'on' if ENCRYPTION_KEY_PARAMETER else 'off'}")
+
+    os.makedirs("/task/in")
+    os.makedirs("/task/out")
+    download(JOB_PREFIX + INPUT_LIST, "/task/input-list")
+    with open("/task/input-list", encoding="utf-8") as f:
+        files = [line for line in f.read().splitlines() if line]
+    document_hashes = {}
+    if DOCUMENT_LIST:
+        download(JOB_PREFIX + DOCUMENT_LIST, "/task/document-list")
+        with open("/task/document-list", encoding="utf-8") as f:
+            for line in f.read().splitlines():
+                if line:
+                    digest, name = line.split("\t", 1)
+                    document_hashes[name] = digest
+    # An update of an index only gets the changed documents of the corpus.
+    partial = bool(document_hashes) and set(files) != set(document_hashes)
+
+    chunks = []
+    skipped = []
+    for name in files:
+        path = os.path.join("/task/in", name)
+        download(f"{JOB_PREFIX}in/{name}", path)
+        try:
+            units = read_units(path)
+        except Exception as err:
+            print(f"Warning: Failed to read {name}: {err}")
+            units = None
+        if units is None:
+            skipped.append(name)
+            continue
+        for i, chunk in enumerate(chunk_units(units, CHUNK_WORDS,
+                                              CHUNK_OVERLAP)):
+            chunks.append({"document": name, "chunk": i,
+                           "text": chunk["text"], **chunk_metadata(chunk)})
+        os.remove(path)
+
+    if not chunks and not partial:
+        sys.exit("Error: No text found in the documents")
+    print(f"Indexing {len(chunks)} chunks of "
+          f"{len(files) - len(skipped)} documents")
+
+    model = SentenceTransformer(MODEL)
+    if chunks:
+        embeddings = model.encode([c["text"] for c in chunks],
+                                  batch_size=BATCH_SIZE,
+                                  normalize_embeddings=True,
+                                  show_progress_bar=False)
+    else:
+        # Only documents were removed from the corpus.
+        embeddings = np.zeros(
+            (0, model.get_sentence_embedding_dimension()), np.float32)
+
+    with open("/task/out/chunks.jsonl", "w", encoding="utf-8") as f:
+        for chunk in chunks:
+            f.write(json.dumps(chunk, ensure_ascii=False) + "\n")
+    np.save("/task/out/embeddings.npy", embeddings.astype(np.float32))
+    with open("/task/out/index.json", "w", encoding="utf-8") as f:
+        json.dump({
+            "model": MODEL,
+            "dimensions": int(embeddings.shape[1]),
+            "normalized": True,
+            "chunk_words": CHUNK_WORDS,
+            "chunk_overlap": CHUNK_OVERLAP,
+            "metadata": sorted(METADATA),
+            "documents": len(files) - len(skipped),
+            "chunks": len(chunks),
+            "skipped": skipped,
+            "document_hashes": document_hashes,
+            "partial": partial,
+        }, f, ensure_ascii=False, indent=2)
+
+    for name in os.listdir("/task/out"):
+        upload(os.path.join("/task/out", name), f"{JOB_PREFIX}out/{name}")
+
+    open(f"/task/{DONE_FLAG}", "w").close()
+    s3.upload_file(f"/task/{DONE_FLAG}", BUCKET, JOB_PREFIX + DONE_FLAG)
+
+
+if __name__ == "__main__":
+    main()
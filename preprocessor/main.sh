@@ -0,0 +1,91 @@
+set -e
+
+# The preprocessed files are named like in
+# trakktor/src/aws_batch/preprocessor.rs.
+
+# Noise reduction followed by EBU R128 loudness normalization, resampled to
+# the 16 kHz Whisper works with.
+FILTERS="afftdn=nf=-25,loudnorm=I=-16:TP=-1.5:LRA=11"
+SAMPLE_RATE=16000
+
+# downloads the object to the file, decrypting it if it was encrypted on the
+# client side
+download() {
+    local key="$1" dest="$2"
+    aws s3 cp "s3://$S3_STORAGE_BUCKET/$key" "$dest"
+    local enc
+    enc=$(aws s3api head-object --bucket "$S3_STORAGE_BUCKET" --key "$key" \
+        --query 'Metadata."trk-enc"' --output text)
+    if [ -n "$enc" ] && [ "$enc" != "None" ]; then
+        if [ -z "$TRK_ENCRYPTION_KEY_PARAMETER" ]; then
+            echo "Error: $key is encrypted, but no encryption key is set"
+            return 1
+        fi
+        python3 /crypt.py decrypt "$dest" "$enc"
+    fi
+}
+
+# uploads the file to the object, encrypting it if the encryption key is set
+upload() {
+    local src="$1" key="$2"
+    if [ -n "$TRK_ENCRYPTION_KEY_PARAMETER" ]; then
+        local enc
+        enc=$(python3 /crypt.py encrypt "$src")
+        aws s3 cp "$src" "s3://$S3_STORAGE_BUCKET/$key" \
+            --content-type application/octet-stream \
+            --metadata "trk-enc=$enc"
+    else
+        aws s3 cp "$src" "s3://$S3_STORAGE_BUCKET/$key"
+    fi
+}
+
+# Children of an array job pick their input file from the input list by
+# their index.
+if [ -n "$AWS_BATCH_JOB_ARRAY_INDEX" ]; then
+    download "${TRK_JOB_PREFIX}${TRK_INPUT_LIST}" /tmp/input-list
+    TRK_INPUT_FILE=$(sed -n "$((AWS_BATCH_JOB_ARRAY_INDEX + 1))p" /tmp/input-list)
+fi
+
+echo "TRK_JOB_UID: $TRK_JOB_UID"
+echo "TRK_JOB_PREFIX: $TRK_JOB_PREFIX"
+echo "TRK_INPUT_FILE: $TRK_INPUT_FILE"
+echo "TRK_OUTPUT_PREFIX: $TRK_OUTPUT_PREFIX"
+echo "TRK_SPLIT_CHANNELS: ${TRK_SPLIT_CHANNELS:-0}"
+echo "TRK_ENCRYPTION: $([ -n "$TRK_ENCRYPTION_KEY_PARAMETER" ] && echo on || echo off)"
+
+if [ -z "$TRK_INPUT_FILE" ]; then
+    echo "Error: No input file"
+    exit 1
+fi
+
+mkdir /task
+cd /task
+mkdir ./in ./out
+download "${TRK_JOB_PREFIX}in/$TRK_INPUT_FILE" "./in/$TRK_INPUT_FILE"
+
+STEM="${TRK_INPUT_FILE%.*}"
+if [ -n "$TRK_SPLIT_CHANNELS" ]; then
+    CHANNELS=$(ffprobe -v error -select_streams a:0 \
+        -show_entries stream=channels \
+        -of default=noprint_wrappers=1:nokey=1 "./in/$TRK_INPUT_FILE")
+    echo "Channels: $CHANNELS"
+    for ((ch = 0; ch < CHANNELS; ch++)); do
+        ffmpeg -nostdin -loglevel error -i "./in/$TRK_INPUT_FILE" -vn \
+            -af "pan=mono|c0=c$ch,$FILTERS" -ar $SAMPLE_RATE \
+            "./out/$STEM.ch$ch.flac"
+    done
+else
+    ffmpeg -nostdin -loglevel error -i "./in/$TRK_INPUT_FILE" -vn \
+        -ac 1 -af "$FILTERS" -ar $SAMPLE_RATE "./out/$STEM.flac"
+fi
+
+for file in ./out/*; do
+    upload "$file" "${TRK_JOB_PREFIX}${TRK_OUTPUT_PREFIX}$(basename -- "$file")"
+done
+
+# The input of a transcription is only prepared, the transcription job marks
+# the job done.
+if [ "$TRK_OUTPUT_PREFIX" = "out/" ]; then
+    touch done.🚜-flag
+    aws s3 cp done.🚜-flag "s3://$S3_STORAGE_BUCKET/${TRK_JOB_PREFIX}"
+fi
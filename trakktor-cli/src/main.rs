@@ -5,13 +5,13 @@ use tracing_subscriber::{
     prelude::*,
     Layer,
 };
+use trakktor::app_config::AppConfigFile;
 use trakktor_cli::Cli;
 
 mod trakktor_cli;
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+fn main() -> anyhow::Result<()> {
+    let mut cli = Cli::parse();
 
     let log_level = if cli.dev {
         LevelFilter::TRACE
@@ -38,7 +38,28 @@ async fn main() -> anyhow::Result<()> {
         }));
     tracing_subscriber::registry().with(layer).init();
 
-    cli.run().await?;
+    let config = AppConfigFile::load(cli.config.as_deref())?;
+    cli.apply_config(&config);
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .max_blocking_threads(cli.limits.blocking_tasks)
+        .build()?;
+
+    // The first Ctrl-C cancels the running operations, e.g. to keep the
+    // interrupted uploads resumable, the second one exits right away.
+    let cancel = cli.cancel.clone();
+    runtime.spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            tracing::warn!("Cancelling, press Ctrl-C again to exit.");
+            cancel.cancel();
+        }
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+
+    runtime.block_on(cli.run())?;
 
     Ok(())
 }
@@ -4,34 +4,81 @@ use std::sync::Arc;
 
 pub use cli::Cli;
 use cli::Commands;
+use tokio::sync::Semaphore;
 use trakktor::{
     ai_chat::{run_ai_chat, AllChatProviders},
+    azure_open_ai::AzureOpenAiAPI,
     embedding::{EmbeddingsAPI, EmbeddingsPlatform},
+    gemini::GeminiAPI,
     llm::{ChatCompletionAPI, ChatCompletionPlatform},
+    llm_audit::{LlmAuditLog, RedactEmails, RedactLiterals, Redactor},
+    llm_budget::LlmBudget,
     open_ai::OpenAiAPI,
+    record_replay::RecordReplayChatAPI,
+    redact::{Pseudonymizer, PseudonymizingAPI},
+    routing::{ChatRoute, ChatRouteConfig, RoutingChatAPI},
+    vector_index::run_index,
 };
 
 impl Cli {
-    pub async fn run(self) -> anyhow::Result<()> {
+    pub async fn run(mut self) -> anyhow::Result<()> {
+        self.budget =
+            Arc::new(LlmBudget::new(self.max_requests, self.max_tokens_budget));
+        if let Some(path) = &self.llm_audit_log {
+            let mut redactors: Vec<Box<dyn Redactor>> =
+                vec![Box::new(RedactLiterals(self.llm_audit_redact.clone()))];
+            if self.llm_audit_redact_emails {
+                redactors.push(Box::new(RedactEmails));
+            }
+            self.audit_log =
+                Some(Arc::new(LlmAuditLog::open(path, redactors).await?));
+        }
+        if self.pseudonymize || !self.pseudonymize_name.is_empty() {
+            self.pseudonymizer =
+                Some(Arc::new(Pseudonymizer::new(&self.pseudonymize_name)));
+        }
+
         match &self.command {
             Commands::AwsBatch(aws_batch) => {
                 self.run_aws_batch(aws_batch).await?;
             },
+            Commands::LocalDocker(local_docker) => {
+                self.run_local_docker(local_docker).await?;
+            },
             Commands::AIChat(ai_chat) => {
                 let all_providers = AllChatProviders {
                     open_ai: self.mk_open_ai_api(),
+                    azure_open_ai: self.mk_azure_open_ai_api().ok(),
+                    gemini: self.mk_gemini_api(),
                 };
                 run_ai_chat(
                     ai_chat,
                     &self.chat_platform,
                     &self.chat_model,
                     &all_providers,
+                    self.limits,
+                    &self.cancel,
                 )
                 .await?;
             },
             Commands::StructifyText(structify_text) => {
                 self.structify_text(structify_text).await?;
             },
+            Commands::IngestUrl(ingest_url) => {
+                self.ingest_url(ingest_url).await?;
+            },
+            Commands::SummarizeEmails(summarize_emails) => {
+                self.summarize_emails(summarize_emails).await?;
+            },
+            Commands::Index(index) => {
+                run_index(index, &self.vector_store).await?;
+            },
+            Commands::Search(search) => {
+                self.search(search).await?;
+            },
+            Commands::Doctor(doctor) => {
+                self.run_doctor(doctor).await?;
+            },
         }
 
         Ok(())
@@ -43,16 +90,139 @@ impl Cli {
             server_url: self.openai_server_url.clone().map(|url| Arc::new(url)),
             chat_model: self.chat_model.clone(),
             embeddings_model: self.embeddings_model.clone(),
+            request_permits: Arc::new(Semaphore::new(self.limits.llm_requests)),
+            extras: Arc::new(self.providers.open_ai.clone()),
+            rate_limiter: Arc::clone(&self.rate_limiters.open_ai),
+            audit_log: self.audit_log.clone(),
+            budget: Arc::clone(&self.budget),
+            seed: self.chat_seed,
+        }
+    }
+
+    fn mk_azure_open_ai_api(&self) -> anyhow::Result<AzureOpenAiAPI> {
+        let Some(endpoint) = &self.azure_openai_endpoint else {
+            anyhow::bail!("No Azure OpenAI endpoint specified!");
+        };
+        Ok(AzureOpenAiAPI {
+            api_key: self.azure_openai_api_key.clone(),
+            endpoint: Arc::new(endpoint.clone()),
+            api_version: self.azure_openai_api_version.clone(),
+            chat_deployment: self.chat_model.clone(),
+            embeddings_deployment: self.embeddings_model.clone(),
+            request_permits: Arc::new(Semaphore::new(self.limits.llm_requests)),
+            extras: Arc::new(self.providers.azure_open_ai.clone()),
+            rate_limiter: Arc::clone(&self.rate_limiters.azure_open_ai),
+            audit_log: self.audit_log.clone(),
+            budget: Arc::clone(&self.budget),
+            seed: self.chat_seed,
+        })
+    }
+
+    fn mk_gemini_api(&self) -> GeminiAPI {
+        GeminiAPI {
+            api_key: self.gemini_api_key.clone(),
+            chat_model: self.chat_model.clone(),
+            embeddings_model: self.embeddings_model.clone(),
+            safety_threshold: self.gemini_safety_threshold,
+            request_permits: Arc::new(Semaphore::new(self.limits.llm_requests)),
+            extras: Arc::new(self.providers.gemini.clone()),
+            rate_limiter: Arc::clone(&self.rate_limiters.gemini),
+            audit_log: self.audit_log.clone(),
+            budget: Arc::clone(&self.budget),
         }
     }
 
     fn mk_chat_api(&self) -> anyhow::Result<Box<dyn ChatCompletionAPI>> {
-        match &self.chat_platform {
-            Some(ChatCompletionPlatform::OpenAI) => {
-                Ok(Box::new(self.mk_open_ai_api()))
-            },
-            None => anyhow::bail!("No chat provider specified!"),
+        if let Some(path) = &self.llm_replay {
+            return Ok(Box::new(RecordReplayChatAPI::replay(path)?));
         }
+        let api: ChatRoute = if !self.routing.is_empty() {
+            Box::new(self.mk_routing_chat_api()?)
+        } else {
+            match &self.chat_platform {
+                Some(platform) => self.mk_platform_chat_api(*platform)?,
+                None => anyhow::bail!("No chat provider specified!"),
+            }
+        };
+        let api: ChatRoute = match &self.pseudonymizer {
+            Some(pseudonymizer) => Box::new(PseudonymizingAPI {
+                inner: api,
+                pseudonymizer: Arc::clone(pseudonymizer),
+            }),
+            None => api,
+        };
+        match &self.llm_record {
+            Some(path) => Ok(Box::new(RecordReplayChatAPI::record(api, path)?)),
+            None => Ok(api),
+        }
+    }
+
+    fn mk_platform_chat_api(
+        &self,
+        platform: ChatCompletionPlatform,
+    ) -> anyhow::Result<ChatRoute> {
+        Ok(match platform {
+            ChatCompletionPlatform::OpenAI => Box::new(self.mk_open_ai_api()),
+            ChatCompletionPlatform::AzureOpenAI => {
+                Box::new(self.mk_azure_open_ai_api()?)
+            },
+            ChatCompletionPlatform::Gemini => Box::new(self.mk_gemini_api()),
+        })
+    }
+
+    /// The provider of a route, with the model of the route rather than the
+    /// chat model.
+    fn mk_chat_route(
+        &self,
+        route: &ChatRouteConfig,
+    ) -> anyhow::Result<ChatRoute> {
+        Ok(match route.platform {
+            ChatCompletionPlatform::OpenAI => Box::new(OpenAiAPI {
+                chat_model: route.model.clone(),
+                ..self.mk_open_ai_api()
+            }),
+            ChatCompletionPlatform::AzureOpenAI => Box::new(AzureOpenAiAPI {
+                chat_deployment: route.model.clone(),
+                ..self.mk_azure_open_ai_api()?
+            }),
+            ChatCompletionPlatform::Gemini => Box::new(GeminiAPI {
+                chat_model: route.model.clone(),
+                ..self.mk_gemini_api()
+            }),
+        })
+    }
+
+    /// The chat providers of the routing config. The tasks without routes
+    /// go to the chat platform when no default routes are configured.
+    fn mk_routing_chat_api(&self) -> anyhow::Result<RoutingChatAPI> {
+        let routes = if self.routing.chat.is_empty() {
+            let Some(platform) = self.chat_platform else {
+                anyhow::bail!("No chat provider specified!");
+            };
+            vec![self.mk_platform_chat_api(platform)?]
+        } else {
+            self.routing
+                .chat
+                .iter()
+                .map(|route| self.mk_chat_route(route))
+                .collect::<anyhow::Result<_>>()?
+        };
+        let task_routes = self
+            .routing
+            .tasks
+            .iter()
+            .map(|(task, routes)| {
+                let routes = routes
+                    .iter()
+                    .map(|route| self.mk_chat_route(route))
+                    .collect::<anyhow::Result<_>>()?;
+                Ok((*task, routes))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(RoutingChatAPI {
+            routes,
+            task_routes,
+        })
     }
 
     fn mk_embeddings_api(&self) -> anyhow::Result<Box<dyn EmbeddingsAPI>> {
@@ -61,13 +231,30 @@ impl Cli {
             (None, Some(ChatCompletionPlatform::OpenAI)) => {
                 EmbeddingsPlatform::OpenAI
             },
+            (None, Some(ChatCompletionPlatform::AzureOpenAI)) => {
+                EmbeddingsPlatform::AzureOpenAI
+            },
+            (None, Some(ChatCompletionPlatform::Gemini)) => {
+                EmbeddingsPlatform::Gemini
+            },
             (None, None) => {
                 anyhow::bail!("No embeddings or chat platform specified!");
             },
         };
 
-        match platform {
-            EmbeddingsPlatform::OpenAI => Ok(Box::new(self.mk_open_ai_api())),
-        }
+        let api: Box<dyn EmbeddingsAPI> = match platform {
+            EmbeddingsPlatform::OpenAI => Box::new(self.mk_open_ai_api()),
+            EmbeddingsPlatform::AzureOpenAI => {
+                Box::new(self.mk_azure_open_ai_api()?)
+            },
+            EmbeddingsPlatform::Gemini => Box::new(self.mk_gemini_api()),
+        };
+        Ok(match &self.pseudonymizer {
+            Some(pseudonymizer) => Box::new(PseudonymizingAPI {
+                inner: api,
+                pseudonymizer: Arc::clone(pseudonymizer),
+            }),
+            None => api,
+        })
     }
 }
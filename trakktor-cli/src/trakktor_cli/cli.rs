@@ -1,13 +1,38 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
 use clap::{Parser, Subcommand, ValueHint};
 use trakktor::{
-    ai_chat::AIChat, embedding::EmbeddingsPlatform,
-    llm::ChatCompletionPlatform, structify_text::StructifyText,
+    ai_chat::AIChat,
+    app_config::{AppConfigFile, Limits},
+    azure_open_ai::AZURE_OPENAI_DEFAULT_API_VERSION,
+    cancellation::CancellationToken,
+    doctor::DoctorArgs,
+    email_threads::SummarizeEmails,
+    embedding::EmbeddingsPlatform,
+    gemini::GeminiSafetyThreshold,
+    ingest_url::IngestUrl,
+    llm::ChatCompletionPlatform,
+    llm_audit::LlmAuditLog,
+    llm_budget::LlmBudget,
+    locale::Lang,
+    rate_limit::RateLimiters,
+    redact::Pseudonymizer,
+    request_extras::ProvidersConfig,
+    routing::RoutingConfig,
+    search::SearchArgs,
+    structify_text::StructifyText,
+    vector_index::IndexArgs,
+    vector_store::VectorStoreConfig,
 };
 
 pub mod aws_batch;
+pub mod doctor;
+pub mod ingest_url;
+pub mod local_docker;
+mod progress;
+pub mod search;
 pub mod structify_text;
+pub mod summarize_emails;
 
 #[derive(Parser, Debug)]
 #[command(about, long_about = None, arg_required_else_help = true)]
@@ -18,24 +43,125 @@ pub struct Cli {
     /// The verbosity level (0-3).
     #[arg(long, default_value_t = 1)]
     pub verbosity: u8,
+    /// The config file with the default settings. Defaults to
+    /// `~/.config/trakktor/config.toml`.
+    #[arg(long, env = "TRAKKTOR_CONFIG", value_hint = ValueHint::FilePath)]
+    pub config: Option<PathBuf>,
     /// The API key to use for OpenAI.
     #[arg(long, env = "OPENAI_API_KEY")]
     pub openai_api_key: Option<Arc<str>>,
     /// The server URL to use for OpenAI.
     #[arg(long, value_hint = ValueHint::Url, value_parser = url::Url::parse)]
     pub openai_server_url: Option<url::Url>,
+    /// The API key to use for Azure OpenAI.
+    #[arg(long, env = "AZURE_OPENAI_API_KEY")]
+    pub azure_openai_api_key: Option<Arc<str>>,
+    /// The endpoint of the Azure OpenAI resource, e.g.
+    /// `https://my-resource.openai.azure.com`. The chat and embeddings models
+    /// are the names of its deployments.
+    #[arg(
+        long,
+        env = "AZURE_OPENAI_ENDPOINT",
+        value_hint = ValueHint::Url,
+        value_parser = url::Url::parse
+    )]
+    pub azure_openai_endpoint: Option<url::Url>,
+    /// The API key to use for Gemini.
+    #[arg(long, env = "GEMINI_API_KEY")]
+    pub gemini_api_key: Option<Arc<str>>,
+    /// The safety threshold of all the harm categories of Gemini. The
+    /// default of the API is used if not given.
+    #[arg(long)]
+    pub gemini_safety_threshold: Option<GeminiSafetyThreshold>,
+    /// The API version to use for Azure OpenAI.
+    #[arg(long, default_value = AZURE_OPENAI_DEFAULT_API_VERSION)]
+    pub azure_openai_api_version: Arc<str>,
     /// The chat platform to use for chat tasks.
     #[arg(long)]
     pub chat_platform: Option<ChatCompletionPlatform>,
     /// The model to use for chat tasks.
     #[arg(long)]
     pub chat_model: Option<Arc<str>>,
+    /// The seed of the chat completions of OpenAI and Azure OpenAI, for
+    /// reproducible responses. The cached responses are kept by the seed.
+    #[arg(long)]
+    pub chat_seed: Option<u64>,
     /// The embeddings platform to use for embeddings tasks.
     #[arg(long)]
     pub embeddings_platform: Option<EmbeddingsPlatform>,
     /// The model to use for embeddings tasks.
     #[arg(long)]
     pub embeddings_model: Option<Arc<str>>,
+    /// The language of the output and of the default prompts. The output
+    /// follows the system locale and the prompts follow the language of the
+    /// text if not given.
+    #[arg(long, env = "TRAKKTOR_LANG")]
+    pub lang: Option<Lang>,
+    /// Append every chat completion to this JSONL file: the prompt, the
+    /// response, the model, the latency and the token usage.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub llm_audit_log: Option<PathBuf>,
+    /// Replace this text in the audit log, can be repeated.
+    #[arg(long, requires = "llm_audit_log")]
+    pub llm_audit_redact: Vec<String>,
+    /// Replace the email addresses in the audit log.
+    #[arg(long, requires = "llm_audit_log")]
+    pub llm_audit_redact_emails: bool,
+    /// Replace the email addresses, the phone numbers and the names of
+    /// `--pseudonymize-name` with placeholders in the requests to the model
+    /// providers, and the placeholders back in their responses.
+    #[arg(long)]
+    pub pseudonymize: bool,
+    /// A name to pseudonymize, can be repeated. Implies `--pseudonymize`.
+    #[arg(long)]
+    pub pseudonymize_name: Vec<String>,
+    /// Stop the run once the chat and embeddings requests used this many
+    /// tokens.
+    #[arg(long)]
+    pub max_tokens_budget: Option<u64>,
+    /// Stop the run after this many chat and embeddings requests.
+    #[arg(long)]
+    pub max_requests: Option<u64>,
+    /// Record the chat responses to this JSON fixture file, to replay them
+    /// later with `--llm-replay`.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub llm_record: Option<PathBuf>,
+    /// Answer the chat completions from a fixture recorded with
+    /// `--llm-record`, without the network. The requests that were not
+    /// recorded fail.
+    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "llm_record")]
+    pub llm_replay: Option<PathBuf>,
+
+    /// The vector store from the config file.
+    #[arg(skip)]
+    pub vector_store: VectorStoreConfig,
+    /// The concurrency limits from the config file.
+    #[arg(skip)]
+    pub limits: Limits,
+    /// The extra settings of the provider requests from the config file.
+    #[arg(skip)]
+    pub providers: ProvidersConfig,
+    /// The rate limiters of the providers, with the limits from the config
+    /// file.
+    #[arg(skip)]
+    pub rate_limiters: RateLimiters,
+    /// The routing of the chat completions from the config file, unused
+    /// when the chat platform is given on the command line.
+    #[arg(skip)]
+    pub routing: RoutingConfig,
+    /// Cancelled on Ctrl-C, to stop the running operations cleanly.
+    #[arg(skip)]
+    pub cancel: CancellationToken,
+    /// The opened audit log of `--llm-audit-log`.
+    #[arg(skip)]
+    pub audit_log: Option<Arc<LlmAuditLog>>,
+    /// The budget of `--max-tokens-budget` and `--max-requests`.
+    #[arg(skip)]
+    pub budget: Arc<LlmBudget>,
+    /// The placeholders of `--pseudonymize`, shared by the chat and the
+    /// embeddings APIs.
+    #[arg(skip)]
+    pub pseudonymizer: Option<Arc<Pseudonymizer>>,
 
     #[clap(subcommand)]
     pub command: Commands,
@@ -45,9 +171,60 @@ pub struct Cli {
 pub enum Commands {
     /// Handle and manage jobs within AWS Batch.
     AwsBatch(self::aws_batch::AwsBatch),
+    /// Run transcription jobs in Docker containers on this machine.
+    LocalDocker(self::local_docker::LocalDocker),
     /// Run AI to process chat messages from a file.
     AIChat(AIChat),
     /// Automatically structure and summarize unstructured text into sections
     /// and paragraphs.
     StructifyText(StructifyText),
+    /// Extract the main content of a web page, without the boilerplate, and
+    /// structify it.
+    IngestUrl(IngestUrl),
+    /// Group emails into threads and summarize them, with their action items.
+    SummarizeEmails(SummarizeEmails),
+    /// Export and import vector indexes of document corpora.
+    Index(IndexArgs),
+    /// Search the indexed documents in the vector store.
+    Search(SearchArgs),
+    /// Check the environment: the tools, the GPUs, the disk space, the local
+    /// stores, the chat and embeddings platforms, and AWS.
+    Doctor(DoctorArgs),
+}
+
+impl Cli {
+    /// Fills the settings not given on the command line from the config
+    /// file.
+    pub fn apply_config(&mut self, config: &AppConfigFile) {
+        if self.chat_platform.is_none() {
+            self.chat_platform = config.chat_platform;
+            self.routing = config.routing.clone();
+        }
+        if self.chat_model.is_none() {
+            self.chat_model = config.chat_model.clone();
+        }
+        if self.embeddings_platform.is_none() {
+            self.embeddings_platform = config.embeddings_platform;
+        }
+        if self.embeddings_model.is_none() {
+            self.embeddings_model = config.embeddings_model.clone();
+        }
+        if self.lang.is_none() {
+            self.lang = config.lang;
+        }
+        self.vector_store = config.vector_store.clone();
+        self.limits = config.limits;
+        self.providers = config.providers.clone();
+        self.rate_limiters = RateLimiters::new(&config.rate_limits);
+        match &mut self.command {
+            Commands::AwsBatch(aws_batch) => aws_batch.apply_config(config),
+            Commands::Doctor(doctor) => {
+                self::doctor::apply_config(doctor, config)
+            },
+            _ => {},
+        }
+    }
+
+    /// The language of the output.
+    pub fn ui_lang(&self) -> Lang { self.lang.unwrap_or_else(Lang::from_env) }
 }
@@ -1,47 +1,172 @@
 use std::{
-    io::Write,
+    io::{IsTerminal, Write},
+    path::PathBuf,
     sync::{Arc, OnceLock},
 };
 
 use aws_config::Region;
 use clap::{Args, Parser, Subcommand};
-use trakktor::aws_batch::{
-    cloudformation::{verify_base_stack_presence, StackId},
-    delete::{do_delete, DeleteArgs},
-    download::{download_job_result, DownloadArgs},
-    list::list_all_jobs,
-    transcribe::{run_transcribe_job, TranscribeJobArgs},
+use trakktor::{
+    app_config::{AppConfigFile, Limits},
+    aws_batch::{
+        align::{run_align_job, AlignJobArgs},
+        budget::{parse_usd, Cents},
+        cloudformation::{
+            verify_base_stack_presence, GpuInstanceType, NotificationTarget,
+            RetentionPolicy, StackId, StackUpdate, WarmPoolChange,
+        },
+        config::parse_root_prefix,
+        delete::DeleteArgs,
+        describe::{describe_job_failure, DescribeArgs},
+        destroy::destroy_all,
+        download::DownloadArgs,
+        encryption::EncryptionKey,
+        index::{run_index_job, IndexJobArgs},
+        list::ListArgs,
+        plan::{run_plan, PlanArgs},
+        preprocess::{run_preprocess_job, PreprocessJobArgs},
+        prune::{do_prune, PruneArgs},
+        retry::{run_retry_job, RetryArgs},
+        s3::TransferProgress,
+        storage_layout::{
+            check_layout_version, ensure_layout_version, migrate_storage,
+            MigrateStorageArgs,
+        },
+        transcribe::TranscribeJobArgs,
+    },
+    cancellation::CancellationToken,
+    job_backend::{AwsBatchBackend, JobBackend},
+    locale::Lang,
 };
 
-use super::Cli;
+use super::{progress::ProgressBar, Cli};
 
 #[derive(Parser, Debug)]
 pub struct AwsBatch {
-    /// The AWS profile to use.
+    /// The AWS profile to use. Defaults to `aws_profile` of the config file.
     #[arg(long)]
     pub profile: Option<Arc<str>>,
-    /// The AWS region to use.
+    /// The AWS region to use. Defaults to the region of the profile in the
+    /// config file.
     #[arg(long)]
     pub region: Option<Arc<str>>,
-    /// The prefix to use for the CloudFormation stack names.
-    #[arg(short, long, default_value = "trakktor")]
-    pub stack_prefix: Arc<str>,
+    /// The prefix to use for the CloudFormation stack names. Defaults to the
+    /// stack prefix of the profile in the config file, or `trakktor`.
+    #[arg(short, long)]
+    pub stack_prefix: Option<Arc<str>>,
+    /// The key namespace (e.g. `trakktor/v1`) under which all the job data is
+    /// stored in the S3 bucket, allowing the bucket to be shared with other
+    /// applications.
+    #[arg(
+        long,
+        env = "TRAKKTOR_S3_PREFIX",
+        default_value = "",
+        value_parser = parse_root_prefix,
+    )]
+    pub s3_prefix: Box<str>,
+    /// A file with a base64 encoded 256-bit key used to encrypt the job data
+    /// before it is uploaded. The key is passed to the jobs in an SSM
+    /// SecureString parameter, which needs the `ssm:PutParameter`
+    /// permission. Generate it with `openssl rand -base64 32`.
+    #[arg(long, env = "TRAKKTOR_ENCRYPTION_KEY_FILE")]
+    pub encryption_key_file: Option<PathBuf>,
+    /// The maximum estimated cost of a single job, in US dollars.
+    #[arg(long, env = "TRAKKTOR_JOB_BUDGET", value_parser = parse_usd)]
+    pub job_budget: Option<Cents>,
+    /// The maximum estimated cost of all the jobs started in a calendar
+    /// month, in US dollars. Only the jobs still in the storage are counted.
+    #[arg(long, env = "TRAKKTOR_MONTHLY_BUDGET", value_parser = parse_usd)]
+    pub monthly_budget: Option<Cents>,
+    /// The instance type to run the GPU jobs on, applied when the next job
+    /// is submitted. The current instance type is kept if not given.
+    #[arg(long, env = "TRAKKTOR_GPU_INSTANCE_TYPE")]
+    pub gpu_instance_type: Option<GpuInstanceType>,
+    /// The work hours, Monday to Friday, in which a GPU instance is kept
+    /// running, so the jobs start without waiting for one, e.g. `9-18` or
+    /// `9-18@Europe/Berlin`, or `off`. The instance is paid for all the
+    /// hours. Applied when the next job is submitted, the current setting is
+    /// kept if not given.
+    #[arg(
+        long,
+        env = "TRAKKTOR_GPU_WARM_POOL",
+        value_parser = WarmPoolChange::parse,
+    )]
+    pub gpu_warm_pool: Option<WarmPoolChange>,
+    /// Update the existing stacks to the templates of this version without
+    /// asking for confirmation.
+    #[arg(long, env = "TRAKKTOR_AUTO_APPROVE")]
+    pub auto_approve: bool,
+    /// Only allow the commands that do not change the stacks or the jobs:
+    /// `list`, `describe` and `download`. Also set by `read_only` of
+    /// the profile in the config file.
+    #[arg(long, env = "TRAKKTOR_READ_ONLY")]
+    pub read_only: bool,
     #[clap(subcommand)]
     pub command: AwsBatchCommands,
 }
 
+pub(crate) use trakktor::aws_batch::config::DEFAULT_STACK_PREFIX;
+
+impl AwsBatch {
+    /// Fills the AWS settings not given on the command line from the config
+    /// file.
+    pub fn apply_config(&mut self, config: &AppConfigFile) {
+        if self.profile.is_none() {
+            self.profile = config.aws_profile.clone();
+        }
+        let profile_config = config.get_aws_profile(self.profile.as_deref());
+        if self.region.is_none() {
+            self.region = profile_config.region;
+        }
+        if self.stack_prefix.is_none() {
+            self.stack_prefix = profile_config.stack_prefix;
+        }
+        self.read_only |= profile_config.read_only;
+    }
+}
+
 #[derive(Subcommand, Debug)]
 pub enum AwsBatchCommands {
     /// Initialize the Trakktor stack.
     Initialize(Initialize),
     /// List all jobs.
-    List,
+    List(ListArgs),
     /// Download the result of a job.
     Download(DownloadArgs),
     /// Delete a job.
     Delete(DeleteArgs),
+    /// Show why a job failed, with the error reports of its containers and
+    /// the status reasons of AWS Batch.
+    Describe(DescribeArgs),
+    /// Delete old jobs.
+    Prune(PruneArgs),
     /// Run a transcription job.
     Transcribe(TranscribeJobArgs),
+    /// Retry a failed transcription job with the input files it already
+    /// uploaded, optionally with another model or language.
+    Retry(RetryArgs),
+    /// Run a job computing the embeddings of documents into a vector index.
+    Index(IndexJobArgs),
+    /// Run a job aligning the words of a transcript with the audio.
+    Align(AlignJobArgs),
+    /// Run a job normalizing the loudness and reducing the noise of the
+    /// audio.
+    Preprocess(PreprocessJobArgs),
+    /// Preview the changes to the stacks and detect manual changes of them.
+    Plan(PlanArgs),
+    /// Delete all job data and all Trakktor stacks.
+    Destroy(Destroy),
+    /// Upgrade the job data in the storage to the current layout.
+    MigrateStorage(MigrateStorageArgs),
+}
+
+impl AwsBatchCommands {
+    /// Whether the command changes the stacks, the jobs or their data. The
+    /// plan creates change sets and runs drift detections in the account,
+    /// even though it deletes the change sets afterwards.
+    fn is_mutating(&self) -> bool {
+        !matches!(self, Self::List(_) | Self::Describe(_) | Self::Download(_))
+    }
 }
 
 #[derive(Args, Debug)]
@@ -49,10 +174,26 @@ pub struct Initialize {
     /// Silently agree to disclaimer.
     #[arg(long)]
     pub agree: bool,
+    #[command(flatten)]
+    pub retention: RetentionPolicy,
+}
+
+#[derive(Args, Debug)]
+pub struct Destroy {
+    /// Do not ask for confirmation.
+    #[arg(long)]
+    pub yes: bool,
 }
 
 impl Cli {
     pub async fn run_aws_batch(&self, args: &AwsBatch) -> anyhow::Result<()> {
+        if args.read_only && args.command.is_mutating() {
+            anyhow::bail!(
+                "The command changes the stack or its jobs, and is refused in \
+                 the read-only mode. Only `list`, `describe` and `download` \
+                 are allowed."
+            );
+        }
         let mut aws_config = aws_config::from_env();
         if let Some(profile) = &args.profile {
             aws_config = aws_config.profile_name(profile.as_ref());
@@ -62,36 +203,104 @@ impl Cli {
                 aws_config.region(Region::new(region.as_ref().to_owned()));
         }
         let aws_config = aws_config.load().await;
+        let encryption_key = match &args.encryption_key_file {
+            Some(path) => Some(EncryptionKey::load(path).await?),
+            None => None,
+        };
         let config_provider = Arc::new(GenericConfigProvider {
             aws_config,
-            stack_prefix: Arc::clone(&args.stack_prefix),
+            stack_prefix: args
+                .stack_prefix
+                .clone()
+                .unwrap_or_else(|| DEFAULT_STACK_PREFIX.into()),
             s3_bucket: OnceLock::new(),
+            s3_root_prefix: args.s3_prefix.clone(),
+            transfer_progress: ProgressBar::new()
+                .map(|p| Arc::new(p) as Arc<dyn TransferProgress>),
+            encryption_key,
+            retention_changes: match &args.command {
+                AwsBatchCommands::Initialize(init) => {
+                    Some(init.retention.clone())
+                },
+                _ => None,
+            },
+            notification_target: match &args.command {
+                AwsBatchCommands::Transcribe(transcribe) => {
+                    transcribe.notify.clone()
+                },
+                _ => None,
+            },
+            gpu_instance_type: args.gpu_instance_type,
+            gpu_warm_pool: args.gpu_warm_pool.clone(),
+            auto_approve: args.auto_approve,
+            job_budget: args.job_budget,
+            monthly_budget: args.monthly_budget,
             dev_mode: self.dev,
+            limits: self.limits,
+            cancel: self.cancel.clone(),
+            lang: self.ui_lang(),
         });
 
-        if !matches!(&args.command, AwsBatchCommands::Initialize(_)) {
+        if !matches!(
+            &args.command,
+            AwsBatchCommands::Initialize(_) |
+                AwsBatchCommands::Plan(_) |
+                AwsBatchCommands::Destroy(_)
+        ) {
             if !verify_base_stack_presence(&*config_provider).await? {
                 anyhow::bail!(
                     "Base stack not found. Please run `initialize` first."
                 );
             }
+
+            if !matches!(&args.command, AwsBatchCommands::MigrateStorage(_)) {
+                check_layout_version(&*config_provider).await?;
+            }
         }
 
+        let backend = AwsBatchBackend::new(config_provider.clone());
         match &args.command {
             AwsBatchCommands::Initialize(init) => {
                 initialize(config_provider.clone(), init).await?
             },
             AwsBatchCommands::Transcribe(transcribe) => {
-                run_transcribe_job(&*config_provider, transcribe).await?
+                backend.submit(transcribe).await?
+            },
+            AwsBatchCommands::Retry(retry) => {
+                run_retry_job(config_provider.clone(), retry).await?
+            },
+            AwsBatchCommands::Index(index) => {
+                run_index_job(config_provider.clone(), index).await?
+            },
+            AwsBatchCommands::Align(align) => {
+                run_align_job(config_provider.clone(), align).await?
+            },
+            AwsBatchCommands::Preprocess(preprocess) => {
+                run_preprocess_job(config_provider.clone(), preprocess).await?
             },
             AwsBatchCommands::Download(download) => {
-                download_job_result(&*config_provider, download).await?
+                backend.download(download).await?
             },
-            AwsBatchCommands::List => {
-                list_all_jobs(config_provider.clone()).await?
+            AwsBatchCommands::List(list_args) => {
+                backend.list(list_args).await?
             },
             AwsBatchCommands::Delete(delete_args) => {
-                do_delete(config_provider.clone(), delete_args).await?
+                backend.delete(delete_args).await?
+            },
+            AwsBatchCommands::Describe(describe_args) => {
+                describe_job_failure(&*config_provider, describe_args).await?
+            },
+            AwsBatchCommands::Prune(prune_args) => {
+                do_prune(config_provider.clone(), prune_args).await?
+            },
+            AwsBatchCommands::Plan(plan_args) => {
+                run_plan(&*config_provider, plan_args).await?
+            },
+            AwsBatchCommands::Destroy(destroy_args) => {
+                destroy(config_provider.clone(), destroy_args).await?
+            },
+            AwsBatchCommands::MigrateStorage(migrate_args) => {
+                migrate_storage(config_provider.clone(), migrate_args).await?
             },
         }
 
@@ -112,7 +321,7 @@ async fn initialize(
         std::io::stdout().flush()?;
         let mut input = String::new();
         std::io::stdin().read_line(&mut input)?;
-        if input.trim().to_lowercase() != "yes" {
+        if !config_provider.lang.strings().is_yes(&input) {
             anyhow::bail!("User did not agree to disclaimer.");
         }
     }
@@ -123,26 +332,120 @@ async fn initialize(
     )
     .await?;
 
+    check_layout_version(&*config_provider).await?;
+    ensure_layout_version(&*config_provider).await?;
+
     tracing::info!("Trakktor stack initialized.");
 
     Ok(())
 }
 
+#[tracing::instrument(level = "info", skip_all)]
+async fn destroy(
+    config_provider: Arc<GenericConfigProvider>,
+    destroy_args: &Destroy,
+) -> anyhow::Result<()> {
+    let strings = config_provider.lang.strings();
+    if !destroy_args.yes {
+        println!(
+            "\n{}\n{}\n",
+            (strings.destroy_warning)(&config_provider.stack_prefix),
+            strings.continue_question
+        );
+
+        print!("> ");
+        std::io::stdout().flush()?;
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        if !strings.is_yes(&input) {
+            anyhow::bail!("User did not confirm the destruction.");
+        }
+    }
+
+    destroy_all(&*config_provider).await?;
+
+    Ok(())
+}
+
 struct GenericConfigProvider {
     aws_config: aws_config::SdkConfig,
     stack_prefix: Arc<str>,
     s3_bucket: OnceLock<Box<str>>,
+    s3_root_prefix: Box<str>,
+    transfer_progress: Option<Arc<dyn TransferProgress>>,
+    encryption_key: Option<EncryptionKey>,
+    retention_changes: Option<RetentionPolicy>,
+    notification_target: Option<NotificationTarget>,
+    gpu_instance_type: Option<GpuInstanceType>,
+    gpu_warm_pool: Option<WarmPoolChange>,
+    auto_approve: bool,
+    job_budget: Option<Cents>,
+    monthly_budget: Option<Cents>,
     dev_mode: bool,
+    limits: Limits,
+    cancel: CancellationToken,
+    lang: Lang,
 }
 
 impl trakktor::aws_batch::config::AwsConfigProvider for GenericConfigProvider {
     fn get_aws_config(&self) -> &aws_config::SdkConfig { &self.aws_config }
+
+    fn get_limits(&self) -> Limits { self.limits }
+
+    fn get_cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
 }
 
 impl trakktor::aws_batch::config::CloudFormationStackProvider
     for GenericConfigProvider
 {
     fn get_stack_prefix(&self) -> &str { &self.stack_prefix }
+
+    fn get_retention_changes(&self) -> Option<&RetentionPolicy> {
+        self.retention_changes.as_ref()
+    }
+
+    fn get_notification_target(&self) -> Option<&NotificationTarget> {
+        self.notification_target.as_ref()
+    }
+
+    fn approve_stack_update(
+        &self,
+        update: &StackUpdate,
+    ) -> anyhow::Result<bool> {
+        if self.auto_approve {
+            return Ok(true);
+        }
+        if !std::io::stdin().is_terminal() {
+            anyhow::bail!(
+                "The stack {} has to be updated, use --auto-approve to allow \
+                 it.",
+                update.stack_name
+            );
+        }
+
+        let strings = self.lang.strings();
+        println!(
+            "\n{}\n\n{}\n{}\n",
+            (strings.stack_update_notice)(&update.stack_name),
+            update.template_diff,
+            strings.continue_question
+        );
+        print!("> ");
+        std::io::stdout().flush()?;
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        Ok(strings.is_yes(&input))
+    }
+
+    fn get_gpu_instance_type(&self) -> Option<GpuInstanceType> {
+        self.gpu_instance_type
+    }
+
+    fn get_gpu_warm_pool_change(&self) -> Option<&WarmPoolChange> {
+        self.gpu_warm_pool.as_ref()
+    }
 }
 
 impl trakktor::aws_batch::config::S3Provider for GenericConfigProvider {
@@ -153,8 +456,28 @@ impl trakktor::aws_batch::config::S3Provider for GenericConfigProvider {
             )
         })
     }
+
+    fn get_root_prefix(&self) -> &str { &self.s3_root_prefix }
+
+    fn get_transfer_progress(&self) -> Option<Arc<dyn TransferProgress>> {
+        self.transfer_progress.clone()
+    }
+
+    fn get_encryption_key(&self) -> Option<&EncryptionKey> {
+        self.encryption_key.as_ref()
+    }
+
+    fn get_pending_uploads_path(&self) -> Option<std::path::PathBuf> {
+        trakktor::aws_batch::pending_uploads::default_pending_uploads_path()
+    }
 }
 
 impl trakktor::app_config::AppConfigProvider for GenericConfigProvider {
     fn is_dev_mode(&self) -> bool { self.dev_mode }
+
+    fn get_job_budget(&self) -> Option<Cents> { self.job_budget }
+
+    fn get_monthly_budget(&self) -> Option<Cents> { self.monthly_budget }
+
+    fn get_lang(&self) -> Lang { self.lang }
 }
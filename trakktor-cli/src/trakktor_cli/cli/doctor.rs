@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use aws_config::Region;
+use trakktor::{
+    app_config::{default_data_dir, AppConfigFile},
+    aws_batch::{
+        config::{AwsConfigProvider, CloudFormationStackProvider},
+        doctor::check_aws,
+    },
+    doctor::{
+        check_chat, check_database, check_disk_space, check_embeddings,
+        check_gpus, check_tools, print_report, Check, CheckStatus, DoctorArgs,
+    },
+};
+
+use super::{aws_batch::DEFAULT_STACK_PREFIX, Cli};
+
+/// Fills the AWS settings not given on the command line from the config
+/// file.
+pub fn apply_config(args: &mut DoctorArgs, config: &AppConfigFile) {
+    if args.profile.is_none() {
+        args.profile = config.aws_profile.clone();
+    }
+    let profile_config = config.get_aws_profile(args.profile.as_deref());
+    if args.region.is_none() {
+        args.region = profile_config.region;
+    }
+    if args.stack_prefix.is_none() {
+        args.stack_prefix = profile_config.stack_prefix;
+    }
+}
+
+struct DoctorConfigProvider {
+    aws_config: aws_config::SdkConfig,
+    stack_prefix: Arc<str>,
+}
+
+impl AwsConfigProvider for DoctorConfigProvider {
+    fn get_aws_config(&self) -> &aws_config::SdkConfig { &self.aws_config }
+}
+
+impl CloudFormationStackProvider for DoctorConfigProvider {
+    fn get_stack_prefix(&self) -> &str { &self.stack_prefix }
+}
+
+impl Cli {
+    pub async fn run_doctor(&self, args: &DoctorArgs) -> anyhow::Result<()> {
+        let mut checks = check_tools().await;
+        checks.push(check_gpus().await);
+
+        match default_data_dir() {
+            Ok(data_dir) => checks.push(check_disk_space(&data_dir).await),
+            Err(err) => checks.push(Check::from_result("disk space", Err(err))),
+        }
+        match self.vector_store.local_path() {
+            Ok(Some(path)) => {
+                checks.push(check_database("local vector store", &path).await)
+            },
+            Ok(None) => {},
+            Err(err) => {
+                checks.push(Check::from_result("local vector store", Err(err)))
+            },
+        }
+
+        if args.no_platforms {
+            checks.push(Check::new(
+                "chat platform",
+                CheckStatus::Skip,
+                "--no-platforms",
+            ));
+        } else if self.chat_platform.is_none() {
+            checks.push(Check::new(
+                "chat platform",
+                CheckStatus::Skip,
+                "No chat platform specified",
+            ));
+        } else {
+            checks.push(match self.mk_chat_api() {
+                Ok(api) => check_chat(api.as_ref()).await,
+                Err(err) => Check::from_result("chat platform", Err(err)),
+            });
+        }
+        if !args.no_platforms &&
+            (self.embeddings_platform.is_some() ||
+                self.chat_platform.is_some())
+        {
+            checks.push(match self.mk_embeddings_api() {
+                Ok(api) => check_embeddings(api.as_ref()).await,
+                Err(err) => Check::from_result("embeddings platform", Err(err)),
+            });
+        }
+
+        if args.no_aws {
+            checks.push(Check::new("AWS", CheckStatus::Skip, "--no-aws"));
+        } else {
+            let mut aws_config = aws_config::from_env();
+            if let Some(profile) = &args.profile {
+                aws_config = aws_config.profile_name(profile.as_ref());
+            }
+            if let Some(region) = &args.region {
+                aws_config =
+                    aws_config.region(Region::new(region.as_ref().to_owned()));
+            }
+            let config = DoctorConfigProvider {
+                aws_config: aws_config.load().await,
+                stack_prefix: args
+                    .stack_prefix
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_STACK_PREFIX.into()),
+            };
+            checks.extend(check_aws(&config).await);
+        }
+
+        if !print_report(&checks) {
+            anyhow::bail!("Some checks failed.");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+use trakktor::{
+    ingest_url::{ingest_url, IngestUrl},
+    structify_text::{run_structify_text, Sectioning, StructifyText},
+};
+
+use super::Cli;
+
+impl Cli {
+    pub async fn ingest_url(&self, args: &IngestUrl) -> anyhow::Result<()> {
+        // Fail before fetching the page if no chat platform is configured.
+        let chat_api = if args.no_structify {
+            None
+        } else {
+            Some(self.mk_chat_api()?)
+        };
+
+        let file = ingest_url(args).await?;
+
+        if let Some(chat_api) = &chat_api {
+            let structify = StructifyText {
+                file,
+                out_dir: None,
+                ocr: Default::default(),
+                dry_run: false,
+                jobs: 1,
+                no_punctuate: false,
+                dialogue: false,
+                ignore_speakers: false,
+                section_per_turn: false,
+                sectioning: Sectioning::Model,
+                prompt_set: args.prompt_set.clone(),
+                language: None,
+                verbatim: false,
+                outline: Default::default(),
+            };
+            run_structify_text(
+                &structify,
+                chat_api.as_ref(),
+                None,
+                self.lang,
+                &self.cancel,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+}
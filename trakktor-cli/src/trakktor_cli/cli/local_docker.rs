@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use trakktor::{
+    aws_batch::{
+        delete::DeleteArgs, download::DownloadArgs, list::ListArgs,
+        transcribe::TranscribeJobArgs,
+    },
+    job_backend::{JobBackend, LocalDockerBackend},
+};
+
+use super::Cli;
+
+#[derive(Parser, Debug)]
+pub struct LocalDocker {
+    /// The directory to keep the jobs in. Defaults to
+    /// `~/.local/share/trakktor/jobs`.
+    #[arg(long, env = "TRAKKTOR_LOCAL_DIR")]
+    pub dir: Option<PathBuf>,
+    /// Run the containers without GPUs, for machines without the NVIDIA
+    /// Container Toolkit. The transcription is much slower.
+    #[arg(long)]
+    pub no_gpu: bool,
+    #[clap(subcommand)]
+    pub command: LocalDockerCommands,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LocalDockerCommands {
+    /// List all jobs.
+    List(ListArgs),
+    /// Download the result of a job.
+    Download(DownloadArgs),
+    /// Delete a job.
+    Delete(DeleteArgs),
+    /// Run a transcription job.
+    Transcribe(TranscribeJobArgs),
+}
+
+impl Cli {
+    pub async fn run_local_docker(
+        &self,
+        args: &LocalDocker,
+    ) -> anyhow::Result<()> {
+        let backend =
+            LocalDockerBackend::new(args.dir.clone(), !args.no_gpu, self.dev)?;
+
+        match &args.command {
+            LocalDockerCommands::List(list_args) => {
+                backend.list(list_args).await?
+            },
+            LocalDockerCommands::Download(download) => {
+                backend.download(download).await?
+            },
+            LocalDockerCommands::Delete(delete_args) => {
+                backend.delete(delete_args).await?
+            },
+            LocalDockerCommands::Transcribe(transcribe) => {
+                backend.submit(transcribe).await?
+            },
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use std::{collections::HashMap, io::IsTerminal, sync::Mutex};
+
+use indicatif::ProgressStyle;
+use trakktor::aws_batch::s3::TransferProgress;
+
+const TEMPLATE: &str = "{msg} [{bar:30}] {percent:>3}% \
+                        {binary_bytes}/{binary_total_bytes} \
+                        {binary_bytes_per_sec}";
+
+/// Progress bar of all the running transfers, drawn on stderr.
+pub struct ProgressBar {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The bar of the running transfers, cleared when they all finish.
+    bar: Option<indicatif::ProgressBar>,
+    /// Transferred and total bytes of each running transfer.
+    transfers: HashMap<Box<str>, (u64, u64)>,
+}
+
+impl ProgressBar {
+    /// Create a progress bar if stderr is a terminal.
+    pub fn new() -> Option<Self> {
+        std::io::stderr().is_terminal().then(|| ProgressBar {
+            state: Mutex::new(State::default()),
+        })
+    }
+}
+
+impl State {
+    fn bar(&mut self) -> &indicatif::ProgressBar {
+        self.bar.get_or_insert_with(|| {
+            indicatif::ProgressBar::new(0).with_style(
+                ProgressStyle::with_template(TEMPLATE)
+                    .expect("valid progress template")
+                    .progress_chars("#>-"),
+            )
+        })
+    }
+
+    fn update_message(&mut self) {
+        let message = match self.transfers.len() {
+            1 => "1 transfer".to_string(),
+            n => format!("{n} transfers"),
+        };
+        self.bar().set_message(message);
+    }
+}
+
+impl TransferProgress for ProgressBar {
+    fn start(&self, s3_key: &str, total_bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.transfers.insert(s3_key.into(), (0, total_bytes));
+        state.bar().inc_length(total_bytes);
+        state.update_message();
+    }
+
+    fn advance(&self, s3_key: &str, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some((done, _)) = state.transfers.get_mut(s3_key) {
+            *done += bytes;
+            state.bar().inc(bytes);
+        }
+    }
+
+    fn finish(&self, s3_key: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some((done, total)) = state.transfers.remove(s3_key) {
+            state.bar().inc(total.saturating_sub(done));
+        }
+        if state.transfers.is_empty() {
+            if let Some(bar) = state.bar.take() {
+                bar.finish_and_clear();
+            }
+        } else {
+            state.update_message();
+        }
+    }
+}
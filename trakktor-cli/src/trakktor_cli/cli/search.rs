@@ -0,0 +1,16 @@
+use trakktor::search::{run_search, SearchArgs};
+
+use super::Cli;
+
+impl Cli {
+    pub async fn search(&self, search: &SearchArgs) -> anyhow::Result<()> {
+        let chat_api = match search.rerank {
+            true => Some(self.mk_chat_api()?),
+            false => None,
+        };
+        run_search(search, &self.vector_store, chat_api.as_deref(), self.dev)
+            .await?;
+
+        Ok(())
+    }
+}
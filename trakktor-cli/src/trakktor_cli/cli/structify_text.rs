@@ -1,4 +1,4 @@
-use trakktor::structify_text::{run_structify_text, StructifyText};
+use trakktor::structify_text::{run_structify_text, Sectioning, StructifyText};
 
 use super::Cli;
 
@@ -8,7 +8,18 @@ impl Cli {
         structify_text: &StructifyText,
     ) -> anyhow::Result<()> {
         let chat_api = self.mk_chat_api()?;
-        run_structify_text(structify_text, &chat_api).await?;
+        let embeddings_api = match structify_text.sectioning {
+            Sectioning::Embeddings => Some(self.mk_embeddings_api()?),
+            Sectioning::Model => None,
+        };
+        run_structify_text(
+            structify_text,
+            chat_api.as_ref(),
+            embeddings_api.as_deref(),
+            self.lang,
+            &self.cancel,
+        )
+        .await?;
 
         Ok(())
     }
@@ -0,0 +1,15 @@
+use trakktor::email_threads::{run_summarize_emails, SummarizeEmails};
+
+use super::Cli;
+
+impl Cli {
+    pub async fn summarize_emails(
+        &self,
+        summarize_emails: &SummarizeEmails,
+    ) -> anyhow::Result<()> {
+        let chat_api = self.mk_chat_api()?;
+        run_summarize_emails(summarize_emails, chat_api.as_ref()).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use trakktor::aws_batch::job::{
+    make_array_done_flag, make_array_heartbeat, JOB_DONE_FLAG, JOB_HEARTBEAT,
+};
+
+/// The environment of a Whisper job container, as set by
+/// [`trakktor::aws_batch::whisper::WhisperJobArgs`], AWS Batch and the
+/// image.
+#[derive(Debug, Deserialize)]
+pub struct WorkerEnv {
+    #[serde(rename = "TRK_JOB_UID")]
+    pub job_uid: String,
+    /// Storage key prefix of all the job objects.
+    #[serde(rename = "TRK_JOB_PREFIX")]
+    pub job_prefix: String,
+    /// The file to transcribe, for regular jobs.
+    #[serde(rename = "TRK_INPUT_FILE")]
+    pub input_file: Option<String>,
+    /// The list of files to transcribe, for array jobs.
+    #[serde(rename = "TRK_INPUT_LIST")]
+    pub input_list: Option<String>,
+    /// The URL to fetch the input from, for the jobs of URLs.
+    #[serde(rename = "TRK_INPUT_URL")]
+    pub input_url: Option<String>,
+    #[serde(rename = "TRK_LANGUAGE")]
+    pub language: String,
+    /// The text Whisper is prompted with, like the names in the audio.
+    #[serde(rename = "TRK_INITIAL_PROMPT")]
+    pub initial_prompt: Option<String>,
+    /// The model the job expects the image to run.
+    #[serde(rename = "TRK_MODEL")]
+    pub model: Option<String>,
+    #[serde(rename = "TRK_OUTPUT_FORMATS")]
+    pub output_formats: Option<String>,
+    #[serde(rename = "TRK_COMPRESS_FORMATS")]
+    pub compress_formats: Option<String>,
+    /// The SSM parameter holding the key of the client-side encryption.
+    #[serde(rename = "TRK_ENCRYPTION_KEY_PARAMETER")]
+    pub encryption_key_parameter: Option<String>,
+    /// The directory the job data is kept in instead of the S3 bucket, for
+    /// the jobs run with the local Docker.
+    #[serde(rename = "TRK_LOCAL_STORAGE")]
+    pub local_storage: Option<PathBuf>,
+    /// Set by the GPU batch stack.
+    #[serde(rename = "S3_STORAGE_BUCKET")]
+    pub bucket: Option<String>,
+    /// The index of the child of an array job, set by AWS Batch.
+    #[serde(rename = "AWS_BATCH_JOB_ARRAY_INDEX")]
+    pub array_index: Option<String>,
+    /// The model the image contains.
+    #[serde(rename = "WHISPER_MODEL")]
+    pub whisper_model: String,
+}
+
+impl WorkerEnv {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_vars(std::env::vars())
+    }
+
+    /// Reads the variables, the unknown ones and the empty ones are ignored.
+    pub fn from_vars(
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> anyhow::Result<Self> {
+        let vars = vars
+            .into_iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(key, value)| (key, serde_json::Value::String(value)))
+            .collect::<serde_json::Map<_, _>>();
+        Ok(serde_json::from_value(serde_json::Value::Object(vars))?)
+    }
+
+    pub fn array_index(&self) -> anyhow::Result<Option<u32>> {
+        self.array_index
+            .as_deref()
+            .map(|index| {
+                index.parse().map_err(|_| {
+                    anyhow::anyhow!("Invalid array index: {index}")
+                })
+            })
+            .transpose()
+    }
+
+    /// The name of the flag written once the job is done.
+    pub fn done_flag(&self) -> anyhow::Result<Box<str>> {
+        Ok(match self.array_index()? {
+            Some(index) => make_array_done_flag(index),
+            None => JOB_DONE_FLAG.into(),
+        })
+    }
+
+    /// The name of the heartbeat of the running job.
+    pub fn heartbeat(&self) -> anyhow::Result<Box<str>> {
+        Ok(match self.array_index()? {
+            Some(index) => make_array_heartbeat(index),
+            None => JOB_HEARTBEAT.into(),
+        })
+    }
+}
+
+#[test]
+fn worker_env_test() {
+    let vars = [
+        ("TRK_JOB_UID", "jid"),
+        ("TRK_JOB_PREFIX", "trakktor/jid/"),
+        ("TRK_INPUT_LIST", "inputs.🚜-list"),
+        ("TRK_LANGUAGE", "en"),
+        ("TRK_OUTPUT_FORMATS", "txt,srt"),
+        ("TRK_COMPRESS_FORMATS", ""),
+        ("AWS_BATCH_JOB_ARRAY_INDEX", "2"),
+        ("WHISPER_MODEL", "large-v3"),
+        ("PATH", "/usr/bin"),
+    ]
+    .map(|(key, value)| (key.to_string(), value.to_string()));
+    let env = WorkerEnv::from_vars(vars).unwrap();
+    assert_eq!(env.job_prefix, "trakktor/jid/");
+    assert_eq!(env.input_file, None);
+    assert_eq!(env.input_url, None);
+    assert_eq!(env.initial_prompt, None);
+    assert_eq!(env.output_formats.as_deref(), Some("txt,srt"));
+    assert_eq!(env.compress_formats, None);
+    assert_eq!(env.done_flag().unwrap().as_ref(), "done-2.🚜-flag");
+    assert_eq!(env.heartbeat().unwrap().as_ref(), "heartbeat-2.🚜-beat");
+
+    let err = WorkerEnv::from_vars([]).unwrap_err();
+    assert!(err.to_string().contains("TRK_JOB_UID"));
+}
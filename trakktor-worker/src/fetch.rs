@@ -0,0 +1,60 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use anyhow::{bail, Context};
+
+/// The name of the fetched file, from the title and the ID of the media,
+/// shortened to fit the object keys.
+const OUTPUT_TEMPLATE: &str = "%(title).100B [%(id)s].%(ext)s";
+
+/// Fetches the input from the URL into the directory with `yt-dlp`, and
+/// returns the file. A page is resolved to its audio, a direct link to a
+/// media file is downloaded as it is.
+#[tracing::instrument(level = "info")]
+pub async fn fetch_input(url: &str, dir: &Path) -> anyhow::Result<PathBuf> {
+    tokio::fs::create_dir_all(dir).await?;
+    let output = tokio::process::Command::new("yt-dlp")
+        .args(["--no-playlist", "--no-progress", "--restrict-filenames"])
+        .args(["--format", "bestaudio/best", "--output", OUTPUT_TEMPLATE])
+        .args(["--no-simulate", "--print", "after_move:filepath", "--paths"])
+        .arg(dir)
+        .arg("--")
+        .arg(url)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .context("Failed to run yt-dlp")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().rev().find(|l| !l.trim().is_empty());
+        bail!(
+            "yt-dlp failed: {}: {}",
+            output.status,
+            reason.unwrap_or("-")
+        );
+    }
+
+    parse_fetched_file(&String::from_utf8_lossy(&output.stdout))
+        .context("yt-dlp did not print the fetched file")
+}
+
+/// The file `yt-dlp` printed the path of last.
+fn parse_fetched_file(stdout: &str) -> Option<PathBuf> {
+    stdout
+        .lines()
+        .map(str::trim)
+        .rev()
+        .find(|line| !line.is_empty())
+        .map(PathBuf::from)
+}
+
+#[test]
+fn parse_fetched_file_test() {
+    assert_eq!(
+        parse_fetched_file("/task/in/Episode_1 [abc].m4a\n\n"),
+        Some(PathBuf::from("/task/in/Episode_1 [abc].m4a"))
+    );
+    assert_eq!(parse_fetched_file(" \n"), None);
+}
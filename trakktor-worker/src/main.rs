@@ -0,0 +1,502 @@
+//! The entrypoint of the Whisper job container: downloads the input of the
+//! job, or fetches it from its URL, transcribes it, and uploads the outputs and
+//! the done flag. A failed job writes a [`JobErrorReport`] with the stage it
+//! failed at instead of the done flag.
+
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{bail, Context};
+use env::WorkerEnv;
+use storage::{S3Storage, Storage};
+use tokio::io::AsyncBufReadExt;
+use trakktor::aws_batch::{
+    job::{
+        make_array_error_report, Heartbeat, JobErrorReport, JobStage,
+        HEARTBEAT_INTERVAL, JOB_ERROR_REPORT, JOB_IN_PREFIX, JOB_OUT_PREFIX,
+    },
+    key_parameter::get_key_parameter,
+    transcode::{make_transcoded_file_name, transcode_range, TranscodeFormat},
+};
+
+mod env;
+mod fetch;
+mod outputs;
+mod storage;
+
+/// The directory the job data is kept in while it runs.
+const WORK_DIR: &str = "/task";
+/// The directory the image keeps the Whisper models in.
+const MODELS_DIR: &str = "/whisper_models";
+/// The `transcribe` binary of `trakktor_candle` in the image.
+const TRANSCRIBER: &str = "/transcribe";
+
+/// The lines of the standard error of the transcriber kept for the error
+/// report.
+const STDERR_TAIL_LINES: usize = 30;
+
+struct Failure {
+    stage: JobStage,
+    error: anyhow::Error,
+    stderr_tail: Vec<String>,
+}
+
+/// Marks the errors with the stage they happened at.
+fn at(stage: JobStage) -> impl FnOnce(anyhow::Error) -> Failure {
+    move |error| Failure {
+        stage,
+        error,
+        stderr_tail: vec![],
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_target(false)
+        .without_time()
+        .init();
+
+    let env = match WorkerEnv::from_env() {
+        Ok(env) => env,
+        Err(err) => {
+            // There is no job to report to.
+            tracing::error!("Failed to read the environment: {err:#}");
+            std::process::exit(1);
+        },
+    };
+    let storage = match make_storage(&env).await {
+        Ok(storage) => storage,
+        Err(err) => {
+            tracing::error!("Failed to access the storage: {err:#}");
+            std::process::exit(1);
+        },
+    };
+
+    let mut input_file = env.input_file.clone();
+    if let Err(failure) =
+        run(&env, &storage, &mut input_file, Path::new(WORK_DIR)).await
+    {
+        tracing::error!("Failed at {:?}: {:#}", failure.stage, failure.error);
+        if let Err(err) =
+            report_failure(&env, &storage, failure, input_file).await
+        {
+            tracing::error!("Failed to write the error report: {err:#}");
+        }
+        std::process::exit(1);
+    }
+}
+
+async fn make_storage(env: &WorkerEnv) -> anyhow::Result<Storage> {
+    if let Some(dir) = &env.local_storage {
+        return Ok(Storage::Local(dir.clone()));
+    }
+    let Some(bucket) = &env.bucket else {
+        bail!("Neither S3_STORAGE_BUCKET nor TRK_LOCAL_STORAGE is set");
+    };
+    let aws_config = aws_config::from_env().load().await;
+    let encryption_key = match &env.encryption_key_parameter {
+        Some(name) => Some(get_key_parameter(&aws_config, name).await?),
+        None => None,
+    };
+    Ok(Storage::S3(Box::new(S3Storage {
+        aws_config,
+        bucket: bucket.clone(),
+        encryption_key,
+    })))
+}
+
+async fn run(
+    env: &WorkerEnv,
+    storage: &Storage,
+    input_file: &mut Option<String>,
+    work_dir: &Path,
+) -> Result<(), Failure> {
+    tracing::info!(
+        job_uid = env.job_uid,
+        job_prefix = env.job_prefix,
+        language = env.language,
+        model = env.model,
+        output_formats = env.output_formats,
+        compress_formats = env.compress_formats,
+        encryption = env.encryption_key_parameter.is_some(),
+        "Starting the job"
+    );
+
+    if let Some(model) = &env.model {
+        if *model != env.whisper_model {
+            return Err(at(JobStage::Config)(anyhow::anyhow!(
+                "Job requested model {model}, image contains {}",
+                env.whisper_model
+            )));
+        }
+    }
+    let done_flag = env.done_flag().map_err(at(JobStage::Config))?;
+    let heartbeat = env.heartbeat().map_err(at(JobStage::Config))?;
+    let heartbeat_key = format!("{}{heartbeat}", env.job_prefix);
+
+    let segments = Arc::new(AtomicU64::new(0));
+    tokio::select! {
+        res = run_stages(env, storage, input_file, work_dir, &segments) => res?,
+        _ = beat(storage, &heartbeat_key, &segments) => unreachable!(),
+    }
+    storage
+        .put_plain(&[], &format!("{}{done_flag}", env.job_prefix))
+        .await
+        .map_err(at(JobStage::Upload))?;
+
+    tracing::info!("Done");
+    Ok(())
+}
+
+/// Writes the heartbeat of the job every [`HEARTBEAT_INTERVAL`], until
+/// dropped. The failed writes are only logged, the job goes on.
+async fn beat(storage: &Storage, key: &str, segments: &AtomicU64) {
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let heartbeat = Heartbeat {
+            time: chrono::Utc::now(),
+            segments: segments.load(Ordering::Relaxed),
+        };
+        let res = match serde_json::to_vec(&heartbeat) {
+            Ok(data) => storage.put_plain(&data, key).await,
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = res {
+            tracing::warn!("Failed to write the heartbeat: {err:#}");
+        }
+    }
+}
+
+async fn run_stages(
+    env: &WorkerEnv,
+    storage: &Storage,
+    input_file: &mut Option<String>,
+    work_dir: &Path,
+    segments: &AtomicU64,
+) -> Result<(), Failure> {
+    let input = match &env.input_url {
+        Some(url) => {
+            fetch_input(env, storage, url, input_file, work_dir).await?
+        },
+        None => download_input(env, storage, input_file, work_dir).await?,
+    };
+
+    let out_dir = work_dir.join("out");
+    transcribe(env, &input, work_dir, &out_dir, segments).await?;
+
+    let outputs = outputs::prepare_outputs(
+        &out_dir,
+        env.output_formats.as_deref(),
+        env.compress_formats.as_deref(),
+    )
+    .await
+    .map_err(at(JobStage::Outputs))?;
+
+    for file in outputs {
+        let name = file.file_name().unwrap().to_string_lossy();
+        storage
+            .upload(&file, &format!("{}{JOB_OUT_PREFIX}{name}", env.job_prefix))
+            .await
+            .map_err(at(JobStage::Upload))?;
+    }
+    Ok(())
+}
+
+/// Fetches the input from the URL, and stores it in the input folder of the
+/// job like an uploaded one, so the job can be retried without the URL.
+async fn fetch_input(
+    env: &WorkerEnv,
+    storage: &Storage,
+    url: &str,
+    input_file: &mut Option<String>,
+    work_dir: &Path,
+) -> Result<PathBuf, Failure> {
+    *input_file = Some(url.to_string());
+    let input = fetch::fetch_input(url, &work_dir.join("in"))
+        .await
+        .map_err(at(JobStage::Download))?;
+    let name = input
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| {
+            at(JobStage::Download)(anyhow::anyhow!("No fetched file name"))
+        })?;
+    tracing::info!(input_file = name, "Fetched input file");
+    storage
+        .upload(&input, &format!("{}{JOB_IN_PREFIX}{name}", env.job_prefix))
+        .await
+        .map_err(at(JobStage::Upload))?;
+    *input_file = Some(name);
+    Ok(input)
+}
+
+async fn download_input(
+    env: &WorkerEnv,
+    storage: &Storage,
+    input_file: &mut Option<String>,
+    work_dir: &Path,
+) -> Result<PathBuf, Failure> {
+    // Children of an array job pick their input file from the input list by
+    // their index.
+    if let Some(index) = env.array_index().map_err(at(JobStage::Config))? {
+        let Some(input_list) = &env.input_list else {
+            return Err(at(JobStage::Config)(anyhow::anyhow!(
+                "No input list for the array job"
+            )));
+        };
+        let list = storage
+            .download(&env.job_prefix, input_list, &work_dir.join("list"))
+            .await
+            .map_err(at(JobStage::Download))?;
+        let list = tokio::fs::read_to_string(&list)
+            .await
+            .map_err(|err| at(JobStage::Download)(err.into()))?;
+        *input_file = list.lines().nth(index as usize).map(str::to_string);
+    }
+    let Some(input_file) = input_file.as_deref() else {
+        return Err(at(JobStage::Config)(anyhow::anyhow!("No input file")));
+    };
+    tracing::info!(input_file, "Input file");
+
+    storage
+        .download(
+            &format!("{}{JOB_IN_PREFIX}", env.job_prefix),
+            input_file,
+            &work_dir.join("in"),
+        )
+        .await
+        .map_err(at(JobStage::Download))
+}
+
+/// Runs the `trakktor_candle` transcriber on the input, writing the outputs
+/// of all the formats to the directory. The segments it prints are counted,
+/// and the last lines of its standard error are kept for the error report.
+async fn transcribe(
+    env: &WorkerEnv,
+    input: &Path,
+    work_dir: &Path,
+    out_dir: &Path,
+    segments: &AtomicU64,
+) -> Result<(), Failure> {
+    let audio = decode_input(input, work_dir)
+        .await
+        .map_err(at(JobStage::Transcribe))?;
+    let stderr_tail = Arc::new(std::sync::Mutex::new(VecDeque::new()));
+    let res =
+        run_transcriber(env, &audio, out_dir, segments, &stderr_tail).await;
+    res.map_err(|error| Failure {
+        stage: JobStage::Transcribe,
+        error,
+        stderr_tail: stderr_tail.lock().unwrap().drain(..).collect(),
+    })
+}
+
+/// Decodes the audio of the input into the 16 kHz mono FLAC file the
+/// transcriber reads, named like the input so the outputs are.
+async fn decode_input(
+    input: &Path,
+    work_dir: &Path,
+) -> anyhow::Result<PathBuf> {
+    let dir = work_dir.join("audio");
+    tokio::fs::create_dir_all(&dir).await?;
+    let name = input.file_name().context("No input file name")?;
+    let audio = dir.join(make_transcoded_file_name(
+        &name.to_string_lossy(),
+        TranscodeFormat::Flac,
+    ));
+    transcode_range(input, &audio, TranscodeFormat::Flac, None).await?;
+    Ok(audio)
+}
+
+/// The command running the transcriber of the image on the audio.
+fn transcriber_command(
+    env: &WorkerEnv,
+    audio: &Path,
+    out_dir: &Path,
+) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new(TRANSCRIBER);
+    command
+        .arg("--output-dir")
+        .arg(out_dir)
+        .args(["--models-data-dir", MODELS_DIR])
+        .args(["--model", &format!("whisper-{}", env.whisper_model)])
+        .args(["--language", &env.language]);
+    if let Some(prompt) = &env.initial_prompt {
+        command.args(["--initial-prompt", prompt]);
+    }
+    command.arg("--").arg(audio);
+    command
+}
+
+#[test]
+fn transcriber_command_test() {
+    let vars = [
+        ("TRK_JOB_UID", "jid"),
+        ("TRK_JOB_PREFIX", "jid/"),
+        ("TRK_LANGUAGE", "en"),
+        ("TRK_INITIAL_PROMPT", "Trakktor, AWS Batch"),
+        ("WHISPER_MODEL", "large-v3-turbo"),
+    ]
+    .map(|(key, value)| (key.to_string(), value.to_string()));
+    let env = WorkerEnv::from_vars(vars).unwrap();
+    let command = transcriber_command(
+        &env,
+        Path::new("/task/audio/a.flac"),
+        Path::new("/task/out"),
+    );
+    let command = command.as_std();
+    assert_eq!(command.get_program(), TRANSCRIBER);
+    assert_eq!(
+        command.get_args().collect::<Vec<_>>(),
+        [
+            "--output-dir",
+            "/task/out",
+            "--models-data-dir",
+            MODELS_DIR,
+            "--model",
+            "whisper-large-v3-turbo",
+            "--language",
+            "en",
+            "--initial-prompt",
+            "Trakktor, AWS Batch",
+            "--",
+            "/task/audio/a.flac",
+        ]
+    );
+}
+
+async fn run_transcriber(
+    env: &WorkerEnv,
+    audio: &Path,
+    out_dir: &Path,
+    segments: &AtomicU64,
+    stderr_tail: &Arc<std::sync::Mutex<VecDeque<String>>>,
+) -> anyhow::Result<()> {
+    tokio::fs::create_dir_all(out_dir).await?;
+    let mut child = transcriber_command(env, audio, out_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run the transcriber")?;
+    let stderr = child
+        .stderr
+        .take()
+        .context("No error output of the transcriber")?;
+    let stderr_task = tokio::spawn({
+        let stderr_tail = Arc::clone(stderr_tail);
+        async move {
+            let mut lines = tokio::io::BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                eprintln!("{line}");
+                let mut tail = stderr_tail.lock().unwrap();
+                if tail.len() == STDERR_TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
+            }
+        }
+    });
+    let stdout = child
+        .stdout
+        .take()
+        .context("No output of the transcriber")?;
+    let mut lines = tokio::io::BufReader::new(stdout).lines();
+    while let Some(line) = lines.next_line().await? {
+        if is_segment_line(&line) {
+            segments.fetch_add(1, Ordering::Relaxed);
+        }
+        println!("{line}");
+    }
+    let status = child.wait().await?;
+    stderr_task.await?;
+    if !status.success() {
+        bail!("The transcriber failed: {status}");
+    }
+    Ok(())
+}
+
+/// Whether the line is a segment printed by the transcriber, like
+/// `[00:00.000 --> 00:05.000]  Hello.`
+fn is_segment_line(line: &str) -> bool {
+    line.strip_prefix('[')
+        .and_then(|line| line.split_once(']'))
+        .is_some_and(|(times, _)| times.contains(" --> "))
+}
+
+#[test]
+fn is_segment_line_test() {
+    assert!(is_segment_line("[00:00.000 --> 00:05.000]  Hello."));
+    assert!(is_segment_line("[01:02:03.000 --> 01:02:05.000] "));
+    assert!(!is_segment_line("Detected language: English"));
+    assert!(!is_segment_line("[warning] --> later"));
+}
+
+async fn report_failure(
+    env: &WorkerEnv,
+    storage: &Storage,
+    failure: Failure,
+    input_file: Option<String>,
+) -> anyhow::Result<()> {
+    let report = JobErrorReport {
+        stage: failure.stage,
+        message: format!("{:#}", failure.error),
+        input_file,
+        stderr_tail: failure.stderr_tail,
+    };
+    let name = match env.array_index()? {
+        Some(index) => make_array_error_report(index),
+        None => JOB_ERROR_REPORT.into(),
+    };
+    storage
+        .put_plain(
+            &serde_json::to_vec_pretty(&report)?,
+            &format!("{}{name}", env.job_prefix),
+        )
+        .await
+}
+
+#[tokio::test]
+async fn run_failure_test() {
+    let dir = std::env::temp_dir()
+        .join(format!("trakktor-worker-{}", uuid::Uuid::new_v4()));
+    let storage = Storage::Local(dir.join("storage"));
+    let vars = [
+        ("TRK_JOB_UID", "jid"),
+        ("TRK_JOB_PREFIX", "jid/"),
+        ("TRK_INPUT_FILE", "a.mp3"),
+        ("TRK_LANGUAGE", "en"),
+        ("WHISPER_MODEL", "large-v3"),
+    ]
+    .map(|(key, value)| (key.to_string(), value.to_string()));
+    let env = WorkerEnv::from_vars(vars).unwrap();
+
+    let mut input_file = env.input_file.clone();
+    let failure = run(&env, &storage, &mut input_file, &dir.join("work"))
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(failure.stage, JobStage::Download);
+    report_failure(&env, &storage, failure, input_file)
+        .await
+        .unwrap();
+
+    let report: JobErrorReport = serde_json::from_slice(
+        &std::fs::read(dir.join("storage/jid").join(JOB_ERROR_REPORT)).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(report.stage, JobStage::Download);
+    assert_eq!(report.input_file.as_deref(), Some("a.mp3"));
+    assert!(report.stderr_tail.is_empty());
+    assert!(!dir.join("storage/jid/done.🚜-flag").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,104 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use anyhow::bail;
+use trakktor::aws_batch::whisper::OutputFormat;
+
+/// The extensions of the files Whisper writes in the formats of the list,
+/// or `None` if the list is not set.
+fn list_extensions(list: Option<&str>) -> anyhow::Result<Option<Vec<String>>> {
+    let Some(list) = list else {
+        return Ok(None);
+    };
+    Ok(Some(
+        OutputFormat::split(list)?
+            .iter()
+            .map(|format| format.get_extension().to_string())
+            .collect(),
+    ))
+}
+
+fn has_extension(file: &Path, exts: &[String]) -> bool {
+    file.extension()
+        .is_some_and(|ext| exts.iter().any(|e| ext == e.as_str()))
+}
+
+/// Keeps the outputs of the requested formats, all of them if none are
+/// requested, and compresses the ones of the compressed formats with zstd.
+/// Returns the files to upload.
+#[tracing::instrument(level = "debug")]
+pub async fn prepare_outputs(
+    dir: &Path,
+    output_formats: Option<&str>,
+    compress_formats: Option<&str>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let keep = list_extensions(output_formats)?;
+    let compress = list_extensions(compress_formats)?.unwrap_or_default();
+
+    let mut files = vec![];
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+
+    let mut outputs = vec![];
+    for file in files {
+        if keep
+            .as_ref()
+            .is_some_and(|keep| !has_extension(&file, keep))
+        {
+            tokio::fs::remove_file(&file).await?;
+        } else if has_extension(&file, &compress) {
+            outputs.push(compress_file(&file).await?);
+        } else {
+            outputs.push(file);
+        }
+    }
+
+    if outputs.is_empty() {
+        bail!("No output generated");
+    }
+    Ok(outputs)
+}
+
+/// Compresses the file with the `zstd` tool, replacing it with the file with
+/// the `.zst` extension.
+async fn compress_file(file: &Path) -> anyhow::Result<PathBuf> {
+    let status = tokio::process::Command::new("zstd")
+        .args(["-q", "--rm", "-19", "--"])
+        .arg(file)
+        .stdin(Stdio::null())
+        .status()
+        .await?;
+    if !status.success() {
+        bail!("zstd failed on {}: {status}", file.display());
+    }
+    let mut compressed = file.as_os_str().to_owned();
+    compressed.push(trakktor::aws_batch::compression::ZSTD_EXTENSION);
+    Ok(compressed.into())
+}
+
+#[tokio::test]
+async fn prepare_outputs_test() {
+    let dir = std::env::temp_dir()
+        .join(format!("trakktor-worker-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for ext in ["txt", "srt", "vtt", "tsv", "json"] {
+        std::fs::write(dir.join(format!("a.{ext}")), ext).unwrap();
+    }
+
+    let outputs = prepare_outputs(&dir, Some("txt,timestamped"), None)
+        .await
+        .unwrap();
+    assert_eq!(outputs, [dir.join("a.tsv"), dir.join("a.txt")]);
+    assert!(!dir.join("a.json").exists());
+
+    assert!(prepare_outputs(&dir, Some("srt"), None).await.is_err());
+    assert!(prepare_outputs(&dir, Some("docx"), None).await.is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,134 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use trakktor::aws_batch::{
+    config::{AwsConfigProvider, S3Provider},
+    encryption::EncryptionKey,
+    s3,
+};
+
+/// Where the job data is kept: the S3 bucket of the stack, or the directory
+/// mounted by the local Docker backend, unencrypted.
+pub enum Storage {
+    S3(Box<S3Storage>),
+    Local(PathBuf),
+}
+
+pub struct S3Storage {
+    pub aws_config: aws_config::SdkConfig,
+    pub bucket: String,
+    pub encryption_key: Option<EncryptionKey>,
+}
+
+impl AwsConfigProvider for S3Storage {
+    fn get_aws_config(&self) -> &aws_config::SdkConfig { &self.aws_config }
+}
+
+impl S3Provider for S3Storage {
+    fn get_bucket_name(&self) -> &str { &self.bucket }
+
+    fn get_encryption_key(&self) -> Option<&EncryptionKey> {
+        self.encryption_key.as_ref()
+    }
+}
+
+impl Storage {
+    /// Downloads the object `{prefix}{name}` to `dir/name`, decrypting it if
+    /// it was encrypted.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn download(
+        &self,
+        prefix: &str,
+        name: &str,
+        dir: &Path,
+    ) -> anyhow::Result<PathBuf> {
+        let dest = dir.join(name);
+        match self {
+            Storage::S3(storage) => {
+                s3::download_folder(
+                    storage.as_ref(),
+                    [format!("{prefix}{name}")],
+                    prefix,
+                    dir,
+                )
+                .await?;
+            },
+            Storage::Local(root) => {
+                if let Some(parent) = dest.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                let src = root.join(format!("{prefix}{name}"));
+                tokio::fs::copy(&src, &dest).await.with_context(|| {
+                    format!("Failed to copy {}", src.display())
+                })?;
+            },
+        }
+        Ok(dest)
+    }
+
+    /// Uploads the file to the object, encrypting it if the encryption key
+    /// is set.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn upload(&self, file: &Path, key: &str) -> anyhow::Result<()> {
+        match self {
+            Storage::S3(storage) => {
+                // Multipart uploads can't be empty.
+                if tokio::fs::metadata(file).await?.len() == 0 {
+                    s3::put_object(storage.as_ref(), &[], key).await
+                } else {
+                    s3::upload_file(storage.as_ref(), file, key).await
+                }
+            },
+            Storage::Local(root) => {
+                let dest = root.join(key);
+                if let Some(parent) = dest.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::copy(file, &dest).await?;
+                Ok(())
+            },
+        }
+    }
+
+    /// Writes the object unencrypted, for the flags and the reports that are
+    /// read without the encryption key.
+    #[tracing::instrument(level = "debug", skip(self, data))]
+    pub async fn put_plain(
+        &self,
+        data: &[u8],
+        key: &str,
+    ) -> anyhow::Result<()> {
+        match self {
+            Storage::S3(storage) => {
+                s3::put_plain_object(storage.as_ref(), data, key).await
+            },
+            Storage::Local(root) => {
+                let dest = root.join(key);
+                if let Some(parent) = dest.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::write(&dest, data).await?;
+                Ok(())
+            },
+        }
+    }
+}
+
+#[tokio::test]
+async fn local_storage_test() {
+    let dir = std::env::temp_dir()
+        .join(format!("trakktor-worker-{}", uuid::Uuid::new_v4()));
+    let storage = Storage::Local(dir.join("storage"));
+    storage.put_plain(b"audio", "jid/in/a b.mp3").await.unwrap();
+
+    let work = dir.join("work");
+    let file = storage.download("jid/in/", "a b.mp3", &work).await.unwrap();
+    assert_eq!(file, work.join("a b.mp3"));
+    storage.upload(&file, "jid/out/a b.txt").await.unwrap();
+    assert_eq!(
+        std::fs::read(dir.join("storage/jid/out/a b.txt")).unwrap(),
+        b"audio"
+    );
+    assert!(storage.download("jid/in/", "missing", &work).await.is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -3,10 +3,12 @@ use std::{
     sync::Arc,
 };
 
+use anyhow::Context;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Semaphore;
+use tokio::{sync::Semaphore, task::spawn_blocking};
 
-use crate::llm::{ChatCompletionPlatform, Role};
+use crate::llm::{ChatCompletionPlatform, ReasoningEffort, Role};
 
 pub struct ChatDoc {
     pub toml_doc: toml_edit::DocumentMut,
@@ -30,20 +32,126 @@ pub struct Cfg {
     pub response_format: Option<String>,
     #[serde(default)]
     pub beautify_json_response: bool,
+    /// How long the reasoning models think before answering.
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// The limit of the generated tokens, including the hidden reasoning
+    /// tokens.
+    pub max_completion_tokens: Option<u32>,
+    /// Summarize the older turns of the conversation once the messages
+    /// are estimated to take more tokens than this.
+    pub compact_above_tokens: Option<u64>,
+    /// The latest messages kept as they are by the compaction, 4 by
+    /// default.
+    pub compact_keep_msgs: Option<usize>,
+    /// Write the model, the tokens, the latency and the cost of each reply
+    /// to the `usage` table of its message. The table is ignored when the
+    /// document is read.
+    #[serde(default)]
+    pub annotate_usage: bool,
 }
 
+/// The largest included file by default, the larger ones are skipped.
+const DEFAULT_MAX_INCLUDE_SIZE: u64 = 1024 * 1024;
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(untagged)]
 pub enum Msg {
-    Text { role: Role, content: String },
-    Include { include: String },
+    Text {
+        role: Role,
+        content: String,
+    },
+    /// Includes the messages of other chat documents, or the contents of
+    /// other files as messages of the role, one per file. The path is
+    /// relative to the document, and is either a file, a directory included
+    /// recursively, or a glob pattern like `notes/*.md` or `notes/**/*.md`.
+    /// The `.toml` files are chat documents unless the role is given, the
+    /// other files are messages of the user by default.
+    Include {
+        include: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+        /// The largest included file in bytes, the larger ones are skipped
+        /// with a warning. 1 MiB by default.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_file_size: Option<u64>,
+    },
+    /// Includes the contents of the files as messages of the role, one per
+    /// file, whatever their type, e.g. a report, code or a `.toml` file
+    /// that is not a chat document. The path is as of the includes.
+    IncludeRaw {
+        include_raw: String,
+        /// The user by default.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+        #[serde(flatten)]
+        format: RawFormat,
+    },
 }
 
-const MAX_CONCURRENT_READS: usize = 64;
+/// How the contents of a raw include are put into a message.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct RawFormat {
+    /// Wrap the contents in a Markdown code block, in the language of the
+    /// extension of the file.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fence: bool,
+    /// Keep at most this many first lines.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_lines: Option<usize>,
+    /// Keep at most this many first characters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_chars: Option<usize>,
+}
+
+impl RawFormat {
+    /// The contents truncated to the limits and fenced if asked, with a
+    /// note of what was cut.
+    fn apply(&self, contents: &str, file: &Path) -> String {
+        let mut text = contents.trim_end().to_string();
+        let mut cut_lines = 0;
+        if let Some(max_lines) = self.max_lines {
+            let lines = text.lines().count();
+            if lines > max_lines {
+                cut_lines = lines - max_lines;
+                text = text.lines().take(max_lines).join("\n");
+            }
+        }
+        let mut cut_chars = 0;
+        if let Some(max_chars) = self.max_chars {
+            let chars = text.chars().count();
+            if chars > max_chars {
+                cut_chars = chars - max_chars;
+                text = text.chars().take(max_chars).collect();
+            }
+        }
+        if self.fence {
+            // Longer than any run of backticks of the contents.
+            let mut fence = "```".to_string();
+            while text.contains(fence.as_str()) {
+                fence.push('`');
+            }
+            let lang = file
+                .extension()
+                .map(|ext| ext.to_string_lossy())
+                .unwrap_or_default();
+            text = format!("{fence}{lang}\n{text}\n{fence}");
+        }
+        if cut_lines > 0 {
+            text.push_str(&format!("\n\n[{cut_lines} more lines truncated]"));
+        } else if cut_chars > 0 {
+            text.push_str(&format!(
+                "\n\n[{cut_chars} more characters truncated]"
+            ));
+        }
+        text
+    }
+}
 
 impl ChatDoc {
+    /// Loads the chat document with its includes, reading at most
+    /// `max_reads` files at once.
     #[tracing::instrument(level = "debug")]
-    pub async fn load(file: &Path) -> anyhow::Result<Self> {
+    pub async fn load(file: &Path, max_reads: usize) -> anyhow::Result<Self> {
         let contents = tokio::fs::read_to_string(file).await?;
 
         let toml_doc = contents.parse::<toml_edit::DocumentMut>()?;
@@ -53,7 +161,7 @@ impl ChatDoc {
         let msgs = handle_msgs(
             file.to_path_buf(),
             original_chat_data.msgs.clone(),
-            Arc::new(Semaphore::new(MAX_CONCURRENT_READS)),
+            Arc::new(Semaphore::new(max_reads)),
         )
         .await?;
 
@@ -64,6 +172,78 @@ impl ChatDoc {
         })
     }
 
+    /// The range of the messages of the main file the compaction may
+    /// summarize: the turns after the last include, but the last `keep`.
+    pub fn compactable_msgs(&self, keep: usize) -> std::ops::Range<usize> {
+        let msgs = &self.original_chat_data.msgs;
+        let start = msgs
+            .iter()
+            .rposition(|msg| !matches!(msg, Msg::Text { .. }))
+            .map_or(0, |i| i + 1);
+        start..msgs.len().saturating_sub(keep).max(start)
+    }
+
+    /// Replaces the messages of the main file in the range with the summary,
+    /// and moves them to the `archive` table of the document.
+    pub fn compact(
+        &mut self,
+        range: std::ops::Range<usize>,
+        summary: Msg,
+    ) -> anyhow::Result<()> {
+        // The messages after the last include are the same in the main file
+        // and in the messages with the includes.
+        let tail = self.original_chat_data.msgs.len() - range.start;
+        let start = self.msgs.len() - tail;
+        self.msgs
+            .splice(start..start + range.len(), [summary.clone()]);
+        self.original_chat_data
+            .msgs
+            .splice(range.clone(), [summary.clone()]);
+
+        let summary = serde::Serialize::serialize(
+            &summary,
+            toml_edit::ser::ValueSerializer::new(),
+        )?
+        .as_inline_table()
+        .context("The summary is not a table")?
+        .clone()
+        .into_table();
+        let msgs = self.toml_doc["msgs"]
+            .as_array_of_tables_mut()
+            .context("No messages in the document")?;
+        let mut tables = msgs.iter().cloned().collect::<Vec<_>>();
+        let archived =
+            tables.splice(range.clone(), [summary]).collect::<Vec<_>>();
+        let archive = self.toml_doc["archive"]
+            .or_insert(toml_edit::Item::ArrayOfTables(Default::default()))
+            .as_array_of_tables_mut()
+            .context("The archive is not an array of tables")?;
+        let mut archive_tables = archive.iter().cloned().collect::<Vec<_>>();
+        archive_tables.extend(archived);
+
+        // The tables are written in the order of their positions: the
+        // messages first, then the archive.
+        let base = self.toml_doc["cfg"]
+            .as_array_of_tables()
+            .and_then(|cfg| cfg.iter().filter_map(|t| t.position()).max())
+            .unwrap_or(0);
+        let mut position = base;
+        let mut renumbered = |tables: Vec<toml_edit::Table>| {
+            let mut res = toml_edit::ArrayOfTables::new();
+            for mut table in tables {
+                position += 1;
+                table.set_position(position);
+                res.push(table);
+            }
+            res
+        };
+        self.toml_doc["msgs"] =
+            toml_edit::Item::ArrayOfTables(renumbered(tables));
+        self.toml_doc["archive"] =
+            toml_edit::Item::ArrayOfTables(renumbered(archive_tables));
+        Ok(())
+    }
+
     #[tracing::instrument(level = "debug", skip(self))]
     pub async fn write_doc(&self, file: &Path) -> anyhow::Result<()> {
         let toml_str = self.toml_doc.to_string();
@@ -86,14 +266,39 @@ async fn handle_msgs(
 
     for msg in msgs {
         match msg {
-            Msg::Include { include } => {
+            Msg::Include {
+                include,
+                role,
+                max_file_size,
+            } => {
                 let permits = Arc::clone(&permits);
-                let include_path = file
+                let dir = file
                     .parent()
                     .expect("file has no parent path")
-                    .join(include);
+                    .to_path_buf();
                 tasks.push(Elem::Include(tokio::spawn(proc_include(
-                    include_path,
+                    dir,
+                    include,
+                    role,
+                    max_file_size.unwrap_or(DEFAULT_MAX_INCLUDE_SIZE),
+                    permits,
+                ))));
+            },
+            Msg::IncludeRaw {
+                include_raw,
+                role,
+                format,
+            } => {
+                let permits = Arc::clone(&permits);
+                let dir = file
+                    .parent()
+                    .expect("file has no parent path")
+                    .to_path_buf();
+                tasks.push(Elem::Include(tokio::spawn(proc_include_raw(
+                    dir,
+                    include_raw,
+                    role.unwrap_or(Role::User),
+                    format,
                     permits,
                 ))));
             },
@@ -124,8 +329,90 @@ async fn read_file(
     Ok(tokio::fs::read_to_string(file).await?)
 }
 
-#[async_recursion::async_recursion]
 async fn proc_include(
+    dir: PathBuf,
+    include: String,
+    role: Option<Role>,
+    max_file_size: u64,
+    permits: Arc<Semaphore>,
+) -> anyhow::Result<Vec<Msg>> {
+    let (files, is_single) = {
+        let (dir, include) = (dir.clone(), include.clone());
+        spawn_blocking(move || expand_include(&dir, &include)).await??
+    };
+
+    let mut msgs = vec![];
+    for file in files {
+        let size = tokio::fs::metadata(&file).await?.len();
+        if size > max_file_size {
+            tracing::warn!(
+                "Skipping the included file {} of {size} bytes, larger than \
+                 {max_file_size} bytes.",
+                file.display()
+            );
+            continue;
+        }
+        let is_chat_doc = file.extension().is_some_and(|ext| ext == "toml");
+        match role {
+            None if is_chat_doc => {
+                msgs.append(&mut proc_chat_doc(file, permits.clone()).await?);
+            },
+            role => {
+                let contents = read_file(&file, &permits).await?;
+                msgs.push(file_msg(
+                    &dir,
+                    &file,
+                    contents,
+                    is_single,
+                    role.unwrap_or(Role::User),
+                ));
+            },
+        }
+    }
+    Ok(msgs)
+}
+
+async fn proc_include_raw(
+    dir: PathBuf,
+    include: String,
+    role: Role,
+    format: RawFormat,
+    permits: Arc<Semaphore>,
+) -> anyhow::Result<Vec<Msg>> {
+    let (files, is_single) = {
+        let (dir, include) = (dir.clone(), include.clone());
+        spawn_blocking(move || expand_include(&dir, &include)).await??
+    };
+
+    let mut msgs = vec![];
+    for file in files {
+        let contents = read_file(&file, &permits).await?;
+        let contents = format.apply(&contents, &file);
+        msgs.push(file_msg(&dir, &file, contents, is_single, role));
+    }
+    Ok(msgs)
+}
+
+/// The message of an included file, headed by its path if the include has
+/// several files.
+fn file_msg(
+    dir: &Path,
+    file: &Path,
+    contents: String,
+    is_single: bool,
+    role: Role,
+) -> Msg {
+    let content = if is_single {
+        contents
+    } else {
+        let name = file.strip_prefix(dir).unwrap_or(file);
+        format!("File `{}`:\n\n{contents}", name.display())
+    };
+    Msg::Text { role, content }
+}
+
+#[async_recursion::async_recursion]
+async fn proc_chat_doc(
     include_path: PathBuf,
     permits: Arc<Semaphore>,
 ) -> anyhow::Result<Vec<Msg>> {
@@ -135,6 +422,111 @@ async fn proc_include(
     Ok(handle_msgs(include_path, chat_data.msgs, permits).await?)
 }
 
+/// The files of the include, sorted by path, and whether it names a single
+/// file rather than a directory or a glob pattern. The hidden files are
+/// left out of the directories and the patterns.
+fn expand_include(
+    dir: &Path,
+    include: &str,
+) -> anyhow::Result<(Vec<PathBuf>, bool)> {
+    let path = dir.join(include);
+    let is_pattern = include.contains(['*', '?']);
+    if !is_pattern && !path.is_dir() {
+        return Ok((vec![path], true));
+    }
+
+    let mut files = vec![];
+    if is_pattern {
+        // The segments before the first wildcard are the directory to
+        // search.
+        let segments = include.split('/').collect::<Vec<_>>();
+        let literal = segments
+            .iter()
+            .take_while(|s| !s.contains(['*', '?']))
+            .count();
+        let base = dir.join(segments[..literal].join("/"));
+        glob_files(&base, &segments[literal..], &mut files);
+    } else {
+        glob_files(&path, &["**"], &mut files);
+    }
+    files.sort();
+    files.dedup();
+    if files.is_empty() {
+        anyhow::bail!("No files to include in {}", path.display());
+    }
+    Ok((files, false))
+}
+
+/// Adds the files under the path matching the segments of a glob pattern,
+/// where `**` matches any number of directories.
+fn glob_files(path: &Path, segments: &[&str], files: &mut Vec<PathBuf>) {
+    let Some((segment, rest)) = segments.split_first() else {
+        if path.is_file() {
+            files.push(path.to_path_buf());
+        }
+        return;
+    };
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return;
+    };
+    let entries = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    if *segment == "**" {
+        glob_files(path, rest, files);
+        for entry in entries {
+            if entry.is_dir() {
+                glob_files(&entry, segments, files);
+            } else if rest.is_empty() {
+                files.push(entry);
+            }
+        }
+        return;
+    }
+    for entry in entries {
+        let name = entry.file_name().unwrap_or_default().to_string_lossy();
+        if wildcard_match(segment, &name) {
+            glob_files(&entry, rest, files);
+        }
+    }
+}
+
+/// Whether the name matches the pattern, where `*` matches any characters
+/// and `?` matches one.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (
+        pattern.chars().collect::<Vec<_>>(),
+        name.chars().collect::<Vec<_>>(),
+    );
+    let (mut p, mut n) = (0, 0);
+    // The position after the last `*` and the name position it matched up
+    // to, to backtrack to.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 impl Msg {
     pub fn is_assistant(&self) -> bool {
         matches!(
@@ -146,3 +538,167 @@ impl Msg {
         )
     }
 }
+
+#[test]
+fn wildcard_match_test() {
+    assert!(wildcard_match("*.md", "notes.md"));
+    assert!(wildcard_match("*.md", ".md"));
+    assert!(!wildcard_match("*.md", "notes.mdx"));
+    assert!(wildcard_match("day-??.txt", "day-01.txt"));
+    assert!(!wildcard_match("day-??.txt", "day-1.txt"));
+    assert!(wildcard_match("a*b*c", "aXbYbZc"));
+    assert!(wildcard_match("*", ""));
+}
+
+#[tokio::test]
+async fn include_glob_test() {
+    let dir = std::env::temp_dir()
+        .join(format!("trakktor-chat-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(dir.join("notes/old")).unwrap();
+    std::fs::write(dir.join("notes/b.md"), "B").unwrap();
+    std::fs::write(dir.join("notes/a.md"), "A").unwrap();
+    std::fs::write(dir.join("notes/big.md"), "Too long").unwrap();
+    std::fs::write(dir.join("notes/.hidden.md"), "Hidden").unwrap();
+    std::fs::write(dir.join("notes/old/c.md"), "C").unwrap();
+    std::fs::write(dir.join("notes/old/c.txt"), "Not markdown").unwrap();
+    std::fs::write(
+        dir.join("chat.toml"),
+        r#"
+[[msgs]]
+include = "notes/*.md"
+max_file_size = 4
+
+[[msgs]]
+include = "notes/**/c.md"
+role = "assistant"
+
+[[msgs]]
+role = "user"
+content = "Summarize."
+"#,
+    )
+    .unwrap();
+
+    let doc = ChatDoc::load(&dir.join("chat.toml"), 2).await.unwrap();
+    let msgs = doc
+        .msgs
+        .iter()
+        .map(|msg| match msg {
+            Msg::Text { role, content } => {
+                (format!("{role:?}"), content.as_str())
+            },
+            _ => unreachable!(),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        msgs,
+        [
+            ("User".to_string(), "File `notes/a.md`:\n\nA"),
+            ("User".to_string(), "File `notes/b.md`:\n\nB"),
+            ("Assistant".to_string(), "File `notes/old/c.md`:\n\nC"),
+            ("User".to_string(), "Summarize."),
+        ]
+    );
+    assert_eq!(
+        expand_include(&dir, "notes").unwrap().0.len(),
+        5,
+        "All the files of the directory, recursively."
+    );
+    assert!(expand_include(&dir, "notes/*.pdf").is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn raw_format_test() {
+    let file = Path::new("main.rs");
+    let code = "fn main() {\n    println!(\"```\");\n}\n";
+    assert_eq!(RawFormat::default().apply(code, file), code.trim_end());
+    let format = RawFormat {
+        fence: true,
+        max_lines: Some(2),
+        max_chars: None,
+    };
+    assert_eq!(
+        format.apply(code, file),
+        "````rs\nfn main() {\n    println!(\"```\");\n````\n\n[1 more lines \
+         truncated]"
+    );
+    let format = RawFormat {
+        max_chars: Some(4),
+        ..Default::default()
+    };
+    assert_eq!(
+        format.apply("Привет, мир", file),
+        "Прив\n\n[7 more characters truncated]"
+    );
+
+    let msg: Msg = toml_edit::de::from_str(
+        "include_raw = \"report.toml\"\nrole = \"system\"\nfence = true",
+    )
+    .unwrap();
+    assert!(matches!(
+        msg,
+        Msg::IncludeRaw {
+            role: Some(Role::System),
+            format: RawFormat { fence: true, .. },
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn compact_test() {
+    let dir = std::env::temp_dir()
+        .join(format!("trakktor-chat-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("notes.md"), "Notes").unwrap();
+    let mut toml = r#"[[cfg]]
+model = "gpt-4o"
+
+[[msgs]]
+include = "notes.md"
+"#
+    .to_string();
+    for i in 1..=5 {
+        let role = if i % 2 == 1 { "user" } else { "assistant" };
+        toml.push_str(&format!(
+            "\n[[msgs]]\nrole = \"{role}\"\ncontent = \"Turn {i}\"\n"
+        ));
+    }
+    std::fs::write(dir.join("chat.toml"), toml).unwrap();
+
+    let mut doc = ChatDoc::load(&dir.join("chat.toml"), 1).await.unwrap();
+    let range = doc.compactable_msgs(2);
+    assert_eq!(range, 1..4);
+    doc.compact(
+        range,
+        Msg::Text {
+            role: Role::System,
+            content: "Summary".into(),
+        },
+    )
+    .unwrap();
+    let contents = |msgs: &[Msg]| {
+        msgs.iter()
+            .map(|msg| match msg {
+                Msg::Text { content, .. } => content.clone(),
+                _ => "include".into(),
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        contents(&doc.msgs),
+        ["Notes", "Summary", "Turn 4", "Turn 5"]
+    );
+
+    let toml = doc.toml_doc.to_string();
+    assert!(toml.find("Turn 5").unwrap() < toml.find("Turn 1").unwrap());
+    let data: ChatData = toml_edit::de::from_str(&toml).unwrap();
+    assert_eq!(data.cfg[0].model.as_deref(), Some("gpt-4o"));
+    assert_eq!(
+        contents(&data.msgs),
+        ["include", "Summary", "Turn 4", "Turn 5"]
+    );
+    assert_eq!(toml.matches("[[archive]]").count(), 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
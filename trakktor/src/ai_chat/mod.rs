@@ -1,16 +1,34 @@
 use std::sync::Arc;
 
 use anyhow::Context;
-use chat_doc::{ChatDoc, Msg};
+use chat_doc::{Cfg, ChatDoc, Msg};
 use clap::Parser;
+use itertools::Itertools;
 
 use crate::{
-    llm::{ChatCompletionPlatform, ChatCompletionsArgs, Message},
+    app_config::Limits,
+    azure_open_ai::AzureOpenAiAPI,
+    cancellation::{cancellable, CancellationToken},
+    dry_run::{estimate_cost, DryRun},
+    gemini::GeminiAPI,
+    llm::{
+        ChatCompletionAPI, ChatCompletionPlatform, ChatCompletionsArgs,
+        ChatResponse, Message, Role,
+    },
     open_ai::OpenAiAPI,
+    rate_limit::estimate_tokens,
 };
 
 pub mod chat_doc;
 
+/// The latest messages kept by the compaction of the history by default.
+const DEFAULT_COMPACT_KEEP_MSGS: usize = 4;
+const COMPACT_PROMPT: &str =
+    "Summarize the conversation below so that it can be continued from the \
+     summary alone. Keep the facts, the decisions, the open questions and the \
+     preferences of the user. Answer with the summary only.";
+const COMPACTED_PREFIX: &str = "The summary of the earlier conversation:";
+
 #[derive(Parser, Debug)]
 pub struct AIChat {
     /// Specify the file containing the chat messages to be processed.
@@ -23,10 +41,17 @@ pub struct AIChat {
     /// (assistant).
     #[arg(long, short, default_value_t = false)]
     pub overwrite_last_response: bool,
+    /// Print the request to the model instead of sending it, with its
+    /// estimated tokens and cost. The file is not changed.
+    #[arg(long)]
+    pub dry_run: bool,
 }
 
 pub struct AllChatProviders {
     pub open_ai: OpenAiAPI,
+    /// Only available when the endpoint of the Azure resource is set.
+    pub azure_open_ai: Option<AzureOpenAiAPI>,
+    pub gemini: GeminiAPI,
 }
 
 pub async fn run_ai_chat(
@@ -34,8 +59,29 @@ pub async fn run_ai_chat(
     chat_platform: &Option<ChatCompletionPlatform>,
     chat_model: &Option<Arc<str>>,
     all_providers: &AllChatProviders,
+    limits: Limits,
+    cancel: &CancellationToken,
+) -> crate::Result<()> {
+    Ok(chat(
+        ai_chat,
+        chat_platform,
+        chat_model,
+        all_providers,
+        limits,
+        cancel,
+    )
+    .await?)
+}
+
+async fn chat(
+    ai_chat: &AIChat,
+    chat_platform: &Option<ChatCompletionPlatform>,
+    chat_model: &Option<Arc<str>>,
+    all_providers: &AllChatProviders,
+    limits: Limits,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<()> {
-    let mut doc = ChatDoc::load(&ai_chat.file).await?;
+    let mut doc = ChatDoc::load(&ai_chat.file, limits.file_reads).await?;
 
     if ai_chat.overwrite_last_response {
         // The last message is deleted only if it was in the main file, i.e., it
@@ -44,6 +90,7 @@ pub async fn run_ai_chat(
             doc.original_chat_data.msgs.last().map(Msg::is_assistant)
         {
             doc.msgs.pop();
+            doc.original_chat_data.msgs.pop();
             let msgs = doc.toml_doc["msgs"].as_array_of_tables_mut().unwrap();
             msgs.remove(msgs.len() - 1);
         }
@@ -83,6 +130,59 @@ pub async fn run_ai_chat(
 
     tracing::debug!("Using configuration: {config:#?}");
 
+    let api: &(dyn ChatCompletionAPI + Sync) = match config
+        .platform
+        .ok_or_else(|| anyhow::anyhow!("Chat Platform not specified"))?
+    {
+        ChatCompletionPlatform::OpenAI => &all_providers.open_ai,
+        ChatCompletionPlatform::AzureOpenAI => {
+            all_providers.azure_open_ai.as_ref().ok_or_else(|| {
+                anyhow::anyhow!("The Azure OpenAI endpoint is not set")
+            })?
+        },
+        ChatCompletionPlatform::Gemini => &all_providers.gemini,
+    };
+
+    if let Some(max_tokens) = config.compact_above_tokens {
+        let len = doc
+            .msgs
+            .iter()
+            .map(|msg| match msg {
+                Msg::Text { content, .. } => content.len(),
+                _ => 0,
+            })
+            .sum();
+        if estimate_tokens(len) > max_tokens as f64 {
+            let keep = config
+                .compact_keep_msgs
+                .unwrap_or(DEFAULT_COMPACT_KEEP_MSGS);
+            let range = doc.compactable_msgs(keep);
+            if range.len() < 2 {
+                tracing::warn!(
+                    "The history is longer than {max_tokens} tokens, but has \
+                     too few messages to compact"
+                );
+            } else if ai_chat.dry_run {
+                tracing::info!(
+                    "The {} older messages would be compacted",
+                    range.len()
+                );
+            } else {
+                tracing::info!("Compacting the {} older messages", range.len());
+                let summary = summarize_history(
+                    api,
+                    &config,
+                    &doc.original_chat_data.msgs[range.clone()],
+                    cancel,
+                )
+                .await?;
+                doc.compact(range, summary)?;
+                // Kept even if the response fails.
+                doc.write_doc(&ai_chat.file).await?;
+            }
+        }
+    }
+
     let messages = doc
         .msgs
         .iter()
@@ -91,7 +191,7 @@ pub async fn run_ai_chat(
                 role: *role,
                 content: content.into(),
             }),
-            Msg::Include { .. } => {
+            Msg::Include { .. } | Msg::IncludeRaw { .. } => {
                 Err(anyhow::anyhow!("Unexpected include message"))
             },
         })
@@ -109,16 +209,31 @@ pub async fn run_ai_chat(
         .maybe_model_overwrite(config.model.as_deref())
         .messages(&messages)
         .maybe_response_format(response_format.as_ref())
+        .maybe_reasoning_effort(config.reasoning_effort)
+        .maybe_max_completion_tokens(config.max_completion_tokens)
         .build();
 
-    let chat_msg = match config
-        .platform
-        .ok_or_else(|| anyhow::anyhow!("Chat Platform not specified"))?
-    {
-        ChatCompletionPlatform::OpenAI => {
-            chat.run_with(&all_providers.open_ai).await?
-        },
-    };
+    if ai_chat.dry_run {
+        let dry_run = DryRun::new();
+        dry_run.print_request(
+            chat.model_overwrite.or(api.model_name(None)),
+            &chat,
+        );
+        dry_run.print_summary();
+        return Ok(());
+    }
+
+    // The document is written only with the whole response.
+    let response = cancellable(cancel, api.run_chat_full(chat)).await?;
+    if response.is_truncated() {
+        tracing::warn!("The response was cut at the limit of the tokens");
+    } else if response.is_filtered() {
+        tracing::warn!("The response was cut by the content filter");
+    }
+    let usage = config.annotate_usage.then(|| {
+        usage_table(&response, config.model.as_deref().or(api.model_name(None)))
+    });
+    let chat_msg = response.message;
 
     let mut msg = Msg::Text {
         role: chat_msg.role,
@@ -144,10 +259,103 @@ pub async fn run_ai_chat(
     )
     .unwrap();
 
+    let mut table = value.as_inline_table().unwrap().clone().into_table();
+    if let Some(usage) = usage {
+        table.insert("usage", toml_edit::value(usage));
+    }
     let msgs = doc.toml_doc["msgs"].as_array_of_tables_mut().unwrap();
-    msgs.push(value.as_inline_table().unwrap().clone().into_table());
+    msgs.push(table);
 
     doc.write_doc(&ai_chat.file).await?;
 
     Ok(())
 }
+
+/// What the response took, for the `usage` table of its message. The model
+/// is the one that answered if the provider tells it.
+fn usage_table(
+    response: &ChatResponse,
+    model: Option<&str>,
+) -> toml_edit::InlineTable {
+    let mut table = toml_edit::InlineTable::new();
+    let model = response.model.as_deref().or(model);
+    if let Some(model) = model {
+        table.insert("model", model.into());
+    }
+    if let Some(usage) = response.usage {
+        table.insert("prompt_tokens", (usage.prompt_tokens as i64).into());
+        table.insert(
+            "completion_tokens",
+            (usage.completion_tokens as i64).into(),
+        );
+        if let Some(cost) = model.and_then(|m| estimate_cost(m, usage)) {
+            // Rounded to a millionth of a dollar.
+            let cost = (cost * 1e6).round() / 1e6;
+            table.insert("cost_usd", cost.into());
+        }
+    }
+    table.insert("latency_ms", (response.latency.as_millis() as i64).into());
+    table
+}
+
+/// Summarizes the messages with the model of the config, into a system
+/// message replacing them.
+async fn summarize_history(
+    api: &(dyn ChatCompletionAPI + Sync),
+    config: &Cfg,
+    msgs: &[Msg],
+    cancel: &CancellationToken,
+) -> anyhow::Result<Msg> {
+    let transcript = msgs
+        .iter()
+        .filter_map(|msg| match msg {
+            Msg::Text { role, content } => Some(format!("{role:?}: {content}")),
+            _ => None,
+        })
+        .join("\n\n");
+    let messages = [
+        Message {
+            role: Role::System,
+            content: COMPACT_PROMPT.into(),
+        },
+        Message {
+            role: Role::User,
+            content: transcript.into(),
+        },
+    ];
+    let chat = ChatCompletionsArgs::builder()
+        .maybe_model_overwrite(config.model.as_deref())
+        .messages(&messages)
+        .maybe_reasoning_effort(config.reasoning_effort)
+        .build();
+    let summary = cancellable(cancel, api.run_chat(chat)).await?;
+    Ok(Msg::Text {
+        role: Role::System,
+        content: format!("{COMPACTED_PREFIX}\n\n{}", summary.content.trim()),
+    })
+}
+
+#[test]
+fn usage_table_test() {
+    let response = ChatResponse {
+        message: Message {
+            role: Role::Assistant,
+            content: "Hi".into(),
+        },
+        finish_reason: None,
+        usage: Some(crate::llm_audit::TokenUsage {
+            prompt_tokens: 1000,
+            completion_tokens: 100,
+        }),
+        model: Some("gpt-4o-2024-08-06".into()),
+        request_id: None,
+        latency: std::time::Duration::from_millis(1500),
+        seed: None,
+        system_fingerprint: None,
+    };
+    assert_eq!(
+        usage_table(&response, Some("gpt-4o")).to_string(),
+        "{ model = \"gpt-4o-2024-08-06\", prompt_tokens = 1000, \
+         completion_tokens = 100, cost_usd = 0.0035, latency_ms = 1500 }"
+    );
+}
@@ -1,3 +1,280 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{
+    aws_batch::budget::Cents, embedding::EmbeddingsPlatform,
+    llm::ChatCompletionPlatform, locale::Lang, rate_limit::RateLimitsConfig,
+    request_extras::ProvidersConfig, routing::RoutingConfig,
+    vector_store::VectorStoreConfig,
+};
+
 pub trait AppConfigProvider {
     fn is_dev_mode(&self) -> bool;
+
+    /// The maximum estimated cost of a single job.
+    fn get_job_budget(&self) -> Option<Cents> { None }
+
+    /// The maximum estimated cost of all the jobs started in a month.
+    fn get_monthly_budget(&self) -> Option<Cents> { None }
+
+    /// The language of the output.
+    fn get_lang(&self) -> Lang { Lang::default() }
+}
+
+const CONFIG_DIR: &str = "trakktor";
+const CONFIG_FILE: &str = "config.toml";
+const DATA_DIR: &str = "trakktor";
+
+/// The directory of the data kept on the machine, `$XDG_DATA_HOME/trakktor`
+/// or `~/.local/share/trakktor`.
+pub fn default_data_dir() -> anyhow::Result<PathBuf> {
+    let data_dir = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(
+            std::env::var_os("HOME")
+                .ok_or_else(|| anyhow::anyhow!("HOME is not set"))?,
+        )
+        .join(".local/share"),
+    };
+    Ok(data_dir.join(DATA_DIR))
+}
+
+/// Settings from the Trakktor config file, used when they are not given on
+/// the command line.
+///
+/// ```toml
+/// chat_platform = "open-ai"
+/// chat_model = "gpt-4o"
+/// aws_profile = "work"
+/// lang = "ru"
+///
+/// [aws.work]
+/// region = "eu-west-1"
+/// stack_prefix = "trakktor-work"
+/// read_only = true
+///
+/// [vector_store]
+/// backend = "qdrant"
+/// url = "http://localhost:6333"
+/// collection = "trakktor"
+///
+/// [limits]
+/// s3_transfers = 8
+/// llm_requests = 2
+///
+/// [rate_limits.open-ai]
+/// requests_per_minute = 500
+/// tokens_per_minute = 30000
+///
+/// [providers.open-ai.headers]
+/// OpenAI-Organization = "org-123"
+///
+/// [[routing.tasks.summary]]
+/// platform = "open-ai"
+/// model = "gpt-4o-mini"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfigFile {
+    pub chat_platform: Option<ChatCompletionPlatform>,
+    pub chat_model: Option<Arc<str>>,
+    pub embeddings_platform: Option<EmbeddingsPlatform>,
+    pub embeddings_model: Option<Arc<str>>,
+    /// The language of the output and of the default prompts.
+    pub lang: Option<Lang>,
+    /// The AWS profile to use when none is given.
+    pub aws_profile: Option<Arc<str>>,
+    /// Settings of the AWS profiles, by profile name. The `default` section
+    /// applies when no profile is used.
+    pub aws: HashMap<String, AwsProfileConfig>,
+    /// The store of the chunk embeddings, local by default.
+    pub vector_store: VectorStoreConfig,
+    /// The concurrency limits of all the commands.
+    pub limits: Limits,
+    /// Extra headers and body fields of the requests to the chat and
+    /// embeddings providers.
+    pub providers: ProvidersConfig,
+    /// The chat providers to fail over to and the providers of the tasks.
+    pub routing: RoutingConfig,
+    /// The requests and tokens per minute of the chat and embeddings
+    /// providers.
+    pub rate_limits: RateLimitsConfig,
+}
+
+/// How much work the commands do at the same time. Lower limits help on slow
+/// connections and with rate limited APIs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Files and parts of files uploaded to or downloaded from S3 at once.
+    pub s3_transfers: usize,
+    /// Requests to the other AWS APIs, e.g. listing or deleting objects.
+    pub aws_requests: usize,
+    /// Local files read at once, e.g. the includes of a chat document.
+    pub file_reads: usize,
+    /// Requests to the chat and embeddings APIs at once.
+    pub llm_requests: usize,
+    /// Threads of blocking work, e.g. hashing files and database access.
+    pub blocking_tasks: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            s3_transfers: 4,
+            aws_requests: 8,
+            file_reads: 64,
+            llm_requests: 4,
+            // The default of the Tokio runtime.
+            blocking_tasks: 512,
+        }
+    }
+}
+
+impl Limits {
+    fn check(&self) -> anyhow::Result<()> {
+        let limits = [
+            ("s3_transfers", self.s3_transfers),
+            ("aws_requests", self.aws_requests),
+            ("file_reads", self.file_reads),
+            ("llm_requests", self.llm_requests),
+            ("blocking_tasks", self.blocking_tasks),
+        ];
+        for (name, limit) in limits {
+            if limit == 0 {
+                anyhow::bail!("The limit {name} must be at least 1.");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AwsProfileConfig {
+    pub region: Option<Arc<str>>,
+    pub stack_prefix: Option<Arc<str>>,
+    /// Refuse the commands that change the stacks or the jobs, e.g. for the
+    /// profiles of the analysts given access to a team stack.
+    pub read_only: bool,
+}
+
+impl AppConfigFile {
+    /// The default location of the config file,
+    /// `$XDG_CONFIG_HOME/trakktor/config.toml` or
+    /// `~/.config/trakktor/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_dir.join(CONFIG_DIR).join(CONFIG_FILE))
+    }
+
+    /// Loads the config file. A missing file at the default location is the
+    /// same as an empty one.
+    ///
+    /// It is loaded before the async runtime is started, since the runtime is
+    /// configured with the limits.
+    #[tracing::instrument(level = "debug")]
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::default_path() {
+                Some(path) if path.try_exists()? => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&contents)
+            .with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    fn parse(contents: &str) -> anyhow::Result<Self> {
+        let config: Self = toml_edit::de::from_str(contents)?;
+        config.limits.check()?;
+        Ok(config)
+    }
+
+    /// The settings of the AWS profile, or of the `default` section if no
+    /// profile is given.
+    pub fn get_aws_profile(&self, profile: Option<&str>) -> AwsProfileConfig {
+        self.aws
+            .get(profile.unwrap_or("default"))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[test]
+fn app_config_file_test() -> anyhow::Result<()> {
+    let config = AppConfigFile::parse(
+        r#"
+        chat_platform = "open-ai"
+        chat_model = "gpt-4o"
+        aws_profile = "work"
+
+        [aws.work]
+        region = "eu-west-1"
+        stack_prefix = "trakktor-work"
+        read_only = true
+
+        [aws.default]
+        region = "us-east-1"
+        "#,
+    )?;
+    assert!(matches!(
+        config.chat_platform,
+        Some(ChatCompletionPlatform::OpenAI)
+    ));
+    assert_eq!(config.chat_model.as_deref(), Some("gpt-4o"));
+    assert_eq!(config.aws_profile.as_deref(), Some("work"));
+
+    let work = config.get_aws_profile(Some("work"));
+    assert_eq!(work.region.as_deref(), Some("eu-west-1"));
+    assert_eq!(work.stack_prefix.as_deref(), Some("trakktor-work"));
+    assert!(work.read_only);
+    let default = config.get_aws_profile(None);
+    assert_eq!(default.region.as_deref(), Some("us-east-1"));
+    assert_eq!(default.stack_prefix, None);
+    assert!(!default.read_only);
+    assert!(config.get_aws_profile(Some("other")).region.is_none());
+
+    assert!(AppConfigFile::parse("chat_modle = \"gpt-4o\"").is_err());
+    Ok(())
+}
+
+#[test]
+fn app_config_limits_test() -> anyhow::Result<()> {
+    let config = AppConfigFile::parse(
+        r#"
+        [limits]
+        s3_transfers = 16
+        llm_requests = 1
+        "#,
+    )?;
+    assert_eq!(config.limits.s3_transfers, 16);
+    assert_eq!(config.limits.llm_requests, 1);
+    assert_eq!(config.limits.aws_requests, Limits::default().aws_requests);
+    assert_eq!(AppConfigFile::parse("")?.limits, Limits::default());
+
+    assert!(AppConfigFile::parse("[limits]\nfile_reads = 0").is_err());
+    assert!(AppConfigFile::parse("[limits]\nmel_buffers = 1").is_err());
+
+    let providers =
+        AppConfigFile::parse("[providers.gemini]\ntimeout = \"90s\"")?
+            .providers;
+    assert_eq!(
+        providers.gemini.timeout,
+        Some(std::time::Duration::from_secs(90))
+    );
+    assert_eq!(providers.open_ai.timeout, None);
+    Ok(())
 }
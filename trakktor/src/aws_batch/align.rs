@@ -0,0 +1,259 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    app_config::AppConfigProvider,
+    aws_batch::{
+        aligner::AlignerJobArgs,
+        batch::{submit_job, DependsOn, JobOptions},
+        cloudformation::{load_gpu_stack_outputs, StackId},
+        config::{AwsConfigProvider, CloudFormationStackProvider, S3Provider},
+        job::{
+            make_info_storage_key, make_job_prefix, parse_job_name,
+            parse_job_tag, JobInfo, JobType, JobUid, MAX_TAGS,
+        },
+        key_parameter::put_key_parameter,
+        s3::put_object,
+        storage_layout::ensure_layout_version,
+        transcribe::{check_unique_file_names, upload_input_file},
+        whisper::OutputFormat,
+    },
+};
+
+#[derive(clap::Args, Debug)]
+pub struct AlignJobArgs {
+    /// The audio file.
+    pub audio: PathBuf,
+    /// The transcript of the audio: plain text, SubRip or WebVTT subtitles,
+    /// or the Whisper JSON output. Its file name must differ from the audio
+    /// file name.
+    pub transcript: PathBuf,
+    /// A name of the job, can be used instead of the job ID.
+    #[arg(short, long, value_parser = parse_job_name)]
+    pub name: Option<Box<str>>,
+    /// Tags of the job, can be used to select the job. May be repeated.
+    #[arg(short, long = "tag", value_parser = parse_job_tag)]
+    pub tags: Vec<Box<str>>,
+}
+
+/// Uploads the audio and its transcript and submits a job aligning the words
+/// of the transcript with the audio. The job writes into its output folder:
+/// - `<audio>.words.json`, named after the audio file without the extension:
+///   the words with their start and end times in seconds, and the alignment
+///   score;
+/// - `<audio>.vtt`: WebVTT subtitles with a timestamp before every word, for
+///   karaoke-style display.
+///
+/// The cost of alignment jobs is not estimated, so they are not checked
+/// against the budget.
+#[tracing::instrument(level = "info", skip(config))]
+pub async fn run_align_job(
+    config: Arc<
+        impl AwsConfigProvider
+            + S3Provider
+            + CloudFormationStackProvider
+            + AppConfigProvider
+            + Sync
+            + Send
+            + 'static,
+    >,
+    job: &AlignJobArgs,
+) -> crate::Result<()> {
+    Ok(align_transcripts(config, job).await?)
+}
+
+async fn align_transcripts(
+    config: Arc<
+        impl AwsConfigProvider
+            + S3Provider
+            + CloudFormationStackProvider
+            + AppConfigProvider
+            + Sync
+            + Send
+            + 'static,
+    >,
+    job: &AlignJobArgs,
+) -> anyhow::Result<()> {
+    check_unique_file_names(&[job.audio.clone(), job.transcript.clone()])?;
+    if job.tags.len() > MAX_TAGS {
+        anyhow::bail!("At most {MAX_TAGS} tags are allowed.");
+    }
+
+    crate::aws_batch::cloudformation::manage_cloudformation_stacks(
+        &*config,
+        [StackId::Base, StackId::GpuBatch].into(),
+    )
+    .await?;
+
+    ensure_layout_version(&*config).await?;
+
+    let stack_outputs = load_gpu_stack_outputs(&*config).await?;
+    let job_definition = stack_outputs.get_align_job_definition()?;
+
+    let jid = JobUid::new();
+    let root_prefix = config.get_root_prefix();
+    tracing::info!(job_id = %jid, "Starting alignment job.");
+
+    let (input_file, transcript_file) = tokio::try_join!(
+        upload_input_file(&*config, &jid, &job.audio),
+        upload_input_file(&*config, &jid, &job.transcript),
+    )?;
+
+    put_object(
+        &*config,
+        b"",
+        &make_info_storage_key(
+            root_prefix,
+            &jid,
+            &JobInfo {
+                job_type: JobType::Align,
+                start_time: chrono::Utc::now(),
+                batch_label: None,
+                array_size: None,
+                model: None,
+                name: job.name.clone(),
+                tags: job.tags.clone(),
+                input_hash: None,
+                language: None,
+                estimated_cost: None,
+                audio_duration: None,
+                preprocessed: false,
+                after: None,
+                retry_of: None,
+                parts: None,
+            },
+        ),
+    )
+    .await?;
+
+    let encryption_key_parameter = put_key_parameter(&*config).await?;
+    submit_job(
+        &*config,
+        jid.clone(),
+        &stack_outputs.job_queue,
+        job_definition,
+        AlignerJobArgs {
+            job_uid: &jid,
+            job_prefix: &make_job_prefix(root_prefix, &jid),
+            input_file: &input_file,
+            transcript_file: &transcript_file,
+            source_prefix: None,
+            encryption_key_parameter: encryption_key_parameter.as_deref(),
+        }
+        .environments(),
+        JobOptions::default(),
+    )
+    .await?;
+
+    tracing::info!(job_id = %jid, "Alignment job submitted.");
+
+    Ok(())
+}
+
+/// A transcription job followed by an alignment job in a pipeline.
+#[derive(Debug)]
+pub(crate) struct AlignAfter<'a> {
+    pub job_uid: &'a JobUid,
+    /// The Batch ID of the transcription job.
+    pub batch_job_id: &'a str,
+    /// The audio file the transcription job transcribes.
+    pub audio_file: &'a str,
+    pub start_time: chrono::DateTime<chrono::Utc>,
+    pub tags: Vec<Box<str>>,
+}
+
+/// Checks that the transcription produces the Whisper JSON output the
+/// alignment reads.
+pub(crate) fn check_alignable_formats(
+    formats: &[OutputFormat],
+    compressed: &[OutputFormat],
+) -> anyhow::Result<()> {
+    if !formats.is_empty() && !formats.contains(&OutputFormat::Json) {
+        anyhow::bail!("The alignment needs the json output format.");
+    }
+    if compressed.contains(&OutputFormat::Json) {
+        anyhow::bail!("The alignment can't read the compressed json output.");
+    }
+    Ok(())
+}
+
+/// Submits a job aligning the words of the transcript of the transcription
+/// job with its audio, which starts once the transcription succeeds. Returns
+/// the ID of the alignment job.
+pub(crate) async fn submit_align_after(
+    config: &(impl AwsConfigProvider + S3Provider + CloudFormationStackProvider),
+    transcription: AlignAfter<'_>,
+    job_queue: &str,
+    job_definition: &str,
+) -> anyhow::Result<JobUid> {
+    let jid = JobUid::new();
+    let root_prefix = config.get_root_prefix();
+
+    // Whisper names its outputs after the audio file without the extension.
+    let transcript_file = format!(
+        "{}.json",
+        Path::new(transcription.audio_file)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or(transcription.audio_file)
+    );
+
+    put_object(
+        config,
+        b"",
+        &make_info_storage_key(
+            root_prefix,
+            &jid,
+            &JobInfo {
+                job_type: JobType::Align,
+                start_time: transcription.start_time,
+                batch_label: None,
+                array_size: None,
+                model: None,
+                name: None,
+                tags: transcription.tags,
+                input_hash: None,
+                language: None,
+                estimated_cost: None,
+                audio_duration: None,
+                preprocessed: false,
+                after: Some(transcription.job_uid.clone()),
+                retry_of: None,
+                parts: None,
+            },
+        ),
+    )
+    .await?;
+
+    let encryption_key_parameter = put_key_parameter(config).await?;
+    submit_job(
+        config,
+        jid.clone(),
+        job_queue,
+        job_definition,
+        AlignerJobArgs {
+            job_uid: &jid,
+            job_prefix: &make_job_prefix(root_prefix, &jid),
+            input_file: transcription.audio_file,
+            transcript_file: &transcript_file,
+            source_prefix: Some(&make_job_prefix(
+                root_prefix,
+                transcription.job_uid,
+            )),
+            encryption_key_parameter: encryption_key_parameter.as_deref(),
+        }
+        .environments(),
+        JobOptions {
+            depends_on: vec![DependsOn {
+                job_id: transcription.batch_job_id,
+                n_to_n: false,
+            }],
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    Ok(jid)
+}
@@ -0,0 +1,57 @@
+use serde::Serialize;
+
+use crate::aws_batch::{batch::ContainerEnvs, job::JobUid};
+
+const VERSION_TAG: &str = "3";
+const DEV_VERSION_TAG: &str = "dev";
+const IMAGE_NAME: &str = "ghcr.io/lymar/trakktor/aligner";
+/// Name of the job definition of the aligner in the GPU batch stack.
+pub const JOB_DEFINITION_NAME: &str = "GpuAlignJob";
+
+pub fn make_image_name(is_dev: bool) -> String {
+    format!(
+        "{}:{}",
+        IMAGE_NAME,
+        if is_dev { DEV_VERSION_TAG } else { VERSION_TAG }
+    )
+}
+
+/// Arguments for an alignment job passed to the container as environment
+/// variables.
+#[derive(Debug, Serialize)]
+pub struct AlignerJobArgs<'a> {
+    #[serde(rename = "TRK_JOB_UID")]
+    pub job_uid: &'a JobUid,
+    /// Storage key prefix of all the job objects.
+    #[serde(rename = "TRK_JOB_PREFIX")]
+    pub job_prefix: &'a str,
+    /// The audio to align the transcript with.
+    #[serde(rename = "TRK_INPUT_FILE")]
+    pub input_file: &'a str,
+    /// The transcript: plain text, SubRip or WebVTT subtitles, or Whisper
+    /// JSON output.
+    #[serde(rename = "TRK_TRANSCRIPT_FILE")]
+    pub transcript_file: &'a str,
+    /// Storage key prefix of the transcription job the alignment follows in
+    /// a pipeline. The audio is read from its input folder and the transcript
+    /// from its output folder instead of the input folder of this job.
+    #[serde(
+        rename = "TRK_SOURCE_PREFIX",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub source_prefix: Option<&'a str>,
+    /// The SSM parameter holding the key of the client-side encryption, see
+    /// [`super::key_parameter`].
+    #[serde(
+        rename = "TRK_ENCRYPTION_KEY_PARAMETER",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub encryption_key_parameter: Option<&'a str>,
+}
+
+impl<'a> AlignerJobArgs<'a> {
+    /// Convert the arguments into a list of environment variables.
+    pub fn environments(&self) -> ContainerEnvs {
+        ContainerEnvs::from_args(self)
+    }
+}
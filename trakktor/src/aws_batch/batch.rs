@@ -1,7 +1,10 @@
 use std::sync::Arc;
 
 use aws_sdk_batch::{
-    types::{ContainerOverrides, JobSummary, KeyValuePair, KeyValuesPair},
+    types::{
+        ArrayJobDependency, ArrayProperties, ContainerOverrides, JobDependency,
+        JobDetail, JobStatus, JobSummary, KeyValuePair, KeyValuesPair,
+    },
     Client,
 };
 use tokio::{sync::Semaphore, task::JoinHandle};
@@ -10,12 +13,66 @@ use tracing::{info_span, Instrument};
 use super::config::{AwsConfigProvider, CloudFormationStackProvider};
 use crate::aws_batch::job::JobUid;
 
-const PARALLEL_REQS: usize = 8;
+/// Job parameters matched by the notification rule of the base stack.
+const NOTIFY_STACK_PARAMETER: &str = "trkStack";
+const NOTIFY_TARGET_PARAMETER: &str = "trkNotify";
 
 /// Environment variables to be passed to the job container.
 #[derive(Debug)]
 pub struct ContainerEnvs(pub Vec<(String, String)>);
 
+impl ContainerEnvs {
+    /// Convert the job arguments into a list of environment variables. The
+    /// arguments must serialize into an object with string values.
+    pub fn from_args(args: &impl serde::Serialize) -> Self {
+        let sv = serde_json::to_value(args).expect("Failed to serialize");
+        let serde_json::Value::Object(vm) = sv else {
+            panic!("Expected object");
+        };
+        ContainerEnvs(
+            vm.into_iter()
+                .map(|(k, v)| {
+                    let serde_json::Value::String(vs) = v else {
+                        panic!("Expected string");
+                    };
+                    (k, vs)
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+}
+
+/// Options of a submitted job.
+#[derive(Debug, Default)]
+pub struct JobOptions<'a> {
+    /// Submit an array job with the given number of children; each child
+    /// receives its index in the `AWS_BATCH_JOB_ARRAY_INDEX` environment
+    /// variable.
+    pub array_size: Option<u32>,
+    /// The jobs to wait for, at most [`MAX_DEPENDENCIES`]. The job fails if
+    /// any of them fails.
+    pub depends_on: Vec<DependsOn<'a>>,
+    /// The name of the stage if the job prepares the input of the main job
+    /// with the same ID, e.g. `preprocess`. It is named after the ID and the
+    /// stage, and its completion is not notified.
+    pub stage: Option<&'a str>,
+}
+
+/// The maximum number of jobs a job can depend on in AWS Batch.
+pub const MAX_DEPENDENCIES: usize = 20;
+
+/// A job the submitted job waits for.
+#[derive(Debug)]
+pub struct DependsOn<'a> {
+    /// The Batch ID of the job.
+    pub job_id: &'a str,
+    /// Both jobs are array jobs of the same size, and each child only waits
+    /// for the child of the job with the same index.
+    pub n_to_n: bool,
+}
+
+/// Submits a job to the queue and returns its Batch ID. The notification
+/// target of the config, if any, is notified when the job finishes.
 #[tracing::instrument(level = "debug", skip(config))]
 pub async fn submit_job(
     config: &(impl AwsConfigProvider + CloudFormationStackProvider),
@@ -23,40 +80,74 @@ pub async fn submit_job(
     queue: &str,
     definition: &str,
     envs: ContainerEnvs,
-) -> anyhow::Result<()> {
+    options: JobOptions<'_>,
+) -> anyhow::Result<String> {
     let client = Client::new(config.get_aws_config());
 
-    client
-        .submit_job()
-        .job_name(uid.to_string())
-        .job_queue(queue)
-        .job_definition(definition)
-        .container_overrides(
-            ContainerOverrides::builder()
-                .set_environment(Some(
-                    envs.0
-                        .into_iter()
-                        .map(|(k, v)| {
-                            KeyValuePair::builder().name(k).value(v).build()
-                        })
-                        .collect(),
-                ))
-                .build(),
-        )
-        .send()
-        .await?;
+    let mut req_builder = client.submit_job();
+    if let Some(target) = config
+        .get_notification_target()
+        .filter(|_| options.stage.is_none())
+    {
+        req_builder = req_builder
+            .parameters(NOTIFY_STACK_PARAMETER, config.get_stack_prefix())
+            .parameters(NOTIFY_TARGET_PARAMETER, target.as_str());
+    }
+    if options.depends_on.len() > MAX_DEPENDENCIES {
+        anyhow::bail!("A job can depend on at most {MAX_DEPENDENCIES} jobs.");
+    }
+    for depends_on in options.depends_on {
+        let mut dependency = JobDependency::builder().job_id(depends_on.job_id);
+        if depends_on.n_to_n {
+            dependency = dependency.r#type(ArrayJobDependency::NToN);
+        }
+        req_builder = req_builder.depends_on(dependency.build());
+    }
+    let job_name = match options.stage {
+        Some(stage) => format!("{uid}-{stage}"),
+        None => uid.to_string(),
+    };
+
+    let res =
+        req_builder
+            .job_name(job_name)
+            .job_queue(queue)
+            .job_definition(definition)
+            .container_overrides(
+                ContainerOverrides::builder()
+                    .set_environment(Some(
+                        envs.0
+                            .into_iter()
+                            .map(|(k, v)| {
+                                KeyValuePair::builder().name(k).value(v).build()
+                            })
+                            .collect(),
+                    ))
+                    .build(),
+            )
+            .set_array_properties(options.array_size.map(|size| {
+                ArrayProperties::builder().size(size as i32).build()
+            }))
+            .send()
+            .await?;
 
-    Ok(())
+    res.job_id
+        .ok_or_else(|| anyhow::anyhow!("Batch returned no ID of the job."))
 }
 
+/// Lists the jobs of every given queue concurrently.
+///
+/// The result contains one entry per queue, in the same order as the input, so
+/// that a failure in a single queue does not prevent the others from being
+/// listed.
 #[tracing::instrument(level = "debug", skip_all)]
 pub async fn load_jobs(
     config: &(impl AwsConfigProvider + CloudFormationStackProvider),
     batch_queues: impl IntoIterator<Item = impl AsRef<str> + Send + 'static>,
-) -> anyhow::Result<Vec<Vec<JobSummary>>> {
+) -> Vec<anyhow::Result<Vec<JobSummary>>> {
     let client = Client::new(config.get_aws_config());
 
-    let par_sem = Arc::new(Semaphore::new(PARALLEL_REQS));
+    let par_sem = Arc::new(Semaphore::new(config.get_limits().aws_requests));
 
     let mut chunks: Vec<JoinHandle<anyhow::Result<Vec<JobSummary>>>> =
         Vec::new();
@@ -95,8 +186,43 @@ pub async fn load_jobs(
     let mut res = vec![];
 
     for c in chunks {
-        res.push(c.await??);
+        res.push(c.await.map_err(anyhow::Error::from).and_then(|r| r));
     }
 
-    Ok(res)
+    res
+}
+
+/// Loads the details of the job, with its attempts, or `None` if AWS Batch
+/// no longer has it.
+#[tracing::instrument(level = "debug", skip(config))]
+pub async fn describe_job(
+    config: &impl AwsConfigProvider,
+    batch_job_id: &str,
+) -> anyhow::Result<Option<JobDetail>> {
+    let res = Client::new(config.get_aws_config())
+        .describe_jobs()
+        .jobs(batch_job_id)
+        .send()
+        .await?;
+    Ok(res.jobs.unwrap_or_default().into_iter().next())
+}
+
+/// Loads the failed children of the array job.
+#[tracing::instrument(level = "debug", skip(config))]
+pub async fn load_failed_children(
+    config: &impl AwsConfigProvider,
+    array_job_id: &str,
+) -> anyhow::Result<Vec<JobSummary>> {
+    Ok(Client::new(config.get_aws_config())
+        .list_jobs()
+        .array_job_id(array_job_id)
+        .job_status(JobStatus::Failed)
+        .into_paginator()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await?
+        .into_iter()
+        .filter_map(|res| res.job_summary_list)
+        .flatten()
+        .collect())
 }
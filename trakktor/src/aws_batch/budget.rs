@@ -0,0 +1,159 @@
+//! Estimation of the cost of the jobs, checked against the budget before
+//! the jobs are submitted.
+//!
+//! The estimate is rough: the instance price differs between the regions,
+//! and the processing speed depends on the audio.
+
+use std::time::Duration;
+
+use chrono::{Datelike, Utc};
+
+use super::{
+    cloudformation::GpuInstanceType, select::StoredJob, whisper::Model,
+};
+
+/// An amount of US dollars in cents.
+pub type Cents = u32;
+
+/// Time to start an instance and pull the image, paid for by every job.
+const JOB_OVERHEAD: Duration = Duration::from_secs(5 * 60);
+
+/// Processing time of the model on g6.xlarge relative to the duration of the
+/// audio.
+fn processing_ratio(model: Model) -> f64 {
+    match model {
+        Model::Small => 0.05,
+        Model::Medium => 0.1,
+        Model::LargeV2 | Model::Large => 0.2,
+        Model::LargeV3Turbo => 0.06,
+    }
+}
+
+/// Estimate the cost of transcribing audio of the given duration in a
+/// separate job.
+pub fn estimate_job_cost(
+    model: Model,
+    instance_type: GpuInstanceType,
+    audio: Duration,
+) -> Cents {
+    let seconds = audio.as_secs_f64() *
+        processing_ratio(model) *
+        instance_type.get_relative_processing_time() +
+        JOB_OVERHEAD.as_secs_f64();
+    (seconds / 3600.0 * instance_type.get_hourly_price_usd() * 100.0).ceil()
+        as Cents
+}
+
+/// Estimate the time the model takes to transcribe audio of the given
+/// duration on g6.xlarge, once the job is running.
+pub fn estimate_processing_time(model: Model, audio: Duration) -> Duration {
+    audio.mul_f64(processing_ratio(model))
+}
+
+#[test]
+fn estimate_job_cost_test() {
+    let g6 = GpuInstanceType::G6;
+    // 5 minutes of overhead only.
+    assert_eq!(estimate_job_cost(Model::Large, g6, Duration::ZERO), 7);
+    // 12 more minutes of processing.
+    assert_eq!(
+        estimate_job_cost(Model::Large, g6, Duration::from_secs(3600)),
+        23
+    );
+    assert_eq!(
+        estimate_processing_time(Model::Large, Duration::from_secs(3600)),
+        Duration::from_secs(12 * 60)
+    );
+    assert!(
+        estimate_job_cost(Model::Small, g6, Duration::from_secs(3600)) <
+            estimate_job_cost(Model::Large, g6, Duration::from_secs(3600))
+    );
+    // 18 minutes of processing on the slower, cheaper instance.
+    assert_eq!(
+        estimate_job_cost(
+            Model::Large,
+            GpuInstanceType::G4dn,
+            Duration::from_secs(3600)
+        ),
+        21
+    );
+}
+
+/// Parse an amount of US dollars, e.g. `12.50`.
+pub fn parse_usd(s: &str) -> Result<Cents, String> {
+    let usd: f64 = s
+        .trim_start_matches('$')
+        .parse()
+        .map_err(|_| format!("Invalid amount: {s}"))?;
+    if !(0.0..=(Cents::MAX / 100) as f64).contains(&usd) {
+        return Err(format!("Invalid amount: {s}"));
+    }
+    Ok((usd * 100.0).round() as Cents)
+}
+
+pub fn format_usd(cents: Cents) -> String {
+    format!("${}.{:02}", cents / 100, cents % 100)
+}
+
+#[test]
+fn parse_usd_test() {
+    assert_eq!(parse_usd("12.5"), Ok(1250));
+    assert_eq!(parse_usd("$3"), Ok(300));
+    assert!(parse_usd("-1").is_err());
+    assert_eq!(format_usd(1205), "$12.05");
+}
+
+/// Estimated cost of the jobs started in the current calendar month (UTC).
+/// Deleted jobs are not counted.
+pub fn get_monthly_spending(jobs: &[StoredJob]) -> Cents {
+    let now = Utc::now();
+    jobs.iter()
+        .filter(|j| {
+            j.info.start_time.year() == now.year() &&
+                j.info.start_time.month() == now.month()
+        })
+        .filter_map(|j| j.info.estimated_cost)
+        .sum()
+}
+
+/// Check the estimated costs of the new jobs against the budgets.
+pub fn check_budget(
+    job_costs: &[Cents],
+    job_budget: Option<Cents>,
+    monthly: Option<(Cents, Cents)>,
+) -> anyhow::Result<()> {
+    if let Some(budget) = job_budget {
+        if let Some(&max) = job_costs.iter().max().filter(|&&c| c > budget) {
+            anyhow::bail!(
+                "A job is estimated to cost {}, which exceeds the job budget \
+                 of {}. Use --ignore-budget to submit it anyway.",
+                format_usd(max),
+                format_usd(budget)
+            );
+        }
+    }
+
+    if let Some((budget, spent)) = monthly {
+        let total = job_costs.iter().sum::<Cents>();
+        if spent + total > budget {
+            anyhow::bail!(
+                "The jobs are estimated to cost {}, and {} has already been \
+                 spent this month, which exceeds the monthly budget of {}. \
+                 Use --ignore-budget to submit them anyway.",
+                format_usd(total),
+                format_usd(spent),
+                format_usd(budget)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn check_budget_test() {
+    assert!(check_budget(&[100, 200], Some(200), None).is_ok());
+    assert!(check_budget(&[100, 201], Some(200), None).is_err());
+    assert!(check_budget(&[100, 200], None, Some((500, 200))).is_ok());
+    assert!(check_budget(&[100, 200], None, Some((500, 201))).is_err());
+}
@@ -6,17 +6,23 @@ use std::{
 
 use aws_sdk_batch::types::JobSummary;
 use aws_sdk_cloudformation::{
-    types::{Capability, Output, StackStatus, Tag},
+    types::{
+        Capability, ChangeSetStatus, ChangeSetType, Output,
+        StackDriftDetectionStatus, StackResourceDriftStatus, StackStatus, Tag,
+    },
     Client,
 };
-use gpu_batch::GpuBatchStackOutputs;
+use itertools::Itertools;
 
 use super::{
     batch,
-    config::{AwsConfigProvider, CloudFormationStackProvider},
+    config::{AwsConfigProvider, CloudFormationStackProvider, S3Provider},
     ec2::get_availability_zone_count,
 };
-use crate::app_config::AppConfigProvider;
+use crate::{
+    app_config::AppConfigProvider,
+    cancellation::{self, CancellationToken},
+};
 
 mod base;
 mod gpu_batch;
@@ -25,7 +31,10 @@ const TRAKKTOR_UID_TAG: &str = "trakktor:uid";
 const TRAKKTOR_VERSION_TAG: &str = "trakktor:version";
 const TRAKKTOR_STACK_TAG: &str = "trakktor:stack";
 
-pub use base::get_s3_storage_name;
+pub use base::{get_s3_storage_name, NotificationTarget, RetentionPolicy};
+pub use gpu_batch::{
+    GpuBatchStackOutputs, GpuInstanceType, WarmPool, WarmPoolChange,
+};
 
 #[derive(
     Debug,
@@ -66,11 +75,41 @@ fn stack_id_str() {
 pub async fn manage_cloudformation_stacks(
     config: &(impl AwsConfigProvider
           + CloudFormationStackProvider
+          + S3Provider
           + AppConfigProvider),
     stacks: HashSet<StackId>,
 ) -> anyhow::Result<()> {
     let client = Client::new(config.get_aws_config());
+    let cancel = config.get_cancellation_token();
+    let all_stacks = StackInfo::load_all(&client).await?;
 
+    for (stack_id, template) in
+        gen_stack_templates(config, &all_stacks, &stacks).await?
+    {
+        manage_stack(
+            config,
+            &all_stacks,
+            &client,
+            stack_id,
+            &template,
+            &cancel,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Generates the templates of the stacks, in the order they have to be
+/// deployed.
+async fn gen_stack_templates(
+    config: &(impl AwsConfigProvider
+          + CloudFormationStackProvider
+          + S3Provider
+          + AppConfigProvider),
+    all_stacks: &HashMap<Box<str>, StackInfo>,
+    stacks: &HashSet<StackId>,
+) -> anyhow::Result<Vec<(StackId, Box<str>)>> {
     let azs_count = tokio::sync::OnceCell::new();
     let azs_count = || async {
         azs_count
@@ -80,41 +119,99 @@ pub async fn manage_cloudformation_stacks(
             .await
     };
 
-    let all_stacks = StackInfo::load_all(&client).await?;
+    let mut templates = vec![];
 
     if stacks.contains(&StackId::Base) {
+        // The retention policy is kept in the stack outputs, so it is kept
+        // unless it is changed explicitly.
+        let retention = all_stacks
+            .get(&config.get_base_stack_name())
+            .map(|s| RetentionPolicy::from_stack_outputs(&s.outputs))
+            .unwrap_or_default();
+        let retention = match config.get_retention_changes() {
+            Some(changes) => retention.merge(changes)?,
+            None => retention,
+        };
+        // So are the notification targets, new ones are added when a job is
+        // submitted with them.
+        let mut notification_targets = all_stacks
+            .get(&config.get_base_stack_name())
+            .map(|s| NotificationTarget::from_stack_outputs(&s.outputs))
+            .unwrap_or_default();
+        if let Some(target) = config.get_notification_target() {
+            if !notification_targets.contains(target) {
+                notification_targets.push(target.clone());
+            }
+        }
         let template = base::gen_cloudformation_template(
             *azs_count().await?,
             config.get_stack_prefix(),
+            config.get_root_prefix(),
+            &retention,
+            &notification_targets,
         );
-
-        manage_stack(config, &all_stacks, &client, StackId::Base, &template)
-            .await?;
+        templates.push((StackId::Base, template));
     }
 
     if stacks.contains(&StackId::GpuBatch) {
+        // Like the retention policy, the instance type is kept unless it is
+        // changed explicitly.
+        let instance_type = match config.get_gpu_instance_type() {
+            Some(instance_type) => instance_type,
+            None => all_stacks
+                .get(&config.get_gpu_batch_stack_name())
+                .map(|s| GpuInstanceType::from_stack_outputs(&s.outputs))
+                .unwrap_or_default(),
+        };
+        // So is the warm pool.
+        let warm_pool = match config.get_gpu_warm_pool_change() {
+            Some(change) => change.clone().into_warm_pool(),
+            None => all_stacks
+                .get(&config.get_gpu_batch_stack_name())
+                .and_then(|s| WarmPool::from_stack_outputs(&s.outputs)),
+        };
+        if let Some(warm_pool) = &warm_pool {
+            tracing::info!(
+                "The warm GPU instance of {warm_pool} costs about ${:.0} a \
+                 month",
+                warm_pool.get_monthly_cost_usd(instance_type)
+            );
+        }
         let template = gpu_batch::gen_gpu_batch_template(
             *azs_count().await?,
             &config.get_base_stack_name(),
             config.is_dev_mode(),
+            instance_type,
+            warm_pool.as_ref(),
         );
-
-        manage_stack(
-            config,
-            &all_stacks,
-            &client,
-            StackId::GpuBatch,
-            &template,
-        )
-        .await?;
+        templates.push((StackId::GpuBatch, template));
     }
 
-    Ok(())
+    Ok(templates)
+}
+
+/// The versions the stacks are tagged with when they are up to date, so the
+/// recorded responses of the tests can describe them.
+#[cfg(all(test, feature = "integration-tests"))]
+pub(crate) async fn current_stack_uids(
+    config: &(impl AwsConfigProvider
+          + CloudFormationStackProvider
+          + S3Provider
+          + AppConfigProvider),
+    stacks: HashSet<StackId>,
+) -> anyhow::Result<Vec<(StackId, String)>> {
+    Ok(gen_stack_templates(config, &HashMap::new(), &stacks)
+        .await?
+        .into_iter()
+        .map(|(stack_id, template)| {
+            (stack_id, crate::hasher::get_hash_value(template.as_bytes()))
+        })
+        .collect())
 }
 
 #[tracing::instrument(
     level = "debug",
-    skip(config, all_stacks, client, template)
+    skip(config, all_stacks, client, template, cancel)
 )]
 async fn manage_stack(
     config: &impl CloudFormationStackProvider,
@@ -122,6 +219,7 @@ async fn manage_stack(
     client: &Client,
     stack_id: StackId,
     template: &str,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<()> {
     let stack_name = stack_id.get_stack_name(config);
     let ver = crate::hasher::get_hash_value(template.as_bytes());
@@ -140,18 +238,160 @@ async fn manage_stack(
         if stack_info.uid.as_ref() == ver {
             tracing::debug!("Stack is up to date");
         } else {
+            let current_template = client
+                .get_template()
+                .stack_name(stack_name.as_ref())
+                .send()
+                .await?
+                .template_body
+                .unwrap_or_default();
+            let update = StackUpdate {
+                stack_name: stack_name.clone(),
+                stack_version: stack_info.version.clone(),
+                template_diff: diff_lines(&current_template, template).into(),
+            };
+            if update.is_from_older_version() {
+                tracing::warn!(
+                    stack_name = stack_name.as_ref(),
+                    "The stack was deployed by Trakktor {}, it will be \
+                     updated to the templates of version {}.",
+                    update.stack_version,
+                    env!("CARGO_PKG_VERSION"),
+                );
+            } else if update.is_from_newer_version() {
+                tracing::warn!(
+                    stack_name = stack_name.as_ref(),
+                    "The stack was deployed by a newer Trakktor {}, updating \
+                     it to the templates of version {} may break it.",
+                    update.stack_version,
+                    env!("CARGO_PKG_VERSION"),
+                );
+            }
+            if !config.approve_stack_update(&update)? {
+                anyhow::bail!(
+                    "The update of stack {} was declined.",
+                    stack_name
+                );
+            }
+
             tracing::debug!("Updating stack");
-            update_stack(&client, &stack_name, &template, &ver, stack_id)
-                .await?;
+            update_stack(
+                client,
+                &stack_name,
+                template,
+                &ver,
+                stack_id,
+                cancel,
+            )
+            .await?;
         }
     } else {
         tracing::debug!(?stack_name, "Creating stack");
-        create_stack(&client, &stack_name, &template, &ver, stack_id).await?;
+        create_stack(client, &stack_name, template, &ver, stack_id, cancel)
+            .await?;
     }
 
     Ok(())
 }
 
+/// An update of an existing stack, shown to the user before it is made.
+#[derive(Debug)]
+pub struct StackUpdate {
+    pub stack_name: Box<str>,
+    /// The version of Trakktor that last deployed the stack.
+    pub stack_version: Box<str>,
+    /// The changed lines of the template with some context.
+    pub template_diff: Box<str>,
+}
+
+impl StackUpdate {
+    fn compare_version(&self) -> Option<std::cmp::Ordering> {
+        let stack_version = semver::Version::parse(&self.stack_version).ok()?;
+        let version = semver::Version::parse(env!("CARGO_PKG_VERSION")).ok()?;
+        Some(stack_version.cmp(&version))
+    }
+
+    pub fn is_from_older_version(&self) -> bool {
+        self.compare_version() == Some(std::cmp::Ordering::Less)
+    }
+
+    pub fn is_from_newer_version(&self) -> bool {
+        self.compare_version() == Some(std::cmp::Ordering::Greater)
+    }
+}
+
+/// Lines of context around the changed lines of a diff.
+const DIFF_CONTEXT: usize = 2;
+
+/// A line diff of the texts, with the removed lines prefixed with `-`, the
+/// added ones with `+`, and the skipped unchanged lines replaced with `...`.
+fn diff_lines(old: &str, new: &str) -> String {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+
+    // Lengths of the longest common subsequences of the line suffixes.
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() &&
+            (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1])
+        {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+
+    let changed = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, (sign, _))| *sign != ' ')
+        .map(|(n, _)| n)
+        .collect::<Vec<_>>();
+    let is_shown =
+        |n: usize| changed.iter().any(|&c| c.abs_diff(n) <= DIFF_CONTEXT);
+
+    let mut diff = String::new();
+    let mut skipped = false;
+    for (n, (sign, line)) in lines.into_iter().enumerate() {
+        if is_shown(n) {
+            diff.push_str(&format!("{sign} {line}\n"));
+            skipped = false;
+        } else if !skipped {
+            diff.push_str("...\n");
+            skipped = true;
+        }
+    }
+    diff
+}
+
+#[test]
+fn diff_lines_test() {
+    let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\n";
+    let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\n";
+    assert_eq!(
+        diff_lines(old, new),
+        "  a\n- b\n+ B\n  c\n  d\n...\n  h\n  i\n+ j\n"
+    );
+}
+
 macro_rules! stack_operation {
     ($client:expr, $method:ident, $stack_name:expr, $template:expr,
         $uid:expr, $stack:expr) => {
@@ -179,13 +419,14 @@ macro_rules! stack_operation {
     };
 }
 
-#[tracing::instrument(level = "debug", skip(client, template))]
+#[tracing::instrument(level = "debug", skip(client, template, cancel))]
 async fn create_stack(
     client: &Client,
     stack_name: &str,
     template: &str,
     uid: &str,
     stack: StackId,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<()> {
     let creation_res = stack_operation!(
         client,
@@ -200,18 +441,19 @@ async fn create_stack(
     tracing::debug!(stack_id = ?creation_res.stack_id,
         "Stack creation initiated");
 
-    await_stack_operation_completion(&client, &stack_name).await?;
+    await_stack_operation_completion(client, stack_name, cancel).await?;
 
     Ok(())
 }
 
-#[tracing::instrument(level = "debug", skip(client, template))]
+#[tracing::instrument(level = "debug", skip(client, template, cancel))]
 async fn update_stack(
     client: &Client,
     stack_name: &str,
     template: &str,
     uid: &str,
     stack: StackId,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<()> {
     let update_res = stack_operation!(
         client,
@@ -224,15 +466,16 @@ async fn update_stack(
     .await?;
 
     tracing::debug!(stack_id = ?update_res.stack_id, "Stack update initiated");
-    await_stack_operation_completion(&client, &stack_name).await?;
+    await_stack_operation_completion(client, stack_name, cancel).await?;
 
     Ok(())
 }
 
-#[tracing::instrument(level = "debug", skip(client))]
+#[tracing::instrument(level = "debug", skip(client, cancel))]
 async fn await_stack_operation_completion(
     client: &Client,
     stack_name: &str,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<()> {
     loop {
         let stack = client
@@ -244,7 +487,10 @@ async fn await_stack_operation_completion(
             .into_iter()
             .flatten()
             .next()
-            .ok_or_else(|| anyhow::anyhow!("Stack {} not found", stack_name))?;
+            .ok_or_else(|| crate::Error::AwsStack {
+                stack: stack_name.to_string(),
+                reason: "not found".into(),
+            })?;
 
         let status = stack.stack_status.ok_or_else(|| {
             anyhow::anyhow!("Stack {} status not found", stack_name)
@@ -257,23 +503,377 @@ async fn await_stack_operation_completion(
         } else if status.ends_with("_FAILED") ||
             status.ends_with("ROLLBACK_COMPLETE")
         {
-            anyhow::bail!("Stack operation failed: {}", status);
+            return Err(crate::Error::AwsStack {
+                stack: stack_name.to_string(),
+                reason: format!("operation failed: {status}"),
+            }
+            .into());
         } else {
-            tokio::time::sleep(std::time::Duration::from_secs(15)).await;
+            cancellation::sleep(cancel, std::time::Duration::from_secs(15))
+                .await?;
         }
     }
 
     Ok(())
 }
 
+/// The changes `manage_cloudformation_stacks` would make to a stack.
+#[derive(Debug)]
+pub struct StackPlan {
+    pub stack_name: Box<str>,
+    pub action: PlanAction,
+}
+
+#[derive(Debug)]
+pub enum PlanAction {
+    Create,
+    Update(Vec<ResourceChange>),
+    UpToDate,
+}
+
+/// A change of a resource in a change set.
+#[derive(Debug)]
+pub struct ResourceChange {
+    /// `Add`, `Modify`, `Remove`, `Import` or `Dynamic`.
+    pub action: Box<str>,
+    pub logical_id: Box<str>,
+    pub resource_type: Box<str>,
+    /// `True`, `False` or `Conditional` for modifications.
+    pub replacement: Option<Box<str>>,
+    /// The changed properties, e.g. `Properties.ComputeResources`.
+    pub properties: Vec<Box<str>>,
+}
+
+impl std::fmt::Display for ResourceChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = match self.action.as_ref() {
+            "Add" => '+',
+            "Remove" => '-',
+            _ => '~',
+        };
+        write!(
+            f,
+            "{sign} {} {} ({})",
+            self.action, self.logical_id, self.resource_type
+        )?;
+        match self.replacement.as_deref() {
+            Some("True") => write!(f, ", replaced")?,
+            Some("Conditional") => write!(f, ", may be replaced")?,
+            _ => {},
+        }
+        for property in &self.properties {
+            write!(f, "\n    {property}")?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn resource_change_display_test() {
+    let change = ResourceChange {
+        action: "Modify".into(),
+        logical_id: "GpuComputeEnvironment".into(),
+        resource_type: "AWS::Batch::ComputeEnvironment".into(),
+        replacement: Some("True".into()),
+        properties: vec!["Properties.ComputeResources".into()],
+    };
+    assert_eq!(
+        change.to_string(),
+        "~ Modify GpuComputeEnvironment (AWS::Batch::ComputeEnvironment), \
+         replaced\n    Properties.ComputeResources"
+    );
+}
+
+/// Previews the changes of the stacks without making them. The changes of
+/// the existing stacks are computed by CloudFormation in change sets, which
+/// are deleted afterwards.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn plan_cloudformation_stacks(
+    config: &(impl AwsConfigProvider
+          + CloudFormationStackProvider
+          + S3Provider
+          + AppConfigProvider),
+    stacks: HashSet<StackId>,
+) -> anyhow::Result<Vec<StackPlan>> {
+    let client = Client::new(config.get_aws_config());
+    let cancel = config.get_cancellation_token();
+    let all_stacks = StackInfo::load_all(&client).await?;
+
+    let mut plans = vec![];
+    for (stack_id, template) in
+        gen_stack_templates(config, &all_stacks, &stacks).await?
+    {
+        let stack_name = stack_id.get_stack_name(config);
+        let ver = crate::hasher::get_hash_value(template.as_bytes());
+        let action = match all_stacks.get(&stack_name) {
+            None => PlanAction::Create,
+            Some(stack_info) if stack_info.uid.as_ref() == ver => {
+                PlanAction::UpToDate
+            },
+            Some(_) => PlanAction::Update(
+                preview_stack_update(
+                    &client,
+                    &stack_name,
+                    &template,
+                    &ver,
+                    stack_id,
+                    &cancel,
+                )
+                .await?,
+            ),
+        };
+        plans.push(StackPlan { stack_name, action });
+    }
+
+    Ok(plans)
+}
+
+#[tracing::instrument(level = "debug", skip(client, template, cancel))]
+async fn preview_stack_update(
+    client: &Client,
+    stack_name: &str,
+    template: &str,
+    uid: &str,
+    stack: StackId,
+    cancel: &CancellationToken,
+) -> anyhow::Result<Vec<ResourceChange>> {
+    let change_set_name =
+        format!("trakktor-plan-{}", chrono::Utc::now().timestamp());
+    let res = client
+        .create_change_set()
+        .stack_name(stack_name)
+        .change_set_name(&change_set_name)
+        .change_set_type(ChangeSetType::Update)
+        .template_body(template)
+        .capabilities(Capability::CapabilityIam)
+        .capabilities(Capability::CapabilityNamedIam)
+        .capabilities(Capability::CapabilityAutoExpand)
+        .tags(Tag::builder().key(TRAKKTOR_UID_TAG).value(uid).build())
+        .tags(
+            Tag::builder()
+                .key(TRAKKTOR_STACK_TAG)
+                .value(stack.to_string())
+                .build(),
+        )
+        .tags(
+            Tag::builder()
+                .key(TRAKKTOR_VERSION_TAG)
+                .value(env!("CARGO_PKG_VERSION"))
+                .build(),
+        )
+        .send()
+        .await?;
+    let change_set_id = res
+        .id
+        .ok_or_else(|| anyhow::anyhow!("Change set has no ID"))?;
+    tracing::debug!(?change_set_id, "Change set creation initiated");
+
+    let changes = load_change_set(client, &change_set_id, cancel).await;
+
+    client
+        .delete_change_set()
+        .change_set_name(&change_set_id)
+        .send()
+        .await?;
+
+    changes
+}
+
+/// Waits for the change set to be created and loads its changes.
+async fn load_change_set(
+    client: &Client,
+    change_set_id: &str,
+    cancel: &CancellationToken,
+) -> anyhow::Result<Vec<ResourceChange>> {
+    let mut changes = vec![];
+    let mut next_token = None;
+    loop {
+        let res = client
+            .describe_change_set()
+            .change_set_name(change_set_id)
+            .set_next_token(next_token.clone())
+            .send()
+            .await?;
+
+        match res.status() {
+            Some(ChangeSetStatus::CreateComplete) => {},
+            Some(ChangeSetStatus::Failed) => {
+                let reason = res.status_reason().unwrap_or_default();
+                // Only the template metadata differs, e.g. the tags.
+                if reason.contains("didn't contain changes") ||
+                    reason.contains("No updates are to be performed")
+                {
+                    return Ok(changes);
+                }
+                anyhow::bail!("Change set creation failed: {reason}");
+            },
+            status => {
+                tracing::debug!(?status, "Change set status");
+                cancellation::sleep(cancel, std::time::Duration::from_secs(5))
+                    .await?;
+                continue;
+            },
+        }
+
+        changes.extend(
+            res.changes()
+                .iter()
+                .filter_map(|c| c.resource_change())
+                .map(|c| ResourceChange {
+                    action: c
+                        .action()
+                        .map(|a| a.as_str())
+                        .unwrap_or("Unknown")
+                        .into(),
+                    logical_id: c.logical_resource_id().unwrap_or("").into(),
+                    resource_type: c.resource_type().unwrap_or("").into(),
+                    replacement: c.replacement().map(|r| r.as_str().into()),
+                    properties: c
+                        .details()
+                        .iter()
+                        .filter_map(|d| d.target())
+                        .filter_map(|t| match (t.attribute(), t.name()) {
+                            (Some(attribute), Some(name)) => Some(
+                                format!("{}.{}", attribute.as_str(), name)
+                                    .into(),
+                            ),
+                            (Some(attribute), None) => {
+                                Some(attribute.as_str().into())
+                            },
+                            _ => None,
+                        })
+                        .unique()
+                        .collect(),
+                }),
+        );
+
+        next_token = res.next_token;
+        if next_token.is_none() {
+            return Ok(changes);
+        }
+    }
+}
+
+/// Resources of a stack that differ from its template.
+#[derive(Debug)]
+pub struct StackDrift {
+    pub stack_name: Box<str>,
+    pub resources: Vec<ResourceDrift>,
+}
+
+#[derive(Debug)]
+pub struct ResourceDrift {
+    pub logical_id: Box<str>,
+    pub resource_type: Box<str>,
+    /// `MODIFIED` or `DELETED`.
+    pub status: Box<str>,
+    /// The differing properties with the expected and the actual values.
+    pub differences: Vec<(Box<str>, Box<str>, Box<str>)>,
+}
+
+/// Detects the manual changes of the Trakktor stacks of the current stack
+/// prefix.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn detect_stacks_drift(
+    config: &(impl AwsConfigProvider + CloudFormationStackProvider),
+) -> anyhow::Result<Vec<StackDrift>> {
+    let client = Client::new(config.get_aws_config());
+    let cancel = config.get_cancellation_token();
+    let all_stacks = StackInfo::load_all(&client).await?;
+
+    let mut drifts = vec![];
+    for stack_id in [StackId::Base, StackId::GpuBatch] {
+        let stack_name = stack_id.get_stack_name(config);
+        if !all_stacks.contains_key(&stack_name) {
+            continue;
+        }
+        drifts.push(StackDrift {
+            resources: detect_stack_drift(&client, &stack_name, &cancel)
+                .await?,
+            stack_name,
+        });
+    }
+
+    Ok(drifts)
+}
+
+#[tracing::instrument(level = "debug", skip(client, cancel))]
+async fn detect_stack_drift(
+    client: &Client,
+    stack_name: &str,
+    cancel: &CancellationToken,
+) -> anyhow::Result<Vec<ResourceDrift>> {
+    let detection_id = client
+        .detect_stack_drift()
+        .stack_name(stack_name)
+        .send()
+        .await?
+        .stack_drift_detection_id
+        .ok_or_else(|| anyhow::anyhow!("Drift detection has no ID"))?;
+
+    loop {
+        let status = client
+            .describe_stack_drift_detection_status()
+            .stack_drift_detection_id(&detection_id)
+            .send()
+            .await?;
+        match status.detection_status() {
+            Some(StackDriftDetectionStatus::DetectionComplete) => break,
+            Some(StackDriftDetectionStatus::DetectionFailed) => {
+                anyhow::bail!(
+                    "Drift detection of {stack_name} failed: {}",
+                    status.detection_status_reason().unwrap_or_default()
+                );
+            },
+            status => {
+                tracing::debug!(?status, "Drift detection status");
+                cancellation::sleep(cancel, std::time::Duration::from_secs(5))
+                    .await?;
+            },
+        }
+    }
+
+    Ok(client
+        .describe_stack_resource_drifts()
+        .stack_name(stack_name)
+        .stack_resource_drift_status_filters(StackResourceDriftStatus::Modified)
+        .stack_resource_drift_status_filters(StackResourceDriftStatus::Deleted)
+        .into_paginator()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await?
+        .into_iter()
+        .flat_map(|page| page.stack_resource_drifts.unwrap_or_default())
+        .map(|drift| ResourceDrift {
+            logical_id: drift.logical_resource_id().unwrap_or("").into(),
+            resource_type: drift.resource_type().unwrap_or("").into(),
+            status: drift
+                .stack_resource_drift_status()
+                .map(|s| s.as_str())
+                .unwrap_or("")
+                .into(),
+            differences: drift
+                .property_differences()
+                .iter()
+                .map(|d| {
+                    (
+                        d.property_path().unwrap_or("").into(),
+                        d.expected_value().unwrap_or("").into(),
+                        d.actual_value().unwrap_or("").into(),
+                    )
+                })
+                .collect(),
+        })
+        .collect())
+}
+
 #[derive(Debug)]
 struct StackInfo {
     stack_id: StackId,
     status: StackStatus,
     uid: Box<str>,
-    // This field is intended for recording the version of Trakktor that
-    // created this stack, which can be viewed in the AWS console.
-    #[allow(dead_code)]
+    /// The version of Trakktor that last deployed the stack, which can also
+    /// be viewed in the AWS console.
     version: Box<str>,
     outputs: serde_json::Value,
 }
@@ -327,19 +927,31 @@ pub async fn verify_base_stack_presence(
     let stack_name = config.get_base_stack_name();
     let client = Client::new(config.get_aws_config());
 
+    Ok(get_stack_status(&client, &stack_name).await?.is_some())
+}
+
+/// Get the status of the stack, or `None` if the stack does not exist.
+async fn get_stack_status(
+    client: &Client,
+    stack_name: &str,
+) -> anyhow::Result<Option<StackStatus>> {
     let res = client.describe_stacks().stack_name(stack_name).send().await;
 
     match res {
-        Ok(_) => Ok(true),
+        Ok(res) => Ok(res
+            .stacks
+            .into_iter()
+            .flatten()
+            .next()
+            .and_then(|s| s.stack_status)),
         Err(err) => {
             if err
                 .source()
-                .map(|e| e.source())
-                .flatten()
+                .and_then(|e| e.source())
                 .map(|e| e.to_string().contains("does not exist")) ==
                 Some(true)
             {
-                Ok(false)
+                Ok(None)
             } else {
                 Err(err.into())
             }
@@ -347,6 +959,61 @@ pub async fn verify_base_stack_presence(
     }
 }
 
+/// Deletes the Trakktor stacks of the current stack prefix. Dependent stacks
+/// are deleted first, since they import the outputs of the base stack.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn delete_cloudformation_stacks(
+    config: &(impl AwsConfigProvider + CloudFormationStackProvider),
+) -> anyhow::Result<()> {
+    let client = Client::new(config.get_aws_config());
+    let cancel = config.get_cancellation_token();
+    let all_stacks = StackInfo::load_all(&client).await?;
+
+    for stack_id in [StackId::GpuBatch, StackId::Base] {
+        let stack_name = stack_id.get_stack_name(config);
+        match all_stacks.get(&stack_name) {
+            Some(stack_info) if stack_info.stack_id == stack_id => {},
+            _ => {
+                tracing::debug!(?stack_name, "Stack not found, skipping");
+                continue;
+            },
+        }
+
+        tracing::info!(?stack_name, "Deleting stack");
+        client
+            .delete_stack()
+            .stack_name(stack_name.as_ref())
+            .send()
+            .await?;
+        await_stack_deletion(&client, &stack_name, &cancel).await?;
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(level = "debug", skip(client, cancel))]
+async fn await_stack_deletion(
+    client: &Client,
+    stack_name: &str,
+    cancel: &CancellationToken,
+) -> anyhow::Result<()> {
+    loop {
+        match get_stack_status(client, stack_name).await? {
+            None | Some(StackStatus::DeleteComplete) => break,
+            Some(StackStatus::DeleteFailed) => {
+                anyhow::bail!("Stack deletion failed: {}", stack_name);
+            },
+            Some(status) => {
+                tracing::debug!(?status, "Stack status");
+                cancellation::sleep(cancel, std::time::Duration::from_secs(15))
+                    .await?;
+            },
+        }
+    }
+
+    Ok(())
+}
+
 /// Convert the stack outputs to a JSON object for easier deserialization
 fn outputs_to_json_obj(outputs: Option<Vec<Output>>) -> serde_json::Value {
     serde_json::Value::Object(
@@ -379,7 +1046,10 @@ async fn load_stack_outputs(
         .into_iter()
         .flatten()
         .next()
-        .ok_or_else(|| anyhow::anyhow!("Stack {} not found", stack_name))?
+        .ok_or_else(|| crate::Error::AwsStack {
+            stack: stack_name.to_string(),
+            reason: "not found".into(),
+        })?
         .outputs;
 
     Ok(outputs_to_json_obj(outputs))
@@ -395,17 +1065,22 @@ pub async fn load_gpu_stack_outputs(
     )?)
 }
 
-/// Load all batch job status
+/// Load all batch job status, keyed by the name of the stack owning the
+/// queue.
+///
+/// Stacks whose outputs cannot be read or whose queues cannot be listed are
+/// skipped with a warning, so that a single broken stack in the account does
+/// not prevent listing the jobs of the others.
 #[tracing::instrument(level = "debug", skip_all)]
 pub async fn load_all_batch_jobs(
     config: &(impl AwsConfigProvider + CloudFormationStackProvider),
-) -> anyhow::Result<HashMap<StackId, Vec<JobSummary>>> {
+) -> anyhow::Result<HashMap<Box<str>, Vec<JobSummary>>> {
     let client = Client::new(config.get_aws_config());
     let stacks = StackInfo::load_all(&client).await?;
     let mut batch_queue_names: Vec<Box<str>> = vec![];
-    let mut batch_queue_ids: Vec<StackId> = vec![];
+    let mut batch_stack_names: Vec<Box<str>> = vec![];
     for (
-        _,
+        stack_name,
         StackInfo {
             stack_id, outputs, ..
         },
@@ -414,17 +1089,44 @@ pub async fn load_all_batch_jobs(
         match stack_id {
             StackId::Base => {},
             StackId::GpuBatch => {
-                batch_queue_names.push(
-                    serde_json::from_value::<GpuBatchStackOutputs>(outputs)?
-                        .job_queue
-                        .into(),
-                );
-                batch_queue_ids.push(StackId::GpuBatch);
+                match serde_json::from_value::<GpuBatchStackOutputs>(outputs) {
+                    Ok(outputs) => {
+                        for queue in outputs.get_job_queues() {
+                            batch_queue_names.push(queue.into());
+                            batch_stack_names.push(stack_name.clone());
+                        }
+                    },
+                    Err(err) => {
+                        tracing::warn!(
+                            stack_name = stack_name.as_ref(),
+                            "Skipping stack with unexpected outputs: {err}"
+                        );
+                    },
+                }
             },
         }
     }
 
-    let jobs = batch::load_jobs(config, batch_queue_names).await?;
+    let jobs = batch::load_jobs(config, batch_queue_names).await;
 
-    Ok(batch_queue_ids.into_iter().zip(jobs.into_iter()).collect())
+    Ok(batch_stack_names.into_iter().zip(jobs).fold(
+        HashMap::new(),
+        |mut all_jobs, (stack_name, jobs)| {
+            match jobs {
+                Ok(jobs) => {
+                    all_jobs
+                        .entry(stack_name)
+                        .or_insert_with(Vec::new)
+                        .extend(jobs);
+                },
+                Err(err) => {
+                    tracing::warn!(
+                        stack_name = stack_name.as_ref(),
+                        "Failed to list jobs of the stack: {err}"
+                    );
+                },
+            }
+            all_jobs
+        },
+    ))
 }
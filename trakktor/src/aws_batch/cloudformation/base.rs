@@ -1,10 +1,221 @@
 use askama::Template;
 
+/// S3 requires objects to be stored for 30 days before the transition to
+/// the Standard-IA storage class.
+const MIN_TRANSITION_TO_IA_DAYS: u32 = 30;
+const RETENTION_EXPIRE_OUTPUT: &str = "RetentionExpireAfterDays";
+const RETENTION_TRANSITION_OUTPUT: &str = "RetentionTransitionToIaAfterDays";
+const NOTIFICATION_TARGETS_OUTPUT: &str = "JobNotificationTargets";
+
 #[derive(Template)]
 #[template(path = "cloudformation/base.yaml", escape = "none")]
 struct BaseTemplate<'a, T: std::fmt::Display> {
     subnets: &'a [T],
     s3_storage_name: &'a str,
+    root_prefix: &'a str,
+    retention: &'a RetentionPolicy,
+    stack_prefix: &'a str,
+    notification_targets: &'a [NotificationTarget],
+    key_parameter_dir: &'a str,
+}
+
+/// Lifecycle of the job data stored in the bucket. The rules apply to the
+/// key namespace of the client that initialized the stack.
+#[derive(clap::Args, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Delete the job data this many days after it is uploaded, 0 to keep it
+    /// forever. The current setting is kept if not given.
+    #[arg(long)]
+    pub expire_after_days: Option<u32>,
+    /// Move the job data to the Standard-IA storage class this many days
+    /// after it is uploaded (at least 30), 0 to disable. The current setting
+    /// is kept if not given.
+    #[arg(long)]
+    pub transition_to_ia_after_days: Option<u32>,
+}
+
+impl RetentionPolicy {
+    /// Load the policy the base stack was created with from its outputs.
+    pub fn from_stack_outputs(outputs: &serde_json::Value) -> Self {
+        let days = |key: &str| {
+            outputs
+                .get(key)
+                .and_then(|v| v.as_str())
+                .and_then(|v| v.parse().ok())
+        };
+        RetentionPolicy {
+            expire_after_days: days(RETENTION_EXPIRE_OUTPUT),
+            transition_to_ia_after_days: days(RETENTION_TRANSITION_OUTPUT),
+        }
+    }
+
+    /// Apply the changes on top of the policy. Zero days disable a rule.
+    pub fn merge(&self, changes: &RetentionPolicy) -> anyhow::Result<Self> {
+        let merge = |current: Option<u32>, change: Option<u32>| match change {
+            Some(0) => None,
+            Some(days) => Some(days),
+            None => current,
+        };
+        let policy = RetentionPolicy {
+            expire_after_days: merge(
+                self.expire_after_days,
+                changes.expire_after_days,
+            ),
+            transition_to_ia_after_days: merge(
+                self.transition_to_ia_after_days,
+                changes.transition_to_ia_after_days,
+            ),
+        };
+
+        if let Some(transition) = policy.transition_to_ia_after_days {
+            if transition < MIN_TRANSITION_TO_IA_DAYS {
+                anyhow::bail!(
+                    "The transition to Standard-IA must be at least \
+                     {MIN_TRANSITION_TO_IA_DAYS} days after upload."
+                );
+            }
+            if policy.expire_after_days.is_some_and(|e| e <= transition) {
+                anyhow::bail!(
+                    "The job data must expire after the transition to \
+                     Standard-IA."
+                );
+            }
+        }
+
+        Ok(policy)
+    }
+}
+
+#[test]
+fn retention_policy_test() -> anyhow::Result<()> {
+    let outputs = serde_json::json!({
+        RETENTION_EXPIRE_OUTPUT: "90",
+        "S3StorageBucket": "trakktor-s3-storage",
+    });
+    let current = RetentionPolicy::from_stack_outputs(&outputs);
+    assert_eq!(current.expire_after_days, Some(90));
+    assert_eq!(current.transition_to_ia_after_days, None);
+
+    let changes = RetentionPolicy {
+        expire_after_days: None,
+        transition_to_ia_after_days: Some(30),
+    };
+    let merged = current.merge(&changes)?;
+    assert_eq!(merged.expire_after_days, Some(90));
+    assert_eq!(merged.transition_to_ia_after_days, Some(30));
+
+    let disable = RetentionPolicy {
+        expire_after_days: Some(0),
+        transition_to_ia_after_days: None,
+    };
+    assert_eq!(merged.merge(&disable)?.expire_after_days, None);
+
+    let too_early = RetentionPolicy {
+        expire_after_days: Some(20),
+        transition_to_ia_after_days: None,
+    };
+    assert!(merged.merge(&too_early).is_err());
+    Ok(())
+}
+
+/// An email address or an HTTPS endpoint notified when the jobs submitted
+/// with it finish. The targets are subscribed to an SNS topic of the base
+/// stack, and have to confirm the subscription: a confirmation email is sent
+/// to the addresses, and the endpoints receive a `SubscriptionConfirmation`
+/// request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotificationTarget(Box<str>);
+
+impl NotificationTarget {
+    pub fn parse(target: &str) -> Result<Self, String> {
+        let target = target.trim();
+        let is_safe =
+            |c: char| c.is_ascii_graphic() && !matches!(c, '\'' | '"' | '\\');
+        if !target.chars().all(is_safe) {
+            return Err(format!("Invalid notification target: {target}"));
+        }
+
+        if target.starts_with("https://") {
+            let url = url::Url::parse(target).map_err(|e| e.to_string())?;
+            if url.host_str().is_none() {
+                return Err(format!("No host in the webhook URL: {target}"));
+            }
+        } else {
+            match target.split_once('@') {
+                Some((user, domain))
+                    if !user.is_empty() &&
+                        domain.contains('.') &&
+                        !domain.contains('@') => {},
+                _ => {
+                    return Err(format!(
+                        "Expected an email address or an HTTPS URL: {target}"
+                    ))
+                },
+            }
+        }
+        Ok(Self(target.into()))
+    }
+
+    pub fn as_str(&self) -> &str { &self.0 }
+
+    fn protocol(&self) -> &str {
+        if self.0.starts_with("https://") {
+            "https"
+        } else {
+            "email"
+        }
+    }
+
+    /// The logical ID of the subscription of the target.
+    fn resource_name(&self) -> String {
+        format!(
+            "JobNotification{}",
+            &blake3::hash(self.0.as_bytes()).to_hex()[..16]
+        )
+    }
+
+    /// Load the targets subscribed in the base stack from its outputs.
+    pub fn from_stack_outputs(outputs: &serde_json::Value) -> Vec<Self> {
+        outputs
+            .get(NOTIFICATION_TARGETS_OUTPUT)
+            .and_then(|v| v.as_str())
+            .map(|v| {
+                v.split_whitespace()
+                    .filter_map(|t| Self::parse(t).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for NotificationTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[test]
+fn notification_target_test() {
+    let email = NotificationTarget::parse("me@example.com").unwrap();
+    assert_eq!(email.protocol(), "email");
+    let webhook =
+        NotificationTarget::parse("https://hooks.example.com/trakktor?k=1")
+            .unwrap();
+    assert_eq!(webhook.protocol(), "https");
+    assert_ne!(email.resource_name(), webhook.resource_name());
+
+    assert!(NotificationTarget::parse("http://example.com").is_err());
+    assert!(NotificationTarget::parse("me@example").is_err());
+    assert!(NotificationTarget::parse("it's@example.com").is_err());
+
+    let outputs = serde_json::json!({
+        NOTIFICATION_TARGETS_OUTPUT:
+            "me@example.com https://hooks.example.com/trakktor?k=1",
+    });
+    assert_eq!(
+        NotificationTarget::from_stack_outputs(&outputs),
+        vec![email, webhook]
+    );
 }
 
 pub fn get_s3_storage_name(stack_prefix: &str) -> Box<str> {
@@ -20,10 +231,18 @@ pub fn gen_subnet_names(availability_zone_count: usize) -> Box<[Box<str>]> {
 pub fn gen_cloudformation_template(
     availability_zone_count: usize,
     stack_prefix: &str,
+    root_prefix: &str,
+    retention: &RetentionPolicy,
+    notification_targets: &[NotificationTarget],
 ) -> Box<str> {
     BaseTemplate {
         subnets: &gen_subnet_names(availability_zone_count),
         s3_storage_name: &get_s3_storage_name(stack_prefix),
+        root_prefix,
+        retention,
+        stack_prefix,
+        notification_targets,
+        key_parameter_dir: crate::aws_batch::key_parameter::KEY_PARAMETER_DIR,
     }
     .render()
     .expect("Failed to generate template")
@@ -32,11 +251,17 @@ pub fn gen_cloudformation_template(
 
 #[test]
 fn template_verification_test() {
-    let stack = gen_cloudformation_template(3, "trakktor");
+    let stack = gen_cloudformation_template(
+        3,
+        "trakktor",
+        "",
+        &RetentionPolicy::default(),
+        &[],
+    );
     println!("{}", stack);
 
     assert_eq!(
         crate::hasher::get_hash_value(stack.as_bytes()),
-        "1Kax8wzp3tI20SzwA2VvzyPEsAmnSxnzI7NbTyU7e0U"
+        "5WmxlwaB9o3yraWuaQ_1k-D3x2XyrcqYFB4n9Krshvc"
     )
 }
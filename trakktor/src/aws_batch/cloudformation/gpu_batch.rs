@@ -1,29 +1,278 @@
+use std::collections::HashMap;
+
 use askama::Template;
+use clap::ValueEnum;
 use serde::Deserialize;
+use strum::IntoEnumIterator;
 
 use super::base::gen_subnet_names;
-use crate::aws_batch::whisper;
+use crate::aws_batch::{aligner, indexer, preprocessor, whisper};
 
 #[derive(Template)]
 #[template(path = "cloudformation/gpu_batch.yaml", escape = "none")]
 struct GpuBatchTemplate<'a, T: std::fmt::Display> {
     subnets: &'a [T],
     base_stack_name: &'a str,
-    whisper_large_image_name: &'a str,
+    whisper_jobs: &'a [JobTemplate],
+    /// Jobs of the containers other than Whisper.
+    tool_jobs: &'a [JobTemplate],
+    /// Jobs run in the CPU job queue.
+    cpu_jobs: &'a [JobTemplate],
+    instance_type: &'a str,
+    warm_pool: Option<&'a WarmPool>,
+    /// The vCPUs of an instance, kept by the compute environment in the
+    /// hours of the warm pool.
+    warm_pool_vcpus: u32,
+}
+
+struct JobTemplate {
+    definition_name: &'static str,
+    image_name: Box<str>,
+}
+
+const INSTANCE_TYPE_OUTPUT: &str = "GpuInstanceType";
+const WARM_POOL_OUTPUT: &str = "GpuWarmPool";
+
+/// The GPU instances the jobs run on. The faster instances cost more per
+/// hour, but finish the jobs sooner.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum GpuInstanceType {
+    /// g4dn.xlarge with an NVIDIA T4, the cheapest and the slowest.
+    G4dn,
+    /// g5.xlarge with an NVIDIA A10G.
+    G5,
+    /// g6.xlarge with an NVIDIA L4.
+    #[default]
+    G6,
+    /// p3.2xlarge with an NVIDIA V100.
+    P3,
+}
+
+impl GpuInstanceType {
+    /// The EC2 instance type.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            GpuInstanceType::G4dn => "g4dn.xlarge",
+            GpuInstanceType::G5 => "g5.xlarge",
+            GpuInstanceType::G6 => "g6.xlarge",
+            GpuInstanceType::P3 => "p3.2xlarge",
+        }
+    }
+
+    /// On-demand price of the instance in the us-east-1 region.
+    pub fn get_hourly_price_usd(&self) -> f64 {
+        match self {
+            GpuInstanceType::G4dn => 0.526,
+            GpuInstanceType::G5 => 1.006,
+            GpuInstanceType::G6 => 0.8048,
+            GpuInstanceType::P3 => 3.06,
+        }
+    }
+
+    /// The vCPUs of the instance.
+    pub fn get_vcpus(&self) -> u32 {
+        match self {
+            GpuInstanceType::P3 => 8,
+            _ => 4,
+        }
+    }
+
+    /// Processing time on the instance relative to g6.xlarge.
+    pub fn get_relative_processing_time(&self) -> f64 {
+        match self {
+            GpuInstanceType::G4dn => 1.5,
+            GpuInstanceType::G5 => 0.8,
+            GpuInstanceType::G6 => 1.0,
+            GpuInstanceType::P3 => 0.9,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::value_variants()
+            .iter()
+            .copied()
+            .find(|t| t.get_name() == name)
+    }
+
+    /// Load the instance type the GPU stack was created with from its
+    /// outputs. The stacks created before the type could be chosen run on
+    /// g6.xlarge.
+    pub fn from_stack_outputs(outputs: &serde_json::Value) -> Self {
+        outputs
+            .get(INSTANCE_TYPE_OUTPUT)
+            .and_then(|v| v.as_str())
+            .and_then(Self::from_name)
+            .unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for GpuInstanceType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.get_name())
+    }
+}
+
+#[test]
+fn gpu_instance_type_test() {
+    let outputs = serde_json::json!({
+        INSTANCE_TYPE_OUTPUT: "g5.xlarge",
+        "GpuJobQueue": "queue",
+    });
+    assert_eq!(
+        GpuInstanceType::from_stack_outputs(&outputs),
+        GpuInstanceType::G5
+    );
+    assert_eq!(
+        GpuInstanceType::from_stack_outputs(&serde_json::json!({})),
+        GpuInstanceType::G6
+    );
+}
+
+/// The work hours in which the GPU compute environment keeps an instance
+/// running, so the jobs start without waiting 5-10 minutes for one. It scales
+/// to zero outside of them and on the weekends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WarmPool {
+    pub start_hour: u8,
+    pub end_hour: u8,
+    /// The IANA time zone of the hours, e.g. `Europe/Berlin`.
+    pub timezone: Box<str>,
+}
+
+/// The work days in a month, Monday to Friday.
+const WARM_POOL_DAYS_PER_MONTH: f64 = 21.7;
+
+impl WarmPool {
+    /// Parses the hours with an optional time zone, UTC by default, e.g.
+    /// `9-18` or `9-18@Europe/Berlin`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (hours, timezone) = value
+            .trim()
+            .split_once('@')
+            .unwrap_or((value.trim(), "UTC"));
+        let parse_hour = |hour: &str| {
+            hour.trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|hour| *hour < 24)
+                .ok_or_else(|| format!("Invalid hour: {hour}"))
+        };
+        let (start, end) = hours
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid hours, e.g. 9-18: {hours}"))?;
+        let (start_hour, end_hour) = (parse_hour(start)?, parse_hour(end)?);
+        if start_hour >= end_hour {
+            return Err(format!("The hours end before they start: {hours}"));
+        }
+        let is_safe = |c: char| c.is_ascii_alphanumeric() || "/_+-".contains(c);
+        if timezone.is_empty() || !timezone.chars().all(is_safe) {
+            return Err(format!("Invalid time zone: {timezone}"));
+        }
+        Ok(Self {
+            start_hour,
+            end_hour,
+            timezone: timezone.into(),
+        })
+    }
+
+    /// Load the warm pool the GPU stack was created with from its outputs.
+    pub fn from_stack_outputs(outputs: &serde_json::Value) -> Option<Self> {
+        outputs
+            .get(WARM_POOL_OUTPUT)
+            .and_then(|v| v.as_str())
+            .and_then(|v| Self::parse(v).ok())
+    }
+
+    /// The cost of the instance kept running in the hours, in US dollars a
+    /// month, besides the jobs run on it.
+    pub fn get_monthly_cost_usd(&self, instance_type: GpuInstanceType) -> f64 {
+        f64::from(self.end_hour - self.start_hour) *
+            WARM_POOL_DAYS_PER_MONTH *
+            instance_type.get_hourly_price_usd()
+    }
+}
+
+impl std::fmt::Display for WarmPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}@{}", self.start_hour, self.end_hour, self.timezone)
+    }
+}
+
+/// A change of the warm pool of the GPU stack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WarmPoolChange {
+    Off,
+    Hours(WarmPool),
+}
+
+impl WarmPoolChange {
+    /// Parses `off`, or the hours of [`WarmPool::parse`].
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim() {
+            "off" => Ok(Self::Off),
+            hours => WarmPool::parse(hours).map(Self::Hours),
+        }
+    }
+
+    pub fn into_warm_pool(self) -> Option<WarmPool> {
+        match self {
+            Self::Off => None,
+            Self::Hours(warm_pool) => Some(warm_pool),
+        }
+    }
+}
+
+#[test]
+fn warm_pool_test() {
+    let warm_pool = WarmPool::parse("9-18@Europe/Berlin").unwrap();
+    assert_eq!((warm_pool.start_hour, warm_pool.end_hour), (9, 18));
+    assert_eq!(warm_pool.to_string(), "9-18@Europe/Berlin");
+    assert_eq!(WarmPool::parse("8-17").unwrap().timezone.as_ref(), "UTC");
+    assert!(WarmPool::parse("18-9").is_err());
+    assert!(WarmPool::parse("9-18@Europe/Berlin'").is_err());
+    assert_eq!(WarmPoolChange::parse("off"), Ok(WarmPoolChange::Off));
+
+    let outputs = serde_json::json!({ WARM_POOL_OUTPUT: "9-18@UTC" });
+    assert_eq!(
+        WarmPool::from_stack_outputs(&outputs),
+        Some(WarmPool::parse("9-18").unwrap())
+    );
+    assert_eq!(WarmPool::from_stack_outputs(&serde_json::json!({})), None);
 }
 
 pub fn gen_gpu_batch_template(
     availability_zone_count: usize,
     base_stack_name: &str,
     is_dev: bool,
+    instance_type: GpuInstanceType,
+    warm_pool: Option<&WarmPool>,
 ) -> Box<str> {
     GpuBatchTemplate {
         subnets: &gen_subnet_names(availability_zone_count),
         base_stack_name,
-        whisper_large_image_name: &whisper::make_image_name(
-            whisper::Model::Large,
-            is_dev,
-        ),
+        whisper_jobs: &whisper::Model::iter()
+            .map(|model| JobTemplate {
+                definition_name: model.get_job_definition_name(),
+                image_name: whisper::make_image_name(model, is_dev).into(),
+            })
+            .collect::<Vec<_>>(),
+        tool_jobs: &[
+            JobTemplate {
+                definition_name: indexer::JOB_DEFINITION_NAME,
+                image_name: indexer::make_image_name(is_dev).into(),
+            },
+            JobTemplate {
+                definition_name: aligner::JOB_DEFINITION_NAME,
+                image_name: aligner::make_image_name(is_dev).into(),
+            },
+        ],
+        cpu_jobs: &[JobTemplate {
+            definition_name: preprocessor::JOB_DEFINITION_NAME,
+            image_name: preprocessor::make_image_name(is_dev).into(),
+        }],
+        instance_type: instance_type.get_name(),
+        warm_pool,
+        warm_pool_vcpus: instance_type.get_vcpus(),
     }
     .render()
     .expect("Failed to generate template")
@@ -32,19 +281,117 @@ pub fn gen_gpu_batch_template(
 
 #[test]
 fn template_verification_test() {
-    let stack = gen_gpu_batch_template(3, "trakktor-net", true);
+    let stack = gen_gpu_batch_template(
+        3,
+        "trakktor-net",
+        true,
+        GpuInstanceType::default(),
+        None,
+    );
     println!("{}", stack);
 
     assert_eq!(
         crate::hasher::get_hash_value(stack.as_bytes()),
-        "U8gmQTMfHG_94BaZQAhgqzeukCMdyOzlhDqRP0ahrBk"
-    )
+        "wZIy6vsJr_bAmuHXs7fTaV5-cL9Trqi_CqI_XTFKX2o"
+    );
+
+    let stack = gen_gpu_batch_template(
+        3,
+        "trakktor-net",
+        true,
+        GpuInstanceType::default(),
+        Some(&WarmPool::parse("9-18@Europe/Berlin").unwrap()),
+    );
+    assert!(stack.contains("cron(0 9 ? * MON-FRI *)"));
+    assert!(stack.contains(r#""MinvCpus": 4}}'"#));
+    assert!(stack.contains("Value: '9-18@Europe/Berlin'"));
 }
 
 #[derive(Debug, Deserialize)]
 pub struct GpuBatchStackOutputs {
     #[serde(rename = "GpuJobQueue")]
     pub job_queue: String,
-    #[serde(rename = "GpuWhisperLargeJob")]
-    pub whisper_large_job: String,
+    /// The stacks created before preprocessing was added have no CPU queue.
+    #[serde(rename = "CpuJobQueue", default)]
+    cpu_job_queue: Option<String>,
+    #[serde(rename = "GpuInstanceType", default)]
+    instance_type: Option<String>,
+
+    #[serde(flatten)]
+    job_definitions: HashMap<String, String>,
+}
+
+impl GpuBatchStackOutputs {
+    pub fn get_instance_type(&self) -> GpuInstanceType {
+        self.instance_type
+            .as_deref()
+            .and_then(GpuInstanceType::from_name)
+            .unwrap_or_default()
+    }
+
+    pub fn get_cpu_job_queue(&self) -> anyhow::Result<&str> {
+        self.cpu_job_queue.as_deref().ok_or_else(|| {
+            anyhow::anyhow!(
+                "CPU job queue not found in the stack, reinitialize the stacks"
+            )
+        })
+    }
+
+    /// The job queues of the stack.
+    pub fn get_job_queues(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.job_queue.as_str())
+            .chain(self.cpu_job_queue.as_deref())
+    }
+
+    pub fn get_whisper_job_definition(
+        &self,
+        model: whisper::Model,
+    ) -> anyhow::Result<&str> {
+        self.job_definitions
+            .get(model.get_job_definition_name())
+            .map(String::as_str)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Job definition for model {} not found in the stack, \
+                     reinitialize the stacks",
+                    model
+                )
+            })
+    }
+
+    pub fn get_index_job_definition(&self) -> anyhow::Result<&str> {
+        self.job_definitions
+            .get(indexer::JOB_DEFINITION_NAME)
+            .map(String::as_str)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Indexing job definition not found in the stack, \
+                     reinitialize the stacks"
+                )
+            })
+    }
+
+    pub fn get_align_job_definition(&self) -> anyhow::Result<&str> {
+        self.job_definitions
+            .get(aligner::JOB_DEFINITION_NAME)
+            .map(String::as_str)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Alignment job definition not found in the stack, \
+                     reinitialize the stacks"
+                )
+            })
+    }
+
+    pub fn get_preprocess_job_definition(&self) -> anyhow::Result<&str> {
+        self.job_definitions
+            .get(preprocessor::JOB_DEFINITION_NAME)
+            .map(String::as_str)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Preprocessing job definition not found in the stack, \
+                     reinitialize the stacks"
+                )
+            })
+    }
 }
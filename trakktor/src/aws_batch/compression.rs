@@ -0,0 +1,53 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+/// Extension appended to the names of the zstd compressed objects.
+pub const ZSTD_EXTENSION: &str = ".zst";
+
+/// Returns the name of the object before compression, or `None` if the
+/// object is not compressed.
+pub fn strip_compressed_ext(name: &str) -> Option<&str> {
+    name.strip_suffix(ZSTD_EXTENSION)
+}
+
+/// Returns the object name as it is shown to the user.
+pub fn display_name(name: &str) -> &str {
+    strip_compressed_ext(name).unwrap_or(name)
+}
+
+/// Decompress a zstd compressed file next to it and remove the compressed
+/// file. Returns the path of the decompressed file.
+///
+/// The `zstd` command line tool is used, so it has to be installed.
+#[tracing::instrument(level = "debug")]
+pub async fn decompress_file(path: &Path) -> anyhow::Result<PathBuf> {
+    let out_path = path
+        .to_str()
+        .and_then(strip_compressed_ext)
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            anyhow::anyhow!("Not a compressed file: {}", path.display())
+        })?;
+
+    let status = tokio::process::Command::new("zstd")
+        .args(["-d", "-q", "-f", "--rm", "-o"])
+        .arg(&out_path)
+        .arg(path)
+        .status()
+        .await
+        .context("Failed to run zstd, is it installed?")?;
+    if !status.success() {
+        anyhow::bail!("zstd failed to decompress {}: {status}", path.display());
+    }
+
+    Ok(out_path)
+}
+
+#[test]
+fn strip_compressed_ext_test() {
+    assert_eq!(strip_compressed_ext("audio.json.zst"), Some("audio.json"));
+    assert_eq!(strip_compressed_ext("audio.json"), None);
+    assert_eq!(display_name("audio.srt.zst"), "audio.srt");
+    assert_eq!(display_name("audio.srt"), "audio.srt");
+}
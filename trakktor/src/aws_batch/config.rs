@@ -1,9 +1,24 @@
 use aws_config::SdkConfig;
 
+use crate::{app_config::Limits, cancellation::CancellationToken};
+
 pub trait AwsConfigProvider {
     fn get_aws_config(&self) -> &SdkConfig;
+
+    /// The limits of the concurrent transfers and requests.
+    fn get_limits(&self) -> Limits { Limits::default() }
+
+    /// The token cancelling the long-running operations of the caller:
+    /// transfers and waits for AWS. Cancelled operations fail with
+    /// [`crate::cancellation::Cancelled`].
+    fn get_cancellation_token(&self) -> CancellationToken {
+        CancellationToken::new()
+    }
 }
 
+/// The prefix of the stack names if none is configured.
+pub const DEFAULT_STACK_PREFIX: &str = "trakktor";
+
 pub trait CloudFormationStackProvider {
     fn get_stack_prefix(&self) -> &str;
 
@@ -14,8 +29,107 @@ pub trait CloudFormationStackProvider {
     fn get_gpu_batch_stack_name(&self) -> Box<str> {
         format!("{}-gpu-batch", self.get_stack_prefix()).into()
     }
+
+    /// Changes of the retention policy of the job data to apply to the base
+    /// stack, if any.
+    fn get_retention_changes(
+        &self,
+    ) -> Option<&super::cloudformation::RetentionPolicy> {
+        None
+    }
+
+    /// The target to notify when the submitted jobs finish, if any. It is
+    /// subscribed to the notifications in the base stack.
+    fn get_notification_target(
+        &self,
+    ) -> Option<&super::cloudformation::NotificationTarget> {
+        None
+    }
+
+    /// Called before an existing stack is updated to new templates, the
+    /// update is cancelled unless it is approved. All the updates are
+    /// approved by default.
+    fn approve_stack_update(
+        &self,
+        _update: &super::cloudformation::StackUpdate,
+    ) -> anyhow::Result<bool> {
+        Ok(true)
+    }
+
+    /// The instance type to switch the GPU compute environment to, if any.
+    fn get_gpu_instance_type(
+        &self,
+    ) -> Option<super::cloudformation::GpuInstanceType> {
+        None
+    }
+
+    /// The change of the warm pool of the GPU compute environment, if any.
+    fn get_gpu_warm_pool_change(
+        &self,
+    ) -> Option<&super::cloudformation::WarmPoolChange> {
+        None
+    }
 }
 
 pub trait S3Provider {
     fn get_bucket_name(&self) -> &str;
+
+    /// The prefix under which all Trakktor objects are stored in the bucket.
+    /// It is either empty or ends with a `/`.
+    fn get_root_prefix(&self) -> &str { "" }
+
+    /// Receiver of the progress of uploads and downloads, if any.
+    fn get_transfer_progress(
+        &self,
+    ) -> Option<std::sync::Arc<dyn super::s3::TransferProgress>> {
+        None
+    }
+
+    /// The key used to encrypt the job data on the client side, if any.
+    fn get_encryption_key(&self) -> Option<&super::encryption::EncryptionKey> {
+        None
+    }
+
+    /// The record of the jobs whose inputs are being uploaded, see
+    /// [`super::pending_uploads`]. The interrupted uploads are not resumed
+    /// if not set.
+    fn get_pending_uploads_path(&self) -> Option<std::path::PathBuf> { None }
+}
+
+/// Parses a user provided root prefix (e.g. `trakktor/v1`), so it can be used
+/// as a key namespace: leading slashes are removed and a trailing slash is
+/// added. Only characters that are safe in S3 keys are allowed.
+pub fn parse_root_prefix(prefix: &str) -> Result<Box<str>, String> {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        return Ok("".into());
+    }
+
+    for segment in prefix.split('/') {
+        if segment.is_empty() || segment == "." || segment == ".." {
+            return Err(format!("Invalid segment in S3 prefix: '{segment}'"));
+        }
+        if let Some(c) = segment
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || "!-_.*'()".contains(*c)))
+        {
+            return Err(format!("Invalid character in S3 prefix: '{c}'"));
+        }
+    }
+
+    Ok(format!("{prefix}/").into())
+}
+
+#[test]
+fn parse_root_prefix_test() {
+    assert_eq!(parse_root_prefix("").unwrap().as_ref(), "");
+    assert_eq!(parse_root_prefix("/").unwrap().as_ref(), "");
+    assert_eq!(parse_root_prefix("trakktor").unwrap().as_ref(), "trakktor/");
+    assert_eq!(
+        parse_root_prefix("/trakktor/v1/").unwrap().as_ref(),
+        "trakktor/v1/"
+    );
+    assert!(parse_root_prefix("trakktor//v1").is_err());
+    assert!(parse_root_prefix("trakktor/../v1").is_err());
+    assert!(parse_root_prefix("trakktor v1").is_err());
 }
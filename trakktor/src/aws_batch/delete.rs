@@ -5,30 +5,33 @@ use tracing::{info_span, Instrument};
 
 use crate::aws_batch::{
     config::{AwsConfigProvider, S3Provider},
-    job::JobUid,
+    job::{make_job_prefix, JobSelector, JobUid},
     s3::delete_dir,
+    select::resolve_job_selectors,
 };
 
 #[derive(clap::Args, Debug)]
 pub struct DeleteArgs {
-    /// List of job IDs to delete.
-    pub job_ids: Vec<String>,
+    /// Jobs to delete, by ID, name, or tag (`tag:<tag>`).
+    #[arg(value_parser = JobSelector::parse_job_selector)]
+    pub jobs: Vec<JobSelector>,
 }
 
-const PARALLEL_REQS: usize = 8;
-
 #[tracing::instrument(level = "debug", skip_all)]
 pub async fn do_delete(
     config: Arc<impl AwsConfigProvider + S3Provider + Sync + Send + 'static>,
     args: &DeleteArgs,
 ) -> anyhow::Result<()> {
-    let jids: Vec<JobUid> = args
-        .job_ids
-        .iter()
-        .map(|j| JobUid::parse_job_uid(j).map_err(|e| anyhow::anyhow!(e)))
-        .collect::<Result<Vec<JobUid>, _>>()?;
+    let jids = resolve_job_selectors(&*config, &args.jobs).await?;
+    delete_jobs(config, jids).await
+}
 
-    let par_sem = Arc::new(Semaphore::new(PARALLEL_REQS));
+/// Delete all the objects of the jobs.
+pub async fn delete_jobs(
+    config: Arc<impl AwsConfigProvider + S3Provider + Sync + Send + 'static>,
+    jids: Vec<JobUid>,
+) -> anyhow::Result<()> {
+    let par_sem = Arc::new(Semaphore::new(config.get_limits().aws_requests));
 
     let mut reqs: Vec<JoinHandle<anyhow::Result<()>>> = Vec::new();
 
@@ -40,7 +43,11 @@ pub async fn do_delete(
             async move {
                 let _permit = par_sem.acquire().await?;
                 tracing::info!("deleting...");
-                delete_dir(&*config, d.as_ref()).await?;
+                delete_dir(
+                    &*config,
+                    &make_job_prefix(config.get_root_prefix(), &d),
+                )
+                .await?;
                 Ok(())
             }
             .instrument(span)
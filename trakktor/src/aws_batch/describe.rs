@@ -0,0 +1,145 @@
+use crate::aws_batch::{
+    batch::{describe_job, load_failed_children},
+    cloudformation::load_all_batch_jobs,
+    config::{AwsConfigProvider, CloudFormationStackProvider, S3Provider},
+    job::{
+        make_job_prefix, parse_array_error_report, JobErrorReport, JobSelector,
+        JOB_ERROR_REPORT,
+    },
+    s3::{get_object, list_objects},
+    select::resolve_single_job,
+};
+
+const IND: &str = "    ";
+
+#[derive(clap::Args, Debug)]
+pub struct DescribeArgs {
+    /// Job to describe, by ID, name, or tag (`tag:<tag>`).
+    #[arg(value_parser = JobSelector::parse_job_selector)]
+    pub job: JobSelector,
+}
+
+/// Prints why the job failed: the error reports its containers wrote, and
+/// the status of the job and of its attempts in AWS Batch.
+#[tracing::instrument(level = "info", skip(config))]
+pub async fn describe_job_failure(
+    config: &(impl AwsConfigProvider + S3Provider + CloudFormationStackProvider),
+    args: &DescribeArgs,
+) -> anyhow::Result<()> {
+    let job_id = resolve_single_job(config, &args.job).await?;
+    let job_prefix = make_job_prefix(config.get_root_prefix(), &job_id);
+    let objs = list_objects(config, &job_prefix).await?.collect::<Vec<_>>();
+    if objs.is_empty() {
+        anyhow::bail!("Job not found.");
+    }
+
+    println!("- {job_id}");
+
+    let mut reports = objs
+        .iter()
+        .filter_map(|o| {
+            let rest = o.strip_prefix(job_prefix.as_ref())?;
+            if rest == JOB_ERROR_REPORT {
+                Some((None, o))
+            } else {
+                Some((Some(parse_array_error_report(rest)?), o))
+            }
+        })
+        .collect::<Vec<_>>();
+    reports.sort();
+    if reports.is_empty() {
+        println!("{IND}No error reports.");
+    }
+    for (index, key) in reports {
+        let Some(data) = get_object(config, key).await? else {
+            continue;
+        };
+        match serde_json::from_slice::<JobErrorReport>(&data) {
+            Ok(report) => print_report(index, &report),
+            Err(err) => tracing::warn!("Skipping invalid report {key}: {err}"),
+        }
+    }
+
+    let summary = load_all_batch_jobs(config)
+        .await?
+        .into_values()
+        .flatten()
+        .find(|j| j.job_name.as_deref() == Some(job_id.as_ref()));
+    let Some(batch_job_id) = summary.and_then(|s| s.job_id) else {
+        println!("{IND}The job is no longer in AWS Batch.");
+        return Ok(());
+    };
+    let Some(detail) = describe_job(config, &batch_job_id).await? else {
+        println!("{IND}The job is no longer in AWS Batch.");
+        return Ok(());
+    };
+
+    println!("{IND}AWS Batch:");
+    println!("{IND}{IND}job: {batch_job_id}");
+    if let Some(status) = &detail.status {
+        println!("{IND}{IND}status: {status}");
+    }
+    if let Some(reason) = &detail.status_reason {
+        println!("{IND}{IND}reason: {reason}");
+    }
+    for (i, attempt) in detail.attempts().iter().enumerate() {
+        let Some(container) = &attempt.container else {
+            continue;
+        };
+        println!(
+            "{IND}{IND}attempt {}: {}",
+            i + 1,
+            attempt.status_reason.as_deref().unwrap_or("-")
+        );
+        if let Some(code) = container.exit_code {
+            println!("{IND}{IND}{IND}exit code: {code}");
+        }
+        if let Some(reason) = &container.reason {
+            println!("{IND}{IND}{IND}container: {reason}");
+        }
+        if let Some(stream) = &container.log_stream_name {
+            println!("{IND}{IND}{IND}log stream: {stream}");
+        }
+    }
+
+    if detail.array_properties.is_some() {
+        let children = load_failed_children(config, &batch_job_id).await?;
+        for child in children {
+            let index = child
+                .array_properties
+                .as_ref()
+                .and_then(|p| p.index)
+                .map(|i| i.to_string())
+                .unwrap_or_else(|| "?".to_string());
+            println!(
+                "{IND}{IND}child {index}: {}",
+                child.status_reason.as_deref().unwrap_or("-")
+            );
+            if let Some(reason) =
+                child.container.as_ref().and_then(|c| c.reason.as_deref())
+            {
+                println!("{IND}{IND}{IND}container: {reason}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_report(index: Option<u32>, report: &JobErrorReport) {
+    match index {
+        Some(index) => println!("{IND}Error report of child {index}:"),
+        None => println!("{IND}Error report:"),
+    }
+    println!("{IND}{IND}stage: {:?}", report.stage);
+    if let Some(file) = &report.input_file {
+        println!("{IND}{IND}file: {file}");
+    }
+    println!("{IND}{IND}error: {}", report.message);
+    if !report.stderr_tail.is_empty() {
+        println!("{IND}{IND}stderr:");
+        for line in &report.stderr_tail {
+            println!("{IND}{IND}{IND}{line}");
+        }
+    }
+}
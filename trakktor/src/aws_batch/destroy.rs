@@ -0,0 +1,26 @@
+use crate::aws_batch::{
+    cloudformation::{
+        delete_cloudformation_stacks, verify_base_stack_presence,
+    },
+    config::{AwsConfigProvider, CloudFormationStackProvider, S3Provider},
+    s3::delete_dir,
+};
+
+/// Removes all the job data and the infrastructure created by Trakktor.
+#[tracing::instrument(level = "info", skip_all)]
+pub async fn destroy_all(
+    config: &(impl AwsConfigProvider + S3Provider + CloudFormationStackProvider),
+) -> anyhow::Result<()> {
+    // The bucket belongs to the base stack and must be empty before the stack
+    // can be deleted.
+    if verify_base_stack_presence(config).await? {
+        tracing::info!("Emptying the storage bucket.");
+        delete_dir(config, "").await?;
+    }
+
+    delete_cloudformation_stacks(config).await?;
+
+    tracing::info!("All Trakktor stacks have been deleted.");
+
+    Ok(())
+}
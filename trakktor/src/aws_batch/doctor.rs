@@ -0,0 +1,111 @@
+use clap::ValueEnum;
+
+use crate::{
+    aws_batch::{
+        cloudformation::{verify_base_stack_presence, GpuInstanceType},
+        config::{AwsConfigProvider, CloudFormationStackProvider},
+        ec2::get_offered_instance_types,
+    },
+    doctor::{Check, CheckStatus},
+};
+
+/// Checks the AWS credentials, whether the stacks are deployed, and which
+/// GPU instance types the region offers. The vCPU quotas of the account are
+/// not checked, a job waiting for capacity shows them in the AWS Batch
+/// console.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn check_aws(
+    config: &(impl AwsConfigProvider + CloudFormationStackProvider),
+) -> Vec<Check> {
+    let aws_config = config.get_aws_config();
+    let mut checks = vec![];
+
+    let Some(region) = aws_config.region() else {
+        checks.push(Check::new(
+            "AWS region",
+            CheckStatus::Fail,
+            "No region is set, use --region or the config file",
+        ));
+        return checks;
+    };
+    checks.push(Check::new("AWS region", CheckStatus::Pass, region.as_ref()));
+
+    let identity = aws_sdk_sts::Client::new(aws_config)
+        .get_caller_identity()
+        .send()
+        .await;
+    match identity {
+        Ok(identity) => checks.push(Check::new(
+            "AWS credentials",
+            CheckStatus::Pass,
+            format!(
+                "account {}, {}",
+                identity.account().unwrap_or_default(),
+                identity.arn().unwrap_or_default()
+            ),
+        )),
+        Err(err) => {
+            checks.push(Check::new(
+                "AWS credentials",
+                CheckStatus::Fail,
+                format!("{:#}", anyhow::Error::from(err)),
+            ));
+            return checks;
+        },
+    }
+
+    checks.push(match verify_base_stack_presence(config).await {
+        Ok(true) => Check::new(
+            "AWS stacks",
+            CheckStatus::Pass,
+            format!("{} is deployed", config.get_base_stack_name()),
+        ),
+        Ok(false) => Check::new(
+            "AWS stacks",
+            CheckStatus::Warn,
+            format!(
+                "{} is not deployed, run `aws-batch initialize`",
+                config.get_base_stack_name()
+            ),
+        ),
+        Err(err) => {
+            Check::new("AWS stacks", CheckStatus::Fail, format!("{err:#}"))
+        },
+    });
+
+    let instance_types = GpuInstanceType::value_variants()
+        .iter()
+        .map(GpuInstanceType::get_name)
+        .collect::<Vec<_>>();
+    checks.push(
+        match get_offered_instance_types(config, &instance_types).await {
+            Ok(offered) => {
+                let missing = instance_types
+                    .iter()
+                    .filter(|t| !offered.iter().any(|o| o == *t))
+                    .copied()
+                    .collect::<Vec<_>>();
+                let status = if missing.is_empty() {
+                    CheckStatus::Pass
+                } else if missing.len() == instance_types.len() {
+                    CheckStatus::Fail
+                } else {
+                    CheckStatus::Warn
+                };
+                let detail = if missing.is_empty() {
+                    "all offered in the region".to_owned()
+                } else {
+                    format!("not offered in the region: {}", missing.join(", "))
+                };
+                Check::new("GPU instance types", status, detail)
+            },
+            Err(err) => Check::new(
+                "GPU instance types",
+                CheckStatus::Fail,
+                format!("{err:#}"),
+            ),
+        },
+    );
+
+    checks
+}
@@ -1,17 +1,36 @@
-use crate::aws_batch::{
-    config::{AwsConfigProvider, S3Provider},
-    job::{make_output_storage_prefix, JobUid, JOB_DONE_FLAG},
-    s3::{download_folder, list_objects},
+use std::path::Path;
+
+use crate::{
+    aws_batch::{
+        compression::{decompress_file, display_name, strip_compressed_ext},
+        config::{AwsConfigProvider, S3Provider},
+        job::{
+            is_job_done, make_job_prefix, make_output_storage_prefix, JobInfo,
+            JobSelector,
+        },
+        parts::stitch_outputs,
+        s3::{download_folder, list_objects},
+        select::resolve_single_job,
+    },
+    dialogue::{parse_dialogue, to_dialogue_markdown},
 };
 
 #[derive(clap::Args, Debug)]
 pub struct DownloadArgs {
-    /// Job ID to download.
-    #[arg(value_parser = JobUid::parse_job_uid)]
-    pub job_id: JobUid,
+    /// Job to download, by ID, name, or tag (`tag:<tag>`).
+    #[arg(value_parser = JobSelector::parse_job_selector)]
+    pub job: JobSelector,
     /// Directory to download to. If not specified, the current directory is
     /// used.
     pub out_path: Option<std::path::PathBuf>,
+    /// Keep the compressed outputs as they are stored instead of
+    /// decompressing them.
+    #[arg(long)]
+    pub keep_compressed: bool,
+    /// Also write the text transcripts with speaker labels, e.g.
+    /// `Jane: Hello`, as dialogue Markdown (`**Jane:** Hello`) next to them.
+    #[arg(long)]
+    pub dialogue: bool,
 }
 
 #[tracing::instrument(level = "info", skip(config))]
@@ -19,9 +38,10 @@ pub async fn download_job_result(
     config: &(impl AwsConfigProvider + S3Provider),
     args: &DownloadArgs,
 ) -> anyhow::Result<()> {
-    let objs = list_objects(config, &args.job_id.to_string())
-        .await?
-        .collect::<Vec<_>>();
+    let job_id = resolve_single_job(config, &args.job).await?;
+    let root_prefix = config.get_root_prefix();
+    let job_prefix = make_job_prefix(root_prefix, &job_id);
+    let objs = list_objects(config, &job_prefix).await?.collect::<Vec<_>>();
 
     if objs.is_empty() {
         anyhow::bail!("Job not found.");
@@ -29,21 +49,83 @@ pub async fn download_job_result(
 
     tracing::debug!(?objs, "Listed objects.");
 
-    if !objs.iter().any(|i| i.ends_with(JOB_DONE_FLAG)) {
+    if !is_job_done(
+        objs.iter()
+            .filter_map(|o| o.strip_prefix(job_prefix.as_ref())),
+    ) {
         anyhow::bail!("Job not finished yet.");
     }
+    let parts = objs
+        .iter()
+        .filter_map(|o| o.strip_prefix(job_prefix.as_ref()))
+        .find_map(|o| JobInfo::deserialize(o).ok())
+        .and_then(|info| info.parts);
+
+    let pfx = make_output_storage_prefix(root_prefix, &job_id);
+    let out_path = args
+        .out_path
+        .as_deref()
+        .unwrap_or(std::path::Path::new("."));
+    let out_objs = objs
+        .into_iter()
+        .filter(|o| o.starts_with(pfx.as_ref()))
+        .collect::<Vec<_>>();
+    let mut files = out_objs
+        .iter()
+        .filter_map(|o| o.strip_prefix(pfx.as_ref()))
+        .filter(|o| !args.keep_compressed || strip_compressed_ext(o).is_none())
+        .map(|o| out_path.join(display_name(o)))
+        .collect::<Vec<_>>();
+    let compressed = out_objs
+        .iter()
+        .filter_map(|o| o.strip_prefix(pfx.as_ref()))
+        .filter(|o| strip_compressed_ext(o).is_some())
+        .map(|o| out_path.join(o))
+        .collect::<Vec<_>>();
+
+    download_folder(config, out_objs, &pfx, out_path).await?;
+
+    if !args.keep_compressed {
+        for file in &compressed {
+            decompress_file(file).await?;
+        }
+    }
 
-    let pfx = make_output_storage_prefix(&args.job_id);
+    // The transcripts of the parts of a split file are stitched into the
+    // transcripts of the whole file.
+    if let Some(parts) = parts {
+        if args.keep_compressed && !compressed.is_empty() {
+            tracing::warn!(
+                "The outputs of the parts are kept compressed, they are not \
+                 stitched."
+            );
+        } else {
+            files = stitch_outputs(out_path, files, parts).await?;
+        }
+    }
 
-    download_folder(
-        config,
-        objs.into_iter().filter(|o| o.starts_with(pfx.as_ref())),
-        &pfx,
-        args.out_path
-            .as_deref()
-            .unwrap_or(std::path::Path::new(".")),
-    )
-    .await?;
+    if args.dialogue {
+        let texts = files
+            .iter()
+            .filter(|path| path.extension().is_some_and(|ext| ext == "txt"));
+        for file in texts {
+            write_dialogue(file).await?;
+        }
+    }
+
+    Ok(())
+}
 
+/// Writes the transcript as dialogue Markdown next to it, if it has speaker
+/// labels.
+async fn write_dialogue(file: &Path) -> anyhow::Result<()> {
+    let text = tokio::fs::read_to_string(file).await?;
+    let Some(turns) = parse_dialogue(&text) else {
+        tracing::warn!("No speaker turns found in: {}", file.display());
+        return Ok(());
+    };
+    let dialogue_file = file.with_extension("dialogue.md");
+    tokio::fs::write(&dialogue_file, to_dialogue_markdown(&turns)).await?;
+    tracing::info!("Wrote the dialogue to: {}", dialogue_file.display());
     Ok(())
 }
@@ -21,3 +21,30 @@ pub async fn get_availability_zone_count(
 
     Ok(count)
 }
+
+/// Get the instance types of the list offered in the region.
+#[tracing::instrument(level = "debug", skip(aws_cfg_provider))]
+pub async fn get_offered_instance_types(
+    aws_cfg_provider: &impl AwsConfigProvider,
+    instance_types: &[&str],
+) -> anyhow::Result<Vec<String>> {
+    let client = aws_sdk_ec2::Client::new(aws_cfg_provider.get_aws_config());
+    let resp = client
+        .describe_instance_type_offerings()
+        .filters(
+            aws_sdk_ec2::types::Filter::builder()
+                .name("instance-type")
+                .set_values(Some(
+                    instance_types.iter().map(|t| t.to_string()).collect(),
+                ))
+                .build(),
+        )
+        .send()
+        .await?;
+
+    Ok(resp
+        .instance_type_offerings()
+        .iter()
+        .filter_map(|o| o.instance_type().map(|t| t.as_str().to_owned()))
+        .collect())
+}
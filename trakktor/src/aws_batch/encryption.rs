@@ -0,0 +1,180 @@
+//! Client-side encryption of the job data.
+//!
+//! Objects are encrypted with AES-256-GCM in chunks, so they can be
+//! streamed. Each object gets its own key derived with HKDF-SHA256 from the
+//! user key and a random salt, which is stored in the object metadata. The
+//! nonce of a chunk is its index, and the last chunk is marked in the
+//! associated data, so chunks can not be reordered or dropped.
+//!
+//! The same format is implemented by `whisper/crypt.py` in the container.
+
+use std::{path::Path, sync::Arc};
+
+use anyhow::{anyhow, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    hkdf,
+    rand::{SecureRandom, SystemRandom},
+};
+
+/// Name of the object metadata entry holding the encryption parameters.
+pub const ENCRYPTION_METADATA: &str = "trk-enc";
+const FORMAT_VERSION: &str = "v1";
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 32;
+const KDF_INFO: &[u8] = b"trakktor-chunk";
+/// Size of the authentication tag added to each chunk.
+pub const TAG_LEN: usize = 16;
+
+/// The user key all the object keys are derived from.
+#[derive(Clone)]
+pub struct EncryptionKey(Arc<[u8; KEY_LEN]>);
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+impl EncryptionKey {
+    /// Parse a base64 encoded 256-bit key.
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let bytes = STANDARD
+            .decode(s.trim())
+            .context("The encryption key must be base64 encoded")?;
+        let key: [u8; KEY_LEN] = bytes.try_into().map_err(|_| {
+            anyhow!("The encryption key must be {KEY_LEN} bytes long")
+        })?;
+        Ok(EncryptionKey(Arc::new(key)))
+    }
+
+    /// Load the key from a file containing it base64 encoded.
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        let data =
+            tokio::fs::read_to_string(path).await.with_context(|| {
+                format!("Failed to read the encryption key: {}", path.display())
+            })?;
+        Self::parse(&data)
+    }
+
+    pub fn to_base64(&self) -> String { STANDARD.encode(self.0.as_ref()) }
+
+    /// Identifies the key without revealing it.
+    pub fn fingerprint(&self) -> String {
+        let hash = blake3::derive_key("trakktor key fingerprint", &*self.0);
+        hash[..8].iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+/// Encrypts or decrypts the chunks of a single object.
+pub struct ObjectCipher {
+    key: LessSafeKey,
+    salt: [u8; SALT_LEN],
+}
+
+impl ObjectCipher {
+    /// Create a cipher for a new object.
+    pub fn new(key: &EncryptionKey) -> anyhow::Result<Self> {
+        let mut salt = [0; SALT_LEN];
+        SystemRandom::new()
+            .fill(&mut salt)
+            .map_err(|_| anyhow!("Failed to generate a salt"))?;
+        Ok(Self::with_salt(key, salt))
+    }
+
+    /// Create a cipher for an existing object from its metadata value.
+    pub fn from_metadata(
+        key: &EncryptionKey,
+        metadata: &str,
+    ) -> anyhow::Result<Self> {
+        let salt = metadata
+            .strip_prefix(FORMAT_VERSION)
+            .and_then(|s| s.strip_prefix(':'))
+            .ok_or_else(|| anyhow!("Unsupported encryption format"))?;
+        let salt: [u8; SALT_LEN] = STANDARD
+            .decode(salt)?
+            .try_into()
+            .map_err(|_| anyhow!("Invalid encryption salt"))?;
+        Ok(Self::with_salt(key, salt))
+    }
+
+    fn with_salt(key: &EncryptionKey, salt: [u8; SALT_LEN]) -> Self {
+        let prk =
+            hkdf::Salt::new(hkdf::HKDF_SHA256, &salt).extract(key.0.as_ref());
+        let okm = prk
+            .expand(&[KDF_INFO], &AES_256_GCM)
+            .expect("Invalid HKDF output length");
+        ObjectCipher {
+            key: LessSafeKey::new(UnboundKey::from(okm)),
+            salt,
+        }
+    }
+
+    /// Value of the [`ENCRYPTION_METADATA`] entry of the object.
+    pub fn metadata(&self) -> String {
+        format!("{FORMAT_VERSION}:{}", STANDARD.encode(self.salt))
+    }
+
+    fn nonce(index: u64) -> Nonce {
+        let mut nonce = [0; NONCE_LEN];
+        nonce[NONCE_LEN - 8..].copy_from_slice(&index.to_be_bytes());
+        Nonce::assume_unique_for_key(nonce)
+    }
+
+    fn aad(is_last: bool) -> Aad<[u8; 1]> { Aad::from([is_last as u8]) }
+
+    /// Encrypt the chunk in place, appending the authentication tag.
+    pub fn seal_chunk(
+        &self,
+        index: u64,
+        is_last: bool,
+        data: &mut Vec<u8>,
+    ) -> anyhow::Result<()> {
+        self.key
+            .seal_in_place_append_tag(
+                Self::nonce(index),
+                Self::aad(is_last),
+                data,
+            )
+            .map_err(|_| anyhow!("Failed to encrypt"))
+    }
+
+    /// Decrypt the chunk in place, removing the authentication tag.
+    pub fn open_chunk(
+        &self,
+        index: u64,
+        is_last: bool,
+        data: &mut Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let len = self
+            .key
+            .open_in_place(Self::nonce(index), Self::aad(is_last), data)
+            .map_err(|_| {
+                anyhow!(
+                    "Failed to decrypt, the data is corrupted or the key is \
+                     wrong"
+                )
+            })?
+            .len();
+        data.truncate(len);
+        Ok(())
+    }
+}
+
+#[test]
+fn object_cipher_test() -> anyhow::Result<()> {
+    let key = EncryptionKey::parse(&STANDARD.encode([7u8; KEY_LEN]))?;
+    let cipher = ObjectCipher::new(&key)?;
+
+    let mut chunk = b"hello".to_vec();
+    cipher.seal_chunk(3, true, &mut chunk)?;
+    assert_eq!(chunk.len(), 5 + TAG_LEN);
+
+    let decipher = ObjectCipher::from_metadata(&key, &cipher.metadata())?;
+    let mut wrong_position = chunk.clone();
+    assert!(decipher.open_chunk(3, false, &mut wrong_position).is_err());
+    decipher.open_chunk(3, true, &mut chunk)?;
+    assert_eq!(chunk, b"hello");
+    Ok(())
+}
@@ -0,0 +1,305 @@
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+
+use tokio::{sync::Semaphore, task::JoinHandle};
+use tracing::{info_span, Instrument};
+
+use crate::{
+    app_config::AppConfigProvider,
+    aws_batch::{
+        batch::{submit_job, JobOptions},
+        cloudformation::{load_gpu_stack_outputs, StackId},
+        config::{AwsConfigProvider, CloudFormationStackProvider, S3Provider},
+        indexer::{ChunkMetadata, IndexerJobArgs, EMBEDDINGS_MODEL},
+        job::{
+            make_document_list_storage_key, make_info_storage_key,
+            make_input_list_storage_key, make_job_prefix, parse_job_name,
+            parse_job_tag, JobInfo, JobType, JobUid, JOB_DOCUMENT_LIST,
+            JOB_INPUT_LIST, MAX_TAGS,
+        },
+        key_parameter::put_key_parameter,
+        s3::put_object,
+        storage_layout::ensure_layout_version,
+        transcribe::{
+            check_unique_file_names, collect_input_files, get_file_name,
+            upload_input_file,
+        },
+    },
+    text_input::OcrOptions,
+    vector_index::{diff_documents, IndexInfo},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct IndexJobArgs {
+    /// Documents to index: plain text, Markdown, HTML, PDF or DOCX files,
+    /// scanned images, and Whisper JSON transcripts. Directories are
+    /// expanded to the files they contain. All the documents are indexed by
+    /// a single job.
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    #[command(flatten)]
+    pub ocr: OcrOptions,
+    /// The maximum number of words in an indexed chunk of a document.
+    #[arg(long, default_value_t = 200)]
+    pub chunk_words: u32,
+    /// The number of words a chunk repeats from the end of the previous one
+    /// in the same section, so that a passage split between the chunks is
+    /// found in one of them.
+    #[arg(long, default_value_t = 0)]
+    pub chunk_overlap: u32,
+    /// The metadata stored with the chunks, to cite them. The document of
+    /// the chunk is always stored.
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = [
+            ChunkMetadata::Section,
+            ChunkMetadata::Speaker,
+            ChunkMetadata::Timestamps,
+        ],
+    )]
+    pub metadata: Vec<ChunkMetadata>,
+    /// A name of the job, can be used instead of the job ID.
+    #[arg(short, long, value_parser = parse_job_name)]
+    pub name: Option<Box<str>>,
+    /// Tags of the job, can be used to select the job. May be repeated.
+    #[arg(short, long = "tag", value_parser = parse_job_tag)]
+    pub tags: Vec<Box<str>>,
+    /// The previous index of the documents, e.g. the downloaded output of an
+    /// earlier job. Only the documents added or changed since are indexed,
+    /// into a partial index to apply onto the previous one with
+    /// `index apply`.
+    #[arg(long)]
+    pub update: Option<PathBuf>,
+}
+
+/// Uploads the documents and submits a job computing their embeddings. The
+/// job writes the vector index into its output folder:
+/// - `chunks.jsonl`: the indexed chunks, with their document, text and
+///   metadata;
+/// - `embeddings.npy`: the normalized embeddings of the chunks, in order;
+/// - `index.json`: the model, the document hashes and the statistics of the
+///   index.
+///
+/// The cost of indexing jobs is not estimated, so they are not checked
+/// against the budget.
+#[tracing::instrument(level = "info", skip(config))]
+pub async fn run_index_job(
+    config: Arc<
+        impl AwsConfigProvider
+            + S3Provider
+            + CloudFormationStackProvider
+            + AppConfigProvider
+            + Sync
+            + Send
+            + 'static,
+    >,
+    job: &IndexJobArgs,
+) -> crate::Result<()> {
+    Ok(index_documents(config, job).await?)
+}
+
+async fn index_documents(
+    config: Arc<
+        impl AwsConfigProvider
+            + S3Provider
+            + CloudFormationStackProvider
+            + AppConfigProvider
+            + Sync
+            + Send
+            + 'static,
+    >,
+    job: &IndexJobArgs,
+) -> anyhow::Result<()> {
+    let files = collect_input_files(&job.files).await?;
+    check_unique_file_names(&files)?;
+    if job.chunk_words == 0 {
+        anyhow::bail!("The chunk size must be positive.");
+    }
+    if job.chunk_overlap >= job.chunk_words {
+        anyhow::bail!("The chunk overlap must be smaller than the chunk size.");
+    }
+    if job.tags.len() > MAX_TAGS {
+        anyhow::bail!("At most {MAX_TAGS} tags are allowed.");
+    }
+
+    let mut document_hashes = BTreeMap::new();
+    for file in &files {
+        document_hashes.insert(
+            get_file_name(file)?.to_string(),
+            crate::hasher::get_file_hash_value(file).await?,
+        );
+    }
+    let files = match &job.update {
+        Some(dir) => {
+            let previous = IndexInfo::load(dir).await?;
+            check_update_settings(&previous, job)?;
+            let (changed, removed) =
+                diff_documents(&previous.document_hashes, &document_hashes);
+            if changed.is_empty() && removed.is_empty() {
+                tracing::info!("The index is up to date.");
+                return Ok(());
+            }
+            tracing::info!(
+                changed = changed.len(),
+                removed = removed.len(),
+                "Indexing the changed documents."
+            );
+            let mut files = files;
+            files.retain(|f| {
+                get_file_name(f)
+                    .is_ok_and(|name| changed.iter().any(|c| c == name))
+            });
+            files
+        },
+        None => files,
+    };
+
+    crate::aws_batch::cloudformation::manage_cloudformation_stacks(
+        &*config,
+        [StackId::Base, StackId::GpuBatch].into(),
+    )
+    .await?;
+
+    ensure_layout_version(&*config).await?;
+
+    let stack_outputs = load_gpu_stack_outputs(&*config).await?;
+    let job_definition = stack_outputs.get_index_job_definition()?;
+
+    let jid = JobUid::new();
+    let root_prefix = config.get_root_prefix();
+    tracing::info!(job_id = %jid, files = files.len(),
+        "Starting indexing job.");
+
+    let par_sem = Arc::new(Semaphore::new(config.get_limits().s3_transfers));
+    let mut tasks: Vec<JoinHandle<anyhow::Result<Box<str>>>> = Vec::new();
+
+    for file in files {
+        let span = info_span!("upload file", ?file);
+        let config = Arc::clone(&config);
+        let jid = jid.clone();
+        let par_sem = Arc::clone(&par_sem);
+
+        tasks.push(tokio::spawn(
+            async move {
+                let _permit = par_sem.acquire().await?;
+                upload_input_file(&*config, &jid, &file).await
+            }
+            .instrument(span),
+        ));
+    }
+
+    let mut input_list = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        input_list.push(task.await??);
+    }
+
+    put_object(
+        &*config,
+        input_list.join("\n").as_bytes(),
+        &make_input_list_storage_key(root_prefix, &jid),
+    )
+    .await?;
+
+    let document_list = document_hashes
+        .iter()
+        .map(|(document, hash)| format!("{hash}\t{document}"))
+        .collect::<Vec<_>>();
+    put_object(
+        &*config,
+        document_list.join("\n").as_bytes(),
+        &make_document_list_storage_key(root_prefix, &jid),
+    )
+    .await?;
+
+    put_object(
+        &*config,
+        b"",
+        &make_info_storage_key(
+            root_prefix,
+            &jid,
+            &JobInfo {
+                job_type: JobType::Index,
+                start_time: chrono::Utc::now(),
+                batch_label: None,
+                array_size: None,
+                model: None,
+                name: job.name.clone(),
+                tags: job.tags.clone(),
+                input_hash: None,
+                language: None,
+                estimated_cost: None,
+                audio_duration: None,
+                preprocessed: false,
+                after: None,
+                retry_of: None,
+                parts: None,
+            },
+        ),
+    )
+    .await?;
+
+    let encryption_key_parameter = put_key_parameter(&*config).await?;
+    submit_job(
+        &*config,
+        jid.clone(),
+        &stack_outputs.job_queue,
+        job_definition,
+        IndexerJobArgs {
+            job_uid: &jid,
+            job_prefix: &make_job_prefix(root_prefix, &jid),
+            input_list: JOB_INPUT_LIST,
+            document_list: JOB_DOCUMENT_LIST,
+            chunk_words: &job.chunk_words.to_string(),
+            chunk_overlap: &job.chunk_overlap.to_string(),
+            metadata: &ChunkMetadata::join(&job.metadata),
+            ocr: job.ocr.ocr.then_some("1"),
+            ocr_language: &job.ocr.ocr_language,
+            encryption_key_parameter: encryption_key_parameter.as_deref(),
+        }
+        .environments(),
+        JobOptions::default(),
+    )
+    .await?;
+
+    tracing::info!(job_id = %jid, "Indexing job submitted.");
+
+    Ok(())
+}
+
+/// Checks that the previous index can be updated with the settings of the
+/// job, which would otherwise give chunks inconsistent with its other chunks.
+fn check_update_settings(
+    previous: &IndexInfo,
+    job: &IndexJobArgs,
+) -> anyhow::Result<()> {
+    if previous.document_hashes.is_empty() {
+        anyhow::bail!(
+            "The previous index has no document hashes, it was computed by an \
+             older version. Index all the documents again."
+        );
+    }
+    let mut previous_metadata = previous
+        .metadata
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    previous_metadata.sort();
+    let mut job_metadata = job
+        .metadata
+        .iter()
+        .map(|m| m.get_name())
+        .collect::<Vec<_>>();
+    job_metadata.sort();
+    if previous.model != EMBEDDINGS_MODEL ||
+        previous.chunk_words != Some(job.chunk_words) ||
+        previous.chunk_overlap.unwrap_or(0) != job.chunk_overlap ||
+        previous_metadata != job_metadata
+    {
+        anyhow::bail!(
+            "The previous index was computed with other settings, index all \
+             the documents again."
+        );
+    }
+    Ok(())
+}
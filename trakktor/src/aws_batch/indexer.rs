@@ -0,0 +1,98 @@
+use serde::Serialize;
+
+use crate::aws_batch::{batch::ContainerEnvs, job::JobUid};
+
+const VERSION_TAG: &str = "6";
+const DEV_VERSION_TAG: &str = "dev";
+const IMAGE_NAME: &str = "ghcr.io/lymar/trakktor/indexer";
+/// The sentence embeddings model baked into the indexer image.
+pub const EMBEDDINGS_MODEL: &str =
+    "sentence-transformers/paraphrase-multilingual-MiniLM-L12-v2";
+/// Name of the job definition of the indexer in the GPU batch stack.
+pub const JOB_DEFINITION_NAME: &str = "GpuIndexJob";
+
+/// Metadata of the chunks stored alongside their embeddings. The document a
+/// chunk comes from is always stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ChunkMetadata {
+    /// The Markdown or HTML heading of the section the chunk is in.
+    Section,
+    /// The speakers of a transcript chunk.
+    Speaker,
+    /// The time range of a transcript chunk in the audio.
+    Timestamps,
+}
+
+impl ChunkMetadata {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            ChunkMetadata::Section => "section",
+            ChunkMetadata::Speaker => "speaker",
+            ChunkMetadata::Timestamps => "timestamps",
+        }
+    }
+
+    /// The comma separated names of the fields.
+    pub fn join(fields: &[ChunkMetadata]) -> String {
+        fields
+            .iter()
+            .map(|f| f.get_name())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+pub fn make_image_name(is_dev: bool) -> String {
+    format!(
+        "{}:{}",
+        IMAGE_NAME,
+        if is_dev { DEV_VERSION_TAG } else { VERSION_TAG }
+    )
+}
+
+/// Arguments for an indexing job passed to the container as environment
+/// variables.
+#[derive(Debug, Serialize)]
+pub struct IndexerJobArgs<'a> {
+    #[serde(rename = "TRK_JOB_UID")]
+    pub job_uid: &'a JobUid,
+    /// Storage key prefix of all the job objects.
+    #[serde(rename = "TRK_JOB_PREFIX")]
+    pub job_prefix: &'a str,
+    /// The list of the documents to index.
+    #[serde(rename = "TRK_INPUT_LIST")]
+    pub input_list: &'a str,
+    /// The hashes of all the documents of the corpus.
+    #[serde(rename = "TRK_DOCUMENT_LIST")]
+    pub document_list: &'a str,
+    /// The maximum number of words in an indexed chunk of a document.
+    #[serde(rename = "TRK_CHUNK_WORDS")]
+    pub chunk_words: &'a str,
+    /// The number of words a chunk repeats from the previous one.
+    #[serde(rename = "TRK_CHUNK_OVERLAP")]
+    pub chunk_overlap: &'a str,
+    /// The comma separated metadata fields stored with the chunks.
+    #[serde(rename = "TRK_METADATA")]
+    pub metadata: &'a str,
+    /// Set to recognize the text of all the PDF files with OCR, not only of
+    /// those without a text layer.
+    #[serde(rename = "TRK_OCR", skip_serializing_if = "Option::is_none")]
+    pub ocr: Option<&'a str>,
+    /// The Tesseract languages of the scanned documents.
+    #[serde(rename = "TRK_OCR_LANGUAGE")]
+    pub ocr_language: &'a str,
+    /// The SSM parameter holding the key of the client-side encryption, see
+    /// [`super::key_parameter`].
+    #[serde(
+        rename = "TRK_ENCRYPTION_KEY_PARAMETER",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub encryption_key_parameter: Option<&'a str>,
+}
+
+impl<'a> IndexerJobArgs<'a> {
+    /// Convert the arguments into a list of environment variables.
+    pub fn environments(&self) -> ContainerEnvs {
+        ContainerEnvs::from_args(self)
+    }
+}
@@ -1,9 +1,11 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::aws_batch::{budget::Cents, parts::PartsInfo, whisper::Model};
+
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Hash)]
 pub struct JobUid(Arc<str>);
 
@@ -65,18 +67,219 @@ fn parse_job_id_test() {
 )]
 pub enum JobType {
     Transcribe,
+    Index,
+    Align,
+    Preprocess,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct JobInfo {
     #[serde(rename = "t")]
     pub job_type: JobType,
     #[serde(rename = "s")]
     pub start_time: DateTime<Utc>,
+    /// Label shared by all the jobs submitted together.
+    #[serde(rename = "b", default)]
+    pub batch_label: Option<Box<str>>,
+    /// Number of children if the job is an array job.
+    #[serde(rename = "a", default)]
+    pub array_size: Option<u32>,
+    /// Whisper model used for the transcription.
+    #[serde(rename = "m", default)]
+    pub model: Option<Model>,
+    /// User given name of the job.
+    #[serde(rename = "n", default)]
+    pub name: Option<Box<str>>,
+    /// User given tags of the job.
+    #[serde(rename = "g", default)]
+    pub tags: Vec<Box<str>>,
+    /// Hash of the input file, for single file jobs.
+    #[serde(rename = "h", default)]
+    pub input_hash: Option<Box<str>>,
+    /// Language of the audio.
+    #[serde(rename = "l", default)]
+    pub language: Option<Box<str>>,
+    /// Estimated cost of the job in US cents.
+    #[serde(rename = "c", default)]
+    pub estimated_cost: Option<Cents>,
+    /// Duration of the audio in seconds, of all the files of an array job.
+    #[serde(rename = "u", default)]
+    pub audio_duration: Option<u32>,
+    /// Whether the audio was preprocessed before the transcription.
+    #[serde(rename = "p", default)]
+    pub preprocessed: bool,
+    /// The previous job of the pipeline the job is part of. The job takes the
+    /// results of that job as its input and starts once it succeeds.
+    #[serde(rename = "d", default)]
+    pub after: Option<JobUid>,
+    /// The failed job this job retries, reusing its input files.
+    #[serde(rename = "r", default)]
+    pub retry_of: Option<JobUid>,
+    /// How the audio was split into the parts transcribed by the children of
+    /// the array job, whose transcripts are stitched on download.
+    #[serde(rename = "x", default)]
+    pub parts: Option<PartsInfo>,
 }
 
 const JOB_INFO_SUFFIX: &str = ".🚜-info";
 pub const JOB_DONE_FLAG: &str = "done.🚜-flag";
+const ARRAY_DONE_FLAG_PREFIX: &str = "done-";
+const FLAG_SUFFIX: &str = ".🚜-flag";
+/// Object listing the input files of an array job, one per line. The child
+/// with index `i` processes the file on line `i`.
+pub const JOB_INPUT_LIST: &str = "inputs.🚜-list";
+/// Object listing the hashes of all the documents of an indexing job's
+/// corpus, one `<hash>\t<document>` per line, including the unchanged
+/// documents left out of the input list of an index update.
+pub const JOB_DOCUMENT_LIST: &str = "documents.🚜-list";
+/// Object listing the files written by the preprocessing stage of a
+/// transcription array job, in the same format as the input list.
+pub const JOB_PREPROCESSED_LIST: &str = "preprocessed.🚜-list";
+
+/// Object written by a job that failed, with its [`JobErrorReport`].
+pub const JOB_ERROR_REPORT: &str = "error.🚜-report";
+const ARRAY_ERROR_REPORT_PREFIX: &str = "error-";
+const REPORT_SUFFIX: &str = ".🚜-report";
+
+/// The stage of a job container at which it failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobStage {
+    /// Reading the environment of the job.
+    Config,
+    /// Downloading the input.
+    Download,
+    /// Running the model.
+    Transcribe,
+    /// Selecting and compressing the outputs.
+    Outputs,
+    /// Uploading the outputs and the done flag.
+    Upload,
+}
+
+/// Why a job failed, written by its container next to where the done flag
+/// would be.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobErrorReport {
+    pub stage: JobStage,
+    /// The error with its causes.
+    pub message: String,
+    /// The input file the job was processing, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_file: Option<String>,
+    /// The last lines the failed tool wrote to its standard error.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stderr_tail: Vec<String>,
+}
+
+/// Object rewritten by a running job every [`HEARTBEAT_INTERVAL`], with its
+/// [`Heartbeat`].
+pub const JOB_HEARTBEAT: &str = "heartbeat.🚜-beat";
+const ARRAY_HEARTBEAT_PREFIX: &str = "heartbeat-";
+const HEARTBEAT_SUFFIX: &str = ".🚜-beat";
+pub const HEARTBEAT_INTERVAL: std::time::Duration =
+    std::time::Duration::from_secs(60);
+/// A running job whose heartbeat is older than this is likely hung.
+pub const STALE_HEARTBEAT: std::time::Duration =
+    std::time::Duration::from_secs(10 * 60);
+
+/// The liveness marker of a running job.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub time: DateTime<Utc>,
+    /// The segments transcribed so far.
+    pub segments: u64,
+}
+
+impl Heartbeat {
+    pub fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        (now - self.time).to_std().unwrap_or_default() > STALE_HEARTBEAT
+    }
+}
+
+/// Make the name of the heartbeat of a child of an array job.
+pub fn make_array_heartbeat(index: u32) -> Box<str> {
+    format!("{ARRAY_HEARTBEAT_PREFIX}{index}{HEARTBEAT_SUFFIX}").into()
+}
+
+/// Parse the index of an array job child from its heartbeat name.
+pub fn parse_array_heartbeat(name: &str) -> Option<u32> {
+    name.strip_prefix(ARRAY_HEARTBEAT_PREFIX)?
+        .strip_suffix(HEARTBEAT_SUFFIX)?
+        .parse()
+        .ok()
+}
+
+/// Make the name of the error report of a failed child of an array job.
+pub fn make_array_error_report(index: u32) -> Box<str> {
+    format!("{ARRAY_ERROR_REPORT_PREFIX}{index}{REPORT_SUFFIX}").into()
+}
+
+/// Parse the index of an array job child from its error report name.
+pub fn parse_array_error_report(name: &str) -> Option<u32> {
+    name.strip_prefix(ARRAY_ERROR_REPORT_PREFIX)?
+        .strip_suffix(REPORT_SUFFIX)?
+        .parse()
+        .ok()
+}
+
+/// Make the name of the flag written when a child of an array job is done.
+pub fn make_array_done_flag(index: u32) -> Box<str> {
+    format!("{ARRAY_DONE_FLAG_PREFIX}{index}{FLAG_SUFFIX}").into()
+}
+
+/// Parse the index of an array job child from its done flag name.
+pub fn parse_array_done_flag(name: &str) -> Option<u32> {
+    name.strip_prefix(ARRAY_DONE_FLAG_PREFIX)?
+        .strip_suffix(FLAG_SUFFIX)?
+        .parse()
+        .ok()
+}
+
+#[test]
+fn array_done_flag_test() {
+    assert_eq!(make_array_done_flag(12).as_ref(), "done-12.🚜-flag");
+    assert_eq!(parse_array_done_flag("done-12.🚜-flag"), Some(12));
+    assert_eq!(parse_array_done_flag(JOB_DONE_FLAG), None);
+    assert_eq!(parse_array_done_flag("done-x.🚜-flag"), None);
+    assert_eq!(make_array_error_report(3).as_ref(), "error-3.🚜-report");
+    assert_eq!(parse_array_error_report("error-3.🚜-report"), Some(3));
+    assert_eq!(parse_array_error_report(JOB_ERROR_REPORT), None);
+    assert_eq!(make_array_heartbeat(7).as_ref(), "heartbeat-7.🚜-beat");
+    assert_eq!(parse_array_heartbeat("heartbeat-7.🚜-beat"), Some(7));
+    assert_eq!(parse_array_heartbeat(JOB_HEARTBEAT), None);
+}
+
+#[test]
+fn heartbeat_stale_test() {
+    let now = Utc::now();
+    let beat = |minutes| Heartbeat {
+        time: now - chrono::Duration::minutes(minutes),
+        segments: 0,
+    };
+    assert!(!beat(1).is_stale(now));
+    assert!(beat(11).is_stale(now));
+    // The clocks of the node and of the client may differ a bit.
+    assert!(!beat(-1).is_stale(now));
+}
+
+/// Checks whether the job is done, given the names of its objects relative to
+/// the job prefix. An array job is done once all of its children are done.
+pub fn is_job_done<'a>(objects: impl IntoIterator<Item = &'a str>) -> bool {
+    let mut array_size = None;
+    let mut done_children = HashSet::new();
+    for o in objects {
+        if o == JOB_DONE_FLAG {
+            return true;
+        } else if let Some(index) = parse_array_done_flag(o) {
+            done_children.insert(index);
+        } else if let Ok(info) = JobInfo::deserialize(o) {
+            array_size = info.array_size;
+        }
+    }
+
+    matches!(array_size, Some(n) if done_children.len() == n as usize)
+}
 
 impl JobInfo {
     pub fn serialize(&self) -> Box<str> {
@@ -106,6 +309,22 @@ fn job_info_serialize_test() -> anyhow::Result<()> {
     let job_info = JobInfo {
         job_type: JobType::Transcribe,
         start_time: Utc::now(),
+        batch_label: Some("batch-20240615-120000".into()),
+        array_size: Some(3),
+        model: Some(Model::Medium),
+        name: Some("board-meeting-2024-06".into()),
+        tags: vec!["meetings".into(), "q2".into()],
+        input_hash: Some(crate::hasher::get_hash_value(b"audio").into()),
+        language: Some("en".into()),
+        estimated_cost: Some(42),
+        audio_duration: Some(3725),
+        preprocessed: true,
+        after: Some(JobUid::new()),
+        retry_of: Some(JobUid::new()),
+        parts: Some(PartsInfo {
+            step: 3570,
+            overlap: 30,
+        }),
     };
     let serialized = job_info.serialize();
     println!("{}", serialized);
@@ -115,21 +334,219 @@ fn job_info_serialize_test() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn job_info_deserialize_without_batch_label_test() -> anyhow::Result<()> {
+    #[derive(Serialize)]
+    struct JobInfoV0 {
+        t: JobType,
+        s: DateTime<Utc>,
+    }
+
+    let start_time = Utc::now();
+    let serialized = URL_SAFE_NO_PAD.encode(rmp_serde::to_vec(&JobInfoV0 {
+        t: JobType::Transcribe,
+        s: start_time,
+    })?) + JOB_INFO_SUFFIX;
+
+    let deserialized = JobInfo::deserialize(&serialized)?;
+    assert_eq!(deserialized.start_time, start_time);
+    assert_eq!(deserialized.batch_label, None);
+    assert_eq!(deserialized.array_size, None);
+    assert_eq!(deserialized.model, None);
+    assert_eq!(deserialized.name, None);
+    assert!(deserialized.tags.is_empty());
+    assert_eq!(deserialized.input_hash, None);
+    assert_eq!(deserialized.estimated_cost, None);
+    assert_eq!(deserialized.audio_duration, None);
+    assert!(!deserialized.preprocessed);
+    assert_eq!(deserialized.after, None);
+    assert_eq!(deserialized.retry_of, None);
+    assert_eq!(deserialized.parts, None);
+    Ok(())
+}
+
+/// Job names, tags and batch labels are stored in the job info object key,
+/// so they are kept short.
+const MAX_NAME_LEN: usize = 64;
+const MAX_TAG_LEN: usize = 32;
+const MAX_BATCH_LABEL_LEN: usize = 64;
+pub const MAX_TAGS: usize = 8;
+
+fn parse_label(s: &str, max_len: usize) -> Result<Box<str>, String> {
+    if s.is_empty() || s.len() > max_len {
+        return Err(format!("Must be from 1 to {max_len} characters long"));
+    }
+    if !s
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err("Only ASCII letters, digits, '-', '_' and '.' are allowed"
+            .to_string());
+    }
+    Ok(s.into())
+}
+
+pub fn parse_job_name(s: &str) -> Result<Box<str>, String> {
+    parse_label(s, MAX_NAME_LEN)
+}
+
+pub fn parse_job_tag(s: &str) -> Result<Box<str>, String> {
+    parse_label(s, MAX_TAG_LEN)
+}
+
+pub fn parse_batch_label(s: &str) -> Result<Box<str>, String> {
+    parse_label(s, MAX_BATCH_LABEL_LEN)
+}
+
+#[test]
+fn parse_batch_label_test() {
+    assert_eq!(
+        parse_batch_label("batch-20240615-120000"),
+        Ok("batch-20240615-120000".into())
+    );
+    assert!(parse_batch_label("").is_err());
+    assert!(parse_batch_label("q2 interviews").is_err());
+    assert!(parse_batch_label(&"a".repeat(MAX_BATCH_LABEL_LEN + 1)).is_err());
+}
+
+const TAG_SELECTOR_PREFIX: &str = "tag:";
+
+/// Selects jobs by ID, by name, or by tag (`tag:<tag>`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobSelector {
+    Id(JobUid),
+    Name(Box<str>),
+    Tag(Box<str>),
+}
+
+impl JobSelector {
+    pub fn parse_job_selector(s: &str) -> Result<Self, String> {
+        if let Some(tag) = s.strip_prefix(TAG_SELECTOR_PREFIX) {
+            return Ok(JobSelector::Tag(parse_job_tag(tag)?));
+        }
+        if let Ok(jid) = JobUid::parse_job_uid(s) {
+            return Ok(JobSelector::Id(jid));
+        }
+        parse_job_name(s)
+            .map(JobSelector::Name)
+            .map_err(|e| format!("Not a job ID nor a valid job name: {e}"))
+    }
+
+    pub fn matches(&self, job_id: &JobUid, info: &JobInfo) -> bool {
+        match self {
+            JobSelector::Id(jid) => jid == job_id,
+            JobSelector::Name(name) => info.name.as_ref() == Some(name),
+            JobSelector::Tag(tag) => info.tags.contains(tag),
+        }
+    }
+}
+
+impl std::fmt::Display for JobSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobSelector::Id(jid) => write!(f, "{jid}"),
+            JobSelector::Name(name) => f.write_str(name),
+            JobSelector::Tag(tag) => write!(f, "{TAG_SELECTOR_PREFIX}{tag}"),
+        }
+    }
+}
+
+#[test]
+fn parse_job_selector_test() {
+    assert_eq!(
+        JobSelector::parse_job_selector("BAtQ5-omTm6ZSRTg2AfFKQ"),
+        Ok(JobSelector::Id(
+            JobUid::parse_job_uid("BAtQ5-omTm6ZSRTg2AfFKQ").unwrap()
+        ))
+    );
+    assert_eq!(
+        JobSelector::parse_job_selector("board-meeting"),
+        Ok(JobSelector::Name("board-meeting".into()))
+    );
+    assert_eq!(
+        JobSelector::parse_job_selector("tag:q2"),
+        Ok(JobSelector::Tag("q2".into()))
+    );
+    assert!(JobSelector::parse_job_selector("tag:").is_err());
+    assert!(JobSelector::parse_job_selector("a/b").is_err());
+}
+
 pub const JOB_IN_PREFIX: &str = "in/";
 
+/// Make a storage key prefix for all the objects of the job.
+pub fn make_job_prefix(root_prefix: &str, job_id: &JobUid) -> Box<str> {
+    format!("{}{}/", root_prefix, job_id).into()
+}
+
 /// Make a storage key for the job input file.
-pub fn make_input_storage_key(job_id: &JobUid, file: &str) -> Box<str> {
-    format!("{}/{}{}", job_id, JOB_IN_PREFIX, file).into()
+pub fn make_input_storage_key(
+    root_prefix: &str,
+    job_id: &JobUid,
+    file: &str,
+) -> Box<str> {
+    format!(
+        "{}{}{}",
+        make_job_prefix(root_prefix, job_id),
+        JOB_IN_PREFIX,
+        file
+    )
+    .into()
 }
 
 /// Make a storage key for the job info object.
-pub fn make_info_storage_key(job_id: &JobUid, job_info: &JobInfo) -> Box<str> {
-    format!("{}/{}", job_id, job_info.serialize()).into()
+pub fn make_info_storage_key(
+    root_prefix: &str,
+    job_id: &JobUid,
+    job_info: &JobInfo,
+) -> Box<str> {
+    format!(
+        "{}{}",
+        make_job_prefix(root_prefix, job_id),
+        job_info.serialize()
+    )
+    .into()
+}
+
+/// Make a storage key for the input list of an array job.
+pub fn make_input_list_storage_key(
+    root_prefix: &str,
+    job_id: &JobUid,
+) -> Box<str> {
+    format!("{}{}", make_job_prefix(root_prefix, job_id), JOB_INPUT_LIST).into()
+}
+
+/// Make a storage key for the list of the preprocessed files of an array job.
+pub fn make_preprocessed_list_storage_key(
+    root_prefix: &str,
+    job_id: &JobUid,
+) -> Box<str> {
+    format!(
+        "{}{}",
+        make_job_prefix(root_prefix, job_id),
+        JOB_PREPROCESSED_LIST
+    )
+    .into()
+}
+
+/// Make a storage key for the document list of an indexing job.
+pub fn make_document_list_storage_key(
+    root_prefix: &str,
+    job_id: &JobUid,
+) -> Box<str> {
+    format!(
+        "{}{}",
+        make_job_prefix(root_prefix, job_id),
+        JOB_DOCUMENT_LIST
+    )
+    .into()
 }
 
 pub const JOB_OUT_PREFIX: &str = "out/";
 
 /// Make a storage key prefix for the job output files.
-pub fn make_output_storage_prefix(job_id: &JobUid) -> Box<str> {
-    format!("{}/{}", job_id, JOB_OUT_PREFIX).into()
+pub fn make_output_storage_prefix(
+    root_prefix: &str,
+    job_id: &JobUid,
+) -> Box<str> {
+    format!("{}{}", make_job_prefix(root_prefix, job_id), JOB_OUT_PREFIX).into()
 }
@@ -0,0 +1,86 @@
+//! The encryption key is passed to the jobs in an SSM SecureString
+//! parameter, which the containers read at start. Only the name of the
+//! parameter is in the job environment, as the environment is visible to
+//! anyone allowed to describe the jobs.
+
+use anyhow::Context;
+use aws_config::SdkConfig;
+use aws_sdk_ssm::types::ParameterType;
+
+use super::{
+    config::{AwsConfigProvider, CloudFormationStackProvider, S3Provider},
+    encryption::EncryptionKey,
+};
+
+/// The directory of the key parameters under the base stack name, the job
+/// role of the base stack can only read the parameters in it.
+pub const KEY_PARAMETER_DIR: &str = "encryption-keys";
+
+/// The name of the parameter of the key. Every key has its own, so the
+/// clients with different keys can share the stacks.
+pub fn key_parameter_name(
+    base_stack_name: &str,
+    key: &EncryptionKey,
+) -> String {
+    format!(
+        "/{base_stack_name}/{KEY_PARAMETER_DIR}/{}",
+        key.fingerprint()
+    )
+}
+
+/// Stores the encryption key of the configuration in its parameter, if
+/// there is a key, and returns the name of the parameter to pass to the jobs.
+#[tracing::instrument(skip_all)]
+pub async fn put_key_parameter(
+    config: &(impl AwsConfigProvider + S3Provider + CloudFormationStackProvider),
+) -> anyhow::Result<Option<String>> {
+    let Some(key) = config.get_encryption_key() else {
+        return Ok(None);
+    };
+    let name = key_parameter_name(&config.get_base_stack_name(), key);
+    aws_sdk_ssm::Client::new(config.get_aws_config())
+        .put_parameter()
+        .name(&name)
+        .value(key.to_base64())
+        .r#type(ParameterType::SecureString)
+        .overwrite(true)
+        .send()
+        .await
+        .context("Failed to store the encryption key in SSM")?;
+    Ok(Some(name))
+}
+
+/// Reads the encryption key from its parameter, in the job.
+#[tracing::instrument(skip(aws_config))]
+pub async fn get_key_parameter(
+    aws_config: &SdkConfig,
+    name: &str,
+) -> anyhow::Result<EncryptionKey> {
+    let output = aws_sdk_ssm::Client::new(aws_config)
+        .get_parameter()
+        .name(name)
+        .with_decryption(true)
+        .send()
+        .await
+        .with_context(|| format!("Failed to read the encryption key {name}"))?;
+    let value = output
+        .parameter
+        .and_then(|p| p.value)
+        .with_context(|| format!("The encryption key {name} has no value"))?;
+    EncryptionKey::parse(&value)
+}
+
+#[test]
+fn key_parameter_name_test() -> anyhow::Result<()> {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    let key = EncryptionKey::parse(&STANDARD.encode([1; 32]))?;
+    let name = key_parameter_name("trakktor-base", &key);
+    assert!(name.starts_with("/trakktor-base/encryption-keys/"));
+    assert_eq!(name.len(), "/trakktor-base/encryption-keys/".len() + 16);
+    assert!(!name.contains(&key.to_base64()));
+
+    let other = EncryptionKey::parse(&STANDARD.encode([2; 32]))?;
+    assert_ne!(name, key_parameter_name("trakktor-base", &other));
+    Ok(())
+}
@@ -1,36 +1,78 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
-use anyhow::bail;
 use aws_sdk_batch::types::JobSummary;
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, Utc};
 use duration_str::HumanFormat;
+use itertools::Itertools;
 use tracing::{info_span, Instrument};
 
-use crate::aws_batch::{
-    cloudformation::load_all_batch_jobs,
-    config::{AwsConfigProvider, CloudFormationStackProvider, S3Provider},
-    job::{JobInfo, JobUid, JOB_DONE_FLAG, JOB_IN_PREFIX, JOB_OUT_PREFIX},
-    s3::list_objects,
+use crate::{
+    app_config::AppConfigProvider,
+    aws_batch::{
+        budget::{estimate_processing_time, format_usd, Cents},
+        cloudformation::load_all_batch_jobs,
+        compression::display_name,
+        config::{AwsConfigProvider, CloudFormationStackProvider, S3Provider},
+        job::{
+            parse_array_done_flag, parse_array_error_report,
+            parse_array_heartbeat, Heartbeat, JobInfo, JobUid,
+            JOB_DOCUMENT_LIST, JOB_DONE_FLAG, JOB_ERROR_REPORT, JOB_HEARTBEAT,
+            JOB_INPUT_LIST, JOB_IN_PREFIX, JOB_OUT_PREFIX,
+            JOB_PREPROCESSED_LIST,
+        },
+        s3::{get_object, list_objects},
+        storage_layout::make_layout_marker_key,
+    },
+    locale::Strings,
 };
 
-#[derive(Debug, strum_macros::Display)]
+#[derive(Debug)]
 enum JobStatus {
     Unknown,
     Done,
     InProgress,
     Failed,
+    /// Running in AWS Batch, but with a stale heartbeat.
+    Stalled,
 }
 
 impl Default for JobStatus {
     fn default() -> Self { Self::Unknown }
 }
 
+impl JobStatus {
+    fn display(&self, strings: &Strings) -> &'static str {
+        match self {
+            Self::Unknown => strings.status_unknown,
+            Self::Done => strings.status_done,
+            Self::InProgress => strings.status_in_progress,
+            Self::Failed => strings.status_failed,
+            Self::Stalled => strings.status_stalled,
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 struct JobDisplayInfo<'a> {
     in_files: Vec<&'a str>,
     out_files: Vec<&'a str>,
     status: JobStatus,
     duration: Option<std::time::Duration>,
+    done_children: u32,
+    /// The children of an array job that are done or failed.
+    finished_children: HashSet<u32>,
+    /// The keys of the heartbeats, with the indexes of the array job
+    /// children.
+    heartbeats: Vec<(Option<u32>, &'a str)>,
+    /// Whether the job is running in AWS Batch.
+    running: bool,
+    /// The oldest heartbeat of the running job.
+    heartbeat: Option<Heartbeat>,
+    /// When the running job is expected to finish.
+    eta: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug)]
@@ -42,16 +84,153 @@ struct JobDisplayFull<'a> {
 
 const IND: &str = "    ";
 
+#[derive(clap::Args, Debug, Default)]
+pub struct ListArgs {
+    /// Show the jobs in groups, with the count, the total duration and the
+    /// total estimated cost of each group.
+    #[arg(long, value_enum)]
+    pub group_by: Option<GroupBy>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GroupBy {
+    /// The batch label of the jobs submitted together.
+    Label,
+    /// The type of the jobs, e.g. transcribe or index.
+    Type,
+    /// The month the jobs started in.
+    Month,
+}
+
+impl GroupBy {
+    fn key(self, info: &JobInfo) -> Option<String> {
+        match self {
+            Self::Label => info.batch_label.as_deref().map(str::to_string),
+            Self::Type => Some(info.job_type.to_string()),
+            Self::Month => Some(
+                DateTime::<Local>::from(info.start_time)
+                    .format("%Y-%m")
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+/// Groups the jobs, sorted by their start time, in the order of the first
+/// job of each group. Without grouping all the jobs are in one group.
+pub(crate) fn group_jobs<T>(
+    jobs: Vec<T>,
+    group_by: Option<GroupBy>,
+    info: impl Fn(&T) -> &JobInfo,
+) -> Vec<(Option<String>, Vec<T>)> {
+    let Some(group_by) = group_by else {
+        return vec![(None, jobs)];
+    };
+    let mut groups: Vec<(Option<String>, Vec<T>)> = Vec::new();
+    for job in jobs {
+        let key = group_by.key(info(&job));
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, group)) => group.push(job),
+            None => groups.push((key, vec![job])),
+        }
+    }
+    groups
+}
+
+/// Prints the heading of a group with its totals. The durations and the
+/// costs are summed over the jobs that have them.
+pub(crate) fn print_group_heading(
+    strings: &Strings,
+    key: Option<&str>,
+    count: usize,
+    durations: impl Iterator<Item = Option<std::time::Duration>>,
+    costs: impl Iterator<Item = Option<Cents>>,
+) {
+    let duration = durations.flatten().sum::<std::time::Duration>();
+    let cost = costs.flatten().sum::<Cents>();
+    let mut heading = format!(
+        "## {} -- {}",
+        key.unwrap_or(strings.no_batch),
+        (strings.jobs_count)(count)
+    );
+    if !duration.is_zero() {
+        heading.push_str(&format!(
+            ", {}: {}",
+            strings.duration,
+            duration.human_format()
+        ));
+    }
+    if cost > 0 {
+        heading.push_str(&format!(
+            ", {}: {}",
+            strings.estimated_cost,
+            format_usd(cost)
+        ));
+    }
+    println!("{heading}\n");
+}
+
+#[test]
+fn group_jobs_test() {
+    use chrono::TimeZone;
+    let info = |label: Option<&str>, day: u32| JobInfo {
+        job_type: super::job::JobType::Transcribe,
+        start_time: chrono::Utc
+            .with_ymd_and_hms(2024, 5, day, 12, 0, 0)
+            .unwrap(),
+        batch_label: label.map(Into::into),
+        array_size: None,
+        model: None,
+        name: None,
+        tags: vec![],
+        input_hash: None,
+        language: None,
+        estimated_cost: None,
+        audio_duration: None,
+        preprocessed: false,
+        after: None,
+        retry_of: None,
+        parts: None,
+    };
+    let jobs = vec![info(Some("a"), 2), info(None, 3), info(Some("a"), 4)];
+    let groups = group_jobs(jobs.clone(), Some(GroupBy::Label), |j| j);
+    assert_eq!(
+        groups
+            .iter()
+            .map(|(k, g)| (k.as_deref(), g.len()))
+            .collect::<Vec<_>>(),
+        [(Some("a"), 2), (None, 1)]
+    );
+    assert_eq!(group_jobs(jobs, Some(GroupBy::Month), |j| j).len(), 1);
+}
+
 #[tracing::instrument(level = "debug", skip(config))]
 pub async fn list_all_jobs(
     config: Arc<
         impl AwsConfigProvider
             + S3Provider
             + CloudFormationStackProvider
+            + AppConfigProvider
             + Sync
             + Send
             + 'static,
     >,
+    args: &ListArgs,
+) -> crate::Result<()> {
+    Ok(list_jobs(config, args).await?)
+}
+
+pub(crate) async fn list_jobs(
+    config: Arc<
+        impl AwsConfigProvider
+            + S3Provider
+            + CloudFormationStackProvider
+            + AppConfigProvider
+            + Sync
+            + Send
+            + 'static,
+    >,
+    args: &ListArgs,
 ) -> anyhow::Result<()> {
     println!();
 
@@ -60,7 +239,9 @@ pub async fn list_all_jobs(
         tokio::spawn(
             async move {
                 anyhow::Result::<Vec<_>>::Ok(
-                    list_objects(&*config, "").await?.collect::<Vec<_>>(),
+                    list_objects(&*config, config.get_root_prefix())
+                        .await?
+                        .collect::<Vec<_>>(),
                 )
             }
             .instrument(info_span!("list s3 objects task")),
@@ -91,24 +272,77 @@ pub async fn list_all_jobs(
     let mut jobs_map = HashMap::<JobUid, JobDisplayInfo>::new();
     let mut jobs_info = HashMap::<JobUid, JobInfo>::new();
 
+    let root_prefix = config.get_root_prefix();
+    let layout_marker_key = make_layout_marker_key(root_prefix);
     for o in &s3_objs {
-        let Some((job_uid, rest)) = o.split_once('/') else {
-            bail!("Invalid job object: {o}");
+        if o == layout_marker_key.as_ref() {
+            continue;
+        }
+
+        // Objects that do not follow the job layout may have been put in the
+        // bucket by someone else, so they are skipped rather than failing the
+        // whole listing.
+        let Some((job_uid, rest)) = o
+            .strip_prefix(root_prefix)
+            .and_then(|key| key.split_once('/'))
+        else {
+            tracing::warn!("Skipping unexpected object: {o}");
+            continue;
+        };
+        let Ok(job_uid) = JobUid::parse_job_uid(job_uid) else {
+            tracing::warn!("Skipping object with invalid job ID: {o}");
+            continue;
         };
-        let job_uid = JobUid::parse_job_uid(job_uid)
-            .map_err(|m| anyhow::anyhow!("{job_uid}: {}", m))?;
-        let info = jobs_map.entry(job_uid.clone()).or_default();
 
         if let Some(in_file) = rest.strip_prefix(JOB_IN_PREFIX) {
-            info.in_files.push(in_file);
+            jobs_map.entry(job_uid).or_default().in_files.push(in_file);
         } else if let Some(out_file) = rest.strip_prefix(JOB_OUT_PREFIX) {
-            info.out_files.push(out_file);
+            jobs_map
+                .entry(job_uid)
+                .or_default()
+                .out_files
+                .push(display_name(out_file));
         } else if rest == JOB_DONE_FLAG {
-            info.status = JobStatus::Done;
+            jobs_map.entry(job_uid).or_default().status = JobStatus::Done;
+        } else if let Some(index) = parse_array_done_flag(rest) {
+            let info = jobs_map.entry(job_uid).or_default();
+            info.done_children += 1;
+            info.finished_children.insert(index);
+        } else if rest == JOB_ERROR_REPORT {
+            let info = jobs_map.entry(job_uid).or_default();
+            if !matches!(info.status, JobStatus::Done) {
+                info.status = JobStatus::Failed;
+            }
+        } else if let Some(index) = parse_array_error_report(rest) {
+            // The status of an array job is the one of AWS Batch until all
+            // of its children are done.
+            jobs_map
+                .entry(job_uid)
+                .or_default()
+                .finished_children
+                .insert(index);
+        } else if rest == JOB_HEARTBEAT {
+            jobs_map
+                .entry(job_uid)
+                .or_default()
+                .heartbeats
+                .push((None, o));
+        } else if let Some(index) = parse_array_heartbeat(rest) {
+            jobs_map
+                .entry(job_uid)
+                .or_default()
+                .heartbeats
+                .push((Some(index), o));
+        } else if rest == JOB_INPUT_LIST ||
+            rest == JOB_DOCUMENT_LIST ||
+            rest == JOB_PREPROCESSED_LIST
+        {
+            // Only used by the jobs to pick their input.
         } else if let Ok(ji) = JobInfo::deserialize(rest) {
-            jobs_info.insert(job_uid.clone(), ji);
+            jobs_map.entry(job_uid.clone()).or_default();
+            jobs_info.insert(job_uid, ji);
         } else {
-            bail!("Unexpected job object: {o}");
+            tracing::warn!("Skipping unexpected job object: {o}");
         }
     }
 
@@ -120,6 +354,10 @@ pub async fn list_all_jobs(
                 return None;
             };
 
+            if job_info.array_size == Some(info.done_children) {
+                info.status = JobStatus::Done;
+            }
+
             if let Some(summ) = job_summaries.remove(ji.as_ref()) {
                 if matches!(info.status, JobStatus::Unknown) {
                     use aws_sdk_batch::types::JobStatus as JS;
@@ -132,6 +370,7 @@ pub async fn list_all_jobs(
                                 s == JS::Submitted =>
                         {
                             info.status = JobStatus::InProgress;
+                            info.running = s == JS::Running;
                         },
                         Some(s) if s == JS::Failed => {
                             info.status = JobStatus::Failed
@@ -149,6 +388,30 @@ pub async fn list_all_jobs(
                     }
                 }
 
+                // The children of an array job run in parallel, so only the
+                // single jobs get an estimate.
+                if let (
+                    true,
+                    Some(started_at),
+                    Some(audio),
+                    Some(model),
+                    None,
+                ) = (
+                    info.running,
+                    summ.started_at,
+                    job_info.audio_duration,
+                    job_info.model,
+                    job_info.array_size,
+                ) {
+                    let processing = estimate_processing_time(
+                        model,
+                        std::time::Duration::from_secs(audio.into()),
+                    );
+                    info.eta = DateTime::from_timestamp_millis(started_at)
+                        .zip(chrono::Duration::from_std(processing).ok())
+                        .map(|(started, processing)| started + processing);
+                }
+
                 if let (Some(started_at), Some(stopped_at)) =
                     (summ.started_at, summ.stopped_at)
                 {
@@ -168,31 +431,147 @@ pub async fn list_all_jobs(
 
     jobs.sort_by_key(|e| e.job_info.start_time);
 
-    for JobDisplayFull {
-        uid,
-        display_info,
-        job_info,
-    } in &jobs
-    {
-        let local_time: DateTime<Local> = DateTime::from(job_info.start_time);
-        println!("- {} -- {} ({})", uid, job_info.job_type, local_time);
-        println!("{IND}status: {}", display_info.status);
-        if let Some(d) = display_info.duration {
-            println!("{IND}duration: {}", d.human_format());
+    // The heartbeats of the running jobs tell the hung ones, e.g. on a GPU
+    // node that stopped responding.
+    let now = chrono::Utc::now();
+    for job in &mut jobs {
+        let info = &mut job.display_info;
+        if !info.running {
+            continue;
         }
-        println!("{IND}files:");
-        print_list(2, display_info.in_files.iter());
-        if !display_info.out_files.is_empty() {
-            println!("{IND}output files:");
-            print_list(2, display_info.out_files.iter());
+        for (index, key) in std::mem::take(&mut info.heartbeats) {
+            if index.is_some_and(|i| info.finished_children.contains(&i)) {
+                continue;
+            }
+            let Some(data) = get_object(&*config, key).await? else {
+                continue;
+            };
+            let heartbeat = match serde_json::from_slice::<Heartbeat>(&data) {
+                Ok(heartbeat) => heartbeat,
+                Err(err) => {
+                    tracing::warn!("Skipping invalid heartbeat {key}: {err}");
+                    continue;
+                },
+            };
+            if heartbeat.is_stale(now) {
+                info.status = JobStatus::Stalled;
+            }
+            if info
+                .heartbeat
+                .as_ref()
+                .is_none_or(|h| heartbeat.time < h.time)
+            {
+                info.heartbeat = Some(heartbeat);
+            }
         }
     }
 
-    println!();
+    let strings = config.get_lang().strings();
+
+    // The jobs of a pipeline are shown with the jobs before and after them.
+    let mut next_jobs = HashMap::<JobUid, Vec<JobUid>>::new();
+    for job in &jobs {
+        if let Some(after) = &job.job_info.after {
+            next_jobs
+                .entry(after.clone())
+                .or_default()
+                .push(job.uid.clone());
+        }
+    }
+
+    for (key, jobs) in group_jobs(jobs, args.group_by, |j| &j.job_info) {
+        if args.group_by.is_some() {
+            print_group_heading(
+                strings,
+                key.as_deref(),
+                jobs.len(),
+                jobs.iter().map(|j| j.display_info.duration),
+                jobs.iter().map(|j| j.job_info.estimated_cost),
+            );
+        }
+        for job in &jobs {
+            print_job(job, &next_jobs, strings);
+        }
+        println!();
+    }
 
     Ok(())
 }
 
+fn print_job(
+    job: &JobDisplayFull,
+    next_jobs: &HashMap<JobUid, Vec<JobUid>>,
+    strings: &Strings,
+) {
+    let JobDisplayFull {
+        uid,
+        display_info,
+        job_info,
+    } = job;
+    let local_time: DateTime<Local> = DateTime::from(job_info.start_time);
+    println!("- {} -- {} ({})", uid, job_info.job_type, local_time);
+    if let Some(name) = &job_info.name {
+        println!("{IND}{}: {}", strings.name, name);
+    }
+    if !job_info.tags.is_empty() {
+        println!("{IND}{}: {}", strings.tags, job_info.tags.join(", "));
+    }
+    if let Some(model) = job_info.model {
+        println!("{IND}{}: {}", strings.model, model);
+    }
+    if let Some(audio) = job_info.audio_duration {
+        let audio = std::time::Duration::from_secs(audio.into());
+        println!("{IND}{}: {}", strings.audio, audio.human_format());
+    }
+    if let Some(cost) = job_info.estimated_cost {
+        println!("{IND}{}: {}", strings.estimated_cost, format_usd(cost));
+    }
+    if let Some(batch_label) = &job_info.batch_label {
+        println!("{IND}{}: {}", strings.batch, batch_label);
+    }
+    if let Some(after) = &job_info.after {
+        println!("{IND}{}: {}", strings.after, after);
+    }
+    if let Some(retry_of) = &job_info.retry_of {
+        println!("{IND}{}: {}", strings.retry_of, retry_of);
+    }
+    if let Some(next) = next_jobs.get(uid) {
+        println!("{IND}{}: {}", strings.then, next.iter().join(", "));
+    }
+    println!(
+        "{IND}{}: {}",
+        strings.status,
+        display_info.status.display(strings)
+    );
+    if let Some(array_size) = job_info.array_size {
+        println!(
+            "{IND}{}: {}",
+            strings.array,
+            (strings.array_done)(display_info.done_children, array_size)
+        );
+    }
+    if let Some(heartbeat) = &display_info.heartbeat {
+        let local_time: DateTime<Local> = DateTime::from(heartbeat.time);
+        println!(
+            "{IND}{}",
+            (strings.heartbeat)(&local_time.to_string(), heartbeat.segments)
+        );
+    }
+    if let Some(eta) = display_info.eta {
+        let local_time: DateTime<Local> = DateTime::from(eta);
+        println!("{IND}{}: {}", strings.eta, local_time);
+    }
+    if let Some(d) = display_info.duration {
+        println!("{IND}{}: {}", strings.duration, d.human_format());
+    }
+    println!("{IND}{}:", strings.files);
+    print_list(2, display_info.in_files.iter());
+    if !display_info.out_files.is_empty() {
+        println!("{IND}{}:", strings.output_files);
+        print_list(2, display_info.out_files.iter());
+    }
+}
+
 fn print_list(
     indent_count: usize,
     data: impl IntoIterator<Item = impl AsRef<str>>,
@@ -204,3 +583,31 @@ fn print_list(
         println!("{}", d.as_ref());
     }
 }
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn list_all_jobs_test() {
+    use crate::aws_replay::{fixture_path, AwsReplay, ReplayConfig};
+
+    let replay = AwsReplay::load(&fixture_path("aws/list.json")).unwrap();
+    let config = Arc::new(ReplayConfig::new(&replay));
+    list_all_jobs(Arc::clone(&config), &ListArgs::default())
+        .await
+        .unwrap();
+    list_all_jobs(
+        config,
+        &ListArgs {
+            group_by: Some(GroupBy::Month),
+        },
+    )
+    .await
+    .unwrap();
+
+    replay.check_unmatched().unwrap();
+    let requests = replay.requests();
+    // The objects, the stacks, and the jobs of both queues.
+    assert_eq!(requests.len(), 2 * 4);
+    assert!(requests
+        .iter()
+        .any(|r| r.body.contains("trakktor-test-cpu-queue")));
+}
@@ -1,12 +1,35 @@
+pub mod align;
 pub mod delete;
+pub mod describe;
+pub mod destroy;
+pub mod doctor;
 pub mod download;
+pub mod index;
 pub mod job;
 pub mod list;
+pub mod plan;
+pub mod preprocess;
+pub mod prune;
+pub mod retry;
+pub mod select;
+pub mod storage_layout;
 pub mod transcribe;
 pub mod whisper;
 
+pub mod aligner;
 pub mod batch;
+pub mod budget;
 pub mod cloudformation;
+pub mod compression;
 pub mod config;
 pub mod ec2;
+pub mod encryption;
+pub mod indexer;
+pub mod key_parameter;
+pub mod parts;
+pub mod pending_uploads;
+pub mod preprocessor;
+pub mod probe;
+pub mod queue_wait;
 pub mod s3;
+pub mod transcode;
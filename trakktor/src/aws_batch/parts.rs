@@ -0,0 +1,417 @@
+//! Long audio transcribed in overlapping parts, each by a child of an array
+//! job. The transcripts of the parts are stitched on download, from the JSON
+//! output of Whisper, so it is produced for every job in parts.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::aws_batch::{
+    transcode::{
+        make_transcoded_file_name, transcode_range, TranscodeDir,
+        TranscodeFormat,
+    },
+    whisper::OutputFormat,
+};
+
+/// The time the neighbouring parts overlap, so the words at the cuts are in
+/// one of the parts whole.
+pub const PART_OVERLAP: Duration = Duration::from_secs(30);
+/// The shortest part length allowed.
+const MIN_PART_LENGTH: Duration = Duration::from_secs(5 * 60);
+const PART_INFIX: &str = ".part";
+
+/// How the audio of a job is split into parts, stored in the job info.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartsInfo {
+    /// The time between the starts of the neighbouring parts, in seconds.
+    #[serde(rename = "s")]
+    pub step: u32,
+    /// The time the neighbouring parts overlap, in seconds.
+    #[serde(rename = "o")]
+    pub overlap: u32,
+}
+
+impl PartsInfo {
+    /// Plans the parts of at most the part length for audio longer than it.
+    /// Returns the parts and their count, or `None` for shorter audio.
+    pub fn plan(
+        duration: Duration,
+        part_length: Duration,
+    ) -> Option<(PartsInfo, u32)> {
+        if duration <= part_length {
+            return None;
+        }
+        let overlap = PART_OVERLAP.as_secs();
+        let step = part_length.as_secs() - overlap;
+        let rest = duration.as_secs_f64() - part_length.as_secs_f64();
+        let count = (rest / step as f64).ceil() as u32 + 1;
+        Some((
+            PartsInfo {
+                step: step as u32,
+                overlap: overlap as u32,
+            },
+            count,
+        ))
+    }
+
+    /// The length of every part but the last one.
+    pub fn part_length(&self) -> Duration {
+        Duration::from_secs((self.step + self.overlap).into())
+    }
+}
+
+pub fn parse_part_length(s: &str) -> Result<Duration, String> {
+    let length = duration_str::parse_std(s)?;
+    if length < MIN_PART_LENGTH {
+        return Err(format!(
+            "The parts must be at least {} minutes long",
+            MIN_PART_LENGTH.as_secs() / 60
+        ));
+    }
+    Ok(length)
+}
+
+/// The output formats of a job in parts, with the json output the stitching
+/// needs. No formats stand for all of them.
+pub fn formats_with_json(formats: &[OutputFormat]) -> Vec<OutputFormat> {
+    let mut formats = formats.to_vec();
+    if !formats.is_empty() && !formats.contains(&OutputFormat::Json) {
+        formats.push(OutputFormat::Json);
+    }
+    formats
+}
+
+/// The name of a part of the file, e.g. `meeting.part002.flac`.
+pub fn make_part_file_name(
+    file_name: &str,
+    index: u32,
+    format: TranscodeFormat,
+) -> String {
+    let name = make_transcoded_file_name(file_name, format);
+    let (stem, ext) = name.rsplit_once('.').unwrap_or((&name, ""));
+    format!("{stem}{PART_INFIX}{index:03}.{ext}")
+}
+
+/// Splits a file name made by [`make_part_file_name`], or of an output of
+/// the part, into the stem, the index of the part and the extension.
+fn parse_part_file_name(name: &str) -> Option<(&str, u32, &str)> {
+    let (rest, ext) = name.rsplit_once('.')?;
+    let (stem, index) = rest.rsplit_once(PART_INFIX)?;
+    if index.len() != 3 || !index.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((stem, index.parse().ok()?, ext))
+}
+
+/// The parts of a file, deleted when dropped.
+#[derive(Debug)]
+pub struct AudioParts {
+    _dir: TranscodeDir,
+    pub files: Vec<PathBuf>,
+}
+
+/// Cuts the audio of the file into the parts, transcoded into 16 kHz mono in
+/// the format with `ffmpeg`.
+#[tracing::instrument(level = "info")]
+pub async fn split_audio(
+    file: &Path,
+    file_name: &str,
+    parts: PartsInfo,
+    count: u32,
+    format: TranscodeFormat,
+) -> anyhow::Result<AudioParts> {
+    let dir = TranscodeDir::new().await?;
+    let mut files = Vec::with_capacity(count as usize);
+    for index in 0..count {
+        let part = dir
+            .path()
+            .join(make_part_file_name(file_name, index, format));
+        let start = Duration::from_secs((index * parts.step).into());
+        transcode_range(
+            file,
+            &part,
+            format,
+            Some((start, parts.part_length())),
+        )
+        .await?;
+        files.push(part);
+    }
+    Ok(AudioParts { _dir: dir, files })
+}
+
+/// A segment of the JSON output of Whisper; the fields not needed for the
+/// stitching are kept as they are.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Segment {
+    start: f64,
+    end: f64,
+    text: String,
+    #[serde(flatten)]
+    rest: serde_json::Map<String, serde_json::Value>,
+}
+
+/// The JSON output of Whisper.
+#[derive(Debug, Serialize, Deserialize)]
+struct Transcript {
+    text: String,
+    segments: Vec<Segment>,
+    #[serde(flatten)]
+    rest: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Shifts the segments of every part by the start of the part, and keeps the
+/// segments of the overlaps from the part they start in the first half of
+/// the overlap.
+fn stitch_segments(parts: Vec<Vec<Segment>>, info: PartsInfo) -> Vec<Segment> {
+    let step = f64::from(info.step);
+    let half_overlap = f64::from(info.overlap) / 2.0;
+    let last = parts.len().saturating_sub(1);
+
+    let mut stitched = vec![];
+    for (i, segments) in parts.into_iter().enumerate() {
+        let offset = i as f64 * step;
+        let from = if i == 0 {
+            f64::NEG_INFINITY
+        } else {
+            offset + half_overlap
+        };
+        let to = if i == last {
+            f64::INFINITY
+        } else {
+            offset + step + half_overlap
+        };
+        for mut segment in segments {
+            segment.start += offset;
+            segment.end += offset;
+            if (from..to).contains(&segment.start) {
+                stitched.push(segment);
+            }
+        }
+    }
+    for (id, segment) in stitched.iter_mut().enumerate() {
+        segment.rest.insert("id".into(), id.into());
+    }
+    stitched
+}
+
+/// Formats the time like Whisper, e.g. `01:02:03,456`.
+fn format_timestamp(seconds: f64, decimal_marker: char) -> String {
+    let ms = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{decimal_marker}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Writes the segments in the format of the extension, like Whisper does.
+fn render(
+    segments: &[Segment],
+    transcript_rest: &serde_json::Map<String, serde_json::Value>,
+    ext: &str,
+) -> anyhow::Result<String> {
+    let mut out = String::new();
+    match ext {
+        "txt" => {
+            for segment in segments {
+                out.push_str(segment.text.trim());
+                out.push('\n');
+            }
+        },
+        "tsv" => {
+            out.push_str("start\tend\ttext\n");
+            for segment in segments {
+                out.push_str(&format!(
+                    "{}\t{}\t{}\n",
+                    (segment.start * 1000.0).round() as u64,
+                    (segment.end * 1000.0).round() as u64,
+                    segment.text.trim().replace('\t', " ")
+                ));
+            }
+        },
+        "srt" => {
+            for (i, segment) in segments.iter().enumerate() {
+                out.push_str(&format!(
+                    "{}\n{} --> {}\n{}\n\n",
+                    i + 1,
+                    format_timestamp(segment.start, ','),
+                    format_timestamp(segment.end, ','),
+                    segment.text.trim().replace("-->", "->")
+                ));
+            }
+        },
+        "vtt" => {
+            out.push_str("WEBVTT\n\n");
+            for segment in segments {
+                out.push_str(&format!(
+                    "{} --> {}\n{}\n\n",
+                    format_timestamp(segment.start, '.'),
+                    format_timestamp(segment.end, '.'),
+                    segment.text.trim().replace("-->", "->")
+                ));
+            }
+        },
+        "json" => {
+            out = serde_json::to_string(&Transcript {
+                text: segments.iter().map(|s| s.text.as_str()).collect(),
+                segments: segments.to_vec(),
+                rest: transcript_rest.clone(),
+            })?;
+        },
+        _ => anyhow::bail!("Unable to stitch the .{ext} outputs"),
+    }
+    Ok(out)
+}
+
+/// Stitches the outputs of the parts among the files into the outputs of the
+/// whole files, named without the part, and deletes the outputs of the
+/// parts. Returns the files left.
+#[tracing::instrument(level = "info", skip(files))]
+pub async fn stitch_outputs(
+    dir: &Path,
+    files: Vec<PathBuf>,
+    info: PartsInfo,
+) -> anyhow::Result<Vec<PathBuf>> {
+    // The stems with the indexes of the parts and the extensions of their
+    // outputs.
+    let mut stems: BTreeMap<String, (Vec<u32>, HashSet<String>)> =
+        BTreeMap::new();
+    let mut left = vec![];
+    for file in files {
+        let name = file.file_name().and_then(|n| n.to_str());
+        let Some((stem, index, ext)) = name.and_then(parse_part_file_name)
+        else {
+            left.push(file);
+            continue;
+        };
+        let (indexes, exts) = stems.entry(stem.to_string()).or_default();
+        if ext == "json" {
+            indexes.push(index);
+        }
+        exts.insert(ext.to_string());
+    }
+
+    for (stem, (mut indexes, exts)) in stems {
+        indexes.sort();
+        if indexes.iter().copied().ne(0..indexes.len() as u32) {
+            anyhow::bail!(
+                "The JSON outputs of some parts of {stem} are missing"
+            );
+        }
+
+        let mut parts = vec![];
+        let mut rest = serde_json::Map::new();
+        for &index in &indexes {
+            let file = dir.join(format!("{stem}{PART_INFIX}{index:03}.json"));
+            let data = tokio::fs::read(&file).await?;
+            let transcript: Transcript = serde_json::from_slice(&data)
+                .with_context(|| {
+                    format!("Invalid output {}", file.display())
+                })?;
+            if index == 0 {
+                rest = transcript.rest;
+            }
+            parts.push(transcript.segments);
+        }
+        let segments = stitch_segments(parts, info);
+
+        for ext in &exts {
+            let file = dir.join(format!("{stem}.{ext}"));
+            tokio::fs::write(&file, render(&segments, &rest, ext)?).await?;
+            tracing::info!("Stitched the parts into: {}", file.display());
+            left.push(file);
+            for &index in &indexes {
+                let part =
+                    dir.join(format!("{stem}{PART_INFIX}{index:03}.{ext}"));
+                tokio::fs::remove_file(&part).await.ok();
+            }
+        }
+    }
+
+    Ok(left)
+}
+
+#[test]
+fn plan_parts_test() {
+    let hour = Duration::from_secs(3600);
+    assert_eq!(PartsInfo::plan(hour, hour), None);
+    // Parts start every 59.5 minutes.
+    let (info, count) = PartsInfo::plan(hour * 3, hour).unwrap();
+    assert_eq!(
+        info,
+        PartsInfo {
+            step: 3570,
+            overlap: 30
+        }
+    );
+    assert_eq!(count, 4);
+    assert!((count - 1) * info.step + 3600 >= 3 * 3600);
+    assert_eq!(info.part_length(), hour);
+
+    assert!(parse_part_length("1h").is_ok());
+    assert!(parse_part_length("1m").is_err());
+}
+
+#[test]
+fn part_file_name_test() {
+    let name = make_part_file_name("talk.2024.wav", 2, TranscodeFormat::Flac);
+    assert_eq!(name, "talk.2024.part002.flac");
+    assert_eq!(
+        parse_part_file_name("talk.2024.part002.srt"),
+        Some(("talk.2024", 2, "srt"))
+    );
+    assert_eq!(parse_part_file_name("talk.2024.srt"), None);
+    assert_eq!(parse_part_file_name("a.partxyz.srt"), None);
+}
+
+#[test]
+fn stitch_segments_test() {
+    let segment = |start: f64, end: f64, text: &str| Segment {
+        start,
+        end,
+        text: text.into(),
+        rest: Default::default(),
+    };
+    let info = PartsInfo {
+        step: 100,
+        overlap: 20,
+    };
+    let stitched = stitch_segments(
+        vec![
+            vec![segment(0.0, 50.0, " a"), segment(112.0, 120.0, " b")],
+            vec![
+                segment(3.0, 8.0, " a"),
+                segment(12.0, 20.0, " b"),
+                segment(50.0, 60.0, " c"),
+            ],
+        ],
+        info,
+    );
+    assert_eq!(
+        stitched
+            .iter()
+            .map(|s| (s.start, s.text.as_str()))
+            .collect::<Vec<_>>(),
+        [(0.0, " a"), (112.0, " b"), (150.0, " c")]
+    );
+    assert_eq!(stitched[2].rest["id"], 2);
+
+    assert_eq!(
+        render(&stitched, &Default::default(), "srt").unwrap(),
+        "1\n00:00:00,000 --> 00:00:50,000\na\n\n2\n00:01:52,000 --> \
+         00:02:00,000\nb\n\n3\n00:02:30,000 --> 00:02:40,000\nc\n\n"
+    );
+    assert_eq!(
+        render(&stitched[..1], &Default::default(), "tsv").unwrap(),
+        "start\tend\ttext\n0\t50000\ta\n"
+    );
+    assert_eq!(format_timestamp(3723.4567, '.'), "01:02:03.457");
+}
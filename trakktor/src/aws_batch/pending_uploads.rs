@@ -0,0 +1,144 @@
+//! The jobs whose input files are being uploaded, recorded on the machine so
+//! that a job interrupted during the upload gets the same ID when its files
+//! are submitted again. The S3 keys of the inputs are then the same, and the
+//! interrupted multipart uploads are resumed.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use tokio::sync::Mutex;
+
+use super::job::JobUid;
+use crate::app_config::default_data_dir;
+
+const PENDING_UPLOADS_FILE: &str = "pending-uploads.json";
+
+/// Serializes the updates of the record by the tasks of the process.
+static RECORD_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// `$XDG_DATA_HOME/trakktor/pending-uploads.json` or
+/// `~/.local/share/trakktor/pending-uploads.json`.
+pub fn default_pending_uploads_path() -> Option<PathBuf> {
+    default_data_dir()
+        .ok()
+        .map(|dir| dir.join(PENDING_UPLOADS_FILE))
+}
+
+/// The input files of a job, identified by their paths, sizes and
+/// modification times. A changed file gets a new job.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InputsId(Box<str>);
+
+impl InputsId {
+    pub(crate) async fn of(files: &[PathBuf]) -> anyhow::Result<Self> {
+        let mut hasher = blake3::Hasher::new();
+        for file in files {
+            let path = tokio::fs::canonicalize(file)
+                .await
+                .unwrap_or_else(|_| file.clone());
+            hasher.update(path.as_os_str().as_encoded_bytes());
+            hasher.update(&[0]);
+            // The inputs fetched by the jobs from URLs are only named.
+            if let Ok(metadata) = tokio::fs::metadata(file).await {
+                let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+                hasher.update(&metadata.len().to_le_bytes());
+                hasher.update(&mtime.as_nanos().to_le_bytes());
+            }
+        }
+        Ok(Self(hasher.finalize().to_hex().as_str().into()))
+    }
+}
+
+/// The ID of the job of the files: the one of an interrupted upload of the
+/// same files if any, otherwise a new one, recorded until
+/// [`finish_upload`]. A new ID is not recorded without the path of the
+/// record.
+pub(crate) async fn pending_job_uid(
+    record_path: Option<&Path>,
+    inputs: &InputsId,
+) -> anyhow::Result<JobUid> {
+    let Some(path) = record_path else {
+        return Ok(JobUid::new());
+    };
+    let _lock = RECORD_LOCK.lock().await;
+    let mut pending = read_record(path).await?;
+    if let Some(jid) = pending.get(inputs.0.as_ref()) {
+        tracing::info!(job_id = %jid, "Resuming the upload of the job.");
+        return Ok(jid.clone());
+    }
+    let jid = JobUid::new();
+    pending.insert(inputs.0.clone(), jid.clone());
+    write_record(path, &pending).await?;
+    Ok(jid)
+}
+
+/// Removes the job of the uploaded files from the record.
+pub(crate) async fn finish_upload(
+    record_path: Option<&Path>,
+    inputs: &InputsId,
+) -> anyhow::Result<()> {
+    let Some(path) = record_path else {
+        return Ok(());
+    };
+    let _lock = RECORD_LOCK.lock().await;
+    let mut pending = read_record(path).await?;
+    if pending.remove(inputs.0.as_ref()).is_some() {
+        write_record(path, &pending).await?;
+    }
+    Ok(())
+}
+
+async fn read_record(path: &Path) -> anyhow::Result<HashMap<Box<str>, JobUid>> {
+    match tokio::fs::read(path).await {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            Ok(HashMap::new())
+        },
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Writes the record into a temporary file renamed over the old one, so
+/// that an interrupted write leaves the old record.
+async fn write_record(
+    path: &Path,
+    pending: &HashMap<Box<str>, JobUid>,
+) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    tokio::fs::write(&tmp_path, serde_json::to_vec(pending)?).await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn pending_job_uid_test() {
+    let dir = std::env::temp_dir()
+        .join(format!("trakktor-pending-uploads-{}", JobUid::new()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("audio.mp3");
+    std::fs::write(&file, b"audio").unwrap();
+    let record = dir.join(PENDING_UPLOADS_FILE);
+    let record = Some(record.as_path());
+
+    let inputs = InputsId::of(std::slice::from_ref(&file)).await.unwrap();
+    let jid = pending_job_uid(record, &inputs).await.unwrap();
+    assert_eq!(pending_job_uid(record, &inputs).await.unwrap(), jid);
+
+    std::fs::write(&file, b"other audio").unwrap();
+    let changed = InputsId::of(std::slice::from_ref(&file)).await.unwrap();
+    assert_ne!(changed, inputs);
+    assert_ne!(pending_job_uid(record, &changed).await.unwrap(), jid);
+
+    finish_upload(record, &inputs).await.unwrap();
+    assert_ne!(pending_job_uid(record, &inputs).await.unwrap(), jid);
+    assert_ne!(pending_job_uid(None, &inputs).await.unwrap(), jid);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,88 @@
+use crate::{
+    app_config::AppConfigProvider,
+    aws_batch::{
+        cloudformation::{
+            detect_stacks_drift, plan_cloudformation_stacks, PlanAction,
+            StackId,
+        },
+        config::{AwsConfigProvider, CloudFormationStackProvider, S3Provider},
+    },
+};
+
+#[derive(clap::Args, Debug)]
+pub struct PlanArgs {
+    /// Do not check whether the stacks were changed outside of Trakktor.
+    #[arg(long)]
+    pub skip_drift: bool,
+}
+
+/// Prints the changes the next job submission would make to the stacks, and
+/// the changes made to them outside of Trakktor.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn run_plan(
+    config: &(impl AwsConfigProvider
+          + CloudFormationStackProvider
+          + S3Provider
+          + AppConfigProvider),
+    args: &PlanArgs,
+) -> crate::Result<()> {
+    Ok(plan(config, args).await?)
+}
+
+async fn plan(
+    config: &(impl AwsConfigProvider
+          + CloudFormationStackProvider
+          + S3Provider
+          + AppConfigProvider),
+    args: &PlanArgs,
+) -> anyhow::Result<()> {
+    let plans = plan_cloudformation_stacks(
+        config,
+        [StackId::Base, StackId::GpuBatch].into(),
+    )
+    .await?;
+
+    for plan in plans {
+        match plan.action {
+            PlanAction::Create => {
+                println!("{}: will be created", plan.stack_name);
+            },
+            PlanAction::UpToDate => {
+                println!("{}: up to date", plan.stack_name);
+            },
+            PlanAction::Update(changes) if changes.is_empty() => {
+                println!("{}: no resource changes", plan.stack_name);
+            },
+            PlanAction::Update(changes) => {
+                println!("{}: will be updated", plan.stack_name);
+                for change in changes {
+                    println!("  {}", change.to_string().replace('\n', "\n  "));
+                }
+            },
+        }
+    }
+
+    if args.skip_drift {
+        return Ok(());
+    }
+
+    tracing::info!("Detecting drift of the stacks.");
+    for drift in detect_stacks_drift(config).await? {
+        if drift.resources.is_empty() {
+            println!("{}: in sync", drift.stack_name);
+            continue;
+        }
+        println!("{}: drifted", drift.stack_name);
+        for resource in drift.resources {
+            println!(
+                "  {} {} ({})",
+                resource.status, resource.logical_id, resource.resource_type
+            );
+            for (path, expected, actual) in resource.differences {
+                println!("      {path}: expected {expected}, actual {actual}");
+            }
+        }
+    }
+
+    Ok(())
+}
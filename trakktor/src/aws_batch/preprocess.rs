@@ -0,0 +1,143 @@
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{
+    app_config::AppConfigProvider,
+    aws_batch::{
+        batch::{submit_job, JobOptions},
+        cloudformation::{load_gpu_stack_outputs, StackId},
+        config::{AwsConfigProvider, CloudFormationStackProvider, S3Provider},
+        job::{
+            make_info_storage_key, make_job_prefix, parse_job_name,
+            parse_job_tag, JobInfo, JobType, JobUid, JOB_OUT_PREFIX, MAX_TAGS,
+        },
+        key_parameter::put_key_parameter,
+        preprocessor::PreprocessorJobArgs,
+        s3::put_object,
+        storage_layout::ensure_layout_version,
+        transcribe::upload_input_file,
+    },
+};
+
+#[derive(clap::Args, Debug)]
+pub struct PreprocessJobArgs {
+    /// The audio or video file to preprocess.
+    pub file: PathBuf,
+    /// Write every channel of the audio into a separate file.
+    #[arg(long)]
+    pub split_channels: bool,
+    /// A name of the job, can be used instead of the job ID.
+    #[arg(short, long, value_parser = parse_job_name)]
+    pub name: Option<Box<str>>,
+    /// Tags of the job, can be used to select the job. May be repeated.
+    #[arg(short, long = "tag", value_parser = parse_job_tag)]
+    pub tags: Vec<Box<str>>,
+}
+
+/// Uploads the file and submits a CPU job normalizing its loudness and
+/// reducing its noise. The job writes the result into its output folder as
+/// `<file>.flac`, named after the file without the extension, or as
+/// `<file>.ch<N>.flac` for every channel if the channels are split.
+///
+/// Use the `--preprocess` flag of `transcribe` to preprocess the audio of a
+/// transcription; this job is for checking the result first.
+#[tracing::instrument(level = "info", skip(config))]
+pub async fn run_preprocess_job(
+    config: Arc<
+        impl AwsConfigProvider
+            + S3Provider
+            + CloudFormationStackProvider
+            + AppConfigProvider
+            + Sync
+            + Send
+            + 'static,
+    >,
+    job: &PreprocessJobArgs,
+) -> crate::Result<()> {
+    Ok(preprocess_files(config, job).await?)
+}
+
+async fn preprocess_files(
+    config: Arc<
+        impl AwsConfigProvider
+            + S3Provider
+            + CloudFormationStackProvider
+            + AppConfigProvider
+            + Sync
+            + Send
+            + 'static,
+    >,
+    job: &PreprocessJobArgs,
+) -> anyhow::Result<()> {
+    if job.tags.len() > MAX_TAGS {
+        anyhow::bail!("At most {MAX_TAGS} tags are allowed.");
+    }
+
+    crate::aws_batch::cloudformation::manage_cloudformation_stacks(
+        &*config,
+        [StackId::Base, StackId::GpuBatch].into(),
+    )
+    .await?;
+
+    ensure_layout_version(&*config).await?;
+
+    let stack_outputs = load_gpu_stack_outputs(&*config).await?;
+    let job_queue = stack_outputs.get_cpu_job_queue()?;
+    let job_definition = stack_outputs.get_preprocess_job_definition()?;
+
+    let jid = JobUid::new();
+    let root_prefix = config.get_root_prefix();
+    tracing::info!(job_id = %jid, "Starting preprocessing job.");
+
+    let input_file = upload_input_file(&*config, &jid, &job.file).await?;
+
+    put_object(
+        &*config,
+        b"",
+        &make_info_storage_key(
+            root_prefix,
+            &jid,
+            &JobInfo {
+                job_type: JobType::Preprocess,
+                start_time: chrono::Utc::now(),
+                batch_label: None,
+                array_size: None,
+                model: None,
+                name: job.name.clone(),
+                tags: job.tags.clone(),
+                input_hash: None,
+                language: None,
+                estimated_cost: None,
+                audio_duration: None,
+                preprocessed: false,
+                after: None,
+                retry_of: None,
+                parts: None,
+            },
+        ),
+    )
+    .await?;
+
+    let encryption_key_parameter = put_key_parameter(&*config).await?;
+    submit_job(
+        &*config,
+        jid.clone(),
+        job_queue,
+        job_definition,
+        PreprocessorJobArgs {
+            job_uid: &jid,
+            job_prefix: &make_job_prefix(root_prefix, &jid),
+            input_file: Some(&input_file),
+            input_list: None,
+            output_prefix: JOB_OUT_PREFIX,
+            split_channels: job.split_channels.then_some("1"),
+            encryption_key_parameter: encryption_key_parameter.as_deref(),
+        }
+        .environments(),
+        JobOptions::default(),
+    )
+    .await?;
+
+    tracing::info!(job_id = %jid, "Preprocessing job submitted.");
+
+    Ok(())
+}
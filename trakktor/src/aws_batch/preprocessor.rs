@@ -0,0 +1,104 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::aws_batch::{batch::ContainerEnvs, job::JobUid};
+
+const VERSION_TAG: &str = "2";
+const DEV_VERSION_TAG: &str = "dev";
+const IMAGE_NAME: &str = "ghcr.io/lymar/trakktor/preprocessor";
+/// Name of the job definition of the preprocessor in the GPU batch stack. It
+/// runs in the CPU job queue of the stack.
+pub const JOB_DEFINITION_NAME: &str = "CpuPreprocessJob";
+/// The stage name of a preprocessing job chained before a transcription job.
+pub const STAGE_NAME: &str = "preprocess";
+
+pub fn make_image_name(is_dev: bool) -> String {
+    format!(
+        "{}:{}",
+        IMAGE_NAME,
+        if is_dev { DEV_VERSION_TAG } else { VERSION_TAG }
+    )
+}
+
+/// Make the name of the file the preprocessor writes for the input file, or
+/// for one of its channels if the channels are split. The preprocessed audio
+/// is stored as FLAC under the name of the input file without the extension,
+/// so the transcripts are named after the input file.
+pub fn make_preprocessed_file_name(
+    file_name: &str,
+    channel: Option<u32>,
+) -> Box<str> {
+    let stem = Path::new(file_name)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(file_name);
+    match channel {
+        Some(channel) => format!("{stem}.ch{channel}.flac").into(),
+        None => format!("{stem}.flac").into(),
+    }
+}
+
+#[test]
+fn preprocessed_file_name_test() {
+    assert_eq!(
+        make_preprocessed_file_name("meeting.mp3", None).as_ref(),
+        "meeting.flac"
+    );
+    assert_eq!(
+        make_preprocessed_file_name("call.2024.wav", Some(1)).as_ref(),
+        "call.2024.ch1.flac"
+    );
+    assert_eq!(
+        make_preprocessed_file_name("noext", None).as_ref(),
+        "noext.flac"
+    );
+}
+
+/// Arguments for a preprocessing job passed to the container as environment
+/// variables.
+#[derive(Debug, Serialize)]
+pub struct PreprocessorJobArgs<'a> {
+    #[serde(rename = "TRK_JOB_UID")]
+    pub job_uid: &'a JobUid,
+    /// Storage key prefix of all the job objects.
+    #[serde(rename = "TRK_JOB_PREFIX")]
+    pub job_prefix: &'a str,
+    /// The file to preprocess, for regular jobs.
+    #[serde(
+        rename = "TRK_INPUT_FILE",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub input_file: Option<&'a str>,
+    /// The list of files to preprocess, for array jobs.
+    #[serde(
+        rename = "TRK_INPUT_LIST",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub input_list: Option<&'a str>,
+    /// The folder of the job the preprocessed files are written to: `in/`
+    /// when the job prepares the input of a transcription, `out/` otherwise.
+    /// The done flag is only written in the latter case.
+    #[serde(rename = "TRK_OUTPUT_PREFIX")]
+    pub output_prefix: &'a str,
+    /// Write every channel of the audio into a separate file.
+    #[serde(
+        rename = "TRK_SPLIT_CHANNELS",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub split_channels: Option<&'a str>,
+    /// The SSM parameter holding the key of the client-side encryption, see
+    /// [`super::key_parameter`].
+    #[serde(
+        rename = "TRK_ENCRYPTION_KEY_PARAMETER",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub encryption_key_parameter: Option<&'a str>,
+}
+
+impl<'a> PreprocessorJobArgs<'a> {
+    /// Convert the arguments into a list of environment variables.
+    pub fn environments(&self) -> ContainerEnvs {
+        ContainerEnvs::from_args(self)
+    }
+}
@@ -0,0 +1,136 @@
+//! Probing of the input files with `ffprobe`, so the files that can't be
+//! transcribed fail before they are uploaded.
+
+use std::{path::Path, time::Duration};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+/// What `ffprobe` tells about an input file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioProbe {
+    pub duration: Duration,
+    /// The bit rate of the whole file, in bits per second.
+    pub bit_rate: Option<u64>,
+    /// The channels of the first audio stream.
+    pub channels: u32,
+    /// The codec of the first audio stream.
+    pub codec: Box<str>,
+    /// Whether the file contains a video, which is uploaded along with the
+    /// audio.
+    pub has_video: bool,
+}
+
+#[derive(Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    format: Option<ProbeFormat>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    channels: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+    duration: Option<String>,
+    bit_rate: Option<String>,
+}
+
+/// Probe the audio or video file, or its URL, with `ffprobe`. A file without
+/// audio fails with [`crate::Error::Audio`].
+#[tracing::instrument(level = "debug")]
+pub async fn probe_audio(path: &Path) -> anyhow::Result<AudioProbe> {
+    let output = tokio::process::Command::new("ffprobe")
+        .args(["-v", "error", "-of", "json"])
+        .args([
+            "-show_entries",
+            "format=duration,bit_rate:stream=codec_type,codec_name,channels",
+        ])
+        .arg(path)
+        .output()
+        .await
+        .context("Failed to run ffprobe, is it installed?")?;
+    if !output.status.success() {
+        return Err(crate::Error::Audio {
+            path: path.to_path_buf(),
+            reason: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
+
+    parse_probe(path, &output.stdout)
+}
+
+fn parse_probe(path: &Path, output: &[u8]) -> anyhow::Result<AudioProbe> {
+    let audio_error = |reason: &str| crate::Error::Audio {
+        path: path.to_path_buf(),
+        reason: reason.to_string(),
+    };
+
+    let output: ProbeOutput = serde_json::from_slice(output)?;
+    let audio = output
+        .streams
+        .iter()
+        .find(|s| s.codec_type.as_deref() == Some("audio"))
+        .ok_or_else(|| audio_error("no audio stream"))?;
+    let format = output.format.ok_or_else(|| audio_error("unknown format"))?;
+    let seconds: f64 = format
+        .duration
+        .and_then(|d| d.parse().ok())
+        .ok_or_else(|| audio_error("unknown duration"))?;
+
+    Ok(AudioProbe {
+        duration: Duration::try_from_secs_f64(seconds)?,
+        bit_rate: format.bit_rate.and_then(|b| b.parse().ok()),
+        channels: audio.channels.unwrap_or(1),
+        codec: audio.codec_name.as_deref().unwrap_or("unknown").into(),
+        has_video: output
+            .streams
+            .iter()
+            .any(|s| s.codec_type.as_deref() == Some("video")),
+    })
+}
+
+#[test]
+fn parse_probe_test() {
+    let path = Path::new("talk.mp4");
+    let probe = parse_probe(
+        path,
+        br#"{
+            "programs": [],
+            "streams": [
+                {"codec_name": "h264", "codec_type": "video"},
+                {"codec_name": "aac", "codec_type": "audio", "channels": 2}
+            ],
+            "format": {"duration": "62.500000", "bit_rate": "1200000"}
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        probe,
+        AudioProbe {
+            duration: Duration::from_millis(62_500),
+            bit_rate: Some(1_200_000),
+            channels: 2,
+            codec: "aac".into(),
+            has_video: true,
+        }
+    );
+
+    let err = parse_probe(
+        path,
+        br#"{"streams": [{"codec_name": "png", "codec_type": "video"}],
+             "format": {"duration": "1.0"}}"#,
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<crate::Error>(),
+        Some(crate::Error::Audio { .. })
+    ));
+    assert!(parse_probe(path, b"{}").is_err());
+}
@@ -0,0 +1,76 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{Local, Utc};
+
+use crate::aws_batch::{
+    config::{AwsConfigProvider, S3Provider},
+    delete::delete_jobs,
+    select::load_stored_jobs,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct PruneArgs {
+    /// Delete the jobs started longer ago than this (e.g. `30d`, `12h`).
+    #[arg(long, value_parser = parse_duration)]
+    pub older_than: Duration,
+    /// Also delete the jobs that are not done, they may still be running.
+    #[arg(long)]
+    pub include_unfinished: bool,
+    /// Only list the jobs that would be deleted.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    duration_str::parse_std(s)
+}
+
+/// Delete the old jobs. Unlike the lifecycle rules of the bucket, all the
+/// objects of a job are deleted at once.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn do_prune(
+    config: Arc<impl AwsConfigProvider + S3Provider + Sync + Send + 'static>,
+    args: &PruneArgs,
+) -> anyhow::Result<()> {
+    let cutoff = Utc::now() - chrono::Duration::from_std(args.older_than)?;
+
+    let jobs = load_stored_jobs(&*config)
+        .await?
+        .into_iter()
+        .filter(|j| j.info.start_time < cutoff)
+        .filter(|j| j.is_done || args.include_unfinished)
+        .collect::<Vec<_>>();
+
+    if jobs.is_empty() {
+        tracing::info!("No jobs to prune.");
+        return Ok(());
+    }
+
+    for job in &jobs {
+        println!(
+            "{} {}{}{}",
+            job.job_id,
+            job.info
+                .start_time
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M"),
+            job.info
+                .name
+                .as_deref()
+                .map(|n| format!(" {n}"))
+                .unwrap_or_default(),
+            if job.is_done { "" } else { " (unfinished)" },
+        );
+    }
+
+    if args.dry_run {
+        tracing::info!("{} jobs would be deleted.", jobs.len());
+        return Ok(());
+    }
+
+    let count = jobs.len();
+    delete_jobs(config, jobs.into_iter().map(|j| j.job_id).collect()).await?;
+    tracing::info!("Deleted {count} jobs.");
+
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+use aws_sdk_batch::{types::JobStatus, Client};
+
+use super::config::AwsConfigProvider;
+
+/// The start of a job on a new instance when no recent jobs are known: the
+/// instance boots and pulls the image.
+const DEFAULT_COLD_START: Duration = Duration::from_secs(10 * 60);
+/// The start of a job on a running instance when no recent jobs are known.
+const DEFAULT_WARM_START: Duration = Duration::from_secs(30);
+/// The recent jobs the start latencies are taken from.
+const RECENT_JOBS: usize = 20;
+
+/// The state of the compute environments of a queue.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct QueueState {
+    desired_vcpus: i32,
+    max_vcpus: i32,
+    /// The jobs waiting for an instance, the submitted ones included.
+    runnable_jobs: usize,
+}
+
+/// The estimated time until a submitted job starts.
+#[derive(Debug, PartialEq, Eq)]
+struct WaitEstimate {
+    wait: Duration,
+    /// No instance is running, so one has to boot first.
+    cold_start: bool,
+    /// The environment cannot grow, so the jobs wait for the running ones.
+    at_capacity: bool,
+}
+
+/// Estimates the wait from the state of the queue and the times the recent
+/// jobs waited to start. A cold start takes about as long as the slowest
+/// recent start, a warm one as the typical one.
+fn estimate_wait(state: &QueueState, latencies: &[Duration]) -> WaitEstimate {
+    let mut latencies = latencies.to_vec();
+    latencies.sort();
+    let cold_start = state.desired_vcpus == 0;
+    let wait = if cold_start {
+        latencies
+            .last()
+            .copied()
+            .unwrap_or(DEFAULT_COLD_START)
+            .max(DEFAULT_WARM_START)
+    } else {
+        latencies
+            .get(latencies.len() / 2)
+            .copied()
+            .unwrap_or(DEFAULT_WARM_START)
+    };
+    WaitEstimate {
+        wait,
+        cold_start,
+        at_capacity: state.max_vcpus > 0 &&
+            state.desired_vcpus >= state.max_vcpus &&
+            state.runnable_jobs > 0,
+    }
+}
+
+async fn load_queue_state(
+    client: &Client,
+    queue: &str,
+) -> anyhow::Result<QueueState> {
+    let queues = client
+        .describe_job_queues()
+        .job_queues(queue)
+        .send()
+        .await?;
+    let environments = queues
+        .job_queues()
+        .iter()
+        .flat_map(|q| q.compute_environment_order())
+        .filter_map(|order| order.compute_environment())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if environments.is_empty() {
+        anyhow::bail!("Queue {queue} has no compute environments.");
+    }
+
+    let mut state = QueueState::default();
+    let res = client
+        .describe_compute_environments()
+        .set_compute_environments(Some(environments))
+        .send()
+        .await?;
+    for resources in res
+        .compute_environments()
+        .iter()
+        .filter_map(|env| env.compute_resources())
+    {
+        state.desired_vcpus += resources.desiredv_cpus().unwrap_or(0);
+        state.max_vcpus += resources.maxv_cpus().unwrap_or(0);
+    }
+
+    state.runnable_jobs = client
+        .list_jobs()
+        .job_queue(queue)
+        .job_status(JobStatus::Runnable)
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<Vec<_>, _>>()
+        .await?
+        .len();
+    Ok(state)
+}
+
+/// The times the latest started jobs of the queue waited to start.
+async fn load_start_latencies(
+    client: &Client,
+    queue: &str,
+) -> anyhow::Result<Vec<Duration>> {
+    let mut started = vec![];
+    for status in [JobStatus::Running, JobStatus::Succeeded] {
+        let jobs = client
+            .list_jobs()
+            .job_queue(queue)
+            .job_status(status)
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<Vec<_>, _>>()
+            .await?;
+        started.extend(jobs.into_iter().filter_map(|job| {
+            let created_at = job.created_at()?;
+            let started_at = job.started_at()?;
+            let latency = u64::try_from(started_at - created_at).ok()?;
+            Some((created_at, Duration::from_millis(latency)))
+        }));
+    }
+    started.sort_by_key(|&(created_at, _)| std::cmp::Reverse(created_at));
+    Ok(started
+        .into_iter()
+        .take(RECENT_JOBS)
+        .map(|(_, latency)| latency)
+        .collect())
+}
+
+/// Logs the estimated time until the jobs just submitted to the queue start,
+/// and warns when the compute environment is at its maximum capacity. The
+/// estimate is only informational, so a failure to make it is logged too.
+#[tracing::instrument(level = "debug", skip(config))]
+pub async fn report_queue_wait(config: &impl AwsConfigProvider, queue: &str) {
+    let client = Client::new(config.get_aws_config());
+    let res = async {
+        let state = load_queue_state(&client, queue).await?;
+        let latencies = load_start_latencies(&client, queue).await?;
+        anyhow::Ok((state, latencies))
+    }
+    .await;
+    let (state, latencies) = match res {
+        Ok(res) => res,
+        Err(err) => {
+            tracing::warn!("Failed to estimate the wait for the jobs: {err}");
+            return;
+        },
+    };
+    tracing::debug!(?state, ?latencies, "Loaded the state of the queue.");
+
+    let estimate = estimate_wait(&state, &latencies);
+    let minutes = estimate.wait.as_secs().div_ceil(60);
+    if estimate.cold_start {
+        tracing::info!(
+            "No instance is running, the jobs should start in about {minutes} \
+             min after one boots."
+        );
+    } else {
+        tracing::info!("The jobs should start in about {minutes} min.");
+    }
+    if estimate.at_capacity {
+        tracing::warn!(
+            runnable_jobs = state.runnable_jobs,
+            "The compute environment is at its maximum capacity, the jobs \
+             wait for the running ones to finish. Consider transcribing \
+             locally with `local-docker`."
+        );
+    }
+}
+
+#[test]
+fn estimate_wait_test() {
+    let latencies = [60, 30, 600, 45].map(Duration::from_secs);
+    let cold = QueueState::default();
+    assert_eq!(
+        estimate_wait(&cold, &latencies),
+        WaitEstimate {
+            wait: Duration::from_secs(600),
+            cold_start: true,
+            at_capacity: false,
+        }
+    );
+    assert_eq!(estimate_wait(&cold, &[]).wait, DEFAULT_COLD_START);
+
+    let full = QueueState {
+        desired_vcpus: 8,
+        max_vcpus: 8,
+        runnable_jobs: 3,
+    };
+    assert_eq!(
+        estimate_wait(&full, &latencies),
+        WaitEstimate {
+            wait: Duration::from_secs(60),
+            cold_start: false,
+            at_capacity: true,
+        }
+    );
+    let busy = QueueState {
+        runnable_jobs: 0,
+        ..full
+    };
+    assert!(!estimate_wait(&busy, &latencies).at_capacity);
+}
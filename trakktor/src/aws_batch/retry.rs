@@ -0,0 +1,498 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::Context;
+use aws_sdk_batch::types::JobStatus;
+use tokio::{sync::Semaphore, task::JoinHandle};
+
+use crate::{
+    app_config::AppConfigProvider,
+    aws_batch::{
+        batch::{submit_job, DependsOn, JobOptions},
+        cloudformation::{
+            load_all_batch_jobs, load_gpu_stack_outputs, StackId,
+        },
+        config::{AwsConfigProvider, CloudFormationStackProvider, S3Provider},
+        job::{
+            make_info_storage_key, make_job_prefix, JobInfo, JobSelector,
+            JobType, JobUid, JOB_INPUT_LIST, JOB_IN_PREFIX,
+            JOB_PREPROCESSED_LIST,
+        },
+        key_parameter::put_key_parameter,
+        parts::formats_with_json,
+        preprocessor::{
+            self, make_preprocessed_file_name, PreprocessorJobArgs,
+        },
+        s3::{copy_object, get_object, list_objects, put_object},
+        select::{load_stored_jobs, select_single_job},
+        storage_layout::ensure_layout_version,
+        transcribe::abandon_if_cancelled,
+        whisper::{Model, OutputFormat, WhisperJobArgs},
+    },
+    cancellation,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct RetryArgs {
+    /// Failed job to retry, by ID, name, or tag (`tag:<tag>`).
+    #[arg(value_parser = JobSelector::parse_job_selector)]
+    pub job: JobSelector,
+    /// The Whisper model to use instead of the model of the failed job.
+    #[arg(short, long, value_enum)]
+    pub model: Option<Model>,
+    /// The language of the audio, instead of the language of the failed job.
+    #[arg(short, long)]
+    pub language: Option<Box<str>>,
+    /// Words the audio is likely to contain, spelled as they should be
+    /// transcribed. The hint of the failed job is not stored.
+    #[arg(long)]
+    pub hint: Option<Box<str>>,
+    /// Output formats to produce, comma-separated. The formats of the failed
+    /// job are not stored, so all the formats are produced if not specified.
+    #[arg(short, long = "format", value_enum, value_delimiter = ',')]
+    pub formats: Vec<OutputFormat>,
+    /// Output formats to store compressed with zstd, comma-separated.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub compress: Vec<OutputFormat>,
+}
+
+/// The input the failed job transcribed, found from its objects.
+#[derive(Debug, PartialEq)]
+enum RetryInput {
+    /// A single file, every channel of which is transcribed separately if
+    /// the channels are split.
+    File {
+        name: Box<str>,
+        channels: Option<u32>,
+    },
+    /// The files of the input list of an array job.
+    List,
+}
+
+/// Submits a new transcription job for the input files of a failed one. The
+/// files are copied within the bucket instead of being uploaded again. The
+/// new job is preprocessed like the failed one, and keeps its tags and batch
+/// label, but not its name.
+#[tracing::instrument(level = "info", skip(config))]
+pub async fn run_retry_job(
+    config: Arc<
+        impl AwsConfigProvider
+            + S3Provider
+            + CloudFormationStackProvider
+            + AppConfigProvider
+            + Sync
+            + Send
+            + 'static,
+    >,
+    args: &RetryArgs,
+) -> crate::Result<()> {
+    Ok(retry_job(config, args).await?)
+}
+
+async fn retry_job(
+    config: Arc<
+        impl AwsConfigProvider
+            + S3Provider
+            + CloudFormationStackProvider
+            + AppConfigProvider
+            + Sync
+            + Send
+            + 'static,
+    >,
+    args: &RetryArgs,
+) -> anyhow::Result<()> {
+    let stored_jobs = load_stored_jobs(&*config).await?;
+    let failed_jid = select_single_job(&stored_jobs, &args.job)?;
+    let failed = stored_jobs
+        .into_iter()
+        .find(|sj| sj.job_id == failed_jid)
+        .context("Job not found.")?;
+    if failed.info.job_type != JobType::Transcribe {
+        anyhow::bail!("Only transcription jobs can be retried.");
+    }
+    if failed.is_done {
+        anyhow::bail!("The job succeeded, there is nothing to retry.");
+    }
+    let status = load_all_batch_jobs(&*config)
+        .await?
+        .into_values()
+        .flatten()
+        .find(|j| j.job_name.as_deref() == Some(failed_jid.as_ref()))
+        .and_then(|j| j.status);
+    if let Some(status) = status.filter(|s| *s != JobStatus::Failed) {
+        anyhow::bail!(
+            "The job is {status} in AWS Batch, only failed jobs can be \
+             retried."
+        );
+    }
+
+    let model = args
+        .model
+        .or(failed.info.model)
+        .context("The model of the job is unknown, use --model.")?;
+    let language: Box<str> = args
+        .language
+        .clone()
+        .or_else(|| failed.info.language.clone())
+        .context("The language of the job is unknown, use --language.")?;
+
+    crate::aws_batch::cloudformation::manage_cloudformation_stacks(
+        &*config,
+        [StackId::Base, StackId::GpuBatch].into(),
+    )
+    .await?;
+
+    ensure_layout_version(&*config).await?;
+
+    let root_prefix = config.get_root_prefix();
+    let failed_prefix = make_job_prefix(root_prefix, &failed_jid);
+    let objects = list_objects(&*config, &failed_prefix)
+        .await?
+        .filter_map(|o| o.strip_prefix(failed_prefix.as_ref()).map(Into::into))
+        .collect::<Vec<String>>();
+    let input = find_retry_input(
+        &failed.info,
+        &objects.iter().map(String::as_str).collect::<Vec<_>>(),
+    )?;
+
+    // The lists are copied along with the files, the new job reads them
+    // under its own prefix.
+    let mut copied = objects
+        .iter()
+        .filter(|o| {
+            o.as_str() == JOB_INPUT_LIST || o.as_str() == JOB_PREPROCESSED_LIST
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    match &input {
+        RetryInput::File { name, .. } => {
+            copied.push(format!("{JOB_IN_PREFIX}{name}"));
+        },
+        RetryInput::List => {
+            let list = get_object(
+                &*config,
+                &format!("{failed_prefix}{JOB_INPUT_LIST}"),
+            )
+            .await?
+            .context("The input list of the job is missing.")?;
+            copied.extend(
+                String::from_utf8(list)?
+                    .lines()
+                    .map(|name| format!("{JOB_IN_PREFIX}{name}")),
+            );
+        },
+    }
+
+    let jid = JobUid::new();
+    let job_prefix = make_job_prefix(root_prefix, &jid);
+    tracing::info!(
+        job_id = %jid,
+        retry_of = %failed_jid,
+        files = copied.len(),
+        "Copying the input of the failed job."
+    );
+    copy_objects(Arc::clone(&config), &failed_prefix, &job_prefix, copied)
+        .await?;
+    abandon_if_cancelled(&*config, &jid).await?;
+
+    let stack_outputs = load_gpu_stack_outputs(&*config).await?;
+    let job_queue = stack_outputs.job_queue.as_str();
+    let job_definition = stack_outputs.get_whisper_job_definition(model)?;
+
+    let array_size = match &input {
+        RetryInput::File { channels, .. } => *channels,
+        RetryInput::List => failed.info.array_size,
+    };
+    put_object(
+        &*config,
+        b"",
+        &make_info_storage_key(
+            root_prefix,
+            &jid,
+            &JobInfo {
+                job_type: JobType::Transcribe,
+                start_time: chrono::Utc::now(),
+                batch_label: failed.info.batch_label.clone(),
+                array_size,
+                model: Some(model),
+                name: None,
+                tags: failed.info.tags.clone(),
+                input_hash: failed.info.input_hash.clone(),
+                language: Some(language.clone()),
+                // The estimate depends on the model.
+                estimated_cost: failed
+                    .info
+                    .estimated_cost
+                    .filter(|_| failed.info.model == Some(model)),
+                audio_duration: failed.info.audio_duration,
+                preprocessed: failed.info.preprocessed,
+                after: None,
+                retry_of: Some(failed_jid.clone()),
+                parts: failed.info.parts,
+            },
+        ),
+    )
+    .await?;
+
+    let encryption_key_parameter = put_key_parameter(&*config).await?;
+    let mut preprocess_job_id = None;
+    if failed.info.preprocessed {
+        let (input_file, input_list) = match &input {
+            RetryInput::File { name, .. } => (Some(name.as_ref()), None),
+            RetryInput::List => (None, Some(JOB_INPUT_LIST)),
+        };
+        preprocess_job_id = Some(
+            submit_job(
+                &*config,
+                jid.clone(),
+                stack_outputs.get_cpu_job_queue()?,
+                stack_outputs.get_preprocess_job_definition()?,
+                PreprocessorJobArgs {
+                    job_uid: &jid,
+                    job_prefix: &job_prefix,
+                    input_file,
+                    input_list,
+                    output_prefix: JOB_IN_PREFIX,
+                    split_channels: matches!(
+                        input,
+                        RetryInput::File {
+                            channels: Some(_),
+                            ..
+                        }
+                    )
+                    .then_some("1"),
+                    encryption_key_parameter: encryption_key_parameter
+                        .as_deref(),
+                }
+                .environments(),
+                JobOptions {
+                    array_size: matches!(input, RetryInput::List)
+                        .then_some(array_size)
+                        .flatten(),
+                    stage: Some(preprocessor::STAGE_NAME),
+                    ..Default::default()
+                },
+            )
+            .await?,
+        );
+    }
+
+    let preprocessed_file;
+    let (input_file, input_list) = match &input {
+        RetryInput::File {
+            channels: Some(_), ..
+        } => (None, Some(JOB_PREPROCESSED_LIST)),
+        RetryInput::File { name, .. } if failed.info.preprocessed => {
+            preprocessed_file = make_preprocessed_file_name(name, None);
+            (Some(preprocessed_file.as_ref()), None)
+        },
+        RetryInput::File { name, .. } => (Some(name.as_ref()), None),
+        RetryInput::List if failed.info.preprocessed => {
+            (None, Some(JOB_PREPROCESSED_LIST))
+        },
+        RetryInput::List => (None, Some(JOB_INPUT_LIST)),
+    };
+    submit_job(
+        &*config,
+        jid.clone(),
+        job_queue,
+        job_definition,
+        WhisperJobArgs {
+            job_uid: &jid,
+            job_prefix: &job_prefix,
+            input_file,
+            input_list,
+            input_url: None,
+            language: &language,
+            initial_prompt: args.hint.as_deref(),
+            model: model.get_name(),
+            output_formats: match failed.info.parts {
+                Some(_) => {
+                    OutputFormat::join(&formats_with_json(&args.formats))
+                },
+                None => OutputFormat::join(&args.formats),
+            }
+            .as_deref(),
+            compress_formats: OutputFormat::join(&args.compress).as_deref(),
+            encryption_key_parameter: encryption_key_parameter.as_deref(),
+        }
+        .environments(),
+        JobOptions {
+            array_size,
+            depends_on: preprocess_job_id
+                .as_deref()
+                .map(|job_id| DependsOn {
+                    job_id,
+                    n_to_n: matches!(input, RetryInput::List),
+                })
+                .into_iter()
+                .collect(),
+            stage: None,
+        },
+    )
+    .await?;
+
+    tracing::info!(
+        job_id = %jid,
+        retry_of = %failed_jid,
+        "Transcription job submitted."
+    );
+
+    Ok(())
+}
+
+/// Copies the objects, given relative to the prefix of the failed job, under
+/// the prefix of the new job.
+async fn copy_objects(
+    config: Arc<impl AwsConfigProvider + S3Provider + Sync + Send + 'static>,
+    from_prefix: &str,
+    to_prefix: &str,
+    objects: Vec<String>,
+) -> anyhow::Result<()> {
+    let par_sem = Arc::new(Semaphore::new(config.get_limits().s3_transfers));
+    let cancel = config.get_cancellation_token();
+    let mut tasks: Vec<JoinHandle<anyhow::Result<()>>> = Vec::new();
+    for object in objects {
+        let config = Arc::clone(&config);
+        let par_sem = Arc::clone(&par_sem);
+        let cancel = cancel.clone();
+        let from_key = format!("{from_prefix}{object}");
+        let to_key = format!("{to_prefix}{object}");
+        tasks.push(tokio::spawn(async move {
+            let _permit = par_sem.acquire().await?;
+            cancellation::check(&cancel)?;
+            copy_object(&*config, &from_key, &to_key)
+                .await
+                .with_context(|| format!("Failed to copy {from_key}"))
+        }));
+    }
+
+    let mut res = Ok(());
+    for task in tasks {
+        if let Err(err) = task.await? {
+            if res.is_ok() {
+                res = Err(err);
+            }
+        }
+    }
+    res
+}
+
+/// Finds the input of the failed job among its objects, given relative to
+/// its prefix. The input folder of a preprocessed job also contains the
+/// preprocessed files, named after the input file.
+fn find_retry_input(
+    info: &JobInfo,
+    objects: &[&str],
+) -> anyhow::Result<RetryInput> {
+    if objects.contains(&JOB_INPUT_LIST) {
+        return Ok(RetryInput::List);
+    }
+
+    let files = objects
+        .iter()
+        .filter_map(|o| o.strip_prefix(JOB_IN_PREFIX))
+        .collect::<Vec<_>>();
+    let inputs = files
+        .iter()
+        .filter(|&&file| {
+            !info.preprocessed ||
+                !files.iter().any(|&other| is_preprocessed_from(file, other))
+        })
+        .collect::<Vec<_>>();
+    let [name] = inputs.as_slice() else {
+        anyhow::bail!(
+            "Unable to find the input file of the job, found {} files.",
+            inputs.len()
+        );
+    };
+
+    Ok(RetryInput::File {
+        name: (**name).into(),
+        channels: info.array_size,
+    })
+}
+
+/// Whether the file is written by the preprocessing of the other file, see
+/// [`make_preprocessed_file_name`].
+fn is_preprocessed_from(file: &str, other: &str) -> bool {
+    if file == other {
+        return false;
+    }
+    if file == make_preprocessed_file_name(other, None).as_ref() {
+        return true;
+    }
+    let stem = Path::new(other)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(other);
+    file.strip_prefix(stem)
+        .and_then(|rest| rest.strip_prefix(".ch"))
+        .and_then(|rest| rest.strip_suffix(".flac"))
+        .is_some_and(|ch| {
+            !ch.is_empty() && ch.bytes().all(|b| b.is_ascii_digit())
+        })
+}
+
+#[test]
+fn find_retry_input_test() {
+    let mut info = JobInfo {
+        job_type: JobType::Transcribe,
+        start_time: chrono::Utc::now(),
+        batch_label: None,
+        array_size: None,
+        model: Some(Model::Large),
+        name: None,
+        tags: vec![],
+        input_hash: None,
+        language: Some("en".into()),
+        estimated_cost: None,
+        audio_duration: None,
+        preprocessed: false,
+        after: None,
+        retry_of: None,
+        parts: None,
+    };
+    let file = |name: &str, channels| RetryInput::File {
+        name: name.into(),
+        channels,
+    };
+
+    assert_eq!(
+        find_retry_input(&info, &["in/meeting.mp3", "error.🚜-report"])
+            .unwrap(),
+        file("meeting.mp3", None)
+    );
+    assert!(find_retry_input(&info, &["error.🚜-report"]).is_err());
+    assert_eq!(
+        find_retry_input(&info, &[JOB_INPUT_LIST, "in/a.mp3", "in/b.mp3"])
+            .unwrap(),
+        RetryInput::List
+    );
+
+    info.preprocessed = true;
+    assert_eq!(
+        find_retry_input(&info, &["in/meeting.flac", "in/meeting.mp3"])
+            .unwrap(),
+        file("meeting.mp3", None)
+    );
+    // A FLAC input is overwritten by its preprocessed file.
+    assert_eq!(
+        find_retry_input(&info, &["in/meeting.flac"]).unwrap(),
+        file("meeting.flac", None)
+    );
+
+    info.array_size = Some(2);
+    assert_eq!(
+        find_retry_input(
+            &info,
+            &[
+                JOB_PREPROCESSED_LIST,
+                "in/call.ch0.flac",
+                "in/call.ch1.flac",
+                "in/call.wav",
+            ]
+        )
+        .unwrap(),
+        file("call.wav", Some(2))
+    );
+}
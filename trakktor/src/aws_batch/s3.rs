@@ -1,4 +1,4 @@
-use std::{path::Path, sync::Arc, time::Duration};
+use std::{collections::HashMap, path::Path, sync::Arc, time::Duration};
 
 use anyhow::{anyhow, bail};
 use aws_config::timeout::TimeoutConfig;
@@ -7,19 +7,48 @@ use aws_sdk_s3::{
     operation::create_multipart_upload::CreateMultipartUploadOutput,
     primitives::ByteStream,
     types::{
-        CompletedMultipartUpload, CompletedPart, Delete, ObjectIdentifier,
+        ChecksumAlgorithm, CompletedMultipartUpload, CompletedPart, Delete,
+        MultipartUpload, ObjectIdentifier,
     },
     Client,
 };
 use aws_smithy_types::{body::SdkBody, byte_stream::Length};
-use tokio::{fs::File, io::AsyncWriteExt, sync::Semaphore, task::JoinHandle};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::Semaphore,
+    task::JoinHandle,
+};
 use tracing::{info_span, Instrument};
 
-use super::config::{AwsConfigProvider, S3Provider};
+use super::{
+    config::{AwsConfigProvider, S3Provider},
+    encryption::{EncryptionKey, ObjectCipher, ENCRYPTION_METADATA, TAG_LEN},
+};
+use crate::cancellation::cancellable;
 
 const CHUNK_SIZE: u64 = 1024 * 1024 * 5;
-const PARALLEL_UPLOADS: usize = 4;
-const PARALLEL_DOWNLOADS: usize = 4;
+const MAX_DELETE_OBJECTS: usize = 1000;
+const PART_UPLOAD_ATTEMPTS: usize = 3;
+/// Content type of the objects encrypted on the client side.
+const ENCRYPTED_CONTENT_TYPE: &str = "application/octet-stream";
+/// The objects belong to the user of the bucket, so they must not be stored
+/// by shared caches when accessed via presigned links.
+const CACHE_CONTROL: &str = "private, max-age=3600";
+/// The checksum algorithm of the plaintext multipart uploads, which can be
+/// resumed. The encrypted ones use another algorithm, to tell them apart in
+/// the listing of the uploads, which does not show their metadata.
+const RESUMABLE_UPLOAD_CHECKSUM: ChecksumAlgorithm = ChecksumAlgorithm::Sha256;
+const ENCRYPTED_UPLOAD_CHECKSUM: ChecksumAlgorithm = ChecksumAlgorithm::Crc32C;
+
+/// Receives the progress of the object transfers. Several transfers may be
+/// in progress at the same time, they are identified by the object key.
+pub trait TransferProgress: Send + Sync {
+    fn start(&self, s3_key: &str, total_bytes: u64);
+    fn advance(&self, s3_key: &str, bytes: u64);
+    fn finish(&self, s3_key: &str);
+}
 
 fn get_client(config: &impl AwsConfigProvider, long_op: bool) -> Client {
     let mut s3_config =
@@ -43,6 +72,43 @@ fn get_client(config: &impl AwsConfigProvider, long_op: bool) -> Client {
     Client::from_conf(s3_config.build())
 }
 
+/// Guess the content type of the object by the extension of its key.
+fn guess_content_type(s3_key: &str) -> String {
+    let ext = Path::new(s3_key)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let mime = match ext.as_deref() {
+        // Whisper outputs that are missing or wrong in the mime database.
+        Some("srt") => "application/x-subrip".to_string(),
+        Some("vtt") => "text/vtt".to_string(),
+        Some("tsv") => "text/tab-separated-values".to_string(),
+        Some("zst") => "application/zstd".to_string(),
+        _ => mime_guess::from_path(s3_key)
+            .first_or_octet_stream()
+            .to_string(),
+    };
+    if mime.starts_with("text/") {
+        format!("{mime}; charset=utf-8")
+    } else {
+        mime
+    }
+}
+
+#[test]
+fn guess_content_type_test() {
+    assert_eq!(guess_content_type("job/in/audio.mp3"), "audio/mpeg");
+    assert_eq!(
+        guess_content_type("job/out/audio.VTT"),
+        "text/vtt; charset=utf-8"
+    );
+    assert_eq!(guess_content_type("job/out/audio.json"), "application/json");
+    assert_eq!(
+        guess_content_type("job/done.🚜-flag"),
+        "application/octet-stream"
+    );
+}
+
 #[tracing::instrument(level = "debug", skip(config))]
 pub async fn upload_file(
     config: &(impl AwsConfigProvider + S3Provider),
@@ -57,19 +123,12 @@ pub async fn upload_file(
     tracing::debug!("Uploading file to S3.");
 
     let bucket_name = Arc::new(config.get_bucket_name().to_string());
-
-    let multipart_upload_res: CreateMultipartUploadOutput = client
-        .create_multipart_upload()
-        .bucket(bucket_name.as_str())
-        .key(s3_key.as_str())
-        .send()
-        .await?;
-    let upload_id = Arc::new(
-        multipart_upload_res
-            .upload_id()
-            .ok_or_else(|| anyhow!("empty upload id"))?
-            .to_string(),
-    );
+    let progress = config.get_transfer_progress();
+    let cipher = config
+        .get_encryption_key()
+        .map(ObjectCipher::new)
+        .transpose()?
+        .map(Arc::new);
 
     let file_size = tokio::fs::metadata(file_path.as_ref()).await?.len();
 
@@ -77,6 +136,53 @@ pub async fn upload_file(
         bail!("Bad file size.");
     }
 
+    // Encrypted uploads are not resumed, the parts of an interrupted upload
+    // were encrypted with an object key that is lost.
+    let checksum_algorithm = match cipher {
+        Some(_) => ENCRYPTED_UPLOAD_CHECKSUM,
+        None => RESUMABLE_UPLOAD_CHECKSUM,
+    };
+    let interrupted_upload = take_multipart_upload(
+        &client,
+        &bucket_name,
+        &s3_key,
+        cipher.is_none(),
+    )
+    .await?;
+
+    let (upload_id, uploaded_parts) = match interrupted_upload {
+        Some(upload_id) => {
+            let parts =
+                list_uploaded_parts(&client, &bucket_name, &s3_key, &upload_id)
+                    .await?;
+            tracing::info!(parts = parts.len(), "Resuming interrupted upload.");
+            (upload_id, parts)
+        },
+        None => {
+            let mut req = client
+                .create_multipart_upload()
+                .bucket(bucket_name.as_str())
+                .key(s3_key.as_str())
+                .cache_control(CACHE_CONTROL)
+                .checksum_algorithm(checksum_algorithm.clone());
+            req = match &cipher {
+                Some(cipher) => req
+                    .content_type(ENCRYPTED_CONTENT_TYPE)
+                    .metadata(ENCRYPTION_METADATA, cipher.metadata()),
+                None => req.content_type(guess_content_type(&s3_key)),
+            };
+            let multipart_upload_res: CreateMultipartUploadOutput =
+                req.send().await?;
+            let upload_id = multipart_upload_res
+                .upload_id()
+                .ok_or_else(|| anyhow!("empty upload id"))?
+                .to_string();
+            (upload_id, HashMap::new())
+        },
+    };
+    let upload_id = Arc::new(upload_id);
+    let uploaded_parts = Arc::new(uploaded_parts);
+
     let mut chunk_count = (file_size / CHUNK_SIZE) + 1;
     let mut size_of_last_chunk = file_size % CHUNK_SIZE;
     if size_of_last_chunk == 0 {
@@ -84,95 +190,660 @@ pub async fn upload_file(
         chunk_count -= 1;
     }
 
+    if let Some(progress) = &progress {
+        progress.start(&s3_key, file_size);
+    }
+
     let mut parts: Vec<JoinHandle<anyhow::Result<CompletedPart>>> = Vec::new();
 
-    let par_sem = Arc::new(Semaphore::new(PARALLEL_UPLOADS));
+    let par_sem = Arc::new(Semaphore::new(config.get_limits().s3_transfers));
+    // A cancelled upload is left unfinished, to be resumed by the next upload
+    // of the file, unless it is encrypted.
+    let cancel = config.get_cancellation_token();
 
     for chunk_index in 0..chunk_count {
         let bucket_name = Arc::clone(&bucket_name);
         let file_path = Arc::clone(&file_path);
         let s3_key = Arc::clone(&s3_key);
         let upload_id = Arc::clone(&upload_id);
+        let uploaded_parts = Arc::clone(&uploaded_parts);
+        let cipher = cipher.clone();
         let par_sem = Arc::clone(&par_sem);
+        let progress = progress.clone();
         let client = client.clone();
+        let cancel = cancel.clone();
+        let checksum_algorithm = checksum_algorithm.clone();
         let span = info_span!("chunk upload", chunk_index);
-        parts.push(tokio::spawn(
-            async move {
-                let _permit = par_sem.acquire().await?;
+        let task = async move {
+            let _permit = par_sem.acquire().await?;
+
+            let this_chunk = if chunk_count - 1 == chunk_index {
+                size_of_last_chunk
+            } else {
+                CHUNK_SIZE
+            };
+            let offset = chunk_index * CHUNK_SIZE;
+            // Chunk index needs to start at 0, but part numbers start at 1.
+            let part_number = (chunk_index as i32) + 1;
+
+            let (e_tag, checksum) = match uploaded_parts.get(&part_number) {
+                Some(part)
+                    if is_same_part(part, &file_path, offset, this_chunk)
+                        .await? =>
+                {
+                    tracing::debug!("already uploaded");
+                    (part.e_tag.clone(), part.checksum_sha256.clone())
+                },
+                _ => {
+                    tracing::debug!("uploading");
+                    let body = match &cipher {
+                        Some(cipher) => {
+                            let mut chunk =
+                                read_chunk(&file_path, offset, this_chunk)
+                                    .await?;
+                            cipher.seal_chunk(
+                                chunk_index,
+                                chunk_index == chunk_count - 1,
+                                &mut chunk,
+                            )?;
+                            PartBody::Data(chunk)
+                        },
+                        None => PartBody::File {
+                            path: &file_path,
+                            offset,
+                            size: this_chunk,
+                        },
+                    };
+                    upload_part(
+                        &client,
+                        &bucket_name,
+                        &s3_key,
+                        &upload_id,
+                        part_number,
+                        &checksum_algorithm,
+                        &body,
+                    )
+                    .await?
+                },
+            };
+
+            if let Some(progress) = &progress {
+                progress.advance(&s3_key, this_chunk);
+            }
 
-                tracing::debug!("uploading");
-                let this_chunk = if chunk_count - 1 == chunk_index {
-                    size_of_last_chunk
-                } else {
-                    CHUNK_SIZE
-                };
-                let stream = ByteStream::read_from()
-                    .path(file_path.as_ref())
-                    .offset(chunk_index * CHUNK_SIZE)
-                    .length(Length::Exact(this_chunk))
-                    .build()
-                    .await?;
-                // Chunk index needs to start at 0, but part numbers start at 1.
-                let part_number = (chunk_index as i32) + 1;
-                let upload_part_res = client
-                    .upload_part()
-                    .key(s3_key.as_str())
-                    .bucket(bucket_name.as_str())
-                    .upload_id(upload_id.as_str())
-                    .body(stream)
-                    .part_number(part_number)
-                    .send()
-                    .await?;
-                Ok(CompletedPart::builder()
-                    .e_tag(upload_part_res.e_tag.unwrap_or_default())
-                    .part_number(part_number)
-                    .build())
+            let part =
+                CompletedPart::builder().e_tag(e_tag).part_number(part_number);
+            Ok(match checksum_algorithm {
+                ENCRYPTED_UPLOAD_CHECKSUM => {
+                    part.set_checksum_crc32_c(checksum)
+                },
+                _ => part.set_checksum_sha256(checksum),
             }
-            .instrument(span),
+            .build())
+        }
+        .instrument(span);
+        parts.push(tokio::spawn(
+            async move { cancellable(&cancel, task).await },
         ));
     }
 
-    let mut upload_parts: Vec<CompletedPart> = Vec::new();
-    for part in parts {
-        upload_parts.push(part.await??);
+    let res = async {
+        let mut upload_parts: Vec<CompletedPart> = Vec::new();
+        for part in &mut parts {
+            upload_parts.push(part.await??);
+        }
+
+        let completed_multipart_upload: CompletedMultipartUpload =
+            CompletedMultipartUpload::builder()
+                .set_parts(Some(upload_parts))
+                .build();
+
+        client
+            .complete_multipart_upload()
+            .bucket(config.get_bucket_name())
+            .key(s3_key.as_str())
+            .multipart_upload(completed_multipart_upload)
+            .upload_id(upload_id.as_str())
+            .send()
+            .await?;
+        anyhow::Ok(())
+    }
+    .await;
+    if let Err(err) = res {
+        // The parts of an encrypted upload are not resumed, so they would
+        // only take the storage.
+        if cipher.is_some() {
+            parts.iter().for_each(JoinHandle::abort);
+            abort_multipart_upload(&client, &bucket_name, &s3_key, &upload_id)
+                .await;
+        }
+        return Err(err);
     }
 
-    let completed_multipart_upload: CompletedMultipartUpload =
-        CompletedMultipartUpload::builder()
-            .set_parts(Some(upload_parts))
-            .build();
+    if let Some(progress) = &progress {
+        progress.finish(&s3_key);
+    }
 
-    client
-        .complete_multipart_upload()
-        .bucket(config.get_bucket_name())
-        .key(s3_key.as_str())
-        .multipart_upload(completed_multipart_upload)
-        .upload_id(upload_id.as_str())
+    tracing::debug!("Upload complete.");
+
+    Ok(())
+}
+
+/// A part of an interrupted multipart upload.
+#[derive(Debug)]
+struct UploadedPart {
+    size: u64,
+    e_tag: String,
+    /// Only the uploads started with the checksum algorithm have it.
+    checksum_sha256: Option<String>,
+}
+
+/// Find an interrupted multipart upload of the object to resume, if
+/// `resume`, and abort the other uploads of the object, which would be left
+/// unfinished.
+async fn take_multipart_upload(
+    client: &Client,
+    bucket_name: &str,
+    s3_key: &str,
+    resume: bool,
+) -> anyhow::Result<Option<String>> {
+    let mut uploads = Vec::new();
+    let mut key_marker = None;
+    let mut upload_id_marker = None;
+    loop {
+        let page = client
+            .list_multipart_uploads()
+            .bucket(bucket_name)
+            .prefix(s3_key)
+            .set_key_marker(key_marker)
+            .set_upload_id_marker(upload_id_marker)
+            .send()
+            .await?;
+        uploads.extend(
+            page.uploads()
+                .iter()
+                .filter(|u| u.key() == Some(s3_key))
+                .cloned(),
+        );
+        if !page.is_truncated().unwrap_or(false) {
+            break;
+        }
+        key_marker = page.next_key_marker().map(str::to_string);
+        upload_id_marker = page.next_upload_id_marker().map(str::to_string);
+    }
+
+    let (resumed, abandoned) = select_resumed_upload(&uploads, resume);
+    for upload_id in abandoned {
+        abort_multipart_upload(client, bucket_name, s3_key, upload_id).await;
+    }
+    Ok(resumed.map(str::to_string))
+}
+
+/// Selects the latest plaintext upload to resume, if `resume`, and returns
+/// the IDs of the rest of the uploads.
+fn select_resumed_upload(
+    uploads: &[MultipartUpload],
+    resume: bool,
+) -> (Option<&str>, Vec<&str>) {
+    let resumed = uploads
+        .iter()
+        .filter(|u| {
+            resume &&
+                u.checksum_algorithm() == Some(&RESUMABLE_UPLOAD_CHECKSUM)
+        })
+        .max_by_key(|u| u.initiated().copied())
+        .and_then(|u| u.upload_id());
+    let abandoned = uploads
+        .iter()
+        .filter_map(|u| u.upload_id())
+        .filter(|&id| Some(id) != resumed)
+        .collect();
+    (resumed, abandoned)
+}
+
+#[test]
+fn select_resumed_upload_test() {
+    use aws_smithy_types::DateTime;
+
+    let upload = |id: &str, checksum: ChecksumAlgorithm, initiated: i64| {
+        MultipartUpload::builder()
+            .upload_id(id)
+            .checksum_algorithm(checksum)
+            .initiated(DateTime::from_secs(initiated))
+            .build()
+    };
+    let uploads = [
+        upload("old", RESUMABLE_UPLOAD_CHECKSUM, 1),
+        upload("encrypted", ENCRYPTED_UPLOAD_CHECKSUM, 3),
+        upload("new", RESUMABLE_UPLOAD_CHECKSUM, 2),
+    ];
+    assert_eq!(
+        select_resumed_upload(&uploads, true),
+        (Some("new"), vec!["old", "encrypted"])
+    );
+    assert_eq!(
+        select_resumed_upload(&uploads, false),
+        (None, vec!["old", "encrypted", "new"])
+    );
+}
+
+/// Abort the multipart upload, so its parts are deleted. A failure is only
+/// logged, as it does not affect the upload of the object.
+async fn abort_multipart_upload(
+    client: &Client,
+    bucket_name: &str,
+    s3_key: &str,
+    upload_id: &str,
+) {
+    let res = client
+        .abort_multipart_upload()
+        .bucket(bucket_name)
+        .key(s3_key)
+        .upload_id(upload_id)
         .send()
-        .await?;
+        .await;
+    match res {
+        Ok(_) => tracing::debug!(upload_id, "Aborted multipart upload."),
+        Err(err) => {
+            tracing::warn!(upload_id, "Failed to abort multipart upload: {err}")
+        },
+    }
+}
 
-    tracing::debug!("Upload complete.");
+async fn list_uploaded_parts(
+    client: &Client,
+    bucket_name: &str,
+    s3_key: &str,
+    upload_id: &str,
+) -> anyhow::Result<HashMap<i32, UploadedPart>> {
+    let mut parts = HashMap::new();
+    let mut pages = client
+        .list_parts()
+        .bucket(bucket_name)
+        .key(s3_key)
+        .upload_id(upload_id)
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        for part in page?.parts() {
+            if let (Some(number), Some(size), Some(e_tag)) =
+                (part.part_number(), part.size(), part.e_tag())
+            {
+                parts.insert(
+                    number,
+                    UploadedPart {
+                        size: size as u64,
+                        e_tag: e_tag.to_string(),
+                        checksum_sha256: part
+                            .checksum_sha256()
+                            .map(|c| c.to_string()),
+                    },
+                );
+            }
+        }
+    }
+    Ok(parts)
+}
+
+/// Check that the uploaded part has the contents of the file chunk by its
+/// SHA256 checksum. The ETag is not the MD5 hash of the contents with
+/// SSE-KMS, so the parts without the checksum are uploaded again.
+async fn is_same_part(
+    part: &UploadedPart,
+    file_path: &Path,
+    offset: u64,
+    size: u64,
+) -> anyhow::Result<bool> {
+    let Some(checksum) = &part.checksum_sha256 else {
+        return Ok(false);
+    };
+    if part.size != size {
+        return Ok(false);
+    }
+
+    let chunk = read_chunk(file_path, offset, size).await?;
+    Ok(*checksum == sha256_checksum(&chunk))
+}
+
+/// The base64 SHA256 hash, as in the checksums of S3.
+fn sha256_checksum(data: &[u8]) -> String {
+    STANDARD.encode(ring::digest::digest(&ring::digest::SHA256, data))
+}
+
+#[test]
+fn sha256_checksum_test() {
+    assert_eq!(
+        sha256_checksum(b"abc"),
+        "ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0="
+    );
+}
+
+async fn read_chunk(
+    file_path: &Path,
+    offset: u64,
+    size: u64,
+) -> anyhow::Result<Vec<u8>> {
+    let mut file = File::open(file_path).await?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    let mut chunk = vec![0; size as usize];
+    file.read_exact(&mut chunk).await?;
+    Ok(chunk)
+}
+
+/// Contents of a part of a multipart upload.
+enum PartBody<'a> {
+    /// A chunk of the file, read when the part is sent.
+    File {
+        path: &'a Path,
+        offset: u64,
+        size: u64,
+    },
+    Data(Vec<u8>),
+}
+
+impl PartBody<'_> {
+    async fn to_stream(&self) -> anyhow::Result<ByteStream> {
+        Ok(match self {
+            PartBody::File { path, offset, size } => {
+                ByteStream::read_from()
+                    .path(path)
+                    .offset(*offset)
+                    .length(Length::Exact(*size))
+                    .build()
+                    .await?
+            },
+            PartBody::Data(data) => ByteStream::from(data.clone()),
+        })
+    }
+}
+
+/// Upload a part of the multipart upload, retrying failed attempts, and
+/// return its ETag and checksum of the algorithm.
+async fn upload_part(
+    client: &Client,
+    bucket_name: &str,
+    s3_key: &str,
+    upload_id: &str,
+    part_number: i32,
+    checksum_algorithm: &ChecksumAlgorithm,
+    body: &PartBody<'_>,
+) -> anyhow::Result<(String, Option<String>)> {
+    let mut attempt = 1;
+    loop {
+        let stream = body.to_stream().await?;
+        let res = client
+            .upload_part()
+            .key(s3_key)
+            .bucket(bucket_name)
+            .upload_id(upload_id)
+            .body(stream)
+            .part_number(part_number)
+            .checksum_algorithm(checksum_algorithm.clone())
+            .send()
+            .await;
+        match res {
+            Ok(res) => {
+                let checksum = match *checksum_algorithm {
+                    ENCRYPTED_UPLOAD_CHECKSUM => res.checksum_crc32_c,
+                    _ => res.checksum_sha256,
+                };
+                return Ok((res.e_tag.unwrap_or_default(), checksum));
+            },
+            Err(err) if attempt < PART_UPLOAD_ATTEMPTS => {
+                tracing::warn!(attempt, "Part upload failed, retrying: {err}");
+                attempt += 1;
+            },
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Encrypt the data in chunks of the multipart upload size, so all the
+/// objects have the same format.
+fn seal_data(cipher: &ObjectCipher, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let chunks = data.chunks(CHUNK_SIZE as usize).collect::<Vec<_>>();
+    let mut res = Vec::with_capacity(data.len() + chunks.len() * TAG_LEN);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let mut chunk = chunk.to_vec();
+        cipher.seal_chunk(i as u64, i == chunks.len() - 1, &mut chunk)?;
+        res.append(&mut chunk);
+    }
+    Ok(res)
+}
 
+fn open_data(cipher: &ObjectCipher, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut decryptor = StreamDecryptor::new(cipher);
+    let mut res = decryptor.push(data)?;
+    res.append(&mut decryptor.finish()?);
+    Ok(res)
+}
+
+#[test]
+fn seal_data_test() -> anyhow::Result<()> {
+    let key =
+        EncryptionKey::parse("AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=")?;
+    let cipher = ObjectCipher::new(&key)?;
+    let data = vec![42; CHUNK_SIZE as usize + 10];
+    let sealed = seal_data(&cipher, &data)?;
+    assert_eq!(sealed.len(), data.len() + 2 * TAG_LEN);
+    assert_eq!(open_data(&cipher, &sealed)?, data);
+
+    let truncated = &sealed[..CHUNK_SIZE as usize + TAG_LEN];
+    assert!(open_data(&cipher, truncated).is_err());
     Ok(())
 }
 
+/// Decrypts an object as it is downloaded.
+struct StreamDecryptor<'a> {
+    cipher: &'a ObjectCipher,
+    buf: Vec<u8>,
+    index: u64,
+}
+
+impl<'a> StreamDecryptor<'a> {
+    const SEALED_CHUNK_SIZE: usize = CHUNK_SIZE as usize + TAG_LEN;
+
+    fn new(cipher: &'a ObjectCipher) -> Self {
+        StreamDecryptor {
+            cipher,
+            buf: vec![],
+            index: 0,
+        }
+    }
+
+    /// Add the downloaded data and return the data decrypted so far. A full
+    /// chunk is held back until more data arrives, as the last chunk is
+    /// decrypted differently.
+    fn push(&mut self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        self.buf.extend_from_slice(data);
+        let mut res = vec![];
+        while self.buf.len() > Self::SEALED_CHUNK_SIZE {
+            let rest = self.buf.split_off(Self::SEALED_CHUNK_SIZE);
+            let mut chunk = std::mem::replace(&mut self.buf, rest);
+            self.cipher.open_chunk(self.index, false, &mut chunk)?;
+            self.index += 1;
+            res.append(&mut chunk);
+        }
+        Ok(res)
+    }
+
+    fn finish(mut self) -> anyhow::Result<Vec<u8>> {
+        self.cipher.open_chunk(self.index, true, &mut self.buf)?;
+        Ok(self.buf)
+    }
+}
+
+/// Get the cipher of the object if it was encrypted on the client side.
+fn get_object_cipher(
+    key: Option<&EncryptionKey>,
+    s3_key: &str,
+    metadata: Option<&HashMap<String, String>>,
+) -> anyhow::Result<Option<ObjectCipher>> {
+    let Some(value) = metadata.and_then(|m| m.get(ENCRYPTION_METADATA)) else {
+        return Ok(None);
+    };
+    let key = key.ok_or_else(|| {
+        anyhow!("{s3_key} is encrypted, the encryption key is required")
+    })?;
+    Ok(Some(ObjectCipher::from_metadata(key, value)?))
+}
+
+/// Put the object, encrypting it if the encryption key is set. Empty
+/// objects are used as markers, so they are never encrypted.
 #[tracing::instrument(level = "debug", skip(config, data))]
 pub async fn put_object(
     config: &(impl AwsConfigProvider + S3Provider),
     data: &[u8],
     s3_key: &str,
+) -> anyhow::Result<()> {
+    match config.get_encryption_key() {
+        Some(key) if !data.is_empty() => {
+            let cipher = ObjectCipher::new(key)?;
+            let data = seal_data(&cipher, data)?;
+            put_object_impl(config, &data, s3_key, Some(&cipher)).await
+        },
+        _ => put_object_impl(config, data, s3_key, None).await,
+    }
+}
+
+/// Put the object without encryption, for the data that has to be readable
+/// without the encryption key.
+#[tracing::instrument(level = "debug", skip(config, data))]
+pub async fn put_plain_object(
+    config: &(impl AwsConfigProvider + S3Provider),
+    data: &[u8],
+    s3_key: &str,
+) -> anyhow::Result<()> {
+    put_object_impl(config, data, s3_key, None).await
+}
+
+async fn put_object_impl(
+    config: &(impl AwsConfigProvider + S3Provider),
+    data: &[u8],
+    s3_key: &str,
+    cipher: Option<&ObjectCipher>,
 ) -> anyhow::Result<()> {
     let client = get_client(config, false);
 
-    client
+    let mut req = client
         .put_object()
         .bucket(config.get_bucket_name())
         .key(s3_key)
-        .body(ByteStream::new(SdkBody::from(data)))
+        .cache_control(CACHE_CONTROL)
+        .body(ByteStream::new(SdkBody::from(data)));
+    req = match cipher {
+        Some(cipher) => req
+            .content_type(ENCRYPTED_CONTENT_TYPE)
+            .metadata(ENCRYPTION_METADATA, cipher.metadata()),
+        None => req.content_type(guess_content_type(s3_key)),
+    };
+    req.send().await.map_err(|err| crate::Error::S3 {
+        key: s3_key.to_string(),
+        source: Box::new(err.into()),
+    })?;
+
+    tracing::debug!("Put object complete.");
+
+    Ok(())
+}
+
+/// Get the contents of the object, or `None` if the object does not exist.
+#[tracing::instrument(level = "debug", skip(config))]
+pub async fn get_object(
+    config: &(impl AwsConfigProvider + S3Provider),
+    s3_key: &str,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let res = get_client(config, false)
+        .get_object()
+        .bucket(config.get_bucket_name())
+        .key(s3_key)
+        .send()
+        .await;
+
+    match res {
+        Ok(object) => {
+            let cipher = get_object_cipher(
+                config.get_encryption_key(),
+                s3_key,
+                object.metadata(),
+            )?;
+            let data = object.body.collect().await?.to_vec();
+            Ok(Some(match cipher {
+                Some(cipher) => open_data(&cipher, &data)?,
+                None => data,
+            }))
+        },
+        Err(err)
+            if err
+                .as_service_error()
+                .map(|e| e.is_no_such_key())
+                .unwrap_or(false) =>
+        {
+            Ok(None)
+        },
+        Err(err) => Err(crate::Error::S3 {
+            key: s3_key.to_string(),
+            source: Box::new(err.into()),
+        }
+        .into()),
+    }
+}
+
+/// Copy the object within the bucket. Objects larger than 5 GB can't be
+/// copied this way.
+#[tracing::instrument(level = "debug", skip(config))]
+pub async fn copy_object(
+    config: &(impl AwsConfigProvider + S3Provider),
+    from_s3_key: &str,
+    to_s3_key: &str,
+) -> anyhow::Result<()> {
+    get_client(config, true)
+        .copy_object()
+        .bucket(config.get_bucket_name())
+        .copy_source(encode_copy_source(config.get_bucket_name(), from_s3_key))
+        .key(to_s3_key)
         .send()
         .await?;
 
-    tracing::debug!("Put object complete.");
+    Ok(())
+}
+
+/// The copy source is passed in a header, so the key must be URL-encoded.
+fn encode_copy_source(bucket: &str, s3_key: &str) -> String {
+    let mut res = format!("{bucket}/");
+    for b in s3_key.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~/".contains(&b) {
+            res.push(b as char);
+        } else {
+            res.push_str(&format!("%{b:02X}"));
+        }
+    }
+    res
+}
+
+#[test]
+fn encode_copy_source_test() {
+    assert_eq!(
+        encode_copy_source("bucket", "job/done.🚜-flag"),
+        "bucket/job/done.%F0%9F%9A%9C-flag"
+    );
+    assert_eq!(
+        encode_copy_source("bucket", "in/a b.mp3"),
+        "bucket/in/a%20b.mp3"
+    );
+}
+
+#[tracing::instrument(level = "debug", skip(config))]
+pub async fn delete_object(
+    config: &(impl AwsConfigProvider + S3Provider),
+    s3_key: &str,
+) -> anyhow::Result<()> {
+    get_client(config, false)
+        .delete_object()
+        .bucket(config.get_bucket_name())
+        .key(s3_key)
+        .send()
+        .await?;
 
     Ok(())
 }
@@ -192,8 +863,7 @@ pub async fn list_objects(
         .await?
         .into_iter()
         .filter_map(|res| res.contents)
-        .map(|i| i.into_iter())
-        .flatten()
+        .flat_map(|i| i.into_iter())
         .filter_map(|o| o.key)
         .filter(|k| !k.ends_with('/')))
 }
@@ -207,27 +877,30 @@ pub async fn download_folder(
 ) -> anyhow::Result<()> {
     let client = get_client(config, false);
     let bucket_name = Arc::new(config.get_bucket_name().to_string());
-    let dest_dir = Arc::new(dest_dir.to_path_buf());
-    let s3_prefix = Arc::new(s3_prefix.to_string());
-    let par_sem = Arc::new(Semaphore::new(PARALLEL_DOWNLOADS));
+    let par_sem = Arc::new(Semaphore::new(config.get_limits().s3_transfers));
+    let progress = config.get_transfer_progress();
+    let key = config.get_encryption_key().cloned();
+    let cancel = config.get_cancellation_token();
     let mut tasks: Vec<JoinHandle<anyhow::Result<()>>> = Vec::new();
 
     for obj in objs {
         let bucket_name = Arc::clone(&bucket_name);
-        let s3_prefix = Arc::clone(&s3_prefix);
-        let dest_dir = Arc::clone(&dest_dir);
         let client = client.clone();
         let par_sem = Arc::clone(&par_sem);
+        let progress = progress.clone();
+        let key = key.clone();
+        let cancel = cancel.clone();
         let span = info_span!("download object", obj);
+        let dest_path = dest_dir.join(
+            obj.strip_prefix(s3_prefix)
+                .expect("unexpected object prefix"),
+        );
 
-        tasks.push(tokio::spawn(
+        let task = {
+            let dest_path = dest_path.clone();
             async move {
                 let _permit = par_sem.acquire().await?;
 
-                let dest_path = dest_dir.join(
-                    obj.strip_prefix(s3_prefix.as_ref())
-                        .expect("unexpected object prefix"),
-                );
                 tracing::debug!(?dest_path, "downloading");
 
                 if let Some(parent) = dest_path.parent() {
@@ -238,18 +911,48 @@ pub async fn download_folder(
                 let mut object = client
                     .get_object()
                     .bucket(bucket_name.as_str())
-                    .key(obj)
+                    .key(&obj)
                     .send()
                     .await?;
 
+                let cipher =
+                    get_object_cipher(key.as_ref(), &obj, object.metadata())?;
+                let mut decryptor = cipher.as_ref().map(StreamDecryptor::new);
+
+                if let Some(progress) = &progress {
+                    progress.start(
+                        &obj,
+                        object.content_length().unwrap_or_default() as u64,
+                    );
+                }
                 while let Some(bytes) = object.body.try_next().await? {
-                    file.write_all(&bytes).await?;
+                    match &mut decryptor {
+                        Some(d) => file.write_all(&d.push(&bytes)?).await?,
+                        None => file.write_all(&bytes).await?,
+                    }
+                    if let Some(progress) = &progress {
+                        progress.advance(&obj, bytes.len() as u64);
+                    }
+                }
+                if let Some(decryptor) = decryptor {
+                    file.write_all(&decryptor.finish()?).await?;
+                }
+                if let Some(progress) = &progress {
+                    progress.finish(&obj);
                 }
 
                 Ok(())
             }
-            .instrument(span),
-        ));
+            .instrument(span)
+        };
+        tasks.push(tokio::spawn(async move {
+            let res = cancellable(&cancel, task).await;
+            if res.is_err() {
+                // Don't leave a partially written file behind.
+                let _ = tokio::fs::remove_file(&dest_path).await;
+            }
+            res
+        }));
     }
 
     for task in tasks {
@@ -276,8 +979,7 @@ pub async fn delete_dir(
         .await?
         .into_iter()
         .filter_map(|res| res.contents)
-        .map(|i| i.into_iter())
-        .flatten();
+        .flat_map(|i| i.into_iter());
 
     let mut delete_objects: Vec<ObjectIdentifier> = vec![];
     for obj in objects {
@@ -287,19 +989,22 @@ pub async fn delete_dir(
         delete_objects.push(obj_id);
     }
 
-    if !delete_objects.is_empty() {
-        get_client(config, false)
+    if delete_objects.is_empty() {
+        tracing::info!("No objects to delete.");
+    }
+
+    // A single `DeleteObjects` request accepts at most 1000 keys.
+    for chunk in delete_objects.chunks(MAX_DELETE_OBJECTS) {
+        client
             .delete_objects()
             .bucket(config.get_bucket_name())
             .delete(
                 Delete::builder()
-                    .set_objects(Some(delete_objects))
+                    .set_objects(Some(chunk.to_vec()))
                     .build()?,
             )
             .send()
             .await?;
-    } else {
-        tracing::info!("No objects to delete.");
     }
 
     Ok(())
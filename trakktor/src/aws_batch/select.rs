@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use crate::aws_batch::{
+    config::{AwsConfigProvider, S3Provider},
+    job::{is_job_done, JobInfo, JobSelector, JobUid},
+    s3::list_objects,
+};
+
+/// A job found in the storage.
+#[derive(Debug)]
+pub struct StoredJob {
+    pub job_id: JobUid,
+    pub info: JobInfo,
+    pub is_done: bool,
+}
+
+/// Load all the jobs in the storage.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn load_stored_jobs(
+    config: &(impl AwsConfigProvider + S3Provider),
+) -> anyhow::Result<Vec<StoredJob>> {
+    let root_prefix = config.get_root_prefix();
+    let mut objects: HashMap<JobUid, Vec<String>> = HashMap::new();
+    for o in list_objects(config, root_prefix).await? {
+        let Some((jid, rest)) =
+            o.strip_prefix(root_prefix).and_then(|k| k.split_once('/'))
+        else {
+            continue;
+        };
+        let Ok(jid) = JobUid::parse_job_uid(jid) else {
+            continue;
+        };
+        objects.entry(jid).or_default().push(rest.to_string());
+    }
+
+    let mut jobs = objects
+        .into_iter()
+        .filter_map(|(job_id, objects)| {
+            let info =
+                objects.iter().find_map(|o| JobInfo::deserialize(o).ok())?;
+            Some(StoredJob {
+                job_id,
+                info,
+                is_done: is_job_done(objects.iter().map(String::as_str)),
+            })
+        })
+        .collect::<Vec<_>>();
+    jobs.sort_by_key(|j| j.info.start_time);
+
+    Ok(jobs)
+}
+
+/// Resolve the selectors into the IDs of the matching jobs, without
+/// duplicates. Fails if a name or tag selector matches no jobs.
+#[tracing::instrument(level = "debug", skip(config))]
+pub async fn resolve_job_selectors(
+    config: &(impl AwsConfigProvider + S3Provider),
+    selectors: &[JobSelector],
+) -> anyhow::Result<Vec<JobUid>> {
+    let needs_info = selectors.iter().any(|s| !matches!(s, JobSelector::Id(_)));
+    let jobs = if needs_info {
+        load_stored_jobs(config).await?
+    } else {
+        vec![]
+    };
+
+    select_jobs(&jobs, selectors)
+}
+
+/// Select the IDs of the jobs matching the selectors, without duplicates. ID
+/// selectors are taken as they are, even without a matching job.
+pub fn select_jobs(
+    jobs: &[StoredJob],
+    selectors: &[JobSelector],
+) -> anyhow::Result<Vec<JobUid>> {
+    let mut jids: Vec<JobUid> = vec![];
+    for selector in selectors {
+        if let JobSelector::Id(jid) = selector {
+            if !jids.contains(jid) {
+                jids.push(jid.clone());
+            }
+            continue;
+        }
+
+        let mut found = false;
+        for job in jobs {
+            if selector.matches(&job.job_id, &job.info) {
+                found = true;
+                if !jids.contains(&job.job_id) {
+                    jids.push(job.job_id.clone());
+                }
+            }
+        }
+        if !found {
+            anyhow::bail!("No jobs found for {selector}");
+        }
+    }
+
+    Ok(jids)
+}
+
+/// Resolve the selector into the ID of a single job.
+pub async fn resolve_single_job(
+    config: &(impl AwsConfigProvider + S3Provider),
+    selector: &JobSelector,
+) -> anyhow::Result<JobUid> {
+    let jids =
+        resolve_job_selectors(config, std::slice::from_ref(selector)).await?;
+    expect_single_job(selector, jids)
+}
+
+/// Select the ID of the single job matching the selector.
+pub fn select_single_job(
+    jobs: &[StoredJob],
+    selector: &JobSelector,
+) -> anyhow::Result<JobUid> {
+    let jids = select_jobs(jobs, std::slice::from_ref(selector))?;
+    expect_single_job(selector, jids)
+}
+
+fn expect_single_job(
+    selector: &JobSelector,
+    jids: Vec<JobUid>,
+) -> anyhow::Result<JobUid> {
+    match jids.as_slice() {
+        [jid] => Ok(jid.clone()),
+        _ => anyhow::bail!(
+            "{selector} matches {} jobs: {}",
+            jids.len(),
+            jids.iter()
+                .map(|j| j.as_ref())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
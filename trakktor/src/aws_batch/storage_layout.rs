@@ -0,0 +1,179 @@
+use std::sync::Arc;
+
+use tokio::{sync::Semaphore, task::JoinHandle};
+use tracing::{info_span, Instrument};
+
+use crate::aws_batch::{
+    config::{parse_root_prefix, AwsConfigProvider, S3Provider},
+    job::JobUid,
+    s3::{
+        copy_object, delete_object, get_object, list_objects, put_plain_object,
+    },
+};
+
+/// Version of the layout of the objects in the storage. It must be increased
+/// whenever the layout changes in an incompatible way, and a migration step
+/// must be added to `migrate_storage`.
+///
+/// Version 0 is the layout without a version marker.
+pub const CURRENT_LAYOUT_VERSION: u32 = 1;
+pub const LAYOUT_VERSION_MARKER: &str = "layout.🚜-version";
+
+#[derive(clap::Args, Debug)]
+pub struct MigrateStorageArgs {
+    /// Move all the jobs stored under this key namespace into the current
+    /// one. The jobs must not be running.
+    #[arg(long, value_parser = parse_root_prefix)]
+    pub from_prefix: Option<Box<str>>,
+}
+
+/// Make a storage key for the layout version marker.
+pub fn make_layout_marker_key(root_prefix: &str) -> Box<str> {
+    format!("{}{}", root_prefix, LAYOUT_VERSION_MARKER).into()
+}
+
+/// Load the layout version of the storage, or `None` if the marker does not
+/// exist.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn load_layout_version(
+    config: &(impl AwsConfigProvider + S3Provider),
+) -> anyhow::Result<Option<u32>> {
+    let Some(data) =
+        get_object(config, &make_layout_marker_key(config.get_root_prefix()))
+            .await?
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(std::str::from_utf8(&data)?.trim().parse()?))
+}
+
+async fn write_layout_version(
+    config: &(impl AwsConfigProvider + S3Provider),
+) -> anyhow::Result<()> {
+    put_plain_object(
+        config,
+        CURRENT_LAYOUT_VERSION.to_string().as_bytes(),
+        &make_layout_marker_key(config.get_root_prefix()),
+    )
+    .await
+}
+
+/// Check that the storage layout is supported by this version of Trakktor.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn check_layout_version(
+    config: &(impl AwsConfigProvider + S3Provider),
+) -> anyhow::Result<()> {
+    match load_layout_version(config).await? {
+        Some(version) if version > CURRENT_LAYOUT_VERSION => {
+            anyhow::bail!(
+                "The storage layout version {version} is not supported by \
+                 this version of Trakktor (supported: \
+                 {CURRENT_LAYOUT_VERSION}). Please update Trakktor."
+            );
+        },
+        Some(version) if version < CURRENT_LAYOUT_VERSION => {
+            tracing::warn!(
+                "The storage layout version {version} is outdated. Please run \
+                 `migrate-storage`."
+            );
+        },
+        _ => {},
+    }
+
+    Ok(())
+}
+
+/// Mark a storage without a layout version marker with the current version.
+/// The layout without a marker only differs by the missing marker.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn ensure_layout_version(
+    config: &(impl AwsConfigProvider + S3Provider),
+) -> anyhow::Result<()> {
+    if load_layout_version(config).await?.is_none() {
+        tracing::debug!("Writing the storage layout version marker.");
+        write_layout_version(config).await?;
+    }
+
+    Ok(())
+}
+
+/// Upgrade the storage to the current layout version.
+#[tracing::instrument(level = "info", skip_all)]
+pub async fn migrate_storage(
+    config: Arc<impl AwsConfigProvider + S3Provider + Sync + Send + 'static>,
+    args: &MigrateStorageArgs,
+) -> anyhow::Result<()> {
+    let version = load_layout_version(&*config).await?.unwrap_or(0);
+    if version > CURRENT_LAYOUT_VERSION {
+        anyhow::bail!(
+            "The storage layout version {version} is newer than the version \
+             supported by this version of Trakktor."
+        );
+    }
+
+    if let Some(from_prefix) = &args.from_prefix {
+        move_jobs(Arc::clone(&config), from_prefix).await?;
+    }
+
+    if version < CURRENT_LAYOUT_VERSION {
+        tracing::info!(
+            "Upgrading the storage layout from version {version} to \
+             {CURRENT_LAYOUT_VERSION}."
+        );
+        write_layout_version(&*config).await?;
+    }
+
+    tracing::info!("The storage layout is up to date.");
+
+    Ok(())
+}
+
+/// Move the objects of all the jobs under `from_prefix` into the current
+/// namespace.
+async fn move_jobs(
+    config: Arc<impl AwsConfigProvider + S3Provider + Sync + Send + 'static>,
+    from_prefix: &str,
+) -> anyhow::Result<()> {
+    let to_prefix: Arc<str> = config.get_root_prefix().into();
+    if from_prefix == to_prefix.as_ref() {
+        anyhow::bail!("The source namespace is the current namespace.");
+    }
+
+    // Only objects that belong to jobs are moved; everything else, including
+    // the current namespace when it is nested in the source one, is kept.
+    let objects = list_objects(&*config, from_prefix)
+        .await?
+        .filter(|o| {
+            o[from_prefix.len()..]
+                .split_once('/')
+                .is_some_and(|(jid, _)| JobUid::parse_job_uid(jid).is_ok())
+        })
+        .collect::<Vec<_>>();
+    tracing::info!(count = objects.len(), "Moving job objects.");
+
+    let par_sem = Arc::new(Semaphore::new(config.get_limits().aws_requests));
+    let mut reqs: Vec<JoinHandle<anyhow::Result<()>>> = Vec::new();
+
+    for from_key in objects {
+        let to_key = format!("{}{}", to_prefix, &from_key[from_prefix.len()..]);
+        let config = Arc::clone(&config);
+        let par_sem = Arc::clone(&par_sem);
+        let span = info_span!("move object", from_key, to_key);
+        reqs.push(tokio::spawn(
+            async move {
+                let _permit = par_sem.acquire().await?;
+                copy_object(&*config, &from_key, &to_key).await?;
+                delete_object(&*config, &from_key).await?;
+                Ok(())
+            }
+            .instrument(span),
+        ));
+    }
+
+    for req in reqs {
+        req.await??;
+    }
+
+    Ok(())
+}
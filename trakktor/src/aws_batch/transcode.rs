@@ -0,0 +1,158 @@
+//! Transcoding of the input files with `ffmpeg` before they are uploaded.
+//! Whisper resamples the audio to 16 kHz mono anyway, so nothing it uses is
+//! lost.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use anyhow::Context;
+
+/// The sample rate Whisper works with.
+const SAMPLE_RATE: &str = "16000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TranscodeFormat {
+    /// Lossless, about half the size of a 16 kHz mono WAV.
+    Flac,
+    /// Opus in Ogg, far smaller, with a little loss.
+    Ogg,
+}
+
+impl TranscodeFormat {
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            TranscodeFormat::Flac => "flac",
+            TranscodeFormat::Ogg => "ogg",
+        }
+    }
+
+    fn codec_args(&self) -> &'static [&'static str] {
+        match self {
+            TranscodeFormat::Flac => &["-c:a", "flac"],
+            TranscodeFormat::Ogg => &["-c:a", "libopus", "-b:a", "32k"],
+        }
+    }
+}
+
+/// A temporary directory of the transcoded files, deleted when dropped.
+#[derive(Debug)]
+pub struct TranscodeDir(PathBuf);
+
+impl TranscodeDir {
+    pub async fn new() -> anyhow::Result<Self> {
+        let dir = std::env::temp_dir()
+            .join(format!("trakktor-transcode-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await?;
+        Ok(Self(dir))
+    }
+
+    pub fn path(&self) -> &Path { &self.0 }
+}
+
+impl Drop for TranscodeDir {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_dir_all(&self.0) {
+            tracing::warn!(dir = ?self.0, "Failed to delete: {err}");
+        }
+    }
+}
+
+/// A transcoded file, deleted when dropped.
+#[derive(Debug)]
+pub struct TranscodedFile {
+    _dir: TranscodeDir,
+    pub file: PathBuf,
+}
+
+/// The name of the transcoded file, the name of the file with the extension
+/// of the format.
+pub fn make_transcoded_file_name(
+    file_name: &str,
+    format: TranscodeFormat,
+) -> String {
+    let stem = Path::new(file_name)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(file_name);
+    format!("{stem}.{}", format.get_extension())
+}
+
+/// Transcodes the audio of the file into 16 kHz mono in the format with
+/// `ffmpeg`. Returns `None` if the result is not smaller than the file, e.g.
+/// for a file compressed already.
+#[tracing::instrument(level = "debug")]
+pub async fn transcode_file(
+    file: &Path,
+    file_name: &str,
+    format: TranscodeFormat,
+) -> anyhow::Result<Option<TranscodedFile>> {
+    let dir = TranscodeDir::new().await?;
+    let transcoded = TranscodedFile {
+        file: dir
+            .path()
+            .join(make_transcoded_file_name(file_name, format)),
+        _dir: dir,
+    };
+    transcode_range(file, &transcoded.file, format, None).await?;
+
+    let size = tokio::fs::metadata(file).await?.len();
+    let transcoded_size = tokio::fs::metadata(&transcoded.file).await?.len();
+    tracing::info!(?file, size, transcoded_size, "Transcoded the input.");
+    Ok((transcoded_size < size).then_some(transcoded))
+}
+
+/// Transcodes the audio of the file, or of its range given by the start and
+/// the length, into 16 kHz mono in the format with `ffmpeg`.
+pub async fn transcode_range(
+    file: &Path,
+    out: &Path,
+    format: TranscodeFormat,
+    range: Option<(Duration, Duration)>,
+) -> anyhow::Result<()> {
+    let mut command = tokio::process::Command::new("ffmpeg");
+    command.args(["-nostdin", "-v", "error"]);
+    if let Some((start, length)) = range {
+        command
+            .arg("-ss")
+            .arg(start.as_secs_f64().to_string())
+            .arg("-t")
+            .arg(length.as_secs_f64().to_string());
+    }
+    let output = command
+        .arg("-i")
+        .arg(file)
+        .args(["-vn", "-ac", "1", "-ar", SAMPLE_RATE])
+        .args(format.codec_args())
+        .arg(out)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .context("Failed to run ffmpeg, is it installed?")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to transcode {}: {}",
+            file.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn make_transcoded_file_name_test() {
+    assert_eq!(
+        make_transcoded_file_name("meeting.wav", TranscodeFormat::Flac),
+        "meeting.flac"
+    );
+    assert_eq!(
+        make_transcoded_file_name("call.2024.wav", TranscodeFormat::Ogg),
+        "call.2024.ogg"
+    );
+    assert_eq!(
+        make_transcoded_file_name("noext", TranscodeFormat::Flac),
+        "noext.flac"
+    );
+}
@@ -1,91 +1,1345 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
 use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use duration_str::HumanFormat;
+use tokio::{sync::Semaphore, task::JoinHandle};
+use tracing::{info_span, Instrument};
 
 use crate::{
     app_config::AppConfigProvider,
     aws_batch::{
-        batch::submit_job,
-        cloudformation::{load_gpu_stack_outputs, StackId},
+        align::{check_alignable_formats, submit_align_after, AlignAfter},
+        batch::{submit_job, DependsOn, JobOptions},
+        budget::{
+            check_budget, estimate_job_cost, format_usd, get_monthly_spending,
+            Cents,
+        },
+        cloudformation::{
+            load_gpu_stack_outputs, GpuInstanceType, NotificationTarget,
+            StackId,
+        },
         config::{AwsConfigProvider, CloudFormationStackProvider, S3Provider},
         job::{
-            make_info_storage_key, make_input_storage_key, JobInfo, JobType,
-            JobUid,
+            make_info_storage_key, make_input_list_storage_key,
+            make_input_storage_key, make_job_prefix,
+            make_preprocessed_list_storage_key, parse_batch_label,
+            parse_job_name, parse_job_tag, JobInfo, JobType, JobUid,
+            JOB_INPUT_LIST, JOB_IN_PREFIX, JOB_PREPROCESSED_LIST, MAX_TAGS,
         },
-        s3::{put_object, upload_file},
-        whisper::WhisperJobArgs,
+        key_parameter::put_key_parameter,
+        parts::{formats_with_json, parse_part_length, split_audio, PartsInfo},
+        pending_uploads::{finish_upload, pending_job_uid, InputsId},
+        preprocessor::{
+            self, make_preprocessed_file_name, PreprocessorJobArgs,
+        },
+        probe::{probe_audio, AudioProbe},
+        queue_wait::report_queue_wait,
+        s3::{delete_dir, put_object, upload_file},
+        select::load_stored_jobs,
+        storage_layout::ensure_layout_version,
+        transcode::{transcode_file, TranscodeFormat},
+        whisper::{Model, OutputFormat, WhisperJobArgs},
     },
+    cancellation,
 };
 
 #[derive(clap::Args, Debug)]
 pub struct TranscribeJobArgs {
-    /// File to transcribe.
-    pub file: std::path::PathBuf,
+    /// Files to transcribe. Directories are expanded to the files they
+    /// contain. Each file is transcribed in a separate job. An http(s) URL
+    /// of the audio, or of a page `yt-dlp` can get it from, like a podcast
+    /// episode or a YouTube video, is fetched by the job itself.
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
     /// The language of the audio.
     #[arg(short, long)]
     pub language: Box<str>,
+    /// The Whisper model to use for the transcription.
+    #[arg(short, long, value_enum, default_value_t = Model::Large)]
+    pub model: Model,
+    /// Output formats to produce, comma-separated. All the formats are
+    /// produced if not specified.
+    #[arg(short, long = "format", value_enum, value_delimiter = ',')]
+    pub formats: Vec<OutputFormat>,
+    /// Output formats to store compressed with zstd, comma-separated. They
+    /// are decompressed on download.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub compress: Vec<OutputFormat>,
+    /// A label shared by all the jobs of this submission. If not specified
+    /// and more than one file is submitted, a label is generated.
+    #[arg(short, long, value_parser = parse_batch_label)]
+    pub batch_label: Option<Box<str>>,
+    /// A name of the job, can be used instead of the job ID. Only allowed
+    /// when a single job is submitted.
+    #[arg(short, long, value_parser = parse_job_name)]
+    pub name: Option<Box<str>>,
+    /// Tags of the jobs, can be used to select the jobs. May be repeated.
+    #[arg(short, long = "tag", value_parser = parse_job_tag)]
+    pub tags: Vec<Box<str>>,
+    /// Submit all the files as a single AWS Batch array job instead of one
+    /// job per file.
+    #[arg(long)]
+    pub array: bool,
+    /// Transcribe the files even if they were already transcribed with the
+    /// same model and language.
+    #[arg(long)]
+    pub force: bool,
+    /// Submit the jobs even if their estimated cost exceeds the budget.
+    #[arg(long)]
+    pub ignore_budget: bool,
+    /// An email address or an HTTPS URL to notify when the jobs finish. The
+    /// subscription of a new target has to be confirmed first.
+    #[arg(long, value_parser = NotificationTarget::parse)]
+    pub notify: Option<NotificationTarget>,
+    /// Normalize the loudness and reduce the noise of the audio in a CPU job
+    /// before the transcription. The transcription job starts once it
+    /// succeeds.
+    #[arg(long)]
+    pub preprocess: bool,
+    /// Transcribe every channel of the audio separately, e.g. the sides of a
+    /// phone call recorded in stereo. Implies --preprocess; the job of a file
+    /// with several channels becomes an array job with a child per channel.
+    #[arg(long, conflicts_with = "array")]
+    pub split_channels: bool,
+    /// Align the words of every transcript with the audio in a job that
+    /// starts once the transcription succeeds, see the `align` command. The
+    /// json output format is required.
+    #[arg(long, conflicts_with_all = ["array", "split_channels"])]
+    pub align: bool,
+    /// Transcode the audio into 16 kHz mono in the format before the upload,
+    /// to upload less. Files that don't get smaller, like the ones compressed
+    /// already, are uploaded as they are.
+    #[arg(long, value_enum, conflicts_with = "split_channels")]
+    pub transcode: Option<TranscodeFormat>,
+    /// Split the files longer than the duration (e.g. `2h`) into parts of
+    /// that length, transcribed in parallel by the children of an array job.
+    /// The transcripts of the parts are stitched on download; the parts are
+    /// cut in the --transcode format, FLAC by default.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_part_length,
+        conflicts_with_all = ["array", "split_channels", "align", "preprocess"]
+    )]
+    pub split_longer_than: Option<Duration>,
+    /// Words the audio is likely to contain, like names and jargon, spelled
+    /// as they should be transcribed, e.g. "Kubernetes, Anya Petrova". They
+    /// are given to Whisper as the initial prompt.
+    #[arg(long)]
+    pub hint: Option<Box<str>>,
 }
 
 impl TranscribeJobArgs {
-    fn get_file_name(&self) -> anyhow::Result<&str> {
-        Ok(self
-            .file
-            .file_name()
-            .ok_or_else(|| anyhow!("Unable to get file name"))?
-            .to_str()
-            .ok_or_else(|| anyhow!("Invalid file name"))?)
+    /// Whether the audio is preprocessed before the transcription.
+    pub fn is_preprocessed(&self) -> bool {
+        self.preprocess || self.split_channels
+    }
+}
+
+pub(crate) fn get_file_name(file: &Path) -> anyhow::Result<&str> {
+    file.file_name()
+        .ok_or_else(|| anyhow!("Unable to get file name"))?
+        .to_str()
+        .ok_or_else(|| anyhow!("Invalid file name"))
+}
+
+/// The URL the input is fetched from by the job, if it is not a local file.
+pub(crate) fn input_url(file: &Path) -> Option<&str> {
+    file.to_str()
+        .filter(|f| f.starts_with("https://") || f.starts_with("http://"))
+}
+
+#[test]
+fn input_url_test() {
+    assert_eq!(
+        input_url(Path::new("https://example.com/episode.mp3")),
+        Some("https://example.com/episode.mp3")
+    );
+    assert!(input_url(Path::new("http://youtu.be/id")).is_some());
+    assert_eq!(input_url(Path::new("https.mp3")), None);
+    assert_eq!(input_url(Path::new("ftp://example.com/a.mp3")), None);
+}
+
+/// Checks that the jobs of the URLs, which have no input file until they
+/// run, are not given the options that process the input file.
+fn check_url_inputs(
+    job: &TranscribeJobArgs,
+    files: &[PathBuf],
+) -> anyhow::Result<()> {
+    if !files.iter().any(|f| input_url(f).is_some()) {
+        return Ok(());
+    }
+    if job.array || job.is_preprocessed() || job.align {
+        anyhow::bail!(
+            "URLs can't be transcribed in an array job, preprocessed or \
+             aligned, download them first."
+        );
+    }
+    Ok(())
+}
+
+/// Expands directories into the (non-hidden) files they contain, sorted by
+/// name. URLs are kept as they are.
+pub(crate) async fn collect_input_files(
+    paths: &[PathBuf],
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+
+    for path in paths {
+        if input_url(path).is_some() ||
+            !tokio::fs::metadata(path).await?.is_dir()
+        {
+            files.push(path.clone());
+            continue;
+        }
+
+        let mut dir_files = vec![];
+        let mut entries = tokio::fs::read_dir(path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let is_hidden =
+                entry.file_name().to_string_lossy().starts_with('.');
+            if entry.file_type().await?.is_file() && !is_hidden {
+                dir_files.push(entry.path());
+            }
+        }
+        dir_files.sort();
+        files.append(&mut dir_files);
     }
+
+    if files.is_empty() {
+        anyhow::bail!("No input files.");
+    }
+
+    Ok(files)
 }
 
 #[tracing::instrument(level = "info", skip(config))]
 pub async fn run_transcribe_job(
-    config: &(impl AwsConfigProvider
-          + S3Provider
-          + CloudFormationStackProvider
-          + AppConfigProvider),
+    config: Arc<
+        impl AwsConfigProvider
+            + S3Provider
+            + CloudFormationStackProvider
+            + AppConfigProvider
+            + Sync
+            + Send
+            + 'static,
+    >,
+    job: &TranscribeJobArgs,
+) -> crate::Result<()> {
+    Ok(transcribe_files(config, job).await?)
+}
+
+pub(crate) async fn transcribe_files(
+    config: Arc<
+        impl AwsConfigProvider
+            + S3Provider
+            + CloudFormationStackProvider
+            + AppConfigProvider
+            + Sync
+            + Send
+            + 'static,
+    >,
     job: &TranscribeJobArgs,
 ) -> anyhow::Result<()> {
+    let mut files = collect_input_files(&job.files).await?;
+    check_url_inputs(job, &files)?;
+    if job.array {
+        check_array_files(
+            &files,
+            job.is_preprocessed() || job.transcode.is_some(),
+        )?;
+    }
+    check_job_labels(job, files.len())?;
+    if job.align {
+        check_alignable_formats(&job.formats, &job.compress)?;
+    }
+
     crate::aws_batch::cloudformation::manage_cloudformation_stacks(
-        config,
+        &*config,
         [StackId::Base, StackId::GpuBatch].into(),
     )
     .await?;
 
-    let jid = JobUid::new();
-    tracing::info!(job_id = %jid, "Starting transcription job.");
+    ensure_layout_version(&*config).await?;
 
-    let file_name = job.get_file_name().with_context(|| {
-        format!("Could not get file name: {}", job.file.display())
-    })?;
+    if let Some(target) = &job.notify {
+        tracing::info!(
+            %target,
+            "The target is notified when the jobs finish. A new target has to \
+             confirm the subscription first."
+        );
+    }
+
+    // Array jobs have a single job info for all the files, so they are not
+    // checked for duplicates.
+    let mut input_hashes = vec![];
+    if !job.array {
+        for file in &files {
+            input_hashes.push(match input_url(file) {
+                Some(_) => None,
+                None => {
+                    Some(crate::hasher::get_file_hash_value(file).await?.into())
+                },
+            });
+        }
+        if !job.force {
+            skip_transcribed_files(
+                &*config,
+                job,
+                &mut files,
+                &mut input_hashes,
+            )
+            .await?;
+            if files.is_empty() {
+                tracing::info!("All the files are already transcribed.");
+                return Ok(());
+            }
+        }
+    }
+
+    let probes = probe_inputs(&files).await?;
+
+    let stack_outputs = load_gpu_stack_outputs(&*config).await?;
+    tracing::debug!(?stack_outputs, "Loaded GPU stack outputs.");
+
+    // Mono files are transcribed in a regular job.
+    let mut channels = Vec::with_capacity(files.len());
+    for (file, probe) in files.iter().zip(&probes) {
+        channels.push(if job.split_channels {
+            let probe = probe.as_ref().with_context(|| {
+                format!("Failed to probe the channels of {}", file.display())
+            })?;
+            Some(probe.channels).filter(|&n| n > 1)
+        } else {
+            None
+        });
+    }
+
+    let durations = probes
+        .iter()
+        .map(|p| p.as_ref().map(|p| p.duration))
+        .collect::<Vec<_>>();
+
+    // The URLs are fetched by the jobs, so only the local files are split.
+    let parts = files
+        .iter()
+        .zip(&durations)
+        .map(|(file, duration)| {
+            let part_length = job.split_longer_than?;
+            if input_url(file).is_some() {
+                return None;
+            }
+            PartsInfo::plan((*duration)?, part_length)
+        })
+        .collect::<Vec<_>>();
+    for ((file, duration), parts) in files.iter().zip(&durations).zip(&parts) {
+        if job.split_longer_than.is_some() && duration.is_none() {
+            tracing::warn!(?file, "The duration is unknown, not splitting.");
+        }
+        if let Some((_, count)) = parts {
+            tracing::info!(?file, count, "Splitting the audio into parts.");
+        }
+    }
+
+    // Every child of the job of a file with parts transcribes a part.
+    let (child_durations, children): (Vec<_>, Vec<_>) = durations
+        .iter()
+        .zip(&channels)
+        .zip(&parts)
+        .map(|((duration, channels), parts)| match parts {
+            Some((info, count)) => (Some(info.part_length()), Some(*count)),
+            None => (*duration, *channels),
+        })
+        .unzip();
+    let estimated_costs = estimate_costs(
+        &*config,
+        job,
+        stack_outputs.get_instance_type(),
+        &files,
+        &child_durations,
+        &children,
+        !job.ignore_budget,
+    )
+    .await?;
 
     let start_time = chrono::Utc::now();
 
-    upload_file(config, &job.file, &make_input_storage_key(&jid, &file_name))
-        .await?;
+    let batch_label: Option<Arc<str>> = match &job.batch_label {
+        Some(label) => Some(label.as_ref().into()),
+        None if files.len() > 1 && !job.array => {
+            Some(format!("batch-{}", start_time.format("%Y%m%d-%H%M%S")).into())
+        },
+        None => None,
+    };
+    if let Some(label) = &batch_label {
+        tracing::info!(
+            batch_label = label.as_ref(),
+            files = files.len(),
+            "Submitting a batch of transcription jobs."
+        );
+    }
+
+    let job_definition =
+        stack_outputs.get_whisper_job_definition(job.model)?.into();
+    let preprocess = if job.is_preprocessed() {
+        Some(PreprocessStage {
+            job_queue: stack_outputs.get_cpu_job_queue()?.into(),
+            job_definition: stack_outputs
+                .get_preprocess_job_definition()?
+                .into(),
+        })
+    } else {
+        None
+    };
 
-    let job_info = JobInfo {
-        job_type: JobType::Transcribe,
+    let align_job_definition = if job.align {
+        Some(stack_outputs.get_align_job_definition()?.into())
+    } else {
+        None
+    };
+
+    let submission = Submission {
         start_time,
+        language: job.language.as_ref().into(),
+        initial_prompt: job.hint.as_deref().map(Into::into),
+        batch_label,
+        model: job.model,
+        name: job.name.as_deref().map(Into::into),
+        tags: job.tags.iter().map(|t| t.as_ref().into()).collect(),
+        output_formats: OutputFormat::join(&job.formats).map(Into::into),
+        parts_output_formats: OutputFormat::join(&formats_with_json(
+            &job.formats,
+        ))
+        .map(Into::into),
+        compress_formats: OutputFormat::join(&job.compress).map(Into::into),
+        encryption_key_parameter: put_key_parameter(&*config)
+            .await?
+            .map(Into::into),
+        job_queue: stack_outputs.job_queue.into(),
+        job_definition,
+        preprocess,
+        align_job_definition,
+        transcode: job.transcode,
     };
 
-    put_object(config, b"", &make_info_storage_key(&jid, &job_info)).await?;
+    let job_queue = Arc::clone(&submission.job_queue);
+    if job.array {
+        let estimated_cost = estimated_costs.into_iter().sum();
+        let audio_duration = durations.into_iter().sum();
+        submit_array_job(
+            Arc::clone(&config),
+            files,
+            estimated_cost,
+            audio_duration,
+            submission,
+        )
+        .await?;
+    } else {
+        let files = files
+            .into_iter()
+            .zip(input_hashes)
+            .zip(estimated_costs)
+            .zip(durations)
+            .zip(channels)
+            .zip(parts)
+            .map(
+                |(
+                    (
+                        (((file, input_hash), estimated_cost), audio_duration),
+                        channels,
+                    ),
+                    parts,
+                )| {
+                    FileJob {
+                        file,
+                        input_hash,
+                        estimated_cost,
+                        audio_duration,
+                        channels,
+                        parts,
+                    }
+                },
+            )
+            .collect();
+        submit_file_jobs(Arc::clone(&config), files, submission).await?;
+    }
 
-    let stack_outputs = load_gpu_stack_outputs(config).await?;
-    tracing::debug!(?stack_outputs, "Loaded GPU stack outputs.");
+    report_queue_wait(&*config, &job_queue).await;
+    Ok(())
+}
+
+/// Checks the name and the tags given to the jobs of the files.
+pub(crate) fn check_job_labels(
+    job: &TranscribeJobArgs,
+    file_count: usize,
+) -> anyhow::Result<()> {
+    if job.name.is_some() && file_count > 1 && !job.array {
+        anyhow::bail!(
+            "A name can only be given to a single job, use tags or a batch \
+             label for several files."
+        );
+    }
+    if job.tags.len() > MAX_TAGS {
+        anyhow::bail!("At most {MAX_TAGS} tags are allowed.");
+    }
+    Ok(())
+}
+
+/// Probes the input files, so the ones without audio fail before they are
+/// uploaded. Without `ffprobe` the files are not probed, and the URLs that
+/// can't be probed, like the pages of the videos, are fetched by the jobs
+/// anyway.
+pub(crate) async fn probe_inputs(
+    files: &[PathBuf],
+) -> anyhow::Result<Vec<Option<AudioProbe>>> {
+    let mut probes = Vec::with_capacity(files.len());
+    for file in files {
+        let probe = match probe_audio(file).await {
+            Ok(probe) => probe,
+            Err(err) if input_url(file).is_some() => {
+                tracing::warn!(?file, "Failed to probe the URL: {err}");
+                probes.push(None);
+                continue;
+            },
+            Err(err) if err.is::<crate::Error>() => {
+                return Err(err.context(format!(
+                    "{} can't be transcribed",
+                    file.display()
+                )));
+            },
+            Err(err) => {
+                tracing::warn!(?file, "Failed to probe the file: {err}");
+                probes.push(None);
+                continue;
+            },
+        };
+
+        tracing::info!(
+            ?file,
+            duration = %probe.duration.human_format(),
+            bit_rate_kbps = probe.bit_rate.map(|b| b / 1000),
+            channels = probe.channels,
+            codec = probe.codec.as_ref(),
+            "Probed the input."
+        );
+        if probe.has_video && input_url(file).is_none() {
+            tracing::warn!(
+                ?file,
+                "The file contains a video, which is uploaded along with the \
+                 audio. Extract the audio to upload less."
+            );
+        }
+        probes.push(Some(probe));
+    }
+    Ok(probes)
+}
+
+/// Estimate the cost of the job of each file, and check it against the
+/// budget. Without a budget, the files that couldn't be probed get no
+/// estimate. Every channel of a file with split channels, or every part of a
+/// split file, is transcribed by a child of the job of the file, given with
+/// the duration of the audio it transcribes; the cost of preprocessing is not
+/// estimated.
+async fn estimate_costs(
+    config: &(impl AwsConfigProvider + S3Provider + AppConfigProvider),
+    job: &TranscribeJobArgs,
+    instance_type: GpuInstanceType,
+    files: &[PathBuf],
+    durations: &[Option<Duration>],
+    children: &[Option<u32>],
+    check: bool,
+) -> anyhow::Result<Vec<Option<Cents>>> {
+    let job_budget = config.get_job_budget().filter(|_| check);
+    let monthly_budget = config.get_monthly_budget().filter(|_| check);
+    let has_budget = job_budget.is_some() || monthly_budget.is_some();
+
+    let mut costs = Vec::with_capacity(files.len());
+    for ((file, duration), children) in
+        files.iter().zip(durations).zip(children)
+    {
+        match duration {
+            Some(duration) => costs.push(Some(
+                estimate_job_cost(job.model, instance_type, *duration) *
+                    children.unwrap_or(1),
+            )),
+            None if has_budget => {
+                anyhow::bail!(
+                    "Failed to estimate the cost of {}, use --ignore-budget \
+                     to submit it anyway",
+                    file.display()
+                );
+            },
+            None => costs.push(None),
+        }
+    }
+
+    let known = costs.iter().flatten().copied().collect::<Vec<_>>();
+    tracing::info!("The estimated cost is {}.", format_usd(known.iter().sum()));
+    if !has_budget {
+        return Ok(costs);
+    }
+
+    // An array job runs on the budget of a single job.
+    let job_costs = if job.array {
+        vec![known.iter().sum()]
+    } else {
+        known
+    };
+    let monthly = match monthly_budget {
+        Some(budget) => Some((
+            budget,
+            get_monthly_spending(&load_stored_jobs(config).await?),
+        )),
+        None => None,
+    };
+    check_budget(&job_costs, job_budget, monthly)?;
+
+    Ok(costs)
+}
+
+/// Removes the files that were already transcribed by a completed job with
+/// the same parameters, pointing the user to the results instead.
+async fn skip_transcribed_files(
+    config: &(impl AwsConfigProvider + S3Provider),
+    job: &TranscribeJobArgs,
+    files: &mut Vec<PathBuf>,
+    input_hashes: &mut Vec<Option<Box<str>>>,
+) -> anyhow::Result<()> {
+    let stored_jobs = load_stored_jobs(config).await?;
+
+    let mut i = 0;
+    while i < files.len() {
+        let existing = stored_jobs.iter().find(|sj| {
+            sj.is_done &&
+                sj.info.input_hash.is_some() &&
+                sj.info.input_hash == input_hashes[i] &&
+                sj.info.model == Some(job.model) &&
+                sj.info.language.as_deref() == Some(job.language.as_ref()) &&
+                sj.info.preprocessed == job.is_preprocessed() &&
+                sj.info.array_size.is_some() == job.split_channels
+        });
+        if let Some(existing) = existing {
+            tracing::warn!(
+                file = ?files[i],
+                job_id = %existing.job_id,
+                "The file is already transcribed, download the results with \
+                 `download {}` or use --force to transcribe it again.",
+                existing.job_id
+            );
+            files.remove(i);
+            input_hashes.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parameters shared by all the jobs of a single submission.
+#[derive(Debug, Clone)]
+struct Submission {
+    start_time: DateTime<Utc>,
+    language: Arc<str>,
+    initial_prompt: Option<Arc<str>>,
+    batch_label: Option<Arc<str>>,
+    model: Model,
+    name: Option<Arc<str>>,
+    tags: Arc<[Arc<str>]>,
+    output_formats: Option<Arc<str>>,
+    /// The output formats of the jobs of the split files, which need the
+    /// json output to stitch the transcripts.
+    parts_output_formats: Option<Arc<str>>,
+    compress_formats: Option<Arc<str>>,
+    /// The SSM parameter of the encryption key, if the data is encrypted.
+    encryption_key_parameter: Option<Arc<str>>,
+    job_queue: Arc<str>,
+    job_definition: Arc<str>,
+    preprocess: Option<PreprocessStage>,
+    /// The definition of the alignment jobs following the transcriptions, if
+    /// they are aligned.
+    align_job_definition: Option<Arc<str>>,
+    transcode: Option<TranscodeFormat>,
+}
+
+/// The job preprocessing the audio before the transcription.
+#[derive(Debug, Clone)]
+struct PreprocessStage {
+    job_queue: Arc<str>,
+    job_definition: Arc<str>,
+}
+
+impl Submission {
+    fn job_info(
+        &self,
+        array_size: Option<u32>,
+        input_hash: Option<Box<str>>,
+        estimated_cost: Option<Cents>,
+        audio_duration: Option<Duration>,
+    ) -> JobInfo {
+        JobInfo {
+            job_type: JobType::Transcribe,
+            start_time: self.start_time,
+            batch_label: self.batch_label.as_deref().map(Into::into),
+            array_size,
+            model: Some(self.model),
+            name: self.name.as_deref().map(Into::into),
+            tags: self.tags.iter().map(|t| t.as_ref().into()).collect(),
+            input_hash,
+            language: Some(self.language.as_ref().into()),
+            estimated_cost,
+            audio_duration: audio_duration.map(|d| d.as_secs() as u32),
+            preprocessed: self.preprocess.is_some(),
+            after: None,
+            retry_of: None,
+            parts: None,
+        }
+    }
+}
+
+/// A file transcribed in a separate job.
+#[derive(Debug)]
+struct FileJob {
+    file: PathBuf,
+    input_hash: Option<Box<str>>,
+    estimated_cost: Option<Cents>,
+    audio_duration: Option<Duration>,
+    /// The number of channels transcribed separately, if they are split.
+    channels: Option<u32>,
+    /// The parts the audio is split into and their count, if it is split.
+    parts: Option<(PartsInfo, u32)>,
+}
+
+/// AWS Batch limits on the size of an array job.
+const MIN_ARRAY_SIZE: usize = 2;
+const MAX_ARRAY_SIZE: usize = 10_000;
+
+/// Checks the files of an array job. The names of the files without the
+/// extension must be unique too if the files are renamed by preprocessing or
+/// transcoding.
+fn check_array_files(files: &[PathBuf], renamed: bool) -> anyhow::Result<()> {
+    if !(MIN_ARRAY_SIZE..=MAX_ARRAY_SIZE).contains(&files.len()) {
+        anyhow::bail!(
+            "An array job must contain from {MIN_ARRAY_SIZE} to \
+             {MAX_ARRAY_SIZE} files, got {}.",
+            files.len()
+        );
+    }
+
+    // All the files of an array job are stored under the same prefix, the
+    // preprocessed files along with them.
+    check_unique_file_names(files)?;
+    if renamed {
+        let mut names = HashSet::new();
+        for file in files {
+            let name = make_preprocessed_file_name(get_file_name(file)?, None);
+            if !names.insert(name) {
+                anyhow::bail!(
+                    "Duplicate file name without the extension: {}",
+                    file.display()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the files can be stored in the input folder of a single job.
+pub(crate) fn check_unique_file_names(files: &[PathBuf]) -> anyhow::Result<()> {
+    let mut names = HashSet::new();
+    for file in files {
+        if !names.insert(get_file_name(file)?) {
+            anyhow::bail!("Duplicate file name: {}", file.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Submits a separate job for each file.
+async fn submit_file_jobs(
+    config: Arc<
+        impl AwsConfigProvider
+            + S3Provider
+            + CloudFormationStackProvider
+            + Sync
+            + Send
+            + 'static,
+    >,
+    files: Vec<FileJob>,
+    submission: Submission,
+) -> anyhow::Result<()> {
+    let par_sem = Arc::new(Semaphore::new(config.get_limits().s3_transfers));
+    let cancel = config.get_cancellation_token();
+    let mut tasks: Vec<JoinHandle<anyhow::Result<()>>> = Vec::new();
+
+    for FileJob {
+        file,
+        input_hash,
+        estimated_cost,
+        audio_duration,
+        channels,
+        parts,
+    } in files
+    {
+        let inputs = InputsId::of(std::slice::from_ref(&file)).await?;
+        let pending_uploads = config.get_pending_uploads_path();
+        let jid = pending_job_uid(pending_uploads.as_deref(), &inputs).await?;
+        let span = info_span!("transcribe file", job_id = %jid, ?file);
+        let config = Arc::clone(&config);
+        let submission = submission.clone();
+        let par_sem = Arc::clone(&par_sem);
+        let cancel = cancel.clone();
+
+        tasks.push(tokio::spawn(
+            async move {
+                let _permit = par_sem.acquire().await?;
+                cancellation::check(&cancel)?;
+                tracing::info!("Starting transcription job.");
+
+                // The input of a URL is fetched by the job, which stores it
+                // under the name it gets.
+                let url = input_url(&file);
+                let mut file_name = None;
+                if let Some((info, count)) = parts {
+                    upload_audio_parts(
+                        &*config,
+                        &jid,
+                        &file,
+                        info,
+                        count,
+                        submission.transcode.unwrap_or(TranscodeFormat::Flac),
+                    )
+                    .await?;
+                } else if url.is_none() {
+                    file_name = Some(
+                        upload_audio_file(
+                            &*config,
+                            &jid,
+                            &file,
+                            submission.transcode,
+                        )
+                        .await?,
+                    );
+                }
+                finish_upload(pending_uploads.as_deref(), &inputs).await?;
+                let array_size = channels.or(parts.map(|(_, count)| count));
+
+                // The children of a job with split channels transcribe the
+                // channels in order.
+                let root_prefix = config.get_root_prefix();
+                if let (Some(channels), Some(file_name)) =
+                    (channels, &file_name)
+                {
+                    let preprocessed_list = (0..channels)
+                        .map(|ch| {
+                            make_preprocessed_file_name(file_name, Some(ch))
+                        })
+                        .collect::<Vec<_>>();
+                    put_object(
+                        &*config,
+                        preprocessed_list.join("\n").as_bytes(),
+                        &make_preprocessed_list_storage_key(root_prefix, &jid),
+                    )
+                    .await?;
+                }
+
+                abandon_if_cancelled(&*config, &jid).await?;
+                put_object(
+                    &*config,
+                    b"",
+                    &make_info_storage_key(
+                        root_prefix,
+                        &jid,
+                        &JobInfo {
+                            parts: parts.map(|(info, _)| info),
+                            ..submission.job_info(
+                                array_size,
+                                input_hash,
+                                estimated_cost,
+                                audio_duration,
+                            )
+                        },
+                    ),
+                )
+                .await?;
+
+                let job_prefix = make_job_prefix(root_prefix, &jid);
+                let mut preprocess_job_id = None;
+                if let Some(stage) = &submission.preprocess {
+                    preprocess_job_id = Some(
+                        submit_job(
+                            &*config,
+                            jid.clone(),
+                            &stage.job_queue,
+                            &stage.job_definition,
+                            PreprocessorJobArgs {
+                                job_uid: &jid,
+                                job_prefix: &job_prefix,
+                                input_file: file_name.as_deref(),
+                                input_list: None,
+                                output_prefix: JOB_IN_PREFIX,
+                                split_channels: channels.map(|_| "1"),
+                                encryption_key_parameter: submission
+                                    .encryption_key_parameter
+                                    .as_deref(),
+                            }
+                            .environments(),
+                            JobOptions {
+                                stage: Some(preprocessor::STAGE_NAME),
+                                ..Default::default()
+                            },
+                        )
+                        .await?,
+                    );
+                    file_name = file_name
+                        .map(|name| make_preprocessed_file_name(&name, None));
+                }
+
+                let batch_job_id = submit_job(
+                    &*config,
+                    jid.clone(),
+                    &submission.job_queue,
+                    &submission.job_definition,
+                    WhisperJobArgs {
+                        job_uid: &jid,
+                        job_prefix: &job_prefix,
+                        input_file: file_name
+                            .as_deref()
+                            .filter(|_| channels.is_none()),
+                        input_list: channels
+                            .map(|_| JOB_PREPROCESSED_LIST)
+                            .or(parts.map(|_| JOB_INPUT_LIST)),
+                        input_url: url,
+                        language: &submission.language,
+                        initial_prompt: submission.initial_prompt.as_deref(),
+                        model: submission.model.get_name(),
+                        output_formats: match parts {
+                            Some(_) => {
+                                submission.parts_output_formats.as_deref()
+                            },
+                            None => submission.output_formats.as_deref(),
+                        },
+                        compress_formats: submission
+                            .compress_formats
+                            .as_deref(),
+                        encryption_key_parameter: submission
+                            .encryption_key_parameter
+                            .as_deref(),
+                    }
+                    .environments(),
+                    JobOptions {
+                        array_size,
+                        depends_on: preprocess_job_id
+                            .as_deref()
+                            .map(|job_id| DependsOn {
+                                job_id,
+                                n_to_n: false,
+                            })
+                            .into_iter()
+                            .collect(),
+                        stage: None,
+                    },
+                )
+                .await?;
+
+                tracing::info!("Transcription job submitted.");
+
+                if let (Some(align_job_definition), Some(file_name)) =
+                    (&submission.align_job_definition, &file_name)
+                {
+                    let align_jid = submit_align_after(
+                        &*config,
+                        AlignAfter {
+                            job_uid: &jid,
+                            batch_job_id: &batch_job_id,
+                            audio_file: file_name,
+                            start_time: submission.start_time,
+                            tags: submission
+                                .tags
+                                .iter()
+                                .map(|t| t.as_ref().into())
+                                .collect(),
+                        },
+                        &submission.job_queue,
+                        align_job_definition,
+                    )
+                    .await?;
+                    tracing::info!(
+                        align_job_id = %align_jid,
+                        "Alignment job submitted."
+                    );
+                }
+
+                Ok(())
+            }
+            .instrument(span),
+        ));
+    }
+
+    let total = tasks.len();
+    let mut submitted = 0;
+    let mut res = Ok(());
+    for task in tasks {
+        match task.await? {
+            Ok(()) => submitted += 1,
+            Err(err) => {
+                if res.is_ok() {
+                    res = Err(err);
+                }
+            },
+        }
+    }
+    if submitted < total && cancel.is_cancelled() {
+        tracing::warn!(
+            submitted,
+            total,
+            "Cancelled, the jobs of the other files are not submitted. \
+             Transcribing the files again submits them, the submitted ones \
+             are skipped unless forced."
+        );
+    }
+    res
+}
+
+/// Deletes the uploaded files of a job cancelled before it is submitted,
+/// so no job is listed without a Batch job, and fails with
+/// [`Cancelled`](cancellation::Cancelled).
+pub(crate) async fn abandon_if_cancelled(
+    config: &(impl AwsConfigProvider + S3Provider),
+    jid: &JobUid,
+) -> anyhow::Result<()> {
+    let res = cancellation::check(&config.get_cancellation_token());
+    if res.is_err() {
+        let job_prefix = make_job_prefix(config.get_root_prefix(), jid);
+        if let Err(err) = delete_dir(config, &job_prefix).await {
+            tracing::warn!(%jid, "Failed to delete the files of the job: {err}");
+        }
+    }
+    res
+}
+
+/// Submits a single array job, each child of which transcribes one file.
+async fn submit_array_job(
+    config: Arc<
+        impl AwsConfigProvider
+            + S3Provider
+            + CloudFormationStackProvider
+            + Sync
+            + Send
+            + 'static,
+    >,
+    files: Vec<PathBuf>,
+    estimated_cost: Option<Cents>,
+    audio_duration: Option<Duration>,
+    submission: Submission,
+) -> anyhow::Result<()> {
+    let inputs = InputsId::of(&files).await?;
+    let pending_uploads = config.get_pending_uploads_path();
+    let jid = pending_job_uid(pending_uploads.as_deref(), &inputs).await?;
+    let root_prefix = config.get_root_prefix();
+    tracing::info!(job_id = %jid, files = files.len(),
+        "Starting transcription array job.");
+
+    let par_sem = Arc::new(Semaphore::new(config.get_limits().s3_transfers));
+    let mut tasks: Vec<JoinHandle<anyhow::Result<Box<str>>>> = Vec::new();
+
+    for file in files {
+        let span = info_span!("upload file", ?file);
+        let config = Arc::clone(&config);
+        let jid = jid.clone();
+        let par_sem = Arc::clone(&par_sem);
+        let transcode = submission.transcode;
+
+        tasks.push(tokio::spawn(
+            async move {
+                let _permit = par_sem.acquire().await?;
+                upload_audio_file(&*config, &jid, &file, transcode).await
+            }
+            .instrument(span),
+        ));
+    }
+
+    // The order of the list defines which file is processed by which child
+    // of the array job.
+    let mut input_list = Vec::with_capacity(tasks.len());
+    let mut failure = None;
+    for task in tasks {
+        match task.await? {
+            Ok(file_name) => input_list.push(file_name),
+            Err(err) => {
+                failure.get_or_insert(err);
+            },
+        }
+    }
+    abandon_if_cancelled(&*config, &jid).await?;
+    if let Some(err) = failure {
+        return Err(err);
+    }
+    finish_upload(pending_uploads.as_deref(), &inputs).await?;
+    let array_size = input_list.len() as u32;
+
+    put_object(
+        &*config,
+        input_list.join("\n").as_bytes(),
+        &make_input_list_storage_key(root_prefix, &jid),
+    )
+    .await?;
+
+    // The children of the preprocessing job write the files in the order of
+    // the input list.
+    if submission.preprocess.is_some() {
+        let preprocessed_list = input_list
+            .iter()
+            .map(|file_name| make_preprocessed_file_name(file_name, None))
+            .collect::<Vec<_>>();
+        put_object(
+            &*config,
+            preprocessed_list.join("\n").as_bytes(),
+            &make_preprocessed_list_storage_key(root_prefix, &jid),
+        )
+        .await?;
+    }
+
+    put_object(
+        &*config,
+        b"",
+        &make_info_storage_key(
+            root_prefix,
+            &jid,
+            &submission.job_info(
+                Some(array_size),
+                None,
+                estimated_cost,
+                audio_duration,
+            ),
+        ),
+    )
+    .await?;
+
+    let job_prefix = make_job_prefix(root_prefix, &jid);
+    let mut input_list = JOB_INPUT_LIST;
+    let mut preprocess_job_id = None;
+    if let Some(stage) = &submission.preprocess {
+        preprocess_job_id = Some(
+            submit_job(
+                &*config,
+                jid.clone(),
+                &stage.job_queue,
+                &stage.job_definition,
+                PreprocessorJobArgs {
+                    job_uid: &jid,
+                    job_prefix: &job_prefix,
+                    input_file: None,
+                    input_list: Some(JOB_INPUT_LIST),
+                    output_prefix: JOB_IN_PREFIX,
+                    split_channels: None,
+                    encryption_key_parameter: submission
+                        .encryption_key_parameter
+                        .as_deref(),
+                }
+                .environments(),
+                JobOptions {
+                    array_size: Some(array_size),
+                    stage: Some(preprocessor::STAGE_NAME),
+                    ..Default::default()
+                },
+            )
+            .await?,
+        );
+        input_list = JOB_PREPROCESSED_LIST;
+    }
 
     submit_job(
-        config,
+        &*config,
         jid.clone(),
-        &stack_outputs.job_queue,
-        &stack_outputs.whisper_large_job,
+        &submission.job_queue,
+        &submission.job_definition,
         WhisperJobArgs {
             job_uid: &jid,
-            input_file: &file_name,
-            language: &job.language,
+            job_prefix: &job_prefix,
+            input_file: None,
+            input_list: Some(input_list),
+            input_url: None,
+            language: &submission.language,
+            initial_prompt: submission.initial_prompt.as_deref(),
+            model: submission.model.get_name(),
+            output_formats: submission.output_formats.as_deref(),
+            compress_formats: submission.compress_formats.as_deref(),
+            encryption_key_parameter: submission
+                .encryption_key_parameter
+                .as_deref(),
         }
         .environments(),
+        JobOptions {
+            array_size: Some(array_size),
+            depends_on: preprocess_job_id
+                .as_deref()
+                .map(|job_id| DependsOn {
+                    job_id,
+                    n_to_n: true,
+                })
+                .into_iter()
+                .collect(),
+            stage: None,
+        },
     )
     .await?;
 
-    tracing::info!(job_id = %jid, "Transcription job submitted.");
+    tracing::info!(job_id = %jid, "Transcription array job submitted.");
+
+    Ok(())
+}
+
+/// Uploads the audio file into the input folder of the job, transcoded into
+/// the format if it gets smaller, and returns the name it is stored under.
+async fn upload_audio_file(
+    config: &(impl AwsConfigProvider + S3Provider),
+    jid: &JobUid,
+    file: &Path,
+    transcode: Option<TranscodeFormat>,
+) -> anyhow::Result<Box<str>> {
+    let Some(format) = transcode else {
+        return upload_input_file(config, jid, file).await;
+    };
+    let file_name = get_file_name(file).with_context(|| {
+        format!("Could not get file name: {}", file.display())
+    })?;
+    let Some(transcoded) = transcode_file(file, file_name, format).await?
+    else {
+        tracing::info!(?file, "The transcoded file is not smaller.");
+        return upload_input_file(config, jid, file).await;
+    };
+    upload_input_file(config, jid, &transcoded.file).await
+}
 
+/// Splits the audio file into the parts, uploads them into the input folder
+/// of the job, and lists them in the input list of the job in order.
+async fn upload_audio_parts(
+    config: &(impl AwsConfigProvider + S3Provider),
+    jid: &JobUid,
+    file: &Path,
+    info: PartsInfo,
+    count: u32,
+    format: TranscodeFormat,
+) -> anyhow::Result<()> {
+    let file_name = get_file_name(file).with_context(|| {
+        format!("Could not get file name: {}", file.display())
+    })?;
+    let parts = split_audio(file, file_name, info, count, format).await?;
+    let mut input_list = Vec::with_capacity(parts.files.len());
+    for part in &parts.files {
+        input_list.push(upload_input_file(config, jid, part).await?);
+    }
+    put_object(
+        config,
+        input_list.join("\n").as_bytes(),
+        &make_input_list_storage_key(config.get_root_prefix(), jid),
+    )
+    .await?;
     Ok(())
 }
+
+/// Uploads the file into the input folder of the job and returns its name.
+pub(crate) async fn upload_input_file(
+    config: &(impl AwsConfigProvider + S3Provider),
+    jid: &JobUid,
+    file: &Path,
+) -> anyhow::Result<Box<str>> {
+    let file_name = get_file_name(file).with_context(|| {
+        format!("Could not get file name: {}", file.display())
+    })?;
+
+    upload_file(
+        config,
+        file,
+        &make_input_storage_key(config.get_root_prefix(), jid, file_name),
+    )
+    .await?;
+
+    Ok(file_name.into())
+}
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn run_transcribe_job_test() {
+    use crate::aws_replay::{fixture_path, AwsReplay, ReplayConfig};
+
+    let replay = AwsReplay::load(&fixture_path("aws/transcribe.json")).unwrap();
+    let config = Arc::new(ReplayConfig::new(&replay));
+    let stacks = [StackId::Base, StackId::GpuBatch].into();
+    for (stack_id, uid) in
+        crate::aws_batch::cloudformation::current_stack_uids(&*config, stacks)
+            .await
+            .unwrap()
+    {
+        replay.set_var(&stack_id.to_string(), &uid);
+    }
+
+    let dir = std::env::temp_dir().join(format!("trakktor-{}", JobUid::new()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("meeting.mp3");
+    // A second of silence in a WAV file. It is probed only where ffprobe is
+    // installed, the test doesn't depend on it.
+    let mut wav = Vec::new();
+    wav.extend(b"RIFF");
+    wav.extend((36 + 32_000u32).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(16_000u32.to_le_bytes());
+    wav.extend(32_000u32.to_le_bytes());
+    wav.extend(2u16.to_le_bytes());
+    wav.extend(16u16.to_le_bytes());
+    wav.extend(b"data");
+    wav.extend(32_000u32.to_le_bytes());
+    wav.resize(wav.len() + 32_000, 0);
+    std::fs::write(&file, wav).unwrap();
+    let args = TranscribeJobArgs {
+        files: vec![file],
+        language: "en".into(),
+        model: Model::Large,
+        formats: vec![],
+        compress: vec![],
+        batch_label: None,
+        name: Some("meeting".into()),
+        tags: vec![],
+        array: false,
+        force: false,
+        ignore_budget: false,
+        notify: None,
+        preprocess: false,
+        split_channels: false,
+        align: false,
+        transcode: None,
+        split_longer_than: None,
+        hint: None,
+    };
+    let res = run_transcribe_job(config, &args).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+    res.unwrap();
+
+    replay.check_unmatched().unwrap();
+    let requests = replay.requests();
+    let submit = requests
+        .iter()
+        .find(|r| r.path == "/v1/submitjob")
+        .expect("No job submitted");
+    assert!(submit
+        .body
+        .contains(r#""jobQueue":"trakktor-test-gpu-queue""#));
+    assert!(submit.body.contains("whisper-large"));
+    // The input and the job info are uploaded.
+    assert!(requests.iter().any(|r| r.path.ends_with("/in/meeting.mp3")));
+    assert!(requests
+        .iter()
+        .any(|r| r.method == "PUT" && r.path.ends_with("-info")));
+}
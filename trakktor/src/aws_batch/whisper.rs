@@ -1,25 +1,69 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::aws_batch::{batch::ContainerEnvs, job::JobUid};
 
-const VERSION_TAG: &str = "1";
+const VERSION_TAG: &str = "5";
 const DEV_VERSION_TAG: &str = "dev";
 const IMAGE_NAME: &str = "ghcr.io/lymar/trakktor/whisper";
+const SMALL_MODEL: &str = "small";
+const MEDIUM_MODEL: &str = "medium";
+const LARGE_V2_MODEL: &str = "large-v2";
 const LARGE_MODEL: &str = "large-v3";
+const LARGE_V3_TURBO_MODEL: &str = "large-v3-turbo";
 
-#[derive(Debug, Clone, Copy)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+    strum_macros::EnumIter,
+)]
+#[serde(rename_all = "kebab-case")]
 pub enum Model {
+    Small,
+    Medium,
+    LargeV2,
+    #[serde(rename = "large-v3")]
+    #[value(name = "large-v3")]
     Large,
+    /// large-v3 with the decoder pruned to 4 layers, several times faster
+    /// at a slightly lower accuracy.
+    LargeV3Turbo,
 }
 
 impl Model {
     pub fn get_name(&self) -> &str {
         match self {
+            Model::Small => SMALL_MODEL,
+            Model::Medium => MEDIUM_MODEL,
+            Model::LargeV2 => LARGE_V2_MODEL,
             Model::Large => LARGE_MODEL,
+            Model::LargeV3Turbo => LARGE_V3_TURBO_MODEL,
+        }
+    }
+
+    /// Name of the job definition of the model in the GPU batch stack.
+    pub fn get_job_definition_name(&self) -> &'static str {
+        match self {
+            Model::Small => "GpuWhisperSmallJob",
+            Model::Medium => "GpuWhisperMediumJob",
+            Model::LargeV2 => "GpuWhisperLargeV2Job",
+            Model::Large => "GpuWhisperLargeJob",
+            Model::LargeV3Turbo => "GpuWhisperLargeV3TurboJob",
         }
     }
 }
 
+impl std::fmt::Display for Model {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.get_name())
+    }
+}
+
 pub fn make_image_name(model: Model, is_dev: bool) -> String {
     format!(
         "{}:{}-{}",
@@ -29,46 +73,158 @@ pub fn make_image_name(model: Model, is_dev: bool) -> String {
     )
 }
 
+/// Output formats produced by the Whisper container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Plain text.
+    Txt,
+    /// Tab-separated segments with start and end times in milliseconds.
+    Timestamped,
+    /// SubRip subtitles.
+    Srt,
+    /// WebVTT subtitles.
+    Vtt,
+    /// Whisper's full JSON output, including segments and tokens.
+    Json,
+}
+
+impl OutputFormat {
+    pub fn get_name(&self) -> &str {
+        match self {
+            OutputFormat::Txt => "txt",
+            OutputFormat::Timestamped => "timestamped",
+            OutputFormat::Srt => "srt",
+            OutputFormat::Vtt => "vtt",
+            OutputFormat::Json => "json",
+        }
+    }
+
+    /// Joins the formats into the value of a formats list variable, or
+    /// `None` if there are no formats.
+    pub fn join(formats: &[OutputFormat]) -> Option<Box<str>> {
+        if formats.is_empty() {
+            return None;
+        }
+        let names: Vec<_> = formats.iter().map(|f| f.get_name()).collect();
+        Some(names.join(",").into())
+    }
+
+    /// Splits the value of a formats list variable into the formats.
+    pub fn split(list: &str) -> anyhow::Result<Vec<OutputFormat>> {
+        list.split(',')
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                <OutputFormat as clap::ValueEnum>::from_str(name, false)
+                    .map_err(|_| {
+                        anyhow::anyhow!("Unknown output format {name}")
+                    })
+            })
+            .collect()
+    }
+
+    /// The extension of the file Whisper writes in the format.
+    pub fn get_extension(&self) -> &str {
+        match self {
+            OutputFormat::Timestamped => "tsv",
+            _ => self.get_name(),
+        }
+    }
+}
+
 /// Arguments for a Whisper job passed to the container as environment
 /// variables.
 #[derive(Debug, Serialize)]
 pub struct WhisperJobArgs<'a> {
     #[serde(rename = "TRK_JOB_UID")]
     pub job_uid: &'a JobUid,
-    #[serde(rename = "TRK_INPUT_FILE")]
-    pub input_file: &'a str,
+    /// Storage key prefix of all the job objects.
+    #[serde(rename = "TRK_JOB_PREFIX")]
+    pub job_prefix: &'a str,
+    /// The file to transcribe, for regular jobs.
+    #[serde(
+        rename = "TRK_INPUT_FILE",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub input_file: Option<&'a str>,
+    /// The list of files to transcribe, for array jobs.
+    #[serde(
+        rename = "TRK_INPUT_LIST",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub input_list: Option<&'a str>,
+    /// The URL the job fetches its input from instead, with `yt-dlp`.
+    #[serde(rename = "TRK_INPUT_URL", skip_serializing_if = "Option::is_none")]
+    pub input_url: Option<&'a str>,
     #[serde(rename = "TRK_LANGUAGE")]
     pub language: &'a str,
+    /// The text Whisper is prompted with before the audio, like the names
+    /// and the terms the audio contains, spelled right.
+    #[serde(
+        rename = "TRK_INITIAL_PROMPT",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub initial_prompt: Option<&'a str>,
+    /// The model the job definition is expected to run.
+    #[serde(rename = "TRK_MODEL")]
+    pub model: &'a str,
+    /// Comma-separated output formats, all the formats are produced if not
+    /// set.
+    #[serde(
+        rename = "TRK_OUTPUT_FORMATS",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub output_formats: Option<&'a str>,
+    /// Comma-separated output formats to compress with zstd before upload.
+    #[serde(
+        rename = "TRK_COMPRESS_FORMATS",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub compress_formats: Option<&'a str>,
+    /// The SSM parameter holding the key of the client-side encryption, see
+    /// [`super::key_parameter`].
+    #[serde(
+        rename = "TRK_ENCRYPTION_KEY_PARAMETER",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub encryption_key_parameter: Option<&'a str>,
 }
 
 impl<'a> WhisperJobArgs<'a> {
     /// Convert the arguments into a list of environment variables.
     pub fn environments(&self) -> ContainerEnvs {
-        let sv = serde_json::to_value(&self).expect("Failed to serialize");
-        let serde_json::Value::Object(vm) = sv else {
-            panic!("Expected object");
-        };
-        ContainerEnvs(
-            vm.into_iter()
-                .map(|(k, v)| {
-                    let serde_json::Value::String(vs) = v else {
-                        panic!("Expected string");
-                    };
-                    (k, vs)
-                })
-                .collect::<Vec<_>>(),
-        )
+        ContainerEnvs::from_args(self)
     }
 }
 
+#[test]
+fn output_format_split_test() {
+    let formats = [OutputFormat::Txt, OutputFormat::Timestamped];
+    let list = OutputFormat::join(&formats).unwrap();
+    assert_eq!(OutputFormat::split(&list).unwrap(), formats);
+    assert_eq!(formats[1].get_extension(), "tsv");
+    assert!(OutputFormat::split("txt,docx").is_err());
+}
+
 #[test]
 fn whisper_job_args_test() -> anyhow::Result<()> {
     let jid = JobUid::new();
 
     let mut envs = WhisperJobArgs {
         job_uid: &jid,
-        input_file: "input.mp3",
+        job_prefix: "trakktor/job/",
+        input_file: Some("input.mp3"),
+        input_list: None,
+        input_url: None,
         language: "en",
+        initial_prompt: Some("Trakktor, AWS Batch"),
+        model: Model::Large.get_name(),
+        output_formats: OutputFormat::join(&[
+            OutputFormat::Txt,
+            OutputFormat::Srt,
+        ])
+        .as_deref(),
+        compress_formats: OutputFormat::join(&[OutputFormat::Json]).as_deref(),
+        encryption_key_parameter: None,
     }
     .environments()
     .0;
@@ -76,12 +232,35 @@ fn whisper_job_args_test() -> anyhow::Result<()> {
 
     assert_eq!(
         vec![
+            ("TRK_COMPRESS_FORMATS".to_string(), "json".to_string()),
+            (
+                "TRK_INITIAL_PROMPT".to_string(),
+                "Trakktor, AWS Batch".to_string()
+            ),
             ("TRK_INPUT_FILE".to_string(), "input.mp3".to_string()),
+            ("TRK_JOB_PREFIX".to_string(), "trakktor/job/".to_string()),
             ("TRK_JOB_UID".to_string(), jid.to_string()),
             ("TRK_LANGUAGE".to_string(), "en".to_string()),
+            ("TRK_MODEL".to_string(), "large-v3".to_string()),
+            ("TRK_OUTPUT_FORMATS".to_string(), "txt,srt".to_string()),
         ],
         envs
     );
 
     Ok(())
 }
+
+#[test]
+fn model_names_test() {
+    use strum::IntoEnumIterator;
+
+    for model in Model::iter() {
+        let name = format!("\"{}\"", model.get_name());
+        assert_eq!(serde_json::to_string(&model).unwrap(), name);
+        assert_eq!(
+            <Model as clap::ValueEnum>::from_str(model.get_name(), false),
+            Ok(model)
+        );
+    }
+    assert_eq!(Model::LargeV3Turbo.get_name(), "large-v3-turbo");
+}
@@ -0,0 +1,261 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use aws_config::{retry::RetryConfig, BehaviorVersion, Region, SdkConfig};
+use aws_sdk_sts::config::{Credentials, SharedCredentialsProvider};
+use aws_smithy_runtime_api::{
+    client::{
+        http::{
+            HttpClient, HttpConnector, HttpConnectorFuture,
+            HttpConnectorSettings, SharedHttpConnector,
+        },
+        orchestrator::{HttpRequest, HttpResponse},
+        result::ConnectorError,
+        runtime_components::RuntimeComponents,
+    },
+    http::StatusCode,
+};
+use aws_smithy_types::body::SdkBody;
+use serde::Deserialize;
+
+use crate::{
+    app_config::AppConfigProvider,
+    aws_batch::config::{
+        AwsConfigProvider, CloudFormationStackProvider, S3Provider,
+    },
+};
+
+/// The recorded responses of AWS, in a JSON fixture file.
+#[derive(Debug, Deserialize)]
+struct Fixture {
+    exchanges: Vec<Exchange>,
+}
+
+/// A recorded response, and the requests it answers: all the given parts
+/// of a request must match.
+#[derive(Debug, Deserialize)]
+struct Exchange {
+    method: String,
+    /// The start of the path, e.g. `/v1/submitjob`.
+    #[serde(default)]
+    path: Option<String>,
+    /// A part of the query, e.g. `list-type=2`.
+    #[serde(default)]
+    query: Option<String>,
+    /// A part of the body, e.g. `Action=DescribeStacks`.
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default = "default_status")]
+    status: u16,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default)]
+    response: String,
+}
+
+fn default_status() -> u16 { 200 }
+
+impl Exchange {
+    fn matches(&self, request: &SentRequest) -> bool {
+        self.method == request.method &&
+            self.path
+                .as_ref()
+                .is_none_or(|p| request.path.starts_with(p.as_str())) &&
+            self.query
+                .as_ref()
+                .is_none_or(|q| request.query.contains(q.as_str())) &&
+            self.body
+                .as_ref()
+                .is_none_or(|b| request.body.contains(b.as_str()))
+    }
+}
+
+/// A request sent to AWS, with the body if it is not streamed.
+#[derive(Debug, Clone)]
+pub struct SentRequest {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub body: String,
+    /// Whether a recorded response answered it.
+    pub matched: bool,
+}
+
+impl std::fmt::Display for SentRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.method, self.path)?;
+        if !self.query.is_empty() {
+            write!(f, "?{}", self.query)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    exchanges: Vec<Exchange>,
+    /// The values of the `${NAME}` placeholders of the responses, for the
+    /// parts that are not known when they are recorded, e.g. the hashes of
+    /// the stack templates.
+    vars: Mutex<BTreeMap<String, String>>,
+    requests: Mutex<Vec<SentRequest>>,
+}
+
+/// Answers the requests of the AWS clients with the responses recorded in
+/// a fixture file, without the network. A request is answered by the first
+/// recorded response it matches, as many times as it is sent, so the
+/// concurrent requests need no order. The requests that match none fail.
+#[derive(Debug, Clone)]
+pub struct AwsReplay(Arc<Inner>);
+
+impl AwsReplay {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read(path).with_context(|| {
+            format!("Failed to read the fixture {}", path.display())
+        })?;
+        let fixture: Fixture =
+            serde_json::from_slice(&data).with_context(|| {
+                format!("Failed to parse the fixture {}", path.display())
+            })?;
+        Ok(Self(Arc::new(Inner {
+            exchanges: fixture.exchanges,
+            vars: Default::default(),
+            requests: Default::default(),
+        })))
+    }
+
+    /// Sets the value of the `${name}` placeholder of the responses.
+    pub fn set_var(&self, name: &str, value: &str) {
+        let mut vars = self.0.vars.lock().unwrap();
+        vars.insert(format!("${{{name}}}"), value.to_string());
+    }
+
+    /// The config of the AWS clients answered from the fixture. The failed
+    /// requests are not retried, so a missing response fails at once.
+    pub fn sdk_config(&self) -> SdkConfig {
+        SdkConfig::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(SharedCredentialsProvider::new(
+                Credentials::new("AKIDTEST", "secret", None, None, "replay"),
+            ))
+            .retry_config(RetryConfig::disabled())
+            .http_client(self.clone())
+            .build()
+    }
+
+    /// The requests sent so far, in order.
+    pub fn requests(&self) -> Vec<SentRequest> {
+        self.0.requests.lock().unwrap().clone()
+    }
+
+    /// Fails with the requests no recorded response matched, if any.
+    pub fn check_unmatched(&self) -> anyhow::Result<()> {
+        let unmatched = self
+            .requests()
+            .into_iter()
+            .filter(|r| !r.matched)
+            .map(|r| r.to_string())
+            .collect::<Vec<_>>();
+        if !unmatched.is_empty() {
+            anyhow::bail!("Unmatched requests:\n{}", unmatched.join("\n"));
+        }
+        Ok(())
+    }
+
+    fn respond(&self, request: &HttpRequest) -> Result<HttpResponse, String> {
+        let uri = url::Url::parse(request.uri()).map_err(|e| e.to_string())?;
+        let mut sent = SentRequest {
+            method: request.method().to_string(),
+            path: uri.path().to_string(),
+            query: uri.query().unwrap_or_default().to_string(),
+            body: request
+                .body()
+                .bytes()
+                .map(|b| String::from_utf8_lossy(b).into_owned())
+                .unwrap_or_default(),
+            matched: false,
+        };
+        let exchange = self.0.exchanges.iter().find(|e| e.matches(&sent));
+        sent.matched = exchange.is_some();
+        let description = sent.to_string();
+        self.0.requests.lock().unwrap().push(sent);
+        let exchange = exchange
+            .ok_or_else(|| format!("No recorded response for {description}"))?;
+
+        let mut body = exchange.response.clone();
+        for (name, value) in self.0.vars.lock().unwrap().iter() {
+            body = body.replace(name, value);
+        }
+        let status =
+            StatusCode::try_from(exchange.status).map_err(|e| e.to_string())?;
+        let mut response = HttpResponse::new(status, SdkBody::from(body));
+        for (name, value) in &exchange.headers {
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+        Ok(response)
+    }
+}
+
+impl HttpConnector for AwsReplay {
+    fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+        HttpConnectorFuture::ready(
+            self.respond(&request)
+                .map_err(|e| ConnectorError::other(e.into(), None)),
+        )
+    }
+}
+
+impl HttpClient for AwsReplay {
+    fn http_connector(
+        &self,
+        _settings: &HttpConnectorSettings,
+        _components: &RuntimeComponents,
+    ) -> SharedHttpConnector {
+        SharedHttpConnector::new(self.clone())
+    }
+}
+
+/// The config of the tests: the stacks, the bucket and the jobs are
+/// answered by an [`AwsReplay`].
+pub struct ReplayConfig {
+    aws_config: SdkConfig,
+}
+
+impl ReplayConfig {
+    pub const STACK_PREFIX: &'static str = "trakktor-test";
+    pub const BUCKET_NAME: &'static str = "trakktor-test-bucket";
+
+    pub fn new(replay: &AwsReplay) -> Self {
+        Self {
+            aws_config: replay.sdk_config(),
+        }
+    }
+}
+
+impl AwsConfigProvider for ReplayConfig {
+    fn get_aws_config(&self) -> &SdkConfig { &self.aws_config }
+}
+
+impl CloudFormationStackProvider for ReplayConfig {
+    fn get_stack_prefix(&self) -> &str { Self::STACK_PREFIX }
+}
+
+impl S3Provider for ReplayConfig {
+    fn get_bucket_name(&self) -> &str { Self::BUCKET_NAME }
+}
+
+impl AppConfigProvider for ReplayConfig {
+    fn is_dev_mode(&self) -> bool { false }
+}
+
+/// The path of a fixture of the crate, e.g. `aws/list.json`.
+pub fn fixture_path(name: &str) -> std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join(name)
+}
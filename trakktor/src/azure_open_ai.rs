@@ -0,0 +1,314 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{bail, Context};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::Semaphore;
+use url::Url;
+
+use crate::{
+    embedding::{EmbeddingsAPI, EmbeddingsArgs},
+    llm::{
+        ChatCompletionAPI, ChatCompletionsArgs, ChatResponse, ChatTask,
+        Message, Role,
+    },
+    llm_audit::{audit_chat, LlmAuditLog, TokenUsage},
+    llm_budget::LlmBudget,
+    open_ai::{
+        OpenAiChatCompletions, OpenAiChatCompletionsResponse, OpenAiEmbeddings,
+        OpenAiEmbeddingsResponse,
+    },
+    rate_limit::RateLimiter,
+    request_extras::RequestExtras,
+};
+
+pub const AZURE_OPENAI_DEFAULT_API_VERSION: &str = "2024-06-01";
+
+const CHAT_OPERATION: &str = "chat/completions";
+const EMBEDDING_OPERATION: &str = "embeddings";
+
+/// The Azure OpenAI Service. Models are called through the deployments of an
+/// Azure resource, so the chat and embeddings models are the names of the
+/// deployments rather than the names of the models.
+#[derive(Debug, Clone)]
+pub struct AzureOpenAiAPI {
+    pub api_key: Option<Arc<str>>,
+    /// The endpoint of the resource, e.g.
+    /// `https://my-resource.openai.azure.com`.
+    pub endpoint: Arc<Url>,
+    pub api_version: Arc<str>,
+    pub chat_deployment: Option<Arc<str>>,
+    pub embeddings_deployment: Option<Arc<str>>,
+    /// Limits the requests in flight, shared by the clones of the API.
+    pub request_permits: Arc<Semaphore>,
+    pub extras: Arc<RequestExtras>,
+    /// Shared by all the APIs of the provider.
+    pub rate_limiter: Arc<RateLimiter>,
+    /// Records the chat completions if set.
+    pub audit_log: Option<Arc<LlmAuditLog>>,
+    /// The budget of the run, shared by all the providers.
+    pub budget: Arc<LlmBudget>,
+    /// The seed of the chat completions, for the best-effort deterministic
+    /// sampling.
+    pub seed: Option<u64>,
+}
+
+/// Make the URL of the operation of the deployment.
+fn make_deployment_url(
+    endpoint: &Url,
+    deployment: &str,
+    operation: &str,
+    api_version: &str,
+) -> anyhow::Result<Url> {
+    let mut url = endpoint
+        .join(&format!("openai/deployments/{deployment}/{operation}"))?;
+    url.query_pairs_mut()
+        .append_pair("api-version", api_version);
+    Ok(url)
+}
+
+#[test]
+fn deployment_url_test() {
+    let endpoint = Url::parse("https://res.openai.azure.com").unwrap();
+    assert_eq!(
+        make_deployment_url(&endpoint, "gpt4o", CHAT_OPERATION, "2024-06-01")
+            .unwrap()
+            .as_str(),
+        "https://res.openai.azure.com/openai/deployments/gpt4o/chat/\
+         completions?api-version=2024-06-01"
+    );
+}
+
+impl AzureOpenAiAPI {
+    #[tracing::instrument(level = "debug", skip(self, req, extra_body))]
+    async fn make_request<I, O>(
+        &self,
+        req: &I,
+        deployment: &str,
+        operation: &str,
+        extra_body: &serde_json::Map<String, serde_json::Value>,
+    ) -> anyhow::Result<O>
+    where
+        I: Serialize + ?Sized + std::fmt::Debug,
+        O: DeserializeOwned + std::fmt::Debug,
+    {
+        let client = self.extras.client()?;
+        let endpoint = make_deployment_url(
+            &self.endpoint,
+            deployment,
+            operation,
+            &self.api_version,
+        )?;
+
+        tracing::debug!(
+            endpoint = endpoint.to_string(),
+            ?req,
+            "Sending request to API"
+        );
+        let body_len = serde_json::to_vec(req)?.len();
+        let mut req_builder =
+            self.extras.apply(client.post(endpoint), req, extra_body)?;
+        if let Some(api_key) = &self.api_key {
+            req_builder = req_builder.header("api-key", api_key.as_ref())
+        }
+        self.budget.start_request()?;
+        self.rate_limiter.acquire(body_len).await;
+        let _permit = self.request_permits.acquire().await?;
+        let res = req_builder.send().await?;
+
+        let code = res.status();
+        tracing::debug!(status = ?code, "API call completed");
+        let res = res.text().await?;
+        tracing::debug!(response = ?res, "API response received");
+
+        if !code.is_success() {
+            return Err(crate::Error::Llm {
+                status: code.as_u16(),
+                body: res,
+            }
+            .into());
+        }
+
+        serde_json::from_str(&res).with_context(|| {
+            format!("Failed to parse response from API:\n{res}")
+        })
+    }
+
+    fn hash_config(
+        &self,
+        deployment: &Option<Arc<str>>,
+        seed: Option<u64>,
+        extra_body: &serde_json::Map<String, serde_json::Value>,
+    ) -> String {
+        let mut hasher = blake3::Hasher::new();
+        if let Some(api_key) = &self.api_key {
+            hasher.update(api_key.as_bytes());
+        }
+        hasher.update(b":");
+        hasher.update(self.endpoint.as_str().as_bytes());
+        hasher.update(b":");
+        hasher.update(self.api_version.as_bytes());
+        hasher.update(b":");
+        if let Some(deployment) = deployment {
+            hasher.update(deployment.as_bytes());
+        }
+        if let Some(seed) = seed {
+            hasher.update(format!(":seed={seed}").as_bytes());
+        }
+        self.extras.hash_into(&mut hasher, extra_body);
+        URL_SAFE_NO_PAD.encode(hasher.finalize().as_bytes())
+    }
+
+    async fn chat(
+        &self,
+        deployment: &str,
+        args: &ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<ChatResponse> {
+        let seed = args.seed.or(self.seed);
+        let res: OpenAiChatCompletionsResponse = self
+            .make_request(
+                &OpenAiChatCompletions {
+                    seed,
+                    ..OpenAiChatCompletions::new(deployment, args)
+                },
+                deployment,
+                CHAT_OPERATION,
+                &self.extras.chat_body,
+            )
+            .await?;
+
+        let choice =
+            res.choices.into_iter().next().ok_or_else(|| {
+                anyhow::anyhow!("Empty response from Chat API")
+            })?;
+        if !matches!(&choice.message.role, Role::Assistant) {
+            bail!(
+                "Unexpected role in response from API: {:?}",
+                choice.message.role
+            );
+        }
+
+        self.rate_limiter.record_response_tokens(
+            res.usage.completion_tokens.unwrap_or_default(),
+        );
+        self.budget.record_tokens(res.usage.total_tokens);
+        tracing::info!(usage = ?res.usage, model = res.model,
+            finish_reason = choice.finish_reason,
+            system_fingerprint = res.system_fingerprint,
+            "API call completed successfully");
+
+        Ok(ChatResponse {
+            message: Message {
+                role: choice.message.role,
+                content: choice
+                    .message
+                    .into_content(&choice.finish_reason)?
+                    .into(),
+            },
+            finish_reason: Some(choice.finish_reason),
+            usage: Some(TokenUsage {
+                prompt_tokens: res.usage.prompt_tokens,
+                completion_tokens: res
+                    .usage
+                    .completion_tokens
+                    .unwrap_or_default(),
+            }),
+            model: Some(res.model),
+            request_id: res.id,
+            // Set by `audit_chat`.
+            latency: Duration::ZERO,
+            seed,
+            system_fingerprint: res.system_fingerprint,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatCompletionAPI for AzureOpenAiAPI {
+    async fn run_chat(
+        &self,
+        args: ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<Message<'static>> {
+        Ok(self.run_chat_full(args).await?.message)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn run_chat_full(
+        &self,
+        args: ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<ChatResponse> {
+        let deployment = args
+            .model_overwrite
+            .or(self.chat_deployment.as_deref())
+            .ok_or_else(|| {
+                anyhow::anyhow!("The Azure OpenAI chat deployment is not set")
+            })?;
+        audit_chat(
+            self.audit_log.as_deref(),
+            "azure-openai",
+            deployment,
+            &args,
+            self.chat(deployment, &args),
+        )
+        .await
+    }
+
+    fn config_hash(&self) -> String {
+        self.hash_config(
+            &self.chat_deployment,
+            self.seed,
+            &self.extras.chat_body,
+        )
+    }
+
+    fn model_name(&self, _task: Option<ChatTask>) -> Option<&str> {
+        self.chat_deployment.as_deref()
+    }
+}
+
+#[async_trait::async_trait]
+impl EmbeddingsAPI for AzureOpenAiAPI {
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn get_embedding(
+        &self,
+        args: EmbeddingsArgs<'_>,
+    ) -> anyhow::Result<Vec<f64>> {
+        let deployment = args
+            .model_overwrite
+            .or(self.embeddings_deployment.as_deref())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "The Azure OpenAI embeddings deployment is not set"
+                )
+            })?;
+        let res: OpenAiEmbeddingsResponse = self
+            .make_request(
+                &OpenAiEmbeddings {
+                    model: deployment,
+                    input: args.input,
+                },
+                deployment,
+                EMBEDDING_OPERATION,
+                &self.extras.embeddings_body,
+            )
+            .await?;
+        self.budget.record_tokens(res.usage.total_tokens);
+
+        Ok(res
+            .data
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                anyhow::anyhow!("Empty response from Embeddings API")
+            })?
+            .embedding)
+    }
+
+    fn config_hash(&self) -> String {
+        self.hash_config(
+            &self.embeddings_deployment,
+            None,
+            &self.extras.embeddings_body,
+        )
+    }
+}
@@ -0,0 +1,61 @@
+use std::{future::Future, time::Duration};
+
+pub use tokio_util::sync::CancellationToken;
+
+/// The error of an operation stopped by its cancellation token.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("The operation was cancelled.")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Runs the future unless the token is cancelled first, in which case the
+/// future is dropped and [`Cancelled`] is returned.
+pub async fn cancellable<T>(
+    token: &CancellationToken,
+    future: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    tokio::select! {
+        biased;
+        _ = token.cancelled() => Err(Cancelled.into()),
+        res = future => res,
+    }
+}
+
+/// Fails with [`Cancelled`] if the token is cancelled, before a step that
+/// is not to start after the cancellation.
+pub fn check(token: &CancellationToken) -> anyhow::Result<()> {
+    if token.is_cancelled() {
+        return Err(Cancelled.into());
+    }
+    Ok(())
+}
+
+/// Waits for the duration, e.g. between the polls of an AWS operation,
+/// unless the token is cancelled first.
+pub async fn sleep(
+    token: &CancellationToken,
+    duration: Duration,
+) -> anyhow::Result<()> {
+    cancellable(token, async {
+        tokio::time::sleep(duration).await;
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn cancellable_test() {
+    let token = CancellationToken::new();
+    assert_eq!(cancellable(&token, async { Ok(1) }).await.unwrap(), 1);
+
+    token.cancel();
+    assert!(check(&token).unwrap_err().is::<Cancelled>());
+    let err = sleep(&token, Duration::from_secs(3600)).await.unwrap_err();
+    assert!(err.is::<Cancelled>());
+}
@@ -0,0 +1,383 @@
+//! The entry point of the library for the programs using Trakktor without
+//! the CLI.
+//!
+//! ```no_run
+//! # async fn example() -> trakktor::Result<()> {
+//! use trakktor::client::{AwsSettings, OpenAiSettings, Trakktor};
+//!
+//! let trakktor = Trakktor::builder()
+//!     .openai(OpenAiSettings {
+//!         api_key: Some("sk-...".into()),
+//!         ..Default::default()
+//!     })
+//!     .aws(AwsSettings::from_env().await)
+//!     .build()?;
+//! let reply = trakktor.chat("Say hello!").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+
+use bon::bon;
+use tokio::sync::Semaphore;
+use url::Url;
+
+use crate::{
+    app_config::{AppConfigProvider, Limits},
+    aws_batch::{
+        cloudformation::get_s3_storage_name,
+        config::{
+            AwsConfigProvider, CloudFormationStackProvider, S3Provider,
+            DEFAULT_STACK_PREFIX,
+        },
+        encryption::EncryptionKey,
+        pending_uploads::default_pending_uploads_path,
+        transcribe::{run_transcribe_job, TranscribeJobArgs},
+        whisper::Model,
+    },
+    cancellation::CancellationToken,
+    embedding::{EmbeddingsAPI, EmbeddingsArgs},
+    gemini::GeminiAPI,
+    llm::{ChatCompletionAPI, ChatCompletionsArgs, Message, Role},
+    llm_budget::LlmBudget,
+    locale::Lang,
+    open_ai::OpenAiAPI,
+    rate_limit::RateLimiters,
+    request_extras::RequestExtras,
+    structify_text::{
+        run_structify_text, Sectioning, StructifyText, DEFAULT_JOBS,
+    },
+};
+
+/// The settings of the OpenAI API or a server compatible with it.
+#[derive(Debug, Clone, Default)]
+pub struct OpenAiSettings {
+    pub api_key: Option<Arc<str>>,
+    /// The OpenAI API if not set.
+    pub server_url: Option<Url>,
+    /// `gpt-4o` if not set.
+    pub chat_model: Option<Arc<str>>,
+    /// `text-embedding-3-large` if not set.
+    pub embeddings_model: Option<Arc<str>>,
+}
+
+/// The settings of the Gemini API of Google AI Studio.
+#[derive(Debug, Clone, Default)]
+pub struct GeminiSettings {
+    pub api_key: Option<Arc<str>>,
+    pub chat_model: Option<Arc<str>>,
+    pub embeddings_model: Option<Arc<str>>,
+}
+
+/// The AWS account the transcription jobs run in.
+#[derive(Clone)]
+pub struct AwsSettings {
+    pub sdk_config: aws_config::SdkConfig,
+    /// The prefix of the CloudFormation stack names.
+    pub stack_prefix: Arc<str>,
+    /// The key namespace of the job data in the S3 bucket, see
+    /// [`crate::aws_batch::config::parse_root_prefix`].
+    pub s3_prefix: Box<str>,
+    /// The key the job data is encrypted with before it is uploaded.
+    pub encryption_key: Option<EncryptionKey>,
+}
+
+impl AwsSettings {
+    /// The settings of the default profile of the environment, with the
+    /// default stack prefix.
+    pub async fn from_env() -> Self {
+        Self {
+            sdk_config: aws_config::from_env().load().await,
+            stack_prefix: DEFAULT_STACK_PREFIX.into(),
+            s3_prefix: "".into(),
+            encryption_key: None,
+        }
+    }
+}
+
+/// A client of the Trakktor operations: the transcription in AWS Batch,
+/// the structifying of texts, and the chat and the embeddings of a model.
+pub struct Trakktor {
+    chat_api: Option<Box<dyn ChatCompletionAPI>>,
+    embeddings_api: Option<Box<dyn EmbeddingsAPI>>,
+    aws: Option<Arc<ClientConfig>>,
+    whisper_model: Model,
+    lang: Option<Lang>,
+    cancel: CancellationToken,
+}
+
+#[bon]
+impl Trakktor {
+    /// At most one model provider can be set. The operations needing a
+    /// provider or AWS that is not set fail.
+    #[builder]
+    pub fn new(
+        openai: Option<OpenAiSettings>,
+        gemini: Option<GeminiSettings>,
+        aws: Option<AwsSettings>,
+        /// The Whisper model of the transcription, [`Model::Large`] if not
+        /// set.
+        whisper_model: Option<Model>,
+        /// The language of the outputs, detected from the texts if not
+        /// set.
+        lang: Option<Lang>,
+        limits: Option<Limits>,
+        /// The HTTP client of the requests to the model provider, e.g. with
+        /// the proxy of the application. A client of its own if not set.
+        http_client: Option<reqwest::Client>,
+        /// Cancels the running operations, which then fail with
+        /// [`crate::Error::Cancelled`].
+        cancel: Option<CancellationToken>,
+    ) -> crate::Result<Self> {
+        let limits = limits.unwrap_or_default();
+        let cancel = cancel.unwrap_or_default();
+        let request_permits = Arc::new(Semaphore::new(limits.llm_requests));
+        let rate_limiters = RateLimiters::default();
+        let budget = Arc::new(LlmBudget::new(None, None));
+        let extras = Arc::new(match http_client {
+            Some(client) => RequestExtras::default().with_client(client),
+            None => RequestExtras::default(),
+        });
+
+        let chat_api: Option<Box<dyn ChatCompletionAPI>>;
+        let embeddings_api: Option<Box<dyn EmbeddingsAPI>>;
+        match (openai, gemini) {
+            (Some(_), Some(_)) => {
+                return Err(crate::Error::InvalidSettings(
+                    "Only one model provider can be set!".into(),
+                ));
+            },
+            (Some(openai), None) => {
+                let api = OpenAiAPI {
+                    api_key: openai.api_key,
+                    server_url: openai.server_url.map(Arc::new),
+                    chat_model: openai.chat_model,
+                    embeddings_model: openai.embeddings_model,
+                    request_permits,
+                    extras,
+                    rate_limiter: rate_limiters.open_ai,
+                    audit_log: None,
+                    budget,
+                    seed: None,
+                };
+                chat_api = Some(Box::new(api.clone()));
+                embeddings_api = Some(Box::new(api));
+            },
+            (None, Some(gemini)) => {
+                let api = GeminiAPI {
+                    api_key: gemini.api_key,
+                    chat_model: gemini.chat_model,
+                    embeddings_model: gemini.embeddings_model,
+                    safety_threshold: None,
+                    request_permits,
+                    extras,
+                    rate_limiter: rate_limiters.gemini,
+                    audit_log: None,
+                    budget,
+                };
+                chat_api = Some(Box::new(api.clone()));
+                embeddings_api = Some(Box::new(api));
+            },
+            (None, None) => {
+                chat_api = None;
+                embeddings_api = None;
+            },
+        }
+
+        let aws = aws.map(|aws| {
+            Arc::new(ClientConfig {
+                aws_config: aws.sdk_config,
+                stack_prefix: aws.stack_prefix,
+                s3_bucket: OnceLock::new(),
+                s3_root_prefix: aws.s3_prefix,
+                encryption_key: aws.encryption_key,
+                limits,
+                cancel: cancel.clone(),
+                lang: lang.unwrap_or_else(Lang::from_env),
+            })
+        });
+
+        Ok(Self {
+            chat_api,
+            embeddings_api,
+            aws,
+            whisper_model: whisper_model.unwrap_or(Model::Large),
+            lang,
+            cancel,
+        })
+    }
+}
+
+impl Trakktor {
+    fn chat_api(&self) -> crate::Result<&dyn ChatCompletionAPI> {
+        self.chat_api
+            .as_deref()
+            .ok_or(crate::Error::NoModelProvider)
+    }
+
+    /// Submits the jobs transcribing the audio or video files with the
+    /// Whisper model of the client, creating the stacks in AWS first if needed.
+    /// The transcripts are stored in S3 once the jobs finish.
+    pub async fn transcribe(
+        &self,
+        files: &[PathBuf],
+        language: &str,
+    ) -> crate::Result<()> {
+        let Some(aws) = &self.aws else {
+            return Err(crate::Error::NoAwsSettings);
+        };
+        let args = TranscribeJobArgs {
+            files: files.to_vec(),
+            language: language.into(),
+            model: self.whisper_model,
+            formats: vec![],
+            compress: vec![],
+            batch_label: None,
+            name: None,
+            tags: vec![],
+            array: false,
+            force: false,
+            ignore_budget: false,
+            notify: None,
+            preprocess: false,
+            split_channels: false,
+            align: false,
+            transcode: None,
+            split_longer_than: None,
+            hint: None,
+        };
+        run_transcribe_job(Arc::clone(aws), &args).await
+    }
+
+    /// Splits the text of the file into paragraphs and titled sections with
+    /// the model. The outputs are written to the directory, or next to the
+    /// file, named as in [`crate::outputs::output_path`].
+    pub async fn structify(
+        &self,
+        file: &Path,
+        out_dir: Option<&Path>,
+    ) -> crate::Result<()> {
+        let args = StructifyText {
+            file: file.to_owned(),
+            out_dir: out_dir.map(Path::to_owned),
+            ocr: Default::default(),
+            dry_run: false,
+            jobs: DEFAULT_JOBS,
+            no_punctuate: false,
+            dialogue: false,
+            ignore_speakers: false,
+            section_per_turn: false,
+            sectioning: Sectioning::Model,
+            prompt_set: None,
+            language: None,
+            verbatim: false,
+            outline: Default::default(),
+        };
+        run_structify_text(
+            &args,
+            self.chat_api()?,
+            self.embeddings_api.as_deref(),
+            self.lang,
+            &self.cancel,
+        )
+        .await
+    }
+
+    /// The reply of the model to the message of the user.
+    pub async fn chat(&self, message: &str) -> crate::Result<String> {
+        let messages = [Message {
+            role: Role::User,
+            content: message.into(),
+        }];
+        let args = ChatCompletionsArgs::builder().messages(&messages).build();
+        let reply = crate::cancellation::cancellable(
+            &self.cancel,
+            self.chat_api()?.run_chat(args),
+        )
+        .await?;
+        Ok(reply.content.into_owned())
+    }
+
+    /// The embedding of the text.
+    pub async fn embed(&self, input: &str) -> crate::Result<Vec<f64>> {
+        let Some(api) = &self.embeddings_api else {
+            return Err(crate::Error::NoModelProvider);
+        };
+        let args = EmbeddingsArgs::builder().input(input).build();
+        Ok(crate::cancellation::cancellable(
+            &self.cancel,
+            api.get_embedding(args),
+        )
+        .await?)
+    }
+}
+
+/// The AWS settings of the client as the config of the jobs.
+struct ClientConfig {
+    aws_config: aws_config::SdkConfig,
+    stack_prefix: Arc<str>,
+    s3_bucket: OnceLock<Box<str>>,
+    s3_root_prefix: Box<str>,
+    encryption_key: Option<EncryptionKey>,
+    limits: Limits,
+    cancel: CancellationToken,
+    lang: Lang,
+}
+
+impl AwsConfigProvider for ClientConfig {
+    fn get_aws_config(&self) -> &aws_config::SdkConfig { &self.aws_config }
+
+    fn get_limits(&self) -> Limits { self.limits }
+
+    fn get_cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+}
+
+impl CloudFormationStackProvider for ClientConfig {
+    fn get_stack_prefix(&self) -> &str { &self.stack_prefix }
+}
+
+impl S3Provider for ClientConfig {
+    fn get_bucket_name(&self) -> &str {
+        self.s3_bucket
+            .get_or_init(|| get_s3_storage_name(&self.stack_prefix))
+    }
+
+    fn get_root_prefix(&self) -> &str { &self.s3_root_prefix }
+
+    fn get_encryption_key(&self) -> Option<&EncryptionKey> {
+        self.encryption_key.as_ref()
+    }
+
+    fn get_pending_uploads_path(&self) -> Option<PathBuf> {
+        default_pending_uploads_path()
+    }
+}
+
+impl AppConfigProvider for ClientConfig {
+    fn is_dev_mode(&self) -> bool { false }
+
+    fn get_lang(&self) -> Lang { self.lang }
+}
+
+#[tokio::test]
+async fn trakktor_builder_test() {
+    let err = Trakktor::builder()
+        .openai(OpenAiSettings::default())
+        .gemini(GeminiSettings::default())
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err.to_string(), "Only one model provider can be set!");
+
+    let trakktor = Trakktor::builder().build().unwrap();
+    let err = trakktor.chat("Hello").await.unwrap_err();
+    assert_eq!(err.to_string(), "No model provider set!");
+    let err = trakktor.transcribe(&[], "en").await.unwrap_err();
+    assert_eq!(err.to_string(), "No AWS settings set!");
+}
@@ -0,0 +1,118 @@
+use itertools::Itertools;
+
+/// A turn of a speaker in a transcript.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Turn {
+    pub speaker: String,
+    pub text: String,
+}
+
+/// The longest speaker label, e.g. `Interviewer` or `Dr. Jane Smith`.
+const MAX_LABEL_WORDS: usize = 4;
+const MAX_LABEL_CHARS: usize = 40;
+
+/// Splits the speaker label off a line, e.g. `Jane: Hello`, `Q: Why?`,
+/// `[SPEAKER_01] Hello` or `[00:01:02] Jane: Hello`.
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let mut line = line.trim();
+    // A leading timestamp.
+    if let Some(rest) = line.strip_prefix('[') {
+        if let Some((inner, rest)) = rest.split_once(']') {
+            if inner
+                .chars()
+                .all(|c| c.is_ascii_digit() || ":.,".contains(c))
+            {
+                line = rest.trim_start();
+            } else if is_label(inner) {
+                return Some((inner.trim(), rest.trim_start()));
+            }
+        }
+    }
+    let (label, text) = line.split_once(':')?;
+    is_label(label).then(|| (label.trim(), text.trim()))
+}
+
+fn is_label(label: &str) -> bool {
+    let label = label.trim();
+    let words = label.split_whitespace().collect::<Vec<_>>();
+    !words.is_empty() &&
+        words.len() <= MAX_LABEL_WORDS &&
+        label.len() <= MAX_LABEL_CHARS &&
+        !label.contains(['?', '!', ',', '"']) &&
+        words.iter().all(|w| {
+            w.starts_with(|c: char| c.is_uppercase() || c.is_ascii_digit())
+        })
+}
+
+/// Parses a transcript with the speaker labels at the beginnings of the
+/// lines into the turns, with the consecutive turns of a speaker merged.
+/// The unlabeled lines continue the previous turn. Returns `None` if the
+/// text does not look like a dialogue: fewer than two speakers, or most of
+/// the lines unlabeled.
+pub fn parse_dialogue(text: &str) -> Option<Vec<Turn>> {
+    let lines = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>();
+    let mut turns: Vec<Turn> = Vec::new();
+    let mut labeled = 0;
+    for line in &lines {
+        let (speaker, text) = match split_label(line) {
+            Some((speaker, text)) => {
+                labeled += 1;
+                (Some(speaker), text)
+            },
+            None => (None, *line),
+        };
+        match (speaker, turns.last_mut()) {
+            (Some(speaker), Some(last)) if last.speaker == speaker => {
+                push_text(&mut last.text, text)
+            },
+            (Some(speaker), _) => turns.push(Turn {
+                speaker: speaker.to_string(),
+                text: text.to_string(),
+            }),
+            (None, Some(last)) => push_text(&mut last.text, text),
+            // The text before the first label is not a dialogue.
+            (None, None) => return None,
+        }
+    }
+    let speakers = turns.iter().map(|t| &t.speaker).unique().count();
+    (speakers >= 2 && labeled * 2 >= lines.len()).then_some(turns)
+}
+
+fn push_text(text: &mut String, more: &str) {
+    if !text.is_empty() && !more.is_empty() {
+        text.push(' ');
+    }
+    text.push_str(more);
+}
+
+impl Turn {
+    /// The turn as a paragraph of dialogue Markdown, `**Speaker:** text`.
+    pub fn to_markdown(&self) -> String {
+        format!("**{}:** {}", self.speaker, self.text)
+    }
+}
+
+/// Formats the turns as dialogue Markdown, a paragraph a turn.
+pub fn to_dialogue_markdown(turns: &[Turn]) -> String {
+    turns.iter().map(Turn::to_markdown).join("\n\n")
+}
+
+#[test]
+fn parse_dialogue_test() {
+    let text = "Q: How did it start?\nJane Smith: It started in 2019.\nWe had \
+                no money.\nJane Smith: None at all.\n[00:01:02] Q: And then?\n";
+    let turns = parse_dialogue(text).unwrap();
+    assert_eq!(
+        to_dialogue_markdown(&turns),
+        "**Q:** How did it start?\n\n**Jane Smith:** It started in 2019. We \
+         had no money. None at all.\n\n**Q:** And then?"
+    );
+    assert!(parse_dialogue("[SPEAKER_00] Hi\n[SPEAKER_01] Hello").is_some());
+    // A single speaker, and a text with a colon.
+    assert!(parse_dialogue("Jane: Hi\nJane: Bye").is_none());
+    assert!(parse_dialogue("The plan: go home.\nThen sleep.").is_none());
+}
@@ -0,0 +1,274 @@
+use std::{borrow::Cow, path::Path, sync::Arc};
+
+use anyhow::Context;
+use tokio::task::spawn_blocking;
+
+use crate::{
+    embedding::{EmbeddingsAPI, EmbeddingsArgs},
+    llm::{ChatCompletionAPI, ChatCompletionsArgs, Message, Role},
+};
+
+/// Free disk space below which the check warns, in GiB.
+const LOW_DISK_SPACE_GIB: u64 = 10;
+/// Free disk space below which the check fails, in GiB.
+const MIN_DISK_SPACE_GIB: u64 = 1;
+
+#[derive(clap::Args, Debug)]
+pub struct DoctorArgs {
+    /// The AWS profile to check. Defaults to `aws_profile` of the config
+    /// file.
+    #[arg(long)]
+    pub profile: Option<Arc<str>>,
+    /// The AWS region to check. Defaults to the region of the profile in the
+    /// config file.
+    #[arg(long)]
+    pub region: Option<Arc<str>>,
+    /// The prefix of the CloudFormation stack names. Defaults to the stack
+    /// prefix of the profile in the config file, or `trakktor`.
+    #[arg(long)]
+    pub stack_prefix: Option<Arc<str>>,
+    /// Skip the checks of AWS.
+    #[arg(long)]
+    pub no_aws: bool,
+    /// Skip the checks of the chat and embeddings platforms, which send a
+    /// tiny request to each.
+    #[arg(long)]
+    pub no_platforms: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    /// Not required by every task, e.g. a GPU for local transcriptions.
+    Warn,
+    Fail,
+    Skip,
+}
+
+/// The result of a check of the environment.
+#[derive(Debug)]
+pub struct Check {
+    pub name: Cow<'static, str>,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    pub fn new(
+        name: impl Into<Cow<'static, str>>,
+        status: CheckStatus,
+        detail: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    /// Passes with the detail of the result, or fails with its error.
+    pub fn from_result(
+        name: impl Into<Cow<'static, str>>,
+        res: anyhow::Result<String>,
+    ) -> Self {
+        match res {
+            Ok(detail) => Self::new(name, CheckStatus::Pass, detail),
+            Err(err) => Self::new(name, CheckStatus::Fail, format!("{err:#}")),
+        }
+    }
+}
+
+/// Prints the report of the checks, returns whether none of them failed.
+pub fn print_report(checks: &[Check]) -> bool {
+    let width = checks
+        .iter()
+        .map(|c| c.name.len())
+        .max()
+        .unwrap_or_default();
+    for check in checks {
+        let status = match check.status {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+            CheckStatus::Skip => "SKIP",
+        };
+        println!("[{status}] {:width$}  {}", check.name, check.detail);
+    }
+    !checks.iter().any(|c| c.status == CheckStatus::Fail)
+}
+
+/// Runs the command and returns the first line of its output.
+async fn run_tool(program: &str, args: &[&str]) -> anyhow::Result<String> {
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .with_context(|| {
+            format!("Failed to run {program}, is it installed?")
+        })?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_owned())
+}
+
+/// Checks the tools the jobs need on this machine: `ffmpeg` and `ffprobe`
+/// for reading and probing the audio, and `docker` for the local jobs.
+pub async fn check_tools() -> Vec<Check> {
+    let (ffmpeg, ffprobe, docker) = tokio::join!(
+        run_tool("ffmpeg", &["-version"]),
+        run_tool("ffprobe", &["-version"]),
+        run_tool("docker", &["version", "--format", "{{.Server.Version}}"]),
+    );
+    vec![
+        Check::from_result("ffmpeg", ffmpeg),
+        Check::from_result("ffprobe", ffprobe),
+        match docker {
+            Ok(version) => Check::new(
+                "docker",
+                CheckStatus::Pass,
+                format!("server {version}"),
+            ),
+            // Only the local jobs need it.
+            Err(err) => Check::new(
+                "docker",
+                CheckStatus::Warn,
+                format!("{err:#}, the local jobs are unavailable"),
+            ),
+        },
+    ]
+}
+
+/// Checks the NVIDIA GPUs visible to the local jobs. The GPUs are optional,
+/// the local jobs run on the CPU without them.
+pub async fn check_gpus() -> Check {
+    let output = tokio::process::Command::new("nvidia-smi")
+        .arg("-L")
+        .output()
+        .await;
+    match output {
+        Ok(output) if output.status.success() => {
+            let gpus = String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter(|l| l.starts_with("GPU "))
+                .count();
+            Check::new("GPU", CheckStatus::Pass, format!("{gpus} visible"))
+        },
+        _ => Check::new(
+            "GPU",
+            CheckStatus::Warn,
+            "No NVIDIA GPU visible, the local jobs run on the CPU",
+        ),
+    }
+}
+
+/// Parses the available space in KiB from the output of `df -Pk`.
+fn parse_df_available(output: &str) -> anyhow::Result<u64> {
+    output
+        .lines()
+        .nth(1)
+        .and_then(|line| line.split_whitespace().nth(3))
+        .ok_or_else(|| anyhow::anyhow!("Unexpected output of df: {output}"))?
+        .parse()
+        .context("Unexpected output of df")
+}
+
+#[test]
+fn parse_df_available_test() {
+    let output = "Filesystem 1024-blocks Used Available Capacity Mounted \
+                  on\n/dev/sda1 102400 51200 51200 50% /\n";
+    assert_eq!(parse_df_available(output).unwrap(), 51200);
+    assert!(parse_df_available("").is_err());
+}
+
+/// Checks the free space of the disk of the directory, or of its closest
+/// existing parent.
+pub async fn check_disk_space(dir: &Path) -> Check {
+    let dir = dir
+        .ancestors()
+        .find(|d| d.exists())
+        .unwrap_or(Path::new("/"));
+    let res = async {
+        let output = tokio::process::Command::new("df")
+            .arg("-Pk")
+            .arg(dir)
+            .output()
+            .await
+            .context("Failed to run df")?;
+        parse_df_available(&String::from_utf8_lossy(&output.stdout))
+    }
+    .await;
+    let name = format!("disk space of {}", dir.display());
+    match res {
+        Ok(kib) => {
+            let gib = kib / (1024 * 1024);
+            let status = if gib < MIN_DISK_SPACE_GIB {
+                CheckStatus::Fail
+            } else if gib < LOW_DISK_SPACE_GIB {
+                CheckStatus::Warn
+            } else {
+                CheckStatus::Pass
+            };
+            Check::new(name, status, format!("{gib} GiB free"))
+        },
+        Err(err) => Check::new(name, CheckStatus::Fail, format!("{err:#}")),
+    }
+}
+
+/// Checks the integrity of a local redb database, e.g. the local vector
+/// store, if it exists.
+pub async fn check_database(name: &'static str, path: &Path) -> Check {
+    if !path.exists() {
+        return Check::new(
+            name,
+            CheckStatus::Skip,
+            format!("{} does not exist", path.display()),
+        );
+    }
+    let path = path.to_owned();
+    let res = spawn_blocking(move || -> anyhow::Result<String> {
+        let mut db = redb::Database::open(&path).with_context(|| {
+            format!("Failed to open {}, is it in use?", path.display())
+        })?;
+        if db.check_integrity()? {
+            Ok(format!("{} is intact", path.display()))
+        } else {
+            Ok(format!("{} was repaired", path.display()))
+        }
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|res| res);
+    Check::from_result(name, res)
+}
+
+/// Checks the chat platform with a tiny chat completion, which verifies the
+/// key, the model and the connectivity.
+pub async fn check_chat(api: &dyn ChatCompletionAPI) -> Check {
+    let messages = [Message {
+        role: Role::User,
+        content: "Reply with the word OK.".into(),
+    }];
+    let res = api
+        .run_chat(ChatCompletionsArgs::builder().messages(&messages).build())
+        .await
+        .map(|msg| format!("replied {:?}", msg.content.trim()));
+    Check::from_result("chat platform", res)
+}
+
+/// Checks the embeddings platform with the embedding of a word.
+pub async fn check_embeddings(api: &dyn EmbeddingsAPI) -> Check {
+    let res = api
+        .get_embedding(EmbeddingsArgs::builder().input("ping").build())
+        .await
+        .map(|embedding| format!("{} dimensions", embedding.len()));
+    Check::from_result("embeddings platform", res)
+}
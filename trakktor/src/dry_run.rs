@@ -0,0 +1,240 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use strum::IntoEnumIterator;
+
+use crate::{
+    llm::{ChatCompletionAPI, ChatCompletionsArgs, ChatTask, Message, Role},
+    llm_audit::TokenUsage,
+    rate_limit::estimate_tokens,
+};
+
+/// The prices of the prompt and the completion tokens of the known models,
+/// in USD per million tokens. The first model the name starts with is used.
+const PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4.1-nano", 0.1, 0.4),
+    ("gpt-4.1-mini", 0.4, 1.6),
+    ("gpt-4.1", 2.0, 8.0),
+    ("o3-mini", 1.1, 4.4),
+    ("o4-mini", 1.1, 4.4),
+    ("o1", 15.0, 60.0),
+    ("gemini-1.5-flash", 0.075, 0.3),
+    ("gemini-1.5-pro", 1.25, 5.0),
+    ("gemini-2.0-flash", 0.1, 0.4),
+];
+
+fn prices(model: &str) -> Option<(f64, f64)> {
+    // E.g. "openai/gpt-4o" of OpenRouter.
+    let model = model.rsplit('/').next().unwrap_or(model);
+    PRICES
+        .iter()
+        .find(|(prefix, ..)| model.starts_with(prefix))
+        .map(|(_, prompt, completion)| (*prompt, *completion))
+}
+
+fn prompt_price(model: &str) -> Option<f64> {
+    prices(model).map(|(prompt, _)| prompt)
+}
+
+/// The cost of the tokens in USD, if the price of the model is known.
+pub fn estimate_cost(model: &str, usage: TokenUsage) -> Option<f64> {
+    let (prompt, completion) = prices(model)?;
+    Some(
+        (prompt * usage.prompt_tokens as f64 +
+            completion * usage.completion_tokens as f64) /
+            1e6,
+    )
+}
+
+#[test]
+fn prompt_price_test() {
+    assert_eq!(prompt_price("gpt-4o-mini-2024-07-18"), Some(0.15));
+    assert_eq!(prompt_price("openai/gpt-4o"), Some(2.5));
+    assert_eq!(prompt_price("my-deployment"), None);
+    let usage = TokenUsage {
+        prompt_tokens: 1000,
+        completion_tokens: 100,
+    };
+    assert_eq!(estimate_cost("gpt-4o", usage), Some(0.0035));
+    assert_eq!(estimate_cost("my-deployment", usage), None);
+}
+
+#[derive(Debug, Default)]
+struct ModelTotals {
+    requests: u64,
+    prompt_tokens: u64,
+}
+
+/// Prints the chat completions of a run instead of sending them, and adds up
+/// their estimated tokens and cost.
+#[derive(Debug, Default)]
+pub struct DryRun {
+    totals: Mutex<BTreeMap<Option<String>, ModelTotals>>,
+}
+
+impl DryRun {
+    pub fn new() -> Arc<Self> { Arc::new(Self::default()) }
+
+    /// A chat API that prints the requests and answers them with the last
+    /// user message, so the later steps of a pipeline still run. It has the
+    /// config hash of the API, so the responses cached by earlier runs are
+    /// used, and are not printed.
+    pub fn chat_api(
+        self: &Arc<Self>,
+        api: &dyn ChatCompletionAPI,
+    ) -> Box<dyn ChatCompletionAPI> {
+        Box::new(DryRunChatAPI {
+            dry_run: Arc::clone(self),
+            config_hash: api.config_hash(),
+            default_model: api.model_name(None).map(str::to_string),
+            task_models: ChatTask::iter()
+                .map(|task| {
+                    (task, api.model_name(Some(task)).map(str::to_string))
+                })
+                .collect(),
+        })
+    }
+
+    /// Prints the request that would be sent to the model.
+    pub fn print_request(
+        &self,
+        model: Option<&str>,
+        args: &ChatCompletionsArgs,
+    ) {
+        let prompt_tokens = args
+            .messages
+            .iter()
+            .map(|msg| estimate_tokens(msg.content.len()) as u64)
+            .sum::<u64>();
+        let number = {
+            let mut totals = self.totals.lock().unwrap();
+            let model_totals =
+                totals.entry(model.map(str::to_string)).or_default();
+            model_totals.requests += 1;
+            model_totals.prompt_tokens += prompt_tokens;
+            totals.values().map(|t| t.requests).sum::<u64>()
+        };
+
+        let mut out = format!(
+            "===== Request {number}: {}{}, ~{prompt_tokens} prompt tokens \
+             =====\n",
+            model.unwrap_or("default model"),
+            args.task
+                .map(|task| format!(" ({task:?})"))
+                .unwrap_or_default(),
+        );
+        for msg in args.messages {
+            out.push_str(&format!(
+                "--- {:?} ---\n{}\n",
+                msg.role,
+                msg.content.trim()
+            ));
+        }
+        if let Some(format) = args.response_format {
+            out.push_str(&format!("--- Response format ---\n{format:#}\n"));
+        }
+        if let Some(effort) = args.reasoning_effort {
+            out.push_str(&format!("--- Reasoning effort: {effort:?}\n"));
+        }
+        if let Some(max_tokens) = args.max_completion_tokens {
+            out.push_str(&format!("--- Max completion tokens: {max_tokens}\n"));
+        }
+        if let Some(seed) = args.seed {
+            out.push_str(&format!("--- Seed: {seed}\n"));
+        }
+        println!("{out}");
+    }
+
+    /// Prints the requests, the tokens and the cost by model.
+    pub fn print_summary(&self) {
+        let totals = self.totals.lock().unwrap();
+        if totals.is_empty() {
+            println!("Dry run: no requests, all the responses are cached.");
+            return;
+        }
+        let mut cost = 0.0;
+        let mut unknown_prices = false;
+        println!("Dry run summary:");
+        for (model, t) in totals.iter() {
+            let price = model.as_deref().and_then(prompt_price);
+            let model_cost = price.map(|p| p * t.prompt_tokens as f64 / 1e6);
+            cost += model_cost.unwrap_or_default();
+            unknown_prices |= model_cost.is_none();
+            println!(
+                "  {}: {} requests, ~{} prompt tokens, {}",
+                model.as_deref().unwrap_or("default model"),
+                t.requests,
+                t.prompt_tokens,
+                model_cost
+                    .map(|c| format!("~${c:.4}"))
+                    .unwrap_or_else(|| "unknown price".to_string()),
+            );
+        }
+        println!(
+            "  Total: ~${cost:.4} for the prompts{}. The completions are not \
+             included, and the requests that depend on the responses are \
+             estimated from stand-in responses.",
+            if unknown_prices {
+                " of the known models"
+            } else {
+                ""
+            },
+        );
+    }
+}
+
+struct DryRunChatAPI {
+    dry_run: Arc<DryRun>,
+    config_hash: String,
+    default_model: Option<String>,
+    task_models: BTreeMap<ChatTask, Option<String>>,
+}
+
+#[async_trait::async_trait]
+impl ChatCompletionAPI for DryRunChatAPI {
+    async fn run_chat(
+        &self,
+        args: ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<Message<'static>> {
+        let model = args.model_overwrite.or(self.model_name(args.task));
+        self.dry_run.print_request(model, &args);
+        let content = args
+            .messages
+            .iter()
+            .rev()
+            .find(|msg| matches!(msg.role, Role::User))
+            .map(|msg| msg.content.to_string())
+            .unwrap_or_default();
+        Ok(Message {
+            role: Role::Assistant,
+            content: content.into(),
+        })
+    }
+
+    fn config_hash(&self) -> String { self.config_hash.clone() }
+
+    fn model_name(&self, task: Option<ChatTask>) -> Option<&str> {
+        task.and_then(|task| self.task_models.get(&task))
+            .unwrap_or(&self.default_model)
+            .as_deref()
+    }
+}
+
+/// Writes an output file of a pipeline, or only logs it in a dry run.
+pub(crate) async fn write_output(
+    dry_run: bool,
+    path: &Path,
+    contents: impl AsRef<[u8]>,
+) -> anyhow::Result<()> {
+    if dry_run {
+        tracing::info!("Dry run, not writing: {}", path.display());
+        return Ok(());
+    }
+    tokio::fs::write(path, contents).await?;
+    Ok(())
+}
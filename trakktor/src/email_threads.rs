@@ -0,0 +1,809 @@
+//! Summarization of email threads.
+//!
+//! Emails are read from `.eml` files or mbox archives and grouped into
+//! threads by their `References` and `In-Reply-To` headers, falling back to
+//! the subject with the reply prefixes removed. The quoted text of previous
+//! messages and the signatures are dropped, so that every message in a
+//! thread contributes only what it adds to the conversation. Each thread is
+//! then summarized and its action items are extracted with the chat API.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, FixedOffset};
+use clap::{Parser, ValueHint};
+use itertools::Itertools;
+use tokio::task::spawn_blocking;
+
+use crate::{
+    dry_run::{write_output, DryRun},
+    ingest_url::extract_article,
+    llm::{ChatCompletionAPI, ChatTask},
+    structify_text::{run_cached_prompt, CallCache, CACHE_FILE_EXT},
+};
+
+#[derive(Parser, Debug)]
+pub struct SummarizeEmails {
+    /// The emails to summarize: an `.eml` file, an mbox archive, or a
+    /// directory of `.eml` files.
+    #[arg(long, short, value_hint = ValueHint::AnyPath)]
+    pub file: PathBuf,
+    /// Print the requests to the model instead of sending them, with their
+    /// estimated tokens and cost. Nothing is written.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+const SUMMARIES_FILE_EXT: &str = "trakktor.email-summaries.md";
+const THREADS_FILE_EXT: &str = "trakktor.email-threads.md";
+/// Only the latest messages of longer threads are summarized.
+const MAX_THREAD_WORDS: usize = 6000;
+const REPLY_PREFIXES: &[&str] = &["re", "fw", "fwd", "aw", "wg", "sv", "vs"];
+
+const SUMMARIZE_THREAD_PROMPT: &str = r#"
+You will be given an email thread, with the quoted text removed from the
+messages. Summarize the thread in one or two paragraphs: what it is about,
+what was discussed, and what was decided. Mention the participants by name
+where it matters. Write in the language of the thread. Reply with the summary
+only.
+"#;
+
+const THREAD_ACTION_ITEMS_PROMPT: &str = r#"
+You will be given an email thread, with the quoted text removed from the
+messages. List the action items that come out of the thread: who has to do
+what, and by when, if it is mentioned. Reply with a Markdown list, one action
+item per line, and nothing else. If there are no action items, reply with
+"None".
+"#;
+
+#[derive(Debug, Clone)]
+struct Email {
+    message_id: Option<String>,
+    /// The ids of the messages this one replies to.
+    references: Vec<String>,
+    subject: String,
+    from: String,
+    date: Option<DateTime<FixedOffset>>,
+    /// The text of the message, without the quotes and the signature.
+    body: String,
+}
+
+#[derive(Debug)]
+struct Thread {
+    subject: String,
+    emails: Vec<Email>,
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn run_summarize_emails(
+    args: &SummarizeEmails,
+    chat_api: &dyn ChatCompletionAPI,
+) -> crate::Result<()> {
+    Ok(summarize_emails(args, chat_api).await?)
+}
+
+async fn summarize_emails(
+    args: &SummarizeEmails,
+    chat_api: &dyn ChatCompletionAPI,
+) -> anyhow::Result<()> {
+    let emails = read_emails(&args.file).await?;
+    if emails.is_empty() {
+        anyhow::bail!("No emails found in: {}", args.file.display());
+    }
+    let threads = group_threads(emails);
+    tracing::info!(threads = threads.len(), "Grouped emails into threads");
+
+    let dry_run = args.dry_run.then(DryRun::new);
+    let dry_run_api = dry_run.as_ref().map(|d| d.chat_api(chat_api));
+    let chat_api = dry_run_api.as_deref().unwrap_or(chat_api);
+
+    let cache = Arc::new({
+        let db_name = args.file.with_extension(CACHE_FILE_EXT);
+        let dry_run = args.dry_run;
+        spawn_blocking(move || {
+            CallCache::open_for(&db_name, dry_run).map_err(|source| {
+                crate::Error::Cache {
+                    path: db_name,
+                    source: source.into(),
+                }
+            })
+        })
+        .await??
+    });
+
+    let mut summaries = Vec::new();
+    let mut thread_texts = Vec::new();
+    for (i, thread) in threads.iter().enumerate() {
+        tracing::info!(
+            "Summarizing thread {}/{}: {}",
+            i + 1,
+            threads.len(),
+            thread.subject,
+        );
+        let text = thread_text(thread);
+        let (summary, action_items) = tokio::try_join!(
+            run_cached_prompt(
+                chat_api,
+                &cache,
+                "summarize_thread",
+                ChatTask::Summary,
+                SUMMARIZE_THREAD_PROMPT,
+                &text,
+            ),
+            run_cached_prompt(
+                chat_api,
+                &cache,
+                "thread_action_items",
+                ChatTask::ActionItems,
+                THREAD_ACTION_ITEMS_PROMPT,
+                &text,
+            ),
+        )?;
+
+        summaries.push(format!(
+            "## {}\n\n{}\n\n### Summary\n\n{}\n\n### Action items\n\n{}",
+            thread.subject,
+            thread_details(thread),
+            summary.trim(),
+            action_items.trim(),
+        ));
+        thread_texts.push(format!("## {}\n\n{}", thread.subject, text));
+    }
+
+    let summaries_file = args.file.with_extension(SUMMARIES_FILE_EXT);
+    write_output(args.dry_run, &summaries_file, summaries.join("\n\n")).await?;
+    let threads_file = args.file.with_extension(THREADS_FILE_EXT);
+    write_output(args.dry_run, &threads_file, thread_texts.join("\n\n"))
+        .await?;
+
+    if !args.dry_run {
+        tracing::info!(
+            "Wrote thread summaries to: {}",
+            summaries_file.display()
+        );
+        tracing::info!("Wrote threads to: {}", threads_file.display());
+    }
+    if let Some(dry_run) = &dry_run {
+        dry_run.print_summary();
+    }
+
+    Ok(())
+}
+
+async fn read_emails(path: &Path) -> anyhow::Result<Vec<Email>> {
+    let mut files = Vec::new();
+    if tokio::fs::metadata(path).await?.is_dir() {
+        let mut entries = tokio::fs::read_dir(path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file = entry.path();
+            if file.extension().is_some_and(|ext| ext == "eml") {
+                files.push(file);
+            }
+        }
+        files.sort();
+    } else {
+        files.push(path.to_path_buf());
+    }
+
+    let mut emails = Vec::new();
+    for file in files {
+        let data = tokio::fs::read(&file).await?;
+        if data.starts_with(b"From ") {
+            emails.extend(split_mbox(&data).iter().map(|m| parse_email(m)));
+        } else {
+            emails.push(parse_email(&data));
+        }
+    }
+    Ok(emails)
+}
+
+/// Splits an mbox archive into messages, undoing the `>From ` quoting.
+fn split_mbox(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    let mut current: Option<Vec<u8>> = None;
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        if line.starts_with(b"From ") {
+            messages.extend(current.take());
+            current = Some(Vec::new());
+            continue;
+        }
+        let Some(message) = current.as_mut() else {
+            continue;
+        };
+        let quotes = line.iter().take_while(|&&b| b == b'>').count();
+        if quotes > 0 && line[quotes..].starts_with(b"From ") {
+            message.extend_from_slice(&line[1..]);
+        } else {
+            message.extend_from_slice(line);
+        }
+    }
+    messages.extend(current);
+    messages
+}
+
+fn parse_email(data: &[u8]) -> Email {
+    let (headers, body) = split_headers(data);
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    };
+
+    let references = ["in-reply-to", "references"]
+        .into_iter()
+        .filter_map(&header)
+        .flat_map(message_ids)
+        .unique()
+        .collect();
+    let from = decode_encoded_words(header("from").unwrap_or_default());
+    let date = header("date").and_then(|date| {
+        // Drop the trailing comment, e.g. "(UTC)".
+        let date = date.split('(').next().unwrap_or_default().trim();
+        DateTime::parse_from_rfc2822(date).ok()
+    });
+    let body = match extract_body(&headers, body) {
+        Some(Body::Plain(text)) => text,
+        Some(Body::Html(html)) => extract_article(&html).text,
+        None => String::new(),
+    };
+
+    Email {
+        message_id: header("message-id")
+            .and_then(|v| message_ids(v).into_iter().next()),
+        references,
+        subject: decode_encoded_words(header("subject").unwrap_or_default())
+            .trim()
+            .to_string(),
+        from: display_name(&from),
+        date,
+        body: normalize_quoting(&body),
+    }
+}
+
+/// Splits a message or a MIME part into the unfolded headers, with the
+/// names lowercased, and the body.
+fn split_headers(data: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let end = data[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(data.len(), |i| pos + i + 1);
+        let line = String::from_utf8_lossy(&data[pos..end]);
+        let line = line.trim_end_matches(['\r', '\n']);
+        pos = end;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            if !name.contains(char::is_whitespace) {
+                headers.push((
+                    name.to_ascii_lowercase(),
+                    value.trim().to_string(),
+                ));
+            }
+        }
+    }
+    (headers, &data[pos..])
+}
+
+/// Extracts the `<...>` message ids from a header value.
+fn message_ids(value: &str) -> Vec<String> {
+    value
+        .split('<')
+        .skip(1)
+        .filter_map(|id| id.split_once('>'))
+        .map(|(id, _)| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect()
+}
+
+/// Takes the display name of an address, or the address itself.
+fn display_name(address: &str) -> String {
+    let name = match address.split_once('<') {
+        Some((name, addr)) => {
+            let name = name.trim().trim_matches('"').trim();
+            if name.is_empty() {
+                addr.trim_end_matches('>').trim()
+            } else {
+                name
+            }
+        },
+        None => address.trim(),
+    };
+    name.to_string()
+}
+
+enum Body {
+    Plain(String),
+    Html(String),
+}
+
+/// Finds the text of a message, preferring the plain text over HTML in
+/// multipart messages. Attachments are skipped.
+fn extract_body(headers: &[(String, String)], body: &[u8]) -> Option<Body> {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    };
+    let (mime, params) = parse_content_type(header("content-type"));
+
+    if mime.starts_with("multipart/") {
+        let boundary = params.get("boundary")?;
+        let mut html = None;
+        for part in split_multipart(body, boundary) {
+            let (part_headers, part_body) = split_headers(part);
+            let is_attachment = part_headers.iter().any(|(n, v)| {
+                n == "content-disposition" &&
+                    v.to_ascii_lowercase().starts_with("attachment")
+            });
+            if is_attachment {
+                continue;
+            }
+            match extract_body(&part_headers, part_body) {
+                Some(Body::Plain(text)) if !text.trim().is_empty() => {
+                    return Some(Body::Plain(text));
+                },
+                Some(Body::Html(text)) => {
+                    html.get_or_insert(text);
+                },
+                _ => {},
+            }
+        }
+        return html.map(Body::Html);
+    }
+
+    if mime != "text/plain" && mime != "text/html" {
+        return None;
+    }
+    let encoding = header("content-transfer-encoding")
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let data = match encoding.as_str() {
+        "base64" => {
+            let data = body
+                .iter()
+                .copied()
+                .filter(|b| !b.is_ascii_whitespace())
+                .collect_vec();
+            STANDARD.decode(data).unwrap_or_else(|_| body.to_vec())
+        },
+        "quoted-printable" => decode_quoted_printable(body, false),
+        _ => body.to_vec(),
+    };
+    let charset = params.get("charset").map_or("utf-8", |c| c.as_str());
+    let text = decode_charset(&data, charset).replace("\r\n", "\n");
+
+    Some(if mime == "text/html" {
+        Body::Html(text)
+    } else {
+        Body::Plain(text)
+    })
+}
+
+/// Parses a `Content-Type` header into the lowercased MIME type and the
+/// parameters. Messages without the header are plain text.
+fn parse_content_type(
+    value: Option<&str>,
+) -> (String, HashMap<String, String>) {
+    let Some(value) = value else {
+        return ("text/plain".to_string(), HashMap::new());
+    };
+    let mut parts = value.split(';');
+    let mime = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+    let params = parts
+        .filter_map(|param| param.split_once('='))
+        .map(|(name, value)| {
+            (
+                name.trim().to_ascii_lowercase(),
+                value.trim().trim_matches('"').to_string(),
+            )
+        })
+        .collect();
+    (mime, params)
+}
+
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{boundary}");
+    let mut parts = Vec::new();
+    let mut part_start = None;
+    let mut pos = 0;
+    while pos < body.len() {
+        let end = body[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(body.len(), |i| pos + i + 1);
+        let line = &body[pos..end];
+        if let Some(rest) = line.strip_prefix(delimiter.as_bytes()) {
+            if let Some(start) = part_start {
+                parts.push(trim_line_break(&body[start..pos]));
+            }
+            if rest.starts_with(b"--") {
+                return parts;
+            }
+            part_start = Some(end);
+        }
+        pos = end;
+    }
+    // Tolerate a missing closing delimiter.
+    if let Some(start) = part_start {
+        parts.push(&body[start..]);
+    }
+    parts
+}
+
+fn trim_line_break(data: &[u8]) -> &[u8] {
+    let data = data.strip_suffix(b"\n").unwrap_or(data);
+    data.strip_suffix(b"\r").unwrap_or(data)
+}
+
+/// Decodes the quoted-printable encoding of bodies, or the "Q" encoding of
+/// the encoded words in headers, where `_` stands for a space.
+fn decode_quoted_printable(data: &[u8], underscore_is_space: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'=' => {
+                let rest = &data[i + 1..];
+                // Soft line break.
+                if rest.starts_with(b"\r\n") {
+                    i += 3;
+                    continue;
+                }
+                if rest.starts_with(b"\n") {
+                    i += 2;
+                    continue;
+                }
+                let byte = rest
+                    .get(..2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match byte {
+                    Some(byte) => {
+                        out.push(byte);
+                        i += 3;
+                        continue;
+                    },
+                    None => out.push(b'='),
+                }
+            },
+            b'_' if underscore_is_space => out.push(b' '),
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    out
+}
+
+/// Decodes the text in the given charset. Only UTF-8 and the Latin-1 family
+/// are supported, anything else is decoded as UTF-8 with replacements.
+fn decode_charset(data: &[u8], charset: &str) -> String {
+    if let Ok(text) = std::str::from_utf8(data) {
+        return text.to_string();
+    }
+    let charset = charset.to_ascii_lowercase();
+    if charset.starts_with("iso-8859") ||
+        charset.starts_with("latin") ||
+        charset == "windows-1252"
+    {
+        return data.iter().map(|&b| b as char).collect();
+    }
+    String::from_utf8_lossy(data).into_owned()
+}
+
+/// Decodes the RFC 2047 encoded words, e.g. `=?UTF-8?B?...?=`, in a header
+/// value.
+fn decode_encoded_words(value: &str) -> String {
+    let mut result = String::new();
+    let mut rest = value;
+    let mut after_encoded_word = false;
+    while let Some(start) = rest.find("=?") {
+        let Some((word, len)) = parse_encoded_word(&rest[start..]) else {
+            result.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            after_encoded_word = false;
+            continue;
+        };
+        // The whitespace between adjacent encoded words is ignored.
+        let between = &rest[..start];
+        if !(after_encoded_word && between.trim().is_empty()) {
+            result.push_str(between);
+        }
+        result.push_str(&word);
+        rest = &rest[start + len..];
+        after_encoded_word = true;
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Parses an encoded word at the start of the text, returning the decoded
+/// text and the length of the word.
+fn parse_encoded_word(text: &str) -> Option<(String, usize)> {
+    let mut parts = text.strip_prefix("=?")?.splitn(3, '?');
+    let charset = parts.next()?;
+    let encoding = parts.next()?;
+    let encoded = parts.next()?;
+    let end = encoded.find("?=")?;
+    let encoded = &encoded[..end];
+    if charset.contains(char::is_whitespace) ||
+        encoded.contains(char::is_whitespace)
+    {
+        return None;
+    }
+    let data = match encoding {
+        "B" | "b" => STANDARD.decode(encoded).ok()?,
+        "Q" | "q" => decode_quoted_printable(encoded.as_bytes(), true),
+        _ => return None,
+    };
+    let len = charset.len() + encoding.len() + end + 6;
+    // The charset may carry a language, e.g. "utf-8*en".
+    let charset = charset.split('*').next().unwrap_or_default();
+    Some((decode_charset(&data, charset), len))
+}
+
+/// Removes the quoted text of the previous messages together with its
+/// attribution line, the signature, and the forwarded or replied-to message
+/// appended below the reply.
+fn normalize_quoting(body: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in body.lines() {
+        let line = line.trim_end();
+        if line == "--" || is_original_message_marker(line) {
+            break;
+        }
+        if line.starts_with('>') {
+            while lines.last().is_some_and(|l| l.is_empty()) {
+                lines.pop();
+            }
+            if lines.last().is_some_and(|l| is_attribution(l)) {
+                lines.pop();
+            }
+            continue;
+        }
+        lines.push(line);
+    }
+
+    lines
+        .split(|line| line.trim().is_empty())
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|paragraph| paragraph.join("\n"))
+        .join("\n\n")
+}
+
+fn is_attribution(line: &str) -> bool {
+    let line = line.trim();
+    line.ends_with("wrote:") ||
+        line.ends_with("writes:") ||
+        (line.starts_with("On ") && line.ends_with(':'))
+}
+
+fn is_original_message_marker(line: &str) -> bool {
+    let line = line.trim();
+    (line.starts_with("-----") && line.to_lowercase().contains("original")) ||
+        (line.len() >= 10 && line.chars().all(|c| c == '_'))
+}
+
+fn normalize_subject(subject: &str) -> String {
+    let mut subject = subject.trim();
+    while let Some((prefix, rest)) = subject.split_once(':') {
+        let prefix = prefix.trim().to_ascii_lowercase();
+        // E.g. "Re[2]: ..."
+        let prefix = prefix.split('[').next().unwrap_or_default();
+        if !REPLY_PREFIXES.contains(&prefix) {
+            break;
+        }
+        subject = rest.trim();
+    }
+    subject.to_lowercase()
+}
+
+/// Groups the emails into threads, ordered by the date of their first
+/// message.
+fn group_threads(emails: Vec<Email>) -> Vec<Thread> {
+    let mut parents = (0..emails.len()).collect_vec();
+    fn find(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+    let mut union = |a: usize, b: usize| {
+        let (a, b) = (find(&mut parents, a), find(&mut parents, b));
+        parents[a.max(b)] = a.min(b);
+    };
+
+    let by_id: HashMap<&str, usize> = emails
+        .iter()
+        .enumerate()
+        .filter_map(|(i, e)| e.message_id.as_deref().map(|id| (id, i)))
+        .collect();
+    let mut by_subject: HashMap<String, usize> = HashMap::new();
+    for (i, email) in emails.iter().enumerate() {
+        for id in &email.references {
+            if let Some(&j) = by_id.get(id.as_str()) {
+                union(i, j);
+            }
+        }
+        let subject = normalize_subject(&email.subject);
+        if !subject.is_empty() {
+            union(i, *by_subject.entry(subject).or_insert(i));
+        }
+    }
+
+    let mut groups: Vec<Vec<Email>> = Vec::new();
+    let mut group_of_root = HashMap::new();
+    for (i, email) in emails.into_iter().enumerate() {
+        let root = find(&mut parents, i);
+        let group = *group_of_root.entry(root).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[group].push(email);
+    }
+
+    let mut threads = groups
+        .into_iter()
+        .map(|mut emails| {
+            emails.sort_by_key(|e| e.date);
+            let subject = emails
+                .iter()
+                .map(|e| e.subject.as_str())
+                .find(|s| !s.is_empty())
+                .unwrap_or("(no subject)")
+                .to_string();
+            Thread { subject, emails }
+        })
+        .collect_vec();
+    threads.sort_by_key(|t| t.emails.first().and_then(|e| e.date));
+    threads
+}
+
+/// The text of the thread for the chat API, truncated to the latest
+/// messages.
+fn thread_text(thread: &Thread) -> String {
+    let mut words = 0;
+    let mut messages = Vec::new();
+    for email in thread.emails.iter().rev() {
+        let mut text = format!("From: {}\n", email.from);
+        if let Some(date) = email.date {
+            text.push_str(&format!("Date: {}\n", date.to_rfc2822()));
+        }
+        text.push('\n');
+        text.push_str(&email.body);
+
+        words += text.split_whitespace().count();
+        if words > MAX_THREAD_WORDS {
+            if messages.is_empty() {
+                messages.push(
+                    text.split_whitespace().take(MAX_THREAD_WORDS).join(" "),
+                );
+            }
+            break;
+        }
+        messages.push(text);
+    }
+    messages.reverse();
+    messages.join("\n\n---\n\n")
+}
+
+fn thread_details(thread: &Thread) -> String {
+    let participants = thread
+        .emails
+        .iter()
+        .map(|e| e.from.as_str())
+        .filter(|from| !from.is_empty())
+        .unique()
+        .join(", ");
+    let mut details = format!(
+        "- Messages: {}\n- Participants: {}",
+        thread.emails.len(),
+        participants,
+    );
+    let dates = thread.emails.iter().filter_map(|e| e.date).collect_vec();
+    if let (Some(first), Some(last)) = (dates.first(), dates.last()) {
+        details.push_str(&format!(
+            "\n- Dates: {} – {}",
+            first.format("%Y-%m-%d %H:%M"),
+            last.format("%Y-%m-%d %H:%M"),
+        ));
+    }
+    details
+}
+
+#[test]
+fn parse_email_test() {
+    let email = parse_email(
+        b"From: =?UTF-8?B?SsO8cmdlbg==?= <j@example.com>\r\n\
+          Subject: =?ISO-8859-1?Q?Caf=E9_?=\r\n =?UTF-8?Q?plans?=\r\n\
+          Message-ID: <b@example.com>\r\n\
+          In-Reply-To: <a@example.com>\r\n\
+          Date: Tue, 1 Oct 2024 10:00:00 +0200 (CEST)\r\n\
+          Content-Type: multipart/alternative; boundary=\"xyz\"\r\n\
+          \r\n\
+          --xyz\r\n\
+          Content-Type: text/html\r\n\
+          \r\n\
+          <p>Ignored</p>\r\n\
+          --xyz\r\n\
+          Content-Type: text/plain; charset=utf-8\r\n\
+          Content-Transfer-Encoding: quoted-printable\r\n\
+          \r\n\
+          Let's meet at the caf=C3=A9 at 5, the one on the main =\r\n\
+          street.\r\n\
+          --xyz--\r\n",
+    );
+    assert_eq!(email.from, "Jürgen");
+    assert_eq!(email.subject, "Café plans");
+    assert_eq!(email.message_id.as_deref(), Some("b@example.com"));
+    assert_eq!(email.references, vec!["a@example.com"]);
+    assert!(email.date.is_some());
+    assert_eq!(
+        email.body,
+        "Let's meet at the café at 5, the one on the main street."
+    );
+}
+
+#[test]
+fn normalize_quoting_test() {
+    let body = "Sounds good.\n\n\nI'll book the room.\n\nOn Mon, Sep 30, 2024 \
+                at 9:00 AM Ann <ann@example.com> wrote:\n> Can we meet \
+                tomorrow?\n> \n\nAlso, bring the slides.\n-- \nBob\n";
+    assert_eq!(
+        normalize_quoting(body),
+        "Sounds good.\n\nI'll book the room.\n\nAlso, bring the slides."
+    );
+    assert_eq!(
+        normalize_quoting("Yes.\n\n-----Original Message-----\nFrom: Ann"),
+        "Yes."
+    );
+}
+
+#[test]
+fn group_threads_test() {
+    let email = |id: &str, refs: &[&str], subject: &str, day: u32| Email {
+        message_id: Some(id.to_string()),
+        references: refs.iter().map(|r| r.to_string()).collect(),
+        subject: subject.to_string(),
+        from: String::new(),
+        date: DateTime::parse_from_rfc3339(&format!(
+            "2024-10-{day:02}T10:00:00Z"
+        ))
+        .ok(),
+        body: String::new(),
+    };
+    let threads = group_threads(vec![
+        email("c", &["a"], "Budget review", 3),
+        email("d", &[], "Offsite", 2),
+        email("a", &[], "Budget", 1),
+        email("e", &[], "RE: Fwd: offsite", 4),
+    ]);
+    let ids = threads
+        .iter()
+        .map(|t| {
+            t.emails
+                .iter()
+                .map(|e| e.message_id.as_deref().unwrap())
+                .collect_vec()
+        })
+        .collect_vec();
+    assert_eq!(ids, vec![vec!["a", "c"], vec!["d", "e"]]);
+    assert_eq!(threads[0].subject, "Budget");
+    assert_eq!(threads[1].subject, "Offsite");
+}
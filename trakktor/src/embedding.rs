@@ -6,6 +6,13 @@ use serde::Deserialize;
 pub enum EmbeddingsPlatform {
     #[serde(rename = "open-ai")]
     OpenAI,
+    /// The Azure OpenAI Service, the embeddings model is the name of the
+    /// deployment.
+    #[serde(rename = "azure-openai")]
+    #[value(name = "azure-openai")]
+    AzureOpenAI,
+    #[serde(rename = "gemini")]
+    Gemini,
 }
 
 #[builder]
@@ -25,7 +32,7 @@ impl<'a> EmbeddingsArgs<'a> {
 }
 
 #[async_trait::async_trait]
-pub trait EmbeddingsAPI {
+pub trait EmbeddingsAPI: Sync {
     async fn get_embedding(
         &self,
         args: EmbeddingsArgs<'_>,
@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+
+use crate::cancellation::Cancelled;
+
+/// The error of the entry points of the library: the [`crate::client`]
+/// facade and the functions running the commands. The failures a
+/// caller may want to tell apart have their own variants, the rest are
+/// [`Error::Other`]. Inside the library the errors are carried in
+/// [`anyhow::Error`] and converted at the entry points.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A CloudFormation stack is missing or in a failed state.
+    #[error("Stack {stack} {reason}")]
+    AwsStack { stack: String, reason: String },
+    /// An S3 object could not be read or written.
+    #[error("Failed to access the S3 object {key}")]
+    S3 {
+        key: String,
+        #[source]
+        source: Box<aws_sdk_s3::Error>,
+    },
+    /// An LLM API responded with an error status.
+    #[error("Failed to call API!\nCode: {status}\nResponse: {body}")]
+    Llm { status: u16, body: String },
+    /// The cache of the LLM calls could not be opened.
+    #[error("Failed to open the cache {}", path.display())]
+    Cache {
+        path: PathBuf,
+        #[source]
+        source: Box<redb::Error>,
+    },
+    /// `ffprobe` could not read the audio or video file.
+    #[error("ffprobe failed on {}: {reason}", path.display())]
+    Audio { path: PathBuf, reason: String },
+    /// The client has no model provider for the call.
+    #[error("No model provider set!")]
+    NoModelProvider,
+    /// The client has no AWS settings for the call.
+    #[error("No AWS settings set!")]
+    NoAwsSettings,
+    /// The settings of the client contradict each other.
+    #[error("{0}")]
+    InvalidSettings(String),
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Takes the typed error out of the [`anyhow::Error`], looking through the
+/// contexts added on top of it, so the callers can match on it. The messages
+/// of the contexts are dropped with them.
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        if err.chain().any(|e| e.is::<Error>()) {
+            return err.downcast().unwrap_or_else(Error::Other);
+        }
+        if err.chain().any(|e| e.is::<Cancelled>()) {
+            return err.downcast().map_or_else(Error::Other, Error::Cancelled);
+        }
+        Error::Other(err)
+    }
+}
+
+#[test]
+fn error_downcast_test() {
+    let err = Error::from(anyhow::Error::from(Error::Llm {
+        status: 429,
+        body: "Slow down".into(),
+    }));
+    assert!(matches!(err, Error::Llm { status: 429, .. }));
+
+    let err = anyhow::Error::from(Error::Cache {
+        path: "cache.redb".into(),
+        source: redb::Error::DatabaseAlreadyOpen.into(),
+    });
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::Cache { .. })
+    ));
+    assert_eq!(
+        format!("{err:#}"),
+        "Failed to open the cache cache.redb: Database already open. Cannot \
+         acquire lock."
+    );
+
+    let err = Error::from(err.context("Failed to structify"));
+    assert!(matches!(err, Error::Cache { .. }));
+
+    let err = anyhow::Error::from(Error::Llm {
+        status: 500,
+        body: "Oops".into(),
+    })
+    .context("Failed to punctuate")
+    .context("Failed to structify");
+    assert!(matches!(Error::from(err), Error::Llm { status: 500, .. }));
+
+    let err = Error::from(anyhow::Error::from(Cancelled));
+    assert!(matches!(err, Error::Cancelled(_)));
+    let err = anyhow::Error::from(Cancelled).context("Failed to upload");
+    assert!(matches!(Error::from(err), Error::Cancelled(_)));
+
+    let err = Error::from(anyhow::anyhow!("Bad file").context("Failed"));
+    assert!(matches!(err, Error::Other(_)));
+    assert_eq!(format!("{err:#}"), "Failed: Bad file");
+}
@@ -0,0 +1,530 @@
+use std::{borrow::Cow, sync::Arc, time::Duration};
+
+use anyhow::{bail, Context};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use url::Url;
+
+use crate::{
+    embedding::{EmbeddingsAPI, EmbeddingsArgs},
+    llm::{
+        ChatCompletionAPI, ChatCompletionsArgs, ChatResponse, ChatTask,
+        Message, Role,
+    },
+    llm_audit::{audit_chat, LlmAuditLog, TokenUsage},
+    llm_budget::LlmBudget,
+    rate_limit::RateLimiter,
+    request_extras::RequestExtras,
+};
+
+pub const GEMINI_SERVER_URL: &str = "https://generativelanguage.googleapis.com";
+
+pub const GEMINI_CHAT_DEFAULT_MODEL: &str = "gemini-1.5-pro";
+pub const GEMINI_EMBEDDING_DEFAULT_MODEL: &str = "text-embedding-004";
+
+/// The harm categories the safety threshold applies to.
+const HARM_CATEGORIES: [&str; 4] = [
+    "HARM_CATEGORY_HARASSMENT",
+    "HARM_CATEGORY_HATE_SPEECH",
+    "HARM_CATEGORY_SEXUALLY_EXPLICIT",
+    "HARM_CATEGORY_DANGEROUS_CONTENT",
+];
+
+/// The probability of harm from which the content is blocked by Gemini.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum GeminiSafetyThreshold {
+    /// Never block, e.g. for transcripts of the news.
+    BlockNone,
+    BlockOnlyHigh,
+    BlockMediumAndAbove,
+    BlockLowAndAbove,
+}
+
+impl GeminiSafetyThreshold {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::BlockNone => "BLOCK_NONE",
+            Self::BlockOnlyHigh => "BLOCK_ONLY_HIGH",
+            Self::BlockMediumAndAbove => "BLOCK_MEDIUM_AND_ABOVE",
+            Self::BlockLowAndAbove => "BLOCK_LOW_AND_ABOVE",
+        }
+    }
+}
+
+/// The Gemini API of Google AI Studio.
+#[derive(Debug, Clone)]
+pub struct GeminiAPI {
+    pub api_key: Option<Arc<str>>,
+    pub chat_model: Option<Arc<str>>,
+    pub embeddings_model: Option<Arc<str>>,
+    /// The safety threshold of all the harm categories, the default of the
+    /// API if not set.
+    pub safety_threshold: Option<GeminiSafetyThreshold>,
+    /// Limits the requests in flight, shared by the clones of the API.
+    pub request_permits: Arc<Semaphore>,
+    pub extras: Arc<RequestExtras>,
+    /// Shared by all the APIs of the provider.
+    pub rate_limiter: Arc<RateLimiter>,
+    /// Records the chat completions if set.
+    pub audit_log: Option<Arc<LlmAuditLog>>,
+    /// The budget of the run, shared by all the providers.
+    pub budget: Arc<LlmBudget>,
+}
+
+impl GeminiAPI {
+    #[tracing::instrument(level = "debug", skip(self, req, extra_body))]
+    async fn make_request<I, O>(
+        &self,
+        req: &I,
+        model: &str,
+        method: &str,
+        extra_body: &serde_json::Map<String, serde_json::Value>,
+    ) -> anyhow::Result<O>
+    where
+        I: Serialize + ?Sized + std::fmt::Debug,
+        O: DeserializeOwned + std::fmt::Debug,
+    {
+        let client = self.extras.client()?;
+        let endpoint = Url::parse(GEMINI_SERVER_URL)?
+            .join(&format!("v1beta/models/{model}:{method}"))?;
+
+        tracing::debug!(
+            endpoint = endpoint.to_string(),
+            ?req,
+            "Sending request to API"
+        );
+        let body_len = serde_json::to_vec(req)?.len();
+        let mut req_builder =
+            self.extras.apply(client.post(endpoint), req, extra_body)?;
+        if let Some(api_key) = &self.api_key {
+            req_builder = req_builder.header("x-goog-api-key", api_key.as_ref())
+        }
+        self.budget.start_request()?;
+        self.rate_limiter.acquire(body_len).await;
+        let _permit = self.request_permits.acquire().await?;
+        let res = req_builder.send().await?;
+
+        let code = res.status();
+        tracing::debug!(status = ?code, "API call completed");
+        let res = res.text().await?;
+        tracing::debug!(response = ?res, "API response received");
+
+        if !code.is_success() {
+            return Err(crate::Error::Llm {
+                status: code.as_u16(),
+                body: res,
+            }
+            .into());
+        }
+
+        serde_json::from_str(&res).with_context(|| {
+            format!("Failed to parse response from API:\n{res}")
+        })
+    }
+
+    fn hash_config(
+        &self,
+        model: &Option<Arc<str>>,
+        extra_body: &serde_json::Map<String, serde_json::Value>,
+    ) -> String {
+        let mut hasher = blake3::Hasher::new();
+        if let Some(api_key) = &self.api_key {
+            hasher.update(api_key.as_bytes());
+        }
+        hasher.update(b":");
+        if let Some(model) = model {
+            hasher.update(model.as_bytes());
+        }
+        self.extras.hash_into(&mut hasher, extra_body);
+        URL_SAFE_NO_PAD.encode(hasher.finalize().as_bytes())
+    }
+
+    async fn chat(
+        &self,
+        model: &str,
+        args: &ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<ChatResponse> {
+        let (contents, system_instruction) = to_gemini_contents(args.messages);
+        let mut generation_config =
+            args.response_format.and_then(to_generation_config);
+        if let Some(max_tokens) = args.max_completion_tokens {
+            generation_config
+                .get_or_insert_with(Default::default)
+                .max_output_tokens = Some(max_tokens);
+        }
+        if args.reasoning_effort.is_some() {
+            tracing::debug!(model, "Ignoring the reasoning effort");
+        }
+        let res: GeminiGenerateContentResponse = self
+            .make_request(
+                &GeminiGenerateContent {
+                    contents,
+                    system_instruction,
+                    safety_settings: self
+                        .safety_threshold
+                        .map(|threshold| {
+                            HARM_CATEGORIES
+                                .iter()
+                                .map(|category| GeminiSafetySetting {
+                                    category,
+                                    threshold: threshold.as_str(),
+                                })
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    generation_config,
+                },
+                model,
+                "generateContent",
+                &self.extras.chat_body,
+            )
+            .await?;
+
+        let Some(candidate) = res.candidates.into_iter().next() else {
+            bail!(
+                "Empty response from Chat API, the prompt may be blocked: {}",
+                res.prompt_feedback.unwrap_or_default()
+            );
+        };
+        let finish_reason = candidate.finish_reason.unwrap_or_default();
+        if finish_reason == "SAFETY" {
+            bail!("The response was blocked by the safety settings of Gemini.");
+        }
+        let content = candidate
+            .content
+            .map(|content| {
+                content
+                    .parts
+                    .into_iter()
+                    .filter(|part| !part.thought)
+                    .map(|part| part.text)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if let Some(usage) = &res.usage_metadata {
+            self.rate_limiter
+                .record_response_tokens(usage.candidates_token_count);
+            self.budget.record_tokens(usage.total_token_count);
+        }
+        tracing::info!(usage = ?res.usage_metadata, model = res.model_version,
+            finish_reason, "API call completed successfully");
+
+        Ok(ChatResponse {
+            message: Message {
+                role: Role::Assistant,
+                content: Cow::Owned(content),
+            },
+            finish_reason: Some(finish_reason).filter(|r| !r.is_empty()),
+            usage: res.usage_metadata.map(|usage| TokenUsage {
+                prompt_tokens: usage.prompt_token_count,
+                completion_tokens: usage.candidates_token_count,
+            }),
+            model: res.model_version,
+            request_id: res.response_id,
+            // Set by `audit_chat`.
+            latency: Duration::ZERO,
+            // Not supported by the API.
+            seed: None,
+            system_fingerprint: None,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiGenerateContent<'a> {
+    pub contents: Vec<GeminiContent<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<GeminiContent<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub safety_settings: Vec<GeminiSafetySetting>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation_config: Option<GeminiGenerationConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GeminiContent<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Cow<'a, str>>,
+    #[serde(default)]
+    pub parts: Vec<GeminiPart<'a>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GeminiPart<'a> {
+    pub text: Cow<'a, str>,
+    /// Whether the part is the reasoning of a thinking model rather than
+    /// the answer.
+    #[serde(default, skip_serializing)]
+    pub thought: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GeminiSafetySetting {
+    pub category: &'static str,
+    pub threshold: &'static str,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiGenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_mime_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_schema: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+}
+
+/// Maps the messages to the contents of Gemini: the system messages become
+/// the system instruction, and the assistant is the model.
+fn to_gemini_contents<'a>(
+    messages: &'a [Message<'a>],
+) -> (Vec<GeminiContent<'a>>, Option<GeminiContent<'a>>) {
+    let (system, chat): (Vec<_>, Vec<_>) = messages
+        .iter()
+        .partition(|msg| matches!(msg.role, Role::System));
+
+    let system_instruction = (!system.is_empty()).then(|| GeminiContent {
+        role: None,
+        parts: system
+            .into_iter()
+            .map(|msg| GeminiPart {
+                text: Cow::Borrowed(msg.content.as_ref()),
+                thought: false,
+            })
+            .collect(),
+    });
+    let contents = chat
+        .into_iter()
+        .map(|msg| GeminiContent {
+            role: Some(Cow::Borrowed(match msg.role {
+                Role::Assistant => "model",
+                _ => "user",
+            })),
+            parts: vec![GeminiPart {
+                text: Cow::Borrowed(msg.content.as_ref()),
+                thought: false,
+            }],
+        })
+        .collect();
+
+    (contents, system_instruction)
+}
+
+/// Maps the OpenAI response format to the generation config of Gemini. The
+/// JSON schema keywords Gemini doesn't support are removed from the schema.
+fn to_generation_config(
+    response_format: &serde_json::Value,
+) -> Option<GeminiGenerationConfig> {
+    fn strip_unsupported(schema: &mut serde_json::Value) {
+        match schema {
+            serde_json::Value::Object(obj) => {
+                obj.remove("additionalProperties");
+                obj.remove("strict");
+                obj.values_mut().for_each(strip_unsupported);
+            },
+            serde_json::Value::Array(items) => {
+                items.iter_mut().for_each(strip_unsupported);
+            },
+            _ => {},
+        }
+    }
+
+    match response_format["type"].as_str() {
+        Some("json_object") => Some(GeminiGenerationConfig {
+            response_mime_type: Some("application/json"),
+            ..Default::default()
+        }),
+        Some("json_schema") => Some(GeminiGenerationConfig {
+            response_mime_type: Some("application/json"),
+            response_schema: response_format["json_schema"]
+                .get("schema")
+                .cloned()
+                .map(|mut schema| {
+                    strip_unsupported(&mut schema);
+                    schema
+                }),
+            ..Default::default()
+        }),
+        _ => None,
+    }
+}
+
+#[test]
+fn gemini_request_test() {
+    let messages = [
+        Message {
+            role: Role::System,
+            content: "Be brief.".into(),
+        },
+        Message {
+            role: Role::User,
+            content: "Hi".into(),
+        },
+        Message {
+            role: Role::Assistant,
+            content: "Hello".into(),
+        },
+    ];
+    let (contents, system) = to_gemini_contents(&messages);
+    assert_eq!(system.unwrap().parts[0].text, "Be brief.");
+    assert_eq!(
+        contents
+            .iter()
+            .map(|c| c.role.as_deref().unwrap())
+            .collect::<Vec<_>>(),
+        ["user", "model"]
+    );
+
+    let config = to_generation_config(&serde_json::json!({
+        "type": "json_schema",
+        "json_schema": {"schema": {
+            "type": "object",
+            "properties": {"a": {"type": "string"}},
+            "additionalProperties": false,
+        }},
+    }))
+    .unwrap();
+    assert_eq!(
+        config.response_schema.unwrap(),
+        serde_json::json!({
+            "type": "object",
+            "properties": {"a": {"type": "string"}},
+        })
+    );
+    assert!(
+        to_generation_config(&serde_json::json!({"type": "text"})).is_none()
+    );
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiGenerateContentResponse {
+    #[serde(default)]
+    pub candidates: Vec<GeminiCandidate>,
+    #[serde(default)]
+    pub prompt_feedback: Option<serde_json::Value>,
+    #[serde(default)]
+    pub usage_metadata: Option<GeminiUsage>,
+    #[serde(default)]
+    pub model_version: Option<String>,
+    #[serde(default)]
+    pub response_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiCandidate {
+    #[serde(default)]
+    pub content: Option<GeminiContent<'static>>,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiUsage {
+    #[serde(default)]
+    pub prompt_token_count: u64,
+    #[serde(default)]
+    pub candidates_token_count: u64,
+    #[serde(default)]
+    pub total_token_count: u64,
+}
+
+#[async_trait::async_trait]
+impl ChatCompletionAPI for GeminiAPI {
+    async fn run_chat(
+        &self,
+        args: ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<Message<'static>> {
+        Ok(self.run_chat_full(args).await?.message)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn run_chat_full(
+        &self,
+        args: ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<ChatResponse> {
+        let model = args
+            .model_overwrite
+            .or(self.chat_model.as_deref())
+            .unwrap_or(GEMINI_CHAT_DEFAULT_MODEL);
+        audit_chat(
+            self.audit_log.as_deref(),
+            "gemini",
+            model,
+            &args,
+            self.chat(model, &args),
+        )
+        .await
+    }
+
+    fn config_hash(&self) -> String {
+        self.hash_config(&self.chat_model, &self.extras.chat_body)
+    }
+
+    fn model_name(&self, _task: Option<ChatTask>) -> Option<&str> {
+        Some(
+            self.chat_model
+                .as_deref()
+                .unwrap_or(GEMINI_CHAT_DEFAULT_MODEL),
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct GeminiEmbedContent<'a> {
+    pub model: String,
+    pub content: GeminiContent<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GeminiEmbedContentResponse {
+    pub embedding: GeminiEmbedding,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GeminiEmbedding {
+    pub values: Vec<f64>,
+}
+
+#[async_trait::async_trait]
+impl EmbeddingsAPI for GeminiAPI {
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn get_embedding(
+        &self,
+        args: EmbeddingsArgs<'_>,
+    ) -> anyhow::Result<Vec<f64>> {
+        let model = args
+            .model_overwrite
+            .or(self.embeddings_model.as_deref())
+            .unwrap_or(GEMINI_EMBEDDING_DEFAULT_MODEL);
+        let res: GeminiEmbedContentResponse = self
+            .make_request(
+                &GeminiEmbedContent {
+                    model: format!("models/{model}"),
+                    content: GeminiContent {
+                        role: None,
+                        parts: vec![GeminiPart {
+                            text: Cow::Borrowed(args.input),
+                            thought: false,
+                        }],
+                    },
+                },
+                model,
+                "embedContent",
+                &self.extras.embeddings_body,
+            )
+            .await?;
+
+        Ok(res.embedding.values)
+    }
+
+    fn config_hash(&self) -> String {
+        self.hash_config(&self.embeddings_model, &self.extras.embeddings_body)
+    }
+}
@@ -6,3 +6,16 @@ pub fn get_hash_value(data: impl AsRef<[u8]>) -> String {
     let hash = hasher.finalize();
     URL_SAFE_NO_PAD.encode(&hash.as_bytes())
 }
+
+/// Hash the contents of the file without loading it into memory.
+pub async fn get_file_hash_value(
+    path: impl AsRef<std::path::Path>,
+) -> anyhow::Result<String> {
+    let path = path.as_ref().to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut hasher = blake3::Hasher::new();
+        std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+        Ok(URL_SAFE_NO_PAD.encode(hasher.finalize().as_bytes()))
+    })
+    .await?
+}
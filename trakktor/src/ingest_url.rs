@@ -0,0 +1,681 @@
+//! Ingestion of web pages.
+//!
+//! The main content of the page is extracted in the spirit of Readability:
+//! the navigation, comments, ads and other boilerplate are removed, the
+//! paragraphs are scored, and the element containing the best scoring
+//! paragraphs is taken as the article. The HTML parser is lenient, as real
+//! pages are rarely well-formed.
+
+use std::path::PathBuf;
+
+use anyhow::bail;
+use clap::{Parser, ValueHint};
+use reqwest::header::{CONTENT_TYPE, USER_AGENT};
+
+use crate::text_input::{heading_prefix, unescape_html};
+
+#[derive(Parser, Debug)]
+pub struct IngestUrl {
+    /// The URL of the page to ingest.
+    #[arg(value_hint = ValueHint::Url, value_parser = url::Url::parse)]
+    pub url: url::Url,
+    /// The directory to write the content of the page to.
+    #[arg(long, short, default_value = ".", value_hint = ValueHint::DirPath)]
+    pub output_dir: PathBuf,
+    /// Only extract the content of the page, without structifying it.
+    #[arg(long)]
+    pub no_structify: bool,
+    /// The prompt set to structify the page with, as of `structify-text`.
+    #[arg(long, conflicts_with = "no_structify")]
+    pub prompt_set: Option<String>,
+}
+
+const PAGE_FILE_EXT: &str = "md";
+const MAX_FILE_STEM_LEN: usize = 80;
+/// Paragraphs shorter than this are not scored.
+const MIN_PARAGRAPH_LEN: usize = 25;
+
+const VOID_TAGS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta",
+    "source", "track", "wbr",
+];
+/// Elements whose contents are not parsed as HTML.
+const RAW_TEXT_TAGS: &[&str] = &["script", "style", "textarea", "title"];
+const BLOCK_TAGS: &[&str] = &[
+    "p",
+    "div",
+    "li",
+    "ul",
+    "ol",
+    "blockquote",
+    "section",
+    "article",
+    "main",
+    "br",
+    "tr",
+    "table",
+    "pre",
+    "figcaption",
+    "dd",
+    "dt",
+];
+/// Opening any of these elements closes an open paragraph.
+const CLOSES_PARAGRAPH: &[&str] = &[
+    "p",
+    "div",
+    "ul",
+    "ol",
+    "blockquote",
+    "section",
+    "article",
+    "table",
+    "pre",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+];
+const PARAGRAPH_TAGS: &[&str] = &["p", "pre", "td", "blockquote"];
+const BOILERPLATE_TAGS: &[&str] = &[
+    "script", "style", "noscript", "nav", "header", "footer", "aside", "form",
+    "iframe", "svg", "button", "select", "textarea", "template",
+];
+/// Elements with any of these words in their class or id are boilerplate,
+/// unless they also have one of the [`CONTENT_NAMES`].
+const BOILERPLATE_NAMES: &[&str] = &[
+    "comment",
+    "sidebar",
+    "footer",
+    "header",
+    "nav",
+    "menu",
+    "share",
+    "social",
+    "related",
+    "advert",
+    "promo",
+    "cookie",
+    "banner",
+    "popup",
+    "newsletter",
+    "subscribe",
+    "breadcrumb",
+    "pagination",
+    "sponsor",
+];
+const CONTENT_NAMES: &[&str] = &[
+    "article", "content", "main", "post", "entry", "story", "body",
+];
+
+/// Fetch the page and write its main content to a markdown file in the
+/// output directory. Returns the path of the file.
+#[tracing::instrument(level = "debug", skip(args), fields(url = %args.url))]
+pub async fn ingest_url(args: &IngestUrl) -> anyhow::Result<PathBuf> {
+    let html = fetch_page(&args.url).await?;
+    let article = extract_article(&html);
+    if article.text.is_empty() {
+        bail!("No content found at {}", args.url);
+    }
+
+    let mut text = String::new();
+    if let Some(title) = &article.title {
+        let heading = format!("# {title}");
+        if !article.text.starts_with(&heading) {
+            text.push_str(&heading);
+            text.push_str("\n\n");
+        }
+    }
+    text.push_str(&article.text);
+
+    tokio::fs::create_dir_all(&args.output_dir).await?;
+    let path = args.output_dir.join(format!(
+        "{}.{PAGE_FILE_EXT}",
+        file_stem(&args.url, article.title.as_deref())
+    ));
+    tokio::fs::write(&path, text).await?;
+
+    tracing::info!("Wrote the page content to: {}", path.display());
+
+    Ok(path)
+}
+
+async fn fetch_page(url: &url::Url) -> anyhow::Result<String> {
+    let res = reqwest::Client::new()
+        .get(url.clone())
+        .header(USER_AGENT, concat!("trakktor/", env!("CARGO_PKG_VERSION")))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let content_type = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if !content_type.is_empty() && !content_type.contains("html") {
+        bail!("{url} is not a web page: {content_type}");
+    }
+
+    Ok(res.text().await?)
+}
+
+/// Name of the file for the page: the slug of its title, or of the URL.
+fn file_stem(url: &url::Url, title: Option<&str>) -> String {
+    let source = title
+        .map(str::to_string)
+        .or_else(|| {
+            url.path_segments()
+                .and_then(|mut s| s.rfind(|s| !s.is_empty()))
+                .map(|s| s.rsplit_once('.').map_or(s, |(s, _)| s).to_string())
+        })
+        .or_else(|| url.host_str().map(str::to_string))
+        .unwrap_or_default();
+
+    let mut slug = String::new();
+    for ch in source.chars().flat_map(char::to_lowercase) {
+        if ch.is_alphanumeric() {
+            slug.push(ch);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+        if slug.chars().count() >= MAX_FILE_STEM_LEN {
+            break;
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+
+    if slug.is_empty() {
+        "page".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+#[test]
+fn file_stem_test() -> anyhow::Result<()> {
+    let url = url::Url::parse("https://example.com/blog/my-post.html?x=1")?;
+    assert_eq!(
+        file_stem(&url, Some("Hello, World! (2024)")),
+        "hello-world-2024"
+    );
+    assert_eq!(file_stem(&url, None), "my-post");
+    let url = url::Url::parse("https://example.com/")?;
+    assert_eq!(file_stem(&url, None), "example-com");
+    assert_eq!(file_stem(&url, Some("!!!")), "page");
+    Ok(())
+}
+
+/// The main content of a page.
+#[derive(Debug)]
+pub struct Article {
+    pub title: Option<String>,
+    /// Paragraphs separated by blank lines, with markdown headings.
+    pub text: String,
+}
+
+#[derive(Debug)]
+enum Node {
+    Element(usize),
+    Text(String),
+}
+
+#[derive(Debug)]
+struct Element {
+    tag: Box<str>,
+    attrs: Vec<(Box<str>, String)>,
+    parent: Option<usize>,
+    children: Vec<Node>,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(n, _)| n.as_ref() == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn is_boilerplate(&self) -> bool {
+        if BOILERPLATE_TAGS.contains(&self.tag.as_ref()) ||
+            self.attr("hidden").is_some() ||
+            self.attr("aria-hidden") == Some("true") ||
+            self.attr("style").is_some_and(|s| {
+                s.replace(' ', "")
+                    .to_ascii_lowercase()
+                    .contains("display:none")
+            })
+        {
+            return true;
+        }
+        if ["html", "body", "article", "main"].contains(&self.tag.as_ref()) {
+            return false;
+        }
+        let names = format!(
+            "{} {}",
+            self.attr("class").unwrap_or_default(),
+            self.attr("id").unwrap_or_default()
+        )
+        .to_ascii_lowercase();
+        BOILERPLATE_NAMES.iter().any(|n| names.contains(n)) &&
+            !CONTENT_NAMES.iter().any(|n| names.contains(n))
+    }
+}
+
+/// Parsed page. The elements are stored in document order, so the parent of
+/// an element always precedes it. The first element is the root.
+#[derive(Debug)]
+struct Document {
+    elements: Vec<Element>,
+}
+
+impl Document {
+    fn parse(html: &str) -> Document {
+        let mut doc = Document {
+            elements: vec![Element {
+                tag: "".into(),
+                attrs: vec![],
+                parent: None,
+                children: vec![],
+            }],
+        };
+        let mut stack = vec![0];
+        let mut rest = html;
+
+        while !rest.is_empty() {
+            let top = *stack.last().expect("root is never closed");
+            let Some(start) = rest.find('<') else {
+                doc.push_text(top, rest);
+                break;
+            };
+            doc.push_text(top, &rest[..start]);
+            rest = &rest[start..];
+
+            if let Some(comment) = rest.strip_prefix("<!--") {
+                rest =
+                    comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            } else if rest.starts_with("<!") || rest.starts_with("<?") {
+                rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+            } else if let Some(end_tag) = rest.strip_prefix("</") {
+                let end = end_tag.find('>').unwrap_or(end_tag.len());
+                let name = end_tag[..end].trim().to_ascii_lowercase();
+                // Close the element with all the elements left open in it.
+                if let Some(pos) = stack
+                    .iter()
+                    .rposition(|&e| doc.elements[e].tag.as_ref() == name)
+                    .filter(|&pos| pos > 0)
+                {
+                    stack.truncate(pos);
+                }
+                rest = end_tag.get(end + 1..).unwrap_or_default();
+            } else if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+                let end = find_tag_end(rest);
+                let (tag, attrs) = parse_start_tag(&rest[1..end]);
+                let self_closing = rest[..end].ends_with('/');
+                rest = rest.get(end + 1..).unwrap_or_default();
+
+                let top_tag = doc.elements[top].tag.as_ref();
+                if (top_tag == "p" && CLOSES_PARAGRAPH.contains(&tag.as_str())) ||
+                    (top_tag == "li" && tag == "li")
+                {
+                    stack.pop();
+                }
+                let parent = *stack.last().expect("root is never closed");
+                let idx = doc.elements.len();
+                doc.elements[parent].children.push(Node::Element(idx));
+                doc.elements.push(Element {
+                    tag: tag.as_str().into(),
+                    attrs,
+                    parent: Some(parent),
+                    children: vec![],
+                });
+
+                if RAW_TEXT_TAGS.contains(&tag.as_str()) {
+                    // The search is ASCII case-insensitive, which keeps the
+                    // byte offsets.
+                    let close = format!("</{tag}");
+                    let end = rest.to_ascii_lowercase().find(&close);
+                    let text = &rest[..end.unwrap_or(rest.len())];
+                    if tag == "title" || tag == "textarea" {
+                        doc.push_text(idx, text);
+                    }
+                    rest = end
+                        .and_then(|end| rest[end..].find('>').map(|e| end + e))
+                        .map_or("", |end| &rest[end + 1..]);
+                } else if !VOID_TAGS.contains(&tag.as_str()) && !self_closing {
+                    stack.push(idx);
+                }
+            } else {
+                doc.push_text(top, "<");
+                rest = &rest[1..];
+            }
+        }
+
+        doc
+    }
+
+    fn push_text(&mut self, element: usize, text: &str) {
+        if !text.is_empty() {
+            self.elements[element]
+                .children
+                .push(Node::Text(unescape_html(text)));
+        }
+    }
+
+    fn find(&self, tag: &str) -> Option<usize> {
+        self.elements.iter().position(|e| e.tag.as_ref() == tag)
+    }
+
+    fn meta(&self, property: &str) -> Option<String> {
+        self.elements
+            .iter()
+            .filter(|e| e.tag.as_ref() == "meta")
+            .find(|e| {
+                e.attr("property").or_else(|| e.attr("name")) == Some(property)
+            })
+            .and_then(|e| e.attr("content"))
+            .map(normalize_whitespace)
+            .filter(|t| !t.is_empty())
+    }
+
+    /// All the text of the element, with normalized whitespace.
+    fn text(&self, element: usize) -> String {
+        let mut text = String::new();
+        let mut stack = vec![element];
+        while let Some(e) = stack.pop() {
+            for child in self.elements[e].children.iter().rev() {
+                match child {
+                    Node::Element(idx) => stack.push(*idx),
+                    Node::Text(t) => {
+                        text.push_str(t);
+                        text.push(' ');
+                    },
+                }
+            }
+        }
+        normalize_whitespace(&text)
+    }
+}
+
+fn normalize_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Find the `>` ending the tag, skipping quoted attribute values.
+fn find_tag_end(tag: &str) -> usize {
+    let mut quote = None;
+    for (i, ch) in tag.char_indices() {
+        match (quote, ch) {
+            (None, '"' | '\'') => quote = Some(ch),
+            (Some(q), _) if q == ch => quote = None,
+            (None, '>') => return i,
+            _ => {},
+        }
+    }
+    tag.len()
+}
+
+/// Parse the name and the attributes of a start tag, given without the
+/// angle brackets.
+fn parse_start_tag(tag: &str) -> (String, Vec<(Box<str>, String)>) {
+    let tag = tag.trim_end_matches('/');
+    let name_end = tag
+        .find(|c: char| c.is_whitespace() || c == '/')
+        .unwrap_or(tag.len());
+    let name = tag[..name_end].to_ascii_lowercase();
+
+    let mut attrs = vec![];
+    let mut rest = &tag[name_end..];
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            break;
+        }
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let attr = rest[..end].to_ascii_lowercase();
+        rest = rest[end..].trim_start();
+
+        let mut value = String::new();
+        if let Some(v) = rest.strip_prefix('=') {
+            let v = v.trim_start();
+            let (raw, after) = match v.chars().next() {
+                Some(q @ ('"' | '\'')) => {
+                    let v = &v[1..];
+                    let end = v.find(q).unwrap_or(v.len());
+                    (&v[..end], v.get(end + 1..).unwrap_or_default())
+                },
+                _ => {
+                    let end = v.find(char::is_whitespace).unwrap_or(v.len());
+                    (&v[..end], &v[end..])
+                },
+            };
+            value = unescape_html(raw);
+            rest = after;
+        }
+        attrs.push((attr.into(), value));
+    }
+
+    (name, attrs)
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Stats {
+    text_len: usize,
+    link_len: usize,
+    commas: usize,
+    has_blocks: bool,
+}
+
+/// Extract the title and the main content of the page.
+pub fn extract_article(html: &str) -> Article {
+    let doc = Document::parse(html);
+    let count = doc.elements.len();
+
+    let mut removed = vec![false; count];
+    for i in 1..count {
+        let element = &doc.elements[i];
+        removed[i] = element.parent.is_some_and(|p| removed[p]) ||
+            element.is_boilerplate();
+    }
+
+    // Children follow their parents, so going backwards the stats of the
+    // children are ready before their parents.
+    let mut stats = vec![Stats::default(); count];
+    for i in (0..count).rev() {
+        if removed[i] {
+            continue;
+        }
+        let mut s = Stats::default();
+        for child in &doc.elements[i].children {
+            match child {
+                Node::Element(idx) => {
+                    let c = stats[*idx];
+                    s.text_len += c.text_len;
+                    s.link_len += c.link_len;
+                    s.commas += c.commas;
+                    s.has_blocks |= c.text_len > 0 &&
+                        BLOCK_TAGS
+                            .contains(&doc.elements[*idx].tag.as_ref());
+                },
+                Node::Text(t) => {
+                    s.text_len += t
+                        .split_whitespace()
+                        .map(|w| w.chars().count() + 1)
+                        .sum::<usize>();
+                    s.commas += t.matches([',', '，']).count();
+                },
+            }
+        }
+        if doc.elements[i].tag.as_ref() == "a" {
+            s.link_len = s.text_len;
+        }
+        stats[i] = s;
+    }
+
+    let mut scores = vec![0.0; count];
+    for i in 0..count {
+        let tag = doc.elements[i].tag.as_ref();
+        let is_paragraph = PARAGRAPH_TAGS.contains(&tag) ||
+            (tag == "div" && !stats[i].has_blocks);
+        if removed[i] || !is_paragraph || stats[i].text_len < MIN_PARAGRAPH_LEN
+        {
+            continue;
+        }
+        let score = 1.0 +
+            stats[i].commas as f64 +
+            (stats[i].text_len as f64 / 100.0).min(3.0);
+        if let Some(parent) = doc.elements[i].parent {
+            scores[parent] += score;
+            if let Some(grandparent) = doc.elements[parent].parent {
+                scores[grandparent] += score / 2.0;
+            }
+        }
+    }
+
+    let content = (0..count)
+        .filter(|&i| scores[i] > 0.0)
+        .map(|i| (i, scores[i] * (1.0 - link_density(&stats[i]))))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| i)
+        .or_else(|| doc.find("body"))
+        .unwrap_or(0);
+
+    let text = render_text(&doc, content, &removed, &stats);
+    let title = doc
+        .meta("og:title")
+        .or_else(|| doc.find("title").map(|t| doc.text(t)))
+        .filter(|t| !t.is_empty());
+
+    Article { title, text }
+}
+
+fn link_density(stats: &Stats) -> f64 {
+    if stats.text_len == 0 {
+        return 0.0;
+    }
+    stats.link_len as f64 / stats.text_len as f64
+}
+
+/// Render the element as paragraphs with markdown headings. Blocks that are
+/// mostly links are dropped.
+fn render_text(
+    doc: &Document,
+    content: usize,
+    removed: &[bool],
+    stats: &[Stats],
+) -> String {
+    enum Visit<'a> {
+        Enter(usize),
+        Exit(usize),
+        Text(&'a str),
+    }
+
+    let mut paragraphs: Vec<String> = vec![];
+    let mut paragraph = String::new();
+    let mut heading = None;
+
+    let flush = |paragraphs: &mut Vec<String>,
+                 paragraph: &mut String,
+                 heading: Option<usize>| {
+        let text = normalize_whitespace(paragraph);
+        paragraph.clear();
+        if text.is_empty() {
+            return;
+        }
+        match heading {
+            Some(level) => {
+                paragraphs.push(format!("{} {}", heading_prefix(level), text))
+            },
+            None => paragraphs.push(text),
+        }
+    };
+
+    let mut stack = vec![Visit::Enter(content)];
+    while let Some(visit) = stack.pop() {
+        match visit {
+            Visit::Enter(i) => {
+                if removed[i] {
+                    continue;
+                }
+                let tag = doc.elements[i].tag.as_ref();
+                let level = heading_level(tag);
+                if level.is_some() || BLOCK_TAGS.contains(&tag) {
+                    if level.is_none() && link_density(&stats[i]) > 0.5 {
+                        continue;
+                    }
+                    flush(&mut paragraphs, &mut paragraph, heading);
+                    heading = level;
+                }
+                stack.push(Visit::Exit(i));
+                for child in doc.elements[i].children.iter().rev() {
+                    stack.push(match child {
+                        Node::Element(idx) => Visit::Enter(*idx),
+                        Node::Text(t) => Visit::Text(t),
+                    });
+                }
+            },
+            Visit::Exit(i) => {
+                let tag = doc.elements[i].tag.as_ref();
+                if heading_level(tag).is_some() || BLOCK_TAGS.contains(&tag) {
+                    flush(&mut paragraphs, &mut paragraph, heading);
+                    heading = None;
+                }
+            },
+            Visit::Text(text) => {
+                paragraph.push_str(text);
+                paragraph.push(' ');
+            },
+        }
+    }
+    flush(&mut paragraphs, &mut paragraph, heading);
+
+    paragraphs.join("\n\n")
+}
+
+fn heading_level(tag: &str) -> Option<usize> {
+    tag.strip_prefix('h')
+        .and_then(|l| l.parse().ok())
+        .filter(|l| (1..=6).contains(l))
+}
+
+#[test]
+fn extract_article_test() {
+    let html = r##"<!DOCTYPE html>
+<html>
+<head>
+<title>A Walk in the Park | Example News</title>
+<meta property="og:title" content="A Walk in the Park">
+<script>if (a < b) { document.write("<p>no</p>"); }</script>
+</head>
+<body>
+<header class="site-header"><a href="/">Example News</a></header>
+<nav><ul><li><a href="/world">World</a><li><a href="/sport">Sport</a></ul></nav>
+<div id="main-content">
+  <article>
+    <h1>A Walk in the Park</h1>
+    <p>The park was quiet in the morning, with the fog still hanging over
+    the pond, and only a few joggers on the paths.
+    <p>By noon, the families arrived, and the lawns filled with picnics,
+    kites, and dogs chasing frisbees&nbsp;&mdash; as every Sunday.
+    <div class="share-buttons"><a href="#">Share on social media</a></div>
+    <p><a href="/more">Read more stories like this</a></p>
+  </article>
+  <aside class="sidebar"><p>Trending: ten things you did not know, and more.</p></aside>
+</div>
+<div class="comments"><p>First comment, and a long one, about the article.</p></div>
+<footer>Copyright, all rights reserved, Example News.</footer>
+</body>
+</html>"##;
+    let article = extract_article(html);
+    assert_eq!(article.title.as_deref(), Some("A Walk in the Park"));
+    assert_eq!(
+        article.text,
+        "# A Walk in the Park\n\nThe park was quiet in the morning, with the \
+         fog still hanging over the pond, and only a few joggers on the \
+         paths.\n\nBy noon, the families arrived, and the lawns filled with \
+         picnics, kites, and dogs chasing frisbees — as every Sunday."
+    );
+}
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use super::JobBackend;
+use crate::{
+    app_config::AppConfigProvider,
+    aws_batch::{
+        config::{AwsConfigProvider, CloudFormationStackProvider, S3Provider},
+        delete::{do_delete, DeleteArgs},
+        download::{download_job_result, DownloadArgs},
+        list::{list_jobs, ListArgs},
+        transcribe::{transcribe_files, TranscribeJobArgs},
+    },
+};
+
+/// Runs the jobs on AWS Batch, with the job data in the S3 bucket of the
+/// stacks.
+pub struct AwsBatchBackend<C> {
+    config: Arc<C>,
+}
+
+impl<C> AwsBatchBackend<C> {
+    pub fn new(config: Arc<C>) -> Self { Self { config } }
+}
+
+#[async_trait::async_trait]
+impl<C> JobBackend for AwsBatchBackend<C>
+where
+    C: AwsConfigProvider
+        + S3Provider
+        + CloudFormationStackProvider
+        + AppConfigProvider
+        + Sync
+        + Send
+        + 'static,
+{
+    async fn submit(&self, args: &TranscribeJobArgs) -> anyhow::Result<()> {
+        transcribe_files(Arc::clone(&self.config), args).await
+    }
+
+    async fn list(&self, args: &ListArgs) -> anyhow::Result<()> {
+        list_jobs(Arc::clone(&self.config), args).await
+    }
+
+    async fn download(&self, args: &DownloadArgs) -> anyhow::Result<()> {
+        download_job_result(&*self.config, args).await
+    }
+
+    async fn delete(&self, args: &DeleteArgs) -> anyhow::Result<()> {
+        do_delete(Arc::clone(&self.config), args).await
+    }
+}
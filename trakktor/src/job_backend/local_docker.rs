@@ -0,0 +1,457 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, Local};
+
+use super::JobBackend;
+use crate::{
+    app_config::default_data_dir,
+    aws_batch::{
+        compression::{decompress_file, display_name, strip_compressed_ext},
+        delete::DeleteArgs,
+        download::DownloadArgs,
+        job::{JobInfo, JobType, JobUid, JOB_DONE_FLAG},
+        list::{group_jobs, print_group_heading, ListArgs},
+        select::{select_jobs, select_single_job, StoredJob},
+        transcribe::{
+            check_job_labels, collect_input_files, get_file_name, input_url,
+            probe_inputs, TranscribeJobArgs,
+        },
+        whisper::{make_image_name, OutputFormat, WhisperJobArgs},
+    },
+    locale::Lang,
+};
+
+const JOBS_DIR: &str = "jobs";
+/// Where the jobs directory is mounted in the container.
+const CONTAINER_STORAGE: &str = "/storage";
+const CONTAINER_PREFIX: &str = "trakktor-";
+/// Label of the containers with the ID of their job.
+const JOB_LABEL: &str = "trakktor.job";
+/// The job info is kept in a file of the job, as the serialized info may be
+/// too long for a file name.
+const JOB_INFO_FILE: &str = "info.🚜-info";
+const IN_DIR: &str = "in";
+const OUT_DIR: &str = "out";
+
+/// Runs the Whisper container of the AWS Batch jobs with the local Docker,
+/// each job in its own container. The jobs are kept in a local directory,
+/// with the same layout as in the S3 bucket.
+///
+/// The data never leaves the machine, so it is not encrypted, and there is
+/// no cost to budget.
+pub struct LocalDockerBackend {
+    dir: PathBuf,
+    gpus: bool,
+    dev_mode: bool,
+}
+
+impl LocalDockerBackend {
+    /// Uses the jobs directory `dir`, or `~/.local/share/trakktor/jobs` if
+    /// not given. The containers get all the GPUs of the machine if `gpus` is
+    /// set.
+    pub fn new(
+        dir: Option<PathBuf>,
+        gpus: bool,
+        dev_mode: bool,
+    ) -> anyhow::Result<Self> {
+        let dir = match dir {
+            Some(dir) => dir,
+            None => default_data_dir()?.join(JOBS_DIR),
+        };
+        Ok(Self {
+            dir,
+            gpus,
+            dev_mode,
+        })
+    }
+
+    fn job_dir(&self, job_id: &JobUid) -> PathBuf {
+        self.dir.join(job_id.as_ref())
+    }
+
+    /// Loads all the jobs of the directory.
+    async fn load_jobs(&self) -> anyhow::Result<Vec<StoredJob>> {
+        if !tokio::fs::try_exists(&self.dir).await? {
+            return Ok(vec![]);
+        }
+
+        let mut jobs = vec![];
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let Some(job_id) =
+                name.to_str().and_then(|n| JobUid::parse_job_uid(n).ok())
+            else {
+                continue;
+            };
+            let info_file = entry.path().join(JOB_INFO_FILE);
+            let info = match tokio::fs::read_to_string(&info_file).await {
+                Ok(info) => JobInfo::deserialize(&info).with_context(|| {
+                    format!("Invalid job info: {}", info_file.display())
+                })?,
+                Err(err) => {
+                    tracing::warn!(%job_id, "Skipping job without info: {err}");
+                    continue;
+                },
+            };
+            jobs.push(StoredJob {
+                is_done: tokio::fs::try_exists(
+                    entry.path().join(JOB_DONE_FLAG),
+                )
+                .await?,
+                job_id,
+                info,
+            });
+        }
+        jobs.sort_by_key(|j| j.info.start_time);
+
+        Ok(jobs)
+    }
+
+    /// Creates the job directory with the input file and the job info, and
+    /// starts the container of the job. The input of a URL is fetched by the
+    /// container.
+    #[tracing::instrument(level = "info", skip(self, info, args))]
+    async fn start_job(
+        &self,
+        file: &Path,
+        info: JobInfo,
+        args: &TranscribeJobArgs,
+    ) -> anyhow::Result<JobUid> {
+        let jid = JobUid::new();
+        let job_dir = self.job_dir(&jid);
+        let url = input_url(file);
+        let file_name = match url {
+            Some(_) => None,
+            None => Some(get_file_name(file)?),
+        };
+        tokio::fs::create_dir_all(job_dir.join(IN_DIR)).await?;
+        if let Some(file_name) = file_name {
+            tokio::fs::copy(file, job_dir.join(IN_DIR).join(file_name))
+                .await
+                .with_context(|| {
+                    format!("Failed to copy {}", file.display())
+                })?;
+        }
+        tokio::fs::write(job_dir.join(JOB_INFO_FILE), &*info.serialize())
+            .await?;
+
+        let output_formats = OutputFormat::join(&args.formats);
+        let compress_formats = OutputFormat::join(&args.compress);
+        let envs = WhisperJobArgs {
+            job_uid: &jid,
+            job_prefix: &format!("{jid}/"),
+            input_file: file_name,
+            input_list: None,
+            input_url: url,
+            language: &args.language,
+            initial_prompt: args.hint.as_deref(),
+            model: args.model.get_name(),
+            output_formats: output_formats.as_deref(),
+            compress_formats: compress_formats.as_deref(),
+            encryption_key_parameter: None,
+        }
+        .environments();
+
+        // Docker needs an absolute path of the directory to mount.
+        let mut volume =
+            tokio::fs::canonicalize(&self.dir).await?.into_os_string();
+        volume.push(format!(":{CONTAINER_STORAGE}"));
+        let mut docker_args: Vec<OsString> = vec![
+            "run".into(),
+            "--detach".into(),
+            "--name".into(),
+            container_name(&jid).into(),
+            "--label".into(),
+            format!("{JOB_LABEL}={jid}").into(),
+            "--volume".into(),
+            volume,
+            "--env".into(),
+            format!("TRK_LOCAL_STORAGE={CONTAINER_STORAGE}").into(),
+        ];
+        if self.gpus {
+            docker_args.extend(["--gpus".into(), "all".into()]);
+        }
+        for (key, value) in envs.0 {
+            docker_args
+                .extend(["--env".into(), format!("{key}={value}").into()]);
+        }
+        docker_args.push(make_image_name(args.model, self.dev_mode).into());
+
+        if let Err(err) = run_docker(docker_args).await {
+            tokio::fs::remove_dir_all(&job_dir).await?;
+            return Err(err);
+        }
+
+        Ok(jid)
+    }
+}
+
+fn container_name(job_id: &JobUid) -> String {
+    format!("{CONTAINER_PREFIX}{job_id}")
+}
+
+/// Runs the `docker` command line tool and returns its output.
+#[tracing::instrument(level = "debug")]
+async fn run_docker(args: Vec<OsString>) -> anyhow::Result<String> {
+    let output = tokio::process::Command::new("docker")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .context("Failed to run docker, is it installed?")?;
+    if !output.status.success() {
+        bail!(
+            "docker failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// The states of the containers of the jobs (e.g. `running` or `exited`),
+/// by job ID.
+async fn load_container_states() -> anyhow::Result<HashMap<JobUid, String>> {
+    let output = run_docker(
+        [
+            "ps",
+            "--all",
+            "--filter",
+            &format!("label={JOB_LABEL}"),
+            "--format",
+            &format!("{{{{.Label \"{JOB_LABEL}\"}}}}\t{{{{.State}}}}"),
+        ]
+        .map(OsString::from)
+        .into(),
+    )
+    .await?;
+    Ok(parse_container_states(&output))
+}
+
+fn parse_container_states(output: &str) -> HashMap<JobUid, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (job_id, state) = line.split_once('\t')?;
+            Some((JobUid::parse_job_uid(job_id).ok()?, state.to_string()))
+        })
+        .collect()
+}
+
+#[test]
+fn parse_container_states_test() {
+    let states = parse_container_states(
+        "BAtQ5-omTm6ZSRTg2AfFKQ\trunning\nnot-a-job\texited\n",
+    );
+    assert_eq!(states.len(), 1);
+    assert_eq!(
+        states[&JobUid::parse_job_uid("BAtQ5-omTm6ZSRTg2AfFKQ").unwrap()],
+        "running"
+    );
+}
+
+/// The names of the files in the directory, sorted. A missing directory has
+/// no files.
+async fn list_files(dir: &Path) -> anyhow::Result<Vec<String>> {
+    if !tokio::fs::try_exists(dir).await? {
+        return Ok(vec![]);
+    }
+    let mut files = vec![];
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        files.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    files.sort();
+    Ok(files)
+}
+
+const IND: &str = "    ";
+
+#[async_trait::async_trait]
+impl JobBackend for LocalDockerBackend {
+    #[tracing::instrument(level = "info", skip_all)]
+    async fn submit(&self, args: &TranscribeJobArgs) -> anyhow::Result<()> {
+        if args.array {
+            bail!("Array jobs are only supported on AWS Batch.");
+        }
+        if args.notify.is_some() {
+            bail!("Notifications are only supported on AWS Batch.");
+        }
+        if args.is_preprocessed() {
+            bail!("Preprocessing is only supported on AWS Batch.");
+        }
+        if args.align {
+            bail!("Alignment is only supported on AWS Batch.");
+        }
+        if args.transcode.is_some() {
+            bail!("Transcoding is only supported on AWS Batch.");
+        }
+        if args.split_longer_than.is_some() {
+            bail!("Splitting into parts is only supported on AWS Batch.");
+        }
+        let files = collect_input_files(&args.files).await?;
+        check_job_labels(args, files.len())?;
+        let probes = probe_inputs(&files).await?;
+
+        let start_time = chrono::Utc::now();
+        let batch_label = match &args.batch_label {
+            Some(label) => Some(label.clone()),
+            None if files.len() > 1 => Some(
+                format!("batch-{}", start_time.format("%Y%m%d-%H%M%S")).into(),
+            ),
+            None => None,
+        };
+
+        for (file, probe) in files.into_iter().zip(probes) {
+            let info = JobInfo {
+                job_type: JobType::Transcribe,
+                start_time,
+                batch_label: batch_label.clone(),
+                array_size: None,
+                model: Some(args.model),
+                name: args.name.clone(),
+                tags: args.tags.clone(),
+                input_hash: match input_url(&file) {
+                    Some(_) => None,
+                    None => Some(
+                        crate::hasher::get_file_hash_value(&file).await?.into(),
+                    ),
+                },
+                language: Some(args.language.clone()),
+                estimated_cost: None,
+                audio_duration: probe.map(|p| p.duration.as_secs() as u32),
+                preprocessed: false,
+                after: None,
+                retry_of: None,
+                parts: None,
+            };
+            let jid = self.start_job(&file, info, args).await?;
+            tracing::info!(job_id = %jid, ?file, "Transcription job started.");
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn list(&self, args: &ListArgs) -> anyhow::Result<()> {
+        let jobs = self.load_jobs().await?;
+        let states = load_container_states().await?;
+
+        println!();
+        for (key, jobs) in group_jobs(jobs, args.group_by, |j| &j.info) {
+            if args.group_by.is_some() {
+                // The durations of the containers are not kept.
+                print_group_heading(
+                    Lang::En.strings(),
+                    key.as_deref(),
+                    jobs.len(),
+                    jobs.iter().map(|_| None),
+                    jobs.iter().map(|j| j.info.estimated_cost),
+                );
+            }
+            for job in jobs {
+                let info = &job.info;
+                let local_time: DateTime<Local> =
+                    DateTime::from(info.start_time);
+                println!(
+                    "- {} -- {} ({})",
+                    job.job_id, info.job_type, local_time
+                );
+                if let Some(name) = &info.name {
+                    println!("{IND}name: {}", name);
+                }
+                if !info.tags.is_empty() {
+                    println!("{IND}tags: {}", info.tags.join(", "));
+                }
+                if let Some(model) = info.model {
+                    println!("{IND}model: {}", model);
+                }
+                if let Some(batch_label) = &info.batch_label {
+                    println!("{IND}batch: {}", batch_label);
+                }
+                let status = match states.get(&job.job_id).map(String::as_str) {
+                    _ if job.is_done => "Done",
+                    Some("exited" | "dead") => "Failed",
+                    Some(_) => "InProgress",
+                    None => "Unknown",
+                };
+                println!("{IND}status: {status}");
+
+                let job_dir = self.job_dir(&job.job_id);
+                println!("{IND}files:");
+                for file in list_files(&job_dir.join(IN_DIR)).await? {
+                    println!("{IND}{IND}{file}");
+                }
+                let out_files = list_files(&job_dir.join(OUT_DIR)).await?;
+                if !out_files.is_empty() {
+                    println!("{IND}output files:");
+                    for file in &out_files {
+                        println!("{IND}{IND}{}", display_name(file));
+                    }
+                }
+            }
+            if args.group_by.is_some() {
+                println!();
+            }
+        }
+        println!();
+
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "info", skip(self))]
+    async fn download(&self, args: &DownloadArgs) -> anyhow::Result<()> {
+        let jobs = self.load_jobs().await?;
+        let job_id = select_single_job(&jobs, &args.job)?;
+        let Some(job) = jobs.iter().find(|j| j.job_id == job_id) else {
+            bail!("Job not found.");
+        };
+        if !job.is_done {
+            bail!("Job not finished yet.");
+        }
+
+        let out_dir = self.job_dir(&job_id).join(OUT_DIR);
+        let out_path = args.out_path.as_deref().unwrap_or(Path::new("."));
+        tokio::fs::create_dir_all(out_path).await?;
+        for file in list_files(&out_dir).await? {
+            let dest = out_path.join(&file);
+            tokio::fs::copy(out_dir.join(&file), &dest).await?;
+            if !args.keep_compressed && strip_compressed_ext(&file).is_some() {
+                decompress_file(&dest).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn delete(&self, args: &DeleteArgs) -> anyhow::Result<()> {
+        let jobs = self.load_jobs().await?;
+        let jids = select_jobs(&jobs, &args.jobs)?;
+        let states = load_container_states().await?;
+
+        for jid in jids {
+            tracing::info!(id = jid.as_ref(), "deleting...");
+            if states.contains_key(&jid) {
+                run_docker(vec![
+                    "rm".into(),
+                    "--force".into(),
+                    container_name(&jid).into(),
+                ])
+                .await?;
+            }
+            let job_dir = self.job_dir(&jid);
+            if tokio::fs::try_exists(&job_dir).await? {
+                tokio::fs::remove_dir_all(&job_dir).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+//! Backends running the transcription jobs.
+//!
+//! The jobs run on AWS Batch by default. The local Docker backend runs the
+//! same Whisper container on the user's machine, keeping the job data in a
+//! local directory instead of the S3 bucket.
+
+use crate::aws_batch::{
+    delete::DeleteArgs, download::DownloadArgs, list::ListArgs,
+    transcribe::TranscribeJobArgs,
+};
+
+mod aws_batch;
+mod local_docker;
+
+pub use aws_batch::AwsBatchBackend;
+pub use local_docker::LocalDockerBackend;
+
+/// The operations on the jobs shared by all the backends. Each of them takes
+/// the arguments of the matching command.
+#[async_trait::async_trait]
+pub trait JobBackend: Send + Sync {
+    /// Submits the jobs transcribing the files.
+    async fn submit(&self, args: &TranscribeJobArgs) -> anyhow::Result<()>;
+
+    /// Prints all the jobs with their status.
+    async fn list(&self, args: &ListArgs) -> anyhow::Result<()>;
+
+    /// Downloads the results of a finished job.
+    async fn download(&self, args: &DownloadArgs) -> anyhow::Result<()>;
+
+    /// Deletes the jobs with all their data.
+    async fn delete(&self, args: &DeleteArgs) -> anyhow::Result<()>;
+}
@@ -0,0 +1,147 @@
+use std::borrow::Cow;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::locale::{Lang, Prompts};
+
+/// The language of a document, which the summaries and the titles are
+/// written in.
+#[derive(
+    ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    En,
+    Ru,
+    Uk,
+    De,
+    Fr,
+    Es,
+    It,
+    Pt,
+}
+
+/// The words of the document looked at.
+const SAMPLE_WORDS: usize = 500;
+
+impl Language {
+    /// The English name, for the prompts.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::En => "English",
+            Self::Ru => "Russian",
+            Self::Uk => "Ukrainian",
+            Self::De => "German",
+            Self::Fr => "French",
+            Self::Es => "Spanish",
+            Self::It => "Italian",
+            Self::Pt => "Portuguese",
+        }
+    }
+
+    /// The language of the prompts, if there are prompts in it.
+    pub fn lang(self) -> Option<Lang> {
+        match self {
+            Self::En => Some(Lang::En),
+            Self::Ru => Some(Lang::Ru),
+            _ => None,
+        }
+    }
+
+    /// The most frequent words, which are rarely the same in the other
+    /// languages of the script.
+    fn common_words(self) -> &'static [&'static str] {
+        match self {
+            Self::En => &["the", "and", "is", "of", "that", "with", "you"],
+            Self::De => &["der", "die", "und", "ist", "nicht", "mit", "ich"],
+            Self::Fr => &["le", "les", "et", "est", "des", "une", "pas"],
+            Self::Es => &["el", "los", "y", "es", "las", "una", "por"],
+            Self::It => &["il", "che", "è", "della", "gli", "sono", "non"],
+            Self::Pt => &["o", "os", "e", "não", "uma", "com", "são"],
+            Self::Ru | Self::Uk => &[],
+        }
+    }
+
+    /// Guesses the language of the text: the Cyrillic languages by their
+    /// letters, the Latin ones by their most frequent words. Returns `None`
+    /// for the other scripts and the texts without the common words.
+    pub fn detect(text: &str) -> Option<Self> {
+        if Lang::detect(text) == Lang::Ru {
+            let ukrainian = text.chars().any(|c| "іїєґІЇЄҐ".contains(c));
+            return Some(if ukrainian { Self::Uk } else { Self::Ru });
+        }
+        let words = text
+            .split_whitespace()
+            .take(SAMPLE_WORDS)
+            .map(|w| {
+                w.trim_matches(|c: char| !c.is_alphanumeric())
+                    .to_lowercase()
+            })
+            .collect::<Vec<_>>();
+        Self::value_variants()
+            .iter()
+            .map(|&language| {
+                let common = language.common_words();
+                let hits =
+                    words.iter().filter(|w| common.contains(&w.as_str()));
+                (language, hits.count())
+            })
+            .filter(|(_, hits)| *hits > 0)
+            .max_by_key(|(_, hits)| *hits)
+            .map(|(language, _)| language)
+    }
+
+    /// The prompts for the documents in the language. The languages without
+    /// their own prompts get the English ones, told to write in the
+    /// language.
+    pub fn adapt_prompts(
+        self,
+        prompts: Cow<'static, Prompts>,
+    ) -> Cow<'static, Prompts> {
+        if self.lang().is_some() {
+            return prompts;
+        }
+        let mut prompts = prompts.into_owned();
+        let instruction = format!("\nWrite in {}.", self.name());
+        prompts.summarize_paragraph.to_mut().push_str(&instruction);
+        prompts.section_title.to_mut().push_str(&instruction);
+        prompts.document_title.to_mut().push_str(&instruction);
+        Cow::Owned(prompts)
+    }
+}
+
+impl From<Lang> for Language {
+    fn from(lang: Lang) -> Self {
+        match lang {
+            Lang::En => Self::En,
+            Lang::Ru => Self::Ru,
+        }
+    }
+}
+
+#[test]
+fn detect_language_test() {
+    assert_eq!(
+        Language::detect("The cat is on the mat, and that is all."),
+        Some(Language::En)
+    );
+    assert_eq!(
+        Language::detect("Die Katze ist nicht auf der Matte."),
+        Some(Language::De)
+    );
+    assert_eq!(
+        Language::detect("Le chat est sur le tapis, et les chiens pas."),
+        Some(Language::Fr)
+    );
+    assert_eq!(Language::detect("Привет, как дела?"), Some(Language::Ru));
+    assert_eq!(Language::detect("Привіт, як справи?"), Some(Language::Uk));
+    assert_eq!(Language::detect("42"), None);
+
+    let prompts = Language::De.adapt_prompts(Cow::Borrowed(Lang::En.prompts()));
+    assert!(prompts.section_title.ends_with("\nWrite in German."));
+    assert!(matches!(
+        Language::Ru.adapt_prompts(Cow::Borrowed(Lang::Ru.prompts())),
+        Cow::Borrowed(_)
+    ));
+}
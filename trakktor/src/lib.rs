@@ -1,8 +1,42 @@
 pub mod ai_chat;
 pub mod app_config;
 pub mod aws_batch;
+#[cfg(feature = "integration-tests")]
+pub mod aws_replay;
+pub mod azure_open_ai;
+pub mod cancellation;
+pub mod client;
+pub mod dialogue;
+pub mod doctor;
+pub mod dry_run;
+pub mod email_threads;
 pub mod embedding;
+pub mod error;
+pub mod gemini;
 mod hasher;
+pub mod ingest_url;
+pub mod job_backend;
+pub mod language;
 pub mod llm;
+pub mod llm_audit;
+pub mod llm_budget;
+pub mod locale;
 pub mod open_ai;
+pub mod outputs;
+pub mod prompts;
+pub mod punctuation;
+pub mod rate_limit;
+pub mod record_replay;
+pub mod redact;
+pub mod request_extras;
+pub mod routing;
+pub mod search;
+pub mod sentences;
 pub mod structify_text;
+pub mod text_diff;
+pub mod text_input;
+pub mod text_tiling;
+pub mod vector_index;
+pub mod vector_store;
+
+pub use error::{Error, Result};
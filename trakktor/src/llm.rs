@@ -1,13 +1,24 @@
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    time::{Duration, Instant},
+};
 
 use bon::builder;
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
+use crate::llm_audit::TokenUsage;
+
 #[derive(ValueEnum, Clone, Copy, Debug, Deserialize)]
 pub enum ChatCompletionPlatform {
     #[serde(rename = "open-ai")]
     OpenAI,
+    /// The Azure OpenAI Service, the chat model is the name of the deployment.
+    #[serde(rename = "azure-openai")]
+    #[value(name = "azure-openai")]
+    AzureOpenAI,
+    #[serde(rename = "gemini")]
+    Gemini,
     // #[serde(rename = "aws-bedrock")]
     // AWSBedrock,
 }
@@ -26,12 +37,57 @@ pub struct Message<'a> {
     pub content: Cow<'a, str>,
 }
 
+/// The kind of work of a chat completion, for routing it to a provider.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    strum_macros::EnumIter,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChatTask {
+    /// Splitting a text into paragraphs.
+    Structify,
+    Summary,
+    Title,
+    ActionItems,
+    Rerank,
+    /// Restoring the punctuation of a transcript.
+    Punctuate,
+}
+
+/// How long the reasoning models think before answering.
+#[derive(ValueEnum, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
 #[builder]
 #[derive(Debug)]
 pub struct ChatCompletionsArgs<'a> {
     pub model_overwrite: Option<&'a str>,
     pub messages: &'a [Message<'a>],
     pub response_format: Option<&'a serde_json::Value>,
+    pub task: Option<ChatTask>,
+    /// Ignored by the models without reasoning.
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// The limit of the generated tokens, including the hidden reasoning
+    /// tokens.
+    pub max_completion_tokens: Option<u32>,
+    /// Overrides the seed of the API. The same seed and request give the
+    /// same response as long as the system fingerprint is the same, as far
+    /// as the provider supports it.
+    pub seed: Option<u64>,
 }
 
 impl<'a> ChatCompletionsArgs<'a> {
@@ -43,12 +99,72 @@ impl<'a> ChatCompletionsArgs<'a> {
     }
 }
 
+/// The response of a chat completion with its metadata, as far as the
+/// provider reports it.
+#[derive(Debug)]
+pub struct ChatResponse {
+    pub message: Message<'static>,
+    /// Why the model stopped, as named by the provider, e.g. `stop` or
+    /// `length` of OpenAI and `STOP` or `MAX_TOKENS` of Gemini.
+    pub finish_reason: Option<String>,
+    pub usage: Option<TokenUsage>,
+    /// The model that answered, with its version.
+    pub model: Option<String>,
+    /// The ID of the response, for the support requests to the provider.
+    pub request_id: Option<String>,
+    pub latency: Duration,
+    /// The seed the response was sampled with.
+    pub seed: Option<u64>,
+    /// The configuration of the backend that answered, e.g.
+    /// `fp_44709d6fcb` of OpenAI. The responses with a seed are only
+    /// reproducible while it stays the same.
+    pub system_fingerprint: Option<String>,
+}
+
+impl ChatResponse {
+    /// Whether the response was cut at the limit of the tokens.
+    pub fn is_truncated(&self) -> bool {
+        matches!(self.finish_reason.as_deref(), Some("length" | "MAX_TOKENS"))
+    }
+
+    /// Whether the response was cut by the content filter of the provider.
+    pub fn is_filtered(&self) -> bool {
+        matches!(
+            self.finish_reason.as_deref(),
+            Some("content_filter" | "SAFETY" | "RECITATION")
+        )
+    }
+}
+
 #[async_trait::async_trait]
-pub trait ChatCompletionAPI {
+pub trait ChatCompletionAPI: Sync {
     async fn run_chat(
         &self,
         args: ChatCompletionsArgs<'_>,
     ) -> anyhow::Result<Message<'static>>;
 
+    /// Runs the chat completion and returns the response with its metadata.
+    /// Only the latency is known unless the API reports the rest.
+    async fn run_chat_full(
+        &self,
+        args: ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<ChatResponse> {
+        let started = Instant::now();
+        let message = self.run_chat(args).await?;
+        Ok(ChatResponse {
+            message,
+            finish_reason: None,
+            usage: None,
+            model: None,
+            request_id: None,
+            latency: started.elapsed(),
+            seed: None,
+            system_fingerprint: None,
+        })
+    }
+
     fn config_hash(&self) -> String;
+
+    /// The model the chat completions of the task are sent to, if known.
+    fn model_name(&self, _task: Option<ChatTask>) -> Option<&str> { None }
 }
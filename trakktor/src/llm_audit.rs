@@ -0,0 +1,252 @@
+use std::{
+    borrow::Cow,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use serde::Serialize;
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+
+use crate::llm::{ChatCompletionsArgs, ChatResponse, ChatTask, Role};
+
+/// Rewrites the texts of the audit log, e.g. to hide personal data.
+pub trait Redactor: Send + Sync {
+    fn redact<'a>(&self, text: &'a str) -> Cow<'a, str>;
+}
+
+/// Replaces the given texts, e.g. names or account numbers.
+pub struct RedactLiterals(pub Vec<String>);
+
+impl Redactor for RedactLiterals {
+    fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for literal in self.0.iter().filter(|l| !l.is_empty()) {
+            if text.contains(literal.as_str()) {
+                text = Cow::Owned(text.replace(literal.as_str(), REDACTED));
+            }
+        }
+        text
+    }
+}
+
+/// Replaces the email addresses.
+pub struct RedactEmails;
+
+impl Redactor for RedactEmails {
+    fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        replace_emails(text, |_| REDACTED.to_string())
+    }
+}
+
+/// Replaces the email addresses of the text with what `replace` returns
+/// for them.
+pub(crate) fn replace_emails<'a>(
+    text: &'a str,
+    mut replace: impl FnMut(&str) -> String,
+) -> Cow<'a, str> {
+    if !text.contains('@') {
+        return Cow::Borrowed(text);
+    }
+    let is_email_char = |c: char| c.is_alphanumeric() || "._%+-@".contains(c);
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        let len = rest.find(|c| !is_email_char(c)).unwrap_or(rest.len());
+        let (token, tail) = rest.split_at(len);
+        let word = token.trim_end_matches('.');
+        match word.split_once('@') {
+            Some((local, domain))
+                if !local.is_empty() &&
+                    domain.contains('.') &&
+                    !domain.contains('@') =>
+            {
+                res.push_str(&replace(word));
+                res.push_str(&token[word.len()..]);
+            },
+            _ => res.push_str(token),
+        }
+        // The separator after the token.
+        let mut chars = tail.chars();
+        res.extend(chars.next());
+        rest = chars.as_str();
+    }
+    Cow::Owned(res)
+}
+
+const REDACTED: &str = "[REDACTED]";
+
+#[test]
+fn redact_test() {
+    assert_eq!(
+        RedactEmails.redact("Mail jane.doe@example.com, or @here."),
+        "Mail [REDACTED], or @here."
+    );
+    assert_eq!(RedactEmails.redact("a@b.c."), "[REDACTED].");
+    assert_eq!(
+        RedactLiterals(vec!["Jane".into()]).redact("Hi Jane!"),
+        "Hi [REDACTED]!"
+    );
+}
+
+/// The tokens of a chat completion, as reported by the provider.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+#[derive(Serialize)]
+struct AuditMessage<'a> {
+    role: Role,
+    content: Cow<'a, str>,
+}
+
+#[derive(Serialize)]
+struct ChatRecord<'a> {
+    time: chrono::DateTime<chrono::Utc>,
+    provider: &'a str,
+    model: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    task: Option<ChatTask>,
+    latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<TokenUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_fingerprint: Option<&'a str>,
+    messages: Vec<AuditMessage<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Appends every chat completion to a JSONL file: the prompt, the response
+/// or the error, the model, the latency and the token usage.
+pub struct LlmAuditLog {
+    file: Mutex<tokio::fs::File>,
+    redactors: Vec<Box<dyn Redactor>>,
+}
+
+impl std::fmt::Debug for LlmAuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LlmAuditLog").finish_non_exhaustive()
+    }
+}
+
+impl LlmAuditLog {
+    pub async fn open(
+        path: &Path,
+        redactors: Vec<Box<dyn Redactor>>,
+    ) -> anyhow::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| {
+                format!("Failed to open the audit log {}", path.display())
+            })?;
+        Ok(Self {
+            file: Mutex::new(file),
+            redactors,
+        })
+    }
+
+    fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for redactor in &self.redactors {
+            let redacted = match redactor.redact(&text) {
+                Cow::Owned(redacted) => Some(redacted),
+                Cow::Borrowed(_) => None,
+            };
+            if let Some(redacted) = redacted {
+                text = Cow::Owned(redacted);
+            }
+        }
+        text
+    }
+
+    /// Records a chat completion. The failures to write are logged rather
+    /// than failing the completion.
+    pub async fn record_chat(
+        &self,
+        provider: &str,
+        model: &str,
+        args: &ChatCompletionsArgs<'_>,
+        latency: Duration,
+        res: &anyhow::Result<ChatResponse>,
+    ) {
+        let record = ChatRecord {
+            time: chrono::Utc::now(),
+            provider,
+            model,
+            task: args.task,
+            latency_ms: latency.as_millis(),
+            usage: res.as_ref().ok().and_then(|res| res.usage),
+            seed: res.as_ref().ok().and_then(|res| res.seed),
+            system_fingerprint: res
+                .as_ref()
+                .ok()
+                .and_then(|res| res.system_fingerprint.as_deref()),
+            messages: args
+                .messages
+                .iter()
+                .map(|msg| AuditMessage {
+                    role: msg.role,
+                    content: self.redact(&msg.content),
+                })
+                .collect(),
+            response: res
+                .as_ref()
+                .ok()
+                .map(|res| self.redact(&res.message.content)),
+            error: res
+                .as_ref()
+                .err()
+                .map(|err| self.redact(&format!("{err:#}")).into_owned()),
+        };
+        if let Err(err) = self.write_record(&record).await {
+            tracing::warn!("Failed to write the audit log: {err:#}");
+        }
+    }
+
+    async fn write_record(
+        &self,
+        record: &impl Serialize,
+    ) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+/// Runs the chat completion of a provider, sets the latency of the response,
+/// and records it if there is an audit log.
+pub async fn audit_chat<F>(
+    audit_log: Option<&LlmAuditLog>,
+    provider: &str,
+    model: &str,
+    args: &ChatCompletionsArgs<'_>,
+    chat: F,
+) -> anyhow::Result<ChatResponse>
+where
+    F: std::future::Future<Output = anyhow::Result<ChatResponse>>,
+{
+    let started = Instant::now();
+    let res = chat.await.map(|res| ChatResponse {
+        latency: started.elapsed(),
+        ..res
+    });
+    if let Some(audit_log) = audit_log {
+        audit_log
+            .record_chat(provider, model, args, started.elapsed(), &res)
+            .await;
+    }
+    res
+}
@@ -0,0 +1,91 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The LLM budget of a run is exceeded, the following requests are refused.
+#[derive(Debug)]
+pub struct BudgetExceeded {
+    pub requests: u64,
+    pub tokens: u64,
+    limit: String,
+}
+
+impl std::fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The LLM budget of {} is exceeded after {} requests and {} \
+             tokens. The finished calls are cached, raise the limit to \
+             continue.",
+            self.limit, self.requests, self.tokens
+        )
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+/// Limits the requests and the tokens of all the LLM providers in a run, to
+/// stop a runaway pipeline. The requests in flight when the limit is reached
+/// still complete, so the tokens may exceed the limit slightly.
+#[derive(Debug, Default)]
+pub struct LlmBudget {
+    max_requests: Option<u64>,
+    max_tokens: Option<u64>,
+    requests: AtomicU64,
+    tokens: AtomicU64,
+}
+
+impl LlmBudget {
+    pub fn new(max_requests: Option<u64>, max_tokens: Option<u64>) -> Self {
+        Self {
+            max_requests,
+            max_tokens,
+            ..Default::default()
+        }
+    }
+
+    /// Counts a request, or fails if the budget is exceeded.
+    pub fn start_request(&self) -> Result<(), BudgetExceeded> {
+        let tokens = self.tokens.load(Ordering::Relaxed);
+        if let Some(max_tokens) = self.max_tokens {
+            if tokens >= max_tokens {
+                return Err(BudgetExceeded {
+                    requests: self.requests.load(Ordering::Relaxed),
+                    tokens,
+                    limit: format!("{max_tokens} tokens"),
+                });
+            }
+        }
+        let requests = self.requests.fetch_add(1, Ordering::Relaxed);
+        if let Some(max_requests) = self.max_requests {
+            if requests >= max_requests {
+                self.requests.fetch_sub(1, Ordering::Relaxed);
+                return Err(BudgetExceeded {
+                    requests,
+                    tokens,
+                    limit: format!("{max_requests} requests"),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts the tokens of a response, prompt and completion.
+    pub fn record_tokens(&self, tokens: u64) {
+        self.tokens.fetch_add(tokens, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn budget_test() {
+    let budget = LlmBudget::new(Some(2), Some(100));
+    assert!(budget.start_request().is_ok());
+    budget.record_tokens(60);
+    assert!(budget.start_request().is_ok());
+    let err = budget.start_request().unwrap_err();
+    assert_eq!((err.requests, err.tokens), (2, 60));
+
+    let budget = LlmBudget::new(None, Some(100));
+    assert!(budget.start_request().is_ok());
+    budget.record_tokens(100);
+    assert!(budget.start_request().is_err());
+    assert!(LlmBudget::default().start_request().is_ok());
+}
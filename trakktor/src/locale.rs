@@ -0,0 +1,331 @@
+use std::borrow::Cow;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// The language of the CLI output and of the default prompts.
+#[derive(
+    ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Lang {
+    #[default]
+    En,
+    Ru,
+}
+
+impl Lang {
+    /// The language of the system locale, English if it is not supported.
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .map(|locale| Self::from_locale(&locale))
+            .unwrap_or_default()
+    }
+
+    fn from_locale(locale: &str) -> Self {
+        if locale.starts_with("ru") {
+            Self::Ru
+        } else {
+            Self::En
+        }
+    }
+
+    /// Guesses the language of the text by its script, from the letters of
+    /// its beginning.
+    pub fn detect(text: &str) -> Self {
+        const SAMPLE_LETTERS: usize = 1000;
+        let (cyrillic, total) = text
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .take(SAMPLE_LETTERS)
+            .fold((0, 0), |(cyrillic, total), c| {
+                let is_cyrillic = matches!(c, '\u{0400}'..='\u{04FF}');
+                (cyrillic + usize::from(is_cyrillic), total + 1)
+            });
+        if total > 0 && cyrillic * 2 > total {
+            Self::Ru
+        } else {
+            Self::En
+        }
+    }
+
+    pub fn strings(self) -> &'static Strings {
+        match self {
+            Self::En => &EN_STRINGS,
+            Self::Ru => &RU_STRINGS,
+        }
+    }
+
+    pub fn prompts(self) -> &'static Prompts {
+        match self {
+            Self::En => &EN_PROMPTS,
+            Self::Ru => &RU_PROMPTS,
+        }
+    }
+}
+
+#[test]
+fn lang_test() {
+    assert_eq!(Lang::from_locale("ru_RU.UTF-8"), Lang::Ru);
+    assert_eq!(Lang::from_locale("C.UTF-8"), Lang::En);
+    assert_eq!(Lang::detect("Привет, как дела? OK"), Lang::Ru);
+    assert_eq!(Lang::detect("Hello there, как дела?"), Lang::En);
+    assert_eq!(Lang::detect("42"), Lang::En);
+}
+
+/// The user-facing strings of the CLI.
+pub struct Strings {
+    /// The answer confirming a question, besides `yes`.
+    pub yes: &'static str,
+    pub continue_question: &'static str,
+    pub destroy_warning: fn(stack_prefix: &str) -> String,
+    pub stack_update_notice: fn(stack_name: &str) -> String,
+    pub name: &'static str,
+    pub tags: &'static str,
+    pub model: &'static str,
+    pub estimated_cost: &'static str,
+    pub batch: &'static str,
+    pub after: &'static str,
+    pub retry_of: &'static str,
+    pub then: &'static str,
+    pub status: &'static str,
+    pub array: &'static str,
+    pub array_done: fn(done: u32, size: u32) -> String,
+    pub duration: &'static str,
+    /// The duration of the audio of the job.
+    pub audio: &'static str,
+    /// When the running job is expected to finish.
+    pub eta: &'static str,
+    pub files: &'static str,
+    pub output_files: &'static str,
+    pub status_unknown: &'static str,
+    pub status_done: &'static str,
+    pub status_in_progress: &'static str,
+    pub status_failed: &'static str,
+    /// Running, but the heartbeat of the job is stale.
+    pub status_stalled: &'static str,
+    pub heartbeat: fn(time: &str, segments: u64) -> String,
+    pub jobs_count: fn(count: usize) -> String,
+    /// The group of the jobs without a batch label.
+    pub no_batch: &'static str,
+}
+
+impl Strings {
+    /// Whether the answer to a question confirms it.
+    pub fn is_yes(&self, answer: &str) -> bool {
+        let answer = answer.trim().to_lowercase();
+        answer == "yes" || answer == self.yes
+    }
+}
+
+static EN_STRINGS: Strings = Strings {
+    yes: "yes",
+    continue_question: "Do you want to continue? (yes/no)",
+    destroy_warning: |stack_prefix| {
+        format!(
+            "This will permanently delete all job data and all Trakktor \
+             stacks with the prefix `{stack_prefix}`."
+        )
+    },
+    stack_update_notice: |stack_name| {
+        format!("The stack {stack_name} will be updated:")
+    },
+    name: "name",
+    tags: "tags",
+    model: "model",
+    estimated_cost: "estimated cost",
+    batch: "batch",
+    after: "after",
+    retry_of: "retry of",
+    then: "then",
+    status: "status",
+    array: "array",
+    array_done: |done, size| format!("{done} of {size} done"),
+    duration: "duration",
+    audio: "audio",
+    eta: "expected to finish",
+    files: "files",
+    output_files: "output files",
+    status_unknown: "Unknown",
+    status_done: "Done",
+    status_in_progress: "InProgress",
+    status_failed: "Failed",
+    status_stalled: "Stalled",
+    heartbeat: |time, segments| {
+        format!("heartbeat: {time}, {segments} segments transcribed")
+    },
+    jobs_count: |count| {
+        format!("{count} {}", if count == 1 { "job" } else { "jobs" })
+    },
+    no_batch: "(no batch)",
+};
+
+static RU_STRINGS: Strings = Strings {
+    yes: "да",
+    continue_question: "Продолжить? (да/нет)",
+    destroy_warning: |stack_prefix| {
+        format!(
+            "Все данные заданий и все стеки Trakktor с префиксом \
+             `{stack_prefix}` будут удалены безвозвратно."
+        )
+    },
+    stack_update_notice: |stack_name| {
+        format!("Стек {stack_name} будет обновлён:")
+    },
+    name: "имя",
+    tags: "метки",
+    model: "модель",
+    estimated_cost: "оценка стоимости",
+    batch: "пакет",
+    after: "после",
+    retry_of: "повтор",
+    then: "затем",
+    status: "статус",
+    array: "массив",
+    array_done: |done, size| format!("готово {done} из {size}"),
+    duration: "длительность",
+    audio: "аудио",
+    eta: "ожидаемое завершение",
+    files: "файлы",
+    output_files: "результаты",
+    status_unknown: "Неизвестно",
+    status_done: "Готово",
+    status_in_progress: "Выполняется",
+    status_failed: "Ошибка",
+    status_stalled: "Завис",
+    heartbeat: |time, segments| {
+        format!("пульс: {time}, распознано сегментов: {segments}")
+    },
+    jobs_count: |count| format!("заданий: {count}"),
+    no_batch: "(без пакета)",
+};
+
+/// The default prompts of the text processing. The prompts in the language
+/// of the text keep the model from answering in English.
+#[derive(Clone)]
+pub struct Prompts {
+    pub structify: Cow<'static, str>,
+    pub summarize_paragraph: Cow<'static, str>,
+    pub section_title: Cow<'static, str>,
+    /// Titling the whole document by the summaries of its sections.
+    pub document_title: Cow<'static, str>,
+    /// Restoring the punctuation and the capitalization of a transcript.
+    pub punctuate: Cow<'static, str>,
+    /// The version hash of the prompt set the prompts are from, `None` for
+    /// the default prompts.
+    pub version: Option<String>,
+}
+
+static EN_PROMPTS: Prompts = Prompts {
+    structify: Cow::Borrowed(
+        r#"""
+You are an AI assistant tasked with splitting any text input into paragraphs. Your goal is to format the text by inserting paragraph breaks at logical points without altering the original content in any way. Each paragraph should be separated by exactly one blank line. Follow these guidelines when breaking the text into paragraphs:
+
+- **Logical Divisions:** Insert paragraph breaks where there are shifts in topic, introduction of new ideas, changes in time or place, or natural pauses in the narrative.
+- **Preserve Original Formatting:** Do not change any words, punctuation, capitalization, or spacing within sentences. Maintain any existing paragraph or line breaks.
+- **Consistent Output Format:** Ensure the output text matches the input exactly in content, with the only changes being the insertion of paragraph breaks as specified.
+- **Special Cases:** For texts that are very short or lack clear division points, use your best judgment to determine if paragraph breaks are necessary.
+
+*Example input:*
+
+This is the first sentence. Here is some additional text. This is another idea.
+Now we are shifting to a new point. Another sentence follows this one. Conclusion here.
+
+*Example output:*
+
+This is the first sentence. Here is some additional text. This is another idea.
+
+Now we are shifting to a new point. Another sentence follows this one.
+
+Conclusion here.
+
+
+Ensure that the output text maintains this format regardless of the input, and remember not to alter the content in any way—only adjust the paragraph formatting.
+"""#,
+    ),
+    summarize_paragraph: Cow::Borrowed(
+        r#"""
+When given a text, provide a brief summary in one sentence no longer than 20
+words, using the same language as the original text. """#,
+    ),
+    section_title: Cow::Borrowed(
+        r#"""
+Your task is to generate a headline for the provided text. The headline should capture the main idea and key points clearly and concisely, using simple language. Make sure the headline is a single sentence, do not use quotation marks around it, and use the same language as the text.
+"""#,
+    ),
+    document_title: Cow::Borrowed(
+        r#"
+You will be given the summaries of the sections of a document. Write a short
+title of the whole document that names its main subject, in the same language
+as the summaries. Reply with the title only, without quotation marks.
+"#,
+    ),
+    punctuate: Cow::Borrowed(
+        r#"
+You will be given a fragment of a speech transcript without punctuation and
+capitalization. Restore them: add the punctuation marks, capitalize the
+beginnings of the sentences and the proper names. Do not add, remove, reorder
+or replace any words, and do not split the text into paragraphs. Reply with
+the text only.
+"#,
+    ),
+    version: None,
+};
+
+static RU_PROMPTS: Prompts = Prompts {
+    structify: Cow::Borrowed(
+        r#"""
+Ты — ассистент, который разбивает присланный текст на абзацы. Твоя задача — расставить разрывы абзацев в логичных местах, никак не меняя сам текст. Абзацы разделяются ровно одной пустой строкой. При разбиении следуй правилам:
+
+- **Логичное деление:** начинай новый абзац при смене темы, появлении новой мысли, смене времени или места действия, на естественных паузах повествования.
+- **Сохранение текста:** не меняй слова, пунктуацию, регистр букв и пробелы внутри предложений. Сохраняй имеющиеся разрывы абзацев и строк.
+- **Неизменное содержание:** текст на выходе должен совпадать с исходным, единственное изменение — добавленные разрывы абзацев.
+- **Особые случаи:** если текст очень короткий или в нём нет явных мест для деления, сам реши, нужны ли разрывы.
+
+*Пример входного текста:*
+
+Это первое предложение. Вот ещё немного текста. А это другая мысль.
+Теперь мы переходим к новому вопросу. За ним следует ещё одно предложение. Вывод.
+
+*Пример результата:*
+
+Это первое предложение. Вот ещё немного текста. А это другая мысль.
+
+Теперь мы переходим к новому вопросу. За ним следует ещё одно предложение.
+
+Вывод.
+
+
+Соблюдай этот формат для любого текста и помни: содержание менять нельзя, только разбивку на абзацы.
+"""#,
+    ),
+    summarize_paragraph: Cow::Borrowed(
+        r#"""
+Кратко перескажи присланный текст одним предложением не длиннее 20 слов, на
+русском языке. """#,
+    ),
+    section_title: Cow::Borrowed(
+        r#"""
+Придумай заголовок для присланного текста. Заголовок должен ясно и кратко передавать главную мысль, простыми словами. Заголовок — одно предложение на русском языке, без кавычек.
+"""#,
+    ),
+    document_title: Cow::Borrowed(
+        r#"
+Тебе пришлют краткие пересказы разделов документа. Придумай короткое название
+всего документа, которое называет его главную тему, на русском языке. В ответе
+пришли только название, без кавычек.
+"#,
+    ),
+    punctuate: Cow::Borrowed(
+        r#"
+Тебе пришлют фрагмент расшифровки речи без знаков препинания и заглавных
+букв. Восстанови их: расставь знаки препинания, начни предложения и имена
+собственные с заглавной буквы. Не добавляй, не удаляй, не переставляй и не
+заменяй слова, не разбивай текст на абзацы. В ответе пришли только текст.
+"#,
+    ),
+    version: None,
+};
@@ -1,13 +1,21 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use anyhow::{bail, Context};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::Semaphore;
 use url::Url;
 
 use crate::{
     embedding::{EmbeddingsAPI, EmbeddingsArgs},
-    llm::{ChatCompletionAPI, ChatCompletionsArgs, Message, Role},
+    llm::{
+        ChatCompletionAPI, ChatCompletionsArgs, ChatResponse, ChatTask,
+        Message, ReasoningEffort, Role,
+    },
+    llm_audit::{audit_chat, LlmAuditLog, TokenUsage},
+    llm_budget::LlmBudget,
+    rate_limit::RateLimiter,
+    request_extras::RequestExtras,
 };
 
 pub const OPENAI_DEFAULT_SERVER_URL: &str = "https://api.openai.com";
@@ -24,20 +32,33 @@ pub struct OpenAiAPI {
     pub server_url: Option<Arc<Url>>,
     pub chat_model: Option<Arc<str>>,
     pub embeddings_model: Option<Arc<str>>,
+    /// Limits the requests in flight, shared by the clones of the API.
+    pub request_permits: Arc<Semaphore>,
+    pub extras: Arc<RequestExtras>,
+    /// Shared by all the APIs of the provider.
+    pub rate_limiter: Arc<RateLimiter>,
+    /// Records the chat completions if set.
+    pub audit_log: Option<Arc<LlmAuditLog>>,
+    /// The budget of the run, shared by all the providers.
+    pub budget: Arc<LlmBudget>,
+    /// The seed of the chat completions, for the best-effort deterministic
+    /// sampling.
+    pub seed: Option<u64>,
 }
 
 impl OpenAiAPI {
-    #[tracing::instrument(level = "debug", skip(self, req))]
+    #[tracing::instrument(level = "debug", skip(self, req, extra_body))]
     async fn make_request<I, O>(
         &self,
         req: &I,
         endpoint: &str,
+        extra_body: &serde_json::Map<String, serde_json::Value>,
     ) -> anyhow::Result<O>
     where
         I: Serialize + ?Sized + std::fmt::Debug,
         O: DeserializeOwned + std::fmt::Debug,
     {
-        let client = reqwest::Client::new();
+        let client = self.extras.client()?;
         let endpoint = if let Some(server_url) = &self.server_url {
             server_url.join(endpoint)?
         } else {
@@ -49,11 +70,16 @@ impl OpenAiAPI {
             ?req,
             "Sending request to API"
         );
-        let mut req_builder = client.post(endpoint).json(&req);
+        let body_len = serde_json::to_vec(req)?.len();
+        let mut req_builder =
+            self.extras.apply(client.post(endpoint), req, extra_body)?;
         if let Some(api_key) = &self.api_key {
             req_builder =
                 req_builder.header("Authorization", format!("Bearer {api_key}"))
         }
+        self.budget.start_request()?;
+        self.rate_limiter.acquire(body_len).await;
+        let _permit = self.request_permits.acquire().await?;
         let res = req_builder.send().await?;
 
         let code = res.status();
@@ -62,33 +88,32 @@ impl OpenAiAPI {
         tracing::debug!(response = ?res, "API response received");
 
         if !code.is_success() {
-            bail!("Failed to call API!\nCode: {code}\nResponse: {res}");
+            return Err(crate::Error::Llm {
+                status: code.as_u16(),
+                body: res,
+            }
+            .into());
         }
 
         Ok(serde_json::from_str(&res).with_context(|| {
             format!("Failed to parse response from API:\n{res}")
         })?)
     }
-}
 
-#[async_trait::async_trait]
-impl ChatCompletionAPI for OpenAiAPI {
-    #[tracing::instrument(level = "debug", skip_all)]
-    async fn run_chat(
+    async fn chat(
         &self,
-        args: ChatCompletionsArgs<'_>,
-    ) -> anyhow::Result<Message<'static>> {
+        model: &str,
+        args: &ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<ChatResponse> {
+        let seed = args.seed.or(self.seed);
         let res: OpenAiChatCompletionsResponse = self
             .make_request(
                 &OpenAiChatCompletions {
-                    model: args
-                        .model_overwrite
-                        .or(self.chat_model.as_deref())
-                        .unwrap_or_else(|| OPENAI_CHAT_DEFAULT_MODEL),
-                    messages: args.messages,
-                    response_format: args.response_format,
+                    seed,
+                    ..OpenAiChatCompletions::new(model, args)
                 },
                 CHAT_ENDPOINT,
+                &self.extras.chat_body,
             )
             .await?;
 
@@ -103,15 +128,68 @@ impl ChatCompletionAPI for OpenAiAPI {
             );
         }
 
+        self.rate_limiter.record_response_tokens(
+            res.usage.completion_tokens.unwrap_or_default(),
+        );
+        self.budget.record_tokens(res.usage.total_tokens);
         tracing::info!(usage = ?res.usage, model = res.model,
             finish_reason = choice.finish_reason,
+            system_fingerprint = res.system_fingerprint,
             "API call completed successfully");
 
-        Ok(Message {
-            role: choice.message.role,
-            content: choice.message.content,
+        Ok(ChatResponse {
+            message: Message {
+                role: choice.message.role,
+                content: choice
+                    .message
+                    .into_content(&choice.finish_reason)?
+                    .into(),
+            },
+            finish_reason: Some(choice.finish_reason),
+            usage: Some(TokenUsage {
+                prompt_tokens: res.usage.prompt_tokens,
+                completion_tokens: res
+                    .usage
+                    .completion_tokens
+                    .unwrap_or_default(),
+            }),
+            model: Some(res.model),
+            request_id: res.id,
+            // Set by `audit_chat`.
+            latency: Duration::ZERO,
+            seed,
+            system_fingerprint: res.system_fingerprint,
         })
     }
+}
+
+#[async_trait::async_trait]
+impl ChatCompletionAPI for OpenAiAPI {
+    async fn run_chat(
+        &self,
+        args: ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<Message<'static>> {
+        Ok(self.run_chat_full(args).await?.message)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn run_chat_full(
+        &self,
+        args: ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<ChatResponse> {
+        let model = args
+            .model_overwrite
+            .or(self.chat_model.as_deref())
+            .unwrap_or(OPENAI_CHAT_DEFAULT_MODEL);
+        audit_chat(
+            self.audit_log.as_deref(),
+            "open-ai",
+            model,
+            &args,
+            self.chat(model, &args),
+        )
+        .await
+    }
 
     fn config_hash(&self) -> String {
         let mut hasher = blake3::Hasher::new();
@@ -126,31 +204,188 @@ impl ChatCompletionAPI for OpenAiAPI {
         if let Some(chat_model) = &self.chat_model {
             hasher.update(chat_model.as_bytes());
         }
+        // Only with a seed, to keep the responses cached without one.
+        if let Some(seed) = self.seed {
+            hasher.update(format!(":seed={seed}").as_bytes());
+        }
+        self.extras.hash_into(&mut hasher, &self.extras.chat_body);
         URL_SAFE_NO_PAD.encode(&hasher.finalize().as_bytes())
     }
+
+    fn model_name(&self, _task: Option<ChatTask>) -> Option<&str> {
+        Some(
+            self.chat_model
+                .as_deref()
+                .unwrap_or(OPENAI_CHAT_DEFAULT_MODEL),
+        )
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub struct OpenAiChatCompletions<'a> {
     pub model: &'a str,
-    pub messages: &'a [Message<'a>],
+    pub messages: Vec<OpenAiMessage<'a>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub response_format: Option<&'a serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl<'a> OpenAiChatCompletions<'a> {
+    pub fn new(model: &'a str, args: &ChatCompletionsArgs<'a>) -> Self {
+        let reasoning = is_reasoning_model(model);
+        if args.reasoning_effort.is_some() && !reasoning {
+            tracing::debug!(model, "Ignoring the reasoning effort");
+        }
+        Self {
+            model,
+            messages: to_openai_messages(args.messages, model),
+            response_format: args.response_format,
+            reasoning_effort: args.reasoning_effort.filter(|_| reasoning),
+            max_completion_tokens: args.max_completion_tokens,
+            seed: args.seed,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpenAiMessage<'a> {
+    pub role: &'static str,
+    pub content: &'a str,
+}
+
+/// Whether the model is a reasoning model of the o-series, with or without
+/// the prefix of the provider, e.g. `openai/o3-mini`.
+pub fn is_reasoning_model(model: &str) -> bool {
+    let model = model.rsplit('/').next().unwrap_or(model);
+    ["o1", "o3", "o4"].iter().any(|series| {
+        model == *series || model.starts_with(&format!("{series}-"))
+    })
+}
+
+/// Maps the messages to the messages of the API. The reasoning models take
+/// the instructions as developer messages, and the first ones of them as
+/// user messages.
+fn to_openai_messages<'a>(
+    messages: &'a [Message<'a>],
+    model: &str,
+) -> Vec<OpenAiMessage<'a>> {
+    let system_role = if !is_reasoning_model(model) {
+        "system"
+    } else if model.contains("o1-mini") || model.contains("o1-preview") {
+        "user"
+    } else {
+        "developer"
+    };
+    messages
+        .iter()
+        .map(|msg| OpenAiMessage {
+            role: match msg.role {
+                Role::System => system_role,
+                Role::User => "user",
+                Role::Assistant => "assistant",
+            },
+            content: &msg.content,
+        })
+        .collect()
+}
+
+#[test]
+fn openai_messages_test() {
+    let messages = [
+        Message {
+            role: Role::System,
+            content: "Be brief.".into(),
+        },
+        Message {
+            role: Role::User,
+            content: "Hi".into(),
+        },
+    ];
+    let roles = |model| {
+        to_openai_messages(&messages, model)
+            .iter()
+            .map(|msg| msg.role)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(roles("gpt-4o"), ["system", "user"]);
+    assert_eq!(roles("o3-mini"), ["developer", "user"]);
+    assert_eq!(roles("openai/o1"), ["developer", "user"]);
+    assert_eq!(roles("o1-mini"), ["user", "user"]);
+    assert!(!is_reasoning_model("gpt-4o-mini"));
 }
 
 #[derive(Debug, Deserialize)]
 pub struct OpenAiChatCompletionsResponse {
+    #[serde(default)]
+    pub id: Option<String>,
     pub model: String,
     pub choices: Vec<Choice>,
     pub usage: Usage,
+    #[serde(default)]
+    pub system_fingerprint: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Choice {
-    pub message: Message<'static>,
+    pub message: OpenAiResponseMessage,
     pub finish_reason: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct OpenAiResponseMessage {
+    pub role: Role,
+    /// Empty when the model is out of tokens, e.g. after a long reasoning.
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default)]
+    pub refusal: Option<String>,
+    /// The reasoning of the compatible servers of the open reasoning
+    /// models, it is not part of the answer.
+    #[serde(default)]
+    pub reasoning_content: Option<String>,
+}
+
+impl OpenAiResponseMessage {
+    /// The answer without the reasoning.
+    pub fn into_content(self, finish_reason: &str) -> anyhow::Result<String> {
+        if let Some(reasoning) = &self.reasoning_content {
+            tracing::debug!(reasoning, "Skipping the reasoning of the model");
+        }
+        match (self.content, self.refusal) {
+            (Some(content), _) => Ok(strip_reasoning(&content).to_owned()),
+            (None, Some(refusal)) => bail!("The model refused: {refusal}"),
+            (None, None) => bail!(
+                "Empty response from Chat API, finish reason: {finish_reason}"
+            ),
+        }
+    }
+}
+
+/// Removes the `<think>` block the open reasoning models start the answer
+/// with.
+fn strip_reasoning(content: &str) -> &str {
+    let trimmed = content.trim_start();
+    match trimmed
+        .strip_prefix("<think>")
+        .and_then(|rest| rest.split_once("</think>"))
+    {
+        Some((_, answer)) => answer.trim_start(),
+        None => content,
+    }
+}
+
+#[test]
+fn strip_reasoning_test() {
+    assert_eq!(strip_reasoning("<think>\nHmm.\n</think>\n\nOK"), "OK");
+    assert_eq!(strip_reasoning("OK <think>"), "OK <think>");
+    assert_eq!(strip_reasoning("<think>unfinished"), "<think>unfinished");
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Usage {
     pub prompt_tokens: u64,
@@ -176,8 +411,10 @@ impl EmbeddingsAPI for OpenAiAPI {
                     input: args.input,
                 },
                 EMBEDDING_ENDPOINT,
+                &self.extras.embeddings_body,
             )
             .await?;
+        self.budget.record_tokens(res.usage.total_tokens);
 
         Ok(res
             .data
@@ -202,6 +439,8 @@ impl EmbeddingsAPI for OpenAiAPI {
         if let Some(embeddings_model) = &self.embeddings_model {
             hasher.update(embeddings_model.as_bytes());
         }
+        self.extras
+            .hash_into(&mut hasher, &self.extras.embeddings_body);
         URL_SAFE_NO_PAD.encode(&hasher.finalize().as_bytes())
     }
 }
@@ -0,0 +1,185 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::Serialize;
+
+use crate::{
+    dry_run::write_output,
+    hasher::{get_file_hash_value, get_hash_value},
+};
+
+/// The output files of a structified document. Each is named after the
+/// document with the extension of its kind, e.g. `talk.trakktor.text.md` for
+/// `talk.txt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputKind {
+    /// The text split into paragraphs.
+    Text,
+    /// A short summary of each paragraph.
+    Summaries,
+    /// The summaries of the paragraphs grouped by the sections.
+    SectionSummaries,
+    /// The text titled by sections, with the outline options.
+    Document,
+    /// The words the model changed in the text, if it changed any.
+    Diff,
+    /// The structified chapters of a book, with a table of contents.
+    Book,
+    /// The sections and their summaries by the chapters of a book.
+    BookSummaries,
+    /// The manifest of the run, listing the other outputs.
+    Manifest,
+}
+
+impl OutputKind {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputKind::Text => "trakktor.text.md",
+            OutputKind::Summaries => "trakktor.summaries.md",
+            OutputKind::SectionSummaries => "trakktor.section-summaries.md",
+            OutputKind::Document => "trakktor.document.md",
+            OutputKind::Diff => "trakktor.diff",
+            OutputKind::Book => "trakktor.book.md",
+            OutputKind::BookSummaries => "trakktor.book-summaries.md",
+            OutputKind::Manifest => "trakktor.manifest.json",
+        }
+    }
+}
+
+/// The path of an output of the file, in the directory or next to the file.
+pub fn output_path(
+    out_dir: Option<&Path>,
+    file: &Path,
+    kind: OutputKind,
+) -> PathBuf {
+    let path = file.with_extension(kind.extension());
+    match (out_dir, path.file_name()) {
+        (Some(dir), Some(name)) => dir.join(name),
+        _ => path,
+    }
+}
+
+/// An output written by a run.
+#[derive(Debug, Serialize)]
+struct Artifact {
+    kind: OutputKind,
+    /// The name of the file, in the directory of the manifest.
+    file: String,
+    bytes: usize,
+    /// The BLAKE3 hash of the contents.
+    hash: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+    input: String,
+    /// The BLAKE3 hash of the input file.
+    input_hash: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+    artifacts: &'a [Artifact],
+}
+
+/// Writes the outputs of a document and keeps track of them for the
+/// manifest.
+#[derive(Debug)]
+pub(crate) struct Outputs {
+    file: PathBuf,
+    out_dir: Option<PathBuf>,
+    dry_run: bool,
+    artifacts: Mutex<Vec<Artifact>>,
+}
+
+impl Outputs {
+    /// The outputs of the file, in the directory if given, which is created
+    /// if missing.
+    pub async fn new(
+        file: &Path,
+        out_dir: Option<&Path>,
+        dry_run: bool,
+    ) -> anyhow::Result<Self> {
+        if let Some(dir) = out_dir {
+            if !dry_run {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+        }
+        Ok(Self {
+            file: file.to_path_buf(),
+            out_dir: out_dir.map(Path::to_path_buf),
+            dry_run,
+            artifacts: Default::default(),
+        })
+    }
+
+    pub fn dry_run(&self) -> bool { self.dry_run }
+
+    pub fn path(&self, kind: OutputKind) -> PathBuf {
+        output_path(self.out_dir.as_deref(), &self.file, kind)
+    }
+
+    /// Writes the output, or only logs it in a dry run, and returns its path.
+    pub async fn write(
+        &self,
+        kind: OutputKind,
+        contents: impl AsRef<[u8]>,
+    ) -> anyhow::Result<PathBuf> {
+        let path = self.path(kind);
+        let contents = contents.as_ref();
+        write_output(self.dry_run, &path, contents).await?;
+        self.artifacts.lock().unwrap().push(Artifact {
+            kind,
+            file: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            bytes: contents.len(),
+            hash: get_hash_value(contents),
+        });
+        Ok(path)
+    }
+
+    /// Removes the output of an earlier run, which this one does not make.
+    pub async fn remove(&self, kind: OutputKind) -> anyhow::Result<()> {
+        let path = self.path(kind);
+        if !self.dry_run && path.exists() {
+            tokio::fs::remove_file(&path).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the manifest listing the outputs written so far with their
+    /// hashes, and the hash of the input they were made from.
+    pub async fn write_manifest(&self) -> anyhow::Result<()> {
+        let input_hash = get_file_hash_value(&self.file).await?;
+        let manifest = {
+            let artifacts = self.artifacts.lock().unwrap();
+            serde_json::to_string_pretty(&Manifest {
+                input: self.file.display().to_string(),
+                input_hash,
+                created_at: chrono::Utc::now(),
+                artifacts: &artifacts,
+            })?
+        };
+        let path = self.path(OutputKind::Manifest);
+        write_output(self.dry_run, &path, manifest).await?;
+        if !self.dry_run {
+            tracing::info!("Wrote the manifest to: {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn output_path_test() {
+    let file = Path::new("talks/intro.txt");
+    assert_eq!(
+        output_path(None, file, OutputKind::Document),
+        Path::new("talks/intro.trakktor.document.md")
+    );
+    assert_eq!(
+        output_path(Some(Path::new("out")), file, OutputKind::Manifest),
+        Path::new("out/intro.trakktor.manifest.json")
+    );
+}
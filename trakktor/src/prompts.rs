@@ -0,0 +1,185 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+
+use crate::{
+    app_config::AppConfigFile,
+    hasher::get_hash_value,
+    locale::{Lang, Prompts},
+};
+
+/// The directory of the prompt sets next to the config file.
+const PROMPTS_DIR: &str = "prompts";
+const TEMPLATE_EXT: &str = "md";
+/// The names of the templates, the fields of [`Prompts`].
+const TEMPLATE_NAMES: &[&str] = &[
+    "structify",
+    "summarize_paragraph",
+    "section_title",
+    "document_title",
+    "punctuate",
+];
+
+/// A named set of prompt templates that replace the default prompts, e.g.
+/// for the documents of a project. A set is a directory with a Markdown
+/// file a template, e.g. `structify.md`, or `structify.ru.md` for the texts
+/// in Russian. The prompts without templates are the default ones.
+#[derive(Debug)]
+pub struct PromptSet {
+    pub name: String,
+    /// The templates by the file name without the extension, e.g.
+    /// `structify` or `structify.ru`.
+    templates: BTreeMap<String, String>,
+}
+
+/// Where the prompt sets are looked up by name,
+/// `~/.config/trakktor/prompts`.
+pub fn prompt_sets_dir() -> Option<PathBuf> {
+    Some(AppConfigFile::default_path()?.parent()?.join(PROMPTS_DIR))
+}
+
+impl PromptSet {
+    /// Loads the prompt set by its name in the prompt sets directory, or
+    /// from a directory path.
+    #[tracing::instrument(level = "debug")]
+    pub fn load(name: &str) -> anyhow::Result<Self> {
+        let dir = if name.contains(std::path::MAIN_SEPARATOR) {
+            PathBuf::from(name)
+        } else {
+            prompt_sets_dir()
+                .context("Failed to find the prompt sets directory")?
+                .join(name)
+        };
+        let set = Self::load_dir(&dir)?;
+        tracing::info!(
+            name = set.name,
+            version = set.version_hash(),
+            "Loaded the prompt set"
+        );
+        Ok(set)
+    }
+
+    fn load_dir(dir: &Path) -> anyhow::Result<Self> {
+        let entries = std::fs::read_dir(dir).with_context(|| {
+            format!("Failed to read the prompt set {}", dir.display())
+        })?;
+        let mut templates = BTreeMap::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) !=
+                Some(TEMPLATE_EXT)
+            {
+                continue;
+            }
+            let Some(stem) = path.file_stem().and_then(|stem| stem.to_str())
+            else {
+                continue;
+            };
+            let name = stem.split('.').next().unwrap_or(stem);
+            if !TEMPLATE_NAMES.contains(&name) {
+                bail!(
+                    "Unknown prompt template {}, the templates are: {}",
+                    path.display(),
+                    TEMPLATE_NAMES.join(", ")
+                );
+            }
+            let template =
+                std::fs::read_to_string(&path).with_context(|| {
+                    format!(
+                        "Failed to read the prompt template {}",
+                        path.display()
+                    )
+                })?;
+            templates.insert(stem.to_string(), template);
+        }
+        if templates.is_empty() {
+            bail!("No prompt templates in {}", dir.display());
+        }
+        Ok(Self {
+            name: dir
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            templates,
+        })
+    }
+
+    /// The hash of the templates, which changes with any of them.
+    pub fn version_hash(&self) -> String {
+        get_hash_value(
+            serde_json::to_vec(&self.templates)
+                .expect("The templates are serializable"),
+        )
+    }
+
+    /// The template of the language, or the one for all the languages.
+    fn template(&self, name: &str, lang: Lang) -> Option<Cow<'static, str>> {
+        let lang = lang.to_possible_value()?;
+        self.templates
+            .get(&format!("{name}.{}", lang.get_name()))
+            .or_else(|| self.templates.get(name))
+            .map(|template| Cow::Owned(template.clone()))
+    }
+
+    /// The prompts of the language, with the templates of the set in place
+    /// of the default prompts.
+    pub fn prompts(&self, lang: Lang) -> Prompts {
+        let defaults = lang.prompts();
+        Prompts {
+            structify: self
+                .template("structify", lang)
+                .unwrap_or_else(|| defaults.structify.clone()),
+            summarize_paragraph: self
+                .template("summarize_paragraph", lang)
+                .unwrap_or_else(|| defaults.summarize_paragraph.clone()),
+            section_title: self
+                .template("section_title", lang)
+                .unwrap_or_else(|| defaults.section_title.clone()),
+            document_title: self
+                .template("document_title", lang)
+                .unwrap_or_else(|| defaults.document_title.clone()),
+            punctuate: self
+                .template("punctuate", lang)
+                .unwrap_or_else(|| defaults.punctuate.clone()),
+            version: Some(self.version_hash()),
+        }
+    }
+}
+
+/// The prompts of the language from the prompt set if there is one.
+pub fn prompts_for(
+    prompt_set: Option<&PromptSet>,
+    lang: Lang,
+) -> Cow<'static, Prompts> {
+    match prompt_set {
+        Some(set) => Cow::Owned(set.prompts(lang)),
+        None => Cow::Borrowed(lang.prompts()),
+    }
+}
+
+#[test]
+fn prompt_set_test() {
+    let dir = std::env::temp_dir()
+        .join(format!("trakktor-prompts-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("section_title.md"), "Title it.").unwrap();
+    std::fs::write(dir.join("section_title.ru.md"), "Озаглавь.").unwrap();
+    let set = PromptSet::load_dir(&dir).unwrap();
+    let hash = set.version_hash();
+
+    let prompts = set.prompts(Lang::En);
+    assert_eq!(prompts.section_title, "Title it.");
+    assert_eq!(prompts.structify, Lang::En.prompts().structify);
+    assert_eq!(set.prompts(Lang::Ru).section_title, "Озаглавь.");
+
+    std::fs::write(dir.join("section_title.md"), "Name it.").unwrap();
+    assert_ne!(PromptSet::load_dir(&dir).unwrap().version_hash(), hash);
+    std::fs::write(dir.join("sumarize.md"), "A typo.").unwrap();
+    assert!(PromptSet::load_dir(&dir).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use itertools::Itertools;
+
+use crate::{
+    llm::{ChatCompletionAPI, ChatTask},
+    locale::Prompts,
+    structify_text::{run_cached_prompt, CallCache},
+};
+
+/// The words of a fragment sent to the model at once.
+const CHUNK_WORDS: usize = 300;
+/// Shorter texts are left as they are.
+const MIN_WORDS: usize = 30;
+
+/// Whether the text looks like a raw transcript without punctuation, i.e.
+/// it has almost no sentence ends. A punctuated text has one every 15-20
+/// words.
+pub fn is_unpunctuated(text: &str) -> bool {
+    let words = text.split_whitespace().count();
+    let sentence_ends = text.chars().filter(|c| ".!?…".contains(*c)).count();
+    words >= MIN_WORDS && sentence_ends * 40 < words
+}
+
+/// Restores the punctuation of the transcripts with one segment per line,
+/// as written by most speech recognizers: every line becomes a sentence.
+/// Returns `None` if the text is not split into segments.
+pub fn restore_by_rules(text: &str) -> Option<String> {
+    let lines = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>();
+    let words = text.split_whitespace().count();
+    const MAX_SEGMENT_WORDS: usize = 40;
+    if lines.len() < 3 || words > lines.len() * MAX_SEGMENT_WORDS {
+        return None;
+    }
+    Some(lines.into_iter().map(punctuate_sentence).join(" "))
+}
+
+/// Capitalizes the sentence and ends it with a period, unless it already
+/// ends with a punctuation mark.
+fn punctuate_sentence(sentence: &str) -> String {
+    let mut chars = sentence.chars();
+    let mut res = chars
+        .next()
+        .map(|c| c.to_uppercase().chain(chars).collect::<String>())
+        .unwrap_or_default();
+    if res.ends_with(char::is_alphanumeric) {
+        res.push('.');
+    }
+    res
+}
+
+#[test]
+fn restore_by_rules_test() {
+    let segments = "so we met on monday\nthe plan is ready\n\nwhat's next?\n";
+    assert_eq!(
+        restore_by_rules(segments).as_deref(),
+        Some("So we met on monday. The plan is ready. What's next?")
+    );
+    assert_eq!(restore_by_rules("one long line without breaks"), None);
+    assert!(is_unpunctuated(&"and then we went home ".repeat(10)));
+    assert!(!is_unpunctuated(&"And then we went home. ".repeat(10)));
+}
+
+/// The words of the text without the punctuation and the case, to check
+/// that the model has not changed them.
+fn bare_words(text: &str) -> String {
+    text.split_whitespace()
+        .map(|w| {
+            w.chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|w| !w.is_empty())
+        .join(" ")
+}
+
+/// Restores the punctuation and the capitalization of a raw transcript: by
+/// the rules if it has one segment per line, otherwise with the model, a
+/// fragment at a time. The fragments the model changed the words of are
+/// kept as they are, only capitalized.
+pub(crate) async fn restore_punctuation(
+    chat_api: &dyn ChatCompletionAPI,
+    cache: &Arc<CallCache>,
+    prompts: &Prompts,
+    text: &str,
+) -> anyhow::Result<String> {
+    if let Some(restored) = restore_by_rules(text) {
+        tracing::info!("Restored the punctuation by the segments");
+        return Ok(restored);
+    }
+
+    let chunks = text
+        .split_whitespace()
+        .chunks(CHUNK_WORDS)
+        .into_iter()
+        .map(|mut words| words.join(" "))
+        .collect::<Vec<_>>();
+    let mut restored = Vec::with_capacity(chunks.len());
+    for (i, chunk) in chunks.iter().enumerate() {
+        tracing::info!(
+            "Restoring the punctuation, fragment {} of {}",
+            i + 1,
+            chunks.len()
+        );
+        let response = run_cached_prompt(
+            chat_api,
+            cache,
+            "punctuate",
+            ChatTask::Punctuate,
+            &prompts.punctuate,
+            chunk,
+        )
+        .await?;
+        let response = response.split_whitespace().join(" ");
+
+        let (orig, res) = (bare_words(chunk), bare_words(&response));
+        let distance = edit_distance::edit_distance(&orig, &res);
+        const MAX_CHANGED_CHARS: usize = 100;
+        if distance > (orig.len() / MAX_CHANGED_CHARS).max(4) {
+            tracing::warn!(
+                distance,
+                "The model changed the words of the fragment, keeping it \
+                 unpunctuated"
+            );
+            restored.push(punctuate_sentence(chunk));
+        } else {
+            restored.push(response);
+        }
+    }
+    Ok(restored.join(" "))
+}
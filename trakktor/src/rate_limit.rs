@@ -0,0 +1,170 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+/// The rate limits of a provider, shared by all the requests of its chat and
+/// embeddings tasks. No limits are applied if not given.
+///
+/// ```toml
+/// [rate_limits.open-ai]
+/// requests_per_minute = 500
+/// tokens_per_minute = 30000
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimit {
+    pub requests_per_minute: Option<u32>,
+    /// The tokens of the requests are estimated from their size, and the
+    /// tokens of the responses are counted from the usage of the responses.
+    pub tokens_per_minute: Option<u32>,
+}
+
+/// The rate limits by provider.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitsConfig {
+    #[serde(rename = "open-ai")]
+    pub open_ai: RateLimit,
+    #[serde(rename = "azure-openai")]
+    pub azure_open_ai: RateLimit,
+    pub gemini: RateLimit,
+}
+
+/// A token bucket refilled at the rate of the limit, full after a minute.
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn new(per_minute: u32, now: Instant) -> Self {
+        Self {
+            capacity: per_minute.into(),
+            available: per_minute.into(),
+            refilled_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.available = (self.available +
+            elapsed.as_secs_f64() * self.capacity / 60.0)
+            .min(self.capacity);
+        self.refilled_at = now;
+    }
+
+    /// Takes the amount from the bucket, or returns how long to wait until
+    /// it is available. An amount over the capacity waits for a full bucket.
+    fn take(&mut self, amount: f64, now: Instant) -> Option<Duration> {
+        self.refill(now);
+        let amount = amount.min(self.capacity);
+        if self.available >= amount {
+            self.available -= amount;
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (amount - self.available) * 60.0 / self.capacity,
+            ))
+        }
+    }
+}
+
+#[test]
+fn bucket_test() {
+    let start = Instant::now();
+    let mut bucket = Bucket::new(60, start);
+    assert_eq!(bucket.take(50.0, start), None);
+    assert_eq!(bucket.take(20.0, start), Some(Duration::from_secs(10)));
+    assert_eq!(bucket.take(20.0, start + Duration::from_secs(10)), None);
+    // Waits for a full bucket.
+    assert_eq!(
+        bucket.take(100.0, start + Duration::from_secs(10)),
+        Some(Duration::from_secs(60))
+    );
+}
+
+/// Enforces the rate limits of a provider across the concurrent requests.
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests: Option<Mutex<Bucket>>,
+    tokens: Option<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        let now = Instant::now();
+        Self {
+            requests: limit
+                .requests_per_minute
+                .map(|n| Mutex::new(Bucket::new(n, now))),
+            tokens: limit
+                .tokens_per_minute
+                .map(|n| Mutex::new(Bucket::new(n, now))),
+        }
+    }
+
+    async fn take(bucket: &Option<Mutex<Bucket>>, amount: f64) {
+        let Some(bucket) = bucket else {
+            return;
+        };
+        loop {
+            let wait = bucket.lock().unwrap().take(amount, Instant::now());
+            match wait {
+                Some(wait) => {
+                    tracing::debug!(?wait, "Waiting for the rate limit");
+                    tokio::time::sleep(wait).await;
+                },
+                None => return,
+            }
+        }
+    }
+
+    /// Waits until a request with the body of the given size can be sent.
+    pub async fn acquire(&self, body_len: usize) {
+        Self::take(&self.requests, 1.0).await;
+        Self::take(&self.tokens, estimate_tokens(body_len)).await;
+    }
+
+    /// Counts the tokens of a response, the following requests wait for
+    /// them.
+    pub fn record_response_tokens(&self, tokens: u64) {
+        if let Some(bucket) = &self.tokens {
+            let mut bucket = bucket.lock().unwrap();
+            bucket.refill(Instant::now());
+            bucket.available -= tokens as f64;
+        }
+    }
+}
+
+/// A rough estimate of the tokens of a request body, about four bytes a
+/// token for English text.
+pub(crate) fn estimate_tokens(body_len: usize) -> f64 {
+    (body_len / 4).max(1) as f64
+}
+
+/// The rate limiters of the providers, shared by all their APIs.
+#[derive(Debug, Clone)]
+pub struct RateLimiters {
+    pub open_ai: Arc<RateLimiter>,
+    pub azure_open_ai: Arc<RateLimiter>,
+    pub gemini: Arc<RateLimiter>,
+}
+
+impl RateLimiters {
+    pub fn new(config: &RateLimitsConfig) -> Self {
+        Self {
+            open_ai: Arc::new(RateLimiter::new(config.open_ai)),
+            azure_open_ai: Arc::new(RateLimiter::new(config.azure_open_ai)),
+            gemini: Arc::new(RateLimiter::new(config.gemini)),
+        }
+    }
+}
+
+impl Default for RateLimiters {
+    fn default() -> Self { Self::new(&RateLimitsConfig::default()) }
+}
@@ -0,0 +1,210 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    hasher::get_hash_value,
+    llm::{
+        ChatCompletionAPI, ChatCompletionsArgs, ChatTask, Message,
+        ReasoningEffort, Role,
+    },
+};
+
+/// The recorded responses, by the hash of their requests.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Fixture {
+    /// The config hash of the recorded API, so the cache keys of the
+    /// replayed responses are the same as of the recorded ones.
+    config_hash: String,
+    responses: BTreeMap<String, RecordedResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    task: Option<ChatTask>,
+    content: String,
+}
+
+/// All that affects the response of a chat completion.
+#[derive(Serialize)]
+struct RequestKey<'a> {
+    model_overwrite: Option<&'a str>,
+    messages: &'a [Message<'a>],
+    response_format: Option<&'a serde_json::Value>,
+    task: Option<ChatTask>,
+    reasoning_effort: Option<ReasoningEffort>,
+    max_completion_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+}
+
+fn request_key(args: &ChatCompletionsArgs) -> anyhow::Result<String> {
+    let key = RequestKey {
+        model_overwrite: args.model_overwrite,
+        messages: args.messages,
+        response_format: args.response_format,
+        task: args.task,
+        reasoning_effort: args.reasoning_effort,
+        max_completion_tokens: args.max_completion_tokens,
+        seed: args.seed,
+    };
+    Ok(get_hash_value(serde_json::to_vec(&key)?))
+}
+
+enum Mode {
+    Record(Box<dyn ChatCompletionAPI + Send + Sync>),
+    Replay,
+}
+
+/// Records the responses of a chat API to a JSON fixture file, and replays
+/// them later without the network, e.g. in the tests of the pipelines. The
+/// responses are matched by their requests, so a replayed pipeline must
+/// send the same requests as the recorded one.
+pub struct RecordReplayChatAPI {
+    mode: Mode,
+    path: PathBuf,
+    config_hash: String,
+    fixture: Mutex<Fixture>,
+}
+
+impl RecordReplayChatAPI {
+    /// Sends the chat completions to the API, and adds the responses to the
+    /// fixture file. The responses already in the file are kept.
+    pub fn record(
+        api: Box<dyn ChatCompletionAPI + Send + Sync>,
+        path: &Path,
+    ) -> anyhow::Result<Self> {
+        let mut fixture = if path.exists() {
+            load_fixture(path)?
+        } else {
+            Fixture::default()
+        };
+        fixture.config_hash = api.config_hash();
+        Ok(Self {
+            mode: Mode::Record(api),
+            path: path.to_path_buf(),
+            config_hash: fixture.config_hash.clone(),
+            fixture: Mutex::new(fixture),
+        })
+    }
+
+    /// Answers the chat completions from the fixture file. The requests that
+    /// were not recorded fail.
+    pub fn replay(path: &Path) -> anyhow::Result<Self> {
+        let fixture = load_fixture(path)?;
+        Ok(Self {
+            mode: Mode::Replay,
+            path: path.to_path_buf(),
+            config_hash: fixture.config_hash.clone(),
+            fixture: Mutex::new(fixture),
+        })
+    }
+}
+
+fn load_fixture(path: &Path) -> anyhow::Result<Fixture> {
+    let data = std::fs::read(path).with_context(|| {
+        format!("Failed to read the fixture {}", path.display())
+    })?;
+    serde_json::from_slice(&data).with_context(|| {
+        format!("Failed to parse the fixture {}", path.display())
+    })
+}
+
+#[async_trait::async_trait]
+impl ChatCompletionAPI for RecordReplayChatAPI {
+    #[tracing::instrument(level = "debug", skip_all, fields(task = ?args.task))]
+    async fn run_chat(
+        &self,
+        args: ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<Message<'static>> {
+        let key = request_key(&args)?;
+        let task = args.task;
+        match &self.mode {
+            Mode::Replay => {
+                let fixture = self.fixture.lock().await;
+                let response =
+                    fixture.responses.get(&key).with_context(|| {
+                        format!(
+                            "No recorded response for the request {key} in {}",
+                            self.path.display()
+                        )
+                    })?;
+                Ok(Message {
+                    role: Role::Assistant,
+                    content: response.content.clone().into(),
+                })
+            },
+            Mode::Record(api) => {
+                let message = api.run_chat(args).await?;
+                let mut fixture = self.fixture.lock().await;
+                fixture.responses.insert(
+                    key,
+                    RecordedResponse {
+                        task,
+                        content: message.content.to_string(),
+                    },
+                );
+                // Written after every response, so an interrupted run keeps
+                // what it recorded.
+                let data = serde_json::to_vec_pretty(&*fixture)?;
+                tokio::fs::write(&self.path, data).await.with_context(
+                    || {
+                        format!(
+                            "Failed to write the fixture {}",
+                            self.path.display()
+                        )
+                    },
+                )?;
+                Ok(message)
+            },
+        }
+    }
+
+    fn config_hash(&self) -> String { self.config_hash.clone() }
+
+    fn model_name(&self, task: Option<ChatTask>) -> Option<&str> {
+        match &self.mode {
+            Mode::Record(api) => api.model_name(task),
+            Mode::Replay => None,
+        }
+    }
+}
+
+#[tokio::test]
+async fn record_replay_test() {
+    let path = std::env::temp_dir()
+        .join(format!("trakktor-fixture-{}.json", uuid::Uuid::new_v4()));
+    let messages = [Message {
+        role: Role::User,
+        content: "Hi!".into(),
+    }];
+    let args = || {
+        ChatCompletionsArgs::builder()
+            .messages(&messages)
+            .task(ChatTask::Summary)
+            .build()
+    };
+
+    let api = RecordReplayChatAPI::record(
+        Box::new(crate::routing::FakeChatAPI(Some("Hello!"))),
+        &path,
+    )
+    .unwrap();
+    assert_eq!(api.run_chat(args()).await.unwrap().content, "Hello!");
+
+    let api = RecordReplayChatAPI::replay(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(api.config_hash(), r#"Some("Hello!")"#);
+    assert_eq!(api.run_chat(args()).await.unwrap().content, "Hello!");
+    // Another task is another request.
+    assert!(api
+        .run_chat(ChatCompletionsArgs::builder().messages(&messages).build())
+        .await
+        .is_err());
+}
@@ -0,0 +1,316 @@
+//! Pseudonymization of the texts sent to the model providers.
+//!
+//! The email addresses, the phone numbers and the given names are replaced
+//! with placeholders like `[PERSON_1]` before a request is sent, and the
+//! placeholders in the response are replaced back, so the provider never
+//! sees them while the outputs keep them. The same text gets the same
+//! placeholder for the whole run. There is no named entity recognition:
+//! only the names given are found.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    embedding::{EmbeddingsAPI, EmbeddingsArgs},
+    llm::{
+        ChatCompletionAPI, ChatCompletionsArgs, ChatResponse, ChatTask, Message,
+    },
+    llm_audit::replace_emails,
+    routing::ChatRoute,
+};
+
+/// The placeholders of the pseudonymized texts, kept to restore them.
+#[derive(Debug, Default)]
+struct Mapping {
+    /// The placeholders by the original texts.
+    placeholders: HashMap<String, String>,
+    /// The original texts with their placeholders, in the order they were
+    /// found.
+    originals: Vec<(String, String)>,
+    /// The number of placeholders by kind.
+    counts: HashMap<&'static str, usize>,
+}
+
+impl Mapping {
+    fn placeholder(&mut self, kind: &'static str, original: &str) -> String {
+        if let Some(placeholder) = self.placeholders.get(original) {
+            return placeholder.clone();
+        }
+        let count = self.counts.entry(kind).or_default();
+        *count += 1;
+        let placeholder = format!("[{kind}_{count}]");
+        self.placeholders
+            .insert(original.to_string(), placeholder.clone());
+        self.originals
+            .push((placeholder.clone(), original.to_string()));
+        placeholder
+    }
+}
+
+/// Replaces the personal data of the texts with placeholders, and the
+/// placeholders back.
+#[derive(Debug, Default)]
+pub struct Pseudonymizer {
+    /// The names to replace, the longest first so that a full name is
+    /// replaced before its parts.
+    names: Vec<String>,
+    mapping: Mutex<Mapping>,
+}
+
+impl Pseudonymizer {
+    /// Finds the email addresses and the phone numbers, and the names
+    /// given as whole words.
+    pub fn new(names: &[String]) -> Self {
+        let mut names = names
+            .iter()
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+        names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+        Self {
+            names,
+            mapping: Default::default(),
+        }
+    }
+
+    pub fn pseudonymize(&self, text: &str) -> String {
+        let mut mapping = self.mapping.lock().unwrap();
+        let text =
+            replace_emails(text, |email| mapping.placeholder("EMAIL", email));
+        let mut text =
+            replace_phones(&text, |phone| mapping.placeholder("PHONE", phone));
+        for name in &self.names {
+            text = replace_words(&text, name, |name| {
+                mapping.placeholder("PERSON", name)
+            });
+        }
+        text
+    }
+
+    /// Replaces the placeholders of the text with the original texts.
+    pub fn restore(&self, text: &str) -> String {
+        let mapping = self.mapping.lock().unwrap();
+        let mut text = text.to_string();
+        for (placeholder, original) in &mapping.originals {
+            if text.contains(placeholder.as_str()) {
+                text = text.replace(placeholder.as_str(), original);
+            }
+        }
+        text
+    }
+}
+
+/// Replaces the occurrences of the word that are not a part of a longer
+/// word.
+fn replace_words(
+    text: &str,
+    word: &str,
+    mut replace: impl FnMut(&str) -> String,
+) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, _) in text.match_indices(word) {
+        let end = start + word.len();
+        let is_whole = !text[..start]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_alphanumeric) &&
+            !text[end..]
+                .chars()
+                .next()
+                .is_some_and(char::is_alphanumeric);
+        if is_whole && start >= last {
+            res.push_str(&text[last..start]);
+            res.push_str(&replace(word));
+            last = end;
+        }
+    }
+    res.push_str(&text[last..]);
+    res
+}
+
+/// Replaces the phone numbers of the text: 7 to 15 digits starting with `+`
+/// or an area code in parentheses, e.g. `+1 555 123 4567` or
+/// `(555) 123-4567`, or 9 to 15 digits in at least three groups, e.g.
+/// `555-123-4567`. Dates, plain numbers, and four or more groups separated
+/// by dots alone, like the IPv4 addresses and the versions, are kept.
+fn replace_phones(
+    text: &str,
+    mut replace: impl FnMut(&str) -> String,
+) -> String {
+    let chars = text.char_indices().collect::<Vec<_>>();
+    let mut res = String::with_capacity(text.len());
+    let mut last = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let (start, c) = chars[i];
+        let is_start = (c == '+' || c == '(' || c.is_ascii_digit()) &&
+            (i == 0 || !chars[i - 1].1.is_alphanumeric());
+        if !is_start {
+            i += 1;
+            continue;
+        }
+        // The country and the area codes allow groups separated by spaces.
+        let spaced = c == '+' || c == '(';
+        let (mut digits, mut separators, mut end) = (0, 0, None);
+        for (j, &(_, d)) in chars.iter().enumerate().skip(i) {
+            if d.is_ascii_digit() {
+                digits += 1;
+                end = Some(j);
+            } else if j > i && ("-.()".contains(d) || d == ' ' && spaced) {
+                separators += 1;
+            } else if j != i {
+                break;
+            }
+        }
+        let Some(end) = end else {
+            i += 1;
+            continue;
+        };
+        let end_byte = chars.get(end + 1).map_or(text.len(), |&(b, _)| b);
+        let is_phone = if spaced {
+            (7..=15).contains(&digits)
+        } else {
+            (9..=15).contains(&digits) &&
+                separators >= 2 &&
+                !is_dotted_number(&text[start..end_byte])
+        };
+        let is_whole = chars
+            .get(end + 1)
+            .is_none_or(|&(_, n)| !n.is_alphanumeric());
+        if is_phone && is_whole {
+            res.push_str(&text[last..start]);
+            res.push_str(&replace(&text[start..end_byte]));
+            last = end_byte;
+        }
+        i = end + 1;
+    }
+    res.push_str(&text[last..]);
+    res
+}
+
+/// Whether the number is four or more groups of digits separated by dots,
+/// like `192.168.100.200`.
+fn is_dotted_number(number: &str) -> bool {
+    let groups = number.split('.').collect::<Vec<_>>();
+    groups.len() >= 4 &&
+        groups.iter().all(|g| {
+            !g.is_empty() && g.chars().all(|c| c.is_ascii_digit())
+        })
+}
+
+/// An API whose requests are pseudonymized and whose responses are
+/// restored.
+pub struct PseudonymizingAPI<T> {
+    pub inner: T,
+    pub pseudonymizer: Arc<Pseudonymizer>,
+}
+
+impl<T> PseudonymizingAPI<T> {
+    fn pseudonymize_messages(
+        &self,
+        messages: &[Message<'_>],
+    ) -> Vec<Message<'static>> {
+        messages
+            .iter()
+            .map(|message| Message {
+                role: message.role,
+                content: self
+                    .pseudonymizer
+                    .pseudonymize(&message.content)
+                    .into(),
+            })
+            .collect()
+    }
+
+    fn restore_message(&self, message: Message<'_>) -> Message<'static> {
+        Message {
+            role: message.role,
+            content: self.pseudonymizer.restore(&message.content).into(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatCompletionAPI for PseudonymizingAPI<ChatRoute> {
+    async fn run_chat(
+        &self,
+        args: ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<Message<'static>> {
+        Ok(self.run_chat_full(args).await?.message)
+    }
+
+    async fn run_chat_full(
+        &self,
+        args: ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<ChatResponse> {
+        let messages = self.pseudonymize_messages(args.messages);
+        let mut response = self
+            .inner
+            .run_chat_full(ChatCompletionsArgs {
+                messages: &messages,
+                ..args
+            })
+            .await?;
+        response.message = self.restore_message(response.message);
+        Ok(response)
+    }
+
+    fn config_hash(&self) -> String { self.inner.config_hash() }
+
+    fn model_name(&self, task: Option<ChatTask>) -> Option<&str> {
+        self.inner.model_name(task)
+    }
+}
+
+#[async_trait::async_trait]
+impl EmbeddingsAPI for PseudonymizingAPI<Box<dyn EmbeddingsAPI>> {
+    async fn get_embedding(
+        &self,
+        args: EmbeddingsArgs<'_>,
+    ) -> anyhow::Result<Vec<f64>> {
+        let input = self.pseudonymizer.pseudonymize(args.input);
+        self.inner
+            .get_embedding(EmbeddingsArgs {
+                input: &input,
+                ..args
+            })
+            .await
+    }
+
+    fn config_hash(&self) -> String { self.inner.config_hash() }
+}
+
+#[test]
+fn pseudonymize_test() {
+    let pseudonymizer =
+        Pseudonymizer::new(&["Jane".into(), "Jane Doe".into(), " ".into()]);
+    let text = "Jane Doe (jane@example.com, +1 555 123 4567) met Janet and \
+                Jane on 2024-01-15; call 555-123-4567 or (030) 1234567.";
+    let pseudonymized = pseudonymizer.pseudonymize(text);
+    assert_eq!(
+        pseudonymized,
+        "[PERSON_1] ([EMAIL_1], [PHONE_1]) met Janet and [PERSON_2] on \
+         2024-01-15; call [PHONE_2] or [PHONE_3]."
+    );
+    assert_eq!(pseudonymizer.restore(&pseudonymized), text);
+    // The same texts get the same placeholders.
+    assert_eq!(
+        pseudonymizer.pseudonymize("Write to jane@example.com, Jane."),
+        "Write to [EMAIL_1], [PERSON_2]."
+    );
+    assert_eq!(
+        pseudonymizer.pseudonymize("In 1990 2000 people paid 3.14."),
+        "In 1990 2000 people paid 3.14."
+    );
+    assert_eq!(
+        pseudonymizer.pseudonymize(
+            "Hosts 10.0.0.12 and 192.168.100.200 run 2024.10.15.1234, call \
+             555.123.4567."
+        ),
+        "Hosts 10.0.0.12 and 192.168.100.200 run 2024.10.15.1234, call \
+         [PHONE_4]."
+    );
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use anyhow::Context;
+use reqwest::{Certificate, Proxy, RequestBuilder};
+use serde::{Deserialize, Serialize};
+
+/// Extra settings of the requests to a provider, for the provider-specific
+/// knobs Trakktor has no option for.
+///
+/// ```toml
+/// [providers.open-ai.headers]
+/// HTTP-Referer = "https://example.com"
+///
+/// [providers.open-ai]
+/// timeout = "5m"
+/// connect_timeout = "10s"
+/// proxy = "http://proxy.corp.example.com:3128"
+/// ca_certificate = "/etc/ssl/certs/corp-ca.pem"
+///
+/// [providers.open-ai.chat_body]
+/// reasoning_effort = "low"
+/// ```
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RequestExtras {
+    /// Extra HTTP headers of all the requests, e.g. the routing headers of
+    /// OpenRouter or the organization ID of OpenAI.
+    pub headers: BTreeMap<String, String>,
+    /// Extra fields of the JSON body of the chat requests, they replace the
+    /// fields set by Trakktor.
+    pub chat_body: serde_json::Map<String, serde_json::Value>,
+    /// Extra fields of the JSON body of the embeddings requests.
+    pub embeddings_body: serde_json::Map<String, serde_json::Value>,
+    /// The longest a request may take, e.g. `5m`, so a stalled one fails
+    /// instead of holding up the run. No limit if not set.
+    #[serde(deserialize_with = "duration_str::deserialize_option_duration")]
+    pub timeout: Option<Duration>,
+    /// The longest connecting to the provider may take.
+    #[serde(deserialize_with = "duration_str::deserialize_option_duration")]
+    pub connect_timeout: Option<Duration>,
+    /// The longest a read of the response may wait for data, so a request
+    /// may take longer than that as long as the response keeps coming.
+    #[serde(deserialize_with = "duration_str::deserialize_option_duration")]
+    pub read_timeout: Option<Duration>,
+    /// The HTTP, HTTPS or SOCKS proxy of all the requests, e.g.
+    /// `socks5h://localhost:1080`, instead of the one of the `HTTPS_PROXY`
+    /// environment variable.
+    pub proxy: Option<String>,
+    /// A PEM file with the certificates of the authorities to trust in
+    /// addition to the ones of the system, e.g. of a TLS-intercepting
+    /// corporate proxy.
+    pub ca_certificate: Option<PathBuf>,
+    /// The client of the settings, made by the first request and shared by
+    /// the clones of the settings, so that the connections to the provider
+    /// are reused by all its APIs.
+    #[serde(skip)]
+    client: Arc<OnceLock<reqwest::Client>>,
+}
+
+/// The extra settings of the requests by provider.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProvidersConfig {
+    #[serde(rename = "open-ai")]
+    pub open_ai: RequestExtras,
+    #[serde(rename = "azure-openai")]
+    pub azure_open_ai: RequestExtras,
+    pub gemini: RequestExtras,
+}
+
+impl RequestExtras {
+    /// The HTTP client with the proxy, the certificates and the timeouts
+    /// of the settings.
+    pub fn client(&self) -> anyhow::Result<reqwest::Client> {
+        if let Some(client) = self.client.get() {
+            return Ok(client.clone());
+        }
+        let mut builder = reqwest::Client::builder();
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(
+                Proxy::all(proxy)
+                    .with_context(|| format!("Invalid proxy: {proxy}"))?,
+            );
+        }
+        if let Some(path) = &self.ca_certificate {
+            let pem = std::fs::read(path).with_context(|| {
+                format!("Failed to read the certificate {}", path.display())
+            })?;
+            for cert in Certificate::from_pem_bundle(&pem)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.read_timeout(timeout);
+        }
+        let client = builder.build()?;
+        Ok(self.client.get_or_init(|| client).clone())
+    }
+
+    /// The settings with the client made by the caller, e.g. with the
+    /// middleware or the TLS settings of the application, used instead of
+    /// the one of the settings.
+    pub fn with_client(self, client: reqwest::Client) -> Self {
+        Self {
+            client: Arc::new(OnceLock::from(client)),
+            ..self
+        }
+    }
+
+    /// Sets the body of the request with the extra fields merged into it,
+    /// and adds the extra headers and the timeout.
+    pub fn apply(
+        &self,
+        mut req_builder: RequestBuilder,
+        req: &(impl Serialize + ?Sized),
+        extra_body: &serde_json::Map<String, serde_json::Value>,
+    ) -> anyhow::Result<RequestBuilder> {
+        let body = merge_body(req, extra_body)?;
+        req_builder = req_builder.json(&body);
+        for (name, value) in &self.headers {
+            req_builder = req_builder.header(name, value);
+        }
+        if let Some(timeout) = self.timeout {
+            req_builder = req_builder.timeout(timeout);
+        }
+        Ok(req_builder)
+    }
+
+    /// Adds the extra settings to the hash of the config of the API, they
+    /// may change the responses. Nothing is added without them, so the
+    /// hashes of the cached responses stay the same.
+    pub fn hash_into(
+        &self,
+        hasher: &mut blake3::Hasher,
+        extra_body: &serde_json::Map<String, serde_json::Value>,
+    ) {
+        if self.headers.is_empty() && extra_body.is_empty() {
+            return;
+        }
+        hasher.update(b":");
+        for (name, value) in &self.headers {
+            hasher.update(name.as_bytes());
+            hasher.update(b"=");
+            hasher.update(value.as_bytes());
+            hasher.update(b";");
+        }
+        hasher.update(b":");
+        // The keys of the map are sorted, so the hash does not depend on the
+        // order of the config file.
+        hasher.update(
+            serde_json::Value::from(extra_body.clone())
+                .to_string()
+                .as_bytes(),
+        );
+    }
+}
+
+fn merge_body(
+    req: &(impl Serialize + ?Sized),
+    extra_body: &serde_json::Map<String, serde_json::Value>,
+) -> anyhow::Result<serde_json::Value> {
+    let mut body = serde_json::to_value(req)?;
+    if !extra_body.is_empty() {
+        let fields = body
+            .as_object_mut()
+            .context("The body of the request is not an object")?;
+        fields.extend(extra_body.clone());
+    }
+    Ok(body)
+}
+
+#[test]
+fn merge_body_test() {
+    let extra_body = serde_json::json!({
+        "reasoning_effort": "low",
+        "model": "o3-mini",
+    });
+    let body = merge_body(
+        &serde_json::json!({"model": "gpt-4o", "messages": []}),
+        extra_body.as_object().unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "model": "o3-mini",
+            "messages": [],
+            "reasoning_effort": "low",
+        })
+    );
+}
+
+#[test]
+fn request_extras_client_test() {
+    let extras = RequestExtras {
+        proxy: Some("http://proxy.example.com:3128".into()),
+        connect_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let clone = extras.clone();
+    assert!(extras.client().is_ok());
+    // The clones share the client made by the first request.
+    assert!(clone.client.get().is_some());
+
+    let extras = RequestExtras {
+        proxy: Some("socks5h://127.0.0.1:1080".into()),
+        ..Default::default()
+    };
+    assert!(extras.client().is_ok());
+
+    let extras = RequestExtras {
+        ca_certificate: Some("/nonexistent/ca.pem".into()),
+        ..Default::default()
+    };
+    assert!(extras.client().is_err());
+}
@@ -0,0 +1,202 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::{
+    llm::{
+        ChatCompletionAPI, ChatCompletionPlatform, ChatCompletionsArgs,
+        ChatResponse, ChatTask, Message,
+    },
+    llm_budget::BudgetExceeded,
+};
+
+/// The providers a chat completion is sent to, in order. The next provider
+/// is tried when one fails, e.g. when it is rate limited or down.
+///
+/// ```toml
+/// [[routing.chat]]
+/// platform = "open-ai"
+///
+/// [[routing.chat]]
+/// platform = "gemini"
+///
+/// [[routing.tasks.summary]]
+/// platform = "open-ai"
+/// model = "gpt-4o-mini"
+/// ```
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoutingConfig {
+    /// The providers of all the tasks without their own providers. Defaults
+    /// to the chat platform and model.
+    pub chat: Vec<ChatRouteConfig>,
+    /// The providers of a task, e.g. a cheaper model for the summaries.
+    pub tasks: BTreeMap<ChatTask, Vec<ChatRouteConfig>>,
+}
+
+impl RoutingConfig {
+    pub fn is_empty(&self) -> bool {
+        self.chat.is_empty() && self.tasks.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChatRouteConfig {
+    pub platform: ChatCompletionPlatform,
+    /// The model, the default model of the platform if not given.
+    pub model: Option<Arc<str>>,
+}
+
+pub type ChatRoute = Box<dyn ChatCompletionAPI + Send + Sync>;
+
+/// Sends the chat completions to the first provider of the task that
+/// succeeds.
+pub struct RoutingChatAPI {
+    pub routes: Vec<ChatRoute>,
+    pub task_routes: BTreeMap<ChatTask, Vec<ChatRoute>>,
+}
+
+impl RoutingChatAPI {
+    fn get_routes(&self, task: Option<ChatTask>) -> &[ChatRoute] {
+        task.and_then(|task| self.task_routes.get(&task))
+            .unwrap_or(&self.routes)
+    }
+}
+
+#[async_trait::async_trait]
+impl ChatCompletionAPI for RoutingChatAPI {
+    async fn run_chat(
+        &self,
+        args: ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<Message<'static>> {
+        Ok(self.run_chat_full(args).await?.message)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all, fields(task = ?args.task))]
+    async fn run_chat_full(
+        &self,
+        args: ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<ChatResponse> {
+        let routes = self.get_routes(args.task);
+        if routes.is_empty() {
+            anyhow::bail!("No chat providers to route to!");
+        }
+
+        let mut errors = Vec::new();
+        for (i, route) in routes.iter().enumerate() {
+            let route_args = ChatCompletionsArgs {
+                model_overwrite: args.model_overwrite,
+                messages: args.messages,
+                response_format: args.response_format,
+                task: args.task,
+                reasoning_effort: args.reasoning_effort,
+                max_completion_tokens: args.max_completion_tokens,
+                seed: args.seed,
+            };
+            match route.run_chat_full(route_args).await {
+                Ok(response) => return Ok(response),
+                // The other providers share the budget.
+                Err(err) if err.is::<BudgetExceeded>() => return Err(err),
+                Err(err) => {
+                    tracing::warn!(
+                        route = i,
+                        error = format!("{err:#}"),
+                        "Chat provider failed"
+                    );
+                    errors.push(err);
+                },
+            }
+        }
+
+        anyhow::bail!(
+            "All the chat providers failed:\n{}",
+            errors.iter().map(|err| format!("- {err:#}")).join("\n")
+        )
+    }
+
+    /// The hash of the first providers only, the responses of the fallbacks
+    /// are cached as the responses of the first ones. Adding a fallback
+    /// keeps the cached responses.
+    fn config_hash(&self) -> String {
+        let mut hasher = blake3::Hasher::new();
+        if let Some(route) = self.routes.first() {
+            hasher.update(route.config_hash().as_bytes());
+        }
+        for (task, routes) in &self.task_routes {
+            if let Some(route) = routes.first() {
+                hasher.update(format!(":{task:?}=").as_bytes());
+                hasher.update(route.config_hash().as_bytes());
+            }
+        }
+        URL_SAFE_NO_PAD.encode(hasher.finalize().as_bytes())
+    }
+
+    fn model_name(&self, task: Option<ChatTask>) -> Option<&str> {
+        self.get_routes(task).first()?.model_name(task)
+    }
+}
+
+/// Replies with the text, or fails like a rate limited provider.
+#[cfg(test)]
+pub(crate) struct FakeChatAPI(pub Option<&'static str>);
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl ChatCompletionAPI for FakeChatAPI {
+    async fn run_chat(
+        &self,
+        _args: ChatCompletionsArgs<'_>,
+    ) -> anyhow::Result<Message<'static>> {
+        match self.0 {
+            Some(reply) => Ok(Message {
+                role: crate::llm::Role::Assistant,
+                content: reply.into(),
+            }),
+            None => Err(crate::Error::Llm {
+                status: 429,
+                body: String::new(),
+            }
+            .into()),
+        }
+    }
+
+    fn config_hash(&self) -> String { format!("{:?}", self.0) }
+}
+
+#[tokio::test]
+async fn routing_test() {
+    let api = RoutingChatAPI {
+        routes: vec![
+            Box::new(FakeChatAPI(None)),
+            Box::new(FakeChatAPI(Some("a"))),
+        ],
+        task_routes: [(
+            ChatTask::Title,
+            vec![Box::new(FakeChatAPI(Some("b"))) as ChatRoute],
+        )]
+        .into(),
+    };
+    let run = |task| {
+        api.run_chat(
+            ChatCompletionsArgs::builder()
+                .messages(&[])
+                .maybe_task(task)
+                .build(),
+        )
+    };
+    assert_eq!(run(None).await.unwrap().content, "a");
+    assert_eq!(run(Some(ChatTask::Summary)).await.unwrap().content, "a");
+    assert_eq!(run(Some(ChatTask::Title)).await.unwrap().content, "b");
+
+    let api = RoutingChatAPI {
+        routes: vec![Box::new(FakeChatAPI(None))],
+        task_routes: Default::default(),
+    };
+    assert!(api
+        .run_chat(ChatCompletionsArgs::builder().messages(&[]).build())
+        .await
+        .is_err());
+}
@@ -0,0 +1,305 @@
+//! Search of the chunks of the indexed documents in the vector store.
+//!
+//! The query is embedded with the model of the indexes by the indexer image,
+//! run with the local `docker`, as the model is not available on the machine
+//! otherwise. The hits are shown with their document, the section and the
+//! time in the audio they are at, and a snippet of their text with the words
+//! of the query highlighted. With `--rerank` the hits of a wider search are
+//! reordered by the chat model.
+
+use std::{
+    path::{Component, Path, PathBuf},
+    process::Stdio,
+};
+
+use anyhow::{bail, Context};
+use clap::{Parser, ValueHint};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    aws_batch::indexer::{make_image_name, EMBEDDINGS_MODEL},
+    llm::ChatCompletionAPI,
+    vector_store::{
+        rerank_with_llm, SearchHit, VectorStore, VectorStoreConfig,
+    },
+};
+
+/// How many times more hits than shown are given to the chat model to
+/// rerank.
+const RERANK_CANDIDATES_FACTOR: usize = 4;
+
+#[derive(Parser, Debug)]
+pub struct SearchArgs {
+    /// The text to search for.
+    pub query: String,
+    /// The number of results to show.
+    #[arg(long, short = 'n', default_value_t = 5)]
+    pub limit: usize,
+    /// The words shown around the first match of the query in the snippets.
+    #[arg(long, default_value_t = 12)]
+    pub context_words: usize,
+    /// Reorder the hits by their relevance to the query as rated by the chat
+    /// model, which is given the hits of a wider search.
+    #[arg(long)]
+    pub rerank: bool,
+    /// Open the document of the best result with the default application.
+    #[arg(long)]
+    pub open: bool,
+    /// The directory the indexed documents are in, to open them.
+    #[arg(long, default_value = ".", value_hint = ValueHint::DirPath)]
+    pub documents: PathBuf,
+}
+
+#[tracing::instrument(level = "info", skip_all)]
+pub async fn run_search(
+    args: &SearchArgs,
+    vector_store: &VectorStoreConfig,
+    chat_api: Option<&dyn ChatCompletionAPI>,
+    dev_mode: bool,
+) -> crate::Result<()> {
+    Ok(search_documents(args, vector_store, chat_api, dev_mode).await?)
+}
+
+async fn search_documents(
+    args: &SearchArgs,
+    vector_store: &VectorStoreConfig,
+    chat_api: Option<&dyn ChatCompletionAPI>,
+    dev_mode: bool,
+) -> anyhow::Result<()> {
+    let embedding = embed_query(&args.query, dev_mode).await?;
+    let store = vector_store.open().await?;
+    let hits = search(
+        store.as_ref(),
+        &args.query,
+        &embedding,
+        args.limit,
+        chat_api.filter(|_| args.rerank),
+    )
+    .await?;
+    if hits.is_empty() {
+        println!("Nothing found.");
+        return Ok(());
+    }
+
+    for (i, hit) in hits.iter().enumerate() {
+        println!(
+            "{}\n",
+            format_hit(i + 1, hit, &args.query, args.context_words)
+        );
+    }
+    if args.open {
+        let path = document_path(&args.documents, &hits[0].chunk.document)?;
+        open_document(&path).await?;
+    }
+    Ok(())
+}
+
+/// Finds the chunks most similar to the embedding of the query, best first,
+/// or the most relevant ones as rated by the chat model if it is given.
+pub async fn search(
+    store: &dyn VectorStore,
+    query: &str,
+    embedding: &[f32],
+    limit: usize,
+    rerank_api: Option<&dyn ChatCompletionAPI>,
+) -> anyhow::Result<Vec<SearchHit>> {
+    let Some(chat_api) = rerank_api else {
+        return store.search(EMBEDDINGS_MODEL, embedding, limit).await;
+    };
+    let candidates = limit.saturating_mul(RERANK_CANDIDATES_FACTOR);
+    let hits = store
+        .search(EMBEDDINGS_MODEL, embedding, candidates)
+        .await?;
+    rerank_with_llm(chat_api, query, hits, limit).await
+}
+
+/// Computes the embedding of the query in the indexer image.
+async fn embed_query(query: &str, dev_mode: bool) -> anyhow::Result<Vec<f32>> {
+    let mut child = tokio::process::Command::new("docker")
+        .args(["run", "--rm", "-i", "-e", "HF_HUB_OFFLINE=1"])
+        .arg(make_image_name(dev_mode))
+        .args(["python3", "/embed_query.py"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run docker, is it installed?")?;
+    let mut stdin = child.stdin.take().context("No input of docker")?;
+    stdin.write_all(query.as_bytes()).await?;
+    drop(stdin);
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!(
+            "Failed to embed the query: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    serde_json::from_slice(&output.stdout)
+        .context("Failed to read the embedding of the query")
+}
+
+/// The hit as shown in the results: its rank, the document, the section and
+/// the time in the audio if known, the similarity, and the snippet.
+fn format_hit(
+    rank: usize,
+    hit: &SearchHit,
+    query: &str,
+    context_words: usize,
+) -> String {
+    let chunk = &hit.chunk;
+    let mut title = format!("{rank}. {}", chunk.document);
+    if let Some(section) = &chunk.section {
+        title += &format!(" › {section}");
+    }
+    if let Some(start) = chunk.start {
+        title += &format!(" [{}", format_time(start));
+        if let Some(end) = chunk.end {
+            title += &format!("–{}", format_time(end));
+        }
+        title += "]";
+    }
+    if let Some(speaker) = &chunk.speaker {
+        title += &format!(" {speaker}");
+    }
+    format!(
+        "{title} ({:.2})\n   {}",
+        hit.score,
+        hit.snippet(query, context_words)
+    )
+}
+
+/// Formats the seconds like `01:02:03`.
+fn format_time(seconds: f64) -> String {
+    let s = seconds.max(0.0).round() as u64;
+    format!("{:02}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+}
+
+/// The path of the indexed document in the directory of the documents. The
+/// name comes from the index, which may be shared, so it must not lead out of
+/// the directory.
+fn document_path(documents: &Path, document: &str) -> anyhow::Result<PathBuf> {
+    let is_relative = !document.is_empty() &&
+        Path::new(document)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+    if !is_relative {
+        bail!("The document {document} is not in the documents directory");
+    }
+    Ok(documents.join(document))
+}
+
+#[test]
+fn document_path_test() {
+    let documents = Path::new("/home/anna/docs");
+    assert_eq!(
+        document_path(documents, "calls/standup.json").unwrap(),
+        Path::new("/home/anna/docs/calls/standup.json")
+    );
+    assert!(document_path(documents, "/etc/passwd").is_err());
+    assert!(document_path(documents, "../.ssh/id_rsa").is_err());
+    assert!(document_path(documents, "calls/../../secret.txt").is_err());
+}
+
+/// Opens the file with the default application of the system.
+async fn open_document(path: &Path) -> anyhow::Result<()> {
+    if !path.exists() {
+        bail!(
+            "The document {} is not found, set the directory of the \
+             documents with --documents",
+            path.display()
+        );
+    }
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    let status = tokio::process::Command::new(opener)
+        .arg(path)
+        .stdin(Stdio::null())
+        .status()
+        .await
+        .with_context(|| format!("Failed to run {opener}"))?;
+    if !status.success() {
+        bail!("Failed to open {}: {status}", path.display());
+    }
+    Ok(())
+}
+
+#[test]
+fn format_hit_test() {
+    use crate::vector_index::IndexChunk;
+
+    let hit = SearchHit {
+        chunk: IndexChunk {
+            document: "standup.json".to_string(),
+            chunk: 3,
+            text: "We approved the budget for the new hires.".to_string(),
+            section: Some("Planning".to_string()),
+            speaker: Some("Anna".to_string()),
+            start: Some(3725.4),
+            end: Some(3790.0),
+        },
+        score: 0.834,
+    };
+    assert_eq!(
+        format_hit(1, &hit, "budget", 2),
+        "1. standup.json › Planning [01:02:05–01:03:10] Anna (0.83)\n   … \
+         approved the **budget** for the …"
+    );
+
+    let hit = SearchHit {
+        chunk: IndexChunk {
+            document: "notes.md".to_string(),
+            text: "Budget".to_string(),
+            ..Default::default()
+        },
+        score: 0.5,
+    };
+    assert_eq!(
+        format_hit(2, &hit, "budget", 2),
+        "2. notes.md (0.50)\n   **Budget**"
+    );
+}
+
+#[tokio::test]
+async fn search_test() -> anyhow::Result<()> {
+    use crate::{
+        vector_index::IndexChunk,
+        vector_store::{LocalVectorStore, VectorEntry},
+    };
+
+    let path = std::env::temp_dir()
+        .join(format!("trakktor-search-{}.redb", uuid::Uuid::new_v4()));
+    let store = LocalVectorStore::open(path.clone()).await?;
+    let entry = |document: &str, embedding: [f32; 2]| VectorEntry {
+        chunk: IndexChunk {
+            document: document.to_string(),
+            text: document.to_string(),
+            ..Default::default()
+        },
+        embedding: embedding.to_vec(),
+    };
+    store
+        .upsert(
+            EMBEDDINGS_MODEL,
+            &[entry("a", [1.0, 0.0]), entry("b", [0.6, 0.8])],
+        )
+        .await?;
+    store.upsert("other", &[entry("c", [0.0, 1.0])]).await?;
+
+    let hits = search(&store, "query", &[0.0, 1.0], 1, None).await?;
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].chunk.document, "b");
+
+    // The chat model rates the second hit of the wider search higher.
+    let chat_api = crate::routing::FakeChatAPI(Some("1: 2\n2: 9"));
+    let hits = search(&store, "query", &[0.0, 1.0], 1, Some(&chat_api)).await?;
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].chunk.document, "a");
+
+    drop(store);
+    std::fs::remove_file(path)?;
+    Ok(())
+}
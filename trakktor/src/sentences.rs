@@ -0,0 +1,110 @@
+/// The abbreviations that end with a period but rarely end a sentence, in
+/// lowercase. The ones followed by a lowercase word, e.g. "etc." in the
+/// middle of a sentence, are told by the next word anyway.
+const ABBREVIATIONS: &[&str] = &[
+    // English.
+    "mr. mrs. ms. dr. prof. sr. jr. st. vs. no. e.g. i.e. cf. approx. fig. \
+     jan. feb. mar. apr. jun. jul. aug. sep. sept. oct. nov. dec. inc. ltd. \
+     co. mt.",
+    // Russian.
+    "т.е. т.к. т.н. т.д. т.п. др. пр. г. гг. ул. д. им. см. рис. стр. тыс. \
+     млн. млрд. руб.",
+];
+
+/// The marks that end a sentence, the Chinese and Japanese ones included.
+const TERMINATORS: &[char] = &['.', '!', '?', '…', '。', '！', '？', '‼', '⁇'];
+
+/// Whether the word ends a sentence, given the word after it. A closing
+/// quote or bracket after the mark is part of the sentence, e.g. `done."`.
+/// A period ends no sentence after an abbreviation, an initial, or before
+/// a word in lowercase.
+pub fn is_sentence_end(word: &str, next: Option<&str>) -> bool {
+    let stripped = word.trim_end_matches(|c: char| {
+        matches!(c, '"' | '\'' | ')' | ']' | '»' | '”' | '’' | '」' | '』')
+    });
+    let Some(mark) = stripped.chars().last() else {
+        return false;
+    };
+    if !TERMINATORS.contains(&mark) {
+        return false;
+    }
+    let starts_lowercase = next
+        .and_then(|w| w.chars().find(|c| c.is_alphanumeric()))
+        .is_some_and(char::is_lowercase);
+    if mark != '.' {
+        return !starts_lowercase;
+    }
+    let bare = stripped
+        .trim_start_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase();
+    let is_initial = {
+        let mut chars = bare.chars();
+        matches!(
+            (chars.next(), chars.next(), chars.next()),
+            (Some(c), Some('.'), None) if c.is_alphabetic()
+        )
+    };
+    let is_abbreviation = ABBREVIATIONS
+        .iter()
+        .flat_map(|a| a.split_whitespace())
+        .any(|a| a == bare);
+    !starts_lowercase && !is_initial && !is_abbreviation
+}
+
+/// The number of the first words up to `max_words` that end with a whole
+/// sentence, or `max_words` if no sentence ends in the second half of them,
+/// e.g. in a text without punctuation.
+pub fn sentence_chunk_len(words: &[String], max_words: usize) -> usize {
+    if words.len() <= max_words {
+        return words.len();
+    }
+    (max_words / 2..max_words)
+        .rev()
+        .find(|&i| {
+            is_sentence_end(&words[i - 1], words.get(i).map(String::as_str))
+        })
+        .unwrap_or(max_words)
+}
+
+/// The first word at or after `pos` that starts a sentence, at most
+/// `max_shift` words later, or `pos` if none does.
+pub fn sentence_start(words: &[String], pos: usize, max_shift: usize) -> usize {
+    (pos.max(1)..(pos + max_shift).min(words.len()))
+        .find(|&i| is_sentence_end(&words[i - 1], Some(&words[i])))
+        .unwrap_or(pos)
+}
+
+#[test]
+fn is_sentence_end_test() {
+    let ends = |text: &str| {
+        let words = text.split_whitespace().collect::<Vec<_>>();
+        (0..words.len())
+            .filter(|&i| is_sentence_end(words[i], words.get(i + 1).copied()))
+            .map(|i| words[i].to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        ends("Mr. Smith met Dr. J. Doe, i.e. a friend. \"Why?\" He asked."),
+        ["friend.", "\"Why?\"", "asked."]
+    );
+    assert_eq!(
+        ends("Итак, т.е. всё. Он ушёл в 1990 г. Зачем?"),
+        ["всё.", "Зачем?"]
+    );
+    assert_eq!(ends("Wait... what? Fine!"), ["what?", "Fine!"]);
+    assert_eq!(ends("天气很好。我们走吧！"), ["天气很好。我们走吧！"]);
+}
+
+#[test]
+fn sentence_chunk_len_test() {
+    let words = "One two three. Four five six seven. Eight nine"
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    assert_eq!(sentence_chunk_len(&words, 20), 9);
+    assert_eq!(sentence_chunk_len(&words, 8), 7);
+    assert_eq!(sentence_chunk_len(&words, 6), 3);
+    assert_eq!(sentence_chunk_len(&words, 2), 2);
+    assert_eq!(sentence_start(&words, 1, 4), 3);
+    assert_eq!(sentence_start(&words, 4, 2), 4);
+}
@@ -1,75 +1,999 @@
 use std::{
     borrow::Cow,
     collections::{BTreeMap, HashSet},
-    path::Path,
-    sync::Arc,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::Poll,
+    time::{Duration, Instant},
 };
 
-use anyhow::bail;
-use clap::Parser;
+use anyhow::{bail, Context};
+use clap::{Args, Parser, ValueEnum};
+use duration_str::HumanFormat;
 use itertools::Itertools;
 use redb::TableDefinition;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use tokio::task::spawn_blocking;
+use tokio::{sync::Semaphore, task::spawn_blocking};
+use tracing::{info_span, Instrument};
 
 use crate::{
+    cancellation::{cancellable, CancellationToken},
+    dialogue::{parse_dialogue, Turn},
+    dry_run::DryRun,
+    embedding::{EmbeddingsAPI, EmbeddingsArgs},
     hasher::get_hash_value,
-    llm::{ChatCompletionAPI, ChatCompletionsArgs, Message, Role},
+    language::Language,
+    llm::{
+        ChatCompletionAPI, ChatCompletionsArgs, ChatResponse, ChatTask,
+        Message, Role,
+    },
+    locale::{Lang, Prompts},
+    outputs::{output_path, OutputKind, Outputs},
+    prompts::{prompts_for, PromptSet},
+    punctuation::{is_unpunctuated, restore_punctuation},
+    sentences::{sentence_chunk_len, sentence_start},
+    text_diff::{
+        diff_words, format_word_diff, paragraph_starts, reconstruct_paragraphs,
+    },
+    text_input::{is_epub, read_epub, read_input_text, OcrOptions},
+    text_tiling::section_starts,
 };
 
 #[derive(Parser, Debug)]
 pub struct StructifyText {
-    /// The text file to structify.
+    /// The file to structify: plain text, PDF, DOCX, EPUB, or a scanned
+    /// image. The chapters of an EPUB book are structified separately. The
+    /// documents of a directory are structified concurrently.
     #[arg(long, short)]
     pub file: std::path::PathBuf,
+    /// The directory to write the outputs to instead of next to the file:
+    /// `<name>.trakktor.text.md` with the paragraphs, `.summaries.md` with
+    /// their summaries, `.section-summaries.md` with them by the sections,
+    /// `.document.md` with the titled sections, `.diff` with the words the
+    /// model changed, and `.manifest.json` listing them with their hashes.
+    /// A book is written to `.book.md` and `.book-summaries.md` instead.
+    #[arg(long)]
+    pub out_dir: Option<PathBuf>,
+    #[command(flatten)]
+    pub ocr: OcrOptions,
+    /// Print the requests to the model instead of sending them, with their
+    /// estimated tokens and cost. Nothing is written.
+    #[arg(long)]
+    pub dry_run: bool,
+    /// The documents of a directory structified at once. The requests of all
+    /// the documents share the rate limits of the chat platform.
+    #[arg(long, default_value_t = DEFAULT_JOBS)]
+    pub jobs: usize,
+    /// Do not restore the punctuation of the texts without it, e.g. the raw
+    /// output of a speech recognizer.
+    #[arg(long)]
+    pub no_punctuate: bool,
+    /// Format a transcript with speaker labels, e.g. `Jane: Hello`, as
+    /// dialogue Markdown with a paragraph a turn, instead of splitting it
+    /// into paragraphs with the model.
+    #[arg(long)]
+    pub dialogue: bool,
+    /// Treat a transcript with speaker labels as a plain text. By default
+    /// the labels are detected and kept out of the text the model splits,
+    /// every paragraph of a turn is labeled with its speaker.
+    #[arg(long, conflicts_with = "dialogue")]
+    pub ignore_speakers: bool,
+    /// Make a section of every speaker turn of a dialogue instead of
+    /// grouping the paragraphs by the topics.
+    #[arg(long)]
+    pub section_per_turn: bool,
+    /// How the paragraphs are grouped into the sections.
+    #[arg(long, value_enum, default_value_t = Sectioning::Model)]
+    pub sectioning: Sectioning,
+    /// The prompt templates to use instead of the default prompts: the name
+    /// of a prompt set in `~/.config/trakktor/prompts`, or its directory.
+    #[arg(long)]
+    pub prompt_set: Option<String>,
+    /// The language of the documents, which the summaries and the titles are
+    /// written in. Detected from the text if neither it nor `--lang` is
+    /// given.
+    #[arg(long)]
+    pub language: Option<Language>,
+    /// Take only the paragraph breaks from the model and keep the words of
+    /// the text as they are, so the model cannot change them. The
+    /// punctuation of the texts without it is not restored either.
+    #[arg(long, conflicts_with = "dialogue")]
+    pub verbatim: bool,
+    #[command(flatten)]
+    pub outline: OutlineOptions,
 }
 
-const CHUNK_WORDS_THRESHOLD: usize = 1000;
-const CACHE_FILE_EXT: &str = "trakktor.cache";
-const RESULT_FILE_EXT: &str = "trakktor.text.md";
-const PARAGRAPHS_SUMMARY_FILE_EXT: &str = "trakktor.summaries.md";
+/// The algorithm grouping the paragraphs into the sections.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sectioning {
+    /// The model splits the summaries of the paragraphs into sections.
+    Model,
+    /// The sections end where the embeddings of the summaries of the
+    /// paragraphs change the most. Cheaper, and stable between runs.
+    Embeddings,
+}
+
+/// The outline of the final document, the text titled by sections.
+#[derive(Args, Debug, Clone)]
+pub struct OutlineOptions {
+    /// The level of the headings of the sections, from 1 to 6.
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u8).range(1..=6))]
+    pub heading_level: u8,
+    /// Number the sections.
+    #[arg(long)]
+    pub numbered_sections: bool,
+    /// Start the document with a table of contents linking to the sections.
+    #[arg(long)]
+    pub toc: bool,
+    /// Title the document with the model, by the summaries of its sections.
+    #[arg(long)]
+    pub document_title: bool,
+}
+
+impl Default for OutlineOptions {
+    fn default() -> Self {
+        OutlineOptions {
+            heading_level: 6,
+            numbered_sections: false,
+            toc: false,
+            document_title: false,
+        }
+    }
+}
 
+/// The documents of a directory structified at once by default.
+pub const DEFAULT_JOBS: usize = 4;
+const CHUNK_WORDS_THRESHOLD: usize = 1000;
+/// The chunks of a text split by the model at the same time.
+const SPECULATIVE_CHUNKS: usize = 3;
+/// The turns of a dialogue up to this long are kept as one paragraph.
+const TURN_PARAGRAPH_WORDS: usize = 150;
+pub(crate) const CACHE_FILE_EXT: &str = "trakktor.cache";
+
+/// Structifies the file, or the documents of the directory, unless the token
+/// is cancelled first. The answers of the model are cached next to the file,
+/// so a cancelled run is resumed by running it again.
+///
+/// The prompts are in the language `lang`, or in the language of the text if
+/// not given.
 pub async fn run_structify_text(
     args: &StructifyText,
-    chat_api: &Box<dyn ChatCompletionAPI>,
+    chat_api: &dyn ChatCompletionAPI,
+    embeddings_api: Option<&dyn EmbeddingsAPI>,
+    lang: Option<Lang>,
+    cancel: &CancellationToken,
+) -> crate::Result<()> {
+    Ok(structify(args, chat_api, embeddings_api, lang, cancel).await?)
+}
+
+async fn structify(
+    args: &StructifyText,
+    chat_api: &dyn ChatCompletionAPI,
+    embeddings_api: Option<&dyn EmbeddingsAPI>,
+    lang: Option<Lang>,
+    cancel: &CancellationToken,
 ) -> anyhow::Result<()> {
-    let input_text = tokio::fs::read_to_string(&args.file).await?;
+    if args.sectioning == Sectioning::Embeddings && embeddings_api.is_none() {
+        bail!("The embeddings sectioning needs an embeddings platform!");
+    }
+    let dry_run = args.dry_run.then(DryRun::new);
+    let dry_run_api = dry_run.as_ref().map(|d| d.chat_api(chat_api));
+    let chat_api = dry_run_api.as_deref().unwrap_or(chat_api);
+    let prompt_set = args
+        .prompt_set
+        .as_deref()
+        .map(PromptSet::load)
+        .transpose()?;
+    let prompt_set = prompt_set.as_ref();
+    if args.file.is_dir() {
+        cancellable(
+            cancel,
+            structify_dir(args, chat_api, embeddings_api, lang, prompt_set),
+        )
+        .await?;
+    } else {
+        cancellable(
+            cancel,
+            structify_text(args, chat_api, embeddings_api, lang, prompt_set),
+        )
+        .await?;
+    }
+    if let Some(dry_run) = &dry_run {
+        dry_run.print_summary();
+    }
+    Ok(())
+}
 
+/// Structifies the documents of the directory concurrently. A failed
+/// document does not stop the others, the failures are listed in the
+/// summary.
+async fn structify_dir(
+    args: &StructifyText,
+    chat_api: &dyn ChatCompletionAPI,
+    embeddings_api: Option<&dyn EmbeddingsAPI>,
+    lang: Option<Lang>,
+    prompt_set: Option<&PromptSet>,
+) -> anyhow::Result<()> {
+    let files = list_documents(&args.file).await?;
+    if files.is_empty() {
+        bail!("No documents found in: {}", args.file.display());
+    }
+    tracing::info!(documents = files.len(), "Structifying the directory");
+
+    let permits = Semaphore::new(args.jobs.max(1));
+    let done = AtomicUsize::new(0);
+    let (permits, done, total) = (&permits, &done, files.len());
+    let results = join_all(files.iter().map(|file| {
+        let doc_args = StructifyText {
+            file: file.clone(),
+            out_dir: args.out_dir.clone(),
+            ocr: args.ocr.clone(),
+            dry_run: args.dry_run,
+            jobs: 1,
+            no_punctuate: args.no_punctuate,
+            dialogue: args.dialogue,
+            ignore_speakers: args.ignore_speakers,
+            section_per_turn: args.section_per_turn,
+            sectioning: args.sectioning,
+            prompt_set: args.prompt_set.clone(),
+            language: args.language,
+            verbatim: args.verbatim,
+            outline: args.outline.clone(),
+        };
+        let span = info_span!("document", file = %display_name(file));
+        async move {
+            let _permit = permits.acquire().await?;
+            let started = Instant::now();
+            let res = structify_text(
+                &doc_args,
+                chat_api,
+                embeddings_api,
+                lang,
+                prompt_set,
+            )
+            .await;
+            let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+            match &res {
+                Ok(()) => tracing::info!("[{done}/{total}] Structified"),
+                Err(err) => {
+                    tracing::warn!("[{done}/{total}] Failed: {err:#}")
+                },
+            }
+            anyhow::Ok((res, started.elapsed()))
+        }
+        .instrument(span)
+    }))
+    .await;
+
+    let name_width = files
+        .iter()
+        .map(|f| display_name(f).len())
+        .max()
+        .unwrap_or(0);
+    let mut failed = 0;
+    println!();
+    for (file, res) in files.iter().zip(results) {
+        let (res, elapsed) = res?;
+        let outcome = match res {
+            Ok(()) => {
+                let kind = if is_epub(file) {
+                    OutputKind::Book
+                } else {
+                    OutputKind::Text
+                };
+                display_name(&output_path(args.out_dir.as_deref(), file, kind))
+            },
+            Err(err) => {
+                failed += 1;
+                format!("FAILED: {err:#}")
+            },
+        };
+        let elapsed = Duration::from_secs(elapsed.as_secs());
+        println!(
+            "{:<name_width$}  {:>8}  {}",
+            display_name(file),
+            elapsed.human_format(),
+            outcome
+        );
+    }
+
+    if failed > 0 {
+        bail!("Failed to structify {failed} of {total} documents!");
+    }
+    Ok(())
+}
+
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// The documents of the directory, without the hidden files and the outputs
+/// and caches of Trakktor. The outputs and the cache of a document are named
+/// after its stem, so the documents with the same stem, like `notes.txt` and
+/// `notes.pdf`, are refused.
+async fn list_documents(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || name.contains(".trakktor.") {
+            continue;
+        }
+        if entry.file_type().await?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+
+    let collisions = files
+        .iter()
+        .into_group_map_by(|file| file.with_extension(""))
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .map(|group| group.into_iter().map(|f| display_name(f)).join(", "))
+        .sorted()
+        .collect::<Vec<_>>();
+    if !collisions.is_empty() {
+        bail!(
+            "Documents with the same name would share their outputs, rename \
+             them: {}",
+            collisions.join("; ")
+        );
+    }
+    Ok(files)
+}
+
+#[tokio::test]
+async fn list_documents_test() {
+    let dir = std::env::temp_dir()
+        .join(format!("trakktor-structify-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in [
+        "notes.txt",
+        "talk.txt",
+        "talk.trakktor.text.md",
+        "talk.trakktor.cache",
+        ".hidden.txt",
+    ] {
+        std::fs::write(dir.join(name), "").unwrap();
+    }
+    let files = list_documents(&dir).await.unwrap();
+    assert_eq!(files, [dir.join("notes.txt"), dir.join("talk.txt")]);
+
+    std::fs::write(dir.join("notes.pdf"), "").unwrap();
+    let err = list_documents(&dir).await.unwrap_err();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(err.to_string().ends_with("notes.pdf, notes.txt"));
+}
+
+/// Runs the futures concurrently on the current task. Unlike spawned tasks,
+/// they may borrow, e.g. the chat API.
+async fn join_all<F: Future>(
+    futures: impl IntoIterator<Item = F>,
+) -> Vec<F::Output> {
+    let mut futures = futures
+        .into_iter()
+        .map(|f| Some(Box::pin(f)))
+        .collect::<Vec<_>>();
+    let mut outputs = futures.iter().map(|_| None).collect::<Vec<_>>();
+    std::future::poll_fn(|cx| {
+        let mut pending = false;
+        for (future, output) in futures.iter_mut().zip(outputs.iter_mut()) {
+            if let Some(f) = future {
+                match f.as_mut().poll(cx) {
+                    Poll::Ready(value) => {
+                        *output = Some(value);
+                        *future = None;
+                    },
+                    Poll::Pending => pending = true,
+                }
+            }
+        }
+        if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    })
+    .await;
+    outputs.into_iter().map(Option::unwrap).collect()
+}
+
+#[tokio::test]
+async fn join_all_test() {
+    let outputs = join_all((0..3u64).map(|i| async move {
+        tokio::time::sleep(Duration::from_millis(30 - i * 10)).await;
+        i
+    }))
+    .await;
+    assert_eq!(outputs, [0, 1, 2]);
+}
+
+async fn structify_text(
+    args: &StructifyText,
+    chat_api: &dyn ChatCompletionAPI,
+    embeddings_api: Option<&dyn EmbeddingsAPI>,
+    lang: Option<Lang>,
+    prompt_set: Option<&PromptSet>,
+) -> anyhow::Result<()> {
     let cache = Arc::new({
         let db_name = args.file.with_extension(CACHE_FILE_EXT);
-        spawn_blocking(move || CallCache::open(&db_name)).await??
+        let dry_run = args.dry_run;
+        spawn_blocking(move || {
+            CallCache::open_for(&db_name, dry_run).map_err(|source| {
+                crate::Error::Cache {
+                    path: db_name,
+                    source: source.into(),
+                }
+            })
+        })
+        .await??
     });
 
-    let result_paragraphs = words_to_paragraphs(
+    let outputs =
+        &Outputs::new(&args.file, args.out_dir.as_deref(), args.dry_run)
+            .await?;
+    if is_epub(&args.file) {
+        structify_book(
+            args,
+            chat_api,
+            embeddings_api,
+            &cache,
+            outputs,
+            lang,
+            prompt_set,
+        )
+        .await?;
+    } else {
+        structify_document(
+            args,
+            chat_api,
+            embeddings_api,
+            &cache,
+            outputs,
+            lang,
+            prompt_set,
+        )
+        .await?;
+    }
+    outputs.write_manifest().await
+}
+
+async fn structify_document(
+    args: &StructifyText,
+    chat_api: &dyn ChatCompletionAPI,
+    embeddings_api: Option<&dyn EmbeddingsAPI>,
+    cache: &Arc<CallCache>,
+    outputs: &Outputs,
+    lang: Option<Lang>,
+    prompt_set: Option<&PromptSet>,
+) -> anyhow::Result<()> {
+    let input_text = read_input_text(&args.file, &args.ocr).await?;
+    let prompts =
+        &text_prompts(args, cache, lang, prompt_set, &input_text).await?;
+
+    let turns = if args.dialogue {
+        Some(parse_dialogue(&input_text).with_context(|| {
+            format!("No speaker turns found in: {}", args.file.display())
+        })?)
+    } else if args.ignore_speakers {
+        None
+    } else {
+        parse_dialogue(&input_text)
+    };
+
+    let (result_paragraphs, turn_sizes) = match turns {
+        Some(turns) if args.dialogue => {
+            let turn_sizes = vec![1; turns.len()];
+            (
+                turns.iter().map(Turn::to_markdown).collect(),
+                Some(turn_sizes),
+            )
+        },
+        Some(turns) => {
+            tracing::info!(
+                turns = turns.len(),
+                "The text is a dialogue, keeping the speaker labels"
+            );
+            let (paragraphs, turn_sizes) = turns_to_paragraphs(
+                args, chat_api, cache, outputs, prompts, &turns,
+            )
+            .await?;
+            (paragraphs, Some(turn_sizes))
+        },
+        None => {
+            let input_text =
+                punctuate_if_needed(args, chat_api, cache, prompts, input_text)
+                    .await?;
+            let paragraphs = words_to_paragraphs(
+                chat_api,
+                cache,
+                prompts,
+                input_text.split_whitespace().map(|c| c.to_string()),
+            )
+            .await?;
+            let paragraphs =
+                reconcile_paragraphs(args, &input_text, paragraphs);
+            let diff = drift_diff(&input_text, &paragraphs);
+            write_diff(outputs, diff.as_deref().unwrap_or_default()).await?;
+            (paragraphs, None)
+        },
+    };
+
+    let full_text_file = outputs
+        .write(OutputKind::Text, result_paragraphs.join("\n\n"))
+        .await?;
+    if !args.dry_run {
+        tracing::info!(
+            "Wrote structified text to: {}",
+            full_text_file.display()
+        );
+    }
+
+    let sectioned = section_paragraphs(
+        args,
         chat_api,
-        &cache,
-        input_text.split_whitespace().map(|c| c.to_string()),
+        embeddings_api,
+        cache,
+        prompts,
+        &result_paragraphs,
+        turn_sizes.as_deref(),
     )
     .await?;
+    create_titles(args, chat_api, cache, outputs, prompts, sectioned).await?;
 
-    let full_text_file = args.file.with_extension(RESULT_FILE_EXT);
-    tokio::fs::write(&full_text_file, &result_paragraphs.join("\n\n")).await?;
+    Ok(())
+}
+
+/// Splits the long turns of a dialogue into paragraphs, each labeled with
+/// the speaker, so the labels are not taken for the words of the text.
+/// Returns the paragraphs and their number in each turn.
+async fn turns_to_paragraphs(
+    args: &StructifyText,
+    chat_api: &dyn ChatCompletionAPI,
+    cache: &Arc<CallCache>,
+    outputs: &Outputs,
+    prompts: &Prompts,
+    turns: &[Turn],
+) -> anyhow::Result<(Vec<String>, Vec<usize>)> {
+    let mut paragraphs = vec![];
+    let mut turn_sizes = vec![];
+    let mut diff_text = String::new();
+    for (i, turn) in turns.iter().enumerate() {
+        let text = punctuate_if_needed(
+            args,
+            chat_api,
+            cache,
+            prompts,
+            turn.text.clone(),
+        )
+        .await?;
+        let turn_paragraphs =
+            if text.split_whitespace().count() <= TURN_PARAGRAPH_WORDS {
+                vec![text]
+            } else {
+                let turn_paragraphs = words_to_paragraphs(
+                    chat_api,
+                    cache,
+                    prompts,
+                    text.split_whitespace().map(|c| c.to_string()),
+                )
+                .await?;
+                let turn_paragraphs =
+                    reconcile_paragraphs(args, &text, turn_paragraphs);
+                if let Some(diff) = drift_diff(&text, &turn_paragraphs) {
+                    diff_text.push_str(&format!(
+                        "## Turn {}: {}\n\n{}\n",
+                        i + 1,
+                        turn.speaker,
+                        diff
+                    ));
+                }
+                turn_paragraphs
+            };
+        turn_sizes.push(turn_paragraphs.len());
+        paragraphs.extend(turn_paragraphs.into_iter().map(|text| {
+            Turn {
+                speaker: turn.speaker.clone(),
+                text,
+            }
+            .to_markdown()
+        }));
+    }
+    write_diff(outputs, &diff_text).await?;
+    Ok((paragraphs, turn_sizes))
+}
 
-    tracing::info!("Wrote structified text to: {}", full_text_file.display());
+/// The paragraphs of the model, or in the verbatim mode the words of the
+/// original text split at the same places.
+fn reconcile_paragraphs(
+    args: &StructifyText,
+    orig_text: &str,
+    paragraphs: Vec<String>,
+) -> Vec<String> {
+    if !args.verbatim {
+        return paragraphs;
+    }
+    let orig = orig_text.split_whitespace().collect::<Vec<_>>();
+    reconstruct_paragraphs(&orig, &paragraphs)
+}
 
-    create_titles(args, chat_api, &cache, &result_paragraphs).await?;
+/// The words the model changed in the text besides the paragraph breaks,
+/// as a word diff, if it changed any.
+fn drift_diff(orig_text: &str, paragraphs: &[String]) -> Option<String> {
+    let orig = orig_text.split_whitespace().collect::<Vec<_>>();
+    let res = paragraphs
+        .iter()
+        .flat_map(|p| p.split_whitespace())
+        .collect::<Vec<_>>();
+    let changes = diff_words(&orig, &res);
+    if changes.is_empty() {
+        return None;
+    }
+    let changed_words = changes
+        .iter()
+        .map(|c| c.removed.len().max(c.added.len()))
+        .sum::<usize>();
+    tracing::info!(
+        changes = changes.len(),
+        changed_words,
+        "The model changed the words of the text"
+    );
+    Some(format_word_diff(&orig, &changes))
+}
 
+/// Writes the changes of the model to the words of the text, so they can be
+/// checked. A diff of an earlier run is removed if there are none.
+async fn write_diff(outputs: &Outputs, diff: &str) -> anyhow::Result<()> {
+    if diff.is_empty() {
+        return outputs.remove(OutputKind::Diff).await;
+    }
+    let diff_file = outputs.write(OutputKind::Diff, diff).await?;
+    if !outputs.dry_run() {
+        tracing::info!("Wrote the changed words to: {}", diff_file.display());
+    }
     Ok(())
 }
 
+/// The prompts for the text in its language: the one given, or the detected
+/// one, which is cached with the responses.
+async fn text_prompts(
+    args: &StructifyText,
+    cache: &Arc<CallCache>,
+    lang: Option<Lang>,
+    prompt_set: Option<&PromptSet>,
+    text: &str,
+) -> anyhow::Result<Cow<'static, Prompts>> {
+    let language = match (args.language, lang) {
+        (Some(language), _) => language,
+        (None, Some(lang)) => lang.into(),
+        (None, None) => {
+            let call_hash =
+                Arc::new(get_hash_value(format!("detect_language:\n{text}")));
+            match cache.get_data::<Option<Language>>(&call_hash).await? {
+                Some(language) => language,
+                None => {
+                    let language = Arc::new(Language::detect(text));
+                    cache.put_data(&call_hash, &language).await?;
+                    *language
+                },
+            }
+            .unwrap_or(Language::En)
+        },
+    };
+    tracing::info!(language = language.name(), "The language of the text");
+    let prompts = prompts_for(prompt_set, language.lang().unwrap_or(Lang::En));
+    Ok(language.adapt_prompts(prompts))
+}
+
+/// Restores the punctuation of a raw transcript, it is split into
+/// paragraphs by the sentences.
+async fn punctuate_if_needed(
+    args: &StructifyText,
+    chat_api: &dyn ChatCompletionAPI,
+    cache: &Arc<CallCache>,
+    prompts: &Prompts,
+    text: String,
+) -> anyhow::Result<String> {
+    if args.no_punctuate || args.verbatim || !is_unpunctuated(&text) {
+        return Ok(text);
+    }
+    tracing::info!("The text has no punctuation, restoring it");
+    restore_punctuation(chat_api, cache, prompts, &text).await
+}
+
+/// Groups the paragraphs into titled sections: a section a speaker turn if
+/// asked, or by the topics with the sectioning algorithm of the args.
+async fn section_paragraphs(
+    args: &StructifyText,
+    chat_api: &dyn ChatCompletionAPI,
+    embeddings_api: Option<&dyn EmbeddingsAPI>,
+    cache: &Arc<CallCache>,
+    prompts: &Prompts,
+    paragraphs: &[String],
+    turn_sizes: Option<&[usize]>,
+) -> anyhow::Result<Sectioned> {
+    if let Some(turn_sizes) = turn_sizes.filter(|_| args.section_per_turn) {
+        return turn_sections(chat_api, cache, prompts, paragraphs, turn_sizes)
+            .await;
+    }
+    match (args.sectioning, embeddings_api) {
+        (Sectioning::Embeddings, Some(embeddings_api)) => {
+            embedding_sections(
+                chat_api,
+                embeddings_api,
+                cache,
+                prompts,
+                paragraphs,
+            )
+            .await
+        },
+        (Sectioning::Embeddings, None) => {
+            bail!("The embeddings sectioning needs an embeddings platform!")
+        },
+        (Sectioning::Model, _) => {
+            make_sections(chat_api, cache, prompts, paragraphs).await
+        },
+    }
+}
+
 async fn create_titles(
     args: &StructifyText,
-    chat_api: &Box<dyn ChatCompletionAPI>,
+    chat_api: &dyn ChatCompletionAPI,
     cache: &Arc<CallCache>,
-    result_paragraphs: &[String],
+    outputs: &Outputs,
+    prompts: &Prompts,
+    sectioned: Sectioned,
 ) -> anyhow::Result<()> {
+    let summaries_file = outputs
+        .write(OutputKind::Summaries, sectioned.summaries.join("\n\n"))
+        .await?;
+    outputs
+        .write(
+            OutputKind::SectionSummaries,
+            sectioned.section_summaries.join("\n\n"),
+        )
+        .await?;
+    if !args.dry_run {
+        tracing::info!("Wrote summaries to: {}", summaries_file.display());
+    }
+
+    let document_title = if args.outline.document_title {
+        let title = run_cached_prompt(
+            chat_api,
+            cache,
+            "document_title",
+            ChatTask::Title,
+            &prompts.document_title,
+            &sectioned.section_summaries.join("\n\n"),
+        )
+        .await?;
+        Some(title.trim().to_string())
+    } else {
+        None
+    };
+    let text_with_sections = format_outline(
+        &args.outline,
+        document_title.as_deref(),
+        &sectioned.sections,
+    );
+
+    let document_file = outputs
+        .write(OutputKind::Document, text_with_sections)
+        .await?;
+    if !args.dry_run {
+        tracing::info!("Wrote the document to: {}", document_file.display());
+    }
+
+    Ok(())
+}
+
+/// Formats the sections with their titles as headings, optionally numbered,
+/// under the title of the document and a table of contents.
+fn format_outline(
+    outline: &OutlineOptions,
+    document_title: Option<&str>,
+    sections: &[(String, Vec<String>)],
+) -> String {
+    let level = usize::from(outline.heading_level.clamp(1, 6));
+    let heading = "#".repeat(level);
+    let titles = sections
+        .iter()
+        .enumerate()
+        .map(|(i, (title, _))| match outline.numbered_sections {
+            true => format!("{}. {}", i + 1, title),
+            false => title.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut text = String::new();
+    if let Some(title) = document_title {
+        // A level above the sections, unless they are at the top level.
+        text.push_str(&format!(
+            "{} {}\n\n",
+            "#".repeat(level.max(2) - 1),
+            title
+        ));
+    }
+    if outline.toc {
+        text.push_str(&format!("{} Contents\n\n", heading));
+        for (i, title) in titles.iter().enumerate() {
+            text.push_str(&format!("- [{}](#section-{})\n", title, i + 1));
+        }
+        text.push('\n');
+    }
+    for (i, (title, (_, paragraphs))) in titles.iter().zip(sections).enumerate()
+    {
+        if outline.toc {
+            text.push_str(&format!("<a id=\"section-{}\"></a>\n\n", i + 1));
+        }
+        text.push_str(&format!("{} {}\n\n", heading, title));
+        for par in paragraphs {
+            text.push_str(&format!("{}\n\n", par));
+        }
+    }
+    text
+}
+
+#[test]
+fn format_outline_test() {
+    let sections = [
+        (
+            "Start".to_string(),
+            vec!["One.".to_string(), "Two.".to_string()],
+        ),
+        ("End".to_string(), vec!["Three.".to_string()]),
+    ];
+    assert_eq!(
+        format_outline(&OutlineOptions::default(), None, &sections),
+        "###### Start\n\nOne.\n\nTwo.\n\n###### End\n\nThree.\n\n"
+    );
+    let outline = OutlineOptions {
+        heading_level: 2,
+        numbered_sections: true,
+        toc: true,
+        document_title: true,
+    };
+    assert_eq!(
+        format_outline(&outline, Some("Story"), &sections),
+        "# Story\n\n## Contents\n\n- [1. Start](#section-1)\n- [2. \
+         End](#section-2)\n\n<a id=\"section-1\"></a>\n\n## 1. \
+         Start\n\nOne.\n\nTwo.\n\n<a id=\"section-2\"></a>\n\n## 2. \
+         End\n\nThree.\n\n"
+    );
+}
+
+/// Structify each chapter of the book separately, and write the book with a
+/// table of contents and the summaries of its chapters.
+async fn structify_book(
+    args: &StructifyText,
+    chat_api: &dyn ChatCompletionAPI,
+    embeddings_api: Option<&dyn EmbeddingsAPI>,
+    cache: &Arc<CallCache>,
+    outputs: &Outputs,
+    lang: Option<Lang>,
+    prompt_set: Option<&PromptSet>,
+) -> anyhow::Result<()> {
+    let book = read_epub(&args.file).await?;
+    if book.chapters.is_empty() {
+        bail!("No chapters found in the book!");
+    }
+
+    let mut contents = String::new();
+    let mut chapters_text = String::new();
+    let mut summaries_text = String::new();
+    let mut diff_text = String::new();
+    if let Some(title) = &book.title {
+        contents.push_str(&format!("# {}\n\n", title));
+        summaries_text.push_str(&format!("# {}\n\n", title));
+    }
+    contents.push_str("## Contents\n\n");
+
+    for (i, chapter) in book.chapters.iter().enumerate() {
+        let title = chapter
+            .title
+            .clone()
+            .unwrap_or_else(|| format!("Chapter {}", i + 1));
+        let anchor = format!("chapter-{}", i + 1);
+        tracing::info!(
+            "Structifying chapter {} of {}: {}",
+            i + 1,
+            book.chapters.len(),
+            title
+        );
+
+        let prompts =
+            &text_prompts(args, cache, lang, prompt_set, &chapter.text).await?;
+        let text = punctuate_if_needed(
+            args,
+            chat_api,
+            cache,
+            prompts,
+            chapter.text.clone(),
+        )
+        .await?;
+        let paragraphs = words_to_paragraphs(
+            chat_api,
+            cache,
+            prompts,
+            text.split_whitespace().map(|c| c.to_string()),
+        )
+        .await?;
+        let paragraphs = reconcile_paragraphs(args, &text, paragraphs);
+        if let Some(diff) = drift_diff(&text, &paragraphs) {
+            diff_text.push_str(&format!("## {}\n\n{}\n", title, diff));
+        }
+        let sectioned = section_paragraphs(
+            args,
+            chat_api,
+            embeddings_api,
+            cache,
+            prompts,
+            &paragraphs,
+            None,
+        )
+        .await?;
+
+        contents.push_str(&format!("- [{}](#{})\n", title, anchor));
+
+        chapters_text.push_str(&format!(
+            "<a id=\"{}\"></a>\n\n## {}\n\n",
+            anchor, title
+        ));
+        summaries_text.push_str(&format!("## {}\n\n", title));
+        for (sec_title, sec) in &sectioned.sections {
+            chapters_text.push_str(&format!("### {}\n\n", sec_title));
+            summaries_text.push_str(&format!("- {}\n", sec_title));
+            for par in sec {
+                chapters_text.push_str(&format!("{}\n\n", par));
+            }
+        }
+        summaries_text.push('\n');
+        summaries_text.push_str(&sectioned.section_summaries.join("\n\n"));
+        summaries_text.push_str("\n\n");
+    }
+
+    let book_file = outputs
+        .write(OutputKind::Book, format!("{}\n{}", contents, chapters_text))
+        .await?;
+    let summaries_file = outputs
+        .write(OutputKind::BookSummaries, &summaries_text)
+        .await?;
+    write_diff(outputs, &diff_text).await?;
+    if !args.dry_run {
+        tracing::info!("Wrote structified book to: {}", book_file.display());
+        tracing::info!("Wrote book summaries to: {}", summaries_file.display());
+    }
+
+    Ok(())
+}
+
+struct Sectioned {
+    /// Short summary of each paragraph.
+    summaries: Vec<String>,
+    /// The paragraph summaries split into sections.
+    section_summaries: Vec<String>,
+    /// Titles of the sections with their paragraphs.
+    sections: Vec<(String, Vec<String>)>,
+}
+
+/// Group the paragraphs into titled sections.
+async fn make_sections(
+    chat_api: &dyn ChatCompletionAPI,
+    cache: &Arc<CallCache>,
+    prompts: &Prompts,
+    result_paragraphs: &[String],
+) -> anyhow::Result<Sectioned> {
     // Short summaries of each paragraph
     let result_summaries =
-        summarize_paragraphs(chat_api, &cache, &result_paragraphs).await?;
-
-    // Write summaries to a file
-    let summaries_file = args.file.with_extension(PARAGRAPHS_SUMMARY_FILE_EXT);
-    tokio::fs::write(&summaries_file, &result_summaries.join("\n\n")).await?;
+        summarize_paragraphs(chat_api, cache, prompts, result_paragraphs)
+            .await?;
 
     // Split summaries into paragraphs
 
@@ -77,23 +1001,18 @@ async fn create_titles(
     let summaries_words = result_summaries
         .iter()
         .enumerate()
-        .map(|(i, s)| s.split_whitespace().map(move |c| (i, c.to_string())))
-        .flatten()
+        .flat_map(|(i, s)| {
+            s.split_whitespace().map(move |c| (i, c.to_string()))
+        })
         .collect::<Vec<_>>();
     let sections = words_to_paragraphs(
         chat_api,
-        &cache,
+        cache,
+        prompts,
         summaries_words.iter().map(|s| &s.1).cloned(),
     )
     .await?;
 
-    // ************ todo: надо переименовать файл
-    let sections_file = args.file.with_extension("trakktor.sections.md");
-    tokio::fs::write(&sections_file, &sections.join("\n\n")).await?;
-
-    tracing::info!("Wrote summaries to: {}", summaries_file.display());
-    // ************
-
     // Key: section index, Value: paragraph index -> word count in section
     let mut section_par_words: Vec<BTreeMap<usize, usize>> =
         vec![Default::default(); sections.len()];
@@ -103,9 +1022,12 @@ async fn create_titles(
         sections.iter().zip(section_par_words.iter_mut())
     {
         let current_text = summaries_words_p.iter().map(|s| &s.1).join(" ");
-        let next =
-            get_next_text_words(cache, &[section.clone()], &current_text)
-                .await?;
+        let next = get_next_text_words(
+            cache,
+            std::slice::from_ref(section),
+            &current_text,
+        )
+        .await?;
 
         for (n, _) in &summaries_words_p[..=next.skipped_words] {
             par_words.entry(*n).and_modify(|n| *n += 1).or_insert(1);
@@ -154,42 +1076,93 @@ async fn create_titles(
         bail!("Not all paragraphs were used in the sections!");
     }
 
-    let mut text_with_sections = String::new();
-    for sec in &final_sections {
-        // if text_with_sections.len() > 0 {
-        //     text_with_sections.push_str("\n\n");
-        // }
-
-        let title = get_section_title(chat_api, cache, sec).await?;
-        text_with_sections.push_str(&format!("###### {}\n\n", title.trim()));
+    let mut sections_with_titles = vec![];
+    for sec in final_sections {
+        let title = get_section_title(chat_api, cache, prompts, &sec).await?;
+        sections_with_titles.push((title.trim().to_string(), sec));
+    }
 
-        for par in sec {
-            text_with_sections.push_str(&format!("{}\n\n", par));
-        }
+    Ok(Sectioned {
+        summaries: result_summaries,
+        section_summaries: sections,
+        sections: sections_with_titles,
+    })
+}
 
-        // text_with_sections.push_str(&sec.join("\n\n"));
-        // text_with_sections.push_str("\n\n");
+/// Groups the paragraphs into sections at the valleys of the similarity of
+/// the embeddings of their summaries, and titles them with the model.
+async fn embedding_sections(
+    chat_api: &dyn ChatCompletionAPI,
+    embeddings_api: &dyn EmbeddingsAPI,
+    cache: &Arc<CallCache>,
+    prompts: &Prompts,
+    paragraphs: &[String],
+) -> anyhow::Result<Sectioned> {
+    let summaries =
+        summarize_paragraphs(chat_api, cache, prompts, paragraphs).await?;
+    let mut embeddings = vec![];
+    for summary in &summaries {
+        embeddings.push(cache.get_embedding(embeddings_api, summary).await?);
     }
 
-    // ************ todo: надо переименовать файл
-    let final_file = args.file.with_extension("trakktor.final.md");
-    tokio::fs::write(&final_file, &text_with_sections).await?;
-
-    // tracing::info!("Wrote summaries to: {}", summaries_file.display());
-    // ************
+    let mut starts = section_starts(&embeddings);
+    starts.push(paragraphs.len());
+    let mut section_summaries = vec![];
+    let mut sections = vec![];
+    let mut start = 0;
+    for end in starts {
+        section_summaries.push(summaries[start..end].join(" "));
+        let sec = paragraphs[start..end].to_vec();
+        let title = get_section_title(chat_api, cache, prompts, &sec).await?;
+        sections.push((title.trim().to_string(), sec));
+        start = end;
+    }
+    Ok(Sectioned {
+        summaries,
+        section_summaries,
+        sections,
+    })
+}
 
-    Ok(())
+/// Makes a section of the paragraphs of every speaker turn, titled by the
+/// model like the sections grouped by the topics.
+async fn turn_sections(
+    chat_api: &dyn ChatCompletionAPI,
+    cache: &Arc<CallCache>,
+    prompts: &Prompts,
+    paragraphs: &[String],
+    turn_sizes: &[usize],
+) -> anyhow::Result<Sectioned> {
+    let summaries =
+        summarize_paragraphs(chat_api, cache, prompts, paragraphs).await?;
+    let mut section_summaries = vec![];
+    let mut sections = vec![];
+    let mut start = 0;
+    for size in turn_sizes {
+        let turn = start..start + size;
+        start += size;
+        section_summaries.push(summaries[turn.clone()].join(" "));
+        let sec = paragraphs[turn].to_vec();
+        let title = get_section_title(chat_api, cache, prompts, &sec).await?;
+        sections.push((title.trim().to_string(), sec));
+    }
+    Ok(Sectioned {
+        summaries,
+        section_summaries,
+        sections,
+    })
 }
 
 async fn get_section_title(
-    chat_api: &Box<dyn ChatCompletionAPI>,
+    chat_api: &dyn ChatCompletionAPI,
     cache: &Arc<CallCache>,
+    prompts: &Prompts,
     paragraphs: &[String],
 ) -> anyhow::Result<String> {
     let call_hash = Arc::new(get_hash_value(format!(
         "get_section_title:\n{}\n\n{}\n\n{:?}",
-        chat_api.config_hash(),
-        GET_SECTION_TITLE_PROMPT,
+        config_hash(chat_api, prompts),
+        prompts.section_title,
         paragraphs,
     )));
 
@@ -198,87 +1171,282 @@ async fn get_section_title(
         Ok(summary)
     } else {
         let section_text = paragraphs.join("\n\n");
-        let summary = chat_api
-            .run_chat(
-                ChatCompletionsArgs::builder()
-                    .messages(&[
-                        Message {
-                            role: Role::System,
-                            content: Cow::Borrowed(
-                                &GET_SECTION_TITLE_PROMPT.trim(),
-                                // &SUMMARIZE_PARAGRAPH_PROMPT.trim(),
-                            ),
-                        },
-                        Message {
-                            role: Role::User,
-                            content: Cow::Borrowed(&section_text),
-                        },
-                    ])
-                    .build(),
-            )
-            .await?
-            .content
-            .to_string();
+        let summary = run_chat_checked(
+            chat_api,
+            cache,
+            ChatCompletionsArgs::builder()
+                .messages(&[
+                    Message {
+                        role: Role::System,
+                        content: Cow::Borrowed(
+                            prompts.section_title.trim(),
+                            // &SUMMARIZE_PARAGRAPH_PROMPT.trim(),
+                        ),
+                    },
+                    Message {
+                        role: Role::User,
+                        content: Cow::Borrowed(&section_text),
+                    },
+                ])
+                .task(ChatTask::Title)
+                .build(),
+        )
+        .await?
+        .content
+        .to_string();
         let summary = Arc::new(summary);
         cache.put_data(&call_hash, &summary).await?;
         Ok(Arc::into_inner(summary).unwrap())
     }
 }
 
+/// The config hash of the chat API for the hashes of the calls, with the
+/// version of the prompt set.
+fn config_hash(chat_api: &dyn ChatCompletionAPI, prompts: &Prompts) -> String {
+    match &prompts.version {
+        Some(version) => {
+            format!("{}:prompts={version}", chat_api.config_hash())
+        },
+        None => chat_api.config_hash(),
+    }
+}
+
+/// Runs the chat completion, and checks the system fingerprint of the
+/// response against the one of the cached responses.
+async fn run_chat_checked(
+    chat_api: &dyn ChatCompletionAPI,
+    cache: &Arc<CallCache>,
+    args: ChatCompletionsArgs<'_>,
+) -> anyhow::Result<Message<'static>> {
+    let response = chat_api.run_chat_full(args).await?;
+    cache.check_fingerprint(&response).await?;
+    Ok(response.message)
+}
+
+/// Run the prompt on the input, caching the response.
+pub(crate) async fn run_cached_prompt(
+    chat_api: &dyn ChatCompletionAPI,
+    cache: &Arc<CallCache>,
+    call_name: &str,
+    task: ChatTask,
+    prompt: &str,
+    input: &str,
+) -> anyhow::Result<String> {
+    let call_hash = Arc::new(get_hash_value(format!(
+        "{}:\n{}\n\n{}\n\n{}",
+        call_name,
+        chat_api.config_hash(),
+        prompt,
+        input,
+    )));
+
+    if let Some(response) = cache.get_data::<String>(&call_hash).await? {
+        tracing::debug!(call_name, "Using cached response");
+        return Ok(response);
+    }
+
+    let response = run_chat_checked(
+        chat_api,
+        cache,
+        ChatCompletionsArgs::builder()
+            .messages(&[
+                Message {
+                    role: Role::System,
+                    content: Cow::Borrowed(prompt.trim()),
+                },
+                Message {
+                    role: Role::User,
+                    content: Cow::Borrowed(input),
+                },
+            ])
+            .task(task)
+            .build(),
+    )
+    .await?
+    .content
+    .to_string();
+    let response = Arc::new(response);
+    cache.put_data(&call_hash, &response).await?;
+    Ok(Arc::into_inner(response).unwrap())
+}
+
+/// The paragraphs of a chunk of the words, with the indices of the words
+/// they start at.
+#[derive(Debug)]
+struct ChunkParagraphs {
+    /// Whether the chunk reaches the end of the words.
+    last: bool,
+    paragraphs: Vec<(usize, String)>,
+}
+
+impl ChunkParagraphs {
+    /// Where the next chunk starts: the last paragraph may be cut by the
+    /// end of the chunk, so it is split again with the words after it.
+    fn handoff(&self) -> usize {
+        self.paragraphs.last().map_or(0, |(start, _)| *start)
+    }
+}
+
+async fn chunk_paragraphs(
+    chat_api: &dyn ChatCompletionAPI,
+    cache: &Arc<CallCache>,
+    prompts: &Prompts,
+    words: &[String],
+    start: usize,
+) -> anyhow::Result<ChunkParagraphs> {
+    // The chunk ends with a sentence, so none is split across chunks.
+    let len = sentence_chunk_len(&words[start..], CHUNK_WORDS_THRESHOLD);
+    let chunk = words[start..start + len]
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    let paragraphs =
+        get_paragraphs(Arc::clone(cache), chat_api, prompts, &chunk.join(" "))
+            .await?;
+    let starts = paragraph_starts(&chunk, &paragraphs);
+    Ok(ChunkParagraphs {
+        last: start + len == words.len(),
+        paragraphs: starts
+            .into_iter()
+            .map(|s| start + s)
+            .zip(paragraphs.iter().cloned())
+            .collect(),
+    })
+}
+
+/// The starts of the chunks split at once: the next chunks are split
+/// speculatively before the previous ones, starting at their middles, as
+/// most of a chunk is accepted.
+fn speculative_starts(words: &[String], start: usize) -> Vec<usize> {
+    let mut starts = vec![start];
+    while starts.len() < SPECULATIVE_CHUNKS {
+        let prev = starts[starts.len() - 1];
+        if prev + CHUNK_WORDS_THRESHOLD >= words.len() {
+            break;
+        }
+        starts.push(sentence_start(
+            words,
+            prev + CHUNK_WORDS_THRESHOLD / 2,
+            CHUNK_WORDS_THRESHOLD / 4,
+        ));
+    }
+    starts
+}
+
+/// The word where the paragraphs of the next chunk may continue the ones of
+/// the chunk: the first break of both, up to the handoff of the chunk.
+fn find_seam(chunk: &ChunkParagraphs, next: &ChunkParagraphs) -> Option<usize> {
+    if chunk.last {
+        return None;
+    }
+    let next_starts = next
+        .paragraphs
+        .iter()
+        .map(|(start, _)| *start)
+        .collect::<HashSet<_>>();
+    chunk
+        .paragraphs
+        .iter()
+        .map(|(start, _)| *start)
+        .find(|start| next_starts.contains(start))
+}
+
+/// Splits the words into paragraphs with the model, a chunk at a time. A
+/// chunk waits for the previous one to know where it starts, so the next
+/// chunks are split at the same time from where they likely start, and
+/// are used from a paragraph break they share with the previous one. The
+/// chunks that share none are split again from the right place.
 async fn words_to_paragraphs(
-    chat_api: &Box<dyn ChatCompletionAPI>,
+    chat_api: &dyn ChatCompletionAPI,
     cache: &Arc<CallCache>,
+    prompts: &Prompts,
     words: impl Iterator<Item = String>,
 ) -> anyhow::Result<Vec<String>> {
-    let mut all_words = Arc::new(words.collect::<Vec<_>>());
-
-    if all_words.len() < 1 {
+    let words = words.collect::<Vec<_>>();
+    if words.is_empty() {
         bail!("No words found in the input text!");
     }
 
     let mut result_paragraphs: Vec<String> = Vec::new();
-
+    let mut start = 0;
     loop {
-        let mut llm_text = String::new();
-        let mut orig_text = String::new();
-        for i in 0..all_words.len() {
-            push_word(&mut orig_text, &all_words[i]);
-            if i < CHUNK_WORDS_THRESHOLD {
-                push_word(&mut llm_text, &all_words[i]);
-            }
+        let starts = speculative_starts(&words, start);
+        let mut chunks = join_all(starts.iter().map(|&chunk_start| {
+            chunk_paragraphs(chat_api, cache, prompts, &words, chunk_start)
+        }))
+        .await
+        .into_iter();
+        let mut chunk = chunks.next().unwrap()?;
+        for next in chunks {
+            let next = next?;
+            let Some(seam) = find_seam(&chunk, &next) else {
+                tracing::debug!("The speculative chunk shares no break");
+                break;
+            };
+            result_paragraphs.extend(
+                chunk
+                    .paragraphs
+                    .into_iter()
+                    .filter(|(par_start, _)| *par_start < seam)
+                    .map(|(_, par)| par),
+            );
+            chunk = ChunkParagraphs {
+                last: next.last,
+                paragraphs: next
+                    .paragraphs
+                    .into_iter()
+                    .filter(|(par_start, _)| *par_start >= seam)
+                    .collect(),
+            };
         }
 
-        let last_chunk = llm_text == orig_text;
-
-        let paragraphs =
-            get_paragraphs(Arc::clone(&cache), chat_api, &llm_text).await?;
-
-        if last_chunk {
-            result_paragraphs.extend(paragraphs.iter().cloned());
+        if chunk.last {
+            result_paragraphs
+                .extend(chunk.paragraphs.into_iter().map(|(_, par)| par));
             break;
         }
-
-        if paragraphs.len() < 2 {
+        let handoff = chunk.handoff();
+        if handoff <= start {
             bail!(
                 "Failed to split text into paragraphs: too few paragraphs \
                  returned!"
             );
         }
-
-        let accepted_paragraphs = &paragraphs[0..paragraphs.len() - 1];
-        result_paragraphs.extend(accepted_paragraphs.iter().cloned());
-
-        all_words = get_next_text_words(cache, accepted_paragraphs, &orig_text)
-            .await?
-            .words;
+        result_paragraphs.extend(
+            chunk
+                .paragraphs
+                .into_iter()
+                .filter(|(par_start, _)| *par_start < handoff)
+                .map(|(_, par)| par),
+        );
+        start = handoff;
     }
 
     Ok(result_paragraphs)
 }
 
+#[test]
+fn find_seam_test() {
+    let chunk = |last, starts: &[usize]| ChunkParagraphs {
+        last,
+        paragraphs: starts.iter().map(|&s| (s, s.to_string())).collect(),
+    };
+    let first = chunk(false, &[0, 120, 380, 610, 900]);
+    assert_eq!(
+        find_seam(&first, &chunk(false, &[500, 610, 880])),
+        Some(610)
+    );
+    assert_eq!(find_seam(&first, &chunk(false, &[500, 700, 990])), None);
+    assert_eq!(
+        find_seam(&chunk(true, &[0, 120]), &chunk(true, &[120])),
+        None
+    );
+}
+
 async fn summarize_paragraphs(
-    chat_api: &Box<dyn ChatCompletionAPI>,
+    chat_api: &dyn ChatCompletionAPI,
     cache: &Arc<CallCache>,
+    prompts: &Prompts,
     paragraphs: &[String],
 ) -> anyhow::Result<Vec<String>> {
     let mut result_summaries: Vec<String> = Vec::new();
@@ -286,8 +1454,8 @@ async fn summarize_paragraphs(
     for src_par in paragraphs {
         let call_hash = Arc::new(get_hash_value(format!(
             "summarize_paragraphs:\n{}\n\n{}\n\n{}",
-            chat_api.config_hash(),
-            SUMMARIZE_PARAGRAPH_PROMPT,
+            config_hash(chat_api, prompts),
+            prompts.summarize_paragraph,
             src_par,
         )));
 
@@ -295,26 +1463,28 @@ async fn summarize_paragraphs(
             tracing::debug!("Using cached summary");
             result_summaries.push(summary);
         } else {
-            let summary = chat_api
-                .run_chat(
-                    ChatCompletionsArgs::builder()
-                        .messages(&[
-                            Message {
-                                role: Role::System,
-                                content: Cow::Borrowed(
-                                    &SUMMARIZE_PARAGRAPH_PROMPT.trim(),
-                                ),
-                            },
-                            Message {
-                                role: Role::User,
-                                content: Cow::Borrowed(src_par),
-                            },
-                        ])
-                        .build(),
-                )
-                .await?
-                .content
-                .to_string();
+            let summary = run_chat_checked(
+                chat_api,
+                cache,
+                ChatCompletionsArgs::builder()
+                    .messages(&[
+                        Message {
+                            role: Role::System,
+                            content: Cow::Borrowed(
+                                prompts.summarize_paragraph.trim(),
+                            ),
+                        },
+                        Message {
+                            role: Role::User,
+                            content: Cow::Borrowed(src_par),
+                        },
+                    ])
+                    .task(ChatTask::Summary)
+                    .build(),
+            )
+            .await?
+            .content
+            .to_string();
             let summary = Arc::new(summary);
             cache.put_data(&call_hash, &summary).await?;
             result_summaries.push(Arc::into_inner(summary).unwrap());
@@ -324,13 +1494,6 @@ async fn summarize_paragraphs(
     Ok(result_summaries)
 }
 
-fn push_word(text: &mut String, word: &str) {
-    if !text.is_empty() {
-        text.push(' ');
-    }
-    text.push_str(word);
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 struct NextTextWordsRes {
     words: Arc<Vec<String>>,
@@ -360,8 +1523,7 @@ async fn get_next_text_words(
 
     let llm_res_words = accepted_paragraphs
         .iter()
-        .map(|p| p.split_whitespace())
-        .flatten()
+        .flat_map(|p| p.split_whitespace())
         .filter(|w| !w.is_empty())
         .collect::<Vec<_>>();
     let llm_res_words_text = llm_res_words.iter().join(" ");
@@ -377,11 +1539,11 @@ async fn get_next_text_words(
     let mut orig_fragment = String::new();
     let mut dist = vec![];
 
-    for i in 0..orig_part_words.len() {
-        if orig_fragment.len() > 0 {
+    for (i, word) in orig_part_words.iter().enumerate() {
+        if !orig_fragment.is_empty() {
             orig_fragment.push(' ');
         }
-        orig_fragment.push_str(orig_part_words[i]);
+        orig_fragment.push_str(word);
 
         if (i as isize) >= orig_from_idx {
             dist.push((
@@ -409,8 +1571,8 @@ async fn get_next_text_words(
     );
 
     let res = Arc::new(NextTextWordsRes {
-        words: next_words.into(),
-        skipped_words: min_e.0 as usize,
+        words: next_words,
+        skipped_words: min_e.0,
         distance: min_e.1,
     });
 
@@ -421,13 +1583,14 @@ async fn get_next_text_words(
 
 async fn get_paragraphs(
     call_cache: Arc<CallCache>,
-    chat_api: &Box<dyn ChatCompletionAPI>,
+    chat_api: &dyn ChatCompletionAPI,
+    prompts: &Prompts,
     text: &str,
 ) -> anyhow::Result<Arc<Vec<String>>> {
     let call_hash = Arc::new(get_hash_value(format!(
         "get_paragraphs:\n{}\n\n{}\n\n{}",
-        chat_api.config_hash(),
-        STRUCTIFY_PROMPT,
+        config_hash(chat_api, prompts),
+        prompts.structify,
         text
     )));
 
@@ -443,27 +1606,27 @@ async fn get_paragraphs(
         let mut retries = vec![];
 
         loop {
-            let content = chat_api
-                .run_chat(
-                    ChatCompletionsArgs::builder()
-                        .messages(&[
-                            Message {
-                                role: Role::System,
-                                content: Cow::Borrowed(
-                                    &STRUCTIFY_PROMPT.trim(),
-                                ),
-                            },
-                            Message {
-                                role: Role::User,
-                                content: Cow::Borrowed(text),
-                            },
-                        ])
-                        .build(),
-                )
-                .await?
-                .content;
+            let content = run_chat_checked(
+                chat_api,
+                &call_cache,
+                ChatCompletionsArgs::builder()
+                    .messages(&[
+                        Message {
+                            role: Role::System,
+                            content: Cow::Borrowed(prompts.structify.trim()),
+                        },
+                        Message {
+                            role: Role::User,
+                            content: Cow::Borrowed(text),
+                        },
+                    ])
+                    .task(ChatTask::Structify)
+                    .build(),
+            )
+            .await?
+            .content;
             let res_text = content.split_whitespace().join(" ");
-            let distance = edit_distance::edit_distance(&res_text, &text);
+            let distance = edit_distance::edit_distance(&res_text, text);
             const MAX_RETRIES: usize = 10;
             tracing::info!(
                 "Paragraphs Levenshtein distance: {}, retry number: {} (of {})",
@@ -517,15 +1680,20 @@ async fn get_paragraphs(
     Ok(paragraphs)
 }
 
-struct CallCache {
+pub(crate) struct CallCache {
     db: redb::Database,
+    /// The new responses are not stored, e.g. in a dry run.
+    read_only: bool,
 }
 
 const KV_TABLE: TableDefinition<&str, Vec<u8>> =
     TableDefinition::new("kv_table");
+/// The system fingerprints of the models, kept with the responses.
+const FINGERPRINT_KEY_PREFIX: &str = "system_fingerprint:";
 
 impl CallCache {
-    fn open(file_path: &Path) -> anyhow::Result<Self> {
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn open(file_path: &Path) -> Result<Self, redb::Error> {
         let db = redb::Database::create(file_path)?;
         let write_txn = db.begin_write()?;
 
@@ -534,10 +1702,40 @@ impl CallCache {
 
         write_txn.commit()?;
 
-        Ok(Self { db })
+        Ok(Self {
+            db,
+            read_only: false,
+        })
     }
 
-    async fn get_data<T>(
+    /// Opens the cache, or in a dry run only reads the existing responses
+    /// and keeps the new ones in memory for the rest of the run.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn open_for(
+        file_path: &Path,
+        dry_run: bool,
+    ) -> Result<Self, redb::Error> {
+        if !dry_run {
+            return Self::open(file_path);
+        }
+        if file_path.exists() {
+            return Ok(Self {
+                db: redb::Database::open(file_path)?,
+                read_only: true,
+            });
+        }
+        let db = redb::Database::builder()
+            .create_with_backend(redb::backends::InMemoryBackend::new())?;
+        let write_txn = db.begin_write()?;
+        write_txn.open_table(KV_TABLE)?;
+        write_txn.commit()?;
+        Ok(Self {
+            db,
+            read_only: false,
+        })
+    }
+
+    pub(crate) async fn get_data<T>(
         self: &Arc<Self>,
         call_hash: &Arc<String>,
     ) -> anyhow::Result<Option<T>>
@@ -564,7 +1762,7 @@ impl CallCache {
         }
     }
 
-    async fn put_data<T>(
+    pub(crate) async fn put_data<T>(
         self: &Arc<Self>,
         call_hash: &Arc<String>,
         data: &Arc<T>,
@@ -572,6 +1770,9 @@ impl CallCache {
     where
         T: Serialize + Send + Sync + 'static,
     {
+        if self.read_only {
+            return Ok(());
+        }
         let cache = Arc::clone(self);
         let call_hash = Arc::clone(call_hash);
         let data = Arc::clone(data);
@@ -595,6 +1796,229 @@ impl CallCache {
         write_txn.commit()?;
         Ok(())
     }
+
+    /// The embedding of the input, computed by the API unless it is cached
+    /// for the same config of the API.
+    pub(crate) async fn get_embedding(
+        self: &Arc<Self>,
+        api: &dyn EmbeddingsAPI,
+        input: &str,
+    ) -> anyhow::Result<Vec<f64>> {
+        let call_hash = Arc::new(get_hash_value(format!(
+            "embedding:\n{}\n\n{}",
+            api.config_hash(),
+            get_hash_value(input),
+        )));
+        if let Some(embedding) = self.get_data::<Vec<f64>>(&call_hash).await? {
+            return Ok(embedding);
+        }
+        let embedding = Arc::new(
+            api.get_embedding(EmbeddingsArgs::builder().input(input).build())
+                .await?,
+        );
+        self.put_data(&call_hash, &embedding).await?;
+        Ok(Arc::into_inner(embedding).unwrap())
+    }
+
+    /// Drops the cached responses if the backend of the model has changed
+    /// since they were sampled with a seed, as they can no longer be
+    /// reproduced.
+    pub(crate) async fn check_fingerprint(
+        self: &Arc<Self>,
+        response: &ChatResponse,
+    ) -> anyhow::Result<()> {
+        let (Some(_), Some(fingerprint)) =
+            (response.seed, &response.system_fingerprint)
+        else {
+            return Ok(());
+        };
+        if self.read_only {
+            return Ok(());
+        }
+        let cache = Arc::clone(self);
+        let key = format!(
+            "{FINGERPRINT_KEY_PREFIX}{}",
+            response.model.as_deref().unwrap_or_default()
+        );
+        let fingerprint = fingerprint.clone();
+        spawn_blocking(move || cache.check_fingerprint_sync(&key, &fingerprint))
+            .await?
+    }
+
+    fn check_fingerprint_sync(
+        &self,
+        key: &str,
+        fingerprint: &str,
+    ) -> anyhow::Result<()> {
+        match self.get_data_sync::<String>(key)? {
+            Some(cached) if cached == fingerprint => return Ok(()),
+            Some(cached) => {
+                tracing::warn!(
+                    cached,
+                    fingerprint,
+                    "The backend of the model has changed, dropping the \
+                     cached responses"
+                );
+                let write_txn = self.db.begin_write()?;
+                write_txn.delete_table(KV_TABLE)?;
+                write_txn.open_table(KV_TABLE)?;
+                write_txn.commit()?;
+            },
+            None => {},
+        }
+        self.put_data_sync(key, &fingerprint)
+    }
+}
+
+#[tokio::test]
+async fn check_fingerprint_test() {
+    // In memory, as the file does not exist.
+    let cache = Arc::new(
+        CallCache::open_for(Path::new("/nonexistent/cache.redb"), true)
+            .unwrap(),
+    );
+    let response = |fingerprint: &str| ChatResponse {
+        message: Message {
+            role: Role::Assistant,
+            content: "".into(),
+        },
+        finish_reason: None,
+        usage: None,
+        model: Some("gpt-4o".to_string()),
+        request_id: None,
+        latency: Duration::ZERO,
+        seed: Some(42),
+        system_fingerprint: Some(fingerprint.to_string()),
+    };
+    let call_hash = Arc::new("call".to_string());
+    cache.put_data(&call_hash, &Arc::new(1)).await.unwrap();
+
+    cache.check_fingerprint(&response("fp_1")).await.unwrap();
+    cache.check_fingerprint(&response("fp_1")).await.unwrap();
+    assert_eq!(cache.get_data::<i32>(&call_hash).await.unwrap(), Some(1));
+    cache.check_fingerprint(&response("fp_2")).await.unwrap();
+    assert_eq!(cache.get_data::<i32>(&call_hash).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn get_embedding_cached_test() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingEmbeddingsAPI(AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl EmbeddingsAPI for CountingEmbeddingsAPI {
+        async fn get_embedding(
+            &self,
+            args: EmbeddingsArgs<'_>,
+        ) -> anyhow::Result<Vec<f64>> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(vec![args.input.len() as f64])
+        }
+
+        fn config_hash(&self) -> String { "counting".into() }
+    }
+
+    let cache = Arc::new(
+        CallCache::open_for(Path::new("/nonexistent/cache.redb"), true)
+            .unwrap(),
+    );
+    let api = CountingEmbeddingsAPI(AtomicUsize::new(0));
+    assert_eq!(cache.get_embedding(&api, "one").await.unwrap(), [3.0]);
+    assert_eq!(cache.get_embedding(&api, "one").await.unwrap(), [3.0]);
+    assert_eq!(cache.get_embedding(&api, "three").await.unwrap(), [5.0]);
+    assert_eq!(api.0.load(Ordering::Relaxed), 2);
+}
+
+#[cfg(feature = "integration-tests")]
+#[tokio::test]
+async fn run_structify_text_test() {
+    use crate::{aws_replay::fixture_path, record_replay::RecordReplayChatAPI};
+
+    let dir = std::env::temp_dir()
+        .join(format!("trakktor-structify-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("library.txt");
+    std::fs::copy(fixture_path("llm/structify.txt"), &file).unwrap();
+    // Re-recorded with `--llm-record` when the prompts change.
+    let chat_api =
+        RecordReplayChatAPI::replay(&fixture_path("llm/structify.json"))
+            .unwrap();
+    let out_dir = dir.join("out");
+    let args = StructifyText {
+        file: file.clone(),
+        out_dir: Some(out_dir.clone()),
+        ocr: Default::default(),
+        dry_run: false,
+        jobs: 1,
+        no_punctuate: false,
+        dialogue: false,
+        ignore_speakers: false,
+        section_per_turn: false,
+        sectioning: Sectioning::Model,
+        prompt_set: None,
+        language: None,
+        verbatim: false,
+        outline: Default::default(),
+    };
+    let res = run_structify_text(
+        &args,
+        &chat_api,
+        None,
+        None,
+        &CancellationToken::new(),
+    )
+    .await;
+    let path = |kind| output_path(Some(&out_dir), &file, kind);
+    let read = |kind| std::fs::read_to_string(path(kind));
+    let (text, final_text) =
+        (read(OutputKind::Text), read(OutputKind::Document));
+    let manifest = read(OutputKind::Manifest);
+    let diff_exists = path(OutputKind::Diff).exists();
+    std::fs::remove_dir_all(&dir).unwrap();
+    res.unwrap();
+
+    let text = text.unwrap();
+    assert_eq!(text.split("\n\n").count(), 5);
+    assert_eq!(
+        text.split_whitespace().join(" "),
+        std::fs::read_to_string(fixture_path("llm/structify.txt"))
+            .unwrap()
+            .split_whitespace()
+            .join(" ")
+    );
+    assert!(!diff_exists);
+    let titles = final_text
+        .unwrap()
+        .lines()
+        .filter_map(|l| l.strip_prefix("###### "))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        titles,
+        [
+            "The new reading room",
+            "Opening hours",
+            "Local history talks"
+        ]
+    );
+    let manifest: serde_json::Value =
+        serde_json::from_str(&manifest.unwrap()).unwrap();
+    let artifacts = manifest["artifacts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|a| (a["kind"].as_str().unwrap(), a["file"].as_str().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        artifacts,
+        [
+            ("text", "library.trakktor.text.md"),
+            ("summaries", "library.trakktor.summaries.md"),
+            ("section-summaries", "library.trakktor.section-summaries.md"),
+            ("document", "library.trakktor.document.md"),
+        ]
+    );
 }
 
 // const STRUCTIFY_PROMPT: &str = r#"""
@@ -625,36 +2049,3 @@ impl CallCache {
 // Make sure the output text maintains this format regardless of the input.
 
 // """#;
-
-const STRUCTIFY_PROMPT: &str = r#"""
-You are an AI assistant tasked with splitting any text input into paragraphs. Your goal is to format the text by inserting paragraph breaks at logical points without altering the original content in any way. Each paragraph should be separated by exactly one blank line. Follow these guidelines when breaking the text into paragraphs:
-
-- **Logical Divisions:** Insert paragraph breaks where there are shifts in topic, introduction of new ideas, changes in time or place, or natural pauses in the narrative.
-- **Preserve Original Formatting:** Do not change any words, punctuation, capitalization, or spacing within sentences. Maintain any existing paragraph or line breaks.
-- **Consistent Output Format:** Ensure the output text matches the input exactly in content, with the only changes being the insertion of paragraph breaks as specified.
-- **Special Cases:** For texts that are very short or lack clear division points, use your best judgment to determine if paragraph breaks are necessary.
-
-*Example input:*
-
-This is the first sentence. Here is some additional text. This is another idea.
-Now we are shifting to a new point. Another sentence follows this one. Conclusion here.
-
-*Example output:*
-
-This is the first sentence. Here is some additional text. This is another idea.
-
-Now we are shifting to a new point. Another sentence follows this one.
-
-Conclusion here.
-
-
-Ensure that the output text maintains this format regardless of the input, and remember not to alter the content in any way—only adjust the paragraph formatting.
-"""#;
-
-const SUMMARIZE_PARAGRAPH_PROMPT: &str = r#"""
-When given a text, provide a brief summary in one sentence no longer than 20
-words, using the same language as the original text. """#;
-
-const GET_SECTION_TITLE_PROMPT: &str = r#"""
-Your task is to generate a headline for the provided text. The headline should capture the main idea and key points clearly and concisely, using simple language. Make sure the headline is a single sentence, do not use quotation marks around it, and use the same language as the text.
-"""#;
@@ -0,0 +1,195 @@
+use itertools::Itertools;
+
+/// How far ahead the words of the texts are searched for the next match
+/// after a change.
+const SYNC_WINDOW: usize = 64;
+/// The unchanged words shown around a change.
+const CONTEXT_WORDS: usize = 6;
+
+/// A run of words of the original text replaced by the model, at the index
+/// of its first word.
+#[derive(Debug, PartialEq, Eq)]
+pub struct WordChange<'a> {
+    pub orig_pos: usize,
+    pub removed: &'a [&'a str],
+    pub added: &'a [&'a str],
+}
+
+/// Finds the words the model changed, ignoring the whitespace, i.e. the
+/// paragraph breaks. The texts are mostly the same, so after a change they
+/// are matched again at the nearest two words that are the same in both.
+pub fn diff_words<'a>(
+    orig: &'a [&'a str],
+    res: &'a [&'a str],
+) -> Vec<WordChange<'a>> {
+    let (mut i, mut j) = (0, 0);
+    let mut changes = Vec::new();
+    while i < orig.len() || j < res.len() {
+        if i < orig.len() && j < res.len() && orig[i] == res[j] {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let (di, dj) = find_sync(&orig[i..], &res[j..])
+            .unwrap_or((orig.len() - i, res.len() - j));
+        changes.push(WordChange {
+            orig_pos: i,
+            removed: &orig[i..i + di],
+            added: &res[j..j + dj],
+        });
+        i += di;
+        j += dj;
+    }
+    changes
+}
+
+/// The nearest offsets at which both texts continue with the same two
+/// words, so a common word like `the` does not match them at a wrong place,
+/// or else with the same word.
+fn find_sync(orig: &[&str], res: &[&str]) -> Option<(usize, usize)> {
+    [2, 1].into_iter().find_map(|run| {
+        (1..=SYNC_WINDOW * 2).find_map(|total| {
+            (0..=total.min(SYNC_WINDOW))
+                .map(|di| (di, total - di))
+                .filter(|&(_, dj)| dj <= SYNC_WINDOW)
+                .find(|&(di, dj)| {
+                    orig.get(di..di + run).is_some_and(|words| {
+                        Some(words) == res.get(dj..dj + run)
+                    })
+                })
+        })
+    })
+}
+
+/// The index in the original text of the word at `res_pos` of the model's
+/// text. A position inside a change is moved to the same offset of its
+/// removed words, the end of a change to their end.
+fn to_orig_pos(changes: &[WordChange], res_pos: usize) -> usize {
+    let mut shift = 0isize;
+    for change in changes {
+        let change_res_pos = change.orig_pos.saturating_add_signed(-shift);
+        if res_pos < change_res_pos {
+            break;
+        }
+        let offset = res_pos - change_res_pos;
+        if offset == change.added.len() {
+            return change.orig_pos + change.removed.len();
+        }
+        if offset < change.added.len() {
+            return change.orig_pos + offset.min(change.removed.len());
+        }
+        shift += change.removed.len() as isize - change.added.len() as isize;
+    }
+    res_pos.saturating_add_signed(shift)
+}
+
+/// The indices of the words of the original text the paragraphs of the
+/// model start at, in order. A paragraph of only the added words starts
+/// where the next one does.
+pub fn paragraph_starts(orig: &[&str], paragraphs: &[String]) -> Vec<usize> {
+    let res = paragraphs
+        .iter()
+        .flat_map(|p| p.split_whitespace())
+        .collect::<Vec<_>>();
+    let changes = diff_words(orig, &res);
+    let mut res_pos = 0;
+    let mut start = 0;
+    paragraphs
+        .iter()
+        .map(|paragraph| {
+            let paragraph_start = start;
+            res_pos += paragraph.split_whitespace().count();
+            start = to_orig_pos(&changes, res_pos).clamp(start, orig.len());
+            paragraph_start
+        })
+        .collect()
+}
+
+/// Rebuilds the paragraphs of the model from the words of the original
+/// text: only the breaks between the paragraphs are taken from the model,
+/// moved to the same places of the original, so none of its words change.
+pub fn reconstruct_paragraphs(
+    orig: &[&str],
+    paragraphs: &[String],
+) -> Vec<String> {
+    let starts = paragraph_starts(orig, paragraphs);
+    let ends = starts.iter().skip(1).copied().chain([orig.len()]);
+    starts
+        .iter()
+        .zip(ends)
+        .filter(|(start, end)| end > start)
+        .map(|(&start, end)| orig[start..end].join(" "))
+        .collect()
+}
+
+/// Formats the changes as a word diff with some context, `[-removed-]` and
+/// `{+added+}`, a change a hunk.
+pub fn format_word_diff(orig: &[&str], changes: &[WordChange]) -> String {
+    changes
+        .iter()
+        .map(|change| {
+            let start = change.orig_pos.saturating_sub(CONTEXT_WORDS);
+            let end = (change.orig_pos + change.removed.len() + CONTEXT_WORDS)
+                .min(orig.len());
+            let mut line = orig[start..change.orig_pos].join(" ");
+            if !change.removed.is_empty() {
+                line.push_str(&format!(" [-{}-]", change.removed.join(" ")));
+            }
+            if !change.added.is_empty() {
+                line.push_str(&format!(" {{+{}+}}", change.added.join(" ")));
+            }
+            let after = &orig[change.orig_pos + change.removed.len()..end];
+            if !after.is_empty() {
+                line.push(' ');
+                line.push_str(&after.join(" "));
+            }
+            format!("@@ word {} @@\n{}\n", change.orig_pos + 1, line.trim())
+        })
+        .join("\n")
+}
+
+#[test]
+fn diff_words_test() {
+    let orig = "the quick brown fox jumps over the lazy dog"
+        .split_whitespace()
+        .collect::<Vec<_>>();
+    let res = "the quick fox jumps\n\nover the very lazy cat"
+        .split_whitespace()
+        .collect::<Vec<_>>();
+    let changes = diff_words(&orig, &res);
+    assert_eq!(
+        changes
+            .iter()
+            .map(|c| (c.removed, c.added))
+            .collect::<Vec<_>>(),
+        [
+            (&["brown"][..], &[][..]),
+            (&[][..], &["very"][..]),
+            (&["dog"][..], &["cat"][..]),
+        ]
+    );
+    assert_eq!(
+        format_word_diff(&orig, &changes[..1]),
+        "@@ word 3 @@\nthe quick [-brown-] fox jumps over the lazy dog\n"
+    );
+    assert!(diff_words(&orig, &orig).is_empty());
+}
+
+#[test]
+fn reconstruct_paragraphs_test() {
+    let orig = "the quick brown fox jumps over the lazy dog"
+        .split_whitespace()
+        .collect::<Vec<_>>();
+    let paragraphs =
+        ["the quick fox", "jumps over", "the very lazy cat"].map(String::from);
+    assert_eq!(
+        reconstruct_paragraphs(&orig, &paragraphs),
+        ["the quick brown fox", "jumps over", "the lazy dog"]
+    );
+    // A break inside a change, and words the model dropped at the end.
+    let paragraphs = ["the quick red", "cat jumps over the"].map(String::from);
+    assert_eq!(
+        reconstruct_paragraphs(&orig, &paragraphs),
+        ["the quick brown", "fox jumps over the lazy dog"]
+    );
+}
@@ -0,0 +1,742 @@
+//! Reading of the input documents as plain text.
+//!
+//! PDF and DOCX files are converted to text with markdown headings as hints
+//! of the document structure. The conversion relies on external tools:
+//! `pdftohtml` (from poppler) for PDF and `unzip` for DOCX and EPUB.
+//!
+//! Images and scanned PDFs are recognized with `tesseract`, the pages of
+//! scanned PDFs are rendered with `pdftoppm` (from poppler) first.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::Args;
+use xmlparser::{ElementEnd, Token, Tokenizer};
+
+/// A PDF line is a heading hint if its font is this much larger than the
+/// font of the body text.
+const PDF_HEADING_FONT_RATIO: f32 = 1.2;
+const DOCX_DOCUMENT: &str = "word/document.xml";
+const IMAGE_EXTENSIONS: &[&str] =
+    &["png", "jpg", "jpeg", "tif", "tiff", "bmp", "gif", "webp"];
+/// Resolution the pages of scanned PDFs are rendered at for OCR.
+const OCR_PAGE_DPI: &str = "300";
+
+/// Options of the optical character recognition of scanned documents.
+#[derive(Args, Debug, Clone)]
+pub struct OcrOptions {
+    /// Recognize the text of PDF files with OCR even if they have a text
+    /// layer. PDF files without any text are always recognized with OCR.
+    #[arg(long)]
+    pub ocr: bool,
+    /// The languages of the scanned documents, as Tesseract language codes
+    /// joined with `+` (e.g. `eng+deu`).
+    #[arg(long, default_value = "eng")]
+    pub ocr_language: Box<str>,
+}
+
+impl Default for OcrOptions {
+    fn default() -> Self {
+        OcrOptions {
+            ocr: false,
+            ocr_language: "eng".into(),
+        }
+    }
+}
+
+/// Read the text of the file, converting it from PDF or DOCX, or
+/// recognizing it with OCR if needed.
+#[tracing::instrument(level = "debug")]
+pub async fn read_input_text(
+    path: &Path,
+    ocr: &OcrOptions,
+) -> anyhow::Result<String> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("pdf") if ocr.ocr => ocr_pdf(path, ocr).await,
+        Some("pdf") => {
+            let xml = run_tool(
+                "pdftohtml",
+                &["-xml", "-stdout", "-i", "-q"],
+                path,
+                &[],
+            )
+            .await?;
+            let text = pdf_xml_to_text(&xml)?;
+            if text.split_whitespace().next().is_some() {
+                return Ok(text);
+            }
+            tracing::info!("The PDF has no text, recognizing it with OCR.");
+            ocr_pdf(path, ocr).await
+        },
+        Some(ext) if IMAGE_EXTENSIONS.contains(&ext) => {
+            ocr_image(path, ocr).await
+        },
+        Some("docx") => {
+            let xml =
+                run_tool("unzip", &["-p"], path, &[DOCX_DOCUMENT]).await?;
+            docx_xml_to_text(&xml)
+        },
+        _ => Ok(tokio::fs::read_to_string(path).await?),
+    }
+}
+
+/// Run the tool on the file and return its output.
+async fn run_tool(
+    tool: &str,
+    args: &[&str],
+    path: &Path,
+    args_after: &[&str],
+) -> anyhow::Result<String> {
+    let output = tokio::process::Command::new(tool)
+        .args(args)
+        .arg(path)
+        .args(args_after)
+        .output()
+        .await
+        .with_context(|| format!("Failed to run {tool}, is it installed?"))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{tool} failed on {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+async fn ocr_image(path: &Path, ocr: &OcrOptions) -> anyhow::Result<String> {
+    let text =
+        run_tool("tesseract", &[], path, &["stdout", "-l", &ocr.ocr_language])
+            .await?;
+    Ok(join_ocr_lines(&text))
+}
+
+/// Render the pages of the PDF to images and recognize them one by one.
+#[tracing::instrument(level = "debug")]
+async fn ocr_pdf(path: &Path, ocr: &OcrOptions) -> anyhow::Result<String> {
+    let pages_dir = std::env::temp_dir()
+        .join(format!("trakktor-ocr-{}", uuid::Uuid::new_v4()));
+    tokio::fs::create_dir_all(&pages_dir).await?;
+    let res = ocr_pdf_pages(path, ocr, &pages_dir).await;
+    if let Err(err) = tokio::fs::remove_dir_all(&pages_dir).await {
+        tracing::warn!("Failed to remove {}: {err}", pages_dir.display());
+    }
+    res
+}
+
+async fn ocr_pdf_pages(
+    path: &Path,
+    ocr: &OcrOptions,
+    pages_dir: &Path,
+) -> anyhow::Result<String> {
+    let prefix = pages_dir.join("page");
+    let prefix = prefix
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Bad temporary directory"))?;
+    run_tool("pdftoppm", &["-r", OCR_PAGE_DPI, "-png"], path, &[prefix])
+        .await?;
+
+    // The page numbers are zero-padded to the same width.
+    let mut pages: Vec<PathBuf> = vec![];
+    let mut entries = tokio::fs::read_dir(pages_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        pages.push(entry.path());
+    }
+    pages.sort();
+
+    let mut text = vec![];
+    for (i, page) in pages.iter().enumerate() {
+        tracing::info!("Recognizing page {} of {}.", i + 1, pages.len());
+        let page_text = ocr_image(page, ocr).await?;
+        if !page_text.is_empty() {
+            text.push(page_text);
+        }
+    }
+    Ok(text.join("\n\n"))
+}
+
+/// Join the lines of the recognized text into paragraphs, which Tesseract
+/// separates with blank lines.
+fn join_ocr_lines(text: &str) -> String {
+    let trim_word = |word: &str| {
+        word.trim_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase()
+    };
+    // The words with a hyphen within a line are spelled so in the source.
+    let hyphenated = text
+        .split_whitespace()
+        .map(trim_word)
+        .filter(|word| word.contains('-'))
+        .collect::<HashSet<_>>();
+
+    let mut paragraphs = vec![];
+    let mut paragraph = String::new();
+    for line in text.lines().map(str::trim).chain([""]) {
+        if line.is_empty() {
+            if !paragraph.is_empty() {
+                paragraphs.push(std::mem::take(&mut paragraph));
+            }
+            continue;
+        }
+        // Words broken at the end of the line are joined back, without the
+        // hyphen if the word continues in lowercase and is not spelled with
+        // it elsewhere in the text.
+        match paragraph.strip_suffix('-') {
+            Some(p) if line.starts_with(char::is_lowercase) => {
+                let head = p.rsplit(char::is_whitespace).next().unwrap_or(p);
+                let tail = line.split_whitespace().next().unwrap_or(line);
+                let word = trim_word(&format!("{head}-{tail}"));
+                if !hyphenated.contains(&word) {
+                    paragraph.truncate(p.len());
+                }
+            },
+            Some(_) => {},
+            None if !paragraph.is_empty() => paragraph.push(' '),
+            None => {},
+        }
+        paragraph.push_str(line);
+    }
+    paragraphs.join("\n\n")
+}
+
+#[test]
+fn join_ocr_lines_test() {
+    let text = "The quick brown fox jum-\nps over the\n  lazy \
+                dog.\n\n\nWell-\nKnown facts.\n\x0c";
+    assert_eq!(
+        join_ocr_lines(text),
+        "The quick brown fox jumps over the lazy dog.\n\nWell-Known facts."
+    );
+
+    let text =
+        "A well-known fact is well-\nknown, the other one is\nun-\nknown.";
+    assert_eq!(
+        join_ocr_lines(text),
+        "A well-known fact is well-known, the other one is unknown."
+    );
+}
+
+/// Replace the predefined XML entities and character references.
+fn unescape_xml(text: &str) -> String { unescape_entities(text, |_| None) }
+
+/// Replace the XML entities, character references, and the common HTML
+/// entities.
+pub(crate) fn unescape_html(text: &str) -> String {
+    unescape_entities(text, |entity| match entity {
+        "mdash" => Some('—'),
+        "ndash" => Some('–'),
+        "hellip" => Some('…'),
+        "lsquo" => Some('‘'),
+        "rsquo" => Some('’'),
+        "ldquo" => Some('“'),
+        "rdquo" => Some('”'),
+        "laquo" => Some('«'),
+        "raquo" => Some('»'),
+        "middot" => Some('·'),
+        "bull" => Some('•'),
+        "copy" => Some('©'),
+        "reg" => Some('®'),
+        "trade" => Some('™'),
+        _ => None,
+    })
+}
+
+fn unescape_entities(text: &str, named: fn(&str) -> Option<char>) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        res.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let ch = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => named(entity).or_else(|| {
+                entity
+                    .strip_prefix("#x")
+                    .map(|h| u32::from_str_radix(h, 16))
+                    .or_else(|| entity.strip_prefix('#').map(str::parse))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32)
+            }),
+        };
+        match ch {
+            Some(ch) => {
+                res.push(ch);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                res.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+pub(crate) fn heading_prefix(level: usize) -> String {
+    "#".repeat(level.clamp(1, 6))
+}
+
+/// Convert the main part of a DOCX document to text. Paragraphs with the
+/// title and heading styles become markdown headings.
+fn docx_xml_to_text(xml: &str) -> anyhow::Result<String> {
+    let mut paragraphs = vec![];
+    let mut paragraph = String::new();
+    let mut heading_level = None;
+    let mut element = "";
+    let mut in_text = false;
+
+    for token in Tokenizer::from(xml) {
+        match token? {
+            Token::ElementStart { local, .. } => {
+                element = local.as_str();
+                match element {
+                    "p" => {
+                        paragraph.clear();
+                        heading_level = None;
+                    },
+                    "tab" => paragraph.push('\t'),
+                    "br" => paragraph.push('\n'),
+                    _ => {},
+                }
+            },
+            Token::Attribute { local, value, .. }
+                if element == "pStyle" && local.as_str() == "val" =>
+            {
+                let style = value.as_str().to_ascii_lowercase();
+                heading_level = if style == "title" {
+                    Some(1)
+                } else {
+                    style
+                        .strip_prefix("heading")
+                        .and_then(|l| l.parse::<usize>().ok())
+                        .map(|l| l + 1)
+                };
+            },
+            Token::ElementEnd { end, .. } => match end {
+                ElementEnd::Open => in_text = element == "t",
+                ElementEnd::Close(_, local) => match local.as_str() {
+                    "t" => in_text = false,
+                    "p" => {
+                        let text = paragraph.trim();
+                        if !text.is_empty() {
+                            paragraphs.push(match heading_level {
+                                Some(l) => {
+                                    format!("{} {}", heading_prefix(l), text)
+                                },
+                                None => text.to_string(),
+                            });
+                        }
+                    },
+                    _ => {},
+                },
+                ElementEnd::Empty => {},
+            },
+            Token::Text { text } if in_text => {
+                paragraph.push_str(&unescape_xml(text.as_str()))
+            },
+            _ => {},
+        }
+    }
+
+    Ok(paragraphs.join("\n\n"))
+}
+
+#[test]
+fn docx_xml_to_text_test() -> anyhow::Result<()> {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+<w:body>
+<w:p><w:pPr><w:pStyle w:val="Heading1"/></w:pPr><w:r><w:t>Results</w:t></w:r></w:p>
+<w:p><w:r><w:t xml:space="preserve">Sales &amp; </w:t></w:r><w:r><w:t>costs.</w:t></w:r></w:p>
+<w:p></w:p>
+</w:body>
+</w:document>"#;
+    assert_eq!(docx_xml_to_text(xml)?, "## Results\n\nSales & costs.");
+    Ok(())
+}
+
+/// Convert the `pdftohtml -xml` output to text. Lines set in a font notably
+/// larger than the body text become markdown headings.
+fn pdf_xml_to_text(xml: &str) -> anyhow::Result<String> {
+    let mut font_sizes: HashMap<&str, f32> = HashMap::new();
+    let mut lines: Vec<(Option<&str>, String)> = vec![];
+    let mut element = "";
+    let mut font_id = None;
+    let mut size = None;
+    let mut in_text = false;
+
+    for token in Tokenizer::from(xml) {
+        match token? {
+            Token::ElementStart { local, .. } => {
+                element = local.as_str();
+                if element == "text" {
+                    lines.push((None, String::new()));
+                }
+            },
+            Token::Attribute { local, value, .. } => {
+                match (element, local.as_str()) {
+                    ("fontspec", "id") => font_id = Some(value.as_str()),
+                    ("fontspec", "size") => size = value.as_str().parse().ok(),
+                    ("text", "font") => {
+                        if let Some(line) = lines.last_mut() {
+                            line.0 = Some(value.as_str());
+                        }
+                    },
+                    _ => {},
+                }
+            },
+            Token::ElementEnd { end, .. } => match end {
+                ElementEnd::Open if element == "text" => in_text = true,
+                ElementEnd::Close(_, local) if local.as_str() == "text" => {
+                    in_text = false
+                },
+                ElementEnd::Empty if element == "fontspec" => {
+                    if let (Some(id), Some(size)) =
+                        (font_id.take(), size.take())
+                    {
+                        font_sizes.insert(id, size);
+                    }
+                },
+                _ => {},
+            },
+            Token::Text { text } if in_text => {
+                if let Some(line) = lines.last_mut() {
+                    line.1.push_str(&unescape_xml(text.as_str()));
+                }
+            },
+            _ => {},
+        }
+    }
+
+    let line_size = |font: Option<&str>| {
+        font.and_then(|f| font_sizes.get(f)).copied().unwrap_or(0.0)
+    };
+
+    // The body font is the one most of the text is set in.
+    let mut text_per_size: HashMap<u32, usize> = HashMap::new();
+    for (font, text) in &lines {
+        *text_per_size.entry(line_size(*font).to_bits()).or_default() +=
+            text.len();
+    }
+    let body_size = text_per_size
+        .into_iter()
+        .max_by_key(|(_, len)| *len)
+        .map(|(size, _)| f32::from_bits(size))
+        .unwrap_or(0.0);
+
+    let mut res = String::new();
+    for (font, text) in &lines {
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        if body_size > 0.0 &&
+            line_size(*font) >= body_size * PDF_HEADING_FONT_RATIO
+        {
+            res.push_str(&format!("\n{} {}\n\n", heading_prefix(2), text));
+        } else {
+            res.push_str(text);
+            res.push('\n');
+        }
+    }
+
+    Ok(res.trim().to_string())
+}
+
+#[test]
+fn pdf_xml_to_text_test() -> anyhow::Result<()> {
+    let xml = r##"<?xml version="1.0" encoding="UTF-8"?>
+<pdf2xml producer="poppler" version="22.02.0">
+<page number="1" position="absolute" top="0" left="0" height="1188" width="918">
+	<fontspec id="0" size="24" family="Times" color="#000000"/>
+	<fontspec id="1" size="12" family="Times" color="#000000"/>
+<text top="100" left="100" width="300" height="30" font="0"><b>Annual report</b></text>
+<text top="150" left="100" width="600" height="15" font="1">The year was good &amp; the</text>
+<text top="170" left="100" width="600" height="15" font="1">costs were low.</text>
+</page>
+</pdf2xml>"##;
+    assert_eq!(
+        pdf_xml_to_text(xml)?,
+        "## Annual report\n\nThe year was good & the\ncosts were low."
+    );
+    Ok(())
+}
+
+const EPUB_CONTAINER: &str = "META-INF/container.xml";
+
+/// A chapter of a book, as defined by the reading order of the EPUB.
+#[derive(Debug)]
+pub struct Chapter {
+    pub title: Option<String>,
+    /// Text of the chapter with markdown headings as structure hints.
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub struct Book {
+    pub title: Option<String>,
+    pub chapters: Vec<Chapter>,
+}
+
+pub fn is_epub(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("epub"))
+}
+
+/// Read the chapters of an EPUB book in the reading order. Documents of the
+/// book without any text (covers, separators) are skipped.
+#[tracing::instrument(level = "debug")]
+pub async fn read_epub(path: &Path) -> anyhow::Result<Book> {
+    let container = run_tool("unzip", &["-p"], path, &[EPUB_CONTAINER]).await?;
+    let opf_path = parse_epub_container(&container)?;
+    let opf = run_tool("unzip", &["-p"], path, &[&opf_path]).await?;
+    let package = parse_epub_package(&opf)?;
+    let base_dir = opf_path.rsplit_once('/').map_or("", |(dir, _)| dir);
+
+    let mut chapters = vec![];
+    for href in &package.spine {
+        let member = resolve_epub_href(base_dir, href);
+        let xhtml = run_tool("unzip", &["-p"], path, &[&member]).await?;
+        let chapter = xhtml_to_chapter(&xhtml)
+            .with_context(|| format!("Failed to read {member}"))?;
+        if chapter.text.is_empty() {
+            tracing::debug!(member, "Skipping document without text.");
+            continue;
+        }
+        chapters.push(chapter);
+    }
+
+    Ok(Book {
+        title: package.title,
+        chapters,
+    })
+}
+
+/// Find the path of the package document in the EPUB container file.
+fn parse_epub_container(xml: &str) -> anyhow::Result<String> {
+    let mut element = "";
+    for token in Tokenizer::from(xml) {
+        match token? {
+            Token::ElementStart { local, .. } => element = local.as_str(),
+            Token::Attribute { local, value, .. }
+                if element == "rootfile" && local.as_str() == "full-path" =>
+            {
+                return Ok(unescape_xml(value.as_str()));
+            },
+            _ => {},
+        }
+    }
+    anyhow::bail!("No package document found in the EPUB container")
+}
+
+struct EpubPackage {
+    title: Option<String>,
+    /// Paths of the documents in the reading order, relative to the package
+    /// document.
+    spine: Vec<String>,
+}
+
+fn parse_epub_package(xml: &str) -> anyhow::Result<EpubPackage> {
+    let mut title = None;
+    let mut manifest: HashMap<String, String> = HashMap::new();
+    let mut spine_ids = vec![];
+    let mut element = "";
+    let mut item_id = None;
+    let mut item_href = None;
+    let mut in_title = false;
+
+    for token in Tokenizer::from(xml) {
+        match token? {
+            Token::ElementStart { local, .. } => element = local.as_str(),
+            Token::Attribute { local, value, .. } => {
+                let value = unescape_xml(value.as_str());
+                match (element, local.as_str()) {
+                    ("item", "id") => item_id = Some(value),
+                    ("item", "href") => item_href = Some(value),
+                    ("itemref", "idref") => spine_ids.push(value),
+                    _ => {},
+                }
+            },
+            Token::ElementEnd { end, .. } => match end {
+                ElementEnd::Open | ElementEnd::Empty if element == "item" => {
+                    if let (Some(id), Some(href)) =
+                        (item_id.take(), item_href.take())
+                    {
+                        manifest.insert(id, href);
+                    }
+                },
+                ElementEnd::Open => in_title = element == "title",
+                ElementEnd::Close(..) => in_title = false,
+                _ => {},
+            },
+            Token::Text { text } if in_title && title.is_none() => {
+                title = Some(unescape_xml(text.as_str()).trim().to_string());
+            },
+            _ => {},
+        }
+    }
+
+    let spine = spine_ids
+        .into_iter()
+        .map(|id| {
+            manifest.remove(&id).ok_or_else(|| {
+                anyhow::anyhow!("Spine item {id} is missing in the manifest")
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(EpubPackage { title, spine })
+}
+
+/// Resolve the path of a document of the book relative to the directory of
+/// the package document.
+fn resolve_epub_href(base_dir: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let href = percent_encoding::percent_decode_str(href).decode_utf8_lossy();
+    let mut segments: Vec<&str> =
+        base_dir.split('/').filter(|s| !s.is_empty()).collect();
+    for segment in href.split('/') {
+        match segment {
+            "" | "." => {},
+            ".." => {
+                segments.pop();
+            },
+            _ => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+#[test]
+fn resolve_epub_href_test() {
+    assert_eq!(resolve_epub_href("OEBPS", "ch1.xhtml"), "OEBPS/ch1.xhtml");
+    assert_eq!(
+        resolve_epub_href("OEBPS/text", "../Text/ch%202.xhtml#p1"),
+        "OEBPS/Text/ch 2.xhtml"
+    );
+    assert_eq!(resolve_epub_href("", "ch1.xhtml"), "ch1.xhtml");
+}
+
+fn heading_level(element: &str) -> Option<usize> {
+    element
+        .strip_prefix('h')
+        .and_then(|l| l.parse().ok())
+        .filter(|l| (1..=6).contains(l))
+}
+
+/// Convert a document of the book to text. The first heading of the
+/// document is used as the title of the chapter.
+fn xhtml_to_chapter(xhtml: &str) -> anyhow::Result<Chapter> {
+    const SKIPPED: &[&str] = &["head", "script", "style"];
+    const BLOCKS: &[&str] =
+        &["p", "div", "li", "blockquote", "section", "br", "tr", "pre"];
+
+    let mut title = None;
+    let mut paragraphs: Vec<String> = vec![];
+    let mut paragraph = String::new();
+    let mut heading = None;
+    let mut element = "";
+    let mut skip_depth = 0;
+
+    let mut flush = |paragraph: &mut String, heading: Option<usize>| {
+        let text = paragraph.split_whitespace().collect::<Vec<_>>().join(" ");
+        paragraph.clear();
+        if text.is_empty() {
+            return;
+        }
+        match heading {
+            Some(level) => {
+                if title.is_none() {
+                    title = Some(text.clone());
+                }
+                paragraphs.push(format!("{} {}", heading_prefix(level), text));
+            },
+            None => paragraphs.push(text),
+        }
+    };
+
+    for token in Tokenizer::from(xhtml) {
+        match token? {
+            Token::ElementStart { local, .. } => {
+                element = local.as_str();
+                if SKIPPED.contains(&element) {
+                    skip_depth += 1;
+                } else if let Some(level) = heading_level(element) {
+                    flush(&mut paragraph, heading);
+                    heading = Some(level);
+                } else if BLOCKS.contains(&element) {
+                    flush(&mut paragraph, heading);
+                }
+            },
+            Token::ElementEnd { end, .. } => {
+                let closed = match end {
+                    ElementEnd::Open => None,
+                    ElementEnd::Close(_, local) => Some(local.as_str()),
+                    ElementEnd::Empty => Some(element),
+                };
+                let Some(closed) = closed else {
+                    continue;
+                };
+                if SKIPPED.contains(&closed) {
+                    skip_depth -= 1;
+                } else if heading_level(closed).is_some() {
+                    flush(&mut paragraph, heading);
+                    heading = None;
+                } else if BLOCKS.contains(&closed) {
+                    flush(&mut paragraph, heading);
+                }
+            },
+            Token::Text { text } if skip_depth == 0 => {
+                paragraph.push_str(&unescape_xml(text.as_str()));
+                paragraph.push(' ');
+            },
+            _ => {},
+        }
+    }
+    flush(&mut paragraph, heading);
+
+    Ok(Chapter {
+        title,
+        text: paragraphs.join("\n\n"),
+    })
+}
+
+#[test]
+fn xhtml_to_chapter_test() -> anyhow::Result<()> {
+    let xhtml = r#"<?xml version="1.0" encoding="utf-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head><title>Book</title><style>p { margin: 0; }</style></head>
+<body>
+<h1>Chapter <em>One</em></h1>
+<p>It was a <b>dark</b> and
+stormy night&#8230;</p>
+<p/>
+<p>The end.</p>
+</body>
+</html>"#;
+    let chapter = xhtml_to_chapter(xhtml)?;
+    assert_eq!(chapter.title.as_deref(), Some("Chapter One"));
+    assert_eq!(
+        chapter.text,
+        "# Chapter One\n\nIt was a dark and stormy night…\n\nThe end."
+    );
+    Ok(())
+}
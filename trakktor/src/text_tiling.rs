@@ -0,0 +1,114 @@
+//! Splits a sequence of paragraphs into sections by their embeddings, like
+//! TextTiling: the topic changes where the paragraphs before a gap are the
+//! least similar to the ones after it.
+
+/// The paragraphs on each side of a gap compared with each other.
+const WINDOW: usize = 2;
+/// The fewest paragraphs of a section.
+const MIN_SECTION: usize = 2;
+
+fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+/// The sum of the embeddings, which points the same way as their mean.
+fn sum(embeddings: &[Vec<f64>]) -> Vec<f64> {
+    let mut res = vec![0.0; embeddings.first().map_or(0, Vec::len)];
+    for embedding in embeddings {
+        for (r, x) in res.iter_mut().zip(embedding) {
+            *r += x;
+        }
+    }
+    res
+}
+
+/// How deep the similarity at each gap is below the peaks around it.
+fn depth_scores(similarities: &[f64]) -> Vec<f64> {
+    (0..similarities.len())
+        .map(|i| {
+            let s = similarities[i];
+            let mut left = s;
+            for &l in similarities[..i].iter().rev() {
+                if l < left {
+                    break;
+                }
+                left = l;
+            }
+            let mut right = s;
+            for &r in &similarities[i + 1..] {
+                if r < right {
+                    break;
+                }
+                right = r;
+            }
+            (left - s) + (right - s)
+        })
+        .collect()
+}
+
+/// The indices of the paragraphs that start a new section, after the first
+/// one. A section starts at the gaps deeper than the mean depth less half
+/// its standard deviation, the deepest first, keeping the sections at
+/// least [`MIN_SECTION`] paragraphs long.
+pub fn section_starts(embeddings: &[Vec<f64>]) -> Vec<usize> {
+    let n = embeddings.len();
+    if n < MIN_SECTION * 2 {
+        return vec![];
+    }
+    // The gap `i` is before the paragraph `i + 1`.
+    let similarities = (1..n)
+        .map(|i| {
+            let before = sum(&embeddings[i.saturating_sub(WINDOW)..i]);
+            let after = sum(&embeddings[i..(i + WINDOW).min(n)]);
+            cosine_similarity(&before, &after)
+        })
+        .collect::<Vec<_>>();
+    let depths = depth_scores(&similarities);
+    let mean = depths.iter().sum::<f64>() / depths.len() as f64;
+    let variance = depths.iter().map(|d| (d - mean).powi(2)).sum::<f64>() /
+        depths.len() as f64;
+    let cutoff = mean - variance.sqrt() / 2.0;
+
+    let mut gaps = (0..depths.len())
+        .filter(|&i| depths[i] > 0.0 && depths[i] > cutoff)
+        .collect::<Vec<_>>();
+    gaps.sort_by(|&a, &b| depths[b].total_cmp(&depths[a]));
+    let mut starts: Vec<usize> = vec![];
+    for start in gaps.into_iter().map(|i| i + 1) {
+        let fits = start >= MIN_SECTION &&
+            n - start >= MIN_SECTION &&
+            starts.iter().all(|&s| s.abs_diff(start) >= MIN_SECTION);
+        if fits {
+            starts.push(start);
+        }
+    }
+    starts.sort();
+    starts
+}
+
+#[test]
+fn section_starts_test() {
+    let topic = |i: usize| {
+        let mut embedding = vec![0.1; 4];
+        embedding[i] = 1.0;
+        embedding
+    };
+    let embeddings = [0, 0, 0, 1, 1, 1, 1, 2, 2].map(topic);
+    assert_eq!(section_starts(&embeddings), [3, 7]);
+    // Too short to split.
+    assert_eq!(section_starts(&embeddings[..3]), [] as [usize; 0]);
+    // One topic.
+    assert_eq!(
+        section_starts(&[0, 0, 0, 0, 0].map(topic)),
+        [] as [usize; 0]
+    );
+}
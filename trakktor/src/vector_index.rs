@@ -0,0 +1,714 @@
+//! Vector indexes of document corpora.
+//!
+//! An index is a directory, as written by the indexing job:
+//! - `index.json`: the model and the statistics of the index;
+//! - `chunks.jsonl`: the indexed chunks, with their document, text and
+//!   metadata;
+//! - `embeddings.npy`: the embeddings of the chunks, a float32 matrix.
+//!
+//! An index can be exported into a single archive file, carrying the schema
+//! version of the format, and imported from it on another machine without
+//! computing the embeddings again, and pushed into the configured vector
+//! store.
+//!
+//! The index keeps the hashes of the documents it was computed from. An
+//! update of the corpus is indexed into a partial index of only the changed
+//! documents, which is then applied onto the previous index.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand, ValueHint};
+use serde::{Deserialize, Serialize};
+
+use crate::vector_store::{VectorEntry, VectorStoreConfig};
+
+#[derive(Parser, Debug)]
+pub struct IndexArgs {
+    #[command(subcommand)]
+    pub command: IndexCommands,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum IndexCommands {
+    /// Export an index directory into a portable archive.
+    Export(ExportIndex),
+    /// Import an index from an archive into a directory.
+    Import(ImportIndex),
+    /// Store the chunks of an index in the vector store, replacing the
+    /// chunks of the same documents.
+    Push(PushIndex),
+    /// Apply a partial index of the changed documents onto the previous
+    /// index of the corpus.
+    Apply(ApplyIndex),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ExportIndex {
+    /// The index directory, e.g. the downloaded output of an indexing job.
+    #[arg(value_hint = ValueHint::DirPath)]
+    pub dir: PathBuf,
+    /// The archive to write. Defaults to the directory name with the
+    /// `.trkindex` extension.
+    #[arg(long, short, value_hint = ValueHint::FilePath)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ImportIndex {
+    /// The archive to import.
+    #[arg(value_hint = ValueHint::FilePath)]
+    pub archive: PathBuf,
+    /// The directory to write the index to. Defaults to the archive name
+    /// without the extension.
+    #[arg(long, short, value_hint = ValueHint::DirPath)]
+    pub output_dir: Option<PathBuf>,
+    /// Overwrite the index files if they already exist.
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct PushIndex {
+    /// The index directory.
+    #[arg(value_hint = ValueHint::DirPath)]
+    pub dir: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ApplyIndex {
+    /// The previous index directory, updated in place.
+    #[arg(value_hint = ValueHint::DirPath)]
+    pub dir: PathBuf,
+    /// The partial index directory, the downloaded output of an indexing
+    /// job run with `--update`.
+    #[arg(value_hint = ValueHint::DirPath)]
+    pub update: PathBuf,
+    /// Also apply the changes to the vector store: store the chunks of the
+    /// changed documents and delete the removed documents.
+    #[arg(long)]
+    pub push: bool,
+}
+
+const INFO_FILE: &str = "index.json";
+const CHUNKS_FILE: &str = "chunks.jsonl";
+const EMBEDDINGS_FILE: &str = "embeddings.npy";
+const ARCHIVE_EXT: &str = "trkindex";
+const ARCHIVE_MAGIC: &[u8] = b"TRKINDEX";
+/// Version of the archive format, bumped on incompatible changes.
+const ARCHIVE_SCHEMA_VERSION: u32 = 1;
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexInfo {
+    /// The sentence embeddings model the index was computed with.
+    pub model: String,
+    pub dimensions: usize,
+    /// Whether the embeddings are normalized to unit length.
+    #[serde(default)]
+    pub normalized: bool,
+    #[serde(default)]
+    pub chunk_words: Option<u32>,
+    #[serde(default)]
+    pub chunk_overlap: Option<u32>,
+    /// The metadata fields stored with the chunks.
+    #[serde(default)]
+    pub metadata: Vec<String>,
+    #[serde(default)]
+    pub documents: usize,
+    #[serde(default)]
+    pub chunks: usize,
+    /// The documents that could not be indexed.
+    #[serde(default)]
+    pub skipped: Vec<String>,
+    /// The content hashes of all the documents of the corpus, by document.
+    #[serde(default)]
+    pub document_hashes: BTreeMap<String, String>,
+    /// Whether the index only holds the chunks of the documents changed
+    /// since the previous index.
+    #[serde(default)]
+    pub partial: bool,
+}
+
+impl IndexInfo {
+    /// Loads only the info of the index in the directory.
+    pub async fn load(dir: &Path) -> anyhow::Result<Self> {
+        let path = dir.join(INFO_FILE);
+        let data = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+}
+
+/// The documents that changed between the hashes of the previous and the
+/// current corpus: the added or modified ones, and the removed ones.
+pub fn diff_documents(
+    previous: &BTreeMap<String, String>,
+    current: &BTreeMap<String, String>,
+) -> (Vec<String>, Vec<String>) {
+    let changed = current
+        .iter()
+        .filter(|(document, hash)| previous.get(*document) != Some(*hash))
+        .map(|(document, _)| document.clone())
+        .collect();
+    let removed = previous
+        .keys()
+        .filter(|document| !current.contains_key(*document))
+        .cloned()
+        .collect();
+    (changed, removed)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IndexChunk {
+    pub document: String,
+    pub chunk: u32,
+    pub text: String,
+    /// The heading of the document section the chunk is in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section: Option<String>,
+    /// The speakers of a transcript chunk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speaker: Option<String>,
+    /// The start of a transcript chunk in the audio, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<f64>,
+    /// The end of a transcript chunk in the audio, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorIndex {
+    pub info: IndexInfo,
+    pub chunks: Vec<IndexChunk>,
+    /// The embeddings of the chunks, row by row.
+    #[serde(with = "f32_bytes")]
+    pub embeddings: Vec<f32>,
+}
+
+impl VectorIndex {
+    #[tracing::instrument(level = "debug")]
+    pub async fn load(dir: &Path) -> anyhow::Result<Self> {
+        let read = |name: &str| {
+            let path = dir.join(name);
+            async move {
+                tokio::fs::read(&path).await.with_context(|| {
+                    format!("Failed to read {}", path.display())
+                })
+            }
+        };
+
+        let info = IndexInfo::load(dir).await?;
+        let chunks = String::from_utf8(read(CHUNKS_FILE).await?)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<IndexChunk>, _>>()?;
+        let (rows, columns, embeddings) =
+            parse_npy(&read(EMBEDDINGS_FILE).await?)?;
+        if rows != chunks.len() || columns != info.dimensions {
+            bail!(
+                "The embeddings have the shape ({rows}, {columns}), expected \
+                 ({}, {})",
+                chunks.len(),
+                info.dimensions
+            );
+        }
+
+        Ok(Self {
+            info,
+            chunks,
+            embeddings,
+        })
+    }
+
+    pub async fn save(&self, dir: &Path) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(dir).await?;
+        tokio::fs::write(
+            dir.join(INFO_FILE),
+            serde_json::to_vec_pretty(&self.info)?,
+        )
+        .await?;
+        let mut chunks = String::new();
+        for chunk in &self.chunks {
+            chunks.push_str(&serde_json::to_string(chunk)?);
+            chunks.push('\n');
+        }
+        tokio::fs::write(dir.join(CHUNKS_FILE), chunks).await?;
+        tokio::fs::write(
+            dir.join(EMBEDDINGS_FILE),
+            write_npy(
+                self.chunks.len(),
+                self.info.dimensions,
+                &self.embeddings,
+            ),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// The chunks with their embeddings.
+    pub fn entries(&self) -> Vec<VectorEntry> {
+        self.chunks
+            .iter()
+            .zip(self.embeddings.chunks_exact(self.info.dimensions))
+            .map(|(chunk, embedding)| VectorEntry {
+                chunk: chunk.clone(),
+                embedding: embedding.to_vec(),
+            })
+            .collect()
+    }
+
+    /// Applies a partial index onto this one: the chunks of the documents
+    /// changed or removed since are replaced with the chunks of the update.
+    /// Returns the changed and the removed documents.
+    pub fn apply_update(
+        &mut self,
+        update: VectorIndex,
+    ) -> anyhow::Result<(Vec<String>, Vec<String>)> {
+        if self.info.document_hashes.is_empty() {
+            bail!(
+                "The index has no document hashes, it was computed by an \
+                 older version"
+            );
+        }
+        if update.info.model != self.info.model ||
+            update.info.dimensions != self.info.dimensions
+        {
+            bail!(
+                "The update was computed with {} ({} dimensions), the index \
+                 with {} ({} dimensions)",
+                update.info.model,
+                update.info.dimensions,
+                self.info.model,
+                self.info.dimensions
+            );
+        }
+
+        let (changed, removed) = diff_documents(
+            &self.info.document_hashes,
+            &update.info.document_hashes,
+        );
+        let replaced = changed
+            .iter()
+            .chain(&removed)
+            .map(String::as_str)
+            .collect::<HashSet<_>>();
+        if let Some(chunk) = update
+            .chunks
+            .iter()
+            .find(|c| !replaced.contains(c.document.as_str()))
+        {
+            bail!(
+                "The update has chunks of the unchanged document {}",
+                chunk.document
+            );
+        }
+
+        let dimensions = self.info.dimensions;
+        let (mut chunks, mut embeddings) = (vec![], vec![]);
+        for (chunk, embedding) in std::mem::take(&mut self.chunks)
+            .into_iter()
+            .zip(self.embeddings.chunks_exact(dimensions))
+            .filter(|(c, _)| !replaced.contains(c.document.as_str()))
+        {
+            chunks.push(chunk);
+            embeddings.extend_from_slice(embedding);
+        }
+        chunks.extend(update.chunks);
+        embeddings.extend(update.embeddings);
+
+        self.info.skipped.retain(|d| !replaced.contains(d.as_str()));
+        self.info.skipped.extend(update.info.skipped);
+        self.info.document_hashes = update.info.document_hashes;
+        self.info.documents =
+            self.info.document_hashes.len() - self.info.skipped.len();
+        self.info.chunks = chunks.len();
+        self.info.partial = false;
+        self.chunks = chunks;
+        self.embeddings = embeddings;
+
+        Ok((changed, removed))
+    }
+
+    /// Encodes the index into an archive.
+    pub fn to_archive(&self) -> Vec<u8> {
+        let mut data = ARCHIVE_MAGIC.to_vec();
+        data.extend_from_slice(&ARCHIVE_SCHEMA_VERSION.to_le_bytes());
+        data.extend(
+            rmp_serde::to_vec_named(self).expect("Failed to serialize index"),
+        );
+        data
+    }
+
+    /// Decodes the index from an archive, checking its schema version.
+    pub fn from_archive(data: &[u8]) -> anyhow::Result<Self> {
+        let Some(data) = data.strip_prefix(ARCHIVE_MAGIC) else {
+            bail!("Not a Trakktor index archive");
+        };
+        let Some((version, data)) = data.split_first_chunk::<4>() else {
+            bail!("Truncated index archive");
+        };
+        let version = u32::from_le_bytes(*version);
+        if version != ARCHIVE_SCHEMA_VERSION {
+            bail!(
+                "Unsupported index archive schema version {version}, expected \
+                 {ARCHIVE_SCHEMA_VERSION}"
+            );
+        }
+        let index: Self = rmp_serde::from_slice(data)?;
+        if index.embeddings.len() != index.chunks.len() * index.info.dimensions
+        {
+            bail!("The number of embeddings does not match the chunks");
+        }
+        Ok(index)
+    }
+}
+
+#[tracing::instrument(level = "info", skip_all)]
+pub async fn run_index(
+    args: &IndexArgs,
+    vector_store: &VectorStoreConfig,
+) -> crate::Result<()> {
+    match &args.command {
+        IndexCommands::Export(export) => export_index(export).await?,
+        IndexCommands::Import(import) => import_index(import).await?,
+        IndexCommands::Push(push) => push_index(push, vector_store).await?,
+        IndexCommands::Apply(apply) => apply_index(apply, vector_store).await?,
+    }
+    Ok(())
+}
+
+async fn apply_index(
+    args: &ApplyIndex,
+    vector_store: &VectorStoreConfig,
+) -> anyhow::Result<()> {
+    let mut index = VectorIndex::load(&args.dir).await?;
+    let update = VectorIndex::load(&args.update).await?;
+    let entries = update.entries();
+    let (changed, removed) = index.apply_update(update)?;
+
+    if args.push {
+        let store = vector_store.open().await?;
+        let stale = changed.iter().chain(&removed).cloned().collect::<Vec<_>>();
+        store.delete_documents(&index.info.model, &stale).await?;
+        store.upsert(&index.info.model, &entries).await?;
+    }
+    index.save(&args.dir).await?;
+
+    tracing::info!(
+        changed = changed.len(),
+        removed = removed.len(),
+        chunks = index.chunks.len(),
+        "Applied the update to the index."
+    );
+    Ok(())
+}
+
+async fn push_index(
+    args: &PushIndex,
+    vector_store: &VectorStoreConfig,
+) -> anyhow::Result<()> {
+    let index = VectorIndex::load(&args.dir).await?;
+    if index.info.partial {
+        bail!(
+            "The index only holds the changed documents, apply it onto the \
+             previous index with `index apply` instead"
+        );
+    }
+    let store = vector_store.open().await?;
+    store.upsert(&index.info.model, &index.entries()).await?;
+
+    tracing::info!(
+        chunks = index.chunks.len(),
+        model = index.info.model,
+        "Pushed the index to the vector store."
+    );
+    Ok(())
+}
+
+async fn export_index(args: &ExportIndex) -> anyhow::Result<()> {
+    let index = VectorIndex::load(&args.dir).await?;
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None => {
+            let dir = tokio::fs::canonicalize(&args.dir).await?;
+            dir.with_extension(ARCHIVE_EXT)
+        },
+    };
+    tokio::fs::write(&output, index.to_archive()).await?;
+
+    tracing::info!(
+        chunks = index.chunks.len(),
+        model = index.info.model,
+        "Exported the index to: {}",
+        output.display()
+    );
+    Ok(())
+}
+
+async fn import_index(args: &ImportIndex) -> anyhow::Result<()> {
+    let data = tokio::fs::read(&args.archive).await?;
+    let index = VectorIndex::from_archive(&data).with_context(|| {
+        format!("Failed to read the archive {}", args.archive.display())
+    })?;
+    let output_dir = match &args.output_dir {
+        Some(dir) => dir.clone(),
+        None => args.archive.with_extension(""),
+    };
+    if !args.force && tokio::fs::try_exists(output_dir.join(INFO_FILE)).await? {
+        bail!(
+            "An index already exists in {}, use --force to overwrite it",
+            output_dir.display()
+        );
+    }
+    index.save(&output_dir).await?;
+
+    tracing::info!(
+        chunks = index.chunks.len(),
+        model = index.info.model,
+        "Imported the index to: {}",
+        output_dir.display()
+    );
+    Ok(())
+}
+
+/// Parses a NumPy file with a little-endian float32 matrix into its rows,
+/// columns and values.
+fn parse_npy(data: &[u8]) -> anyhow::Result<(usize, usize, Vec<f32>)> {
+    let Some(rest) = data.strip_prefix(NPY_MAGIC) else {
+        bail!("Not a NumPy file");
+    };
+    let (header_len, header_start) = match rest.first() {
+        Some(1) if rest.len() >= 4 => {
+            (u16::from_le_bytes([rest[2], rest[3]]) as usize, 10)
+        },
+        Some(2 | 3) if rest.len() >= 6 => (
+            u32::from_le_bytes([rest[2], rest[3], rest[4], rest[5]]) as usize,
+            12,
+        ),
+        _ => bail!("Unsupported NumPy file version"),
+    };
+    let header = data
+        .get(header_start..header_start + header_len)
+        .context("Truncated NumPy header")?;
+    let header = std::str::from_utf8(header)?;
+
+    if !header.contains("'descr': '<f4'") {
+        bail!("Expected float32 values in the NumPy file: {header}");
+    }
+    if !header.contains("'fortran_order': False") {
+        bail!("Expected C order in the NumPy file: {header}");
+    }
+    let shape = header
+        .split_once("'shape': (")
+        .and_then(|(_, s)| s.split_once(')'))
+        .map(|(s, _)| {
+            s.split(',')
+                .map(str::trim)
+                .filter(|d| !d.is_empty())
+                .map(str::parse::<usize>)
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .context("No shape in the NumPy header")??;
+    let [rows, columns] = shape[..] else {
+        bail!("Expected a matrix in the NumPy file, got shape {shape:?}");
+    };
+
+    let values = &data[header_start + header_len..];
+    if values.len() != rows * columns * 4 {
+        bail!("The NumPy data does not match the shape ({rows}, {columns})");
+    }
+    Ok((rows, columns, f32_bytes::from_le_bytes(values)))
+}
+
+/// Writes a float32 matrix in the NumPy format, version 1.0.
+fn write_npy(rows: usize, columns: usize, values: &[f32]) -> Vec<u8> {
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({rows}, \
+         {columns}), }}"
+    );
+    // The header is padded for the data to be aligned to 64 bytes.
+    let unpadded = NPY_MAGIC.len() + 4 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(64) - unpadded));
+    header.push('\n');
+
+    let mut data = NPY_MAGIC.to_vec();
+    data.extend_from_slice(&[1, 0]);
+    data.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    data.extend_from_slice(header.as_bytes());
+    data.extend(f32_bytes::to_le_bytes(values));
+    data
+}
+
+/// Serializes the floats as a single binary value, instead of a sequence of
+/// numbers.
+pub(crate) mod f32_bytes {
+    use serde::{de::Visitor, Deserializer, Serializer};
+
+    pub fn to_le_bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    pub fn from_le_bytes(data: &[u8]) -> Vec<f32> {
+        data.chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    }
+
+    pub fn serialize<S: Serializer>(
+        values: &[f32],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&to_le_bytes(values))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<f32>, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = Vec<f32>;
+
+            fn expecting(
+                &self,
+                f: &mut std::fmt::Formatter,
+            ) -> std::fmt::Result {
+                f.write_str("little-endian float32 bytes")
+            }
+
+            fn visit_bytes<E: serde::de::Error>(
+                self,
+                v: &[u8],
+            ) -> Result<Self::Value, E> {
+                if !v.len().is_multiple_of(4) {
+                    return Err(E::invalid_length(v.len(), &self));
+                }
+                Ok(from_le_bytes(v))
+            }
+        }
+
+        deserializer.deserialize_bytes(BytesVisitor)
+    }
+}
+
+#[test]
+fn npy_roundtrip_test() -> anyhow::Result<()> {
+    let values = [0.5, -1.0, 2.25, 3.0, 0.0, 1e-3];
+    let data = write_npy(2, 3, &values);
+    assert_eq!((data.len() - values.len() * 4) % 64, 0);
+    assert_eq!(parse_npy(&data)?, (2, 3, values.to_vec()));
+
+    let mut transposed = data.clone();
+    let pos = data.windows(5).position(|w| w == b"False").unwrap();
+    transposed[pos..pos + 5].copy_from_slice(b"True ");
+    assert!(parse_npy(&transposed).is_err());
+    Ok(())
+}
+
+#[test]
+fn archive_roundtrip_test() -> anyhow::Result<()> {
+    let index = VectorIndex {
+        info: IndexInfo {
+            model: "model".to_string(),
+            dimensions: 2,
+            normalized: true,
+            chunk_words: Some(200),
+            chunk_overlap: Some(20),
+            metadata: vec!["section".to_string()],
+            documents: 1,
+            chunks: 2,
+            skipped: vec!["image.png".to_string()],
+            document_hashes: [("doc.md".to_string(), "h".to_string())].into(),
+            partial: false,
+        },
+        chunks: vec![
+            IndexChunk {
+                document: "doc.md".to_string(),
+                chunk: 0,
+                text: "First".to_string(),
+                section: Some("Intro".to_string()),
+                ..Default::default()
+            },
+            IndexChunk {
+                document: "doc.md".to_string(),
+                chunk: 1,
+                text: "Second".to_string(),
+                speaker: Some("Ann".to_string()),
+                start: Some(1.5),
+                end: Some(7.25),
+                ..Default::default()
+            },
+        ],
+        embeddings: vec![0.6, 0.8, 1.0, 0.0],
+    };
+    let archive = index.to_archive();
+    assert_eq!(VectorIndex::from_archive(&archive)?, index);
+
+    let mut newer = archive.clone();
+    newer[ARCHIVE_MAGIC.len()] = 2;
+    assert!(VectorIndex::from_archive(&newer).is_err());
+    assert!(VectorIndex::from_archive(&archive[1..]).is_err());
+    Ok(())
+}
+
+#[test]
+fn apply_update_test() -> anyhow::Result<()> {
+    let index = |docs: &[(&str, &str)], chunks: &[(&str, f32)]| VectorIndex {
+        info: IndexInfo {
+            model: "model".to_string(),
+            dimensions: 1,
+            normalized: true,
+            chunk_words: Some(200),
+            chunk_overlap: None,
+            metadata: vec![],
+            documents: docs.len(),
+            chunks: chunks.len(),
+            skipped: vec![],
+            document_hashes: docs
+                .iter()
+                .map(|(d, h)| (d.to_string(), h.to_string()))
+                .collect(),
+            partial: false,
+        },
+        chunks: chunks
+            .iter()
+            .map(|(document, _)| IndexChunk {
+                document: document.to_string(),
+                ..Default::default()
+            })
+            .collect(),
+        embeddings: chunks.iter().map(|(_, e)| *e).collect(),
+    };
+
+    let mut base = index(
+        &[("a", "1"), ("b", "1"), ("c", "1")],
+        &[("a", 1.0), ("b", 2.0), ("b", 3.0), ("c", 4.0)],
+    );
+    let update = index(
+        &[("a", "1"), ("b", "2"), ("d", "1")],
+        &[("b", 5.0), ("d", 6.0)],
+    );
+    let (changed, removed) = base.apply_update(update)?;
+    assert_eq!(changed, vec!["b", "d"]);
+    assert_eq!(removed, vec!["c"]);
+    assert_eq!(
+        base.chunks
+            .iter()
+            .map(|c| c.document.as_str())
+            .collect::<Vec<_>>(),
+        vec!["a", "b", "d"]
+    );
+    assert_eq!(base.embeddings, vec![1.0, 5.0, 6.0]);
+    assert_eq!((base.info.documents, base.info.chunks), (3, 3));
+
+    let stale = index(&[("a", "1")], &[("a", 7.0)]);
+    assert!(base.apply_update(stale).is_err());
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::bail;
+use redb::TableDefinition;
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+
+use super::{
+    cosine_similarity, entry_documents, SearchHit, VectorEntry, VectorStore,
+};
+use crate::vector_index::{f32_bytes, IndexChunk};
+
+/// Chunks by model, document and chunk number.
+const CHUNKS_TABLE: TableDefinition<(&str, &str, u32), Vec<u8>> =
+    TableDefinition::new("chunks");
+
+#[derive(Serialize, Deserialize)]
+struct StoredChunk {
+    text: String,
+    #[serde(with = "f32_bytes")]
+    embedding: Vec<f32>,
+    #[serde(default)]
+    section: Option<String>,
+    #[serde(default)]
+    speaker: Option<String>,
+    #[serde(default)]
+    start: Option<f64>,
+    #[serde(default)]
+    end: Option<f64>,
+}
+
+/// A store in a local redb database. The search compares the query with all
+/// the chunks of the model, which is fast enough for a personal corpus.
+pub struct LocalVectorStore {
+    db: Arc<redb::Database>,
+}
+
+impl LocalVectorStore {
+    pub async fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let db = spawn_blocking(move || -> anyhow::Result<_> {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let db = redb::Database::create(&path)?;
+            let write_txn = db.begin_write()?;
+            drop(write_txn.open_table(CHUNKS_TABLE)?);
+            write_txn.commit()?;
+            Ok(db)
+        })
+        .await??;
+
+        Ok(Self { db: Arc::new(db) })
+    }
+
+    async fn with_db<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&redb::Database) -> anyhow::Result<T> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let db = Arc::clone(&self.db);
+        spawn_blocking(move || f(&db)).await?
+    }
+}
+
+fn delete_documents_sync(
+    table: &mut redb::Table<(&str, &str, u32), Vec<u8>>,
+    model: &str,
+    documents: &[String],
+) -> anyhow::Result<()> {
+    for document in documents {
+        table.retain_in(
+            (model, document.as_str(), 0)..=
+                (model, document.as_str(), u32::MAX),
+            |_, _| false,
+        )?;
+    }
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl VectorStore for LocalVectorStore {
+    #[tracing::instrument(level = "debug", skip(self, entries))]
+    async fn upsert(
+        &self,
+        model: &str,
+        entries: &[VectorEntry],
+    ) -> anyhow::Result<()> {
+        let model = model.to_string();
+        let entries = entries.to_vec();
+        self.with_db(move |db| {
+            let write_txn = db.begin_write()?;
+            {
+                let mut table = write_txn.open_table(CHUNKS_TABLE)?;
+                delete_documents_sync(
+                    &mut table,
+                    &model,
+                    &entry_documents(&entries),
+                )?;
+                for VectorEntry { chunk, embedding } in entries {
+                    let data = rmp_serde::to_vec(&StoredChunk {
+                        text: chunk.text,
+                        embedding,
+                        section: chunk.section,
+                        speaker: chunk.speaker,
+                        start: chunk.start,
+                        end: chunk.end,
+                    })?;
+                    table.insert(
+                        (model.as_str(), chunk.document.as_str(), chunk.chunk),
+                        data,
+                    )?;
+                }
+            }
+            write_txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, embedding))]
+    async fn search(
+        &self,
+        model: &str,
+        embedding: &[f32],
+        limit: usize,
+    ) -> anyhow::Result<Vec<SearchHit>> {
+        let model = model.to_string();
+        let embedding = embedding.to_vec();
+        self.with_db(move |db| {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(CHUNKS_TABLE)?;
+
+            let mut hits = Vec::new();
+            let range = (model.as_str(), "", 0)..;
+            for item in table.range(range)? {
+                let (key, value) = item?;
+                let (key_model, document, chunk) = key.value();
+                if key_model != model {
+                    break;
+                }
+                let stored: StoredChunk =
+                    rmp_serde::from_slice(&value.value())?;
+                if stored.embedding.len() != embedding.len() {
+                    bail!(
+                        "The query has {} dimensions, the stored embeddings \
+                         have {}",
+                        embedding.len(),
+                        stored.embedding.len()
+                    );
+                }
+                hits.push(SearchHit {
+                    score: cosine_similarity(&embedding, &stored.embedding),
+                    chunk: IndexChunk {
+                        document: document.to_string(),
+                        chunk,
+                        text: stored.text,
+                        section: stored.section,
+                        speaker: stored.speaker,
+                        start: stored.start,
+                        end: stored.end,
+                    },
+                });
+            }
+
+            hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+            hits.truncate(limit);
+            Ok(hits)
+        })
+        .await
+    }
+
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn delete_documents(
+        &self,
+        model: &str,
+        documents: &[String],
+    ) -> anyhow::Result<()> {
+        let model = model.to_string();
+        let documents = documents.to_vec();
+        self.with_db(move |db| {
+            let write_txn = db.begin_write()?;
+            {
+                let mut table = write_txn.open_table(CHUNKS_TABLE)?;
+                delete_documents_sync(&mut table, &model, &documents)?;
+            }
+            write_txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[tokio::test]
+async fn local_vector_store_test() -> anyhow::Result<()> {
+    let path = std::env::temp_dir()
+        .join(format!("trakktor-vectors-{}.redb", uuid::Uuid::new_v4()));
+    let store = LocalVectorStore::open(path.clone()).await?;
+
+    let entry = |document: &str, chunk: u32, embedding: [f32; 2]| VectorEntry {
+        chunk: IndexChunk {
+            document: document.to_string(),
+            chunk,
+            text: format!("{document} {chunk}"),
+            ..Default::default()
+        },
+        embedding: embedding.to_vec(),
+    };
+    store
+        .upsert(
+            "m",
+            &[
+                entry("a", 0, [1.0, 0.0]),
+                entry("a", 1, [0.0, 1.0]),
+                entry("b", 0, [0.6, 0.8]),
+            ],
+        )
+        .await?;
+    store.upsert("other", &[entry("c", 0, [1.0, 0.0])]).await?;
+    // Replaces both chunks of the document.
+    store.upsert("m", &[entry("a", 0, [-1.0, 0.0])]).await?;
+
+    let hits = store.search("m", &[0.0, 2.0], 5).await?;
+    let found = hits
+        .iter()
+        .map(|h| (h.chunk.text.as_str(), (h.score * 10.0).round()))
+        .collect::<Vec<_>>();
+    assert_eq!(found, vec![("b 0", 8.0), ("a 0", 0.0)]);
+
+    store.delete_documents("m", &["b".to_string()]).await?;
+    assert_eq!(store.search("m", &[1.0, 0.0], 5).await?.len(), 1);
+    assert!(store.search("m", &[1.0, 0.0, 0.0], 5).await.is_err());
+
+    drop(store);
+    std::fs::remove_file(path)?;
+    Ok(())
+}
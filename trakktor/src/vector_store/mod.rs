@@ -0,0 +1,262 @@
+//! Stores of the chunk embeddings for retrieval.
+//!
+//! The local store keeps the embeddings in a redb database on the machine.
+//! Qdrant and Postgres with the pgvector extension can be used instead, to
+//! share the embeddings within a team. The store is chosen in the
+//! `[vector_store]` section of the config file.
+//!
+//! The hits of a search can be reordered by an LLM with `rerank_with_llm`, as
+//! `trakktor search --rerank` does.
+
+use std::{path::PathBuf, sync::Arc};
+
+use serde::Deserialize;
+
+use crate::{app_config::default_data_dir, vector_index::IndexChunk};
+
+mod local;
+mod pgvector;
+mod qdrant;
+mod rerank;
+
+pub use local::LocalVectorStore;
+pub use pgvector::PgVectorStore;
+pub use qdrant::QdrantVectorStore;
+pub use rerank::rerank_with_llm;
+
+/// A chunk of a document with its embedding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VectorEntry {
+    pub chunk: IndexChunk,
+    pub embedding: Vec<f32>,
+}
+
+/// A chunk found by a search, with its cosine similarity to the query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub chunk: IndexChunk,
+    pub score: f32,
+}
+
+impl SearchHit {
+    /// A snippet of the chunk text around the first match of the query
+    /// words, with the matching words in bold Markdown. The snippet starts
+    /// at the beginning of the chunk if no word matches.
+    pub fn snippet(&self, query: &str, context_words: usize) -> String {
+        let normalize = |word: &str| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        };
+        let terms = query
+            .split_whitespace()
+            .map(normalize)
+            .filter(|t| t.chars().count() > 1)
+            .collect::<Vec<_>>();
+        let is_match = |word: &str| {
+            let word = normalize(word);
+            !word.is_empty() && terms.iter().any(|t| word.starts_with(t))
+        };
+
+        let words = self.chunk.text.split_whitespace().collect::<Vec<_>>();
+        let first = words.iter().position(|w| is_match(w)).unwrap_or(0);
+        let start = first.saturating_sub(context_words);
+        let end = (first + context_words + 1).min(words.len());
+
+        let mut snippet = words[start..end]
+            .iter()
+            .map(|w| {
+                if is_match(w) {
+                    format!("**{w}**")
+                } else {
+                    w.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        if start > 0 {
+            snippet.insert_str(0, "… ");
+        }
+        if end < words.len() {
+            snippet.push_str(" …");
+        }
+        snippet
+    }
+}
+
+#[test]
+fn search_hit_snippet_test() {
+    let hit = SearchHit {
+        chunk: IndexChunk {
+            document: "notes.md".to_string(),
+            chunk: 0,
+            text: "The quarterly budget was approved. Hiring is paused until \
+                   the Budget review in May."
+                .to_string(),
+            ..Default::default()
+        },
+        score: 0.5,
+    };
+    assert_eq!(
+        hit.snippet("budget review", 2),
+        "The quarterly **budget** was approved. …"
+    );
+    assert_eq!(hit.snippet("hiring", 1), "… approved. **Hiring** is …");
+    assert_eq!(hit.snippet("unrelated", 1), "The quarterly …");
+}
+
+/// Embeddings of different models are not comparable, so every operation is
+/// scoped to the model the embeddings were computed with.
+#[async_trait::async_trait]
+pub trait VectorStore: Send + Sync {
+    /// Stores the entries, replacing all the chunks stored before for their
+    /// documents.
+    async fn upsert(
+        &self,
+        model: &str,
+        entries: &[VectorEntry],
+    ) -> anyhow::Result<()>;
+
+    /// Finds the chunks most similar to the embedding, best first.
+    async fn search(
+        &self,
+        model: &str,
+        embedding: &[f32],
+        limit: usize,
+    ) -> anyhow::Result<Vec<SearchHit>>;
+
+    /// Removes all the chunks of the documents.
+    async fn delete_documents(
+        &self,
+        model: &str,
+        documents: &[String],
+    ) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "backend", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum VectorStoreConfig {
+    Local {
+        /// The database file, `~/.local/share/trakktor/vectors.redb` by
+        /// default.
+        path: Option<PathBuf>,
+    },
+    Qdrant {
+        /// The URL of the REST API, e.g. `http://localhost:6333`.
+        url: Arc<str>,
+        collection: Arc<str>,
+        api_key: Option<Arc<str>>,
+    },
+    Pgvector {
+        /// The libpq connection string, a URI or `keyword=value` pairs. It
+        /// is given to `psql` on the command line, which the other users of
+        /// the machine can see, so the password is better kept in
+        /// `PGPASSWORD` or `~/.pgpass` than here.
+        url: Arc<str>,
+        table: Arc<str>,
+    },
+}
+
+impl Default for VectorStoreConfig {
+    fn default() -> Self { Self::Local { path: None } }
+}
+
+const LOCAL_STORE_FILE: &str = "vectors.redb";
+
+impl VectorStoreConfig {
+    /// Opens the configured store.
+    pub async fn open(&self) -> anyhow::Result<Box<dyn VectorStore>> {
+        Ok(match self {
+            Self::Local { path } => {
+                let path = match path {
+                    Some(path) => path.clone(),
+                    None => default_local_store_path()?,
+                };
+                Box::new(LocalVectorStore::open(path).await?)
+            },
+            Self::Qdrant {
+                url,
+                collection,
+                api_key,
+            } => Box::new(QdrantVectorStore::new(
+                url::Url::parse(url)?,
+                Arc::clone(collection),
+                api_key.clone(),
+            )),
+            Self::Pgvector { url, table } => {
+                Box::new(PgVectorStore::new(Arc::clone(url), table)?)
+            },
+        })
+    }
+
+    /// The database file of the local store, `None` for the other stores.
+    pub fn local_path(&self) -> anyhow::Result<Option<PathBuf>> {
+        Ok(match self {
+            Self::Local { path: Some(path) } => Some(path.clone()),
+            Self::Local { path: None } => Some(default_local_store_path()?),
+            _ => None,
+        })
+    }
+}
+
+/// `$XDG_DATA_HOME/trakktor/vectors.redb` or
+/// `~/.local/share/trakktor/vectors.redb`.
+fn default_local_store_path() -> anyhow::Result<PathBuf> {
+    Ok(default_data_dir()?.join(LOCAL_STORE_FILE))
+}
+
+/// The documents of the entries, without duplicates.
+fn entry_documents(entries: &[VectorEntry]) -> Vec<String> {
+    let mut documents = entries
+        .iter()
+        .map(|e| e.chunk.document.clone())
+        .collect::<Vec<_>>();
+    documents.sort();
+    documents.dedup();
+    documents
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+#[test]
+fn vector_store_config_test() -> anyhow::Result<()> {
+    let config: VectorStoreConfig = toml_edit::de::from_str(
+        r#"
+        backend = "qdrant"
+        url = "http://localhost:6333"
+        collection = "corpus"
+        "#,
+    )?;
+    assert!(matches!(
+        config,
+        VectorStoreConfig::Qdrant { ref collection, api_key: None, .. }
+            if collection.as_ref() == "corpus"
+    ));
+
+    let config: VectorStoreConfig = toml_edit::de::from_str(
+        r#"
+        backend = "pgvector"
+        url = "postgres://trakktor@db/corpus"
+        table = "chunks"
+        "#,
+    )?;
+    assert!(matches!(config, VectorStoreConfig::Pgvector { .. }));
+
+    assert!(toml_edit::de::from_str::<VectorStoreConfig>(
+        "backend = \"milvus\""
+    )
+    .is_err());
+    Ok(())
+}
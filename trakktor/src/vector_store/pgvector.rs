@@ -0,0 +1,276 @@
+use std::{process::Stdio, sync::Arc};
+
+use anyhow::{bail, Context};
+use itertools::Itertools;
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+
+use super::{entry_documents, SearchHit, VectorEntry, VectorStore};
+use crate::vector_index::IndexChunk;
+
+/// A store in a Postgres table with the pgvector extension. The queries are
+/// run with `psql`, which has to be installed; the table is created on the
+/// first upsert. The values are passed to the queries as psql variables or
+/// in the data of `COPY`, so psql escapes them for the server.
+pub struct PgVectorStore {
+    url: Arc<str>,
+    table: Arc<str>,
+}
+
+#[derive(Deserialize)]
+struct Row {
+    document: String,
+    chunk: u32,
+    text: String,
+    section: Option<String>,
+    speaker: Option<String>,
+    start_time: Option<f64>,
+    end_time: Option<f64>,
+    score: f32,
+}
+
+impl PgVectorStore {
+    pub fn new(url: Arc<str>, table: &str) -> anyhow::Result<Self> {
+        let is_identifier = table
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_lowercase() || c == '_') &&
+            table.chars().all(|c| {
+                c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'
+            });
+        if !is_identifier {
+            bail!("Invalid table name: {table}");
+        }
+        Ok(Self {
+            url,
+            table: table.into(),
+        })
+    }
+
+    /// Runs the script in a single transaction with the psql variables and
+    /// returns the output. The connection string is given to psql as it is,
+    /// so it is read by libpq, which also takes the password from
+    /// `PGPASSWORD` or `~/.pgpass`.
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn run_psql(
+        &self,
+        script: String,
+        variables: &[(&str, &str)],
+    ) -> anyhow::Result<String> {
+        let mut command = tokio::process::Command::new("psql");
+        for (name, value) in variables {
+            command.args(["--set", &format!("{name}={value}")]);
+        }
+        let mut child = command
+            .arg("--no-psqlrc")
+            .arg("--single-transaction")
+            .arg("--quiet")
+            .arg("--tuples-only")
+            .arg("--no-align")
+            .args(["--set", "ON_ERROR_STOP=1"])
+            .args(["--file", "-"])
+            .args(["--dbname", &self.url])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to run psql, is it installed?")?;
+
+        let mut stdin = child.stdin.take().expect("Failed to open stdin");
+        let writer = tokio::spawn(async move {
+            stdin.write_all(script.as_bytes()).await?;
+            stdin.shutdown().await
+        });
+        let output = child.wait_with_output().await?;
+        writer.await??;
+
+        if !output.status.success() {
+            bail!(
+                "psql failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8(output.stdout)?)
+    }
+
+    fn create_table_sql(&self) -> String {
+        format!(
+            r#"CREATE EXTENSION IF NOT EXISTS vector;
+CREATE TABLE IF NOT EXISTS {0} (
+    model text NOT NULL,
+    document text NOT NULL,
+    chunk integer NOT NULL,
+    text text NOT NULL,
+    embedding vector NOT NULL,
+    PRIMARY KEY (model, document, chunk)
+);
+ALTER TABLE {0}
+    ADD COLUMN IF NOT EXISTS section text,
+    ADD COLUMN IF NOT EXISTS speaker text,
+    ADD COLUMN IF NOT EXISTS start_time double precision,
+    ADD COLUMN IF NOT EXISTS end_time double precision;
+"#,
+            self.table
+        )
+    }
+
+    /// Deletes the chunks of the documents of the model in the `model`
+    /// variable. The documents are copied into a temporary table, as a list
+    /// of them may be longer than an argument of psql can be.
+    fn delete_documents_sql(&self, documents: &[String]) -> String {
+        let mut script = "CREATE TEMPORARY TABLE deleted_documents \
+                          (document text) ON COMMIT DROP;\n\
+                          COPY deleted_documents FROM STDIN;\n"
+            .to_string();
+        for document in documents {
+            script.push_str(&copy_escape(document));
+            script.push('\n');
+        }
+        script.push_str(&format!(
+            "\\.\nDELETE FROM {} WHERE model = :'model' AND document IN \
+             (SELECT document FROM deleted_documents);\n",
+            self.table
+        ));
+        script
+    }
+}
+
+fn vector_literal(embedding: &[f32]) -> String {
+    format!("[{}]", embedding.iter().join(","))
+}
+
+/// A null value in the text format of `COPY`.
+const COPY_NULL: &str = "\\N";
+
+/// Escapes a value for the text format of `COPY`.
+fn copy_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[async_trait::async_trait]
+impl VectorStore for PgVectorStore {
+    #[tracing::instrument(level = "debug", skip(self, entries))]
+    async fn upsert(
+        &self,
+        model: &str,
+        entries: &[VectorEntry],
+    ) -> anyhow::Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let mut script = self.create_table_sql();
+        script.push_str(&self.delete_documents_sql(&entry_documents(entries)));
+        script.push_str(&format!(
+            "COPY {} (model, document, chunk, text, section, speaker, \
+             start_time, end_time, embedding) FROM STDIN;\n",
+            self.table
+        ));
+        let optional = |value: Option<String>| {
+            value.map_or_else(|| COPY_NULL.to_string(), |v| copy_escape(&v))
+        };
+        for VectorEntry { chunk, embedding } in entries {
+            script.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                copy_escape(model),
+                copy_escape(&chunk.document),
+                chunk.chunk,
+                copy_escape(&chunk.text),
+                optional(chunk.section.clone()),
+                optional(chunk.speaker.clone()),
+                optional(chunk.start.map(|t| t.to_string())),
+                optional(chunk.end.map(|t| t.to_string())),
+                vector_literal(embedding),
+            ));
+        }
+        script.push_str("\\.\n");
+
+        self.run_psql(script, &[("model", model)]).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, embedding))]
+    async fn search(
+        &self,
+        model: &str,
+        embedding: &[f32],
+        limit: usize,
+    ) -> anyhow::Result<Vec<SearchHit>> {
+        let query = vector_literal(embedding);
+        let output = self
+            .run_psql(
+                format!(
+                    "SELECT json_build_object('document', document, 'chunk', \
+                     chunk, 'text', text, 'section', section, 'speaker', \
+                     speaker, 'start_time', start_time, 'end_time', end_time, \
+                     'score', 1 - (embedding <=> :'query')) FROM {} WHERE \
+                     model = :'model' ORDER BY embedding <=> :'query' LIMIT \
+                     {limit};\n",
+                    self.table,
+                ),
+                &[("model", model), ("query", &query)],
+            )
+            .await?;
+
+        output
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let row: Row = serde_json::from_str(line)?;
+                Ok(SearchHit {
+                    chunk: IndexChunk {
+                        document: row.document,
+                        chunk: row.chunk,
+                        text: row.text,
+                        section: row.section,
+                        speaker: row.speaker,
+                        start: row.start_time,
+                        end: row.end_time,
+                    },
+                    score: row.score,
+                })
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn delete_documents(
+        &self,
+        model: &str,
+        documents: &[String],
+    ) -> anyhow::Result<()> {
+        if documents.is_empty() {
+            return Ok(());
+        }
+        let mut script = self.create_table_sql();
+        script.push_str(&self.delete_documents_sql(documents));
+        self.run_psql(script, &[("model", model)]).await?;
+        Ok(())
+    }
+}
+
+#[test]
+fn pgvector_sql_test() -> anyhow::Result<()> {
+    let store = PgVectorStore::new("postgres://db".into(), "chunks")?;
+    assert_eq!(
+        store.delete_documents_sql(&["it's.md".into(), "a\\b.md".into()]),
+        "CREATE TEMPORARY TABLE deleted_documents (document text) ON COMMIT \
+         DROP;\nCOPY deleted_documents FROM STDIN;\nit's.md\na\\\\b.md\n\\.\n\
+         DELETE FROM chunks WHERE model = :'model' AND document IN (SELECT \
+         document FROM deleted_documents);\n"
+    );
+    assert_eq!(vector_literal(&[0.5, -1.0]), "[0.5,-1]");
+    assert_eq!(copy_escape("a\tb\\c\nd"), "a\\tb\\\\c\\nd");
+    assert!(PgVectorStore::new("postgres://db".into(), "chunks; DROP").is_err());
+    Ok(())
+}
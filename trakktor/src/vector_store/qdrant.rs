@@ -0,0 +1,224 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+use reqwest::{Method, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+
+use super::{entry_documents, SearchHit, VectorEntry, VectorStore};
+use crate::vector_index::IndexChunk;
+
+/// Points are upserted in batches of this size.
+const UPSERT_BATCH: usize = 256;
+
+/// A store in a Qdrant collection, accessed through the REST API. The
+/// collection is created on the first upsert.
+pub struct QdrantVectorStore {
+    client: reqwest::Client,
+    url: url::Url,
+    collection: Arc<str>,
+    api_key: Option<Arc<str>>,
+}
+
+#[derive(Deserialize)]
+struct QdrantResponse<T> {
+    result: T,
+}
+
+#[derive(Deserialize)]
+struct ScoredPoint {
+    score: f32,
+    payload: IndexChunk,
+}
+
+impl QdrantVectorStore {
+    pub fn new(
+        url: url::Url,
+        collection: Arc<str>,
+        api_key: Option<Arc<str>>,
+    ) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            collection,
+            api_key,
+        }
+    }
+
+    fn request(
+        &self,
+        method: Method,
+        path: &str,
+    ) -> anyhow::Result<reqwest::RequestBuilder> {
+        let endpoint = self
+            .url
+            .join(&format!("collections/{}{}", self.collection, path))?;
+        let mut req_builder = self.client.request(method, endpoint);
+        if let Some(api_key) = &self.api_key {
+            req_builder = req_builder.header("api-key", api_key.as_ref());
+        }
+        Ok(req_builder)
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        req_builder: reqwest::RequestBuilder,
+    ) -> anyhow::Result<T> {
+        let res = req_builder.send().await?;
+        let code = res.status();
+        let res = res.text().await?;
+        if !code.is_success() {
+            bail!("Failed to call Qdrant!\nCode: {code}\nResponse: {res}");
+        }
+        let res: QdrantResponse<T> =
+            serde_json::from_str(&res).with_context(|| {
+                format!("Failed to parse Qdrant response:\n{res}")
+            })?;
+        Ok(res.result)
+    }
+
+    async fn ensure_collection(&self, dimensions: usize) -> anyhow::Result<()> {
+        let res = self.request(Method::GET, "")?.send().await?;
+        if res.status() != StatusCode::NOT_FOUND {
+            res.error_for_status()?;
+            return Ok(());
+        }
+
+        tracing::info!(collection = %self.collection, "Creating collection.");
+        self.send::<serde_json::Value>(self.request(Method::PUT, "")?.json(
+            &json!({
+                "vectors": { "size": dimensions, "distance": "Cosine" },
+            }),
+        ))
+        .await?;
+        for field in ["model", "document"] {
+            self.send::<serde_json::Value>(
+                self.request(Method::PUT, "/index?wait=true")?.json(&json!({
+                    "field_name": field,
+                    "field_schema": "keyword",
+                })),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Qdrant only accepts integers and UUIDs as point IDs, so the ID is derived
+/// from the model, the document and the chunk number.
+fn point_id(model: &str, chunk: &IndexChunk) -> uuid::Uuid {
+    let hash = blake3::hash(
+        format!("{}\0{}\0{}", model, chunk.document, chunk.chunk).as_bytes(),
+    );
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(&hash.as_bytes()[..16]);
+    uuid::Uuid::from_bytes(bytes)
+}
+
+#[async_trait::async_trait]
+impl VectorStore for QdrantVectorStore {
+    #[tracing::instrument(level = "debug", skip(self, entries))]
+    async fn upsert(
+        &self,
+        model: &str,
+        entries: &[VectorEntry],
+    ) -> anyhow::Result<()> {
+        let Some(first) = entries.first() else {
+            return Ok(());
+        };
+        self.ensure_collection(first.embedding.len()).await?;
+        self.delete_documents(model, &entry_documents(entries))
+            .await?;
+
+        for batch in entries.chunks(UPSERT_BATCH) {
+            let points = batch
+                .iter()
+                .map(|e| {
+                    json!({
+                        "id": point_id(model, &e.chunk).to_string(),
+                        "vector": e.embedding,
+                        "payload": {
+                            "model": model,
+                            "document": e.chunk.document,
+                            "chunk": e.chunk.chunk,
+                            "text": e.chunk.text,
+                            "section": e.chunk.section,
+                            "speaker": e.chunk.speaker,
+                            "start": e.chunk.start,
+                            "end": e.chunk.end,
+                        },
+                    })
+                })
+                .collect::<Vec<_>>();
+            self.send::<serde_json::Value>(
+                self.request(Method::PUT, "/points?wait=true")?
+                    .json(&json!({ "points": points })),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, embedding))]
+    async fn search(
+        &self,
+        model: &str,
+        embedding: &[f32],
+        limit: usize,
+    ) -> anyhow::Result<Vec<SearchHit>> {
+        let points: Vec<ScoredPoint> = self
+            .send(self.request(Method::POST, "/points/search")?.json(&json!({
+                "vector": embedding,
+                "limit": limit,
+                "with_payload": [
+                    "document", "chunk", "text", "section", "speaker",
+                    "start", "end",
+                ],
+                "filter": {
+                    "must": [{ "key": "model", "match": { "value": model } }],
+                },
+            })))
+            .await?;
+        Ok(points
+            .into_iter()
+            .map(|p| SearchHit {
+                chunk: p.payload,
+                score: p.score,
+            })
+            .collect())
+    }
+
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn delete_documents(
+        &self,
+        model: &str,
+        documents: &[String],
+    ) -> anyhow::Result<()> {
+        self.send::<serde_json::Value>(
+            self.request(Method::POST, "/points/delete?wait=true")?.json(
+                &json!({
+                    "filter": {
+                        "must": [
+                            { "key": "model", "match": { "value": model } },
+                            { "key": "document", "match": { "any": documents } },
+                        ],
+                    },
+                }),
+            ),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[test]
+fn point_id_test() {
+    let chunk = |chunk| IndexChunk {
+        document: "doc.md".to_string(),
+        chunk,
+        ..Default::default()
+    };
+    assert_eq!(point_id("m", &chunk(0)), point_id("m", &chunk(0)));
+    assert_ne!(point_id("m", &chunk(0)), point_id("m", &chunk(1)));
+    assert_ne!(point_id("m", &chunk(0)), point_id("n", &chunk(0)));
+}
@@ -0,0 +1,106 @@
+use std::borrow::Cow;
+
+use itertools::Itertools;
+
+use super::SearchHit;
+use crate::llm::{
+    ChatCompletionAPI, ChatCompletionsArgs, ChatTask, Message, Role,
+};
+
+const RERANK_PROMPT: &str = r#"
+You will be given a search query and numbered passages found for it. Rate how
+well each passage answers the query on a scale from 0 (unrelated) to 10
+(answers it fully). Reply with one line per passage in the form
+`<number>: <rating>`, and nothing else.
+"#;
+
+/// Reorders the hits of a vector search by their relevance to the query as
+/// rated by the LLM, and keeps the best `limit` of them. The similarity of
+/// the embeddings misses negations and details, so the LLM is given the
+/// top hits of a wider search. The hits the LLM does not rate keep their
+/// order after the rated ones.
+#[tracing::instrument(level = "debug", skip(chat_api, hits))]
+pub async fn rerank_with_llm(
+    chat_api: &dyn ChatCompletionAPI,
+    query: &str,
+    hits: Vec<SearchHit>,
+    limit: usize,
+) -> anyhow::Result<Vec<SearchHit>> {
+    if hits.len() <= 1 {
+        return Ok(hits);
+    }
+
+    let input = format!(
+        "Query: {}\n\n{}",
+        query.trim(),
+        hits.iter()
+            .enumerate()
+            .map(|(i, hit)| format!(
+                "[{}] ({})\n{}",
+                i + 1,
+                hit.chunk.document,
+                hit.chunk.text
+            ))
+            .join("\n\n")
+    );
+    let response = chat_api
+        .run_chat(
+            ChatCompletionsArgs::builder()
+                .messages(&[
+                    Message {
+                        role: Role::System,
+                        content: Cow::Borrowed(RERANK_PROMPT.trim()),
+                    },
+                    Message {
+                        role: Role::User,
+                        content: Cow::Owned(input),
+                    },
+                ])
+                .task(ChatTask::Rerank)
+                .build(),
+        )
+        .await?;
+
+    let ratings = parse_ratings(&response.content, hits.len());
+    tracing::debug!(?ratings, "Rated the hits");
+    Ok(hits
+        .into_iter()
+        .zip(ratings)
+        .sorted_by(|(_, a), (_, b)| {
+            b.unwrap_or(-1.0).total_cmp(&a.unwrap_or(-1.0))
+        })
+        .map(|(hit, _)| hit)
+        .take(limit)
+        .collect())
+}
+
+/// Parses the `<number>: <rating>` lines of the response into the ratings
+/// of the passages, by position.
+fn parse_ratings(response: &str, count: usize) -> Vec<Option<f32>> {
+    let mut ratings = vec![None; count];
+    for line in response.lines() {
+        let Some((number, rating)) = line.split_once(':') else {
+            continue;
+        };
+        let number = number.trim().trim_matches(|c| c == '[' || c == ']');
+        let (Ok(number), Ok(rating)) =
+            (number.parse::<usize>(), rating.trim().parse::<f32>())
+        else {
+            continue;
+        };
+        if let Some(slot) =
+            number.checked_sub(1).and_then(|i| ratings.get_mut(i))
+        {
+            *slot = Some(rating);
+        }
+    }
+    ratings
+}
+
+#[test]
+fn parse_ratings_test() {
+    assert_eq!(
+        parse_ratings("1: 3\n[2]: 9.5\nSure!\n7: 10\n3: high", 3),
+        vec![Some(3.0), Some(9.5), None]
+    );
+}
@@ -0,0 +1,86 @@
+"""Client-side encryption of the job data, see
+trakktor/src/aws_batch/encryption.rs for the format.
+
+Usage:
+    crypt.py encrypt <file>             prints the object metadata value
+    crypt.py decrypt <file> <metadata>
+
+The file is replaced in place. The key is read from the SSM SecureString
+parameter named by TRK_ENCRYPTION_KEY_PARAMETER, see
+trakktor/src/aws_batch/key_parameter.rs.
+"""
+
+import base64
+import functools
+import os
+import sys
+
+import botocore.session
+
+from cryptography.hazmat.primitives import hashes
+from cryptography.hazmat.primitives.ciphers.aead import AESGCM
+from cryptography.hazmat.primitives.kdf.hkdf import HKDF
+
+FORMAT_VERSION = "v1"
+SALT_LEN = 32
+KDF_INFO = b"trakktor-chunk"
+CHUNK_SIZE = 1024 * 1024 * 5
+TAG_LEN = 16
+
+
+@functools.cache
+def load_key():
+    name = os.environ["TRK_ENCRYPTION_KEY_PARAMETER"]
+    ssm = botocore.session.get_session().create_client("ssm")
+    response = ssm.get_parameter(Name=name, WithDecryption=True)
+    return base64.b64decode(response["Parameter"]["Value"])
+
+
+def object_cipher(salt):
+    key = load_key()
+    hkdf = HKDF(algorithm=hashes.SHA256(), length=32, salt=salt, info=KDF_INFO)
+    return AESGCM(hkdf.derive(key))
+
+
+def nonce(index):
+    return b"\0" * 4 + index.to_bytes(8, "big")
+
+
+def transform(path, chunk_size, fn):
+    tmp_path = path + ".tmp"
+    with open(path, "rb") as src, open(tmp_path, "wb") as dst:
+        index = 0
+        chunk = src.read(chunk_size)
+        while True:
+            next_chunk = src.read(chunk_size)
+            is_last = not next_chunk
+            dst.write(fn(nonce(index), chunk, bytes([is_last])))
+            if is_last:
+                break
+            chunk = next_chunk
+            index += 1
+    os.replace(tmp_path, path)
+
+
+def encrypt(path):
+    """Encrypts the file and returns the object metadata value."""
+    salt = os.urandom(SALT_LEN)
+    transform(path, CHUNK_SIZE, object_cipher(salt).encrypt)
+    return f"{FORMAT_VERSION}:{base64.b64encode(salt).decode()}"
+
+
+def decrypt(path, metadata):
+    version, _, salt = metadata.partition(":")
+    if version != FORMAT_VERSION:
+        sys.exit(f"Unsupported encryption format: {version}")
+    cipher = object_cipher(base64.b64decode(salt))
+    transform(path, CHUNK_SIZE + TAG_LEN, cipher.decrypt)
+
+
+if __name__ == "__main__":
+    if sys.argv[1] == "encrypt":
+        print(encrypt(sys.argv[2]))
+    elif sys.argv[1] == "decrypt":
+        decrypt(sys.argv[2], sys.argv[3])
+    else:
+        sys.exit(__doc__)
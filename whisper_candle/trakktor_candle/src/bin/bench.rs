@@ -0,0 +1,57 @@
+use candle_core::Device;
+use trakktor_candle::{
+    models::model_dir,
+    speech_recognition::{
+        bench::{format_table, load_dataset, run_bench, BenchTask},
+        DataFile, DecodingStrategy, WhichModel,
+    },
+};
+
+/// Benchmarks the downloaded models on the dataset directory given as the
+/// argument, on the CPU and the GPU, e.g. `bench ./dataset`.
+fn main() -> anyhow::Result<()> {
+    stderrlog::new()
+        .module("trakktor_candle::speech_recognition")
+        .verbosity(log::Level::Warn)
+        .init()?;
+
+    let Some(dataset_dir) = std::env::args().nth(1) else {
+        anyhow::bail!("usage: bench <dataset directory>");
+    };
+    let dataset = load_dataset(dataset_dir.as_ref())?;
+
+    let models_data_dir = std::path::PathBuf::from("./models_data");
+    let models = [
+        WhichModel::Base,
+        WhichModel::Small,
+        WhichModel::Medium,
+        WhichModel::LargeV3,
+    ]
+    .into_iter()
+    .filter(|model| {
+        model_dir(&models_data_dir, *model)
+            .join(DataFile::Model.file_name())
+            .exists()
+    })
+    .collect::<Vec<_>>();
+    if models.is_empty() {
+        anyhow::bail!("no models downloaded to {}", models_data_dir.display());
+    }
+
+    let results = run_bench(
+        &BenchTask {
+            models_data_dir,
+            models,
+            devices: vec![
+                ("cpu".into(), Device::Cpu),
+                ("metal".into(), Device::new_metal(0)?),
+            ],
+            language: std::env::var("TRK_LANGUAGE").ok(),
+            decoding: DecodingStrategy::Greedy,
+        },
+        &dataset,
+    )?;
+    print!("{}", format_table(&results));
+
+    Ok(())
+}
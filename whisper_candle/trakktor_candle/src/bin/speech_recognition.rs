@@ -1,9 +1,7 @@
 use candle_core::Device;
 use trakktor_candle::speech_recognition::{
-    output_provider::{
-        TextOutputProvider, TimestampFormat, TimestampedTextOutputProvider,
-    },
-    run_speech_recognizer, SpeechRecognizerTask, WhichModel,
+    output_provider::TextOutputProvider, run_speech_recognizer,
+    DecodingStrategy, SpeechRecognizerTask, WhichModel,
 };
 
 fn main() -> anyhow::Result<()> {
@@ -29,6 +27,8 @@ fn main() -> anyhow::Result<()> {
             input: input.into(),
             language: Some("ru".into()),
             seed: None,
+            initial_prompt: None,
+            decoding: DecodingStrategy::Greedy,
         },
         Box::new(output),
     )?;
@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+
+use candle_core::Device;
+use clap::Parser;
+use trakktor_candle::speech_recognition::{
+    output_provider::WhisperFormatsOutputProvider, run_speech_recognizer,
+    DecodingStrategy, SpeechRecognizerTask, WhichModel,
+};
+
+/// Transcribes a 16 kHz audio file on the GPU if there is one, writing the
+/// outputs of all the formats of OpenAI's Whisper CLI, e.g.
+/// `transcribe --model whisper-large-v3 --language en --output-dir out in.wav`.
+#[derive(Parser, Debug)]
+struct Args {
+    input: PathBuf,
+    /// The model, by its repository like `openai/whisper-large-v3`, or its
+    /// name alone.
+    #[arg(long, default_value = "openai/whisper-large-v3")]
+    model: WhichModel,
+    /// The directory the models were downloaded into by `download_models`.
+    #[arg(long, default_value = "./models_data")]
+    models_data_dir: PathBuf,
+    /// The language of the audio, detected if not set.
+    #[arg(long)]
+    language: Option<String>,
+    /// The text to prompt the decoder with, like the names in the audio.
+    #[arg(long)]
+    initial_prompt: Option<String>,
+    /// Decode with beam search of the size instead of greedily.
+    #[arg(long)]
+    beam_size: Option<usize>,
+    /// The directory the outputs are written to, named after the input.
+    #[arg(long, default_value = ".")]
+    output_dir: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    stderrlog::new()
+        .module("trakktor_candle::speech_recognition")
+        .verbosity(log::Level::Warn)
+        .init()?;
+
+    let Some(name) = args.input.file_stem() else {
+        anyhow::bail!("no file name in {}", args.input.display());
+    };
+    let output = WhisperFormatsOutputProvider::new(
+        &args.output_dir,
+        name.to_string_lossy(),
+        args.language.clone(),
+    );
+
+    run_speech_recognizer(
+        SpeechRecognizerTask {
+            models_data_dir: args.models_data_dir,
+            model: args.model,
+            device: Device::cuda_if_available(0)?,
+            input: args.input,
+            language: args.language,
+            seed: None,
+            initial_prompt: args.initial_prompt,
+            decoding: match args.beam_size {
+                Some(beam_size) => DecodingStrategy::BeamSearch {
+                    beam_size,
+                    length_penalty: None,
+                },
+                None => DecodingStrategy::Greedy,
+            },
+        },
+        Box::new(output),
+    )?;
+
+    Ok(())
+}
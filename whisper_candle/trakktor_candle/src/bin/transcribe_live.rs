@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use candle_core::Device;
+use trakktor_candle::speech_recognition::{
+    live::{run_live_recognizer, LiveRecognizerTask, LiveSource},
+    output_provider::{
+        TerminalOutputProvider, TextOutputProvider, TimestampFormat,
+    },
+    DecodingStrategy, WhichModel,
+};
+
+/// Transcribes the PCM piped to stdin live, or the microphone with
+/// `--microphone`, e.g.
+/// `ffmpeg -i <input> -f s16le -ar 16000 -ac 1 - | transcribe_live`.
+fn main() -> anyhow::Result<()> {
+    stderrlog::new()
+        .module("trakktor_candle::speech_recognition")
+        .verbosity(log::Level::Warn)
+        .init()?;
+
+    let source = match std::env::args().nth(1).as_deref() {
+        None => LiveSource::Stdin,
+        #[cfg(feature = "microphone")]
+        Some("--microphone") => LiveSource::Microphone,
+        Some(arg) => anyhow::bail!("unexpected argument {arg}"),
+    };
+    let output = TextOutputProvider::new("tmp_data/live.txt")?;
+
+    run_live_recognizer(
+        LiveRecognizerTask {
+            models_data_dir: "./models_data".into(),
+            model: WhichModel::Small,
+            device: Device::new_metal(0)?,
+            source,
+            language: Some(
+                std::env::var("TRK_LANGUAGE").unwrap_or_else(|_| "en".into()),
+            ),
+            seed: None,
+            initial_prompt: std::env::var("TRK_INITIAL_PROMPT").ok(),
+            decoding: DecodingStrategy::Greedy,
+            step: Duration::from_secs(2),
+        },
+        Box::new(TerminalOutputProvider::new(
+            TimestampFormat::StartEnd,
+            Some(Box::new(output)),
+        )),
+    )?;
+
+    Ok(())
+}
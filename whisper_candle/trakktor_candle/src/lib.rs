@@ -1,3 +1,4 @@
+pub mod models;
 pub mod speech_recognition;
 
 pub fn add(left: usize, right: usize) -> usize { left + right }
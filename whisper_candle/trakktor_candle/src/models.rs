@@ -0,0 +1,294 @@
+//! Downloading of the model files from the Hugging Face hub into the
+//! directories they are loaded from. The files are downloaded concurrently
+//! into `.part` files, resumed on a retry, and renamed once their size and
+//! SHA256 match the metadata of the hub.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::speech_recognition::{DataFile, WhichModel};
+
+const HUB_URL: &str = "https://huggingface.co";
+/// How many times a file is fetched before giving up.
+const ATTEMPTS: u32 = 5;
+const BUFFER_SIZE: usize = 1 << 20;
+
+/// The directory the files of the model are kept in.
+pub fn model_dir(data_dir: &Path, which: WhichModel) -> PathBuf {
+    data_dir.join(which.model_and_revision().0)
+}
+
+/// Downloads the files of the models missing from the data directory.
+pub fn download_models(data_dir: &Path, models: &[WhichModel]) -> Result<()> {
+    let mut files = vec![];
+    for &which in models {
+        let dir = model_dir(data_dir, which);
+        std::fs::create_dir_all(&dir)?;
+        let info = fetch_repo_info(which)
+            .with_context(|| format!("failed to get the files of {which:?}"))?;
+        files.extend(remote_files(which, &dir, &info)?);
+    }
+
+    let files = files
+        .into_iter()
+        .filter(|file| {
+            let exists = is_downloaded(file);
+            if exists {
+                log::info!("{} already exists", file.path.display());
+            }
+            !exists
+        })
+        .collect::<Vec<_>>();
+
+    let failed = std::thread::scope(|scope| {
+        let handles = files
+            .iter()
+            .map(|file| (file, scope.spawn(|| download_file(file))))
+            .collect::<Vec<_>>();
+        let mut failed = 0;
+        for (file, handle) in handles {
+            let result = handle
+                .join()
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("download panicked")));
+            if let Err(err) = result {
+                log::error!("{}: {err:#}", file.path.display());
+                failed += 1;
+            }
+        }
+        failed
+    });
+    if failed > 0 {
+        anyhow::bail!("failed to download {failed} of {} files", files.len());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct RepoInfo {
+    siblings: Vec<Sibling>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Sibling {
+    rfilename: String,
+    size: Option<u64>,
+    lfs: Option<LfsInfo>,
+}
+
+/// Only the files stored in LFS have their SHA256 in the metadata.
+#[derive(Debug, Deserialize)]
+struct LfsInfo {
+    sha256: String,
+}
+
+/// A file of the model with the metadata it is verified against.
+#[derive(Debug, Clone, PartialEq)]
+struct RemoteFile {
+    url: String,
+    path: PathBuf,
+    size: Option<u64>,
+    sha256: Option<String>,
+}
+
+fn fetch_repo_info(which: WhichModel) -> Result<RepoInfo> {
+    let (model, revision) = which.model_and_revision();
+    let url = format!(
+        "{HUB_URL}/api/models/{model}/revision/{}?blobs=true",
+        encode_revision(revision)
+    );
+    let response = ureq::get(&url).call()?;
+    Ok(serde_json::from_reader(response.into_reader())?)
+}
+
+/// The revisions like `refs/pr/15` are a single segment of the URLs.
+fn encode_revision(revision: &str) -> String { revision.replace('/', "%2F") }
+
+fn remote_files(
+    which: WhichModel,
+    dir: &Path,
+    info: &RepoInfo,
+) -> Result<Vec<RemoteFile>> {
+    let (model, revision) = which.model_and_revision();
+    enum_iterator::all::<DataFile>()
+        .map(DataFile::file_name)
+        .map(|file_name| {
+            let sibling = info
+                .siblings
+                .iter()
+                .find(|s| s.rfilename == file_name)
+                .with_context(|| format!("{model} has no {file_name}"))?;
+            Ok(RemoteFile {
+                url: format!(
+                    "{HUB_URL}/{model}/resolve/{}/{file_name}",
+                    encode_revision(revision)
+                ),
+                path: dir.join(file_name),
+                size: sibling.size,
+                sha256: sibling.lfs.as_ref().map(|lfs| lfs.sha256.clone()),
+            })
+        })
+        .collect()
+}
+
+/// A file of the expected size is taken as downloaded, as it is only
+/// renamed into place once verified.
+fn is_downloaded(file: &RemoteFile) -> bool {
+    match std::fs::metadata(&file.path) {
+        Ok(metadata) => file.size.is_none_or(|size| metadata.len() == size),
+        Err(_) => false,
+    }
+}
+
+fn download_file(file: &RemoteFile) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        match fetch_file(file) {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt < ATTEMPTS => {
+                let delay = Duration::from_secs(2u64.pow(attempt));
+                log::warn!(
+                    "{}: {err:#}, retrying in {delay:?}",
+                    file.path.display()
+                );
+                std::thread::sleep(delay);
+                attempt += 1;
+            },
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    part.into()
+}
+
+/// Fetches the file into its `.part` file, continuing from what an earlier
+/// attempt has left, and moves it into place once verified.
+fn fetch_file(file: &RemoteFile) -> Result<()> {
+    let part = part_path(&file.path);
+    let mut hasher = Sha256::new();
+    let mut offset = match File::open(&part) {
+        Ok(mut existing) => std::io::copy(&mut existing, &mut hasher)?,
+        Err(_) => 0,
+    };
+    if file.size.is_some_and(|size| offset > size) {
+        hasher = Sha256::new();
+        offset = 0;
+    }
+
+    if offset == 0 || file.size != Some(offset) {
+        let mut request = ureq::get(&file.url);
+        if offset > 0 {
+            log::info!("resuming {} at {offset} bytes", file.path.display());
+            request = request.set("Range", &format!("bytes={offset}-"));
+        }
+        let response = request.call()?;
+        let mut out = if offset > 0 && response.status() == 206 {
+            OpenOptions::new().append(true).open(&part)?
+        } else {
+            hasher = Sha256::new();
+            File::create(&part)?
+        };
+        let mut reader = response.into_reader();
+        let mut buf = vec![0; BUFFER_SIZE];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            out.write_all(&buf[..n])?;
+            hasher.update(&buf[..n]);
+        }
+        out.sync_all()?;
+    }
+
+    let len = std::fs::metadata(&part)?.len();
+    if let Some(size) = file.size.filter(|&size| len < size) {
+        // Kept for the next attempt to continue from.
+        anyhow::bail!("got {len} of {size} bytes");
+    }
+    if let Err(err) = verify(&part, file, &format!("{:x}", hasher.finalize())) {
+        std::fs::remove_file(&part)?;
+        return Err(err);
+    }
+    std::fs::rename(&part, &file.path)?;
+    log::info!("{} downloaded", file.path.display());
+    Ok(())
+}
+
+fn verify(part: &Path, file: &RemoteFile, sha256: &str) -> Result<()> {
+    let len = std::fs::metadata(part)?.len();
+    if let Some(size) = file.size.filter(|&size| size != len) {
+        anyhow::bail!("got {len} bytes instead of {size}");
+    }
+    if let Some(expected) = file.sha256.as_deref().filter(|&e| e != sha256) {
+        anyhow::bail!("SHA256 {sha256} does not match {expected}");
+    }
+    Ok(())
+}
+
+#[test]
+fn remote_files_test() -> Result<()> {
+    let info: RepoInfo = serde_json::from_str(
+        r#"{"siblings": [
+            {"rfilename": "config.json", "size": 1272},
+            {"rfilename": "tokenizer.json", "size": 2480466},
+            {"rfilename": "model.safetensors", "size": 151061672,
+             "lfs": {"sha256": "ab12", "size": 151061672}}
+        ]}"#,
+    )?;
+    let files = remote_files(WhichModel::TinyEn, Path::new("data"), &info)?;
+    assert_eq!(files.len(), 3);
+    assert_eq!(
+        files[0].url,
+        "https://huggingface.co/openai/whisper-tiny.en/resolve/refs%2Fpr%2F15/config.json"
+    );
+    assert_eq!(files[0].sha256, None);
+    assert_eq!(files[2].path, Path::new("data/model.safetensors"));
+    assert_eq!(files[2].size, Some(151061672));
+    assert_eq!(files[2].sha256.as_deref(), Some("ab12"));
+
+    let info = RepoInfo { siblings: vec![] };
+    assert!(remote_files(WhichModel::Tiny, Path::new("data"), &info).is_err());
+    Ok(())
+}
+
+#[test]
+fn verify_test() -> Result<()> {
+    let dir = std::env::temp_dir()
+        .join(format!("trakktor-candle-models-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("model.safetensors");
+    std::fs::write(part_path(&path), b"abc")?;
+
+    let sha256 =
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    let file = RemoteFile {
+        url: String::new(),
+        path: path.clone(),
+        size: Some(3),
+        sha256: Some(sha256.into()),
+    };
+    let digest = format!("{:x}", Sha256::digest(b"abc"));
+    assert!(verify(&part_path(&path), &file, &digest).is_ok());
+    assert!(verify(&part_path(&path), &file, "0000").is_err());
+    let file = RemoteFile {
+        size: Some(4),
+        ..file
+    };
+    assert!(verify(&part_path(&path), &file, &digest).is_err());
+    assert!(!is_downloaded(&file));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,295 @@
+//! Benchmark of the models against a reference dataset: a directory of audio
+//! files, each with its ground-truth transcript in a `.txt` file of the same
+//! name. The word and character error rates and the realtime factor are
+//! computed for every model on every device, to pick a model objectively.
+
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use candle_core::Device;
+use candle_transformers::models::whisper as m;
+
+use crate::speech_recognition::{
+    check_decoding, language_token, load_model,
+    output_provider::{Segment, SpeechRecognitionOutputProvider},
+    pcm_decode::pcm_decode,
+    pcm_to_mel, Decoder, DecodingStrategy, WhichModel,
+};
+
+/// An audio file of the dataset with its reference transcript.
+#[derive(Debug, Clone)]
+pub struct BenchItem {
+    pub audio: PathBuf,
+    pub reference: String,
+}
+
+/// Loads the audio files of the directory that have a `.txt` reference
+/// next to them, sorted by name.
+pub fn load_dataset(dir: &Path) -> Result<Vec<BenchItem>> {
+    let mut items = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let audio = entry?.path();
+        if !audio.is_file() || audio.extension().is_some_and(|ext| ext == "txt")
+        {
+            continue;
+        }
+        let reference = audio.with_extension("txt");
+        if !reference.exists() {
+            log::warn!("no reference for {}, skipping", audio.display());
+            continue;
+        }
+        items.push(BenchItem {
+            reference: std::fs::read_to_string(&reference)?,
+            audio,
+        });
+    }
+    if items.is_empty() {
+        anyhow::bail!("no audio with references in {}", dir.display());
+    }
+    items.sort_by(|a, b| a.audio.cmp(&b.audio));
+    Ok(items)
+}
+
+#[derive(Debug)]
+pub struct BenchTask {
+    pub models_data_dir: PathBuf,
+    pub models: Vec<WhichModel>,
+    /// The devices with their names for the table.
+    pub devices: Vec<(String, Device)>,
+    pub language: Option<String>,
+    pub decoding: DecodingStrategy,
+}
+
+/// The results of a model on a device, over the whole dataset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    pub model: WhichModel,
+    pub device: String,
+    pub files: usize,
+    /// Word error rate, the word edits over the reference words.
+    pub wer: f64,
+    /// Character error rate, the character edits over the reference
+    /// characters.
+    pub cer: f64,
+    /// The processing time over the duration of the audio.
+    pub realtime_factor: f64,
+}
+
+pub fn run_bench(
+    task: &BenchTask,
+    dataset: &[BenchItem],
+) -> Result<Vec<BenchResult>> {
+    check_decoding(task.decoding)?;
+    let mut results = vec![];
+    for &model in &task.models {
+        for (device_name, device) in &task.devices {
+            log::info!("benchmarking {model:?} on {device_name}");
+            let result = bench_model(task, model, device_name, device, dataset)
+                .with_context(|| {
+                    format!("failed to benchmark {model:?} on {device_name}")
+                })?;
+            results.push(result);
+        }
+    }
+    Ok(results)
+}
+
+fn bench_model(
+    task: &BenchTask,
+    which: WhichModel,
+    device_name: &str,
+    device: &Device,
+    dataset: &[BenchItem],
+) -> Result<BenchResult> {
+    let loaded = load_model(&task.models_data_dir, which, device)?;
+    let mut dc = Decoder::new(
+        loaded.model,
+        loaded.tokenizer,
+        299792458,
+        device,
+        None,
+        None,
+        false,
+        false,
+        None,
+        task.decoding,
+    )?;
+
+    let mut errors = ErrorCounts::default();
+    let mut audio_seconds = 0f64;
+    let mut processing_seconds = 0f64;
+    for item in dataset {
+        let (pcm_data, sample_rate) = pcm_decode(&item.audio)?;
+        if sample_rate != m::SAMPLE_RATE as u32 {
+            anyhow::bail!(
+                "{} must have a {} sampling rate",
+                item.audio.display(),
+                m::SAMPLE_RATE
+            )
+        }
+        audio_seconds += pcm_data.len() as f64 / m::SAMPLE_RATE as f64;
+
+        let start = Instant::now();
+        let mel = pcm_to_mel(
+            dc.model.config(),
+            &pcm_data,
+            &loaded.mel_filters,
+            device,
+        )?;
+        dc.language_token = language_token(
+            which,
+            task.language.clone(),
+            &mut dc.model,
+            &dc.tokenizer,
+            Some(&mel),
+        )?;
+        let output = CollectedText::default();
+        dc.run(&mel, Box::new(output.clone()))?;
+        processing_seconds += start.elapsed().as_secs_f64();
+
+        let hypothesis = output.0.borrow().join(" ");
+        log::debug!("{}: {hypothesis}", item.audio.display());
+        errors.add(&item.reference, &hypothesis);
+    }
+
+    Ok(BenchResult {
+        model: which,
+        device: device_name.to_string(),
+        files: dataset.len(),
+        wer: errors.wer(),
+        cer: errors.cer(),
+        realtime_factor: processing_seconds / audio_seconds,
+    })
+}
+
+/// Collects the text of the segments.
+#[derive(Clone, Default)]
+struct CollectedText(Rc<RefCell<Vec<String>>>);
+
+impl SpeechRecognitionOutputProvider for CollectedText {
+    fn start(&mut self) -> Result<()> { Ok(()) }
+
+    fn add_segment(&mut self, s: Segment) -> Result<()> {
+        self.0.borrow_mut().push(s.dr.text.trim().to_string());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> { Ok(()) }
+}
+
+/// The edits and the reference lengths summed over the dataset, so the error
+/// rates are weighted by the length of the references.
+#[derive(Debug, Default)]
+struct ErrorCounts {
+    word_edits: usize,
+    words: usize,
+    char_edits: usize,
+    chars: usize,
+}
+
+impl ErrorCounts {
+    fn add(&mut self, reference: &str, hypothesis: &str) {
+        let reference = normalize(reference);
+        let hypothesis = normalize(hypothesis);
+        self.word_edits += edit_distance(&reference, &hypothesis);
+        self.words += reference.len();
+
+        let reference = reference.join(" ").chars().collect::<Vec<_>>();
+        let hypothesis = hypothesis.join(" ").chars().collect::<Vec<_>>();
+        self.char_edits += edit_distance(&reference, &hypothesis);
+        self.chars += reference.len();
+    }
+
+    fn wer(&self) -> f64 { self.word_edits as f64 / self.words.max(1) as f64 }
+
+    fn cer(&self) -> f64 { self.char_edits as f64 / self.chars.max(1) as f64 }
+}
+
+/// The lowercase words of the text, without the punctuation.
+fn normalize(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .map(|word| word.trim_matches('\''))
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The Levenshtein distance: the substitutions, deletions and insertions
+/// turning the reference into the hypothesis.
+fn edit_distance<T: PartialEq>(reference: &[T], hypothesis: &[T]) -> usize {
+    let mut prev = (0..=hypothesis.len()).collect::<Vec<_>>();
+    let mut row = vec![0; hypothesis.len() + 1];
+    for (i, r) in reference.iter().enumerate() {
+        row[0] = i + 1;
+        for (j, h) in hypothesis.iter().enumerate() {
+            let substitution = prev[j] + usize::from(r != h);
+            row[j + 1] = substitution.min(prev[j + 1] + 1).min(row[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut row);
+    }
+    prev[hypothesis.len()]
+}
+
+/// The comparison table of the results, the most accurate first.
+pub fn format_table(results: &[BenchResult]) -> String {
+    let mut results = results.to_vec();
+    results.sort_by(|a, b| a.wer.total_cmp(&b.wer));
+    let mut table = format!(
+        "{:<16} {:<8} {:>5} {:>7} {:>7} {:>6}\n",
+        "model", "device", "files", "WER", "CER", "RTF"
+    );
+    for r in results {
+        table.push_str(&format!(
+            "{:<16} {:<8} {:>5} {:>6.1}% {:>6.1}% {:>6.2}\n",
+            format!("{:?}", r.model),
+            r.device,
+            r.files,
+            r.wer * 100.0,
+            r.cer * 100.0,
+            r.realtime_factor
+        ));
+    }
+    table
+}
+
+#[test]
+fn error_rates_test() {
+    assert_eq!(edit_distance(&[1, 2, 3], &[1, 3, 4]), 2);
+    assert_eq!(edit_distance::<u8>(&[], &[1, 2]), 2);
+    assert_eq!(
+        normalize("Hello, world! It's  me."),
+        ["hello", "world", "it's", "me"]
+    );
+
+    let mut errors = ErrorCounts::default();
+    errors.add("The cat sat on the mat.", "the cat sat on a mat");
+    assert!((errors.wer() - 1.0 / 6.0).abs() < 1e-9);
+    // "the" is turned into "a" with a substitution and two deletions.
+    assert!((errors.cer() - 3.0 / 22.0).abs() < 1e-9);
+}
+
+#[test]
+fn format_table_test() {
+    let result = |model, wer| BenchResult {
+        model,
+        device: "cpu".into(),
+        files: 3,
+        wer,
+        cer: 0.02,
+        realtime_factor: 0.5,
+    };
+    let table = format_table(&[
+        result(WhichModel::Small, 0.12),
+        result(WhichModel::LargeV3, 0.05),
+    ]);
+    let lines = table.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("LargeV3 "));
+    assert!(lines[1].ends_with("   5.0%    2.0%   0.50"));
+}
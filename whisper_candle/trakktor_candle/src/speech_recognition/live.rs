@@ -0,0 +1,297 @@
+//! Live transcription of the audio coming from a microphone or stdin. The
+//! audio is kept in a rolling window of up to 30 seconds, decoded again as
+//! new audio comes, and the window is output as a final segment once it is
+//! full.
+
+use std::{
+    io::Read,
+    sync::mpsc::{self, Receiver, Sender},
+    time::Duration,
+};
+
+use anyhow::Result;
+use candle_core::Device;
+use candle_transformers::models::whisper as m;
+
+use crate::speech_recognition::{
+    check_decoding, is_silence, language_token, load_model,
+    output_provider::{Segment, StreamingOutputProvider},
+    pcm_to_mel, Decoder, DecodingStrategy, WhichModel,
+};
+
+/// The size of the chunks the PCM is read from stdin in, 0.1 s.
+const STDIN_CHUNK_BYTES: usize = m::SAMPLE_RATE / 10 * 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LiveSource {
+    /// Raw 16 kHz mono signed 16-bit little-endian PCM, e.g. from
+    /// `ffmpeg -i <input> -f s16le -ar 16000 -ac 1 -`.
+    Stdin,
+    /// The default input device.
+    #[cfg(feature = "microphone")]
+    Microphone,
+}
+
+#[derive(Debug)]
+pub struct LiveRecognizerTask {
+    pub models_data_dir: std::path::PathBuf,
+    pub model: WhichModel,
+    pub device: Device,
+    pub source: LiveSource,
+    /// The language of the audio, required for the multilingual models, as
+    /// there is no audio to detect it from at the start.
+    pub language: Option<String>,
+    pub seed: Option<u64>,
+    pub initial_prompt: Option<String>,
+    pub decoding: DecodingStrategy,
+    /// How much new audio is awaited before the window is decoded again.
+    pub step: Duration,
+}
+
+pub fn run_live_recognizer(
+    task: LiveRecognizerTask,
+    mut output_provider: Box<dyn StreamingOutputProvider>,
+) -> Result<()> {
+    check_decoding(task.decoding)?;
+    let mut loaded =
+        load_model(&task.models_data_dir, task.model, &task.device)?;
+    let language_token = language_token(
+        task.model,
+        task.language,
+        &mut loaded.model,
+        &loaded.tokenizer,
+        None,
+    )?;
+    let config = loaded.model.config().clone();
+    let mel_filters = std::mem::take(&mut loaded.mel_filters);
+    let mut dc = Decoder::new(
+        loaded.model,
+        loaded.tokenizer,
+        task.seed.unwrap_or(299792458),
+        &task.device,
+        language_token,
+        None,
+        false,
+        false,
+        task.initial_prompt.as_deref(),
+        task.decoding,
+    )?;
+
+    let (tx, rx) = mpsc::channel();
+    let _capture = start_capture(task.source, tx)?;
+    let step_samples =
+        (task.step.as_secs_f64() * m::SAMPLE_RATE as f64) as usize;
+
+    let mut window: Vec<f32> = vec![];
+    let mut window_start = 0f64;
+    let mut pending = 0;
+    output_provider.start()?;
+    loop {
+        let ended = !receive(&rx, &mut window, &mut pending);
+        let full = window.len() >= m::N_SAMPLES;
+        if !(ended || full || pending >= step_samples) {
+            continue;
+        }
+        pending = 0;
+
+        if !window.is_empty() {
+            let len = window.len().min(m::N_SAMPLES);
+            let mel = pcm_to_mel(
+                &config,
+                &window[..len],
+                &mel_filters,
+                &task.device,
+            )?;
+            let (_, _, frames) = mel.dims3()?;
+            let mel = mel.narrow(2, 0, frames.min(m::N_FRAMES))?;
+            let dr = dc.decode_with_fallback(&mel)?;
+            let segment = Segment {
+                start: window_start,
+                duration: len as f64 / m::SAMPLE_RATE as f64,
+                dr,
+            };
+            if full || ended {
+                window.drain(..len);
+                window_start += segment.duration;
+                if !is_silence(&segment.dr) {
+                    output_provider.add_segment(segment)?;
+                }
+            } else if !is_silence(&segment.dr) {
+                output_provider.update(&segment)?;
+            }
+        }
+
+        if ended && window.is_empty() {
+            break;
+        }
+    }
+    output_provider.finish()?;
+    Ok(())
+}
+
+/// Appends the received samples to the window, waiting for the first ones.
+/// Returns `false` once the source has ended.
+fn receive(
+    rx: &Receiver<Vec<f32>>,
+    window: &mut Vec<f32>,
+    pending: &mut usize,
+) -> bool {
+    let Ok(samples) = rx.recv() else {
+        return false;
+    };
+    *pending += samples.len();
+    window.extend(samples);
+    while let Ok(samples) = rx.try_recv() {
+        *pending += samples.len();
+        window.extend(samples);
+    }
+    true
+}
+
+/// Keeps the audio captured while it is alive.
+enum Capture {
+    Stdin,
+    #[cfg(feature = "microphone")]
+    Microphone {
+        _stream: cpal::Stream,
+    },
+}
+
+fn start_capture(source: LiveSource, tx: Sender<Vec<f32>>) -> Result<Capture> {
+    match source {
+        LiveSource::Stdin => {
+            // The thread ends along with the input, closing the channel.
+            std::thread::spawn(move || {
+                if let Err(err) = read_stdin(tx) {
+                    log::error!("Failed to read stdin: {err}");
+                }
+            });
+            Ok(Capture::Stdin)
+        },
+        #[cfg(feature = "microphone")]
+        LiveSource::Microphone => Ok(Capture::Microphone {
+            _stream: microphone::start_stream(tx)?,
+        }),
+    }
+}
+
+fn read_stdin(tx: Sender<Vec<f32>>) -> Result<()> {
+    let mut stdin = std::io::stdin().lock();
+    let mut buf = vec![0u8; STDIN_CHUNK_BYTES];
+    // A byte of a sample split between the reads.
+    let mut odd_byte = None;
+    loop {
+        let offset = usize::from(odd_byte.is_some());
+        if let Some(byte) = odd_byte.take() {
+            buf[0] = byte;
+        }
+        let n = stdin.read(&mut buf[offset..])?;
+        if n == 0 {
+            return Ok(());
+        }
+        let len = offset + n;
+        if len % 2 == 1 {
+            odd_byte = Some(buf[len - 1]);
+        }
+        if tx.send(s16le_to_f32(&buf[..len - len % 2])).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Converts signed 16-bit little-endian PCM into the samples.
+fn s16le_to_f32(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+        .collect()
+}
+
+/// Mixes the interleaved channels down to mono and resamples it to 16 kHz
+/// linearly.
+#[cfg_attr(not(feature = "microphone"), allow(dead_code))]
+fn to_whisper_samples(
+    interleaved: &[f32],
+    channels: usize,
+    sample_rate: u32,
+) -> Vec<f32> {
+    let mono = interleaved
+        .chunks(channels.max(1))
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect::<Vec<_>>();
+    if sample_rate == m::SAMPLE_RATE as u32 || mono.is_empty() {
+        return mono;
+    }
+    let ratio = sample_rate as f64 / m::SAMPLE_RATE as f64;
+    let len = (mono.len() as f64 / ratio) as usize;
+    (0..len)
+        .map(|i| {
+            let pos = i as f64 * ratio;
+            let j = pos as usize;
+            let frac = (pos - j as f64) as f32;
+            let next = mono.get(j + 1).copied().unwrap_or(mono[j]);
+            mono[j] * (1.0 - frac) + next * frac
+        })
+        .collect()
+}
+
+#[cfg(feature = "microphone")]
+mod microphone {
+    use std::sync::mpsc::Sender;
+
+    use anyhow::{Context, Result};
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+    use super::to_whisper_samples;
+
+    pub(super) fn start_stream(tx: Sender<Vec<f32>>) -> Result<cpal::Stream> {
+        let device = cpal::default_host()
+            .default_input_device()
+            .context("no input device")?;
+        let config = device.default_input_config()?;
+        log::info!("capturing {:?} from {:?}", config, device.name());
+        let channels = config.channels() as usize;
+        let sample_rate = config.sample_rate().0;
+        let on_error = |err| log::error!("Audio input failed: {err}");
+
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => device.build_input_stream(
+                &config.into(),
+                move |data: &[f32], _: &_| {
+                    let samples =
+                        to_whisper_samples(data, channels, sample_rate);
+                    tx.send(samples).ok();
+                },
+                on_error,
+                None,
+            )?,
+            cpal::SampleFormat::I16 => device.build_input_stream(
+                &config.into(),
+                move |data: &[i16], _: &_| {
+                    let data = data
+                        .iter()
+                        .map(|&s| s as f32 / 32768.0)
+                        .collect::<Vec<_>>();
+                    let samples =
+                        to_whisper_samples(&data, channels, sample_rate);
+                    tx.send(samples).ok();
+                },
+                on_error,
+                None,
+            )?,
+            format => anyhow::bail!("unsupported sample format {format}"),
+        };
+        stream.play()?;
+        Ok(stream)
+    }
+}
+
+#[test]
+fn pcm_conversion_test() {
+    assert_eq!(s16le_to_f32(&[0x00, 0x40, 0x00, 0xc0, 0xff]), [0.5, -0.5]);
+    // Stereo at 32 kHz: every other frame is kept after mixing down.
+    let samples = to_whisper_samples(&[0.2, 0.4, 0.0, 0.0, 0.6, 0.8], 2, 32000);
+    assert_eq!(samples.len(), 1);
+    assert!((samples[0] - 0.3).abs() < 1e-6);
+    assert_eq!(to_whisper_samples(&[0.1, 0.2], 1, 16000), [0.1, 0.2]);
+}
@@ -12,6 +12,11 @@ use crate::speech_recognition::output_provider::{
     DecodingResult, Segment, SpeechRecognitionOutputProvider,
 };
 
+/// The token the previous text, or the initial prompt, is given after.
+const SOT_PREV_TOKEN: &str = "<|startofprev|>";
+
+pub mod bench;
+pub mod live;
 mod multilingual;
 pub mod output_provider;
 mod pcm_decode;
@@ -93,6 +98,10 @@ struct Decoder {
     no_speech_token: u32,
     no_timestamps_token: u32,
     language_token: Option<u32>,
+    /// The context the decoding starts with, `<|startofprev|>` followed by
+    /// the tokens of the initial prompt, or nothing without a prompt.
+    prompt_tokens: Vec<u32>,
+    strategy: DecodingStrategy,
 }
 
 impl Decoder {
@@ -106,6 +115,8 @@ impl Decoder {
         task: Option<Task>,
         timestamps: bool,
         verbose: bool,
+        initial_prompt: Option<&str>,
+        strategy: DecodingStrategy,
     ) -> Result<Self> {
         let no_timestamps_token = token_id(&tokenizer, m::NO_TIMESTAMPS_TOKEN)?;
         // Suppress the notimestamps token when in timestamps mode.
@@ -133,6 +144,14 @@ impl Decoder {
             None => anyhow::bail!("unable to find any non-speech token"),
             Some(n) => n,
         };
+        let prompt_tokens = match initial_prompt {
+            Some(prompt) => prompt_tokens(
+                &tokenizer,
+                prompt,
+                model.config().max_target_positions,
+            )?,
+            None => vec![],
+        };
         Ok(Self {
             model,
             rng: rand::rngs::StdRng::seed_from_u64(seed),
@@ -148,17 +167,17 @@ impl Decoder {
             no_speech_token,
             language_token,
             no_timestamps_token,
+            prompt_tokens,
+            strategy,
         })
     }
 
-    fn decode(&mut self, mel: &Tensor, t: f64) -> Result<DecodingResult> {
-        let model = &mut self.model;
-        let audio_features = model.encoder_forward(mel, true)?;
-        log::info!("audio features: {:?}", audio_features.dims());
-        let sample_len = model.config().max_target_positions / 2;
-        let mut sum_logprob = 0f64;
-        let mut no_speech_prob = f64::NAN;
-        let mut tokens = vec![self.sot_token];
+    /// The tokens the decoding starts with, and the index of the
+    /// `<|startoftranscript|>` token among them, after the prompt.
+    fn initial_tokens(&self) -> (Vec<u32>, usize) {
+        let mut tokens = self.prompt_tokens.clone();
+        let sot_index = tokens.len();
+        tokens.push(self.sot_token);
         if let Some(language_token) = self.language_token {
             tokens.push(language_token);
         }
@@ -170,6 +189,17 @@ impl Decoder {
         if !self.timestamps {
             tokens.push(self.no_timestamps_token);
         }
+        (tokens, sot_index)
+    }
+
+    fn decode(&mut self, mel: &Tensor, t: f64) -> Result<DecodingResult> {
+        let (mut tokens, sot_index) = self.initial_tokens();
+        let model = &mut self.model;
+        let audio_features = model.encoder_forward(mel, true)?;
+        log::info!("audio features: {:?}", audio_features.dims());
+        let sample_len = model.config().max_target_positions / 2;
+        let mut sum_logprob = 0f64;
+        let mut no_speech_prob = f64::NAN;
         for i in 0..sample_len {
             let tokens_t = Tensor::new(tokens.as_slice(), mel.device())?;
 
@@ -180,11 +210,14 @@ impl Decoder {
                 model.decoder_forward(&tokens_t, &audio_features, i == 0)?;
 
             // Extract the no speech probability on the first iteration by
-            // looking at the first token logits and the probability
+            // looking at the logits of the `<|startoftranscript|>` token,
+            // which follows the prompt if there is one, and the probability
             // for the according token.
             if i == 0 {
-                let logits =
-                    model.decoder_final_linear(&ys.i(..1)?)?.i(0)?.i(0)?;
+                let logits = model
+                    .decoder_final_linear(&ys.i(..1)?)?
+                    .i(0)?
+                    .i(sot_index)?;
                 no_speech_prob = softmax(&logits, 0)?
                     .i(self.no_speech_token as usize)?
                     .to_scalar::<f32>()?
@@ -229,8 +262,11 @@ impl Decoder {
             }
             sum_logprob += prob.ln();
         }
+        // The prompt is not a part of the result.
+        let tokens = tokens.split_off(sot_index);
         let text = self.tokenizer.decode(&tokens, true).map_err(E::msg)?;
         let avg_logprob = sum_logprob / tokens.len() as f64;
+        let compression_ratio = compression_ratio(&text);
 
         Ok(DecodingResult {
             tokens,
@@ -238,7 +274,116 @@ impl Decoder {
             avg_logprob,
             no_speech_prob,
             temperature: t,
-            compression_ratio: f64::NAN,
+            compression_ratio,
+        })
+    }
+
+    /// Decodes keeping the most probable sequences of the beam size at each
+    /// step, until as many of them are finished. The best of the finished
+    /// ones by the log probability divided by the length penalty is chosen.
+    /// https://github.com/openai/whisper/blob/e8622f9afc4eba139bf796c210f5c01081000472/whisper/decoding.py#L299
+    fn decode_beam_search(
+        &mut self,
+        mel: &Tensor,
+        beam_size: usize,
+        length_penalty: Option<f64>,
+    ) -> Result<DecodingResult> {
+        let (initial_tokens, sot_index) = self.initial_tokens();
+        let model = &mut self.model;
+        let audio_features = model.encoder_forward(mel, true)?;
+        let sample_len = model.config().max_target_positions / 2;
+        let max_len = model.config().max_target_positions;
+        let mut no_speech_prob = f64::NAN;
+        let mut beams = vec![(initial_tokens.clone(), 0f64)];
+        let mut finished: Vec<(Vec<u32>, f64)> = vec![];
+        for i in 0..sample_len {
+            let mut candidates = vec![];
+            for (tokens, sum_logprob) in &beams {
+                let tokens_t = Tensor::new(tokens.as_slice(), mel.device())?
+                    .unsqueeze(0)?;
+                // All the beams attend to the same audio features, cached on
+                // the first iteration.
+                let ys = model.decoder_forward(
+                    &tokens_t,
+                    &audio_features,
+                    i == 0,
+                )?;
+                // As in `decode`, at the `<|startoftranscript|>` token.
+                if i == 0 {
+                    let logits = model
+                        .decoder_final_linear(&ys.i(..1)?)?
+                        .i(0)?
+                        .i(sot_index)?;
+                    no_speech_prob = softmax(&logits, 0)?
+                        .i(self.no_speech_token as usize)?
+                        .to_scalar::<f32>()?
+                        as f64;
+                }
+                let (_, seq_len, _) = ys.dims3()?;
+                let logits = model
+                    .decoder_final_linear(&ys.i((..1, seq_len - 1..))?)?
+                    .i(0)?
+                    .i(0)?
+                    .broadcast_add(&self.suppress_tokens)?;
+                let logprobs = log_softmax(&logits.to_vec1::<f32>()?);
+                // One more than the beam size, so enough of the candidates
+                // remain when one of them is finished.
+                for (token, logprob) in top_k(&logprobs, beam_size + 1) {
+                    let mut tokens = tokens.clone();
+                    tokens.push(token);
+                    candidates.push((tokens, sum_logprob + logprob));
+                }
+            }
+            candidates.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+            beams.clear();
+            for (tokens, sum_logprob) in candidates {
+                if tokens.last() == Some(&self.eot_token) ||
+                    tokens.len() > max_len
+                {
+                    if finished.len() < beam_size {
+                        finished.push((tokens, sum_logprob));
+                    }
+                } else if beams.len() < beam_size {
+                    beams.push((tokens, sum_logprob));
+                }
+            }
+            if finished.len() >= beam_size || beams.is_empty() {
+                break;
+            }
+        }
+        // The unfinished beams compete when too few are finished.
+        let missing = beam_size.saturating_sub(finished.len());
+        finished.extend(beams.into_iter().take(missing));
+
+        let sampled_len = |tokens: &[u32]| tokens.len() - initial_tokens.len();
+        let (mut tokens, sum_logprob) = finished
+            .into_iter()
+            .max_by(|(a_tokens, a), (b_tokens, b)| {
+                let a = a / length_penalty_of(
+                    sampled_len(a_tokens),
+                    length_penalty,
+                );
+                let b = b / length_penalty_of(
+                    sampled_len(b_tokens),
+                    length_penalty,
+                );
+                a.total_cmp(&b)
+            })
+            .unwrap_or((initial_tokens.clone(), 0.0));
+
+        // The prompt is not a part of the result.
+        let tokens = tokens.split_off(sot_index);
+        let text = self.tokenizer.decode(&tokens, true).map_err(E::msg)?;
+        let avg_logprob = sum_logprob / tokens.len() as f64;
+        let compression_ratio = compression_ratio(&text);
+
+        Ok(DecodingResult {
+            tokens,
+            text,
+            avg_logprob,
+            no_speech_prob,
+            temperature: 0.0,
+            compression_ratio,
         })
     }
 
@@ -247,7 +392,17 @@ impl Decoder {
         segment: &Tensor,
     ) -> Result<DecodingResult> {
         for (i, &t) in m::TEMPERATURES.iter().enumerate() {
-            let dr: Result<DecodingResult> = self.decode(segment, t);
+            // Like OpenAI's implementation, the beam search replaces the
+            // greedy decoding only, the higher temperatures are sampled.
+            let dr: Result<DecodingResult> = match self.strategy {
+                DecodingStrategy::BeamSearch {
+                    beam_size,
+                    length_penalty,
+                } if t == 0.0 => {
+                    self.decode_beam_search(segment, beam_size, length_penalty)
+                },
+                _ => self.decode(segment, t),
+            };
             if i == m::TEMPERATURES.len() - 1 {
                 return dr;
             }
@@ -289,9 +444,7 @@ impl Decoder {
                 (segment_size * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
             let dr = self.decode_with_fallback(&mel_segment)?;
             seek += segment_size;
-            if dr.no_speech_prob > m::NO_SPEECH_THRESHOLD &&
-                dr.avg_logprob < m::LOGPROB_THRESHOLD
-            {
+            if is_silence(&dr) {
                 log::info!("no speech detected, skipping {seek} {dr:?}");
                 continue;
             }
@@ -314,6 +467,103 @@ impl Decoder {
     }
 }
 
+/// The ratio of the size of the text to its size compressed with zlib, like
+/// OpenAI's implementation computes it. The text repeated by a hallucination
+/// compresses well, over [`m::COMPRESSION_RATIO_THRESHOLD`].
+fn compression_ratio(text: &str) -> f64 {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::ZlibEncoder::new(
+        Vec::new(),
+        flate2::Compression::default(),
+    );
+    let compressed = encoder
+        .write_all(text.as_bytes())
+        .and_then(|()| encoder.finish());
+    match compressed {
+        Ok(compressed) => text.len() as f64 / compressed.len() as f64,
+        Err(_) => f64::NAN,
+    }
+}
+
+#[test]
+fn compression_ratio_test() {
+    let repeated = "Thank you for watching. ".repeat(20);
+    assert!(compression_ratio(&repeated) > m::COMPRESSION_RATIO_THRESHOLD);
+    let text =
+        "The quick brown fox jumps over the lazy dog near the riverbank.";
+    assert!(compression_ratio(text) < m::COMPRESSION_RATIO_THRESHOLD);
+}
+
+/// The log probabilities of the logits.
+fn log_softmax(logits: &[f32]) -> Vec<f64> {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max) as f64;
+    let log_sum = logits
+        .iter()
+        .map(|&l| (l as f64 - max).exp())
+        .sum::<f64>()
+        .ln();
+    logits.iter().map(|&l| l as f64 - max - log_sum).collect()
+}
+
+/// The `k` most probable tokens with their log probabilities, the most
+/// probable first.
+fn top_k(logprobs: &[f64], k: usize) -> Vec<(u32, f64)> {
+    let mut tokens = logprobs
+        .iter()
+        .enumerate()
+        .map(|(token, &logprob)| (token as u32, logprob))
+        .collect::<Vec<_>>();
+    tokens.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    tokens.truncate(k);
+    tokens
+}
+
+/// The divisor of the log probability of a sequence of the length: the
+/// length itself, or the penalty of Google's NMT with the given alpha.
+fn length_penalty_of(length: usize, length_penalty: Option<f64>) -> f64 {
+    let length = length.max(1) as f64;
+    match length_penalty {
+        None => length,
+        Some(alpha) => ((5.0 + length) / 6.0).powf(alpha),
+    }
+}
+
+#[test]
+fn beam_search_helpers_test() {
+    let logprobs = log_softmax(&[1.0, 3.0, 2.0, f32::NEG_INFINITY]);
+    let total: f64 = logprobs.iter().map(|l| l.exp()).sum();
+    assert!((total - 1.0).abs() < 1e-9);
+    assert_eq!(
+        top_k(&logprobs, 2)
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect::<Vec<_>>(),
+        [1, 2]
+    );
+    assert_eq!(length_penalty_of(4, None), 4.0);
+    assert_eq!(length_penalty_of(7, Some(1.0)), 2.0);
+    assert_eq!(length_penalty_of(0, Some(0.0)), 1.0);
+}
+
+/// Encodes the initial prompt after `<|startofprev|>`. Like OpenAI's
+/// implementation, only the last tokens of a long prompt are kept, so it
+/// takes at most half of the context of the decoder.
+fn prompt_tokens(
+    tokenizer: &Tokenizer,
+    prompt: &str,
+    max_target_positions: usize,
+) -> Result<Vec<u32>> {
+    let encoding = tokenizer
+        .encode(format!(" {}", prompt.trim()), false)
+        .map_err(E::msg)?;
+    let ids = encoding.get_ids();
+    let max_len = max_target_positions / 2 - 1;
+    let mut tokens = vec![token_id(tokenizer, SOT_PREV_TOKEN)?];
+    tokens.extend_from_slice(&ids[ids.len().saturating_sub(max_len)..]);
+    Ok(tokens)
+}
+
 pub fn token_id(tokenizer: &Tokenizer, token: &str) -> candle::Result<u32> {
     match tokenizer.token_to_id(token) {
         None => candle::bail!("no token-id for {token}"),
@@ -321,13 +571,30 @@ pub fn token_id(tokenizer: &Tokenizer, token: &str) -> candle::Result<u32> {
     }
 }
 
+/// How the tokens are chosen when decoding at the temperature zero, before
+/// falling back to sampling at the higher temperatures.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DecodingStrategy {
+    /// The most probable token at each step.
+    #[default]
+    Greedy,
+    /// The most probable sequences of the beam size are searched, and the
+    /// best of them is chosen by its log probability divided by the length
+    /// penalty: the length itself without an alpha, like OpenAI's
+    /// implementation does by default.
+    BeamSearch {
+        beam_size: usize,
+        length_penalty: Option<f64>,
+    },
+}
+
 #[derive(Clone, Copy, Debug)]
 enum Task {
     Transcribe,
     Translate,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Sequence, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum WhichModel {
     Tiny,
     TinyEn,
@@ -340,8 +607,10 @@ pub enum WhichModel {
     Large,
     LargeV2,
     LargeV3,
+    LargeV3Turbo,
     DistilMediumEn,
     DistilLargeV2,
+    DistilLargeV3,
 }
 
 impl WhichModel {
@@ -354,7 +623,9 @@ impl WhichModel {
             Self::Large |
             Self::LargeV2 |
             Self::LargeV3 |
-            Self::DistilLargeV2 => true,
+            Self::LargeV3Turbo |
+            Self::DistilLargeV2 |
+            Self::DistilLargeV3 => true,
             Self::TinyEn |
             Self::BaseEn |
             Self::SmallEn |
@@ -376,12 +647,37 @@ impl WhichModel {
             Self::Large => ("openai/whisper-large", "refs/pr/36"),
             Self::LargeV2 => ("openai/whisper-large-v2", "refs/pr/57"),
             Self::LargeV3 => ("openai/whisper-large-v3", "main"),
+            Self::LargeV3Turbo => ("openai/whisper-large-v3-turbo", "main"),
             Self::DistilMediumEn => ("distil-whisper/distil-medium.en", "main"),
             Self::DistilLargeV2 => ("distil-whisper/distil-large-v2", "main"),
+            Self::DistilLargeV3 => ("distil-whisper/distil-large-v3", "main"),
         }
     }
 }
 
+/// Parses the model from its repository, like `openai/whisper-large-v3`, or
+/// its name alone, like `whisper-large-v3`.
+impl std::str::FromStr for WhichModel {
+    type Err = E;
+
+    fn from_str(name: &str) -> Result<Self> {
+        let repo = |model: &WhichModel| model.model_and_revision().0;
+        enum_iterator::all::<WhichModel>()
+            .find(|model| {
+                let repo = repo(model);
+                repo == name || repo.rsplit('/').next() == Some(name)
+            })
+            .ok_or_else(|| {
+                let known = enum_iterator::all::<WhichModel>()
+                    .map(|model| repo(&model))
+                    .collect::<Vec<_>>();
+                anyhow::anyhow!(
+                    "unknown model {name}, expected one of {known:?}"
+                )
+            })
+    }
+}
+
 #[derive(Debug)]
 pub struct SpeechRecognizerTask {
     pub models_data_dir: std::path::PathBuf,
@@ -390,14 +686,26 @@ pub struct SpeechRecognizerTask {
     pub input: std::path::PathBuf,
     pub language: Option<String>,
     pub seed: Option<u64>,
+    /// Text to prompt the decoder with, like the names and the terms the
+    /// audio contains, spelled right.
+    pub initial_prompt: Option<String>,
+    pub decoding: DecodingStrategy,
 }
 
-pub fn run_speech_recognizer(
-    task: SpeechRecognizerTask,
-    output_provider: Box<dyn SpeechRecognitionOutputProvider>,
-) -> Result<()> {
-    let model_dir =
-        task.models_data_dir.join(task.model.model_and_revision().0);
+/// A model loaded from the models data directory, with the mel filters of
+/// its number of mel bins.
+struct LoadedModel {
+    model: Model,
+    tokenizer: Tokenizer,
+    mel_filters: Vec<f32>,
+}
+
+fn load_model(
+    models_data_dir: &std::path::Path,
+    which: WhichModel,
+    device: &Device,
+) -> Result<LoadedModel> {
+    let model_dir = crate::models::model_dir(models_data_dir, which);
     let config: Config = serde_json::from_str(&std::fs::read_to_string(
         model_dir.join(DataFile::Config.file_name()),
     )?)?;
@@ -416,42 +724,58 @@ pub fn run_speech_recognizer(
         &mut mel_filters,
     );
 
-    let (pcm_data, sample_rate) = pcm_decode::pcm_decode(&task.input)?;
-    if sample_rate != m::SAMPLE_RATE as u32 {
-        anyhow::bail!("input file must have a {} sampling rate", m::SAMPLE_RATE)
-    }
-    log::info!(
-        "pcm data loaded from {}, len {}",
-        task.input.display(),
-        pcm_data.len()
-    );
-
-    let mel = audio::pcm_to_mel(&config, &pcm_data, &mel_filters);
-    let mel_len = mel.len();
-    let mel = Tensor::from_vec(
-        mel,
-        (1, config.num_mel_bins, mel_len / config.num_mel_bins),
-        &task.device,
-    )?;
-    log::info!("loaded mel: {:?}", mel.dims());
-
-    let mut model = {
+    let model = {
         let vb = VarBuilder::from_buffered_safetensors(
             std::fs::read(model_dir.join(DataFile::Model.file_name()))?,
             m::DTYPE,
-            &task.device,
+            device,
         )?;
 
         Model::Normal(m::model::Whisper::load(&vb, config)?)
     };
 
-    let language_token = match (task.model.is_multilingual(), task.language) {
+    Ok(LoadedModel {
+        model,
+        tokenizer,
+        mel_filters,
+    })
+}
+
+/// The mel spectrogram of the 16 kHz samples, with a batch dimension.
+fn pcm_to_mel(
+    config: &Config,
+    pcm_data: &[f32],
+    mel_filters: &[f32],
+    device: &Device,
+) -> Result<Tensor> {
+    let mel = audio::pcm_to_mel(config, pcm_data, mel_filters);
+    let mel_len = mel.len();
+    Ok(Tensor::from_vec(
+        mel,
+        (1, config.num_mel_bins, mel_len / config.num_mel_bins),
+        device,
+    )?)
+}
+
+/// The token of the language, detected from the mel spectrogram if it is
+/// not set and the model is multilingual.
+fn language_token(
+    which: WhichModel,
+    language: Option<String>,
+    model: &mut Model,
+    tokenizer: &Tokenizer,
+    mel: Option<&Tensor>,
+) -> Result<Option<u32>> {
+    Ok(match (which.is_multilingual(), language) {
         (true, None) => {
-            Some(multilingual::detect_language(&mut model, &tokenizer, &mel)?)
+            let Some(mel) = mel else {
+                anyhow::bail!("a language must be set to transcribe live")
+            };
+            Some(multilingual::detect_language(model, tokenizer, mel)?)
         },
         (false, None) => None,
         (true, Some(language)) => {
-            match token_id(&tokenizer, &format!("<|{language}|>")) {
+            match token_id(tokenizer, &format!("<|{language}|>")) {
                 Ok(token_id) => Some(token_id),
                 Err(_) => anyhow::bail!("language {language} is not supported"),
             }
@@ -461,19 +785,84 @@ pub fn run_speech_recognizer(
                 "a language cannot be set for non-multilingual models"
             )
         },
-    };
+    })
+}
+
+/// Whether the decoded audio is most likely silence, which is not output.
+fn is_silence(dr: &DecodingResult) -> bool {
+    dr.no_speech_prob > m::NO_SPEECH_THRESHOLD &&
+        dr.avg_logprob < m::LOGPROB_THRESHOLD
+}
+
+fn check_decoding(decoding: DecodingStrategy) -> Result<()> {
+    if let DecodingStrategy::BeamSearch { beam_size: 0, .. } = decoding {
+        anyhow::bail!("the beam size must be positive");
+    }
+    Ok(())
+}
+
+pub fn run_speech_recognizer(
+    task: SpeechRecognizerTask,
+    output_provider: Box<dyn SpeechRecognitionOutputProvider>,
+) -> Result<()> {
+    check_decoding(task.decoding)?;
+    let mut loaded =
+        load_model(&task.models_data_dir, task.model, &task.device)?;
+
+    let (pcm_data, sample_rate) = pcm_decode::pcm_decode(&task.input)?;
+    if sample_rate != m::SAMPLE_RATE as u32 {
+        anyhow::bail!("input file must have a {} sampling rate", m::SAMPLE_RATE)
+    }
+    log::info!(
+        "pcm data loaded from {}, len {}",
+        task.input.display(),
+        pcm_data.len()
+    );
+
+    let mel = pcm_to_mel(
+        loaded.model.config(),
+        &pcm_data,
+        &loaded.mel_filters,
+        &task.device,
+    )?;
+    log::info!("loaded mel: {:?}", mel.dims());
+
+    let language_token = language_token(
+        task.model,
+        task.language,
+        &mut loaded.model,
+        &loaded.tokenizer,
+        Some(&mel),
+    )?;
 
     let mut dc = Decoder::new(
-        model,
-        tokenizer,
+        loaded.model,
+        loaded.tokenizer,
         task.seed.unwrap_or(299792458),
         &task.device,
         language_token,
         None,
         false,
         false,
+        task.initial_prompt.as_deref(),
+        task.decoding,
     )?;
     dc.run(&mel, output_provider)?;
 
     Ok(())
 }
+
+#[test]
+fn which_model_from_str_test() {
+    assert_eq!(
+        "openai/whisper-large-v3-turbo"
+            .parse::<WhichModel>()
+            .unwrap(),
+        WhichModel::LargeV3Turbo
+    );
+    assert_eq!(
+        "distil-large-v3".parse::<WhichModel>().unwrap(),
+        WhichModel::DistilLargeV3
+    );
+    assert!("large-v3".parse::<WhichModel>().is_err());
+}
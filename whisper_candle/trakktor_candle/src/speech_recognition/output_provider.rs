@@ -1,4 +1,8 @@
-use std::{fs::File, io::Write, path::Path};
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 #[derive(Debug, Clone)]
 pub struct DecodingResult {
@@ -23,6 +27,17 @@ pub trait SpeechRecognitionOutputProvider {
     fn finish(&mut self) -> anyhow::Result<()>;
 }
 
+/// Receives the segments of a live transcription as the audio comes.
+pub trait StreamingOutputProvider {
+    fn start(&mut self) -> anyhow::Result<()>;
+    /// The transcription of the audio of the current window so far, replaced
+    /// by the next update or by the final segment of the window.
+    fn update(&mut self, s: &Segment) -> anyhow::Result<()>;
+    /// The final segment of the window, the next updates follow it.
+    fn add_segment(&mut self, s: Segment) -> anyhow::Result<()>;
+    fn finish(&mut self) -> anyhow::Result<()>;
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum TimestampFormat {
     Start,
@@ -133,3 +148,256 @@ impl SpeechRecognitionOutputProvider for TextOutputProvider {
         Ok(())
     }
 }
+
+/// Writes the outputs of all the formats of OpenAI's Whisper CLI, the
+/// `.txt`, `.tsv`, `.srt`, `.vtt` and `.json` files named after the input,
+/// and prints the segments like it does, e.g.
+/// `[00:00.000 --> 00:30.000]  Hello.`
+pub struct WhisperFormatsOutputProvider {
+    dir: PathBuf,
+    name: String,
+    language: Option<String>,
+    segments: Vec<Segment>,
+}
+
+impl WhisperFormatsOutputProvider {
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        name: impl Into<String>,
+        language: Option<String>,
+    ) -> Self {
+        Self {
+            dir: dir.into(),
+            name: name.into(),
+            language,
+            segments: vec![],
+        }
+    }
+
+    fn write(&self, ext: &str, content: &str) -> anyhow::Result<()> {
+        let file = self.dir.join(format!("{}.{ext}", self.name));
+        std::fs::write(file, content)?;
+        Ok(())
+    }
+
+    fn txt(&self) -> String {
+        self.segments
+            .iter()
+            .map(|s| format!("{}\n", s.dr.text.trim()))
+            .collect()
+    }
+
+    fn tsv(&self) -> String {
+        let mut tsv = String::from("start\tend\ttext\n");
+        for s in &self.segments {
+            tsv += &format!(
+                "{}\t{}\t{}\n",
+                (s.start * 1000.0).round() as u64,
+                ((s.start + s.duration) * 1000.0).round() as u64,
+                s.dr.text.trim().replace('\t', " "),
+            );
+        }
+        tsv
+    }
+
+    fn srt(&self) -> String {
+        let mut srt = String::new();
+        for (i, s) in self.segments.iter().enumerate() {
+            srt += &format!(
+                "{}\n{} --> {}\n{}\n\n",
+                i + 1,
+                whisper_timestamp(s.start, true, ','),
+                whisper_timestamp(s.start + s.duration, true, ','),
+                s.dr.text.trim().replace("-->", "->"),
+            );
+        }
+        srt
+    }
+
+    fn vtt(&self) -> String {
+        let mut vtt = String::from("WEBVTT\n\n");
+        for s in &self.segments {
+            vtt += &format!(
+                "{} --> {}\n{}\n\n",
+                whisper_timestamp(s.start, false, '.'),
+                whisper_timestamp(s.start + s.duration, false, '.'),
+                s.dr.text.trim().replace("-->", "->"),
+            );
+        }
+        vtt
+    }
+
+    fn json(&self) -> anyhow::Result<String> {
+        let segments = self
+            .segments
+            .iter()
+            .enumerate()
+            .map(|(id, s)| {
+                serde_json::json!({
+                    "id": id,
+                    "start": s.start,
+                    "end": s.start + s.duration,
+                    "text": s.dr.text,
+                    "tokens": s.dr.tokens,
+                    "temperature": s.dr.temperature,
+                    "avg_logprob": s.dr.avg_logprob,
+                    "compression_ratio": s.dr.compression_ratio,
+                    "no_speech_prob": s.dr.no_speech_prob,
+                })
+            })
+            .collect::<Vec<_>>();
+        let text = self.segments.iter().map(|s| s.dr.text.as_str());
+        Ok(serde_json::to_string(&serde_json::json!({
+            "text": text.collect::<String>(),
+            "segments": segments,
+            "language": self.language,
+        }))?)
+    }
+}
+
+impl SpeechRecognitionOutputProvider for WhisperFormatsOutputProvider {
+    fn start(&mut self) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        Ok(())
+    }
+
+    fn add_segment(&mut self, s: Segment) -> anyhow::Result<()> {
+        let mut stdout = std::io::stdout().lock();
+        writeln!(
+            stdout,
+            "[{} --> {}] {}",
+            whisper_timestamp(s.start, false, '.'),
+            whisper_timestamp(s.start + s.duration, false, '.'),
+            s.dr.text,
+        )?;
+        stdout.flush()?;
+        self.segments.push(s);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.write("txt", &self.txt())?;
+        self.write("tsv", &self.tsv())?;
+        self.write("srt", &self.srt())?;
+        self.write("vtt", &self.vtt())?;
+        self.write("json", &self.json()?)?;
+        Ok(())
+    }
+}
+
+/// Formats the time like OpenAI's Whisper CLI, `01:02:03.456`, with the
+/// hours only when there are any unless they are always included.
+fn whisper_timestamp(
+    t: f64,
+    always_include_hours: bool,
+    decimal_marker: char,
+) -> String {
+    let ms = (t.max(0.0) * 1000.0).round() as u64;
+    let (h, m, s, ms) =
+        (ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000);
+    if always_include_hours || h > 0 {
+        format!("{h:02}:{m:02}:{s:02}{decimal_marker}{ms:03}")
+    } else {
+        format!("{m:02}:{s:02}{decimal_marker}{ms:03}")
+    }
+}
+
+#[test]
+fn whisper_formats_test() {
+    assert_eq!(whisper_timestamp(0.0, false, '.'), "00:00.000");
+    assert_eq!(whisper_timestamp(61.5, true, ','), "00:01:01,500");
+    assert_eq!(whisper_timestamp(3723.456, false, '.'), "01:02:03.456");
+
+    let segment = |start: f64, text: &str| Segment {
+        start,
+        duration: 30.0,
+        dr: DecodingResult {
+            tokens: vec![1, 2],
+            text: text.into(),
+            avg_logprob: -0.1,
+            no_speech_prob: 0.0,
+            temperature: 0.0,
+            compression_ratio: 1.0,
+        },
+    };
+    let mut output = WhisperFormatsOutputProvider::new("out", "a", None);
+    output.segments =
+        vec![segment(0.0, " Hello."), segment(30.0, " A --> B\tC")];
+    assert_eq!(output.txt(), "Hello.\nA --> B\tC\n");
+    assert_eq!(
+        output.tsv(),
+        "start\tend\ttext\n0\t30000\tHello.\n30000\t60000\tA --> B C\n"
+    );
+    assert_eq!(
+        output.srt(),
+        "1\n00:00:00,000 --> 00:00:30,000\nHello.\n\n\
+         2\n00:00:30,000 --> 00:01:00,000\nA -> B\tC\n\n"
+    );
+    assert_eq!(
+        output.vtt(),
+        "WEBVTT\n\n00:00.000 --> 00:30.000\nHello.\n\n\
+         00:30.000 --> 01:00.000\nA -> B\tC\n\n"
+    );
+    let json: serde_json::Value =
+        serde_json::from_str(&output.json().unwrap()).unwrap();
+    assert_eq!(json["text"], " Hello. A --> B\tC");
+    assert_eq!(json["segments"][1]["end"], 60.0);
+}
+
+/// Shows the live transcription in the terminal, rewriting the line of the
+/// current window as it is updated, and passes the final segments to the
+/// provider, e.g. to write them to a file.
+pub struct TerminalOutputProvider {
+    format: TimestampFormat,
+    output: Option<Box<dyn SpeechRecognitionOutputProvider>>,
+}
+
+impl TerminalOutputProvider {
+    pub fn new(
+        format: TimestampFormat,
+        output: Option<Box<dyn SpeechRecognitionOutputProvider>>,
+    ) -> Self {
+        Self { format, output }
+    }
+
+    fn print(&self, s: &Segment, end: &str) -> anyhow::Result<()> {
+        let mut stdout = std::io::stdout().lock();
+        // Clears the line of the previous update.
+        write!(
+            stdout,
+            "\r\x1b[2K{} {}{end}",
+            self.format.format(s.start, s.duration),
+            s.dr.text.trim()
+        )?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+impl StreamingOutputProvider for TerminalOutputProvider {
+    fn start(&mut self) -> anyhow::Result<()> {
+        match &mut self.output {
+            Some(output) => output.start(),
+            None => Ok(()),
+        }
+    }
+
+    fn update(&mut self, s: &Segment) -> anyhow::Result<()> {
+        self.print(s, "")
+    }
+
+    fn add_segment(&mut self, s: Segment) -> anyhow::Result<()> {
+        self.print(&s, "\n")?;
+        match &mut self.output {
+            Some(output) => output.add_segment(s),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        match &mut self.output {
+            Some(output) => output.finish(),
+            None => Ok(()),
+        }
+    }
+}